@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+pub const MANIFEST_NAME: &str = "app.ok200.native";
+pub const MANIFEST_FILENAME: &str = "app.ok200.native.json";
+
+/// A Chromium-based browser we register the native messaging host with.
+pub struct Browser {
+    pub name: &'static str,
+    /// Config directory, relative to the home dir (macOS/Linux) or the
+    /// local app data dir (Windows).
+    pub config_dir: &'static str,
+    /// Vendor key under `HKCU` that holds `NativeMessagingHosts` (Windows only).
+    pub registry_key: Option<&'static str>,
+}
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+const fn browser(name: &'static str, config_dir: &'static str) -> Browser {
+    Browser {
+        name,
+        config_dir,
+        registry_key: None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+const BROWSERS: &[Browser] = &[
+    browser("Chrome", "Library/Application Support/Google/Chrome"),
+    browser("Chrome Canary", "Library/Application Support/Google/Chrome Canary"),
+    browser("Chromium", "Library/Application Support/Chromium"),
+    browser("Brave", "Library/Application Support/BraveSoftware/Brave-Browser"),
+    browser("Edge", "Library/Application Support/Microsoft Edge"),
+    browser("Vivaldi", "Library/Application Support/Vivaldi"),
+    browser("Arc", "Library/Application Support/Arc/User Data"),
+];
+
+#[cfg(target_os = "linux")]
+const BROWSERS: &[Browser] = &[
+    browser("Chrome", ".config/google-chrome"),
+    browser("Chromium", ".config/chromium"),
+    browser("Brave", ".config/BraveSoftware/Brave-Browser"),
+    browser("Edge", ".config/microsoft-edge"),
+];
+
+#[cfg(target_os = "windows")]
+const BROWSERS: &[Browser] = &[
+    Browser {
+        name: "Chrome",
+        config_dir: "Google\\Chrome\\User Data",
+        registry_key: Some("Software\\Google\\Chrome"),
+    },
+    Browser {
+        name: "Chromium",
+        config_dir: "Chromium\\User Data",
+        registry_key: Some("Software\\Chromium"),
+    },
+    Browser {
+        name: "Brave",
+        config_dir: "BraveSoftware\\Brave-Browser\\User Data",
+        registry_key: Some("Software\\BraveSoftware\\Brave-Browser"),
+    },
+    Browser {
+        name: "Edge",
+        config_dir: "Microsoft\\Edge\\User Data",
+        registry_key: Some("Software\\Microsoft\\Edge"),
+    },
+];
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+const BROWSERS: &[Browser] = &[];
+
+/// All browsers probed on this platform.
+pub fn known_browsers() -> &'static [Browser] {
+    BROWSERS
+}
+
+impl Browser {
+    /// Absolute path of the browser's config directory.
+    pub fn config_path(&self) -> Option<PathBuf> {
+        #[cfg(windows)]
+        let base = dirs::data_local_dir()?;
+        #[cfg(not(windows))]
+        let base = dirs::home_dir()?;
+        Some(base.join(self.config_dir))
+    }
+
+    /// Registry key holding our manifest path (Windows only).
+    pub fn registry_host_key(&self) -> Option<String> {
+        self.registry_key
+            .map(|key| format!("{key}\\NativeMessagingHosts\\{MANIFEST_NAME}"))
+    }
+
+    /// Where the browser looks for our host manifest. On Windows this is
+    /// whatever the registry points at; elsewhere it is a fixed location
+    /// inside the config directory.
+    pub fn manifest_path(&self) -> Option<PathBuf> {
+        #[cfg(windows)]
+        {
+            use winreg::enums::HKEY_CURRENT_USER;
+            use winreg::RegKey;
+
+            let value: String = RegKey::predef(HKEY_CURRENT_USER)
+                .open_subkey(self.registry_host_key()?)
+                .and_then(|key| key.get_value(""))
+                .ok()?;
+            Some(PathBuf::from(value))
+        }
+        #[cfg(not(windows))]
+        {
+            Some(
+                self.config_path()?
+                    .join("NativeMessagingHosts")
+                    .join(MANIFEST_FILENAME),
+            )
+        }
+    }
+}
+
+/// Read the host `path` field from a native messaging manifest.
+pub fn read_manifest_host_path(manifest_path: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(manifest_path).ok()?;
+    let manifest: serde_json::Value = serde_json::from_str(&text).ok()?;
+    manifest
+        .get("path")
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+}
+
+/// What we found on disk for a single browser.
+#[derive(Serialize)]
+pub struct BrowserProbe {
+    pub browser: String,
+    pub config_dir: Option<String>,
+    pub config_dir_exists: bool,
+    pub manifest_path: Option<String>,
+    pub manifest_present: bool,
+    /// The `path` value inside our manifest, if it could be read.
+    pub host_path: Option<String>,
+}
+
+/// Probe every known browser's config dir and host manifest.
+pub fn probe_browsers() -> Vec<BrowserProbe> {
+    known_browsers()
+        .iter()
+        .map(|b| {
+            let config_dir = b.config_path();
+            let manifest_path = b.manifest_path();
+            let manifest_present = manifest_path.as_deref().is_some_and(Path::is_file);
+            BrowserProbe {
+                browser: b.name.to_string(),
+                config_dir_exists: config_dir.as_deref().is_some_and(Path::exists),
+                config_dir: config_dir.map(|p| p.to_string_lossy().to_string()),
+                host_path: manifest_path
+                    .as_deref()
+                    .and_then(read_manifest_host_path),
+                manifest_path: manifest_path.map(|p| p.to_string_lossy().to_string()),
+                manifest_present,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn test_read_manifest_host_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(MANIFEST_FILENAME);
+        std::fs::write(&path, r#"{"name": "app.ok200.native", "path": "/opt/ok200-host"}"#)
+            .unwrap();
+        assert_eq!(
+            read_manifest_host_path(&path).as_deref(),
+            Some("/opt/ok200-host")
+        );
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(read_manifest_host_path(&path).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[serial]
+    fn test_probe_browsers_finds_manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let original = std::env::var("HOME").ok();
+        std::env::set_var("HOME", tmp.path());
+
+        let hosts_dir = tmp.path().join(".config/chromium/NativeMessagingHosts");
+        std::fs::create_dir_all(&hosts_dir).unwrap();
+        std::fs::write(
+            hosts_dir.join(MANIFEST_FILENAME),
+            r#"{"path": "/usr/lib/ok200/ok200-host"}"#,
+        )
+        .unwrap();
+
+        let probes = probe_browsers();
+        assert_eq!(probes.len(), known_browsers().len());
+
+        let chromium = probes.iter().find(|p| p.browser == "Chromium").unwrap();
+        assert!(chromium.config_dir_exists);
+        assert!(chromium.manifest_present);
+        assert_eq!(
+            chromium.host_path.as_deref(),
+            Some("/usr/lib/ok200/ok200-host")
+        );
+
+        let chrome = probes.iter().find(|p| p.browser == "Chrome").unwrap();
+        assert!(!chrome.config_dir_exists);
+        assert!(!chrome.manifest_present);
+        assert!(chrome.host_path.is_none());
+
+        match original {
+            Some(val) => std::env::set_var("HOME", val),
+            None => std::env::remove_var("HOME"),
+        }
+    }
+}
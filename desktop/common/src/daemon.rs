@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use crate::native_dir;
+
+const DAEMON_CONFIG_FILENAME: &str = "daemon.json";
+/// Bounds for `update_check_interval_hours`: no more than hourly, and at
+/// least every 30 days.
+const UPDATE_CHECK_INTERVAL_HOURS: std::ops::RangeInclusive<u64> = 1..=24 * 30;
+
+fn default_update_check_interval_hours() -> u64 {
+    24
+}
+
+/// Settings for the host's background `--daemon` mode, stored in
+/// `ok200-native/daemon.json` so both the app and the host can read them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DaemonConfig {
+    #[serde(default = "default_update_check_interval_hours")]
+    pub update_check_interval_hours: u64,
+    /// Install updates in the background instead of only checking.
+    #[serde(default)]
+    pub auto_update: bool,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            update_check_interval_hours: default_update_check_interval_hours(),
+            auto_update: false,
+        }
+    }
+}
+
+/// The saved config, with the interval brought into range since the file
+/// may have been edited by hand.
+pub fn load_daemon_config() -> DaemonConfig {
+    let mut config: DaemonConfig = native_dir()
+        .map(|dir| dir.join(DAEMON_CONFIG_FILENAME))
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    config.update_check_interval_hours = config.update_check_interval_hours.clamp(
+        *UPDATE_CHECK_INTERVAL_HOURS.start(),
+        *UPDATE_CHECK_INTERVAL_HOURS.end(),
+    );
+    config
+}
+
+pub fn save_daemon_config(config: &DaemonConfig) -> std::io::Result<()> {
+    let dir = native_dir()
+        .ok_or_else(|| std::io::Error::other("no config directory"))?;
+    std::fs::create_dir_all(&dir)?;
+    let json = serde_json::to_string_pretty(config).map_err(std::io::Error::other)?;
+    std::fs::write(dir.join(DAEMON_CONFIG_FILENAME), json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    fn test_daemon_config_defaults() {
+        let config: DaemonConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config, DaemonConfig::default());
+        assert_eq!(config.update_check_interval_hours, 24);
+        assert!(!config.auto_update);
+    }
+
+    #[test]
+    #[serial]
+    fn test_daemon_config_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key = "OK200_CONFIG_DIR";
+        let original = std::env::var(key).ok();
+        std::env::set_var(key, tmp.path());
+
+        assert_eq!(load_daemon_config(), DaemonConfig::default());
+        let config = DaemonConfig {
+            update_check_interval_hours: 6,
+            auto_update: true,
+        };
+        save_daemon_config(&config).unwrap();
+        assert_eq!(load_daemon_config(), config);
+
+        // Hand-edited intervals are brought into range
+        for (hours, loaded) in [(0, 1), (u64::MAX, 24 * 30)] {
+            let config = DaemonConfig {
+                update_check_interval_hours: hours,
+                auto_update: false,
+            };
+            save_daemon_config(&config).unwrap();
+            assert_eq!(load_daemon_config().update_check_interval_hours, loaded);
+        }
+
+        match original {
+            Some(val) => std::env::set_var(key, val),
+            None => std::env::remove_var(key),
+        }
+    }
+}
@@ -1,10 +1,50 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+pub mod browsers;
+pub mod daemon;
+pub mod http;
+pub mod lock;
+pub mod net;
+pub mod shared_state;
+
+const PORTABLE_FLAG_FILENAME: &str = "portable.flag";
+const PORTABLE_ARG: &str = "--portable";
+const PORTABLE_DATA_DIR: &str = "data";
+
+/// `data/` beside the executable when `exe_dir` holds a `portable.flag` file
+/// or `--portable` was passed.
+fn portable_dir_for(exe_dir: &Path, args: &[String]) -> Option<PathBuf> {
+    let portable =
+        exe_dir.join(PORTABLE_FLAG_FILENAME).is_file() || args.iter().any(|a| a == PORTABLE_ARG);
+    portable.then(|| exe_dir.join(PORTABLE_DATA_DIR))
+}
+
+/// Data directory for portable mode, or `None` for a normal install.
+/// The native host only sees the flag file, since browsers launch it
+/// without our arguments.
+pub fn portable_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let args: Vec<String> = std::env::args().collect();
+    portable_dir_for(exe.parent()?, &args)
+}
 
 pub fn get_config_dir() -> Option<PathBuf> {
     if let Ok(env_dir) = std::env::var("OK200_CONFIG_DIR") {
         return Some(PathBuf::from(env_dir));
     }
-    dirs::config_dir()
+    portable_dir().or_else(dirs::config_dir)
+}
+
+/// Directory shared between the desktop app and the native host.
+pub fn native_dir() -> Option<PathBuf> {
+    Some(get_config_dir()?.join("ok200-native"))
+}
+
+const APP_LOCK_FILENAME: &str = "app.lock";
+
+/// Lock file held by the desktop app for as long as it is running.
+pub fn app_lock_path() -> Option<PathBuf> {
+    Some(native_dir()?.join(APP_LOCK_FILENAME))
 }
 
 const CFU_ID_FILENAME: &str = "cfu-id";
@@ -13,7 +53,7 @@ const CFU_ID_FILENAME: &str = "cfu-id";
 /// Stored as a plain UUID in `~/.config/ok200-native/cfu-id`.
 /// This ID is sent with update check requests to help estimate unique active installs.
 pub fn get_or_create_cfu_id() -> Option<String> {
-    let dir = get_config_dir()?.join("ok200-native");
+    let dir = native_dir()?;
     let path = dir.join(CFU_ID_FILENAME);
 
     if let Ok(id) = std::fs::read_to_string(&path) {
@@ -51,6 +91,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_portable_dir_for() {
+        let tmp = tempfile::tempdir().unwrap();
+        let data = tmp.path().join("data");
+        assert_eq!(portable_dir_for(tmp.path(), &[]), None);
+        assert_eq!(
+            portable_dir_for(tmp.path(), &["ok200".to_string(), "--portable".to_string()]),
+            Some(data.clone())
+        );
+
+        std::fs::write(tmp.path().join("portable.flag"), "").unwrap();
+        assert_eq!(portable_dir_for(tmp.path(), &[]), Some(data));
+    }
+
     #[test]
     #[serial]
     fn test_get_or_create_cfu_id_persistence() {
@@ -0,0 +1,66 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::Path;
+
+/// An advisory file lock, released when dropped (or when the process exits).
+pub struct FileLock {
+    _file: File,
+}
+
+fn open_lock_file(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// Take an exclusive lock on `path`, creating the file if needed.
+/// Returns `Ok(None)` if another process already holds it.
+pub fn try_lock_exclusive(path: &Path) -> io::Result<Option<FileLock>> {
+    let file = open_lock_file(path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(FileLock { _file: file })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Take an exclusive lock on `path`, waiting for any other holder.
+pub fn lock_exclusive(path: &Path) -> io::Result<FileLock> {
+    let file = open_lock_file(path)?;
+    file.lock()?;
+    Ok(FileLock { _file: file })
+}
+
+/// Whether some process currently holds an exclusive lock on `path`.
+pub fn is_locked(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    matches!(file.try_lock_shared(), Err(TryLockError::WouldBlock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusive_lock_is_exclusive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nested/app.lock");
+        assert!(!is_locked(&path));
+
+        let lock = try_lock_exclusive(&path).unwrap().expect("first lock");
+        assert!(is_locked(&path));
+        assert!(try_lock_exclusive(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(!is_locked(&path));
+        assert!(try_lock_exclusive(&path).unwrap().is_some());
+    }
+}
@@ -0,0 +1,93 @@
+//! Pick the address other devices on the local network can reach us at.
+
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+/// Public addresses used only to ask the OS which interface it would route
+/// through. Connecting a UDP socket sends no packets.
+const ROUTE_PROBES: [&str; 2] = ["8.8.8.8:80", "[2001:4860:4860::8888]:80"];
+
+/// Preference for an address as a LAN URL; `None` if it can't be one.
+fn rank(ip: IpAddr) -> Option<u8> {
+    if ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() {
+        return None;
+    }
+    match ip {
+        IpAddr::V4(v4) if v4.is_link_local() => None,
+        IpAddr::V4(v4) if v4.is_private() => Some(3),
+        IpAddr::V4(_) => Some(2),
+        // fe80::/10 needs a zone id to be usable in a URL
+        IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80 => None,
+        IpAddr::V6(_) => Some(1),
+    }
+}
+
+/// Best LAN address among `candidates`: private IPv4 first, then other
+/// IPv4, then IPv6. Ties keep the earlier candidate.
+pub fn pick_lan_ip(candidates: impl IntoIterator<Item = IpAddr>) -> Option<IpAddr> {
+    let mut best: Option<(u8, IpAddr)> = None;
+    for ip in candidates {
+        if let Some(score) = rank(ip) {
+            if best.is_none_or(|(b, _)| score > b) {
+                best = Some((score, ip));
+            }
+        }
+    }
+    best.map(|(_, ip)| ip)
+}
+
+fn outbound_ip(probe: &str) -> Option<IpAddr> {
+    let bind = if probe.starts_with('[') {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(probe).ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Address of this machine on the local network, if it has one.
+pub fn lan_ip() -> Option<IpAddr> {
+    pick_lan_ip(ROUTE_PROBES.iter().filter_map(|p| outbound_ip(p)))
+}
+
+/// `http://<ip>:<port>/`, bracketing IPv6 addresses.
+pub fn http_url(ip: IpAddr, port: u16) -> String {
+    format!("http://{}/", SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_pick_lan_ip() {
+        assert_eq!(pick_lan_ip([ip("127.0.0.1"), ip("::1")]), None);
+        assert_eq!(pick_lan_ip([ip("169.254.1.2"), ip("fe80::1")]), None);
+        assert_eq!(
+            pick_lan_ip([ip("2001:db8::5"), ip("100.64.0.3"), ip("192.168.1.20")]),
+            Some(ip("192.168.1.20"))
+        );
+        assert_eq!(
+            pick_lan_ip([ip("2001:db8::5"), ip("100.64.0.3")]),
+            Some(ip("100.64.0.3"))
+        );
+        assert_eq!(
+            pick_lan_ip([ip("10.0.0.2"), ip("192.168.1.20")]),
+            Some(ip("10.0.0.2"))
+        );
+    }
+
+    #[test]
+    fn test_http_url() {
+        assert_eq!(
+            http_url(ip("192.168.1.20"), 8080),
+            "http://192.168.1.20:8080/"
+        );
+        assert_eq!(http_url(ip("2001:db8::5"), 80), "http://[2001:db8::5]:80/");
+    }
+}
@@ -0,0 +1,115 @@
+//! `ok200-native/state.json`: what the desktop app is doing, for the host
+//! to read. Writers take `state.lock` and replace the file atomically, so
+//! readers never see a half-written file and concurrent updates from the
+//! app and the headless updater don't overwrite each other.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{lock, native_dir};
+
+const STATE_FILENAME: &str = "state.json";
+const STATE_LOCK_FILENAME: &str = "state.lock";
+
+/// A server the desktop app is running.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SharedServer {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SharedState {
+    /// Version of the desktop app that last wrote the file.
+    #[serde(default)]
+    pub app_version: Option<String>,
+    /// Cleared on a clean exit. `load` also clears it if the app lock isn't
+    /// held, so a crashed app doesn't look like it's still running.
+    #[serde(default)]
+    pub running: bool,
+    #[serde(default)]
+    pub pid: Option<u32>,
+    #[serde(default)]
+    pub servers: Vec<SharedServer>,
+    /// Unix time of the last finished update check, by either process.
+    #[serde(default)]
+    pub last_update_check: Option<u64>,
+}
+
+fn load_from(dir: &Path) -> SharedState {
+    std::fs::read_to_string(dir.join(STATE_FILENAME))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn update_in(dir: &Path, f: impl FnOnce(&mut SharedState)) -> std::io::Result<SharedState> {
+    let _lock = lock::lock_exclusive(&dir.join(STATE_LOCK_FILENAME))?;
+    let mut state = load_from(dir);
+    f(&mut state);
+    let json = serde_json::to_string_pretty(&state).map_err(std::io::Error::other)?;
+    let tmp = dir.join(format!("{STATE_FILENAME}.tmp"));
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, dir.join(STATE_FILENAME))?;
+    Ok(state)
+}
+
+/// Current shared state, or the default if there is none yet.
+pub fn load() -> SharedState {
+    let Some(dir) = native_dir() else {
+        return SharedState::default();
+    };
+    let mut state = load_from(&dir);
+    if state.running && !crate::app_lock_path().is_some_and(|p| lock::is_locked(&p)) {
+        state.running = false;
+        state.servers.clear();
+    }
+    state
+}
+
+/// Apply `f` to the shared state and write it back, holding the lock
+/// throughout. Returns the new state.
+pub fn update(f: impl FnOnce(&mut SharedState)) -> std::io::Result<SharedState> {
+    let dir = native_dir().ok_or_else(|| std::io::Error::other("no config directory"))?;
+    update_in(&dir, f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("ok200-native");
+        assert_eq!(load_from(&dir), SharedState::default());
+
+        update_in(&dir, |s| {
+            s.app_version = Some("1.2.3".to_string());
+            s.running = true;
+            s.servers.push(SharedServer {
+                name: "site".to_string(),
+                host: "127.0.0.1".to_string(),
+                port: 8080,
+            });
+        })
+        .unwrap();
+        let state = update_in(&dir, |s| s.last_update_check = Some(1_700_000_000)).unwrap();
+
+        assert_eq!(load_from(&dir), state);
+        assert_eq!(state.app_version.as_deref(), Some("1.2.3"));
+        assert_eq!(state.servers.len(), 1);
+        assert_eq!(state.last_update_check, Some(1_700_000_000));
+        assert!(!dir.join("state.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_unknown_fields_ignored() {
+        let state: SharedState =
+            serde_json::from_str(r#"{"running": true, "future_field": 1}"#).unwrap();
+        assert!(state.running);
+        assert!(state.servers.is_empty());
+    }
+}
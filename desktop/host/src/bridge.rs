@@ -0,0 +1,245 @@
+//! TCP connections tunnelled through the host, so the extension can reach
+//! a local server when the desktop app's IPC isn't available. The host
+//! dials `127.0.0.1:port` and relays bytes both ways as base64.
+//!
+//! Replies to the extension's `bridge-open`, `bridge-data` and
+//! `bridge-close` carry `ok`. Bytes read from the socket, and the socket
+//! closing on its own, arrive as `bridge-data` and `bridge-close` messages
+//! with `"event": true`.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::Engine;
+
+/// Bytes per `bridge-data` event; as base64 it stays well under the 1 MB
+/// limit on messages to the extension.
+const READ_CHUNK_BYTES: usize = 256 * 1024;
+const MAX_BRIDGES: usize = 16;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// A server that stops reading for this long has its bridge closed, rather
+/// than holding up every other message to the host.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type EventSink = Arc<dyn Fn(serde_json::Value) + Send + Sync>;
+
+/// Open bridges on this connection.
+pub struct Bridges {
+    send: EventSink,
+    open: Arc<Mutex<HashMap<u64, TcpStream>>>,
+    next_id: u64,
+}
+
+impl Drop for Bridges {
+    fn drop(&mut self) {
+        for (_, stream) in self.open.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Bridges {
+    /// `send` delivers events to the extension, from the relay threads.
+    pub fn new(send: impl Fn(serde_json::Value) + Send + Sync + 'static) -> Self {
+        Self {
+            send: Arc::new(send),
+            open: Arc::default(),
+            next_id: 1,
+        }
+    }
+
+    /// Answer `msg` if it is a bridge action.
+    pub fn handle(&mut self, msg: &serde_json::Value) -> Option<serde_json::Value> {
+        let action = msg.get("action").and_then(|v| v.as_str())?;
+        let result = match action {
+            "bridge-open" => self.open(msg),
+            "bridge-data" => self.data(msg),
+            "bridge-close" => self.close(msg),
+            _ => return None,
+        };
+        let mut response = match result {
+            Ok(fields) => fields,
+            Err(e) => serde_json::json!({ "ok": false, "error": e }),
+        };
+        response["action"] = action.into();
+        Some(response)
+    }
+
+    fn open(&mut self, msg: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let port = msg
+            .get("port")
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|p| u16::try_from(p).ok())
+            .filter(|p| *p != 0)
+            .ok_or("missing or invalid port")?;
+        if self.open.lock().unwrap().len() >= MAX_BRIDGES {
+            return Err("too many bridges open".to_string());
+        }
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+            .map_err(|e| format!("connect to {addr} failed: {e}"))?;
+        stream
+            .set_write_timeout(Some(WRITE_TIMEOUT))
+            .map_err(|e| format!("connect to {addr} failed: {e}"))?;
+        let reader = stream
+            .try_clone()
+            .map_err(|e| format!("connect to {addr} failed: {e}"))?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open.lock().unwrap().insert(id, stream);
+
+        let open = Arc::clone(&self.open);
+        let send = Arc::clone(&self.send);
+        std::thread::spawn(move || relay(id, reader, &open, &send));
+        Ok(serde_json::json!({ "ok": true, "bridgeId": id }))
+    }
+
+    fn bridge_id(msg: &serde_json::Value) -> Result<u64, String> {
+        msg.get("bridgeId")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| "missing bridgeId".to_string())
+    }
+
+    fn data(&mut self, msg: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let id = Self::bridge_id(msg)?;
+        let data = msg.get("data").and_then(|v| v.as_str()).unwrap_or("");
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| format!("invalid base64: {e}"))?;
+        let mut stream = self
+            .open
+            .lock()
+            .unwrap()
+            .get(&id)
+            .ok_or("unknown bridge")?
+            .try_clone()
+            .map_err(|e| format!("bridge write failed: {e}"))?;
+        // Without the lock held, so a slow server doesn't stall the relay
+        // threads too
+        if let Err(e) = stream.write_all(&bytes) {
+            // Part of the bytes may have gone out, so the stream is broken
+            if let Some(stream) = self.open.lock().unwrap().remove(&id) {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            return Err(format!("bridge write failed: {e}"));
+        }
+        Ok(serde_json::json!({ "ok": true, "bridgeId": id }))
+    }
+
+    fn close(&mut self, msg: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let id = Self::bridge_id(msg)?;
+        let stream = self
+            .open
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or("unknown bridge")?;
+        // Wakes the relay thread, which sees the bridge is gone and exits
+        let _ = stream.shutdown(Shutdown::Both);
+        Ok(serde_json::json!({ "ok": true, "bridgeId": id }))
+    }
+}
+
+/// Forward bytes from the socket until it closes.
+fn relay(id: u64, mut reader: TcpStream, open: &Mutex<HashMap<u64, TcpStream>>, send: &EventSink) {
+    let mut buf = vec![0u8; READ_CHUNK_BYTES];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => send(serde_json::json!({
+                "action": "bridge-data",
+                "event": true,
+                "bridgeId": id,
+                "data": base64::engine::general_purpose::STANDARD.encode(&buf[..n]),
+            })),
+        }
+    }
+    // Only tell the extension if it didn't close the bridge itself
+    if open.lock().unwrap().remove(&id).is_some() {
+        send(serde_json::json!({
+            "action": "bridge-close",
+            "event": true,
+            "bridgeId": id,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    fn b64(bytes: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    }
+
+    #[test]
+    fn test_bridge() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (events_tx, events) = mpsc::channel();
+        let mut bridges = Bridges::new(move |event| {
+            let _ = events_tx.send(event);
+        });
+        assert!(bridges
+            .handle(&serde_json::json!({"action": "ping"}))
+            .is_none());
+
+        let response = bridges
+            .handle(&serde_json::json!({"action": "bridge-open", "port": port}))
+            .unwrap();
+        assert_eq!(response["ok"], true);
+        let id = response["bridgeId"].as_u64().unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let data = serde_json::json!({
+            "action": "bridge-data", "bridgeId": id, "data": b64(b"ping"),
+        });
+        assert_eq!(bridges.handle(&data).unwrap()["ok"], true);
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        server.write_all(b"pong").unwrap();
+        let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event["action"], "bridge-data");
+        assert_eq!(event["data"], b64(b"pong"));
+
+        // The server hanging up closes the bridge
+        drop(server);
+        let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event["action"], "bridge-close");
+        assert_eq!(event["bridgeId"], id);
+        assert_eq!(bridges.handle(&data).unwrap()["error"], "unknown bridge");
+    }
+
+    #[test]
+    fn test_bridge_close() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (events_tx, events) = mpsc::channel();
+        let mut bridges = Bridges::new(move |event| {
+            let _ = events_tx.send(event);
+        });
+
+        let response = bridges
+            .handle(&serde_json::json!({"action": "bridge-open", "port": port}))
+            .unwrap();
+        let id = response["bridgeId"].as_u64().unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        let close = serde_json::json!({"action": "bridge-close", "bridgeId": id});
+        assert_eq!(bridges.handle(&close).unwrap()["ok"], true);
+        // The server sees the hang-up, and no event echoes the close back
+        assert_eq!(server.read(&mut [0u8; 1]).unwrap(), 0);
+        assert!(events.recv_timeout(Duration::from_millis(200)).is_err());
+
+        let invalid = bridges
+            .handle(&serde_json::json!({"action": "bridge-open", "port": 70000}))
+            .unwrap();
+        assert_eq!(invalid["error"], "missing or invalid port");
+    }
+}
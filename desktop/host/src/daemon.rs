@@ -0,0 +1,181 @@
+//! Background `--daemon` mode: periodically runs the desktop app's headless
+//! updater so extension-only users still receive updates.
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ok200_common::daemon::load_daemon_config;
+use ok200_common::{lock, shared_state};
+
+const DAEMON_LOCK_FILENAME: &str = "daemon.lock";
+const UPDATE_RESULT_FILENAME: &str = "update-check-result.json";
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Exit codes of the app's headless updater other than 0 (up to date)
+/// that still mean the check succeeded.
+const UPDATER_EXIT_UPDATE_AVAILABLE: i32 = 10;
+const UPDATER_EXIT_INSTALLED: i32 = 20;
+
+/// Re-launch ourselves with `--daemon --foreground`, detached from the
+/// caller's stdio and session. Returns the child's pid.
+pub fn spawn_detached() -> Result<u32, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?;
+    let mut cmd = Command::new(exe);
+    cmd.args(["--daemon", "--foreground"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let child = cmd
+        .spawn()
+        .map_err(|e| format!("failed to spawn daemon: {e}"))?;
+    Ok(child.id())
+}
+
+/// Whether a daemon is already running.
+pub fn is_running() -> bool {
+    daemon_lock_path().is_some_and(|p| lock::is_locked(&p))
+}
+
+fn daemon_lock_path() -> Option<PathBuf> {
+    Some(ok200_common::native_dir()?.join(DAEMON_LOCK_FILENAME))
+}
+
+fn app_is_running() -> bool {
+    ok200_common::app_lock_path().is_some_and(|p| lock::is_locked(&p))
+}
+
+/// Run the daemon loop in the foreground until the desktop app starts.
+pub fn run() {
+    let Some(lock_path) = daemon_lock_path() else {
+        eprintln!("ok200-host: daemon: no config directory");
+        return;
+    };
+    let _lock = match lock::try_lock_exclusive(&lock_path) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            eprintln!("ok200-host: daemon: already running");
+            return;
+        }
+        Err(e) => {
+            eprintln!("ok200-host: daemon: failed to lock {}: {e}", lock_path.display());
+            return;
+        }
+    };
+
+    eprintln!("ok200-host: daemon: started, pid={}", std::process::id());
+    let mut last_attempt: Option<SystemTime> = None;
+
+    loop {
+        if app_is_running() {
+            eprintln!("ok200-host: daemon: desktop app is running, exiting");
+            break;
+        }
+
+        let config = load_daemon_config();
+        let interval = Duration::from_secs(config.update_check_interval_hours.saturating_mul(3600));
+        let last_check = latest(last_result_time(), last_attempt);
+        let now = SystemTime::now();
+
+        if update_due(last_check, now, interval) {
+            last_attempt = Some(now);
+            if let Err(e) = run_update_check(config.auto_update) {
+                eprintln!("ok200-host: daemon: update check failed: {e}");
+            }
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// When the headless updater last finished a check. Older apps only left
+/// the result file behind, so its modification time counts too.
+fn last_result_time() -> Option<SystemTime> {
+    let recorded = shared_state::load()
+        .last_update_check
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    let path = ok200_common::native_dir()?.join(UPDATE_RESULT_FILENAME);
+    let modified = std::fs::metadata(path).ok().and_then(|m| m.modified().ok());
+    latest(recorded, modified)
+}
+
+fn latest(a: Option<SystemTime>, b: Option<SystemTime>) -> Option<SystemTime> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn update_due(last_check: Option<SystemTime>, now: SystemTime, interval: Duration) -> bool {
+    match last_check {
+        None => true,
+        // A last check in the future means the clock moved backwards; check now.
+        Some(last) => now.duration_since(last).map_or(true, |age| age >= interval),
+    }
+}
+
+fn run_update_check(auto_update: bool) -> Result<(), String> {
+    let app = super::find_app_binary()?;
+    let flag = if auto_update {
+        "--auto-update"
+    } else {
+        "--check-update"
+    };
+    eprintln!("ok200-host: daemon: running {} {flag}", app.display());
+    let status = Command::new(&app)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .map_err(|e| format!("failed to run {}: {e}", app.display()))?;
+    match status.code() {
+        Some(0) => eprintln!("ok200-host: daemon: up to date"),
+        Some(UPDATER_EXIT_UPDATE_AVAILABLE) => eprintln!("ok200-host: daemon: update available"),
+        Some(UPDATER_EXIT_INSTALLED) => eprintln!("ok200-host: daemon: update installed"),
+        _ => return Err(format!("updater exited with {status}")),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_hours(24);
+
+    #[test]
+    fn test_update_due_without_previous_check() {
+        assert!(update_due(None, SystemTime::now(), DAY));
+    }
+
+    #[test]
+    fn test_update_due_respects_interval() {
+        let now = SystemTime::now();
+        assert!(!update_due(Some(now - Duration::from_hours(1)), now, DAY));
+        assert!(update_due(Some(now - DAY), now, DAY));
+        assert!(update_due(Some(now + Duration::from_mins(1)), now, DAY));
+    }
+
+    #[test]
+    fn test_latest() {
+        let now = SystemTime::now();
+        let earlier = now - Duration::from_secs(10);
+        assert_eq!(latest(Some(earlier), Some(now)), Some(now));
+        assert_eq!(latest(None, Some(earlier)), Some(earlier));
+        assert_eq!(latest(None, None), None);
+    }
+}
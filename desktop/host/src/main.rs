@@ -1,4 +1,29 @@
 use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod bridge;
+mod daemon;
+mod serve;
+mod transfer;
+mod validate;
+
+/// Version of the message protocol, `major.minor`. A new major version
+/// means older peers can't talk to this host; minor versions only add.
+const PROTOCOL_VERSION: &str = "1.0";
+/// Actions this host answers, sent in the handshake so peers can skip the
+/// ones it doesn't know.
+const CAPABILITIES: &[&str] = &[
+    "ping",
+    "browsers",
+    "state",
+    "daemon",
+    "launch",
+    "diagnostics",
+    "transfer",
+    "bridge",
+    "serve",
+];
 
 fn read_message_from(reader: &mut impl Read) -> io::Result<Option<serde_json::Value>> {
     let mut len_buf = [0u8; 4];
@@ -43,18 +68,46 @@ fn handle_message(msg: &serde_json::Value) -> serde_json::Value {
     let action = msg.get("action").and_then(|v| v.as_str()).unwrap_or("");
 
     match action {
-        "handshake" => {
+        "handshake" => handshake(msg),
+        "ping" => {
             serde_json::json!({
-                "action": "handshake",
-                "version": env!("CARGO_PKG_VERSION"),
-                "name": "ok200-host"
+                "action": "pong"
             })
         }
-        "ping" => {
+        "browsers" => {
             serde_json::json!({
-                "action": "pong"
+                "action": "browsers",
+                "browsers": ok200_common::browsers::probe_browsers()
+            })
+        }
+        "state" => {
+            serde_json::json!({
+                "action": "state",
+                "state": ok200_common::shared_state::load()
             })
         }
+        "daemon" => {
+            if daemon::is_running() {
+                serde_json::json!({
+                    "action": "daemon",
+                    "ok": true,
+                    "alreadyRunning": true
+                })
+            } else {
+                match daemon::spawn_detached() {
+                    Ok(pid) => serde_json::json!({
+                        "action": "daemon",
+                        "ok": true,
+                        "pid": pid
+                    }),
+                    Err(e) => serde_json::json!({
+                        "action": "daemon",
+                        "ok": false,
+                        "error": e
+                    }),
+                }
+            }
+        }
         "launch" => {
             match launch_app() {
                 Ok(()) => serde_json::json!({
@@ -68,6 +121,7 @@ fn handle_message(msg: &serde_json::Value) -> serde_json::Value {
                 }),
             }
         }
+        "diagnostics" => diagnostics(),
         _ => {
             serde_json::json!({
                 "error": format!("unknown action: {action}")
@@ -76,6 +130,37 @@ fn handle_message(msg: &serde_json::Value) -> serde_json::Value {
     }
 }
 
+/// Major part of a `major.minor` version.
+fn major_version(version: &str) -> Option<u32> {
+    version.split('.').next()?.parse().ok()
+}
+
+/// Answer a handshake. Peers that don't send `protocolVersion` predate it
+/// and are accepted; ones with another major version are refused.
+fn handshake(msg: &serde_json::Value) -> serde_json::Value {
+    let mut response = serde_json::json!({
+        "action": "handshake",
+        "version": env!("CARGO_PKG_VERSION"),
+        "name": "ok200-host",
+        "protocolVersion": PROTOCOL_VERSION,
+        "capabilities": CAPABILITIES,
+        "ok": true
+    });
+    let Some(theirs) = msg.get("protocolVersion").and_then(|v| v.as_str()) else {
+        return response;
+    };
+    if major_version(theirs) != major_version(PROTOCOL_VERSION) {
+        response["ok"] = false.into();
+        response["error"] = serde_json::json!({
+            "code": "unsupported_protocol_version",
+            "message": format!(
+                "protocol version {theirs} is not supported, expected {PROTOCOL_VERSION}"
+            ),
+        });
+    }
+    response
+}
+
 fn launch_app() -> Result<(), String> {
     #[cfg(target_os = "macos")]
     {
@@ -94,20 +179,11 @@ fn launch_app() -> Result<(), String> {
     #[cfg(target_os = "linux")]
     {
         // Try to find the Tauri binary relative to our own path
-        let host_path =
-            std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?;
-        let dir = host_path
-            .parent()
-            .ok_or_else(|| "cannot find parent directory".to_string())?;
-
-        for name in &["200-ok", "ok200-desktop", "200 OK"] {
-            let candidate = dir.join(name);
-            if candidate.exists() {
-                std::process::Command::new(&candidate)
-                    .spawn()
-                    .map_err(|e| format!("failed to spawn {}: {e}", candidate.display()))?;
-                return Ok(());
-            }
+        if let Ok(candidate) = find_app_binary() {
+            std::process::Command::new(&candidate)
+                .spawn()
+                .map_err(|e| format!("failed to spawn {}: {e}", candidate.display()))?;
+            return Ok(());
         }
 
         // Fallback: try gtk-launch with the desktop file
@@ -121,26 +197,16 @@ fn launch_app() -> Result<(), String> {
             return Ok(());
         }
 
-        return Err("could not find 200 OK app".to_string());
+        Err("could not find 200 OK app".to_string())
     }
 
     #[cfg(target_os = "windows")]
     {
-        let host_path =
-            std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?;
-        let dir = host_path
-            .parent()
-            .ok_or_else(|| "cannot find parent directory".to_string())?;
-
-        let app_exe = dir.join("200 OK.exe");
-        if app_exe.exists() {
-            std::process::Command::new(&app_exe)
-                .spawn()
-                .map_err(|e| format!("failed to spawn: {e}"))?;
-            return Ok(());
-        }
-
-        return Err("could not find 200 OK.exe".to_string());
+        let app_exe = find_app_binary().map_err(|_| "could not find 200 OK.exe".to_string())?;
+        std::process::Command::new(&app_exe)
+            .spawn()
+            .map_err(|e| format!("failed to spawn: {e}"))?;
+        return Ok(());
     }
 
     #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
@@ -149,13 +215,124 @@ fn launch_app() -> Result<(), String> {
     }
 }
 
+/// What support needs to triage "launch does nothing": where the host and
+/// its config live, and whether it can find the app.
+fn diagnostics() -> serde_json::Value {
+    let config_dir = ok200_common::native_dir();
+    let (app_binary, app_search_error) = match find_app_binary() {
+        Ok(path) => (Some(path), None),
+        Err(e) => (None, Some(e)),
+    };
+    serde_json::json!({
+        "action": "diagnostics",
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "hostPath": std::env::current_exe().ok(),
+        "configDir": config_dir,
+        // macOS launches by bundle id, so a missing binary is expected there
+        "appBinary": app_binary,
+        "appSearchError": app_search_error,
+        "freeDiskBytes": config_dir.as_deref().and_then(free_disk_bytes),
+    })
+}
+
+/// Free space on the disk holding `dir`, or its nearest existing parent,
+/// as the platform's own tools report it.
+#[cfg(unix)]
+fn free_disk_bytes(dir: &Path) -> Option<u64> {
+    let dir = dir.ancestors().find(|d| d.exists())?;
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // POSIX format: a header, then `fs blocks used available capacity% mount`
+    let fields: Vec<&str> = stdout.lines().nth(1)?.split_whitespace().collect();
+    let capacity = fields.iter().position(|f| f.ends_with('%'))?;
+    let kib: u64 = fields.get(capacity.checked_sub(1)?)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(windows)]
+fn free_disk_bytes(dir: &Path) -> Option<u64> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let dir = dir.ancestors().find(|d| d.exists())?;
+    let output = Command::new("powershell.exe")
+        .args([
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "(Get-Item -LiteralPath $env:OK200_DIR).PSDrive.Free",
+        ])
+        .env("OK200_DIR", dir)
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn free_disk_bytes(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Names the desktop app binary may have next to the host sidecar.
+const APP_BINARY_NAMES: &[&str] = &["200-ok", "ok200-desktop", "200 OK"];
+
+/// Locate the desktop app binary installed alongside this host binary.
+fn find_app_binary() -> Result<PathBuf, String> {
+    let host_path = std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?;
+    let dir = host_path
+        .parent()
+        .ok_or_else(|| "cannot find parent directory".to_string())?;
+
+    APP_BINARY_NAMES
+        .iter()
+        .map(|name| dir.join(format!("{name}{}", std::env::consts::EXE_SUFFIX)))
+        .find(|candidate| candidate.exists())
+        .ok_or_else(|| format!("could not find 200 OK app in {}", dir.display()))
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "--daemon") {
+        if args.iter().any(|a| a == "--foreground") {
+            daemon::run();
+        } else {
+            match daemon::spawn_detached() {
+                Ok(pid) => eprintln!("ok200-host: daemon started, pid={pid}"),
+                Err(e) => {
+                    eprintln!("ok200-host: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
     eprintln!("ok200-host: started, pid={}", std::process::id());
 
+    let mut transfers = transfer::Transfers::default();
+    let mut serving = serve::Serving::default();
+    let mut bridges = bridge::Bridges::new(|event| {
+        if let Err(e) = write_message(&event) {
+            eprintln!("ok200-host: write error: {e}");
+        }
+    });
+    let mut budget = validate::Budget::default();
     loop {
         match read_message() {
             Ok(Some(msg)) => {
-                let response = handle_message(&msg);
+                let checked = budget
+                    .take(std::time::Instant::now())
+                    .and_then(|()| validate::check_message(&msg));
+                let response = match checked {
+                    Ok(()) => transfers
+                        .handle(&msg)
+                        .or_else(|| bridges.handle(&msg))
+                        .or_else(|| serving.handle(&msg))
+                        .unwrap_or_else(|| handle_message(&msg)),
+                    Err(rejection) => rejection.response(&msg),
+                };
                 if let Err(e) = write_message(&response) {
                     eprintln!("ok200-host: write error: {e}");
                     break;
@@ -215,6 +392,57 @@ mod tests {
         assert!(response["version"].as_str().is_some());
     }
 
+    #[test]
+    fn test_handshake_protocol_version() {
+        let response = handle_message(&serde_json::json!({
+            "action": "handshake",
+            "protocolVersion": "1.7",
+            "capabilities": ["launch", "forward"]
+        }));
+        assert_eq!(response["ok"], true);
+        assert_eq!(response["protocolVersion"], PROTOCOL_VERSION);
+        assert!(response["capabilities"]
+            .as_array()
+            .unwrap()
+            .contains(&"launch".into()));
+
+        let response = handle_message(&serde_json::json!({
+            "action": "handshake",
+            "protocolVersion": "2.0"
+        }));
+        assert_eq!(response["ok"], false);
+        assert_eq!(response["error"]["code"], "unsupported_protocol_version");
+        // Still says what it is, so the peer can tell the user
+        assert_eq!(response["version"], env!("CARGO_PKG_VERSION"));
+
+        let response = handle_message(&serde_json::json!({
+            "action": "handshake",
+            "protocolVersion": "garbage"
+        }));
+        assert_eq!(response["ok"], false);
+    }
+
+    #[test]
+    fn test_handle_diagnostics() {
+        let msg = serde_json::json!({"action": "diagnostics"});
+        let response = handle_message(&msg);
+        assert_eq!(response["action"], "diagnostics");
+        assert_eq!(response["os"], std::env::consts::OS);
+        assert!(response["hostPath"].is_string());
+        // Not installed next to the test binary
+        assert!(response["appBinary"].is_null());
+        assert!(response["appSearchError"].is_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_free_disk_bytes() {
+        let tmp = std::env::temp_dir();
+        assert!(free_disk_bytes(&tmp).is_some_and(|b| b > 0));
+        // Falls back to the nearest folder that exists
+        assert!(free_disk_bytes(&tmp.join("missing/deeper")).is_some());
+    }
+
     #[test]
     fn test_handle_ping() {
         let msg = serde_json::json!({"action": "ping"});
@@ -222,6 +450,26 @@ mod tests {
         assert_eq!(response["action"], "pong");
     }
 
+    #[test]
+    fn test_handle_browsers() {
+        let msg = serde_json::json!({"action": "browsers"});
+        let response = handle_message(&msg);
+        assert_eq!(response["action"], "browsers");
+        let browsers = response["browsers"].as_array().unwrap();
+        assert_eq!(
+            browsers.len(),
+            ok200_common::browsers::known_browsers().len()
+        );
+    }
+
+    #[test]
+    fn test_handle_state() {
+        let msg = serde_json::json!({"action": "state"});
+        let response = handle_message(&msg);
+        assert_eq!(response["action"], "state");
+        assert!(response["state"]["servers"].is_array());
+    }
+
     #[test]
     fn test_handle_unknown_action() {
         let msg = serde_json::json!({"action": "unknown"});
@@ -0,0 +1,303 @@
+//! Bare static file server, so the extension can still serve a folder when
+//! the desktop app isn't installed or won't start. GET and HEAD only, on
+//! 127.0.0.1, one thread per connection and no keep-alive.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ok200_common::http::{html_escape, mime_type, percent_decode, percent_encode, reason, resolve};
+
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Server {
+    root: PathBuf,
+    port: u16,
+    stop: Arc<AtomicBool>,
+}
+
+impl Server {
+    fn start(root: &Path, port: u16) -> Result<Self, String> {
+        let root = std::fs::canonicalize(root).map_err(|e| format!("serve failed: {e}"))?;
+        if !root.is_dir() {
+            return Err(format!("serve failed: {} is not a folder", root.display()));
+        }
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .map_err(|e| format!("serve failed: {e}"))?;
+        let port = listener
+            .local_addr()
+            .map_err(|e| format!("serve failed: {e}"))?
+            .port();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (accept_root, accept_stop) = (root.clone(), Arc::clone(&stop));
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let root = accept_root.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &root) {
+                        eprintln!("ok200-host: serve error: {e}");
+                    }
+                });
+            }
+        });
+        Ok(Self { root, port, stop })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wake the accept loop so it sees the flag
+        let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, self.port));
+    }
+}
+
+/// The folder being served, if any.
+#[derive(Default)]
+pub struct Serving(Option<Server>);
+
+impl Serving {
+    /// Answer `msg` if it is `serve` or `serve_stop`. Serving again
+    /// replaces the previous folder.
+    pub fn handle(&mut self, msg: &serde_json::Value) -> Option<serde_json::Value> {
+        let action = msg.get("action").and_then(|v| v.as_str())?;
+        match action {
+            "serve" => {
+                // Free the port first in case the same one is asked for
+                self.0 = None;
+                let root = msg.get("root").and_then(|v| v.as_str()).unwrap_or("");
+                let port = msg
+                    .get("port")
+                    .and_then(serde_json::Value::as_u64)
+                    .unwrap_or(0);
+                let started = u16::try_from(port)
+                    .map_err(|_| "serve failed: invalid port".to_string())
+                    .and_then(|port| Server::start(Path::new(root), port));
+                Some(match started {
+                    Ok(server) => {
+                        let response = serde_json::json!({
+                            "action": "serve",
+                            "ok": true,
+                            "root": server.root,
+                            "port": server.port,
+                            "url": format!("http://127.0.0.1:{}/", server.port),
+                        });
+                        self.0 = Some(server);
+                        response
+                    }
+                    Err(e) => serde_json::json!({ "action": "serve", "ok": false, "error": e }),
+                })
+            }
+            "serve_stop" => {
+                let stopped = self.0.take().is_some();
+                Some(serde_json::json!({ "action": "serve_stop", "ok": stopped }))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Request line and headers, up to the blank line.
+fn read_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || head.len() + n > MAX_HEAD_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad request head",
+            ));
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    String::from_utf8(head).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_head(
+    stream: &mut TcpStream,
+    status: u16,
+    headers: &[(&str, &str)],
+    len: u64,
+) -> io::Result<()> {
+    let mut out = format!("HTTP/1.1 {status} {}\r\n", reason(status));
+    for (name, value) in headers {
+        let _ = write!(out, "{name}: {value}\r\n");
+    }
+    let _ = write!(out, "Content-Length: {len}\r\nConnection: close\r\n\r\n");
+    stream.write_all(out.as_bytes())
+}
+
+fn send(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+    head_only: bool,
+) -> io::Result<()> {
+    write_head(
+        stream,
+        status,
+        &[("Content-Type", content_type)],
+        body.len() as u64,
+    )?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    Ok(())
+}
+
+fn send_status(stream: &mut TcpStream, status: u16, head_only: bool) -> io::Result<()> {
+    let body = format!("{status} {}\n", reason(status));
+    send(
+        stream,
+        status,
+        "text/plain; charset=utf-8",
+        body.as_bytes(),
+        head_only,
+    )
+}
+
+fn listing(dir: &Path, url_path: &str) -> io::Result<String> {
+    let mut entries: Vec<(String, bool)> = std::fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|e| {
+            let is_dir = e.file_type().is_ok_and(|t| t.is_dir());
+            (e.file_name().to_string_lossy().into_owned(), is_dir)
+        })
+        .collect();
+    entries.sort();
+    let title = html_escape(url_path);
+    let mut html = format!("<!doctype html><title>{title}</title><h1>{title}</h1><ul>");
+    for (name, is_dir) in entries {
+        let slash = if is_dir { "/" } else { "" };
+        let _ = write!(
+            html,
+            "<li><a href=\"{}{slash}\">{}{slash}</a></li>",
+            percent_encode(&name),
+            html_escape(&name)
+        );
+    }
+    html.push_str("</ul>");
+    Ok(html)
+}
+
+fn handle_connection(mut stream: TcpStream, root: &Path) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let head = read_head(&mut stream)?;
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (request_line.next(), request_line.next().unwrap_or_default());
+    let head_only = method == Some("HEAD");
+    if !head_only && method != Some("GET") {
+        return send_status(&mut stream, 405, false);
+    }
+    let raw_path = target.split(['?', '#']).next().unwrap_or_default();
+    let Some(url_path) = percent_decode(raw_path).filter(|p| p.starts_with('/')) else {
+        return send_status(&mut stream, 400, head_only);
+    };
+    let Some(mut path) = resolve(root, &url_path) else {
+        return send_status(&mut stream, 404, head_only);
+    };
+    if path.is_dir() {
+        if !url_path.ends_with('/') {
+            let location = format!("{raw_path}/");
+            write_head(&mut stream, 301, &[("Location", &location)], 0)?;
+            return Ok(());
+        }
+        let index = path.join("index.html");
+        if !index.is_file() {
+            let html = listing(&path, &url_path)?;
+            return send(
+                &mut stream,
+                200,
+                mime_type(Some("html")),
+                html.as_bytes(),
+                head_only,
+            );
+        }
+        path = index;
+    }
+    let Ok(mut file) = File::open(&path) else {
+        return send_status(&mut stream, 403, head_only);
+    };
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let len = file.metadata()?.len();
+    write_head(
+        &mut stream,
+        200,
+        &[("Content-Type", mime_type(ext.as_deref()))],
+        len,
+    )?;
+    if !head_only {
+        io::copy(&mut file, &mut stream)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(port: u16, request: &str) -> String {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("index.html"), "<h1>hi</h1>").unwrap();
+        std::fs::create_dir(tmp.path().join("a b")).unwrap();
+        std::fs::write(tmp.path().join("a b/notes.txt"), "notes").unwrap();
+        let mut serving = Serving::default();
+
+        let response = serving
+            .handle(&serde_json::json!({
+                "action": "serve", "root": tmp.path(), "port": 0,
+            }))
+            .unwrap();
+        assert_eq!(response["ok"], true);
+        let port = u16::try_from(response["port"].as_u64().unwrap()).unwrap();
+
+        let index = get(port, "GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(index.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(index.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(index.ends_with("\r\n\r\n<h1>hi</h1>"));
+
+        let head = get(port, "HEAD /a%20b/notes.txt HTTP/1.1\r\n\r\n");
+        assert!(head.contains("Content-Length: 5\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+
+        let redirect = get(port, "GET /a%20b HTTP/1.1\r\n\r\n");
+        assert!(redirect.starts_with("HTTP/1.1 301"));
+        assert!(redirect.contains("Location: /a%20b/\r\n"));
+        let listing = get(port, "GET /a%20b/ HTTP/1.1\r\n\r\n");
+        assert!(listing.contains("<a href=\"notes.txt\">notes.txt</a>"));
+
+        assert!(get(port, "GET /../etc/passwd HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(get(port, "POST / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+
+        let stopped = serving.handle(&serde_json::json!({"action": "serve_stop"}));
+        assert_eq!(stopped.unwrap()["ok"], true);
+        let missing = serving.handle(&serde_json::json!({
+            "action": "serve", "root": tmp.path().join("missing"),
+        }));
+        assert_eq!(missing.unwrap()["ok"], false);
+    }
+}
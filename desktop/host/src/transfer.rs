@@ -0,0 +1,342 @@
+//! Files sent from the extension when the desktop app's HTTP server can't
+//! be reached. Native messaging only carries JSON, so the file arrives as
+//! numbered base64 chunks, one per message, and is checked against the
+//! SHA-256 announced up front.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+/// Largest file accepted.
+pub const MAX_TRANSFER_BYTES: u64 = 256 * 1024 * 1024;
+/// Decoded bytes per chunk; as base64 it stays under the 1 MiB message limit.
+pub const MAX_CHUNK_BYTES: usize = 512 * 1024;
+/// Bytes accepted per second across all transfers.
+const MAX_BYTES_PER_SEC: u64 = 16 * 1024 * 1024;
+const MAX_OPEN_TRANSFERS: usize = 4;
+const TRANSFERS_DIRNAME: &str = "transfers";
+
+struct Transfer {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+    received: u64,
+    next_index: u64,
+    hasher: Sha256,
+}
+
+/// Transfers in progress on this connection.
+pub struct Transfers {
+    dir: Option<PathBuf>,
+    open: HashMap<String, Transfer>,
+    /// Start of the current one-second window, and bytes taken in it.
+    window: (Instant, u64),
+}
+
+impl Default for Transfers {
+    fn default() -> Self {
+        Self::new(ok200_common::native_dir().map(|d| d.join(TRANSFERS_DIRNAME)))
+    }
+}
+
+impl Drop for Transfers {
+    fn drop(&mut self) {
+        // Unfinished files are useless once the extension disconnects
+        for (_, transfer) in self.open.drain() {
+            let _ = std::fs::remove_file(&transfer.path);
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+impl Transfers {
+    fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            open: HashMap::new(),
+            window: (Instant::now(), 0),
+        }
+    }
+
+    /// Answer `msg` if it is a transfer action.
+    pub fn handle(&mut self, msg: &serde_json::Value) -> Option<serde_json::Value> {
+        let action = msg.get("action").and_then(|v| v.as_str())?;
+        let result = match action {
+            "transfer_begin" => self.begin(msg),
+            "transfer_chunk" => self.chunk(msg, Instant::now()),
+            "transfer_end" => self.end(msg),
+            "transfer_abort" => self.abort(msg),
+            _ => return None,
+        };
+        let mut response = match result {
+            Ok(fields) => fields,
+            Err(e) => serde_json::json!({ "ok": false, "error": e }),
+        };
+        response["action"] = action.into();
+        Some(response)
+    }
+
+    fn begin(&mut self, msg: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let name = msg.get("name").and_then(|v| v.as_str()).unwrap_or("");
+        // Only the last component, so the name can't point elsewhere
+        let name = Path::new(name)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or("missing file name")?;
+        let size = msg
+            .get("size")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or("missing size")?;
+        let sha256 = msg
+            .get("sha256")
+            .and_then(|v| v.as_str())
+            .filter(|h| h.len() == 64 && h.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or("missing or invalid sha256")?
+            .to_ascii_lowercase();
+        if size > MAX_TRANSFER_BYTES {
+            return Err(format!(
+                "file is {size} bytes, the limit is {MAX_TRANSFER_BYTES}"
+            ));
+        }
+        if self.open.len() >= MAX_OPEN_TRANSFERS {
+            return Err("too many transfers in progress".to_string());
+        }
+        let dir = self.dir.as_ref().ok_or("no config directory")?;
+        std::fs::create_dir_all(dir).map_err(|e| format!("transfer failed: {e}"))?;
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let path = dir.join(format!("{id}-{name}"));
+        let file = File::create(&path).map_err(|e| format!("transfer failed: {e}"))?;
+        self.open.insert(
+            id.clone(),
+            Transfer {
+                file,
+                path,
+                size,
+                sha256,
+                received: 0,
+                next_index: 0,
+                hasher: Sha256::new(),
+            },
+        );
+        Ok(serde_json::json!({
+            "ok": true,
+            "transferId": id,
+            "chunkBytes": MAX_CHUNK_BYTES,
+        }))
+    }
+
+    fn transfer_id(msg: &serde_json::Value) -> Result<&str, String> {
+        msg.get("transferId")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "missing transferId".to_string())
+    }
+
+    /// Count `bytes` against the rate limit, or say how long to wait.
+    fn take_allowance(&mut self, bytes: u64, now: Instant) -> Result<(), Duration> {
+        let (start, taken) = &mut self.window;
+        let elapsed = now.saturating_duration_since(*start);
+        if elapsed >= Duration::from_secs(1) {
+            (*start, *taken) = (now, 0);
+        } else if *taken + bytes > MAX_BYTES_PER_SEC {
+            return Err(Duration::from_secs(1).saturating_sub(elapsed));
+        }
+        *taken += bytes;
+        Ok(())
+    }
+
+    fn chunk(
+        &mut self,
+        msg: &serde_json::Value,
+        now: Instant,
+    ) -> Result<serde_json::Value, String> {
+        let id = Self::transfer_id(msg)?.to_string();
+        let index = msg
+            .get("index")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or("missing index")?;
+        let data = msg.get("data").and_then(|v| v.as_str()).unwrap_or("");
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| format!("invalid base64: {e}"))?;
+        if bytes.len() > MAX_CHUNK_BYTES {
+            return Err(format!("chunk is over {MAX_CHUNK_BYTES} bytes"));
+        }
+        let transfer = self.open.get(&id).ok_or("unknown transfer")?;
+        if index != transfer.next_index {
+            return Err(format!(
+                "expected chunk {}, got {index}",
+                transfer.next_index
+            ));
+        }
+        if transfer.received + bytes.len() as u64 > transfer.size {
+            return Err("more data than the announced size".to_string());
+        }
+        if let Err(wait) = self.take_allowance(bytes.len() as u64, now) {
+            // Nothing was written, so the same chunk can be sent again
+            return Ok(serde_json::json!({
+                "ok": false,
+                "error": "rate limited",
+                "retryAfterMs": wait.as_millis() as u64,
+            }));
+        }
+        let transfer = self.open.get_mut(&id).ok_or("unknown transfer")?;
+        transfer
+            .file
+            .write_all(&bytes)
+            .map_err(|e| format!("transfer failed: {e}"))?;
+        transfer.hasher.update(&bytes);
+        transfer.received += bytes.len() as u64;
+        transfer.next_index += 1;
+        Ok(serde_json::json!({ "ok": true, "received": transfer.received }))
+    }
+
+    fn end(&mut self, msg: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let id = Self::transfer_id(msg)?;
+        let mut transfer = self.open.remove(id).ok_or("unknown transfer")?;
+        let flushed = transfer.file.flush();
+        let digest = hex(&std::mem::take(&mut transfer.hasher).finalize());
+        let problem = if let Err(e) = flushed {
+            Some(format!("transfer failed: {e}"))
+        } else if transfer.received != transfer.size {
+            Some(format!(
+                "received {} of {} bytes",
+                transfer.received, transfer.size
+            ))
+        } else if digest != transfer.sha256 {
+            Some("sha256 mismatch".to_string())
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            let _ = std::fs::remove_file(&transfer.path);
+            return Err(problem);
+        }
+        Ok(serde_json::json!({ "ok": true, "path": transfer.path }))
+    }
+
+    fn abort(&mut self, msg: &serde_json::Value) -> Result<serde_json::Value, String> {
+        let transfer = self
+            .open
+            .remove(Self::transfer_id(msg)?)
+            .ok_or("unknown transfer")?;
+        let _ = std::fs::remove_file(&transfer.path);
+        Ok(serde_json::json!({ "ok": true }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn b64(bytes: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn begin(transfers: &mut Transfers, name: &str, data: &[u8]) -> serde_json::Value {
+        transfers
+            .handle(&serde_json::json!({
+                "action": "transfer_begin",
+                "name": name,
+                "size": data.len(),
+                "sha256": hex(&Sha256::digest(data)),
+            }))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_transfer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut transfers = Transfers::new(Some(tmp.path().to_path_buf()));
+        assert!(transfers
+            .handle(&serde_json::json!({"action": "ping"}))
+            .is_none());
+
+        let response = begin(&mut transfers, "../../notes.txt", b"hello world");
+        assert_eq!(response["action"], "transfer_begin");
+        assert_eq!(response["ok"], true);
+        let id = response["transferId"].as_str().unwrap().to_string();
+        let chunk = |index: u64, data: &[u8]| {
+            serde_json::json!({
+                "action": "transfer_chunk",
+                "transferId": id,
+                "index": index,
+                "data": b64(data),
+            })
+        };
+
+        assert_eq!(
+            transfers.handle(&chunk(0, b"hello ")).unwrap()["received"],
+            6
+        );
+        // Out of order
+        assert_eq!(transfers.handle(&chunk(0, b"world")).unwrap()["ok"], false);
+        assert_eq!(transfers.handle(&chunk(1, b"world")).unwrap()["ok"], true);
+        let end = serde_json::json!({"action": "transfer_end", "transferId": id});
+        let response = transfers.handle(&end).unwrap();
+        assert_eq!(response["ok"], true);
+        let path = PathBuf::from(response["path"].as_str().unwrap());
+        assert_eq!(path.parent(), Some(tmp.path()));
+        assert!(path.to_string_lossy().ends_with("-notes.txt"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        // Finished transfers are gone
+        assert_eq!(transfers.handle(&end).unwrap()["error"], "unknown transfer");
+    }
+
+    #[test]
+    fn test_transfer_rejects_bad_data() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut transfers = Transfers::new(Some(tmp.path().to_path_buf()));
+
+        let too_big = transfers.handle(&serde_json::json!({
+            "action": "transfer_begin",
+            "name": "big.bin",
+            "size": MAX_TRANSFER_BYTES + 1,
+            "sha256": "0".repeat(64),
+        }));
+        assert_eq!(too_big.unwrap()["ok"], false);
+
+        // Right size, wrong content
+        let response = begin(&mut transfers, "a.bin", b"abc");
+        let id = response["transferId"].as_str().unwrap();
+        let chunk = serde_json::json!({
+            "action": "transfer_chunk", "transferId": id, "index": 0, "data": b64(b"xyz"),
+        });
+        assert_eq!(transfers.handle(&chunk).unwrap()["ok"], true);
+        let end = serde_json::json!({"action": "transfer_end", "transferId": id});
+        assert_eq!(transfers.handle(&end).unwrap()["error"], "sha256 mismatch");
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+
+        // Dropping the connection removes unfinished files
+        begin(&mut transfers, "b.bin", b"abc");
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+        drop(transfers);
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut transfers = Transfers::new(None);
+        let start = transfers.window.0;
+        assert!(transfers.take_allowance(MAX_BYTES_PER_SEC, start).is_ok());
+        let wait = transfers
+            .take_allowance(1, start + Duration::from_millis(400))
+            .unwrap_err();
+        assert_eq!(wait, Duration::from_millis(600));
+        assert!(transfers
+            .take_allowance(1, start + Duration::from_secs(1))
+            .is_ok());
+    }
+}
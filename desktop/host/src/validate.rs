@@ -0,0 +1,255 @@
+//! Checks run on every message before it is handled. Any extension allowed
+//! in the manifest can start the host, so nothing is trusted: messages
+//! must be objects naming a known action, with fields of the right types,
+//! and arrive no faster than `MAX_MESSAGES_PER_SEC`.
+
+use std::time::{Duration, Instant};
+
+const MAX_MESSAGES_PER_SEC: u32 = 500;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Str,
+    Uint,
+    Array,
+}
+
+/// `(field, kind, required)`
+type Fields = &'static [(&'static str, Kind, bool)];
+
+/// Fields each action reads. Others are ignored, so newer extensions can
+/// add fields without upsetting older hosts.
+const SCHEMAS: &[(&str, Fields)] = &[
+    (
+        "handshake",
+        &[
+            ("protocolVersion", Kind::Str, false),
+            ("capabilities", Kind::Array, false),
+        ],
+    ),
+    ("ping", &[]),
+    ("browsers", &[]),
+    ("state", &[]),
+    ("daemon", &[]),
+    ("launch", &[]),
+    ("diagnostics", &[]),
+    (
+        "transfer_begin",
+        &[
+            ("name", Kind::Str, true),
+            ("size", Kind::Uint, true),
+            ("sha256", Kind::Str, true),
+        ],
+    ),
+    (
+        "transfer_chunk",
+        &[
+            ("transferId", Kind::Str, true),
+            ("index", Kind::Uint, true),
+            ("data", Kind::Str, true),
+        ],
+    ),
+    ("transfer_end", &[("transferId", Kind::Str, true)]),
+    ("transfer_abort", &[("transferId", Kind::Str, true)]),
+    ("bridge-open", &[("port", Kind::Uint, true)]),
+    (
+        "bridge-data",
+        &[("bridgeId", Kind::Uint, true), ("data", Kind::Str, true)],
+    ),
+    ("bridge-close", &[("bridgeId", Kind::Uint, true)]),
+    (
+        "serve",
+        &[("root", Kind::Str, true), ("port", Kind::Uint, false)],
+    ),
+    ("serve_stop", &[]),
+];
+
+/// Why a message was turned away.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub code: &'static str,
+    pub message: String,
+    retry_after: Option<Duration>,
+}
+
+impl Rejection {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+
+    /// Response to `msg`, naming its action when it has one.
+    pub fn response(&self, msg: &serde_json::Value) -> serde_json::Value {
+        let mut response = serde_json::json!({
+            "ok": false,
+            "error": self.message,
+            "code": self.code,
+        });
+        if let Some(action) = msg.get("action").and_then(|v| v.as_str()) {
+            response["action"] = action.into();
+        }
+        if let Some(wait) = self.retry_after {
+            response["retryAfterMs"] = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX).into();
+        }
+        response
+    }
+}
+
+fn check_fields(
+    msg: &serde_json::Map<String, serde_json::Value>,
+    fields: Fields,
+) -> Result<(), Rejection> {
+    for &(name, kind, required) in fields {
+        let Some(value) = msg.get(name).filter(|v| !v.is_null()) else {
+            if required {
+                return Err(Rejection::new(
+                    "missing_field",
+                    format!("missing field: {name}"),
+                ));
+            }
+            continue;
+        };
+        let valid = match kind {
+            Kind::Str => value.is_string(),
+            Kind::Uint => value.is_u64(),
+            Kind::Array => value.is_array(),
+        };
+        if !valid {
+            let expected = match kind {
+                Kind::Str => "a string",
+                Kind::Uint => "a non-negative integer",
+                Kind::Array => "an array",
+            };
+            return Err(Rejection::new(
+                "invalid_field",
+                format!("{name} must be {expected}"),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check that `msg` is an object with a known action and well-typed
+/// fields.
+pub fn check_message(msg: &serde_json::Value) -> Result<(), Rejection> {
+    let Some(object) = msg.as_object() else {
+        return Err(Rejection::new(
+            "invalid_message",
+            "message must be an object",
+        ));
+    };
+    let Some(action) = object.get("action").and_then(|v| v.as_str()) else {
+        return Err(Rejection::new("invalid_message", "missing action"));
+    };
+    let Some((_, fields)) = SCHEMAS.iter().find(|(name, _)| *name == action) else {
+        return Err(Rejection::new(
+            "unknown_action",
+            format!("unknown action: {action}"),
+        ));
+    };
+    check_fields(object, fields)
+}
+
+/// Messages-per-second budget for one connection.
+pub struct Budget {
+    /// Start of the current one-second window, and messages seen in it.
+    window: (Instant, u32),
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            window: (Instant::now(), 0),
+        }
+    }
+}
+
+impl Budget {
+    /// Count a message arriving at `now` against the budget.
+    pub fn take(&mut self, now: Instant) -> Result<(), Rejection> {
+        let (start, count) = &mut self.window;
+        let elapsed = now.saturating_duration_since(*start);
+        if elapsed >= Duration::from_secs(1) {
+            (*start, *count) = (now, 0);
+        } else if *count >= MAX_MESSAGES_PER_SEC {
+            let mut rejection = Rejection::new("rate_limited", "too many messages");
+            rejection.retry_after = Some(Duration::from_secs(1).saturating_sub(elapsed));
+            return Err(rejection);
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(msg: &serde_json::Value) -> Option<&'static str> {
+        check_message(msg).err().map(|r| r.code)
+    }
+
+    #[test]
+    fn test_check_message() {
+        assert_eq!(code(&serde_json::json!({"action": "ping"})), None);
+        assert_eq!(code(&serde_json::json!([1, 2])), Some("invalid_message"));
+        assert_eq!(code(&serde_json::json!("ping")), Some("invalid_message"));
+        assert_eq!(
+            code(&serde_json::json!({"action": 1})),
+            Some("invalid_message")
+        );
+        assert_eq!(
+            code(&serde_json::json!({"action": "nope"})),
+            Some("unknown_action")
+        );
+
+        assert_eq!(
+            code(&serde_json::json!({"action": "bridge-open"})),
+            Some("missing_field")
+        );
+        assert_eq!(
+            code(&serde_json::json!({"action": "bridge-open", "port": -1})),
+            Some("invalid_field")
+        );
+        assert_eq!(
+            code(&serde_json::json!({"action": "serve", "root": "/srv", "port": null})),
+            None
+        );
+        // Fields an action doesn't read are left alone
+        assert_eq!(
+            code(&serde_json::json!({"action": "ping", "requestId": 7})),
+            None
+        );
+
+        let msg = serde_json::json!({"action": "transfer_end", "transferId": 1});
+        let response = check_message(&msg).unwrap_err().response(&msg);
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "action": "transfer_end",
+                "ok": false,
+                "error": "transferId must be a string",
+                "code": "invalid_field",
+            })
+        );
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut budget = Budget::default();
+        let start = budget.window.0;
+        for _ in 0..MAX_MESSAGES_PER_SEC {
+            assert!(budget.take(start).is_ok());
+        }
+        let rejection = budget.take(start + Duration::from_millis(250)).unwrap_err();
+        assert_eq!(rejection.code, "rate_limited");
+        assert_eq!(
+            rejection.response(&serde_json::json!({}))["retryAfterMs"],
+            750
+        );
+        assert!(budget.take(start + Duration::from_secs(1)).is_ok());
+    }
+}
@@ -1,3 +1,40 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+
+/// Hash every sidecar in `binaries/`, so the app can tell whether the one it
+/// finds at runtime is the one it was built with. Code signing during
+/// bundling changes the bytes, so a mismatch is only reported. Sidecar
+/// names carry the target triple, so this is a manifest per triple.
+fn write_sidecar_hashes() {
+    println!("cargo:rerun-if-changed=binaries");
+    let mut entries = Vec::new();
+    if let Ok(dir) = std::fs::read_dir("binaries") {
+        for entry in dir.filter_map(Result::ok) {
+            let Ok(bytes) = std::fs::read(entry.path()) else {
+                continue;
+            };
+            let hash = Sha256::digest(&bytes)
+                .iter()
+                .fold(String::new(), |mut out, b| {
+                    let _ = write!(out, "{b:02x}");
+                    out
+                });
+            entries.push((entry.file_name().to_string_lossy().into_owned(), hash));
+        }
+    }
+    entries.sort();
+    let mut source = String::from("&[\n");
+    for (name, hash) in entries {
+        let _ = writeln!(source, "    ({name:?}, {hash:?}),");
+    }
+    source.push(']');
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("sidecar_hashes.rs");
+    std::fs::write(out, source).unwrap();
+}
+
 fn main() {
+    write_sidecar_hashes();
     tauri_build::build();
 }
@@ -0,0 +1,206 @@
+//! Per-server access logs: each request handled by an `http_server` is
+//! written to a rotating file in Common Log Format, Combined Log Format or
+//! JSON lines.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use super::http_server::RequestLog;
+use super::logging::{self, LogFile, UtcTime};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format.
+    Common,
+    /// Common Log Format plus referer and user agent.
+    #[default]
+    Combined,
+    /// One JSON object per line, with every `RequestLog` field.
+    Json,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLogOptions {
+    #[serde(default)]
+    pub format: AccessLogFormat,
+    /// Write to `logs/access-<port>.log`.
+    #[serde(default = "default_true")]
+    pub file: bool,
+    /// Send each request over the channel given at creation.
+    #[serde(default = "default_true")]
+    pub stream: bool,
+}
+
+impl Default for AccessLogOptions {
+    fn default() -> Self {
+        Self {
+            format: AccessLogFormat::default(),
+            file: true,
+            stream: true,
+        }
+    }
+}
+
+/// `[10/Oct/2000:13:55:36 +0000]`
+fn clf_time(secs: u64) -> String {
+    let t = UtcTime::from_unix(secs);
+    let month = usize::try_from(t.month - 1).map_or("Jan", |m| MONTHS[m % 12]);
+    format!(
+        "[{:02}/{month}/{:04}:{:02}:{:02}:{:02} +0000]",
+        t.day, t.year, t.hour, t.minute, t.second
+    )
+}
+
+/// Quoted field; `"-"` when missing.
+fn quoted(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        None => "\"-\"".to_string(),
+    }
+}
+
+/// The line for one entry, without the newline. Connections that never
+/// sent a request only appear in JSON logs.
+pub fn format_entry(entry: &RequestLog, format: AccessLogFormat) -> Option<String> {
+    if format == AccessLogFormat::Json {
+        return serde_json::to_string(entry).ok();
+    }
+    if entry.method.is_empty() {
+        return None;
+    }
+    let host = entry
+        .remote_address
+        .parse::<SocketAddr>()
+        .map_or_else(|_| entry.remote_address.clone(), |a| a.ip().to_string());
+    let request = format!("{} {} HTTP/1.1", entry.method, entry.path);
+    let bytes = if entry.bytes == 0 {
+        "-".to_string()
+    } else {
+        entry.bytes.to_string()
+    };
+    let mut line = format!(
+        "{host} - - {} {} {} {bytes}",
+        clf_time(entry.time),
+        quoted(Some(&request)),
+        entry.status
+    );
+    if format == AccessLogFormat::Combined {
+        let _ = write!(
+            line,
+            " {} {}",
+            quoted(entry.referer.as_deref()),
+            quoted(entry.user_agent.as_deref())
+        );
+    }
+    Some(line)
+}
+
+pub fn access_log_path(dir: &Path, port: u16) -> PathBuf {
+    dir.join(format!("access-{port}.log"))
+}
+
+/// Rotating access log file for one server.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    file: Mutex<LogFile>,
+}
+
+impl AccessLog {
+    pub fn open(path: PathBuf, format: AccessLogFormat) -> Self {
+        Self {
+            format,
+            file: Mutex::new(LogFile::open(path)),
+        }
+    }
+
+    /// Open the log for the server on `port` in the app's log folder.
+    pub fn for_server(
+        app: &tauri::AppHandle,
+        port: u16,
+        format: AccessLogFormat,
+    ) -> Result<Self, String> {
+        let dir = logging::log_dir(&app.config().identifier).ok_or("no data directory")?;
+        Ok(Self::open(access_log_path(&dir, port), format))
+    }
+
+    pub fn write(&self, entry: &RequestLog) {
+        if let Some(line) = format_entry(entry, self.format) {
+            self.file.lock().unwrap().write_line(&format!("{line}\n"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> RequestLog {
+        RequestLog {
+            request_id: "7".to_string(),
+            remote_address: "192.168.1.20:51234".to_string(),
+            time: 971_186_136,
+            method: "GET".to_string(),
+            path: "/apache_pb.gif".to_string(),
+            status: 200,
+            bytes: 2326,
+            duration_ms: 3,
+            user_agent: Some("Mozilla/4.08 \"test\"".to_string()),
+            referer: None,
+            error: None,
+            uploads: Vec::new(),
+            capture: None,
+        }
+    }
+
+    #[test]
+    fn test_format_common_and_combined() {
+        assert_eq!(
+            format_entry(&entry(), AccessLogFormat::Common).unwrap(),
+            "192.168.1.20 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.1\" 200 2326"
+        );
+        assert_eq!(
+            format_entry(&entry(), AccessLogFormat::Combined).unwrap(),
+            "192.168.1.20 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.1\" 200 2326 \
+             \"-\" \"Mozilla/4.08 \\\"test\\\"\""
+        );
+    }
+
+    #[test]
+    fn test_format_json_and_failed_connections() {
+        let mut failed = entry();
+        failed.method.clear();
+        failed.error = Some("bad request".to_string());
+        assert_eq!(format_entry(&failed, AccessLogFormat::Common), None);
+
+        let json = format_entry(&failed, AccessLogFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["error"], "bad request");
+        assert_eq!(value["durationMs"], 3);
+        assert_eq!(value["requestId"], "7");
+    }
+
+    #[test]
+    fn test_access_log_writes_lines() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = access_log_path(tmp.path(), 8080);
+        let log = AccessLog::open(path.clone(), AccessLogFormat::Common);
+        log.write(&entry());
+        log.write(&entry());
+        let text = std::fs::read_to_string(path).unwrap();
+        assert_eq!(text.lines().count(), 2);
+    }
+}
@@ -0,0 +1,203 @@
+//! Per-server access control for the Rust HTTP layer (`http_server`):
+//! HTTP Basic credentials, or a bearer token sent as an `Authorization`
+//! header or a `?token=` query parameter. A token given in the query is
+//! also set as a cookie so pages can load their own assets.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Cookie carrying a token that arrived in the query string.
+pub const TOKEN_COOKIE: &str = "ok200_token";
+pub const TOKEN_PARAM: &str = "token";
+const REALM: &str = "200 OK";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: String,
+    },
+    /// A missing token is generated when the server is created.
+    Token {
+        #[serde(default)]
+        token: String,
+    },
+}
+
+/// Credentials presented by a request.
+#[derive(Default)]
+pub struct Credentials<'a> {
+    pub authorization: Option<&'a str>,
+    pub query_token: Option<String>,
+    pub cookie: Option<&'a str>,
+}
+
+/// Compare secrets without returning early on the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn generate_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Value of `name` in a `Cookie` header.
+fn cookie_value<'a>(cookie: &'a str, name: &str) -> Option<&'a str> {
+    cookie
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(n, _)| *n == name)
+        .map(|(_, value)| value)
+}
+
+impl Auth {
+    /// Fill in a generated token if none was given.
+    pub fn with_token(self) -> Self {
+        match self {
+            Self::Token { token } if token.is_empty() => Self::Token {
+                token: generate_token(),
+            },
+            auth => auth,
+        }
+    }
+
+    pub fn check(&self, creds: &Credentials<'_>) -> bool {
+        match self {
+            Self::Basic { username, password } => {
+                let decoded = creds
+                    .authorization
+                    .and_then(|h| h.strip_prefix("Basic "))
+                    .and_then(|encoded| {
+                        base64::engine::general_purpose::STANDARD
+                            .decode(encoded.trim())
+                            .ok()
+                    });
+                decoded.is_some_and(|d| {
+                    constant_time_eq(&d, format!("{username}:{password}").as_bytes())
+                })
+            }
+            Self::Token { token } => {
+                let presented = creds
+                    .authorization
+                    .and_then(|h| h.strip_prefix("Bearer "))
+                    .map(str::trim)
+                    .or(creds.query_token.as_deref())
+                    .or_else(|| creds.cookie.and_then(|c| cookie_value(c, TOKEN_COOKIE)));
+                presented.is_some_and(|p| constant_time_eq(p.as_bytes(), token.as_bytes()))
+            }
+        }
+    }
+
+    /// Check a user name and password sent some other way than in HTTP
+    /// headers, as FTP's `USER` and `PASS` do. A token is the password, and
+    /// any user name goes with it.
+    pub fn check_login(&self, user: &str, pass: &str) -> bool {
+        match self {
+            Self::Basic { username, password } => {
+                // Both compared, so a wrong name takes as long as a wrong password
+                let name_ok = constant_time_eq(user.as_bytes(), username.as_bytes());
+                constant_time_eq(pass.as_bytes(), password.as_bytes()) && name_ok
+            }
+            Self::Token { token } => constant_time_eq(pass.as_bytes(), token.as_bytes()),
+        }
+    }
+
+    /// `WWW-Authenticate` value for a 401 response.
+    pub fn challenge(&self) -> String {
+        match self {
+            Self::Basic { .. } => format!("Basic realm=\"{REALM}\", charset=\"UTF-8\""),
+            Self::Token { .. } => format!("Bearer realm=\"{REALM}\""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic() {
+        let auth = Auth::Basic {
+            username: "alice".to_string(),
+            password: "s3cret".to_string(),
+        };
+        let header = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("alice:s3cret")
+        );
+        let ok = Credentials {
+            authorization: Some(&header),
+            ..Credentials::default()
+        };
+        assert!(auth.check(&ok));
+        let wrong = Credentials {
+            authorization: Some("Basic YWxpY2U6bm9wZQ=="),
+            ..Credentials::default()
+        };
+        assert!(!auth.check(&wrong));
+        assert!(!auth.check(&Credentials::default()));
+    }
+
+    #[test]
+    fn test_token() {
+        let auth = Auth::Token {
+            token: "abc123".to_string(),
+        };
+        let header = Credentials {
+            authorization: Some("Bearer abc123"),
+            ..Credentials::default()
+        };
+        let query = Credentials {
+            query_token: Some("abc123".to_string()),
+            ..Credentials::default()
+        };
+        let cookie = Credentials {
+            cookie: Some("theme=dark; ok200_token=abc123"),
+            ..Credentials::default()
+        };
+        let wrong = Credentials {
+            query_token: Some("abc124".to_string()),
+            ..Credentials::default()
+        };
+        assert!(auth.check(&header) && auth.check(&query) && auth.check(&cookie));
+        assert!(!auth.check(&wrong));
+        assert!(!auth.check(&Credentials::default()));
+    }
+
+    #[test]
+    fn test_check_login() {
+        let basic = Auth::Basic {
+            username: "alice".to_string(),
+            password: "s3cret".to_string(),
+        };
+        assert!(basic.check_login("alice", "s3cret"));
+        assert!(!basic.check_login("bob", "s3cret"));
+        assert!(!basic.check_login("alice", "nope"));
+        let token = Auth::Token {
+            token: "abc123".to_string(),
+        };
+        assert!(token.check_login("anonymous", "abc123"));
+        assert!(!token.check_login("anonymous", ""));
+    }
+
+    #[test]
+    fn test_generated_token() {
+        let auth: Auth = serde_json::from_str(r#"{"type": "token"}"#).unwrap();
+        let Auth::Token { token } = auth.with_token() else {
+            panic!("expected token auth");
+        };
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"same", b"same"));
+        assert!(!constant_time_eq(b"same", b"sane"));
+        assert!(!constant_time_eq(b"same", b"samey"));
+    }
+}
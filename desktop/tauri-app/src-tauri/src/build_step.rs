@@ -0,0 +1,276 @@
+//! A build command run before a native server starts, for serving the
+//! output of a static site generator (`zola build`, `hugo`, `npm run
+//! build`) in one click. Its output is streamed to the webview line by
+//! line, and when it fails the server isn't started. With `watch` it runs
+//! again whenever files in its folder change; a failed rebuild leaves the
+//! last good output being served.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::task::JoinHandle;
+
+use super::live_reload::{snapshot, Snapshot};
+
+const DEFAULT_TIMEOUT_SECS: u64 = 600;
+const MAX_TIMEOUT_SECS: u64 = 3600;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Changes have to stop for this long before a rebuild, so saving several
+/// files builds once.
+const SETTLE: Duration = Duration::from_millis(500);
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildStep {
+    /// Program to run, by path or looked up in `PATH`.
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Folder to run in, usually the project the served folder is built
+    /// from.
+    pub cwd: String,
+    /// Added to the app's environment.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Build again when files in `cwd` change. Hidden entries and
+    /// `node_modules` are ignored, as is anything the build itself writes.
+    #[serde(default)]
+    pub watch: bool,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Streamed over the `onBuild` channel.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum BuildEvent {
+    /// `rebuild` when set off by a change rather than the server starting.
+    Started {
+        rebuild: bool,
+    },
+    Output {
+        line: String,
+        stderr: bool,
+    },
+    Finished {
+        success: bool,
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+        error: Option<String>,
+    },
+}
+
+/// Check `step` can run: a command, a folder to run it in, and a sane
+/// timeout.
+pub fn check_build(step: &BuildStep) -> Result<(), String> {
+    if step.command.trim().is_empty() {
+        return Err("build without a command".to_string());
+    }
+    if !Path::new(&step.cwd).is_dir() {
+        return Err(format!("build folder not found: {}", step.cwd));
+    }
+    if !(1..=MAX_TIMEOUT_SECS).contains(&step.timeout_secs) {
+        return Err(format!(
+            "build timeoutSecs must be from 1 to {MAX_TIMEOUT_SECS}"
+        ));
+    }
+    Ok(())
+}
+
+async fn forward(
+    pipe: Option<impl AsyncRead + Unpin>,
+    stderr: bool,
+    on_event: &(dyn Fn(BuildEvent) + Sync),
+) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        on_event(BuildEvent::Output { line, stderr });
+    }
+}
+
+async fn run_command(
+    step: &BuildStep,
+    on_event: &(dyn Fn(BuildEvent) + Sync),
+) -> Result<(), String> {
+    let mut cmd = tokio::process::Command::new(&step.command);
+    cmd.args(&step.args)
+        .current_dir(&step.cwd)
+        .envs(&step.env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(target_os = "windows")]
+    {
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    let mut child = cmd.spawn().map_err(|e| format!("build failed: {e}"))?;
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    // Owns the child, so a timeout drops and kills it
+    let finished = async move {
+        tokio::join!(
+            forward(stdout, false, on_event),
+            forward(stderr, true, on_event)
+        );
+        child.wait().await
+    };
+    let timeout = Duration::from_secs(step.timeout_secs);
+    match tokio::time::timeout(timeout, finished).await {
+        Ok(Ok(status)) if status.success() => Ok(()),
+        Ok(Ok(status)) => Err(format!("build failed: {status}")),
+        Ok(Err(e)) => Err(format!("build failed: {e}")),
+        Err(_) => Err(format!("build failed: killed after {timeout:?}")),
+    }
+}
+
+/// Run `step` once, telling `on_event` how it goes.
+pub async fn run(
+    step: &BuildStep,
+    rebuild: bool,
+    on_event: &(dyn Fn(BuildEvent) + Sync),
+) -> Result<(), String> {
+    on_event(BuildEvent::Started { rebuild });
+    let started = Instant::now();
+    let result = run_command(step, on_event).await;
+    let duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    match &result {
+        Ok(()) => tracing::info!("build in {} took {duration_ms} ms", step.cwd),
+        Err(e) => tracing::warn!("build in {}: {e}", step.cwd),
+    }
+    on_event(BuildEvent::Finished {
+        success: result.is_ok(),
+        duration_ms,
+        error: result.as_ref().err().cloned(),
+    });
+    result
+}
+
+async fn scan(dir: &Path) -> Snapshot {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || snapshot(&dir))
+        .await
+        .unwrap_or_default()
+}
+
+/// Rebuilds on changes until dropped.
+pub struct BuildWatch(JoinHandle<()>);
+
+impl Drop for BuildWatch {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Run `step` again whenever files in its folder change.
+pub fn watch(step: BuildStep, on_event: impl Fn(BuildEvent) + Send + Sync + 'static) -> BuildWatch {
+    BuildWatch(tokio::spawn(async move {
+        let cwd = PathBuf::from(&step.cwd);
+        let mut baseline = scan(&cwd).await;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let mut latest = scan(&cwd).await;
+            if latest == baseline {
+                continue;
+            }
+            loop {
+                tokio::time::sleep(SETTLE).await;
+                let next = scan(&cwd).await;
+                if next == latest {
+                    break;
+                }
+                latest = next;
+            }
+            run(&step, true, &on_event).await.ok();
+            // What the build wrote doesn't count as a change
+            baseline = scan(&cwd).await;
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn step(json: serde_json::Value) -> BuildStep {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_check_build() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cwd = tmp.path().to_string_lossy().to_string();
+        let ok = step(serde_json::json!({"command": "zola", "args": ["build"], "cwd": cwd}));
+        assert_eq!(ok.timeout_secs, DEFAULT_TIMEOUT_SECS);
+        assert!(!ok.watch);
+        assert!(check_build(&ok).is_ok());
+        assert!(check_build(&step(serde_json::json!({"command": " ", "cwd": cwd}))).is_err());
+        let missing = tmp.path().join("missing").to_string_lossy().to_string();
+        assert!(check_build(&step(serde_json::json!({"command": "x", "cwd": missing}))).is_err());
+        let forever = serde_json::json!({"command": "x", "cwd": cwd, "timeoutSecs": 0});
+        assert!(check_build(&step(forever)).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cwd = tmp.path().to_string_lossy().to_string();
+        let events = Mutex::new(Vec::new());
+        let on_event = |event| events.lock().unwrap().push(event);
+
+        let script = "echo built $SITE_ENV; echo warning >&2";
+        let build = step(serde_json::json!({
+            "command": "sh",
+            "args": ["-c", script],
+            "cwd": cwd,
+            "env": {"SITE_ENV": "prod"},
+        }));
+        run(&build, false, &on_event).await.unwrap();
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        assert_eq!(events[0], BuildEvent::Started { rebuild: false });
+        assert!(events.contains(&BuildEvent::Output {
+            line: "built prod".to_string(),
+            stderr: false,
+        }));
+        assert!(events.contains(&BuildEvent::Output {
+            line: "warning".to_string(),
+            stderr: true,
+        }));
+        assert!(matches!(
+            events.last(),
+            Some(BuildEvent::Finished {
+                success: true,
+                error: None,
+                ..
+            })
+        ));
+
+        let failing =
+            step(serde_json::json!({"command": "sh", "args": ["-c", "exit 3"], "cwd": cwd}));
+        let error = run(&failing, false, &|_| {}).await.unwrap_err();
+        assert!(error.contains('3'), "{error}");
+
+        let slow = step(serde_json::json!({
+            "command": "sleep",
+            "args": ["5"],
+            "cwd": cwd,
+            "timeoutSecs": 1,
+        }));
+        let started = Instant::now();
+        assert!(run(&slow, false, &|_| {}).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,381 @@
+//! Scoped tokens for the filesystem and TCP commands. The app's own window
+//! is trusted; any other webview must send a token, issued by the main
+//! window, in the `x-capability-token` header, and the token must cover
+//! what the command touches ("read-only access to /home/me/site"). Every
+//! other command is refused to other webviews unless it's in `UNGUARDED`.
+//! Checked centrally in `guard`, before any command runs, so untrusted
+//! content in another window can't reach the rest of the disk or the
+//! network, start servers, or run programs.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::ipc::{Invoke, InvokeBody, InvokeMessage};
+use tauri::{Manager, State, Webview};
+
+use super::fs_commands::FsState;
+
+const TOKEN_HEADER: &str = "x-capability-token";
+const TRUSTED_WEBVIEW: &str = "main";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Scope {
+    /// Files under `root`, read-only unless `write`.
+    Fs {
+        root: PathBuf,
+        #[serde(default)]
+        write: bool,
+    },
+    /// Any TCP server or socket.
+    Tcp,
+}
+
+/// A token's scope and the webview it was issued to.
+#[derive(Clone, Debug)]
+struct Grant {
+    webview: String,
+    scope: Scope,
+}
+
+/// What a guarded command needs from a token.
+#[derive(Debug, PartialEq, Eq)]
+enum Need {
+    Tcp,
+    /// Access to each of `paths`, and to change them if `writes`.
+    Fs {
+        paths: Vec<PathBuf>,
+        writes: bool,
+    },
+}
+
+/// Argument holding the file a command reads or changes.
+enum Target {
+    Paths(&'static [&'static str]),
+    /// An open file, by `handleId`; checked against the path it was
+    /// opened with.
+    Handle,
+    /// Touches no file itself.
+    Nothing,
+}
+
+/// Commands any webview may call without a token; they check the caller
+/// themselves.
+const UNGUARDED: &[&str] = &["capability_issue", "capability_revoke"];
+
+/// Network commands other than `tcp_`, covered by a `Tcp` token.
+const NETWORK_COMMANDS: &[&str] = &["network_resolve"];
+
+/// `tcp_` commands refused to untrusted webviews outright:
+/// `tcp_send_file` reads any file, which a `Tcp` token doesn't cover.
+const TCP_REFUSED: &[&str] = &["tcp_send_file"];
+
+/// `(command, changes files, target)`. Other `fs_` commands are refused
+/// to untrusted webviews outright. `fs_open` is a write unless its mode
+/// is "r".
+const FS_COMMANDS: &[(&str, bool, Target)] = &[
+    ("fs_open", false, Target::Paths(&["path"])),
+    ("fs_read", false, Target::Handle),
+    ("fs_write", true, Target::Handle),
+    ("fs_close", false, Target::Handle),
+    ("fs_truncate", true, Target::Handle),
+    ("fs_sync", true, Target::Handle),
+    ("fs_stat", false, Target::Paths(&["path"])),
+    ("fs_exists", false, Target::Paths(&["path"])),
+    ("fs_readdir", false, Target::Paths(&["path"])),
+    ("fs_readdir_detailed", false, Target::Paths(&["path"])),
+    ("fs_readlink", false, Target::Paths(&["path"])),
+    ("fs_realpath", false, Target::Paths(&["path"])),
+    ("fs_list_tree", false, Target::Paths(&["path"])),
+    ("fs_get_xattr", false, Target::Paths(&["path"])),
+    ("fs_list_xattrs", false, Target::Paths(&["path"])),
+    ("fs_resolve_within", false, Target::Paths(&["base"])),
+    ("fs_set_xattr", true, Target::Paths(&["path"])),
+    ("fs_mkdir", true, Target::Paths(&["path"])),
+    ("fs_delete", true, Target::Paths(&["path"])),
+    ("fs_hardlink", true, Target::Paths(&["src", "dst"])),
+    ("fs_operation_start", false, Target::Nothing),
+    ("fs_cancel", false, Target::Nothing),
+];
+
+/// What `command` needs, or `None` if it isn't guarded. Commands not
+/// listed anywhere are refused. `handle` is the handle id the command was
+/// given, if any.
+fn need(
+    command: &str,
+    args: &serde_json::Value,
+    handle: Option<u32>,
+    handle_path: impl Fn(u32) -> Option<PathBuf>,
+) -> Result<Option<Need>, String> {
+    let refused = || format!("{command} is not available to this window");
+    if UNGUARDED.contains(&command) {
+        return Ok(None);
+    }
+    if TCP_REFUSED.contains(&command) {
+        return Err(refused());
+    }
+    if command.starts_with("tcp_") || NETWORK_COMMANDS.contains(&command) {
+        return Ok(Some(Need::Tcp));
+    }
+    let Some((_, write, target)) = FS_COMMANDS.iter().find(|(name, ..)| *name == command) else {
+        return Err(refused());
+    };
+    let writes = *write || (command == "fs_open" && args["mode"] != "r");
+    let paths = match target {
+        Target::Paths(names) => names
+            .iter()
+            .map(|name| {
+                args[name]
+                    .as_str()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| format!("{command}: missing {name}"))
+            })
+            .collect::<Result<_, _>>()?,
+        Target::Handle => {
+            let handle = handle.ok_or_else(|| format!("{command}: missing handle"))?;
+            vec![handle_path(handle).ok_or_else(|| format!("handle {handle} not found"))?]
+        }
+        Target::Nothing => Vec::new(),
+    };
+    Ok(Some(Need::Fs { paths, writes }))
+}
+
+/// Whether `path` is `root` or inside it. Paths that don't exist yet are
+/// judged by their nearest existing ancestor, so a symlink can't lead out.
+fn within(root: &Path, path: &Path) -> bool {
+    if !path.is_absolute() || path.components().any(|c| c == Component::ParentDir) {
+        return false;
+    }
+    path.ancestors()
+        .find_map(|ancestor| {
+            let resolved = std::fs::canonicalize(ancestor).ok()?;
+            Some(resolved.join(path.strip_prefix(ancestor).ok()?))
+        })
+        .is_some_and(|resolved| resolved.starts_with(root))
+}
+
+impl Scope {
+    fn allows(&self, need: &Need) -> bool {
+        match (self, need) {
+            (Self::Tcp, Need::Tcp) => true,
+            (Self::Fs { root, write }, Need::Fs { paths, writes }) => {
+                (*write || !writes) && paths.iter().all(|p| within(root, p))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Tokens issued so far.
+#[derive(Default)]
+pub struct CapabilityTokens(Mutex<HashMap<String, Grant>>);
+
+impl CapabilityTokens {
+    fn issue(&self, webview: &str, scope: Scope) -> Result<String, String> {
+        let scope = match scope {
+            Scope::Fs { root, write } => Scope::Fs {
+                root: std::fs::canonicalize(&root)
+                    .map_err(|e| format!("issue failed: {}: {e}", root.display()))?,
+                write,
+            },
+            Scope::Tcp => Scope::Tcp,
+        };
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let grant = Grant {
+            webview: webview.to_string(),
+            scope,
+        };
+        self.0.lock().unwrap().insert(token.clone(), grant);
+        Ok(token)
+    }
+
+    /// Drop every token issued to `webview`, e.g. when it closes.
+    pub fn revoke_webview(&self, webview: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|_, grant| grant.webview != webview);
+    }
+
+    fn check(&self, webview: &str, token: Option<&str>, need: &Need) -> Result<(), String> {
+        let tokens = self.0.lock().unwrap();
+        let grant = token
+            .and_then(|token| tokens.get(token))
+            .filter(|grant| grant.webview == webview)
+            .ok_or("missing or invalid capability token")?;
+        if grant.scope.allows(need) {
+            Ok(())
+        } else {
+            Err("capability token does not cover this".to_string())
+        }
+    }
+}
+
+fn header<'a>(message: &'a InvokeMessage, name: &str) -> Option<&'a str> {
+    message.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Refuse the message if it comes from an untrusted webview without a
+/// token that covers it.
+fn check(message: &InvokeMessage) -> Result<(), String> {
+    let webview = message.webview_ref();
+    if webview.label() == TRUSTED_WEBVIEW {
+        return Ok(());
+    }
+    let args = match message.payload() {
+        InvokeBody::Json(args) => args,
+        InvokeBody::Raw(_) => &serde_json::Value::Null,
+    };
+    let handle = args["handleId"]
+        .as_u64()
+        .and_then(|id| u32::try_from(id).ok())
+        .or_else(|| header(message, "x-handle-id")?.parse().ok());
+    let fs = webview.state::<FsState>();
+    let Some(need) = need(message.command(), args, handle, |id| fs.handle_path(id))? else {
+        return Ok(());
+    };
+    webview
+        .state::<CapabilityTokens>()
+        .check(webview.label(), header(message, TOKEN_HEADER), &need)
+}
+
+/// Wrap the app's invoke handler so every command is checked first.
+pub fn guard(
+    handler: impl Fn(Invoke) -> bool + Send + Sync + 'static,
+) -> impl Fn(Invoke) -> bool + Send + Sync + 'static {
+    move |invoke| {
+        if let Err(e) = check(&invoke.message) {
+            tracing::warn!(
+                "{}: refused {}: {e}",
+                invoke.message.webview_ref().label(),
+                invoke.message.command()
+            );
+            invoke.resolver.reject(e);
+            return true;
+        }
+        handler(invoke)
+    }
+}
+
+/// Issue a token for the webview labelled `window`. Only the app's own
+/// window may do this.
+#[tauri::command]
+pub async fn capability_issue(
+    window: String,
+    scope: Scope,
+    webview: Webview,
+    tokens: State<'_, CapabilityTokens>,
+) -> Result<String, String> {
+    if webview.label() != TRUSTED_WEBVIEW {
+        return Err("issue failed: not allowed from this window".to_string());
+    }
+    tokens.issue(&window, scope)
+}
+
+#[tauri::command]
+pub async fn capability_revoke(
+    token: String,
+    webview: Webview,
+    tokens: State<'_, CapabilityTokens>,
+) -> Result<(), String> {
+    if webview.label() != TRUSTED_WEBVIEW {
+        return Err("revoke failed: not allowed from this window".to_string());
+    }
+    tokens.0.lock().unwrap().remove(&token);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fs_need(
+        command: &str,
+        args: &serde_json::Value,
+        handle: Option<u32>,
+    ) -> Result<Option<Need>, String> {
+        need(command, args, handle, |id| {
+            (id == 7).then(|| PathBuf::from("/srv/site/log.txt"))
+        })
+    }
+
+    #[test]
+    fn test_need() {
+        let none = serde_json::Value::Null;
+        assert_eq!(fs_need("capability_issue", &none, None), Ok(None));
+        assert_eq!(fs_need("tcp_connect", &none, None), Ok(Some(Need::Tcp)));
+        assert_eq!(fs_need("network_resolve", &none, None), Ok(Some(Need::Tcp)));
+        assert!(fs_need("fs_audit_tail", &none, None).is_err());
+        // Everything else is refused: servers can run build commands and
+        // hooks, and tcp_send_file reads any file
+        let server = serde_json::json!({"root": "/", "host": "0.0.0.0"});
+        assert!(fs_need("http_server_create", &server, None).is_err());
+        assert!(fs_need("settings_get", &none, None).is_err());
+        let send = serde_json::json!({"path": "/etc/passwd"});
+        assert!(fs_need("tcp_send_file", &send, None).is_err());
+
+        let open = |mode| serde_json::json!({"path": "/srv/site/a.txt", "mode": mode});
+        let read = Need::Fs {
+            paths: vec![PathBuf::from("/srv/site/a.txt")],
+            writes: false,
+        };
+        assert_eq!(fs_need("fs_open", &open("r"), None), Ok(Some(read)));
+        let Ok(Some(Need::Fs { writes, .. })) = fs_need("fs_open", &open("w"), None) else {
+            panic!("expected an fs need");
+        };
+        assert!(writes);
+
+        assert_eq!(
+            fs_need("fs_truncate", &none, Some(7)),
+            Ok(Some(Need::Fs {
+                paths: vec![PathBuf::from("/srv/site/log.txt")],
+                writes: true,
+            }))
+        );
+        assert!(fs_need("fs_read", &none, Some(8)).is_err());
+        assert!(fs_need("fs_hardlink", &serde_json::json!({"src": "/a"}), None).is_err());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir(root.join("site")).unwrap();
+        let site = root.join("site");
+        let fs = |path: PathBuf, writes| Need::Fs {
+            paths: vec![path],
+            writes,
+        };
+
+        let tokens = CapabilityTokens::default();
+        let scope = Scope::Fs {
+            root: site.clone(),
+            write: false,
+        };
+        let token = tokens.issue("preview", scope).unwrap();
+        let check = |webview, need| tokens.check(webview, Some(&token), &need);
+
+        assert!(check("preview", fs(site.join("index.html"), false)).is_ok());
+        assert!(check("preview", fs(site.join("new/dir/file"), false)).is_ok());
+        assert!(check("preview", fs(site.join("index.html"), true)).is_err());
+        assert!(check("preview", fs(root.join("secret"), false)).is_err());
+        assert!(check("preview", fs(site.join("../secret"), false)).is_err());
+        assert!(check("preview", Need::Tcp).is_err());
+        // Bound to the webview it was issued to
+        assert!(check("other", fs(site.join("index.html"), false)).is_err());
+        assert!(tokens
+            .check("preview", None, &fs(site.join("index.html"), false))
+            .is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&root, site.join("escape")).unwrap();
+            assert!(check("preview", fs(site.join("escape/secret"), false)).is_err());
+        }
+
+        tokens.revoke_webview("preview");
+        assert!(check("preview", fs(site.join("index.html"), false)).is_err());
+    }
+}
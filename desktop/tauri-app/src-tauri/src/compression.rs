@@ -0,0 +1,185 @@
+//! Response compression for `http_server`: brotli or gzip, picked from the
+//! client's `Accept-Encoding`. A pre-compressed sibling (`app.js.br`) is
+//! served when present, otherwise the file is compressed on the fly.
+
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+/// Bigger files are sent uncompressed rather than buffered in memory.
+pub const MAX_COMPRESS_BYTES: u64 = 16 * 1024 * 1024;
+/// Brotli quality for on-the-fly compression; higher levels cost more CPU
+/// than they save in transfer on a local network.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Compression {
+    /// MIME type prefixes worth compressing, e.g. `text/`.
+    pub content_types: Vec<String>,
+    /// Smaller files are sent as is.
+    pub min_bytes: u64,
+    /// Serve `<file>.br` or `<file>.gz` when it exists.
+    pub precompressed: bool,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            content_types: [
+                "text/",
+                "application/javascript",
+                "application/json",
+                "application/wasm",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            min_bytes: 1024,
+            precompressed: true,
+        }
+    }
+}
+
+impl Compression {
+    pub fn compressible(&self, content_type: &str) -> bool {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types
+            .iter()
+            .any(|prefix| mime.starts_with(&prefix.to_ascii_lowercase()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// Ours first when the client has no preference.
+    const ALL: [Self; 2] = [Self::Brotli, Self::Gzip];
+
+    /// Value for `Content-Encoding`.
+    pub fn token(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Extension of a pre-compressed sibling file.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gz",
+        }
+    }
+
+    pub fn encode(self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer.write_all(bytes)?;
+                Ok(writer.into_inner())
+            }
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Weight `Accept-Encoding` gives `token`: its own, else `*`'s, else 0.
+fn quality(accept_encoding: &str, token: &str) -> f32 {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let q = params
+            .find_map(|p| p.strip_prefix("q="))
+            .map_or(1.0, |q| q.parse().unwrap_or(0.0));
+        if name.eq_ignore_ascii_case(token) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+/// Encodings the client accepts, best first.
+pub fn accepted(accept_encoding: Option<&str>) -> Vec<Encoding> {
+    let Some(accept_encoding) = accept_encoding else {
+        return Vec::new();
+    };
+    let mut encodings: Vec<(Encoding, f32)> = Encoding::ALL
+        .into_iter()
+        .map(|e| (e, quality(accept_encoding, e.token())))
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    // Stable, so ties keep our preference
+    encodings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    encodings.into_iter().map(|(e, _)| e).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_accepted() {
+        assert!(accepted(None).is_empty());
+        assert_eq!(
+            accepted(Some("gzip, deflate, br")),
+            [Encoding::Brotli, Encoding::Gzip]
+        );
+        assert_eq!(
+            accepted(Some("br;q=0.5, gzip;q=0.8")),
+            [Encoding::Gzip, Encoding::Brotli]
+        );
+        assert_eq!(accepted(Some("*, br;q=0")), [Encoding::Gzip]);
+        assert!(accepted(Some("identity")).is_empty());
+    }
+
+    #[test]
+    fn test_compressible() {
+        let compression = Compression::default();
+        assert!(compression.compressible("text/html; charset=utf-8"));
+        assert!(compression.compressible("Image/SVG+XML"));
+        assert!(!compression.compressible("image/png"));
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let text = "hello compression ".repeat(100);
+
+        let br = Encoding::Brotli.encode(text.as_bytes()).unwrap();
+        let mut out = String::new();
+        brotli::Decompressor::new(br.as_slice(), 4096)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+
+        let gz = Encoding::Gzip.encode(text.as_bytes()).unwrap();
+        assert!(gz.len() < text.len());
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(gz.as_slice())
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+    }
+}
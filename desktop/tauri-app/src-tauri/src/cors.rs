@@ -0,0 +1,170 @@
+//! Per-server CORS settings for the Rust HTTP layer (`http_server`), so
+//! pages on other origins can fetch served files.
+
+use serde::{Deserialize, Serialize};
+
+/// Headers to add to a response.
+pub type Headers = Vec<(&'static str, String)>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Cors {
+    /// Origins allowed to read responses; `*` allows any.
+    pub allowed_origins: Vec<String>,
+    /// Methods preflight requests may ask for.
+    pub allowed_methods: Vec<String>,
+    /// Request headers preflight requests may ask for; empty allows
+    /// whatever the browser asks for.
+    pub allowed_headers: Vec<String>,
+    /// Let pages send cookies and `Authorization` headers.
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight answer.
+    pub max_age_secs: Option<u32>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: ["GET", "HEAD", "OPTIONS"].map(String::from).to_vec(),
+            allowed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age_secs: None,
+        }
+    }
+}
+
+impl Cors {
+    /// `Access-Control-Allow-Origin` value for a request from `origin`, if
+    /// it may read the response.
+    fn allow_origin(&self, origin: Option<&str>) -> Option<String> {
+        let any = self.allowed_origins.iter().any(|o| o == "*");
+        match origin {
+            // The wildcard doesn't work for credentialed requests
+            Some(origin) if any && self.allow_credentials => Some(origin.to_string()),
+            _ if any => Some("*".to_string()),
+            Some(origin) if self.allowed_origins.iter().any(|o| o == origin) => {
+                Some(origin.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// Headers for an ordinary response.
+    pub fn response_headers(&self, origin: Option<&str>) -> Headers {
+        let Some(allow) = self.allow_origin(origin) else {
+            return Vec::new();
+        };
+        let mut headers = Vec::new();
+        if allow != "*" {
+            headers.push(("Vary", "Origin".to_string()));
+        }
+        headers.push(("Access-Control-Allow-Origin", allow));
+        if self.allow_credentials {
+            headers.push(("Access-Control-Allow-Credentials", "true".to_string()));
+        }
+        headers
+    }
+
+    /// Headers answering a preflight `OPTIONS` request. Empty if the
+    /// origin isn't allowed, which makes the browser block the request.
+    pub fn preflight_headers(
+        &self,
+        origin: Option<&str>,
+        requested_headers: Option<&str>,
+    ) -> Headers {
+        let mut headers = self.response_headers(origin);
+        if headers.is_empty() {
+            return headers;
+        }
+        headers.push((
+            "Access-Control-Allow-Methods",
+            self.allowed_methods.join(", "),
+        ));
+        let allowed_headers = if self.allowed_headers.is_empty() {
+            requested_headers.map(ToString::to_string)
+        } else {
+            Some(self.allowed_headers.join(", "))
+        };
+        if let Some(allowed) = allowed_headers {
+            headers.push(("Access-Control-Allow-Headers", allowed));
+        }
+        if let Some(secs) = self.max_age_secs {
+            headers.push(("Access-Control-Max-Age", secs.to_string()));
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(headers: &Headers, name: &str) -> Option<String> {
+        headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn test_default_allows_any_origin() {
+        let cors = Cors::default();
+        let headers = cors.response_headers(Some("http://localhost:5173"));
+        assert_eq!(value(&headers, "Access-Control-Allow-Origin").unwrap(), "*");
+        assert_eq!(value(&headers, "Vary"), None);
+
+        let preflight = cors.preflight_headers(Some("http://a.test"), Some("x-requested-with"));
+        assert_eq!(
+            value(&preflight, "Access-Control-Allow-Methods").unwrap(),
+            "GET, HEAD, OPTIONS"
+        );
+        assert_eq!(
+            value(&preflight, "Access-Control-Allow-Headers").unwrap(),
+            "x-requested-with"
+        );
+        assert_eq!(value(&preflight, "Access-Control-Max-Age"), None);
+    }
+
+    #[test]
+    fn test_listed_origins_with_credentials() {
+        let cors = Cors {
+            allowed_origins: vec!["https://app.test".to_string()],
+            allowed_headers: vec!["Content-Type".to_string()],
+            allow_credentials: true,
+            max_age_secs: Some(600),
+            ..Cors::default()
+        };
+        assert!(cors.response_headers(Some("https://evil.test")).is_empty());
+        assert!(cors.response_headers(None).is_empty());
+
+        let headers = cors.preflight_headers(Some("https://app.test"), Some("x-other"));
+        assert_eq!(
+            value(&headers, "Access-Control-Allow-Origin").unwrap(),
+            "https://app.test"
+        );
+        assert_eq!(value(&headers, "Vary").unwrap(), "Origin");
+        assert_eq!(
+            value(&headers, "Access-Control-Allow-Credentials").unwrap(),
+            "true"
+        );
+        assert_eq!(
+            value(&headers, "Access-Control-Allow-Headers").unwrap(),
+            "Content-Type"
+        );
+        assert_eq!(value(&headers, "Access-Control-Max-Age").unwrap(), "600");
+    }
+
+    #[test]
+    fn test_wildcard_with_credentials_echoes_origin() {
+        let cors = Cors {
+            allow_credentials: true,
+            ..Cors::default()
+        };
+        let headers = cors.response_headers(Some("http://a.test"));
+        assert_eq!(
+            value(&headers, "Access-Control-Allow-Origin").unwrap(),
+            "http://a.test"
+        );
+    }
+}
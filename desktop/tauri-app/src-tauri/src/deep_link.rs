@@ -0,0 +1,187 @@
+//! `ok200://` links, so other apps and web pages can ask the app to serve a
+//! folder or show itself:
+//!
+//! - `ok200://serve?path=/some/folder&port=8080`
+//! - `ok200://open`
+//!
+//! Links are never acted on directly: the webview asks the user to confirm
+//! before serving anything. Windows and Linux deliver links as a launch
+//! argument (forwarded by the single-instance plugin once running); macOS
+//! delivers them as `RunEvent::Opened`, with the scheme declared in
+//! `Info.plist`.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{Emitter, State, Url};
+
+pub const SCHEME: &str = "ok200";
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DeepLink {
+    Serve { path: String, port: Option<u16> },
+    Open,
+}
+
+/// Link the app was launched with, waiting for the webview to pick it up.
+pub struct PendingDeepLink(pub Mutex<Option<DeepLink>>);
+
+pub fn is_deep_link(arg: &str) -> bool {
+    arg.strip_prefix(SCHEME)
+        .is_some_and(|rest| rest.starts_with(':'))
+}
+
+pub fn parse(link: &str) -> Result<DeepLink, String> {
+    let url = Url::parse(link).map_err(|e| format!("invalid link {link}: {e}"))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("not an {SCHEME}:// link: {link}"));
+    }
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    match url.host_str() {
+        Some("serve") => {
+            let path = query("path").ok_or("serve link has no path")?;
+            if !Path::new(&path).is_dir() {
+                return Err(format!("not a folder: {path}"));
+            }
+            let port = query("port")
+                .map(|p| p.parse().map_err(|_| format!("invalid port: {p}")))
+                .transpose()?;
+            Ok(DeepLink::Serve { path, port })
+        }
+        Some("open") => Ok(DeepLink::Open),
+        _ => Err(format!("unknown link action: {link}")),
+    }
+}
+
+/// First `ok200://` link among launch arguments.
+pub fn from_args(args: &[String]) -> Option<DeepLink> {
+    let link = args.iter().skip(1).find(|a| is_deep_link(a))?;
+    parse(link).inspect_err(|e| tracing::warn!("{e}")).ok()
+}
+
+/// Hand a link that arrived while running to the webview.
+pub fn handle(app: &tauri::AppHandle, link: &str) {
+    match parse(link) {
+        Ok(link) => {
+            super::show_main_window(app);
+            let _ = app.emit("deep-link", link);
+        }
+        Err(e) => tracing::warn!("{e}"),
+    }
+}
+
+/// Link the app was launched with, if any. Returns it only once.
+#[tauri::command]
+pub async fn deep_link_take(state: State<'_, PendingDeepLink>) -> Result<Option<DeepLink>, String> {
+    Ok(state.0.lock().unwrap().take())
+}
+
+// -- Scheme registration --
+
+/// Point the `ok200://` scheme at this executable.
+#[cfg(target_os = "windows")]
+pub fn register_scheme() -> Result<(), String> {
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    let exe = std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?;
+    let exe = super::strip_win_prefix(exe);
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let subkey = format!(r"Software\Classes\{SCHEME}");
+    let (key, _) = hkcu
+        .create_subkey(&subkey)
+        .map_err(|e| format!("create HKCU\\{subkey}: {e}"))?;
+    key.set_value("", &"URL:200 OK")
+        .and_then(|()| key.set_value("URL Protocol", &""))
+        .map_err(|e| format!("set HKCU\\{subkey}: {e}"))?;
+    let (command, _) = key
+        .create_subkey(r"shell\open\command")
+        .map_err(|e| format!("create HKCU\\{subkey}\\shell\\open\\command: {e}"))?;
+    command
+        .set_value("", &format!("\"{}\" \"%1\"", exe.display()))
+        .map_err(|e| format!("set HKCU\\{subkey}\\shell\\open\\command: {e}"))
+}
+
+/// Point the `ok200://` scheme at this executable (or `AppImage`).
+#[cfg(target_os = "linux")]
+pub fn register_scheme() -> Result<(), String> {
+    const DESKTOP_FILE: &str = "ok200-url-handler.desktop";
+
+    let exe = match std::env::var_os("APPIMAGE") {
+        Some(appimage) => std::path::PathBuf::from(appimage),
+        None => std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?,
+    };
+    let dir = dirs::data_dir()
+        .ok_or("no data directory")?
+        .join("applications");
+    std::fs::create_dir_all(&dir).map_err(|e| format!("mkdir {}: {e}", dir.display()))?;
+    let entry = format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=200 OK\n\
+         Exec=\"{}\" %u\n\
+         NoDisplay=true\n\
+         MimeType=x-scheme-handler/{SCHEME};\n",
+        exe.display()
+    );
+    let path = dir.join(DESKTOP_FILE);
+    std::fs::write(&path, entry).map_err(|e| format!("write {}: {e}", path.display()))?;
+    let status = std::process::Command::new("xdg-mime")
+        .args(["default", DESKTOP_FILE])
+        .arg(format!("x-scheme-handler/{SCHEME}"))
+        .status()
+        .map_err(|e| format!("xdg-mime failed: {e}"))?;
+    if !status.success() {
+        return Err(format!("xdg-mime exited with {status}"));
+    }
+    Ok(())
+}
+
+/// macOS registers the scheme from `Info.plist` when the app is installed.
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+#[allow(clippy::unnecessary_wraps)]
+pub fn register_scheme() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+        let link = Url::parse_with_params("ok200://serve", &[("path", dir), ("port", "3000")])
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            parse(&link).unwrap(),
+            DeepLink::Serve {
+                path: dir.to_string(),
+                port: Some(3000),
+            }
+        );
+        assert_eq!(parse("ok200://open").unwrap(), DeepLink::Open);
+        assert!(parse("ok200://serve?path=/no/such/folder").is_err());
+        let bad_port = Url::parse_with_params("ok200://serve", &[("path", dir), ("port", "http")])
+            .unwrap()
+            .to_string();
+        assert!(parse(&bad_port).is_err());
+        assert!(parse("ok200://delete").is_err());
+        assert!(parse("https://serve?path=/").is_err());
+    }
+
+    #[test]
+    fn test_is_deep_link() {
+        assert!(is_deep_link("ok200://open"));
+        assert!(!is_deep_link("ok200"));
+        assert!(!is_deep_link("/home/ok200://x"));
+    }
+}
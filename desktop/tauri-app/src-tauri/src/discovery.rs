@@ -0,0 +1,310 @@
+//! Find other copies of 200 OK on the local network, and let them find this
+//! one. Both sides use DNS-SD over multicast DNS (`_ok200._tcp.local.`),
+//! the way network printers announce themselves, so it works without any
+//! configuration on ordinary home and office networks. Each
+//! instance advertises a random id, a display name, its version and,
+//! optionally, the port of a server that accepts uploads; that's where the
+//! UI sends files to. The list of others is kept current as they come and
+//! go, and sent to the webview as a `discovery-changed` event.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use tauri::{Emitter, State};
+use tokio::task::JoinHandle;
+
+pub const SERVICE_TYPE: &str = "_ok200._tcp.local.";
+/// How long stopping waits for the goodbye packets to go out.
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiscoveryOptions {
+    /// Shown to the others; when empty, the computer's name.
+    pub name: String,
+    /// A server here that accepts uploads, or 0 to only browse and be
+    /// listed.
+    pub port: u16,
+    /// Whether that server uses TLS.
+    pub tls: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Instance {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub host: String,
+    pub addresses: Vec<String>,
+    pub port: Option<u16>,
+    /// Where to send files, if it advertised a server.
+    pub url: Option<String>,
+}
+
+/// The computer's name, or `200 OK` if it can't be found.
+fn default_name() -> String {
+    ["COMPUTERNAME", "HOSTNAME"]
+        .iter()
+        .find_map(|key| std::env::var(key).ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "200 OK".to_string())
+}
+
+/// What we advertise about ourselves.
+fn advertisement(id: &str, options: &DiscoveryOptions) -> Result<ServiceInfo, String> {
+    let name = if options.name.trim().is_empty() {
+        default_name()
+    } else {
+        options.name.trim().to_string()
+    };
+    let properties = [
+        ("id", id),
+        ("name", &name),
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("tls", if options.tls { "1" } else { "0" }),
+    ];
+    ServiceInfo::new(
+        SERVICE_TYPE,
+        id,
+        &format!("{id}.local."),
+        (),
+        options.port,
+        &properties[..],
+    )
+    .map(ServiceInfo::enable_addr_auto)
+    .map_err(|e| format!("advertising failed: {e}"))
+}
+
+/// Another instance, from what it advertised. Services of our type that
+/// don't carry an id aren't ours.
+fn instance(info: &ServiceInfo) -> Option<Instance> {
+    let id = info.get_property_val_str("id")?.to_string();
+    let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+    // IPv4 first, since link-local IPv6 addresses don't work in URLs
+    addresses.sort_by_key(|ip| (ip.is_ipv6(), *ip));
+    let port = Some(info.get_port()).filter(|&port| port != 0);
+    let tls = info.get_property_val_str("tls") == Some("1");
+    let url = port.zip(addresses.first()).map(|(port, &ip)| {
+        let scheme = if tls { "https" } else { "http" };
+        format!("{scheme}://{}/", SocketAddr::new(ip, port))
+    });
+    Some(Instance {
+        name: info
+            .get_property_val_str("name")
+            .filter(|name| !name.is_empty())
+            .unwrap_or(&id)
+            .to_string(),
+        id,
+        version: info
+            .get_property_val_str("version")
+            .unwrap_or_default()
+            .to_string(),
+        host: info.get_hostname().trim_end_matches('.').to_string(),
+        addresses: addresses.iter().map(ToString::to_string).collect(),
+        port,
+        url,
+    })
+}
+
+type Instances = Arc<Mutex<BTreeMap<String, Instance>>>;
+
+fn list(instances: &Instances) -> Vec<Instance> {
+    let mut list: Vec<_> = instances.lock().unwrap().values().cloned().collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+    list
+}
+
+/// Keep `instances` up to date with what the daemon finds, calling
+/// `changed` whenever the list changes.
+async fn browse(
+    events: mdns_sd::Receiver<ServiceEvent>,
+    own_id: String,
+    instances: Instances,
+    changed: impl Fn(Vec<Instance>),
+) {
+    while let Ok(event) = events.recv_async().await {
+        let updated = match event {
+            ServiceEvent::ServiceResolved(info) => match instance(&info) {
+                Some(found) if found.id != own_id => {
+                    let fullname = info.get_fullname().to_string();
+                    let previous = instances.lock().unwrap().insert(fullname, found.clone());
+                    previous != Some(found)
+                }
+                _ => false,
+            },
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                instances.lock().unwrap().remove(&fullname).is_some()
+            }
+            _ => false,
+        };
+        if updated {
+            changed(list(&instances));
+        }
+    }
+}
+
+struct Running {
+    daemon: ServiceDaemon,
+    fullname: String,
+    task: JoinHandle<()>,
+}
+
+pub struct Discovery {
+    /// Random per run, so we can recognize our own advertisement.
+    id: String,
+    running: Mutex<Option<Running>>,
+    instances: Instances,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            running: Mutex::default(),
+            instances: Instances::default(),
+        }
+    }
+}
+
+impl Discovery {
+    /// Advertise and browse until stopped. Restarting re-advertises with
+    /// the new options.
+    fn start(
+        &self,
+        options: &DiscoveryOptions,
+        changed: impl Fn(Vec<Instance>) + Send + 'static,
+    ) -> Result<(), String> {
+        self.stop();
+        let info = advertisement(&self.id, options)?;
+        let fullname = info.get_fullname().to_string();
+        let daemon = ServiceDaemon::new().map_err(|e| format!("mDNS failed: {e}"))?;
+        let started = daemon
+            .register(info)
+            .and_then(|()| daemon.browse(SERVICE_TYPE));
+        let events = match started {
+            Ok(events) => events,
+            Err(e) => {
+                let _ = daemon.shutdown();
+                return Err(format!("mDNS failed: {e}"));
+            }
+        };
+        tracing::info!("discovery started as {fullname}");
+        let task = tokio::spawn(browse(
+            events,
+            self.id.clone(),
+            self.instances.clone(),
+            changed,
+        ));
+        *self.running.lock().unwrap() = Some(Running {
+            daemon,
+            fullname,
+            task,
+        });
+        Ok(())
+    }
+
+    /// Say goodbye, so the others drop us right away rather than when our
+    /// records expire, and forget everyone found.
+    pub fn stop(&self) -> bool {
+        let Some(running) = self.running.lock().unwrap().take() else {
+            return false;
+        };
+        running.task.abort();
+        if let Ok(status) = running.daemon.unregister(&running.fullname) {
+            let _ = status.recv_timeout(GOODBYE_TIMEOUT);
+        }
+        let _ = running.daemon.shutdown();
+        self.instances.lock().unwrap().clear();
+        tracing::info!("discovery stopped");
+        true
+    }
+}
+
+#[tauri::command]
+pub async fn discovery_start(
+    options: DiscoveryOptions,
+    app: tauri::AppHandle,
+    state: State<'_, Discovery>,
+) -> Result<(), String> {
+    state.start(&options, move |list| {
+        let _ = app.emit("discovery-changed", list);
+    })
+}
+
+#[tauri::command]
+pub async fn discovery_stop(
+    app: tauri::AppHandle,
+    state: State<'_, Discovery>,
+) -> Result<(), String> {
+    if state.stop() {
+        let _ = app.emit("discovery-changed", Vec::<Instance>::new());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn discovery_list(state: State<'_, Discovery>) -> Result<Vec<Instance>, String> {
+    Ok(list(&state.instances))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(id: &str, ips: &str, port: u16, tls: &str) -> ServiceInfo {
+        let properties = [
+            ("id", id),
+            ("name", "Den"),
+            ("version", "1.2.3"),
+            ("tls", tls),
+        ];
+        ServiceInfo::new(SERVICE_TYPE, id, "den.local.", ips, port, &properties[..]).unwrap()
+    }
+
+    #[test]
+    fn test_instance() {
+        let found = instance(&service("abc", "fe80::1,192.168.1.20", 8080, "0")).unwrap();
+        assert_eq!(found.id, "abc");
+        assert_eq!(found.name, "Den");
+        assert_eq!(found.version, "1.2.3");
+        assert_eq!(found.host, "den.local");
+        assert_eq!(found.addresses, ["192.168.1.20", "fe80::1"]);
+        assert_eq!(found.port, Some(8080));
+        assert_eq!(found.url.as_deref(), Some("http://192.168.1.20:8080/"));
+
+        let tls = instance(&service("abc", "fe80::1", 8443, "1")).unwrap();
+        assert_eq!(tls.url.as_deref(), Some("https://[fe80::1]:8443/"));
+
+        // Browsing only: listed, but nothing to send to
+        let browsing = instance(&service("abc", "192.168.1.20", 0, "0")).unwrap();
+        assert_eq!(browsing.port, None);
+        assert_eq!(browsing.url, None);
+
+        let other = ServiceInfo::new(SERVICE_TYPE, "x", "x.local.", "10.0.0.1", 80, None).unwrap();
+        assert_eq!(instance(&other), None);
+    }
+
+    #[test]
+    fn test_advertisement() {
+        let options = DiscoveryOptions {
+            name: " Laptop ".to_string(),
+            port: 8080,
+            tls: true,
+        };
+        let info = advertisement("abc", &options).unwrap();
+        assert_eq!(info.get_fullname(), "abc._ok200._tcp.local.");
+        assert_eq!(info.get_property_val_str("name"), Some("Laptop"));
+        assert_eq!(info.get_property_val_str("tls"), Some("1"));
+        assert_eq!(info.get_port(), 8080);
+
+        let info = advertisement("abc", &DiscoveryOptions::default()).unwrap();
+        assert!(!info.get_property_val_str("name").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,742 @@
+//! DLNA for `http_server`, so smart TVs, consoles and media players can
+//! find a served folder and play from it. A server with `dlna` announces
+//! itself as a `MediaServer` over SSDP and answers `ContentDirectory`
+//! `Browse` requests under `PREFIX`; the files themselves are ordinary
+//! downloads, with the range requests players seek with. Only folders,
+//! video, audio and images are listed. TVs can't log in, so `dlna` can't
+//! be combined with `auth`.
+
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use ok200_common::http::{html_escape as xml_escape, percent_encode, resolve};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use super::dotfiles::Dotfiles;
+use super::fingerprint::hex;
+
+/// Where the descriptions and control URLs live.
+pub const PREFIX: &str = "/_dlna";
+/// Request header players send to ask for `CONTENT_FEATURES`.
+pub const FEATURES_REQUEST: &str = "getcontentFeatures.dlna.org";
+/// Media can be streamed, and seeked by bytes.
+pub const CONTENT_FEATURES: &str =
+    "DLNA.ORG_OP=01;DLNA.ORG_CI=0;DLNA.ORG_FLAGS=01700000000000000000000000000000";
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// How long an announcement holds; it's repeated at half this.
+const MAX_AGE_SECS: u64 = 1800;
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const CONNECTION_MANAGER: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+const SERVER: &str = concat!("OS/1.0 UPnP/1.0 200OK/", env!("CARGO_PKG_VERSION"));
+/// The root container's object ID. Everything else is its path.
+const ROOT_ID: &str = "0";
+const DEFAULT_NAME: &str = "200 OK";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DlnaOptions {
+    /// Shown on TVs; when empty, `200 OK` and the folder's name.
+    pub name: String,
+}
+
+/// DLNA turned on for a server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dlna {
+    pub options: DlnaOptions,
+    name: String,
+    /// Derived from the folder, so TVs keep recognizing it across restarts.
+    uuid: String,
+}
+
+/// A service offered by the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Service {
+    ContentDirectory,
+    ConnectionManager,
+}
+
+/// What a request under `PREFIX` is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Description,
+    /// The service's description (SCPD).
+    Service(Service),
+    /// SOAP actions.
+    Control(Service),
+    /// Event subscriptions, which are accepted but never sent anything.
+    Event,
+}
+
+/// The URL path of object `id`.
+fn url_path(id: &str) -> String {
+    if id == ROOT_ID {
+        "/".to_string()
+    } else {
+        format!("/{id}")
+    }
+}
+
+/// The object ID of the folder holding `id`.
+fn parent_id(id: &str) -> &str {
+    id.rsplit_once('/').map_or(ROOT_ID, |(parent, _)| parent)
+}
+
+/// `object.item.videoItem` and the like for a MIME type; `None` for files
+/// players can't show.
+fn item_class(mime: &str) -> Option<&'static str> {
+    match mime.split('/').next()? {
+        "video" => Some("object.item.videoItem"),
+        "audio" => Some("object.item.audioItem.musicTrack"),
+        "image" => Some("object.item.imageItem.photo"),
+        _ => None,
+    }
+}
+
+/// Text of the first element named `tag` in `xml`, whatever its namespace
+/// prefix, unescaped. Enough for the flat arguments of a SOAP action.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    for (start, _) in xml.match_indices(tag) {
+        let before = &xml[..start];
+        let opened = before.ends_with('<')
+            || before
+                .rsplit_once('<')
+                .is_some_and(|(_, name)| name.ends_with(':') && !name.contains(['/', ' ', '>']));
+        let rest = &xml[start + tag.len()..];
+        if !opened || !rest.starts_with(['>', ' ', '/']) {
+            continue;
+        }
+        let (open, rest) = rest.split_once('>')?;
+        if open.ends_with('/') {
+            return Some(String::new());
+        }
+        let text = rest.split('<').next().unwrap_or_default();
+        return Some(
+            text.replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+    }
+    None
+}
+
+/// The action named by a `SOAPACTION` header, e.g. `Browse`.
+pub fn soap_action(header: &str) -> Option<&str> {
+    header
+        .trim()
+        .trim_matches('"')
+        .rsplit_once('#')
+        .map(|(_, action)| action)
+}
+
+/// Which endpoint a request target is for.
+pub fn endpoint(target: &str) -> Option<Endpoint> {
+    let path = target.split(['?', '#']).next()?.strip_prefix(PREFIX)?;
+    Some(match path {
+        "/description.xml" => Endpoint::Description,
+        "/content-directory.xml" => Endpoint::Service(Service::ContentDirectory),
+        "/connection-manager.xml" => Endpoint::Service(Service::ConnectionManager),
+        "/control/content-directory" => Endpoint::Control(Service::ContentDirectory),
+        "/control/connection-manager" => Endpoint::Control(Service::ConnectionManager),
+        "/event/content-directory" | "/event/connection-manager" => Endpoint::Event,
+        _ => return None,
+    })
+}
+
+/// The service description for `service`.
+pub fn scpd(service: Service) -> String {
+    let (actions, variables) = match service {
+        Service::ContentDirectory => (
+            concat!(
+                "<action><name>Browse</name><argumentList>",
+                "<argument><name>ObjectID</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_ObjectID</relatedStateVariable></argument>",
+                "<argument><name>BrowseFlag</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_BrowseFlag</relatedStateVariable></argument>",
+                "<argument><name>Filter</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Filter</relatedStateVariable></argument>",
+                "<argument><name>StartingIndex</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Index</relatedStateVariable></argument>",
+                "<argument><name>RequestedCount</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>",
+                "<argument><name>SortCriteria</name><direction>in</direction><relatedStateVariable>A_ARG_TYPE_SortCriteria</relatedStateVariable></argument>",
+                "<argument><name>Result</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Result</relatedStateVariable></argument>",
+                "<argument><name>NumberReturned</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>",
+                "<argument><name>TotalMatches</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_Count</relatedStateVariable></argument>",
+                "<argument><name>UpdateID</name><direction>out</direction><relatedStateVariable>A_ARG_TYPE_UpdateID</relatedStateVariable></argument>",
+                "</argumentList></action>",
+                "<action><name>GetSearchCapabilities</name><argumentList><argument><name>SearchCaps</name><direction>out</direction><relatedStateVariable>SearchCapabilities</relatedStateVariable></argument></argumentList></action>",
+                "<action><name>GetSortCapabilities</name><argumentList><argument><name>SortCaps</name><direction>out</direction><relatedStateVariable>SortCapabilities</relatedStateVariable></argument></argumentList></action>",
+                "<action><name>GetSystemUpdateID</name><argumentList><argument><name>Id</name><direction>out</direction><relatedStateVariable>SystemUpdateID</relatedStateVariable></argument></argumentList></action>",
+            ),
+            concat!(
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_ObjectID</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_BrowseFlag</name><dataType>string</dataType><allowedValueList><allowedValue>BrowseMetadata</allowedValue><allowedValue>BrowseDirectChildren</allowedValue></allowedValueList></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_Filter</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_Index</name><dataType>ui4</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_Count</name><dataType>ui4</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_SortCriteria</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_Result</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>A_ARG_TYPE_UpdateID</name><dataType>ui4</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>SearchCapabilities</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"no\"><name>SortCapabilities</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"yes\"><name>SystemUpdateID</name><dataType>ui4</dataType></stateVariable>",
+            ),
+        ),
+        Service::ConnectionManager => (
+            concat!(
+                "<action><name>GetProtocolInfo</name><argumentList>",
+                "<argument><name>Source</name><direction>out</direction><relatedStateVariable>SourceProtocolInfo</relatedStateVariable></argument>",
+                "<argument><name>Sink</name><direction>out</direction><relatedStateVariable>SinkProtocolInfo</relatedStateVariable></argument>",
+                "</argumentList></action>",
+                "<action><name>GetCurrentConnectionIDs</name><argumentList><argument><name>ConnectionIDs</name><direction>out</direction><relatedStateVariable>CurrentConnectionIDs</relatedStateVariable></argument></argumentList></action>",
+            ),
+            concat!(
+                "<stateVariable sendEvents=\"yes\"><name>SourceProtocolInfo</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"yes\"><name>SinkProtocolInfo</name><dataType>string</dataType></stateVariable>",
+                "<stateVariable sendEvents=\"yes\"><name>CurrentConnectionIDs</name><dataType>string</dataType></stateVariable>",
+            ),
+        ),
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <scpd xmlns=\"urn:schemas-upnp-org:service-1-0\">\
+         <specVersion><major>1</major><minor>0</minor></specVersion>\
+         <actionList>{actions}</actionList>\
+         <serviceStateTable>{variables}</serviceStateTable></scpd>"
+    )
+}
+
+fn soap_envelope(body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body>{body}</s:Body></s:Envelope>"
+    )
+}
+
+/// A successful answer to `action` on the service of type `urn`.
+fn soap_response(urn: &str, action: &str, args: &[(&str, String)]) -> String {
+    let mut body = format!("<u:{action}Response xmlns:u=\"{urn}\">");
+    for (name, value) in args {
+        let _ = write!(body, "<{name}>{}</{name}>", xml_escape(value));
+    }
+    let _ = write!(body, "</u:{action}Response>");
+    soap_envelope(&body)
+}
+
+/// A control error, sent with status 500.
+fn soap_fault(code: u16, description: &str) -> String {
+    soap_envelope(&format!(
+        "<s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring>\
+         <detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\">\
+         <errorCode>{code}</errorCode><errorDescription>{description}</errorDescription>\
+         </UPnPError></detail></s:Fault>"
+    ))
+}
+
+/// A file or folder as `ContentDirectory` shows it.
+struct Object {
+    id: String,
+    title: String,
+    /// MIME type and size, for files.
+    media: Option<(String, u64)>,
+}
+
+impl Object {
+    fn didl(&self, base_url: &str) -> String {
+        let id = xml_escape(&self.id);
+        let parent = xml_escape(if self.id == ROOT_ID {
+            "-1"
+        } else {
+            parent_id(&self.id)
+        });
+        let title = xml_escape(&self.title);
+        let Some((mime, size)) = &self.media else {
+            return format!(
+                "<container id=\"{id}\" parentID=\"{parent}\" restricted=\"1\">\
+                 <dc:title>{title}</dc:title>\
+                 <upnp:class>object.container.storageFolder</upnp:class></container>"
+            );
+        };
+        let class = item_class(mime).unwrap_or("object.item");
+        let path: Vec<_> = self.id.split('/').map(percent_encode).collect();
+        let url = xml_escape(&format!("{base_url}/{}", path.join("/")));
+        format!(
+            "<item id=\"{id}\" parentID=\"{parent}\" restricted=\"1\">\
+             <dc:title>{title}</dc:title><upnp:class>{class}</upnp:class>\
+             <res protocolInfo=\"http-get:*:{mime}:DLNA.ORG_OP=01;DLNA.ORG_CI=0\" \
+             size=\"{size}\">{url}</res></item>"
+        )
+    }
+}
+
+fn didl_lite(objects: &[Object], base_url: &str) -> String {
+    let mut didl = String::from(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">",
+    );
+    for object in objects {
+        didl.push_str(&object.didl(base_url));
+    }
+    didl.push_str("</DIDL-Lite>");
+    didl
+}
+
+/// The served folder as players browse it.
+pub struct Library<'a> {
+    pub root: &'a Path,
+    pub dotfiles: Dotfiles,
+    pub mime_type: &'a dyn Fn(&Path) -> String,
+}
+
+impl Library<'_> {
+    /// Answer SOAP `action` on `service` with the status to send. Media
+    /// URLs start with `base_url`, the origin the player reached us at.
+    pub fn control(
+        &self,
+        service: Service,
+        action: &str,
+        body: &str,
+        base_url: &str,
+    ) -> (u16, String) {
+        let answer = match (service, action) {
+            (Service::ContentDirectory, "Browse") => {
+                return match browse(self, body, base_url) {
+                    Ok(args) => (200, soap_response(CONTENT_DIRECTORY, action, &args)),
+                    Err((code, description)) => (500, soap_fault(code, description)),
+                };
+            }
+            (Service::ContentDirectory, "GetSearchCapabilities") => {
+                vec![("SearchCaps", String::new())]
+            }
+            (Service::ContentDirectory, "GetSortCapabilities") => vec![("SortCaps", String::new())],
+            (Service::ContentDirectory, "GetSystemUpdateID") => vec![("Id", "1".to_string())],
+            (Service::ConnectionManager, "GetProtocolInfo") => vec![
+                ("Source", "http-get:*:*:*".to_string()),
+                ("Sink", String::new()),
+            ],
+            (Service::ConnectionManager, "GetCurrentConnectionIDs") => {
+                vec![("ConnectionIDs", "0".to_string())]
+            }
+            _ => return (500, soap_fault(401, "Invalid Action")),
+        };
+        let urn = match service {
+            Service::ContentDirectory => CONTENT_DIRECTORY,
+            Service::ConnectionManager => CONNECTION_MANAGER,
+        };
+        (200, soap_response(urn, action, &answer))
+    }
+
+    /// The object with ID `id`, if it exists and players can show it.
+    fn object(&self, id: &str, title: &str) -> Option<Object> {
+        let url_path = url_path(id);
+        if !self.dotfiles.allows(&url_path) {
+            return None;
+        }
+        let path = resolve(self.root, &url_path)?;
+        let meta = std::fs::metadata(&path).ok()?;
+        let media = if meta.is_dir() {
+            None
+        } else {
+            let mime = (self.mime_type)(&path);
+            item_class(&mime)?;
+            Some((mime, meta.len()))
+        };
+        Some(Object {
+            id: id.to_string(),
+            title: title.to_string(),
+            media,
+        })
+    }
+
+    /// What's in container `id`, folders first, each sorted by name.
+    fn children(&self, id: &str) -> Option<Vec<Object>> {
+        let url_path = url_path(id);
+        if !self.dotfiles.allows(&url_path) {
+            return None;
+        }
+        let dir = resolve(self.root, &url_path).filter(|d| d.is_dir())?;
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .ok()?
+            .filter_map(Result::ok)
+            .filter_map(|e| e.file_name().to_str().map(str::to_string))
+            .filter(|name| self.dotfiles.listed(name))
+            .collect();
+        names.sort();
+        let mut children: Vec<_> = names
+            .iter()
+            .filter_map(|name| {
+                let child = if id == ROOT_ID {
+                    name.clone()
+                } else {
+                    format!("{id}/{name}")
+                };
+                self.object(&child, name)
+            })
+            .collect();
+        children.sort_by_key(|c| c.media.is_some());
+        Some(children)
+    }
+}
+
+impl Dlna {
+    pub fn new(options: DlnaOptions, root: &Path) -> Self {
+        let name = if options.name.trim().is_empty() {
+            match root.file_name() {
+                Some(folder) => format!("{DEFAULT_NAME} ({})", folder.to_string_lossy()),
+                None => DEFAULT_NAME.to_string(),
+            }
+        } else {
+            options.name.clone()
+        };
+        let digest = hex(&Sha256::digest(root.to_string_lossy().as_bytes()));
+        let uuid = format!(
+            "{}-{}-{}-{}-{}",
+            &digest[..8],
+            &digest[8..12],
+            &digest[12..16],
+            &digest[16..20],
+            &digest[20..32]
+        );
+        Self {
+            options,
+            name,
+            uuid,
+        }
+    }
+
+    /// The device description TVs fetch from an announcement's location.
+    pub fn description(&self) -> String {
+        let service = |urn: &str, id: &str, slug: &str| {
+            format!(
+                "<service><serviceType>{urn}</serviceType>\
+                 <serviceId>urn:upnp-org:serviceId:{id}</serviceId>\
+                 <SCPDURL>{PREFIX}/{slug}.xml</SCPDURL>\
+                 <controlURL>{PREFIX}/control/{slug}</controlURL>\
+                 <eventSubURL>{PREFIX}/event/{slug}</eventSubURL></service>"
+            )
+        };
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <root xmlns=\"urn:schemas-upnp-org:device-1-0\" xmlns:dlna=\"urn:schemas-dlna-org:device-1-0\">\
+             <specVersion><major>1</major><minor>0</minor></specVersion>\
+             <device><deviceType>{DEVICE_TYPE}</deviceType>\
+             <friendlyName>{}</friendlyName>\
+             <manufacturer>200 OK</manufacturer>\
+             <modelName>200 OK</modelName>\
+             <modelNumber>{}</modelNumber>\
+             <UDN>uuid:{}</UDN>\
+             <dlna:X_DLNADOC>DMS-1.50</dlna:X_DLNADOC>\
+             <serviceList>{}{}</serviceList></device></root>",
+            xml_escape(&self.name),
+            env!("CARGO_PKG_VERSION"),
+            self.uuid,
+            service(CONTENT_DIRECTORY, "ContentDirectory", "content-directory"),
+            service(CONNECTION_MANAGER, "ConnectionManager", "connection-manager"),
+        )
+    }
+}
+
+/// The output arguments of `Browse`, or a control error.
+fn browse(
+    library: &Library<'_>,
+    body: &str,
+    base_url: &str,
+) -> Result<Vec<(&'static str, String)>, (u16, &'static str)> {
+    let id = xml_value(body, "ObjectID").unwrap_or_else(|| ROOT_ID.to_string());
+    let start: usize = xml_value(body, "StartingIndex")
+        .map_or(Ok(0), |s| s.trim().parse())
+        .map_err(|_| (402, "Invalid Args"))?;
+    let count: usize = xml_value(body, "RequestedCount")
+        .map_or(Ok(0), |s| s.trim().parse())
+        .map_err(|_| (402, "Invalid Args"))?;
+    let (objects, total) = match xml_value(body, "BrowseFlag").as_deref() {
+        Some("BrowseMetadata") => {
+            let title = id.rsplit('/').next().unwrap_or_default();
+            let title = if id == ROOT_ID { DEFAULT_NAME } else { title };
+            let object = library.object(&id, title).ok_or((701, "No such object"))?;
+            (vec![object], 1)
+        }
+        Some("BrowseDirectChildren") => {
+            let children = library.children(&id).ok_or((701, "No such object"))?;
+            let total = children.len();
+            // A count of 0 asks for everything
+            let count = if count == 0 { total } else { count };
+            (
+                children.into_iter().skip(start).take(count).collect(),
+                total,
+            )
+        }
+        _ => return Err((402, "Invalid Args")),
+    };
+    Ok(vec![
+        ("Result", didl_lite(&objects, base_url)),
+        ("NumberReturned", objects.len().to_string()),
+        ("TotalMatches", total.to_string()),
+        ("UpdateID", "1".to_string()),
+    ])
+}
+
+/// `(NT, USN)` of each thing a server announces.
+fn targets(uuid: &str) -> Vec<(String, String)> {
+    let mut targets = vec![
+        (
+            "upnp:rootdevice".to_string(),
+            format!("uuid:{uuid}::upnp:rootdevice"),
+        ),
+        (format!("uuid:{uuid}"), format!("uuid:{uuid}")),
+    ];
+    for urn in [DEVICE_TYPE, CONTENT_DIRECTORY, CONNECTION_MANAGER] {
+        targets.push((urn.to_string(), format!("uuid:{uuid}::{urn}")));
+    }
+    targets
+}
+
+/// An `ssdp:alive` announcement, or `ssdp:byebye` without a location.
+fn notify(nt: &str, usn: &str, location: Option<&str>) -> String {
+    let mut message =
+        format!("NOTIFY * HTTP/1.1\r\nHOST: {SSDP_GROUP}:{SSDP_PORT}\r\nNT: {nt}\r\n");
+    match location {
+        Some(location) => {
+            let _ = write!(
+                message,
+                "NTS: ssdp:alive\r\nCACHE-CONTROL: max-age={MAX_AGE_SECS}\r\n\
+                 LOCATION: {location}\r\nSERVER: {SERVER}\r\n"
+            );
+        }
+        None => message.push_str("NTS: ssdp:byebye\r\n"),
+    }
+    let _ = write!(message, "USN: {usn}\r\n\r\n");
+    message
+}
+
+/// The search target of an `M-SEARCH` discovery request; `None` for any
+/// other message.
+fn search_target(message: &str) -> Option<&str> {
+    let mut lines = message.lines();
+    if !lines
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("M-SEARCH * HTTP/1.1")
+    {
+        return None;
+    }
+    let mut st = None;
+    let mut discover = false;
+    for (name, value) in lines.filter_map(|l| l.split_once(':')) {
+        match name.trim().to_ascii_uppercase().as_str() {
+            "ST" => st = Some(value.trim()),
+            "MAN" => discover = value.trim().trim_matches('"') == "ssdp:discover",
+            _ => {}
+        }
+    }
+    st.filter(|_| discover)
+}
+
+fn search_reply(st: &str, usn: &str, location: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age={MAX_AGE_SECS}\r\nEXT:\r\n\
+         LOCATION: {location}\r\nSERVER: {SERVER}\r\nST: {st}\r\nUSN: {usn}\r\n\r\n"
+    )
+}
+
+/// A socket on the SSDP port that hears the multicast group on `ip`'s
+/// interface. Shared with other media software on the machine.
+fn ssdp_socket(ip: Ipv4Addr) -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    socket.join_multicast_v4(&SSDP_GROUP, &ip)?;
+    socket.set_multicast_if_v4(&ip)?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Announces a server until dropped, then says goodbye.
+pub struct Advertiser {
+    task: JoinHandle<()>,
+    targets: Vec<(String, String)>,
+}
+
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        self.task.abort();
+        let Ok(socket) = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) else {
+            return;
+        };
+        for (nt, usn) in &self.targets {
+            let _ = socket.send_to(notify(nt, usn, None).as_bytes(), (SSDP_GROUP, SSDP_PORT));
+        }
+    }
+}
+
+async fn announce(socket: UdpSocket, targets: Vec<(String, String)>, location: String) {
+    let group = SocketAddr::from((SSDP_GROUP, SSDP_PORT));
+    let mut renew = tokio::time::interval(Duration::from_secs(MAX_AGE_SECS / 2));
+    let mut buf = [0; 2048];
+    loop {
+        tokio::select! {
+            _ = renew.tick() => {
+                for (nt, usn) in &targets {
+                    let _ = socket.send_to(notify(nt, usn, Some(&location)).as_bytes(), group).await;
+                }
+            }
+            received = socket.recv_from(&mut buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                let message = String::from_utf8_lossy(&buf[..len]);
+                let Some(st) = search_target(&message) else {
+                    continue;
+                };
+                let matching = targets.iter().filter(|(nt, _)| st == "ssdp:all" || st == nt);
+                for (nt, usn) in matching {
+                    let reply = search_reply(nt, usn, &location);
+                    let _ = socket.send_to(reply.as_bytes(), from).await;
+                }
+            }
+        }
+    }
+}
+
+/// Start announcing `dlna` for a server on `port`, at this machine's LAN
+/// address.
+pub fn advertise(dlna: &Dlna, port: u16) -> Result<Advertiser, String> {
+    let Some(IpAddr::V4(ip)) = ok200_common::net::lan_ip() else {
+        return Err("dlna needs an IPv4 address on the local network".to_string());
+    };
+    let socket = ssdp_socket(ip).map_err(|e| format!("SSDP bind failed: {e}"))?;
+    let location = format!("http://{ip}:{port}{PREFIX}/description.xml");
+    let targets = targets(&dlna.uuid);
+    tracing::info!("announcing {} over SSDP at {location}", dlna.name);
+    let task = tokio::spawn(announce(socket, targets.clone(), location));
+    Ok(Advertiser { task, targets })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library_root() -> tempfile::TempDir {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("Movies")).unwrap();
+        std::fs::write(tmp.path().join("Movies/a & b.mp4"), "video").unwrap();
+        std::fs::write(tmp.path().join("Movies/notes.txt"), "text").unwrap();
+        std::fs::write(tmp.path().join("song.mp3"), "audio").unwrap();
+        std::fs::create_dir(tmp.path().join(".hidden")).unwrap();
+        tmp
+    }
+
+    fn mime_type(path: &Path) -> String {
+        let ext = path.extension().and_then(|e| e.to_str());
+        ok200_common::http::mime_type(ext).to_string()
+    }
+
+    fn browse_body(id: &str, flag: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\">\
+             <s:Body><u:Browse xmlns:u=\"{CONTENT_DIRECTORY}\"><ObjectID>{id}</ObjectID>\
+             <BrowseFlag>{flag}</BrowseFlag><Filter>*</Filter><StartingIndex>0</StartingIndex>\
+             <RequestedCount>0</RequestedCount><SortCriteria/></u:Browse></s:Body></s:Envelope>"
+        )
+    }
+
+    #[test]
+    fn test_xml_value() {
+        let body = browse_body("Movies/a &amp; b.mp4", "BrowseMetadata");
+        assert_eq!(xml_value(&body, "ObjectID").unwrap(), "Movies/a & b.mp4");
+        assert_eq!(xml_value(&body, "SortCriteria").unwrap(), "");
+        assert_eq!(xml_value("<u:Id>7</u:Id>", "Id").unwrap(), "7");
+        assert_eq!(xml_value(&body, "Missing"), None);
+        assert_eq!(
+            soap_action("\"urn:schemas-upnp-org:service:ContentDirectory:1#Browse\""),
+            Some("Browse")
+        );
+        assert_eq!(
+            endpoint("/_dlna/control/content-directory"),
+            Some(Endpoint::Control(Service::ContentDirectory))
+        );
+        assert_eq!(endpoint("/_dlna/other"), None);
+    }
+
+    #[test]
+    fn test_browse() {
+        let tmp = library_root();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        let library = Library {
+            root: &root,
+            dotfiles: Dotfiles::Hide,
+            mime_type: &mime_type,
+        };
+        let base = "http://192.168.1.2:8080";
+        let run = |id: &str, flag: &str| {
+            library.control(
+                Service::ContentDirectory,
+                "Browse",
+                &browse_body(id, flag),
+                base,
+            )
+        };
+
+        let (status, xml) = run(ROOT_ID, "BrowseDirectChildren");
+        assert_eq!(status, 200);
+        let didl = xml_value(&xml, "Result").unwrap();
+        assert!(didl.contains("<container id=\"Movies\" parentID=\"0\""));
+        assert!(didl.contains("<upnp:class>object.item.audioItem.musicTrack</upnp:class>"));
+        assert!(didl.find("Movies").unwrap() < didl.find("song.mp3").unwrap());
+        assert!(!didl.contains(".hidden"));
+        assert_eq!(xml_value(&xml, "TotalMatches").unwrap(), "2");
+
+        let (_, xml) = run("Movies", "BrowseDirectChildren");
+        let didl = xml_value(&xml, "Result").unwrap();
+        assert!(didl.contains("parentID=\"Movies\""));
+        assert!(didl.contains(">http://192.168.1.2:8080/Movies/a%20%26%20b.mp4</res>"));
+        assert!(didl.contains("http-get:*:video/mp4:"));
+        assert!(!didl.contains("notes.txt"));
+
+        let (_, xml) = run("Movies/a &amp; b.mp4", "BrowseMetadata");
+        assert_eq!(xml_value(&xml, "NumberReturned").unwrap(), "1");
+        let (status, xml) = run("../etc", "BrowseDirectChildren");
+        assert_eq!(status, 500);
+        assert!(xml.contains("<errorCode>701</errorCode>"));
+        let (status, _) = library.control(Service::ConnectionManager, "Browse", "", base);
+        assert_eq!(status, 500);
+    }
+
+    #[test]
+    fn test_ssdp() {
+        let dlna = Dlna::new(DlnaOptions::default(), Path::new("/srv/media"));
+        assert_eq!(dlna.name, "200 OK (media)");
+        assert_eq!(
+            dlna,
+            Dlna::new(DlnaOptions::default(), Path::new("/srv/media"))
+        );
+        assert!(dlna
+            .description()
+            .contains(&format!("<UDN>uuid:{}</UDN>", dlna.uuid)));
+
+        let search = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\n\
+                      MAN: \"ssdp:discover\"\r\nMX: 2\r\nST: ssdp:all\r\n\r\n";
+        assert_eq!(search_target(search), Some("ssdp:all"));
+        assert_eq!(search_target(&search.replace("discover", "other")), None);
+        assert_eq!(
+            search_target(&notify("upnp:rootdevice", "u", Some("l"))),
+            None
+        );
+
+        let targets = targets(&dlna.uuid);
+        assert_eq!(targets.len(), 5);
+        assert!(targets.iter().any(|(nt, _)| nt == DEVICE_TYPE));
+        let bye = notify(&targets[0].0, &targets[0].1, None);
+        assert!(bye.contains("NTS: ssdp:byebye\r\n") && !bye.contains("LOCATION"));
+        let reply = search_reply(DEVICE_TYPE, "uuid:x", "http://10.0.0.2:80/d.xml");
+        assert!(reply.starts_with("HTTP/1.1 200 OK\r\n") && reply.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,335 @@
+//! Host name lookups for the webview, which can't resolve names itself.
+//! A and AAAA queries go over UDP to the first nameserver in
+//! `/etc/resolv.conf`, so answers come with their TTLs. Names DNS doesn't
+//! know (`/etc/hosts`, mDNS) and systems without that file fall back to the
+//! OS resolver, which runs on tokio's blocking pool and reports no TTL.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// Plain DNS over UDP without EDNS is limited to this.
+const MAX_UDP_RESPONSE: usize = 512;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl Family {
+    fn allows(self, ip: IpAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Ipv4 => ip.is_ipv4(),
+            Self::Ipv6 => ip.is_ipv6(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ResolveOptions {
+    pub family: Family,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedAddress {
+    pub address: String,
+    /// 4 or 6.
+    pub family: u8,
+    /// Seconds the answer may be cached; `None` when the OS resolver
+    /// answered or the name was already an address.
+    pub ttl: Option<u32>,
+}
+
+impl ResolvedAddress {
+    fn new(ip: IpAddr, ttl: Option<u32>) -> Self {
+        Self {
+            address: ip.to_string(),
+            family: if ip.is_ipv4() { 4 } else { 6 },
+            ttl,
+        }
+    }
+}
+
+/// Nameservers listed in a `resolv.conf`.
+fn nameservers(conf: &str) -> Vec<IpAddr> {
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|rest| rest.split_whitespace().next()?.parse().ok())
+        .collect()
+}
+
+/// A recursive query for `name`.
+fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        let len = u8::try_from(label.len())
+            .ok()
+            .filter(|&l| (1..64).contains(&l))
+            .ok_or_else(|| format!("invalid host name: {name}"))?;
+        query.push(len);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Skip an encoded name, which may end in a compression pointer.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l => pos += 1 + usize::from(l),
+        }
+    }
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(msg.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+/// What a nameserver said about a name.
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    Found(Vec<(IpAddr, u32)>),
+    NotFound,
+    /// Truncated, refused or otherwise unusable.
+    Failed,
+}
+
+/// Addresses and TTLs in a response to query `id`. CNAMEs are followed by
+/// taking every A and AAAA record in the answer section.
+fn parse_response(msg: &[u8], id: u16) -> Answer {
+    let Some(header) = msg.get(..12) else {
+        return Answer::Failed;
+    };
+    let truncated = header[2] & 0x02 != 0;
+    if read_u16(header, 0) != Some(id) || header[2] & 0x80 == 0 || truncated {
+        return Answer::Failed;
+    }
+    match header[3] & 0x0f {
+        0 => {}
+        RCODE_NXDOMAIN => return Answer::NotFound,
+        _ => return Answer::Failed,
+    }
+    let parse = || {
+        let questions = read_u16(header, 4)?;
+        let answers = read_u16(header, 6)?;
+        let mut pos = 12;
+        for _ in 0..questions {
+            pos = skip_name(msg, pos)? + 4;
+        }
+        let mut found = Vec::new();
+        for _ in 0..answers {
+            pos = skip_name(msg, pos)?;
+            let record_type = read_u16(msg, pos)?;
+            let ttl = u32::from_be_bytes(msg.get(pos + 4..pos + 8)?.try_into().ok()?);
+            let len = usize::from(read_u16(msg, pos + 8)?);
+            let data = msg.get(pos + 10..pos + 10 + len)?;
+            pos += 10 + len;
+            let ip = match record_type {
+                TYPE_A => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?)),
+                TYPE_AAAA => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?)),
+                _ => continue,
+            };
+            found.push((ip, ttl));
+        }
+        Some(found)
+    };
+    parse().map_or(Answer::Failed, Answer::Found)
+}
+
+/// Ask `server` for one record type.
+async fn query(server: IpAddr, name: &str, record_type: u16) -> Answer {
+    let random = uuid::Uuid::new_v4();
+    let id = u16::from_be_bytes([random.as_bytes()[0], random.as_bytes()[1]]);
+    let Ok(request) = encode_query(id, name, record_type) else {
+        return Answer::Failed;
+    };
+    let local: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let exchange = async {
+        let socket = UdpSocket::bind(local).await.ok()?;
+        socket.connect((server, 53)).await.ok()?;
+        socket.send(&request).await.ok()?;
+        let mut buf = [0; MAX_UDP_RESPONSE];
+        loop {
+            let n = socket.recv(&mut buf).await.ok()?;
+            // Ignore stray datagrams meant for an earlier query
+            if read_u16(&buf[..n], 0) == Some(id) {
+                return Some(parse_response(&buf[..n], id));
+            }
+        }
+    };
+    tokio::time::timeout(QUERY_TIMEOUT, exchange)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(Answer::Failed)
+}
+
+/// `localhost` and mDNS `.local` names, which nameservers don't answer.
+fn system_only(hostname: &str) -> bool {
+    let name = hostname.trim_end_matches('.').to_ascii_lowercase();
+    matches!(name.rsplit('.').next(), Some("localhost" | "local"))
+}
+
+/// Ask the OS, for names DNS can't answer.
+async fn lookup_system(hostname: &str, family: Family) -> Result<Vec<ResolvedAddress>, String> {
+    let addrs = tokio::net::lookup_host((hostname, 0))
+        .await
+        .map_err(|e| format!("resolving {hostname} failed: {e}"))?;
+    let mut resolved: Vec<ResolvedAddress> = Vec::new();
+    for ip in addrs.map(|a| a.ip()).filter(|&ip| family.allows(ip)) {
+        let address = ResolvedAddress::new(ip, None);
+        if !resolved.contains(&address) {
+            resolved.push(address);
+        }
+    }
+    Ok(resolved)
+}
+
+/// Every address of `hostname` in `family`.
+pub async fn resolve(hostname: &str, family: Family) -> Result<Vec<ResolvedAddress>, String> {
+    let hostname = hostname.trim();
+    let literal = hostname.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return Ok(if family.allows(ip) {
+            vec![ResolvedAddress::new(ip, None)]
+        } else {
+            Vec::new()
+        });
+    }
+    if hostname.is_empty() {
+        return Err("empty host name".to_string());
+    }
+    let conf = tokio::fs::read_to_string(RESOLV_CONF)
+        .await
+        .unwrap_or_default();
+    let server = nameservers(&conf).first().copied();
+    if let Some(server) = server.filter(|_| !system_only(hostname)) {
+        let answers = match family {
+            Family::Any => {
+                let (a, aaaa) = tokio::join!(
+                    query(server, hostname, TYPE_A),
+                    query(server, hostname, TYPE_AAAA)
+                );
+                vec![a, aaaa]
+            }
+            Family::Ipv4 => vec![query(server, hostname, TYPE_A).await],
+            Family::Ipv6 => vec![query(server, hostname, TYPE_AAAA).await],
+        };
+        let mut resolved = Vec::new();
+        for answer in answers {
+            if let Answer::Found(found) = answer {
+                resolved.extend(
+                    found
+                        .into_iter()
+                        .map(|(ip, ttl)| ResolvedAddress::new(ip, Some(ttl))),
+                );
+            }
+        }
+        if !resolved.is_empty() {
+            return Ok(resolved);
+        }
+    }
+    let resolved = lookup_system(hostname, family).await?;
+    if resolved.is_empty() {
+        return Err(format!("no addresses for {hostname}"));
+    }
+    Ok(resolved)
+}
+
+#[tauri::command]
+pub async fn network_resolve(
+    hostname: String,
+    options: Option<ResolveOptions>,
+) -> Result<Vec<ResolvedAddress>, String> {
+    resolve(&hostname, options.unwrap_or_default().family).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nameservers() {
+        let conf = "# generated\nsearch lan\nnameserver 192.168.1.1\nnameserver  fd00::1 # v6\nnameserver bogus\n";
+        assert_eq!(
+            nameservers(conf),
+            vec![
+                "192.168.1.1".parse::<IpAddr>().unwrap(),
+                "fd00::1".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let mut msg = encode_query(0x1234, "www.example.com", TYPE_A).unwrap();
+        assert_eq!(&msg[12..17], b"\x03www\x07");
+        // Response, recursion available, two answers
+        msg[2] = 0x81;
+        msg[3] = 0x80;
+        msg[7] = 2;
+        // CNAME to a name elsewhere, via a pointer to the question
+        msg.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16]);
+        msg.extend_from_slice(&[0xc0, 16, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 93, 184, 215, 14]);
+        assert_eq!(
+            parse_response(&msg, 0x1234),
+            Answer::Found(vec![("93.184.215.14".parse().unwrap(), 300)])
+        );
+        assert_eq!(parse_response(&msg, 0x4321), Answer::Failed);
+        assert_eq!(
+            parse_response(&msg[..msg.len() - 1], 0x1234),
+            Answer::Failed
+        );
+        msg[3] = 0x83;
+        assert_eq!(parse_response(&msg, 0x1234), Answer::NotFound);
+        msg[2] |= 0x02;
+        assert_eq!(parse_response(&msg, 0x1234), Answer::Failed);
+        assert!(encode_query(1, "a..b", TYPE_A).is_err());
+        assert!(system_only("LocalHost.") && system_only("mysite.local"));
+        assert!(!system_only("example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_literals() {
+        assert_eq!(
+            resolve("[::1]", Family::Any).await.unwrap(),
+            vec![ResolvedAddress {
+                address: "::1".to_string(),
+                family: 6,
+                ttl: None
+            }]
+        );
+        assert!(resolve("127.0.0.1", Family::Ipv6).await.unwrap().is_empty());
+        let local = resolve("localhost", Family::Ipv4).await.unwrap();
+        assert!(local.iter().all(|a| a.family == 4));
+        assert!(resolve(" ", Family::Any).await.is_err());
+    }
+}
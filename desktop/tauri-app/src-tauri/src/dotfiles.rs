@@ -0,0 +1,53 @@
+//! What `http_server` does with dotfiles (`.git`, `.env`, `.DS_Store`) and
+//! the files Windows leaves in folders it has shown.
+
+use serde::{Deserialize, Serialize};
+
+/// Not dotfiles, but just as unwanted on a website. Compared ignoring case.
+const JUNK: [&str; 4] = ["thumbs.db", "ehthumbs.db", "desktop.ini", "$recycle.bin"];
+
+/// Whether a file or folder name is hidden.
+pub fn is_hidden(name: &str) -> bool {
+    name.starts_with('.') || JUNK.iter().any(|j| name.eq_ignore_ascii_case(j))
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dotfiles {
+    /// Listed and served like any other file.
+    #[default]
+    Serve,
+    /// Left out of listings, but served to anyone with the URL.
+    Hide,
+    /// Left out of listings, and 404 for anything inside them too.
+    Deny,
+}
+
+impl Dotfiles {
+    /// Whether a folder entry called `name` is listed.
+    pub fn listed(self, name: &str) -> bool {
+        self == Self::Serve || !is_hidden(name)
+    }
+
+    /// Whether a decoded URL path can be reached.
+    pub fn allows(self, url_path: &str) -> bool {
+        self != Self::Deny || !url_path.split('/').any(is_hidden)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        assert!(is_hidden(".DS_Store") && is_hidden("Thumbs.db") && is_hidden(".."));
+        assert!(!is_hidden("index.html"));
+        assert!(Dotfiles::Serve.listed(".env") && Dotfiles::Serve.allows("/.git/config"));
+        assert!(!Dotfiles::Hide.listed(".env") && Dotfiles::Hide.allows("/.git/config"));
+        assert!(!Dotfiles::Deny.listed(".env"));
+        assert!(!Dotfiles::Deny.allows("/.git/config"));
+        assert!(!Dotfiles::Deny.allows("/photos/Thumbs.db"));
+        assert!(Dotfiles::Deny.allows("/docs/a.b.c/"));
+    }
+}
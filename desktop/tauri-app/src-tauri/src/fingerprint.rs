@@ -0,0 +1,102 @@
+//! Content hashes of served files, used as strong `ETag`s. Each hash is
+//! cached by path and recomputed when the file's size or modification time
+//! changes, so unchanged files are only read once.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+/// The cache is dropped wholesale past this many files.
+const MAX_ENTRIES: usize = 4096;
+/// Bigger files are identified by size and modification time rather than
+/// read in full.
+const MAX_HASH_BYTES: u64 = 64 * 1024 * 1024;
+
+struct Entry {
+    len: u64,
+    modified: SystemTime,
+    etag: String,
+}
+
+/// Shared by every server; paths are canonical, so entries never clash.
+static CACHE: LazyLock<Mutex<HashMap<PathBuf, Entry>>> = LazyLock::new(Mutex::default);
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        let _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+fn compute(path: &Path, len: u64, modified: SystemTime) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    if len > MAX_HASH_BYTES {
+        let nanos = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        hasher.update(format!("{len}:{nanos}"));
+    } else {
+        let mut file = std::fs::File::open(path)?;
+        std::io::copy(&mut file, &mut hasher)?;
+    }
+    Ok(format!("\"{}\"", hex(&hasher.finalize()[..16])))
+}
+
+/// Quoted `ETag` for the file at `path`, whose metadata is `meta`.
+pub async fn etag(path: &Path, meta: &Metadata) -> std::io::Result<String> {
+    let len = meta.len();
+    let modified = meta.modified()?;
+    if let Some(entry) = CACHE.lock().unwrap().get(path) {
+        if entry.len == len && entry.modified == modified {
+            return Ok(entry.etag.clone());
+        }
+    }
+    let owned = path.to_path_buf();
+    let etag = tokio::task::spawn_blocking(move || compute(&owned, len, modified))
+        .await
+        .map_err(std::io::Error::other)??;
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= MAX_ENTRIES {
+        cache.clear();
+    }
+    cache.insert(
+        path.to_path_buf(),
+        Entry {
+            len,
+            modified,
+            etag: etag.clone(),
+        },
+    );
+    Ok(etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_etag_follows_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("a.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let first = etag(&path, &std::fs::metadata(&path).unwrap())
+            .await
+            .unwrap();
+        assert!(first.starts_with('"') && first.ends_with('"') && first.len() == 34);
+        let again = etag(&path, &std::fs::metadata(&path).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(first, again);
+
+        std::fs::write(&path, "hello, world").unwrap();
+        let changed = etag(&path, &std::fs::metadata(&path).unwrap())
+            .await
+            .unwrap();
+        assert_ne!(first, changed);
+    }
+}
@@ -0,0 +1,371 @@
+//! Folders the user handed to the app, by dropping them on the window or
+//! choosing them in a folder picker. Each is granted a token the webview
+//! can pass back in place of a path. Uses each platform's stock picker so
+//! no dialog plugin is needed.
+//!
+//! Deleting anything outside these folders needs the user's say-so in a
+//! native dialog, shown from here so the webview can't skip it.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{Emitter, Manager, State};
+
+use super::launch_args::display_path;
+
+const PICKER_PROMPT: &str = "Choose a folder to serve";
+const DIALOG_TITLE: &str = "200 OK";
+const REMEMBER_LABEL: &str = "Always in This Folder";
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderGrant {
+    pub token: String,
+    pub path: String,
+}
+
+/// Granted folders by token.
+#[derive(Default)]
+pub struct FolderGrants(Mutex<HashMap<String, PathBuf>>);
+
+impl FolderGrants {
+    /// Grant `path` if it is a folder. Granting it again returns the same
+    /// token.
+    pub fn grant(&self, path: &Path) -> Result<FolderGrant, String> {
+        let path = std::fs::canonicalize(path).map_err(|e| format!("grant failed: {e}"))?;
+        if !path.is_dir() {
+            return Err(format!("grant failed: {} is not a folder", path.display()));
+        }
+        let mut grants = self.0.lock().unwrap();
+        let token = grants
+            .iter()
+            .find(|(_, granted)| **granted == path)
+            .map_or_else(
+                || uuid::Uuid::new_v4().simple().to_string(),
+                |(token, _)| token.clone(),
+            );
+        grants.insert(token.clone(), path.clone());
+        Ok(FolderGrant {
+            token,
+            path: display_path(&path),
+        })
+    }
+
+    /// The folder granted under `token`.
+    pub fn path(&self, token: &str) -> Option<PathBuf> {
+        self.0.lock().unwrap().get(token).cloned()
+    }
+
+    /// Whether `path` is inside a granted folder, not the folder itself.
+    /// Only its parent is resolved, so a symlink to somewhere else still
+    /// counts as inside.
+    pub fn covers(&self, path: &Path) -> bool {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+        let Ok(parent) = std::fs::canonicalize(parent) else {
+            return false;
+        };
+        let path = parent.join(name);
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .any(|root| path != *root && path.starts_with(root))
+    }
+}
+
+/// Grant the first folder dropped on the window and ask the webview to
+/// serve it. Dropped files are ignored.
+pub fn handle_drop(app: &tauri::AppHandle, paths: &[PathBuf]) {
+    let Some(folder) = paths.iter().find(|p| p.is_dir()) else {
+        tracing::warn!("ignoring drop: no folder among {} paths", paths.len());
+        return;
+    };
+    match app.state::<FolderGrants>().grant(folder) {
+        Ok(grant) => {
+            let _ = app.emit("folder-dropped", grant);
+        }
+        Err(e) => tracing::warn!("{e}"),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn picker_commands() -> Vec<Command> {
+    let script = format!("POSIX path of (choose folder with prompt \"{PICKER_PROMPT}\")");
+    let mut cmd = Command::new("osascript");
+    cmd.args(["-e", &script]);
+    vec![cmd]
+}
+
+/// Whichever of the GNOME and KDE pickers is installed.
+#[cfg(target_os = "linux")]
+fn picker_commands() -> Vec<Command> {
+    let mut zenity = Command::new("zenity");
+    zenity.args([
+        "--file-selection",
+        "--directory",
+        &format!("--title={PICKER_PROMPT}"),
+    ]);
+    let mut kdialog = Command::new("kdialog");
+    kdialog.args(["--getexistingdirectory", ".", "--title", PICKER_PROMPT]);
+    vec![zenity, kdialog]
+}
+
+#[cfg(target_os = "windows")]
+fn picker_commands() -> Vec<Command> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    const SCRIPT: &str = "\
+        Add-Type -AssemblyName System.Windows.Forms;\
+        $d = New-Object System.Windows.Forms.FolderBrowserDialog;\
+        $d.Description = $env:OK200_PROMPT;\
+        if ($d.ShowDialog() -eq 'OK') { $d.SelectedPath }";
+    let mut cmd = Command::new("powershell.exe");
+    cmd.args(["-NoProfile", "-NonInteractive", "-STA", "-Command", SCRIPT])
+        .env("OK200_PROMPT", PICKER_PROMPT)
+        .creation_flags(CREATE_NO_WINDOW);
+    vec![cmd]
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+fn picker_commands() -> Vec<Command> {
+    Vec::new()
+}
+
+/// Show a folder picker and wait for it; `None` if the user cancelled.
+fn pick_folder() -> Result<Option<PathBuf>, String> {
+    for mut cmd in picker_commands() {
+        let output = match cmd.stdin(Stdio::null()).stderr(Stdio::null()).output() {
+            Ok(output) => output,
+            // Not installed, so try the next one
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("folder picker failed: {e}")),
+        };
+        let chosen = String::from_utf8_lossy(&output.stdout);
+        let chosen = chosen.trim_end_matches(['\r', '\n']);
+        return Ok((output.status.success() && !chosen.is_empty()).then(|| chosen.into()));
+    }
+    Err("folder picker failed: none is available on this system".to_string())
+}
+
+/// The user's answer to a delete confirmation.
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    Cancel,
+    Delete,
+    /// Delete, and don't ask again for this folder.
+    Remember,
+}
+
+type Decode = fn(&Output) -> Answer;
+
+fn delete_prompt(path: &Path) -> String {
+    format!(
+        "Delete \"{}\"?\n\nIt is outside the folders you gave 200 OK.",
+        display_path(path)
+    )
+}
+
+#[cfg(target_os = "macos")]
+fn confirm_commands(path: &Path) -> Vec<(Command, Decode)> {
+    let script = format!(
+        "display dialog (system attribute \"OK200_PROMPT\") with title \"{DIALOG_TITLE}\" \
+         buttons {{\"Cancel\", \"{REMEMBER_LABEL}\", \"Delete\"}} \
+         default button \"Cancel\" cancel button \"Cancel\" with icon caution\n\
+         button returned of result"
+    );
+    let mut cmd = Command::new("osascript");
+    cmd.args(["-e", &script])
+        .env("OK200_PROMPT", delete_prompt(path));
+    let decode: Decode = |output| {
+        let button = String::from_utf8_lossy(&output.stdout);
+        match (output.status.success(), button.trim()) {
+            (true, "Delete") => Answer::Delete,
+            (true, REMEMBER_LABEL) => Answer::Remember,
+            _ => Answer::Cancel,
+        }
+    };
+    vec![(cmd, decode)]
+}
+
+/// Whichever of the GNOME and KDE dialogs is installed.
+#[cfg(target_os = "linux")]
+fn confirm_commands(path: &Path) -> Vec<(Command, Decode)> {
+    // zenity reads Pango markup
+    let text = ok200_common::http::html_escape(&delete_prompt(path));
+    let mut zenity = Command::new("zenity");
+    zenity.args([
+        "--question",
+        &format!("--title={DIALOG_TITLE}"),
+        &format!("--text={text}"),
+        "--ok-label=Delete",
+        "--cancel-label=Cancel",
+        &format!("--extra-button={REMEMBER_LABEL}"),
+    ]);
+    // The extra button prints its label and exits 1, like Cancel
+    let zenity_decode: Decode = |output| {
+        if output.status.success() {
+            Answer::Delete
+        } else if String::from_utf8_lossy(&output.stdout).trim() == REMEMBER_LABEL {
+            Answer::Remember
+        } else {
+            Answer::Cancel
+        }
+    };
+    let mut kdialog = Command::new("kdialog");
+    kdialog.args([
+        "--title",
+        DIALOG_TITLE,
+        "--warningyesnocancel",
+        &delete_prompt(path),
+        "--yes-label",
+        "Delete",
+        "--no-label",
+        REMEMBER_LABEL,
+    ]);
+    let kdialog_decode: Decode = |output| match output.status.code() {
+        Some(0) => Answer::Delete,
+        Some(1) => Answer::Remember,
+        _ => Answer::Cancel,
+    };
+    vec![(zenity, zenity_decode), (kdialog, kdialog_decode)]
+}
+
+/// A stock message box can't relabel its buttons, so the prompt says what
+/// Yes and No do.
+#[cfg(target_os = "windows")]
+fn confirm_commands(path: &Path) -> Vec<(Command, Decode)> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    const SCRIPT: &str = "\
+        Add-Type -AssemblyName System.Windows.Forms;\
+        [System.Windows.Forms.MessageBox]::Show(\
+            $env:OK200_PROMPT, $env:OK200_TITLE, 'YesNoCancel', 'Warning', 'Button3')";
+    let prompt = format!(
+        "{}\n\nYes: delete it.\nNo: delete it, and don't ask again for this folder.",
+        delete_prompt(path)
+    );
+    let mut cmd = Command::new("powershell.exe");
+    cmd.args(["-NoProfile", "-NonInteractive", "-STA", "-Command", SCRIPT])
+        .env("OK200_PROMPT", prompt)
+        .env("OK200_TITLE", DIALOG_TITLE)
+        .creation_flags(CREATE_NO_WINDOW);
+    let decode: Decode = |output| match String::from_utf8_lossy(&output.stdout).trim() {
+        "Yes" => Answer::Delete,
+        "No" => Answer::Remember,
+        _ => Answer::Cancel,
+    };
+    vec![(cmd, decode)]
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+fn confirm_commands(_path: &Path) -> Vec<(Command, Decode)> {
+    Vec::new()
+}
+
+/// Ask whether to delete `path`, and wait for the answer.
+fn ask_delete(path: &Path) -> Result<Answer, String> {
+    for (mut cmd, decode) in confirm_commands(path) {
+        match cmd.stdin(Stdio::null()).stderr(Stdio::null()).output() {
+            Ok(output) => return Ok(decode(&output)),
+            // Not installed, so try the next one
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("delete confirmation failed: {e}")),
+        }
+    }
+    Err("delete confirmation failed: no dialog is available on this system".to_string())
+}
+
+/// Check with the user before deleting `path` outside the granted
+/// folders. Choosing to remember grants the folder it is in.
+pub async fn confirm_delete(grants: &FolderGrants, path: &Path) -> Result<(), String> {
+    if grants.covers(path) {
+        return Ok(());
+    }
+    let asked = path.to_path_buf();
+    let answer = tokio::task::spawn_blocking(move || ask_delete(&asked))
+        .await
+        .map_err(|e| format!("delete confirmation failed: {e}"))??;
+    match answer {
+        Answer::Cancel => Err(format!("delete cancelled: {}", display_path(path))),
+        Answer::Delete => Ok(()),
+        Answer::Remember => {
+            if let Some(parent) = path.parent() {
+                grants.grant(parent)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Let the user choose a folder and grant it. `None` if they cancelled.
+#[tauri::command]
+pub async fn pick_folder_and_grant(
+    grants: State<'_, FolderGrants>,
+) -> Result<Option<FolderGrant>, String> {
+    let picked = tokio::task::spawn_blocking(pick_folder)
+        .await
+        .map_err(|e| format!("folder picker failed: {e}"))??;
+    picked.map(|path| grants.grant(&path)).transpose()
+}
+
+/// Path of the folder granted under `token`.
+#[tauri::command]
+pub async fn folder_grant_path(
+    token: String,
+    grants: State<'_, FolderGrants>,
+) -> Result<String, String> {
+    grants
+        .path(&token)
+        .map(|path| display_path(&path))
+        .ok_or_else(|| "no folder granted for this token".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("site")).unwrap();
+        std::fs::write(tmp.path().join("file.txt"), "").unwrap();
+        let grants = FolderGrants::default();
+
+        let grant = grants.grant(&tmp.path().join("site")).unwrap();
+        let site = std::fs::canonicalize(tmp.path().join("site")).unwrap();
+        assert_eq!(grant.path, display_path(&site));
+        assert_eq!(grants.path(&grant.token), Some(site));
+        // Same folder by another route, same token
+        let again = grants.grant(&tmp.path().join("site/../site")).unwrap();
+        assert_eq!(again.token, grant.token);
+
+        assert!(grants
+            .grant(&tmp.path().join("file.txt"))
+            .unwrap_err()
+            .ends_with("is not a folder"));
+        assert!(grants.grant(&tmp.path().join("missing")).is_err());
+        assert_eq!(grants.path("unknown"), None);
+    }
+
+    #[test]
+    fn test_covers() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("site/img")).unwrap();
+        let grants = FolderGrants::default();
+        assert!(!grants.covers(&tmp.path().join("site/index.html")));
+
+        grants.grant(&tmp.path().join("site")).unwrap();
+        assert!(grants.covers(&tmp.path().join("site/index.html")));
+        assert!(grants.covers(&tmp.path().join("site/img/logo.png")));
+        assert!(grants.covers(&tmp.path().join("site/img")));
+        assert!(!grants.covers(&tmp.path().join("site")));
+        assert!(!grants.covers(&tmp.path().join("site/../other.txt")));
+        assert!(!grants.covers(&tmp.path().join("missing/file")));
+        assert!(!grants.covers(Path::new("/")));
+    }
+}
@@ -0,0 +1,129 @@
+//! Append-only record of what the app deleted, overwrote or truncated on
+//! disk, and which window asked for it. Kept as JSON lines in
+//! `logs/fs-audit.log`, rotated like the other logs.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, Webview};
+
+use super::logging::{self, channel_path, LogFile};
+
+const AUDIT_CHANNEL: &str = "fs-audit";
+const DEFAULT_TAIL_LINES: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    /// Unix seconds.
+    pub time: u64,
+    /// `delete`, `overwrite` or `truncate`.
+    pub op: String,
+    pub path: String,
+    /// Label of the webview that asked.
+    pub window: String,
+    /// Origin of the page loaded in it, if it has one.
+    pub origin: Option<String>,
+}
+
+/// The audit log, opened on first use.
+#[derive(Default)]
+pub struct FsAudit(Mutex<Option<LogFile>>);
+
+impl FsAudit {
+    fn append(&self, dir: &Path, entry: &AuditEntry) {
+        let Ok(line) = serde_json::to_string(entry) else {
+            return;
+        };
+        self.0
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| LogFile::open(channel_path(dir, AUDIT_CHANNEL)))
+            .write_line(&format!("{line}\n"));
+    }
+
+    /// Note that `webview` had `op` done to `path`.
+    pub fn record(&self, webview: &Webview, op: &str, path: &str) {
+        let Some(dir) = logging::log_dir(&webview.app_handle().config().identifier) else {
+            return;
+        };
+        let origin = webview.url().ok().and_then(|url| {
+            let origin = url.origin();
+            origin.is_tuple().then(|| origin.ascii_serialization())
+        });
+        let entry = AuditEntry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            op: op.to_string(),
+            path: path.to_string(),
+            window: webview.label().to_string(),
+            origin,
+        };
+        self.append(&dir, &entry);
+    }
+}
+
+fn audit_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let dir = logging::log_dir(&app.config().identifier).ok_or("no data directory")?;
+    Ok(channel_path(&dir, AUDIT_CHANNEL))
+}
+
+/// Entries in the last `lines` lines of the log, oldest first. Lines that
+/// don't parse are skipped.
+fn read_tail(path: &Path, lines: usize) -> Result<Vec<AuditEntry>, String> {
+    Ok(logging::tail(path, lines)?
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The most recent destructive filesystem operations (default 200).
+#[tauri::command]
+pub async fn fs_audit_tail(
+    lines: Option<usize>,
+    app: tauri::AppHandle,
+) -> Result<Vec<AuditEntry>, String> {
+    read_tail(&audit_path(&app)?, lines.unwrap_or(DEFAULT_TAIL_LINES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_tail() {
+        let tmp = tempfile::tempdir().unwrap();
+        let audit = FsAudit::default();
+        let entry = |op: &str, path: &str| AuditEntry {
+            time: 1_700_000_000,
+            op: op.to_string(),
+            path: path.to_string(),
+            window: "main".to_string(),
+            origin: Some("http://localhost:1420".to_string()),
+        };
+        audit.append(tmp.path(), &entry("delete", "/srv/old"));
+        audit.append(tmp.path(), &entry("truncate", "/srv/log.txt"));
+        let mut file = audit.0.lock().unwrap();
+        file.as_mut().unwrap().write_line("not json\n");
+        drop(file);
+
+        let path = channel_path(tmp.path(), AUDIT_CHANNEL);
+        assert_eq!(
+            read_tail(&path, 10).unwrap(),
+            [
+                entry("delete", "/srv/old"),
+                entry("truncate", "/srv/log.txt")
+            ]
+        );
+        assert_eq!(
+            read_tail(&path, 2).unwrap(),
+            [entry("truncate", "/srv/log.txt")]
+        );
+        assert!(read_tail(&tmp.path().join("missing.log"), 10)
+            .unwrap()
+            .is_empty());
+    }
+}
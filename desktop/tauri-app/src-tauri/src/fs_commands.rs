@@ -1,19 +1,27 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::UNIX_EPOCH;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 use serde::Serialize;
 use tauri::ipc::{InvokeBody, Request, Response};
-use tauri::State;
+use tauri::{State, Webview};
 use tokio::fs;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::Mutex;
 
+use super::folder_grants::{self, FolderGrants};
+use super::fs_audit::FsAudit;
+
 // -- State --
 
 pub struct FsState {
     handles: Mutex<HashMap<u32, tokio::fs::File>>,
+    /// Path each handle was opened with, for the audit log.
+    paths: StdMutex<HashMap<u32, String>>,
+    /// Long operations that can be cancelled, by id.
+    operations: Mutex<HashMap<u32, Operation>>,
     next_id: AtomicU32,
 }
 
@@ -21,6 +29,8 @@ impl FsState {
     pub fn new() -> Self {
         Self {
             handles: Mutex::new(HashMap::new()),
+            paths: StdMutex::new(HashMap::new()),
+            operations: Mutex::new(HashMap::new()),
             next_id: AtomicU32::new(1),
         }
     }
@@ -28,6 +38,77 @@ impl FsState {
     fn next_id(&self) -> u32 {
         self.next_id.fetch_add(1, Ordering::Relaxed)
     }
+
+    /// Path the handle `handle_id` was opened with.
+    pub fn handle_path(&self, handle_id: u32) -> Option<PathBuf> {
+        self.paths.lock().unwrap().get(&handle_id).map(PathBuf::from)
+    }
+
+    /// Token for the operation `op_id`, or one nothing can cancel.
+    pub async fn cancel_token(&self, op_id: Option<u32>) -> Result<CancelToken, String> {
+        let Some(op_id) = op_id else {
+            return Ok(CancelToken::default());
+        };
+        let mut operations = self.operations.lock().await;
+        let operation = operations
+            .get_mut(&op_id)
+            .ok_or_else(|| format!("operation {op_id} not found"))?;
+        operation.begun = true;
+        Ok(operation.cancel.clone())
+    }
+
+    pub async fn finish(&self, op_id: Option<u32>) {
+        if let Some(op_id) = op_id {
+            self.operations.lock().await.remove(&op_id);
+        }
+    }
+}
+
+/// Ids handed out but never passed to a long command are dropped after
+/// this long.
+const UNUSED_OPERATION_TTL: Duration = Duration::from_mins(1);
+
+struct Operation {
+    /// Label of the webview that started it, the only one that may cancel.
+    webview: String,
+    created: Instant,
+    /// Whether a command has taken its token; it then removes it when done.
+    begun: bool,
+    cancel: CancelToken,
+}
+
+impl Operation {
+    fn new(webview: &str) -> Self {
+        Self {
+            webview: webview.to_string(),
+            created: Instant::now(),
+            begun: false,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    fn expired(&self) -> bool {
+        !self.begun && self.created.elapsed() >= UNUSED_OPERATION_TTL
+    }
+}
+
+/// Set by `fs_cancel`; long operations check it between units of work.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// `Err` once cancelled, so work can stop with `?`.
+    pub fn check(&self) -> Result<(), String> {
+        if self.0.load(Ordering::Relaxed) {
+            Err("cancelled".to_string())
+        } else {
+            Ok(())
+        }
+    }
 }
 
 // -- Response types --
@@ -44,6 +125,141 @@ pub struct FileStat {
 pub struct TreeEntry {
     path: String,
     size: u64,
+    /// A symlink whose target doesn't exist; `size` is 0.
+    broken_link: bool,
+}
+
+#[derive(Serialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct DirEntry {
+    name: String,
+    size: u64,
+    mtime_ms: f64,
+    is_directory: bool,
+    is_file: bool,
+    is_symlink: bool,
+    /// A symlink whose target doesn't exist. The other fields then
+    /// describe the link itself.
+    broken_link: bool,
+}
+
+fn mtime_ms(meta: &std::fs::Metadata) -> f64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+struct Linked {
+    /// Of the link's target, or of the link itself when that's missing.
+    meta: std::fs::Metadata,
+    is_symlink: bool,
+    broken: bool,
+}
+
+/// Metadata for `path`, following a symlink if it leads anywhere.
+async fn link_metadata(path: &Path) -> std::io::Result<Linked> {
+    let meta = fs::symlink_metadata(path).await?;
+    if !meta.is_symlink() {
+        return Ok(Linked {
+            meta,
+            is_symlink: false,
+            broken: false,
+        });
+    }
+    match fs::metadata(path).await {
+        Ok(target) => Ok(Linked {
+            meta: target,
+            is_symlink: true,
+            broken: false,
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Linked {
+            meta,
+            is_symlink: true,
+            broken: true,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+// -- Containment --
+
+/// `relative` joined onto `base` with `.` and `..` worked out on the path
+/// alone, or `None` if it would step outside `base`.
+fn join_within(base: &Path, relative: &str) -> Option<PathBuf> {
+    let mut names = Vec::new();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                names.pop()?;
+            }
+            Component::Normal(name) => names.push(name),
+            // Absolute paths and drive prefixes
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    let mut path = base.to_path_buf();
+    path.extend(names);
+    Some(path)
+}
+
+/// Whether `path` is still inside `base` once symlinks are followed, as far
+/// as it exists. Dangling links can't be checked, so they count as outside.
+fn links_within(base: &Path, path: &Path) -> std::io::Result<bool> {
+    let base = std::fs::canonicalize(base)?;
+    let Some(existing) = path
+        .ancestors()
+        .find(|p| std::fs::symlink_metadata(p).is_ok())
+    else {
+        return Ok(false);
+    };
+    Ok(std::fs::canonicalize(existing).is_ok_and(|real| real.starts_with(&base)))
+}
+
+// -- Extended attributes --
+
+#[cfg(unix)]
+fn get_xattr(path: &str, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+    xattr::get(path, name)
+}
+
+#[cfg(unix)]
+fn set_xattr(path: &str, name: &str, value: &[u8]) -> std::io::Result<()> {
+    xattr::set(path, name, value)
+}
+
+#[cfg(unix)]
+fn list_xattrs(path: &str) -> std::io::Result<Vec<String>> {
+    Ok(xattr::list(path)?
+        .map(|name| name.to_string_lossy().to_string())
+        .collect())
+}
+
+/// NTFS alternate data streams stand in for attributes, e.g.
+/// `Zone.Identifier` for the quarantine flag.
+#[cfg(windows)]
+fn get_xattr(path: &str, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+    match std::fs::read(format!("{path}:{name}")) {
+        Ok(value) => Ok(Some(value)),
+        // The file itself has to exist
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => std::fs::metadata(path).map(|_| None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(windows)]
+fn set_xattr(path: &str, name: &str, value: &[u8]) -> std::io::Result<()> {
+    std::fs::write(format!("{path}:{name}"), value)
+}
+
+/// Enumerating streams needs `FindFirstStreamW`, which std doesn't wrap.
+#[cfg(windows)]
+fn list_xattrs(_path: &str) -> std::io::Result<Vec<String>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "listing data streams is not supported on Windows",
+    ))
 }
 
 // -- Commands --
@@ -53,7 +269,10 @@ pub async fn fs_open(
     path: String,
     mode: String,
     state: State<'_, FsState>,
+    audit: State<'_, FsAudit>,
+    webview: Webview,
 ) -> Result<u32, String> {
+    let overwrites = mode == "w" && fs::try_exists(&path).await.unwrap_or(false);
     let file = match mode.as_str() {
         "r" => tokio::fs::OpenOptions::new()
             .read(true)
@@ -76,8 +295,12 @@ pub async fn fs_open(
         _ => return Err(format!("invalid mode: {mode}")),
     };
 
+    if overwrites {
+        audit.record(&webview, "overwrite", &path);
+    }
     let id = state.next_id();
     state.handles.lock().await.insert(id, file);
+    state.paths.lock().unwrap().insert(id, path);
     Ok(id)
 }
 
@@ -155,6 +378,7 @@ pub async fn fs_write(
 #[tauri::command]
 pub async fn fs_close(handle_id: u32, state: State<'_, FsState>) -> Result<(), String> {
     state.handles.lock().await.remove(&handle_id);
+    state.paths.lock().unwrap().remove(&handle_id);
     Ok(())
 }
 
@@ -164,15 +388,9 @@ pub async fn fs_stat(path: String) -> Result<FileStat, String> {
         .await
         .map_err(|e| format!("stat failed: {e}"))?;
 
-    let mtime_ms = meta
-        .modified()
-        .ok()
-        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
-        .map_or(0.0, |d| d.as_secs_f64() * 1000.0);
-
     Ok(FileStat {
         size: meta.len(),
-        mtime_ms,
+        mtime_ms: mtime_ms(&meta),
         is_directory: meta.is_dir(),
         is_file: meta.is_file(),
     })
@@ -205,6 +423,112 @@ pub async fn fs_readdir(path: String) -> Result<Vec<String>, String> {
     Ok(entries)
 }
 
+/// Like `fs_readdir`, with what each entry is. Dangling symlinks are
+/// listed with `broken_link` rather than failing the whole listing.
+#[tauri::command]
+pub async fn fs_readdir_detailed(path: String) -> Result<Vec<DirEntry>, String> {
+    let mut entries = Vec::new();
+    let mut dir = fs::read_dir(&path)
+        .await
+        .map_err(|e| format!("readdir failed: {e}"))?;
+
+    while let Some(entry) = dir
+        .next_entry()
+        .await
+        .map_err(|e| format!("readdir failed: {e}"))?
+    {
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let linked = link_metadata(&entry.path())
+            .await
+            .map_err(|e| format!("readdir failed: {name}: {e}"))?;
+        let meta = &linked.meta;
+        entries.push(DirEntry {
+            name,
+            size: if linked.broken { 0 } else { meta.len() },
+            mtime_ms: mtime_ms(meta),
+            is_directory: !linked.broken && meta.is_dir(),
+            is_file: !linked.broken && meta.is_file(),
+            is_symlink: linked.is_symlink,
+            broken_link: linked.broken,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[tauri::command]
+pub async fn fs_readlink(path: String) -> Result<String, String> {
+    let target = fs::read_link(&path)
+        .await
+        .map_err(|e| format!("readlink failed: {e}"))?;
+    Ok(target.to_string_lossy().to_string())
+}
+
+/// Make `dst` another name for the file at `src`, without copying it.
+#[tauri::command]
+pub async fn fs_hardlink(src: String, dst: String) -> Result<(), String> {
+    fs::hard_link(&src, &dst).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::CrossesDevices {
+            format!("hardlink failed: {src} and {dst} are on different devices")
+        } else {
+            format!("hardlink failed: {e}")
+        }
+    })
+}
+
+/// Value of the extended attribute `name` (an alternate data stream on
+/// Windows), or `None` if the file doesn't have it.
+#[tauri::command]
+pub async fn fs_get_xattr(path: String, name: String) -> Result<Option<Vec<u8>>, String> {
+    tokio::task::spawn_blocking(move || get_xattr(&path, &name))
+        .await
+        .map_err(|e| format!("getxattr failed: {e}"))?
+        .map_err(|e| format!("getxattr failed: {e}"))
+}
+
+#[tauri::command]
+pub async fn fs_set_xattr(path: String, name: String, value: Vec<u8>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || set_xattr(&path, &name, &value))
+        .await
+        .map_err(|e| format!("setxattr failed: {e}"))?
+        .map_err(|e| format!("setxattr failed: {e}"))
+}
+
+#[tauri::command]
+pub async fn fs_list_xattrs(path: String) -> Result<Vec<String>, String> {
+    tokio::task::spawn_blocking(move || list_xattrs(&path))
+        .await
+        .map_err(|e| format!("listxattr failed: {e}"))?
+        .map_err(|e| format!("listxattr failed: {e}"))
+}
+
+/// `relative` joined onto `base`, refusing anything that would step outside
+/// it. With `confine_symlinks`, links along the way must point inside too.
+#[tauri::command]
+pub async fn fs_resolve_within(
+    base: String,
+    relative: String,
+    confine_symlinks: Option<bool>,
+) -> Result<String, String> {
+    let path = join_within(Path::new(&base), &relative)
+        .ok_or_else(|| format!("resolve failed: {relative} is outside {base}"))?;
+    if confine_symlinks.unwrap_or(false) {
+        let (root, checked) = (PathBuf::from(&base), path.clone());
+        let within = tokio::task::spawn_blocking(move || links_within(&root, &checked))
+            .await
+            .map_err(|e| format!("resolve failed: {e}"))?
+            .map_err(|e| format!("resolve failed: {e}"))?;
+        if !within {
+            return Err(format!(
+                "resolve failed: {relative} leads outside {base} through a symlink"
+            ));
+        }
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
 #[tauri::command]
 pub async fn fs_mkdir(path: String) -> Result<(), String> {
     fs::create_dir_all(&path)
@@ -213,20 +537,28 @@ pub async fn fs_mkdir(path: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub async fn fs_delete(path: String) -> Result<(), String> {
+pub async fn fs_delete(
+    path: String,
+    audit: State<'_, FsAudit>,
+    grants: State<'_, FolderGrants>,
+    webview: Webview,
+) -> Result<(), String> {
     let meta = fs::metadata(&path)
         .await
         .map_err(|e| format!("delete failed: {e}"))?;
+    folder_grants::confirm_delete(&grants, Path::new(&path)).await?;
 
     if meta.is_dir() {
         fs::remove_dir_all(&path)
             .await
-            .map_err(|e| format!("delete failed: {e}"))
+            .map_err(|e| format!("delete failed: {e}"))?;
     } else {
         fs::remove_file(&path)
             .await
-            .map_err(|e| format!("delete failed: {e}"))
+            .map_err(|e| format!("delete failed: {e}"))?;
     }
+    audit.record(&webview, "delete", &path);
+    Ok(())
 }
 
 #[tauri::command]
@@ -237,11 +569,61 @@ pub async fn fs_realpath(path: String) -> Result<String, String> {
     Ok(canonical.to_string_lossy().to_string())
 }
 
+/// An id to pass to a long command so `fs_cancel` can stop it. It has to
+/// be used within a minute.
+#[tauri::command]
+pub async fn fs_operation_start(
+    webview: Webview,
+    state: State<'_, FsState>,
+) -> Result<u32, String> {
+    let id = state.next_id();
+    let mut operations = state.operations.lock().await;
+    operations.retain(|_, op| !op.expired());
+    operations.insert(id, Operation::new(webview.label()));
+    Ok(id)
+}
+
+/// Stop the operation `op_id`; it fails with "cancelled". Cancelling one
+/// that hasn't begun yet stops it as soon as it does. Only the webview
+/// that started it can.
+#[tauri::command]
+pub async fn fs_cancel(
+    op_id: u32,
+    webview: Webview,
+    state: State<'_, FsState>,
+) -> Result<(), String> {
+    cancel(&state, op_id, webview.label()).await
+}
+
+async fn cancel(state: &FsState, op_id: u32, webview: &str) -> Result<(), String> {
+    state
+        .operations
+        .lock()
+        .await
+        .get(&op_id)
+        .filter(|op| op.webview == webview)
+        .ok_or_else(|| format!("operation {op_id} not found"))?
+        .cancel
+        .cancel();
+    Ok(())
+}
+
 #[tauri::command]
-pub async fn fs_list_tree(path: String) -> Result<Vec<TreeEntry>, String> {
-    let base = PathBuf::from(&path);
+pub async fn fs_list_tree(
+    path: String,
+    op_id: Option<u32>,
+    state: State<'_, FsState>,
+) -> Result<Vec<TreeEntry>, String> {
+    let cancel = state.cancel_token(op_id).await?;
+    let listed = list_tree(&path, &cancel).await;
+    state.finish(op_id).await;
+    listed
+}
+
+async fn list_tree(path: &str, cancel: &CancelToken) -> Result<Vec<TreeEntry>, String> {
+    let base = PathBuf::from(path);
     let mut result = Vec::new();
-    list_tree_recursive(&base, &base, &mut result).await?;
+    list_tree_recursive(&base, &base, &mut result, cancel).await?;
     Ok(result)
 }
 
@@ -249,6 +631,7 @@ async fn list_tree_recursive(
     base: &PathBuf,
     current: &PathBuf,
     result: &mut Vec<TreeEntry>,
+    cancel: &CancelToken,
 ) -> Result<(), String> {
     let mut dir = fs::read_dir(current)
         .await
@@ -259,24 +642,29 @@ async fn list_tree_recursive(
         .await
         .map_err(|e| format!("list_tree failed: {e}"))?
     {
+        cancel
+            .check()
+            .map_err(|e| format!("list_tree failed: {e}"))?;
         let entry_path = entry.path();
-        let meta = match fs::metadata(&entry_path).await {
-            Ok(m) => m,
-            Err(_) => continue,
+        let Ok(linked) = link_metadata(&entry_path).await else {
+            continue;
         };
-
-        if meta.is_file() {
-            let relative = entry_path
+        let relative = || {
+            entry_path
                 .strip_prefix(base)
                 .unwrap_or(&entry_path)
                 .to_string_lossy()
-                .to_string();
+                .to_string()
+        };
+
+        if linked.broken || linked.meta.is_file() {
             result.push(TreeEntry {
-                path: relative,
-                size: meta.len(),
+                path: relative(),
+                size: if linked.broken { 0 } else { linked.meta.len() },
+                broken_link: linked.broken,
             });
-        } else if meta.is_dir() {
-            Box::pin(list_tree_recursive(base, &entry_path, result)).await?;
+        } else if linked.meta.is_dir() {
+            Box::pin(list_tree_recursive(base, &entry_path, result, cancel)).await?;
         }
     }
 
@@ -288,6 +676,8 @@ pub async fn fs_truncate(
     handle_id: u32,
     length: u64,
     state: State<'_, FsState>,
+    audit: State<'_, FsAudit>,
+    webview: Webview,
 ) -> Result<(), String> {
     let mut handles = state.handles.lock().await;
     let file = handles
@@ -296,7 +686,11 @@ pub async fn fs_truncate(
 
     file.set_len(length)
         .await
-        .map_err(|e| format!("truncate failed: {e}"))
+        .map_err(|e| format!("truncate failed: {e}"))?;
+    if let Some(path) = state.handle_path(handle_id) {
+        audit.record(&webview, "truncate", &path.to_string_lossy());
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -311,6 +705,21 @@ pub async fn fs_sync(handle_id: u32, state: State<'_, FsState>) -> Result<(), St
         .map_err(|e| format!("sync failed: {e}"))
 }
 
+/// Show `path` selected in Finder, Explorer or the Linux file manager,
+/// rather than just opening its folder.
+#[tauri::command]
+pub async fn os_reveal(path: String, app: tauri::AppHandle) -> Result<(), String> {
+    use tauri_plugin_opener::OpenerExt;
+
+    let path = PathBuf::from(path);
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        return Err(format!("reveal failed: {} does not exist", path.display()));
+    }
+    app.opener()
+        .reveal_item_in_dir(&path)
+        .map_err(|e| format!("reveal failed: {e}"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -322,6 +731,217 @@ mod tests {
         assert_eq!(state.next_id(), 2);
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_broken_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("real.txt"), "hello").unwrap();
+        std::os::unix::fs::symlink("real.txt", dir.join("good")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", dir.join("dangling")).unwrap();
+        let path = dir.to_string_lossy().to_string();
+
+        let mut entries = fs_readdir_detailed(path.clone()).await.unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| {
+                (
+                    e.name.as_str(),
+                    e.size,
+                    e.is_file,
+                    e.is_symlink,
+                    e.broken_link,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("dangling", 0, false, true, true),
+                ("good", 5, true, true, false),
+                ("real.txt", 5, true, false, false),
+            ]
+        );
+
+        let mut tree = list_tree(&path, &CancelToken::default()).await.unwrap();
+        tree.sort_by(|a, b| a.path.cmp(&b.path));
+        let summary: Vec<_> = tree
+            .iter()
+            .map(|e| (e.path.as_str(), e.size, e.broken_link))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("dangling", 0, true),
+                ("good", 5, false),
+                ("real.txt", 5, false)
+            ]
+        );
+        assert_eq!(
+            fs_readlink(dir.join("dangling").to_string_lossy().to_string())
+                .await
+                .unwrap(),
+            "missing.txt"
+        );
+        assert!(
+            fs_readlink(dir.join("real.txt").to_string_lossy().to_string())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_tree_cancel() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("sub")).unwrap();
+        std::fs::write(tmp.path().join("sub/a.txt"), "a").unwrap();
+        let path = tmp.path().to_string_lossy().to_string();
+
+        let cancel = CancelToken::default();
+        assert_eq!(list_tree(&path, &cancel).await.unwrap().len(), 1);
+        cancel.cancel();
+        assert_eq!(
+            list_tree(&path, &cancel).await.err().as_deref(),
+            Some("list_tree failed: cancelled")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_operations() {
+        let state = FsState::new();
+        state
+            .operations
+            .lock()
+            .await
+            .insert(1, Operation::new("main"));
+        assert_eq!(
+            cancel(&state, 1, "other").await.err().as_deref(),
+            Some("operation 1 not found")
+        );
+        let token = state.cancel_token(Some(1)).await.unwrap();
+        assert!(token.check().is_ok());
+        cancel(&state, 1, "main").await.unwrap();
+        assert!(token.check().is_err());
+        state.finish(Some(1)).await;
+        assert!(state.operations.lock().await.is_empty());
+
+        let mut unused = Operation::new("main");
+        unused.created -= UNUSED_OPERATION_TTL;
+        assert!(unused.expired());
+        unused.begun = true;
+        assert!(!unused.expired());
+    }
+
+    #[tokio::test]
+    async fn test_hardlink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("a.txt");
+        let dst = tmp.path().join("b.txt");
+        std::fs::write(&src, "shared").unwrap();
+        let path = |p: &std::path::Path| p.to_string_lossy().to_string();
+
+        fs_hardlink(path(&src), path(&dst)).await.unwrap();
+        std::fs::write(&src, "changed").unwrap();
+        assert_eq!(std::fs::read_to_string(&dst).unwrap(), "changed");
+        assert!(fs_hardlink(path(&src), path(&dst))
+            .await
+            .unwrap_err()
+            .starts_with("hardlink failed: "));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_xattrs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("upload.bin");
+        std::fs::write(&file, "x").unwrap();
+        let path = file.to_string_lossy().to_string();
+        let name = "user.ok200.origin".to_string();
+
+        let set = fs_set_xattr(path.clone(), name.clone(), b"https://example.com".to_vec()).await;
+        // Some filesystems (older tmpfs) don't take user attributes
+        if set.as_ref().is_err_and(|e| e.contains("not supported")) {
+            return;
+        }
+        set.unwrap();
+        assert_eq!(
+            fs_get_xattr(path.clone(), name.clone()).await.unwrap(),
+            Some(b"https://example.com".to_vec())
+        );
+        assert!(fs_list_xattrs(path.clone()).await.unwrap().contains(&name));
+        assert_eq!(
+            fs_get_xattr(path, "user.ok200.missing".to_string())
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            fs_list_xattrs(tmp.path().join("gone").to_string_lossy().to_string())
+                .await
+                .unwrap_err()
+                .starts_with("listxattr failed: ")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_within() {
+        let base = std::path::Path::new("/srv/site");
+        let resolve = |relative: &str| {
+            join_within(base, relative).map(|p| p.to_string_lossy().replace('\\', "/"))
+        };
+        assert_eq!(
+            resolve("a/./b/../c.txt").as_deref(),
+            Some("/srv/site/a/c.txt")
+        );
+        assert_eq!(resolve("").as_deref(), Some("/srv/site"));
+        assert_eq!(resolve("a/..").as_deref(), Some("/srv/site"));
+        assert_eq!(resolve(".."), None);
+        assert_eq!(resolve("a/../../site/x"), None);
+        assert_eq!(resolve("/etc/passwd"), None);
+
+        let err = fs_resolve_within("/srv".into(), "../etc".into(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, "resolve failed: ../etc is outside /srv");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resolve_within_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path().join("base");
+        std::fs::create_dir_all(base.join("inner")).unwrap();
+        std::os::unix::fs::symlink("inner", base.join("ok")).unwrap();
+        std::os::unix::fs::symlink(tmp.path(), base.join("out")).unwrap();
+        std::os::unix::fs::symlink("missing", base.join("dangling")).unwrap();
+        let resolve = |relative: &str| {
+            fs_resolve_within(
+                base.to_string_lossy().to_string(),
+                relative.to_string(),
+                Some(true),
+            )
+        };
+
+        assert!(resolve("ok/new.txt")
+            .await
+            .unwrap()
+            .ends_with("base/ok/new.txt"));
+        assert!(resolve("inner/a/b").await.is_ok());
+        assert!(resolve("out/elsewhere")
+            .await
+            .unwrap_err()
+            .contains("through a symlink"));
+        assert!(resolve("dangling").await.is_err());
+        // Only checked when asked
+        let unchecked = fs_resolve_within(
+            base.to_string_lossy().to_string(),
+            "out/x".to_string(),
+            None,
+        );
+        assert!(unchecked.await.is_ok());
+    }
+
     #[test]
     fn test_file_stat_serialization() {
         let stat = FileStat {
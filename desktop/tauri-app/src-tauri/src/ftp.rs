@@ -0,0 +1,1062 @@
+//! Folders served over FTP, for devices that can't speak HTTP well: old
+//! TVs and media players, IP cameras, scanners that save to a share.
+//! Read-only unless `write` is set. Paths are confined to the root the way
+//! `http_server` confines them, and `dotfiles` and `auth` mean what they
+//! do there; without `auth`, any user name and password log in. With `tls`
+//! it's explicit FTPS: clients have to send `AUTH TLS` before logging in,
+//! and `PROT P` to encrypt transfers as well. Only passive mode is offered,
+//! since in active mode the server connects wherever a client tells it to.
+
+use std::collections::HashMap;
+use std::io::{self, SeekFrom};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ok200_common::http::resolve;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+use super::auth::Auth;
+use super::dotfiles::Dotfiles;
+use super::http_server::MONTHS;
+use super::logging::UtcTime;
+use super::metrics::ServerStats;
+use super::tcp::ACCEPT_RETRY_DELAY;
+use super::tls::{self, Tls, TlsOptions};
+use super::uploads::{file_name, temp_path};
+
+/// Longer command lines close the connection.
+const MAX_LINE: u64 = 4096;
+const IDLE_TIMEOUT: Duration = Duration::from_mins(5);
+/// How long a client has to open the data connection it asked for.
+const DATA_TIMEOUT: Duration = Duration::from_secs(30);
+/// The connection is closed after this many wrong passwords.
+const MAX_LOGIN_FAILURES: u32 = 3;
+/// Commands answered before logging in.
+const PRE_LOGIN: [&str; 10] = [
+    "USER", "PASS", "AUTH", "PBSZ", "PROT", "FEAT", "SYST", "OPTS", "NOOP", "QUIT",
+];
+/// Listings show the time for files newer than this, the year otherwise.
+const RECENT_SECS: u64 = 183 * 86_400;
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FtpServerOptions {
+    pub root: String,
+    /// 0 picks a free port; 21 usually needs admin rights.
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// Allow uploads, deletes, renames and new folders.
+    #[serde(default)]
+    pub write: bool,
+    #[serde(default)]
+    pub dotfiles: Dotfiles,
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    /// Require explicit FTPS with this certificate.
+    #[serde(default)]
+    pub tls: Option<TlsOptions>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FtpServerInfo {
+    pub id: u32,
+    pub root: String,
+    pub host: String,
+    /// The bound port, which differs from the requested one for port 0.
+    pub port: u16,
+    pub write: bool,
+    pub dotfiles: Dotfiles,
+    /// Includes the generated token, which is the password.
+    pub auth: Option<Auth>,
+    pub tls: Option<TlsOptions>,
+}
+
+struct FtpServer {
+    info: FtpServerInfo,
+    task: JoinHandle<()>,
+    stats: ServerStats,
+}
+
+#[derive(Default)]
+pub struct FtpState {
+    servers: Mutex<HashMap<u32, FtpServer>>,
+    next_id: AtomicU32,
+}
+
+impl FtpState {
+    /// Stop every server.
+    pub fn close_all(&self) {
+        for (_, server) in self.servers.lock().unwrap().drain() {
+            server.task.abort();
+        }
+    }
+
+    /// Sessions open on every server, for confirming before quitting.
+    pub fn active_connections(&self) -> u64 {
+        let servers = self.servers.lock().unwrap();
+        servers.values().map(|s| s.stats.active()).sum()
+    }
+}
+
+/// A served folder, shared by its connections.
+struct Served {
+    /// Canonical, for `resolve`.
+    root: PathBuf,
+    write: bool,
+    dotfiles: Dotfiles,
+    auth: Option<Auth>,
+    tls: Option<Arc<ServerConfig>>,
+    /// Counts open sessions.
+    stats: ServerStats,
+}
+
+impl Served {
+    /// The file or folder at `path`, if it exists and may be reached.
+    fn existing(&self, path: &str) -> Option<PathBuf> {
+        if !self.dotfiles.allows(path) {
+            return None;
+        }
+        resolve(&self.root, path)
+    }
+
+    /// The entry `path` names itself, rather than what a link there points
+    /// at: a plain name in an existing folder. For creating, deleting and
+    /// renaming.
+    fn entry(&self, path: &str) -> Option<PathBuf> {
+        if !self.dotfiles.allows(path) {
+            return None;
+        }
+        let (parent, name) = path.rsplit_once('/')?;
+        let dir = resolve(&self.root, parent).filter(|d| d.is_dir())?;
+        Some(dir.join(file_name(name)?))
+    }
+}
+
+/// How a connection's command loop ended.
+#[derive(Debug, PartialEq, Eq)]
+enum Flow {
+    Quit,
+    /// `AUTH TLS` was accepted, so the handshake comes next.
+    Secure,
+}
+
+/// A data connection, encrypted or not.
+trait DataStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> DataStream for T {}
+
+/// What goes over a data connection.
+enum Transfer {
+    Listing(Vec<u8>),
+    Send(tokio::fs::File),
+    /// Written to `temp`, then renamed over `target` once complete.
+    Receive {
+        temp: PathBuf,
+        target: PathBuf,
+    },
+}
+
+struct Session {
+    peer: SocketAddr,
+    /// Where the client reached us, so data ports open on the same address.
+    local: IpAddr,
+    user: Option<String>,
+    logged_in: bool,
+    failures: u32,
+    /// Starts with `/` and only ends with one at the root.
+    cwd: String,
+    secure: bool,
+    /// `PROT P`: data connections are encrypted too.
+    protected: bool,
+    passive: Option<TcpListener>,
+    /// Where the next download starts, from `REST`.
+    offset: u64,
+    rename_from: Option<PathBuf>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('"', "\"\""))
+}
+
+/// The command, upper-cased, and its argument.
+fn parse(line: &str) -> (String, &str) {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (verb, arg) = line.split_once(' ').unwrap_or((line, ""));
+    (verb.to_ascii_uppercase(), arg)
+}
+
+/// `arg` taken from `cwd`, as a clean path from the root. `..` stops at
+/// the root, as it does at `/` in a shell.
+pub fn join_path(cwd: &str, arg: &str) -> String {
+    let mut segments: Vec<&str> = if arg.starts_with('/') {
+        Vec::new()
+    } else {
+        cwd.split('/').filter(|s| !s.is_empty()).collect()
+    };
+    for segment in arg.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            name => segments.push(name),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// A `LIST` or `NLST` argument without the `ls` flags many clients send.
+fn strip_flags(arg: &str) -> &str {
+    let mut rest = arg.trim_start();
+    while rest.starts_with('-') {
+        rest = rest.split_once(' ').map_or("", |(_, r)| r).trim_start();
+    }
+    rest
+}
+
+/// One line of `ls -l` output, the format clients parse `LIST` replies as.
+fn list_line(name: &str, dir: bool, size: u64, mtime: u64, now: u64, write: bool) -> String {
+    let mode = match (dir, write) {
+        (true, true) => "drwxr-xr-x",
+        (true, false) => "dr-xr-xr-x",
+        (false, true) => "-rw-r--r--",
+        (false, false) => "-r--r--r--",
+    };
+    let t = UtcTime::from_unix(mtime);
+    let month = MONTHS[usize::try_from(t.month - 1).unwrap_or(0) % 12];
+    let when = if now.abs_diff(mtime) < RECENT_SECS {
+        format!("{:02}:{:02}", t.hour, t.minute)
+    } else {
+        format!("{:>5}", t.year)
+    };
+    format!(
+        "{mode} 1 ftp ftp {size:>12} {month} {:>2} {when} {name}",
+        t.day
+    )
+}
+
+/// `MDTM` time: `YYYYMMDDHHMMSS` in UTC.
+fn mdtm(secs: u64) -> String {
+    let t = UtcTime::from_unix(secs);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+/// The `227` reply for a data port on `addr`; `None` for IPv6, which
+/// needs `EPSV`.
+fn pasv_reply(addr: SocketAddr) -> Option<String> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped()?,
+    };
+    let [a, b, c, d] = ip.octets();
+    let (hi, lo) = (addr.port() >> 8, addr.port() & 0xff);
+    Some(format!(
+        "227 Entering Passive Mode ({a},{b},{c},{d},{hi},{lo})"
+    ))
+}
+
+fn features(served: &Served) -> String {
+    let mut features = vec!["UTF8", "EPSV", "PASV", "SIZE", "MDTM", "REST STREAM"];
+    if served.tls.is_some() {
+        features.extend(["AUTH TLS", "PBSZ", "PROT"]);
+    }
+    format!("211-Features:\r\n {}\r\n211 End", features.join("\r\n "))
+}
+
+/// `LIST` or `NLST` output for a folder's entries, or for one file.
+fn listing(target: &Path, dotfiles: Dotfiles, write: bool, names_only: bool) -> io::Result<String> {
+    let mut entries = Vec::new();
+    if target.is_dir() {
+        for entry in std::fs::read_dir(target)?.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // Followed, so links show as what they point at
+            if let (true, Ok(meta)) = (dotfiles.listed(&name), std::fs::metadata(entry.path())) {
+                entries.push((name, meta));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+    } else {
+        let name = target.file_name().unwrap_or_default();
+        entries.push((
+            name.to_string_lossy().to_string(),
+            std::fs::metadata(target)?,
+        ));
+    }
+    let now = unix_secs(SystemTime::now());
+    let mut lines: Vec<_> = entries
+        .iter()
+        .map(|(name, meta)| {
+            if names_only {
+                name.clone()
+            } else {
+                let mtime = meta.modified().map_or(0, unix_secs);
+                list_line(name, meta.is_dir(), meta.len(), mtime, now, write)
+            }
+        })
+        .collect();
+    lines.push(String::new());
+    Ok(lines.join("\r\n"))
+}
+
+async fn reply<S: AsyncWrite + Unpin>(stream: &mut S, text: &str) -> io::Result<()> {
+    stream.write_all(format!("{text}\r\n").as_bytes()).await?;
+    stream.flush().await
+}
+
+/// The next command line, or `None` once the client has gone, sent too
+/// long a line or been idle too long.
+async fn read_command<S: AsyncRead + Unpin>(stream: &mut BufReader<S>) -> Option<String> {
+    let mut line = Vec::new();
+    let mut limited = (&mut *stream).take(MAX_LINE);
+    let read = limited.read_until(b'\n', &mut line);
+    match tokio::time::timeout(IDLE_TIMEOUT, read).await {
+        // Not every legacy client sends UTF-8
+        Ok(Ok(_)) if line.ends_with(b"\n") => Some(String::from_utf8_lossy(&line).into_owned()),
+        _ => None,
+    }
+}
+
+async fn receive(data: &mut dyn DataStream, temp: &Path, target: &Path) -> io::Result<u64> {
+    let mut file = tokio::fs::File::create(temp).await?;
+    let bytes = tokio::io::copy(data, &mut file).await?;
+    file.flush().await?;
+    drop(file);
+    tokio::fs::rename(temp, target).await?;
+    Ok(bytes)
+}
+
+/// Move `transfer` over `data`, returning the bytes moved.
+async fn run(transfer: Transfer, data: &mut dyn DataStream) -> io::Result<u64> {
+    let bytes = match transfer {
+        Transfer::Listing(listing) => {
+            data.write_all(&listing).await?;
+            listing.len() as u64
+        }
+        Transfer::Send(mut file) => tokio::io::copy(&mut file, data).await?,
+        Transfer::Receive { temp, target } => {
+            let received = receive(data, &temp, &target).await;
+            if received.is_err() {
+                tokio::fs::remove_file(&temp).await.ok();
+            }
+            return received;
+        }
+    };
+    // Sends TLS close_notify, which tells the client the file is complete
+    data.shutdown().await?;
+    Ok(bytes)
+}
+
+impl Session {
+    fn new(peer: SocketAddr, local: IpAddr) -> Self {
+        Self {
+            peer,
+            local,
+            user: None,
+            logged_in: false,
+            failures: 0,
+            cwd: "/".to_string(),
+            secure: false,
+            protected: false,
+            passive: None,
+            offset: 0,
+            rename_from: None,
+        }
+    }
+
+    /// Answer one command, returning how the connection ends if it does.
+    async fn dispatch<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        served: &Served,
+        control: &mut S,
+        verb: &str,
+        arg: &str,
+    ) -> io::Result<Option<Flow>> {
+        let text = match verb {
+            "QUIT" => {
+                reply(control, "221 Goodbye").await?;
+                return Ok(Some(Flow::Quit));
+            }
+            "AUTH" => match self.auth_tls(served, arg) {
+                Ok(text) => {
+                    reply(control, text).await?;
+                    return Ok(Some(Flow::Secure));
+                }
+                Err(text) => text.to_string(),
+            },
+            _ if !self.logged_in && !PRE_LOGIN.contains(&verb) => {
+                "530 Log in with USER and PASS first".to_string()
+            }
+            "LIST" | "NLST" | "RETR" | "STOR" => {
+                self.transfer(served, control, verb, arg).await?;
+                return Ok(None);
+            }
+            _ => match self.account(served, verb, arg) {
+                Some(text) => text,
+                None => match self.modify(served, verb, arg) {
+                    Some(text) => text,
+                    None => self.navigate(served, verb, arg).await,
+                },
+            },
+        };
+        reply(control, &text).await?;
+        Ok((self.failures >= MAX_LOGIN_FAILURES).then_some(Flow::Quit))
+    }
+
+    fn auth_tls(&self, served: &Served, arg: &str) -> Result<&'static str, &'static str> {
+        if served.tls.is_none() {
+            return Err("502 TLS isn't set up on this server");
+        }
+        if self.secure {
+            return Err("503 Already using TLS");
+        }
+        match arg.to_ascii_uppercase().as_str() {
+            "TLS" | "TLS-C" | "SSL" => Ok("234 Starting TLS"),
+            _ => Err("504 Use AUTH TLS"),
+        }
+    }
+
+    /// Logging in, and commands about the connection rather than files.
+    fn account(&mut self, served: &Served, verb: &str, arg: &str) -> Option<String> {
+        let text = match verb {
+            "USER" if served.tls.is_some() && !self.secure => "530 Send AUTH TLS first",
+            "USER" => {
+                self.user = Some(arg.to_string());
+                self.logged_in = false;
+                "331 Send the password"
+            }
+            "PASS" => {
+                let Some(user) = &self.user else {
+                    return Some("503 Send USER first".to_string());
+                };
+                if !served
+                    .auth
+                    .as_ref()
+                    .is_none_or(|a| a.check_login(user, arg))
+                {
+                    self.failures += 1;
+                    tracing::warn!("ftp {}: wrong password for {user}", self.peer);
+                    return Some("530 Login incorrect".to_string());
+                }
+                self.logged_in = true;
+                "230 Logged in"
+            }
+            "PBSZ" | "PROT" if !self.secure => "503 Send AUTH TLS first",
+            "PBSZ" => "200 PBSZ=0",
+            "PROT" => match arg.to_ascii_uppercase().as_str() {
+                "P" => {
+                    self.protected = true;
+                    "200 Transfers are encrypted"
+                }
+                "C" => {
+                    self.protected = false;
+                    "200 Transfers are in the clear"
+                }
+                _ => "504 Only C and P are supported",
+            },
+            "FEAT" => return Some(features(served)),
+            "SYST" => "215 UNIX Type: L8",
+            "OPTS" if arg.eq_ignore_ascii_case("UTF8 ON") => "200 Always in UTF-8",
+            "OPTS" => "501 Unknown option",
+            "NOOP" => "200 OK",
+            _ => return None,
+        };
+        Some(text.to_string())
+    }
+
+    /// Open a data port on the address the client reached us on.
+    async fn listen(&mut self) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind((self.local, 0)).await?;
+        let addr = listener.local_addr()?;
+        self.passive = Some(listener);
+        Ok(addr)
+    }
+
+    /// Moving around, file details and transfer settings.
+    async fn navigate(&mut self, served: &Served, verb: &str, arg: &str) -> String {
+        let path = join_path(&self.cwd, arg);
+        let meta = || {
+            served
+                .existing(&path)
+                .and_then(|p| std::fs::metadata(p).ok())
+        };
+        match verb {
+            "PWD" | "XPWD" => format!("257 {} is the current folder", quote(&self.cwd)),
+            "CWD" | "XCWD" | "CDUP" | "XCUP" => {
+                let path = if verb.ends_with("UP") {
+                    join_path(&self.cwd, "..")
+                } else {
+                    path.clone()
+                };
+                if served.existing(&path).is_some_and(|p| p.is_dir()) {
+                    self.cwd = path;
+                    "250 OK".to_string()
+                } else {
+                    "550 No such folder".to_string()
+                }
+            }
+            // Files are always sent as they are
+            "TYPE" => match arg
+                .split(' ')
+                .next()
+                .map(str::to_ascii_uppercase)
+                .as_deref()
+            {
+                Some("A" | "I" | "L") => "200 OK".to_string(),
+                _ => "504 Unknown type".to_string(),
+            },
+            "MODE" if arg.eq_ignore_ascii_case("S") => "200 OK".to_string(),
+            "STRU" if arg.eq_ignore_ascii_case("F") => "200 OK".to_string(),
+            "MODE" | "STRU" => "504 Only stream mode and file structure".to_string(),
+            "PASV" => match self.listen().await {
+                Ok(addr) => pasv_reply(addr).unwrap_or_else(|| "522 Use EPSV".to_string()),
+                Err(e) => format!("425 Can't open a data port: {e}"),
+            },
+            "EPSV" if arg.eq_ignore_ascii_case("ALL") => "200 OK".to_string(),
+            "EPSV" => match self.listen().await {
+                Ok(addr) => format!("229 Entering Extended Passive Mode (|||{}|)", addr.port()),
+                Err(e) => format!("425 Can't open a data port: {e}"),
+            },
+            "PORT" | "EPRT" => "502 Only passive mode; use PASV or EPSV".to_string(),
+            "SIZE" => match meta().filter(std::fs::Metadata::is_file) {
+                Some(meta) => format!("213 {}", meta.len()),
+                None => "550 Not a file".to_string(),
+            },
+            "MDTM" => match meta().and_then(|m| m.modified().ok()) {
+                Some(modified) => format!("213 {}", mdtm(unix_secs(modified))),
+                None => "550 Not found".to_string(),
+            },
+            "REST" => match arg.trim().parse::<u64>() {
+                Ok(offset) => {
+                    self.offset = offset;
+                    format!("350 Restarting at {offset}")
+                }
+                Err(_) => "501 Bad offset".to_string(),
+            },
+            // Transfers finish before the next command is read
+            "ABOR" => "225 No transfer to abort".to_string(),
+            _ => "502 Command not implemented".to_string(),
+        }
+    }
+
+    /// Deleting, creating and renaming; `None` for other commands.
+    fn modify(&mut self, served: &Served, verb: &str, arg: &str) -> Option<String> {
+        if !matches!(
+            verb,
+            "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR" | "RNTO"
+        ) {
+            return None;
+        }
+        if !served.write {
+            return Some("550 This server is read-only".to_string());
+        }
+        if arg.is_empty() {
+            return Some("501 Missing name".to_string());
+        }
+        let path = join_path(&self.cwd, arg);
+        let Some(target) = served.entry(&path) else {
+            return Some("550 Not allowed".to_string());
+        };
+        let done = match verb {
+            "DELE" => std::fs::remove_file(&target).map(|()| "250 Deleted".to_string()),
+            "MKD" | "XMKD" => {
+                std::fs::create_dir(&target).map(|()| format!("257 {} created", quote(&path)))
+            }
+            "RMD" | "XRMD" => std::fs::remove_dir(&target).map(|()| "250 Removed".to_string()),
+            "RNFR" => std::fs::symlink_metadata(&target).map(|_| {
+                self.rename_from = Some(target.clone());
+                "350 Send RNTO".to_string()
+            }),
+            _ => match self.rename_from.take() {
+                Some(from) => std::fs::rename(from, &target).map(|()| "250 Renamed".to_string()),
+                None => return Some("503 Send RNFR first".to_string()),
+            },
+        };
+        Some(match done {
+            Ok(text) => {
+                if verb != "RNFR" {
+                    tracing::info!("ftp {}: {verb} {path}", self.peer);
+                }
+                text
+            }
+            Err(e) => format!("550 {verb} failed: {e}"),
+        })
+    }
+
+    /// Check a transfer can happen before the client connects for it.
+    async fn prepare(
+        &self,
+        served: &Served,
+        verb: &str,
+        arg: &str,
+        offset: u64,
+    ) -> Result<Transfer, String> {
+        match verb {
+            "LIST" | "NLST" => {
+                let path = join_path(&self.cwd, strip_flags(arg));
+                let target = served.existing(&path).ok_or("550 Not found")?;
+                let (dotfiles, write, names_only) = (served.dotfiles, served.write, verb == "NLST");
+                let listing = tokio::task::spawn_blocking(move || {
+                    listing(&target, dotfiles, write, names_only)
+                })
+                .await
+                .map_err(|e| format!("451 List failed: {e}"))?
+                .map_err(|e| format!("450 List failed: {e}"))?;
+                Ok(Transfer::Listing(listing.into_bytes()))
+            }
+            "RETR" => {
+                let path = join_path(&self.cwd, arg);
+                let target = served
+                    .existing(&path)
+                    .filter(|p| p.is_file())
+                    .ok_or("550 Not a file")?;
+                let mut file = tokio::fs::File::open(&target)
+                    .await
+                    .map_err(|e| format!("550 Open failed: {e}"))?;
+                file.seek(SeekFrom::Start(offset))
+                    .await
+                    .map_err(|e| format!("550 Seek failed: {e}"))?;
+                Ok(Transfer::Send(file))
+            }
+            _ => {
+                if !served.write {
+                    return Err("550 This server is read-only".to_string());
+                }
+                if offset > 0 {
+                    return Err("504 Resuming uploads isn't supported".to_string());
+                }
+                let path = join_path(&self.cwd, arg);
+                let target = served
+                    .entry(&path)
+                    .filter(|p| !p.is_dir())
+                    .ok_or("550 Not allowed")?;
+                let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+                    return Err("550 Not allowed".to_string());
+                };
+                let temp = temp_path(dir, &name.to_string_lossy());
+                Ok(Transfer::Receive { temp, target })
+            }
+        }
+    }
+
+    /// Wait for the client on the data port from `PASV` or `EPSV`.
+    async fn accept(
+        &self,
+        served: &Served,
+        listener: TcpListener,
+    ) -> Result<Box<dyn DataStream>, &'static str> {
+        let accepted = tokio::time::timeout(DATA_TIMEOUT, listener.accept()).await;
+        let Ok(Ok((stream, peer))) = accepted else {
+            return Err("425 No data connection");
+        };
+        // Whoever connects first would get the data otherwise
+        if peer.ip() != self.peer.ip() {
+            tracing::warn!("ftp {}: data connection from {peer} refused", self.peer);
+            return Err("425 Data connection from another address");
+        }
+        match &served.tls {
+            Some(config) if self.protected => {
+                let handshake = TlsAcceptor::from(config.clone()).accept(stream);
+                match tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, handshake).await {
+                    Ok(Ok(stream)) => Ok(Box::new(stream)),
+                    _ => Err("425 TLS handshake failed"),
+                }
+            }
+            _ => Ok(Box::new(stream)),
+        }
+    }
+
+    async fn transfer<S: AsyncWrite + Unpin>(
+        &mut self,
+        served: &Served,
+        control: &mut S,
+        verb: &str,
+        arg: &str,
+    ) -> io::Result<()> {
+        let offset = std::mem::take(&mut self.offset);
+        let Some(listener) = self.passive.take() else {
+            return reply(control, "425 Send PASV or EPSV first").await;
+        };
+        let transfer = match self.prepare(served, verb, arg, offset).await {
+            Ok(transfer) => transfer,
+            Err(text) => return reply(control, &text).await,
+        };
+        reply(control, "150 Opening data connection").await?;
+        let mut data = match self.accept(served, listener).await {
+            Ok(data) => data,
+            Err(text) => return reply(control, text).await,
+        };
+        let text = match run(transfer, &mut *data).await {
+            Ok(bytes) => {
+                if verb != "LIST" && verb != "NLST" {
+                    tracing::info!("ftp {}: {verb} {arg} ({bytes} bytes)", self.peer);
+                }
+                "226 Transfer complete".to_string()
+            }
+            Err(e) => format!("426 Transfer failed: {e}"),
+        };
+        reply(control, &text).await
+    }
+}
+
+/// Answer commands until the client quits or asks for TLS.
+async fn control<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut BufReader<S>,
+    session: &mut Session,
+    served: &Served,
+) -> io::Result<Flow> {
+    while let Some(line) = read_command(stream).await {
+        let (verb, arg) = parse(&line);
+        if let Some(flow) = session.dispatch(served, stream, &verb, arg).await? {
+            return Ok(flow);
+        }
+    }
+    Ok(Flow::Quit)
+}
+
+async fn serve_client(stream: TcpStream, peer: SocketAddr, served: &Served) -> io::Result<()> {
+    let mut session = Session::new(peer, stream.local_addr()?.ip());
+    let mut plain = BufReader::new(stream);
+    reply(&mut plain, "220 200 OK FTP server ready").await?;
+    let flow = control(&mut plain, &mut session, served).await?;
+    let (Flow::Secure, Some(config)) = (flow, served.tls.clone()) else {
+        return Ok(());
+    };
+    // Clients wait for the 234 before starting the handshake, so nothing
+    // they sent is left in the buffer
+    let handshake = TlsAcceptor::from(config).accept(plain.into_inner());
+    let Ok(Ok(stream)) = tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, handshake).await else {
+        return Ok(());
+    };
+    session.secure = true;
+    control(&mut BufReader::new(stream), &mut session, served).await?;
+    Ok(())
+}
+
+/// Serve each connection on its own task.
+async fn serve(listener: TcpListener, served: Arc<Served>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("ftp accept error: {e}");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let served = served.clone();
+        let connection = served.stats.connection();
+        tokio::spawn(async move {
+            let _connection = connection;
+            if let Err(e) = serve_client(stream, peer, &served).await {
+                tracing::debug!("ftp {peer}: {e}");
+            }
+        });
+    }
+}
+
+#[tauri::command]
+pub async fn ftp_server_create(
+    options: FtpServerOptions,
+    state: State<'_, FtpState>,
+) -> Result<FtpServerInfo, String> {
+    let root = std::fs::canonicalize(&options.root)
+        .ok()
+        .filter(|r| r.is_dir())
+        .ok_or_else(|| format!("not a folder: {}", options.root))?;
+    let listener = TcpListener::bind((options.host.as_str(), options.port))
+        .await
+        .map_err(|e| format!("bind failed: {e}"))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("local_addr failed: {e}"))?
+        .port();
+    let tls = options
+        .tls
+        .clone()
+        .map(|t| Tls::load(t, port))
+        .transpose()?;
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let info = FtpServerInfo {
+        id,
+        root: options.root,
+        host: options.host,
+        port,
+        write: options.write,
+        dotfiles: options.dotfiles,
+        auth: options.auth.map(Auth::with_token),
+        tls: options.tls,
+    };
+    let served = Arc::new(Served {
+        root,
+        write: info.write,
+        dotfiles: info.dotfiles,
+        auth: info.auth.clone(),
+        tls: tls.map(|t| Arc::new(t.ftp_config())),
+        stats: ServerStats::default(),
+    });
+    let sessions = served.stats.clone();
+    tracing::info!("ftp server {id} on {}:{port} for {}", info.host, info.root);
+    let task = tokio::spawn(serve(listener, served));
+    state.servers.lock().unwrap().insert(
+        id,
+        FtpServer {
+            info: info.clone(),
+            task,
+            stats: sessions,
+        },
+    );
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn ftp_server_close(id: u32, state: State<'_, FtpState>) -> Result<(), String> {
+    let server = state.servers.lock().unwrap().remove(&id);
+    match server {
+        Some(server) => {
+            server.task.abort();
+            Ok(())
+        }
+        None => Err(format!("FTP server {id} not found")),
+    }
+}
+
+#[tauri::command]
+pub async fn ftp_server_list(state: State<'_, FtpState>) -> Result<Vec<FtpServerInfo>, String> {
+    let servers = state.servers.lock().unwrap();
+    let mut infos: Vec<_> = servers.values().map(|s| s.info.clone()).collect();
+    infos.sort_by_key(|i| i.id);
+    Ok(infos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_path() {
+        assert_eq!(join_path("/", ""), "/");
+        assert_eq!(join_path("/", "photos"), "/photos");
+        assert_eq!(join_path("/photos", "2024/a.jpg"), "/photos/2024/a.jpg");
+        assert_eq!(join_path("/photos", "/music"), "/music");
+        assert_eq!(join_path("/photos/2024", ".."), "/photos");
+        assert_eq!(join_path("/photos", "../../../etc/passwd"), "/etc/passwd");
+        assert_eq!(join_path("/photos", "./a//b/"), "/photos/a/b");
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("retr my file.txt\r\n"),
+            ("RETR".to_string(), "my file.txt")
+        );
+        assert_eq!(parse("PASV\r\n"), ("PASV".to_string(), ""));
+        assert_eq!(strip_flags("-la"), "");
+        assert_eq!(strip_flags("-a -l photos"), "photos");
+        assert_eq!(strip_flags("photos"), "photos");
+    }
+
+    #[test]
+    fn test_replies() {
+        // 2024-03-05 14:07:09 UTC
+        let mtime = 1_709_647_629;
+        assert_eq!(
+            list_line("a.jpg", false, 1234, mtime, mtime + 60, false),
+            "-r--r--r-- 1 ftp ftp         1234 Mar  5 14:07 a.jpg"
+        );
+        assert_eq!(
+            list_line("old", true, 0, mtime, mtime + 365 * 86_400, true),
+            "drwxr-xr-x 1 ftp ftp            0 Mar  5  2024 old"
+        );
+        assert_eq!(mdtm(mtime), "20240305140709");
+        let addr: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        assert_eq!(
+            pasv_reply(addr).unwrap(),
+            "227 Entering Passive Mode (192,168,1,20,195,80)"
+        );
+        assert_eq!(pasv_reply("[::1]:21".parse().unwrap()), None);
+        assert_eq!(quote("/say \"hi\""), "\"/say \"\"hi\"\"\"");
+    }
+
+    fn served(root: &Path, write: bool, auth: Option<Auth>) -> Arc<Served> {
+        Arc::new(Served {
+            root: std::fs::canonicalize(root).unwrap(),
+            write,
+            dotfiles: Dotfiles::Deny,
+            auth,
+            tls: None,
+            stats: ServerStats::default(),
+        })
+    }
+
+    /// A client on `served`, after the greeting.
+    async fn connect(served: Arc<Served>) -> BufReader<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, served));
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        assert!(read_command(&mut client).await.unwrap().starts_with("220"));
+        client
+    }
+
+    async fn send(client: &mut BufReader<TcpStream>, line: &str) -> String {
+        reply(client, line).await.unwrap();
+        read_command(client).await.unwrap().trim_end().to_string()
+    }
+
+    /// Send `command` over a fresh passive connection, returning what came
+    /// back on it and the final reply.
+    async fn data(
+        client: &mut BufReader<TcpStream>,
+        command: &str,
+        upload: &[u8],
+    ) -> (Vec<u8>, String) {
+        let pasv = send(client, "EPSV").await;
+        let port: u16 = pasv.split('|').nth(3).unwrap().parse().unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let opened = send(client, command).await;
+        if !opened.starts_with("150") {
+            return (Vec::new(), opened);
+        }
+        let mut received = Vec::new();
+        if upload.is_empty() {
+            stream.read_to_end(&mut received).await.unwrap();
+        } else {
+            stream.write_all(upload).await.unwrap();
+            stream.shutdown().await.unwrap();
+        }
+        let done = read_command(client).await.unwrap().trim_end().to_string();
+        (received, done)
+    }
+
+    #[tokio::test]
+    async fn test_read_only_session() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("photos")).unwrap();
+        std::fs::write(tmp.path().join("photos/a.jpg"), "jpeg bytes").unwrap();
+        std::fs::write(tmp.path().join(".env"), "SECRET=1").unwrap();
+        let auth = Auth::Basic {
+            username: "tv".to_string(),
+            password: "pw".to_string(),
+        };
+        let mut client = connect(served(tmp.path(), false, Some(auth))).await;
+
+        assert!(send(&mut client, "LIST").await.starts_with("530"));
+        assert!(send(&mut client, "USER tv").await.starts_with("331"));
+        assert!(send(&mut client, "PASS nope").await.starts_with("530"));
+        send(&mut client, "USER tv").await;
+        assert!(send(&mut client, "PASS pw").await.starts_with("230"));
+
+        let (listing, done) = data(&mut client, "NLST", b"").await;
+        assert_eq!(String::from_utf8(listing).unwrap(), "photos\r\n");
+        assert!(done.starts_with("226"));
+        assert!(send(&mut client, "CWD photos").await.starts_with("250"));
+        assert_eq!(
+            send(&mut client, "PWD").await,
+            "257 \"/photos\" is the current folder"
+        );
+        assert_eq!(send(&mut client, "SIZE a.jpg").await, "213 10");
+        send(&mut client, "REST 5").await;
+        let (file, done) = data(&mut client, "RETR a.jpg", b"").await;
+        assert_eq!(file, b"bytes");
+        assert!(done.starts_with("226"));
+
+        // Can't climb out, see denied dotfiles or change anything
+        assert!(send(&mut client, "CWD ../..").await.starts_with("250"));
+        assert_eq!(
+            send(&mut client, "PWD").await,
+            "257 \"/\" is the current folder"
+        );
+        assert!(send(&mut client, "SIZE .env").await.starts_with("550"));
+        assert!(send(&mut client, "DELE photos/a.jpg")
+            .await
+            .starts_with("550"));
+        let (_, refused) = data(&mut client, "STOR new.txt", b"").await;
+        assert!(refused.starts_with("550"));
+        assert!(tmp.path().join("photos/a.jpg").exists());
+        assert!(send(&mut client, "QUIT").await.starts_with("221"));
+    }
+
+    #[tokio::test]
+    async fn test_active_sessions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let served = served(tmp.path(), false, None);
+        let stats = served.stats.clone();
+        let mut client = connect(served).await;
+        assert_eq!(stats.active(), 1);
+        assert!(send(&mut client, "QUIT").await.starts_with("221"));
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        // The session task may still be winding down
+        for _ in 0..100 {
+            if stats.active() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(stats.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_writable_session() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut client = connect(served(tmp.path(), true, None)).await;
+        send(&mut client, "USER anonymous").await;
+        assert!(send(&mut client, "PASS guest@").await.starts_with("230"));
+
+        assert_eq!(
+            send(&mut client, "MKD scans").await,
+            "257 \"/scans\" created"
+        );
+        let (_, done) = data(&mut client, "STOR scans/page.pdf", b"%PDF").await;
+        assert!(done.starts_with("226"), "{done}");
+        assert_eq!(
+            std::fs::read(tmp.path().join("scans/page.pdf")).unwrap(),
+            b"%PDF"
+        );
+        assert_eq!(
+            std::fs::read_dir(tmp.path().join("scans")).unwrap().count(),
+            1
+        );
+
+        assert!(send(&mut client, "RNFR scans/page.pdf")
+            .await
+            .starts_with("350"));
+        assert!(send(&mut client, "RNTO scans/1.pdf")
+            .await
+            .starts_with("250"));
+        assert!(tmp.path().join("scans/1.pdf").exists());
+        assert!(send(&mut client, "MKD .git").await.starts_with("550"));
+        assert!(send(&mut client, "MKD ../outside").await.starts_with("257"));
+        assert!(tmp.path().join("outside").is_dir());
+        assert!(send(&mut client, "RMD scans").await.starts_with("550"));
+        assert!(send(&mut client, "DELE scans/1.pdf")
+            .await
+            .starts_with("250"));
+        assert!(send(&mut client, "RMD scans").await.starts_with("250"));
+    }
+}
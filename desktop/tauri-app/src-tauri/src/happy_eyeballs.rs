@@ -0,0 +1,122 @@
+//! Outbound connections to host names with both IPv4 and IPv6 addresses,
+//! raced as in RFC 8305 so a broken IPv6 route costs a fraction of a
+//! second instead of a full connect timeout.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use super::dns::{self, Family};
+
+/// How long an attempt gets before the next address is tried alongside it.
+pub const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+const MIN_ATTEMPT_DELAY: Duration = Duration::from_millis(10);
+const MAX_ATTEMPT_DELAY: Duration = Duration::from_secs(2);
+
+/// `delay_ms` within the limits RFC 8305 suggests, or the default.
+pub fn attempt_delay(delay_ms: Option<u64>) -> Duration {
+    delay_ms.map_or(DEFAULT_ATTEMPT_DELAY, |ms| {
+        Duration::from_millis(ms).clamp(MIN_ATTEMPT_DELAY, MAX_ATTEMPT_DELAY)
+    })
+}
+
+/// IPv6 first, then alternating families, each keeping its own order.
+fn interleave(addrs: &[IpAddr]) -> Vec<IpAddr> {
+    let (v6, v4): (Vec<IpAddr>, Vec<IpAddr>) = addrs.iter().partition(|ip| ip.is_ipv6());
+    let mut ordered = Vec::with_capacity(addrs.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connect to the first of `addrs` that answers, starting the next attempt
+/// whenever one fails or has been waiting for `delay`.
+pub async fn connect_addrs(addrs: &[SocketAddr], delay: Duration) -> io::Result<TcpStream> {
+    let mut pending = addrs.iter().copied();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = pending.next() {
+            attempts.spawn(TcpStream::connect(addr));
+        } else if attempts.is_empty() {
+            return Err(last_error
+                .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses")));
+        }
+        let next = if pending.len() > 0 {
+            tokio::time::timeout(delay, attempts.join_next()).await
+        } else {
+            Ok(attempts.join_next().await)
+        };
+        match next {
+            // Dropping the set aborts the other attempts
+            Ok(Some(Ok(Ok(stream)))) => return Ok(stream),
+            Ok(Some(Ok(Err(e)))) => last_error = Some(e),
+            Ok(Some(Err(e))) => last_error = Some(io::Error::other(e)),
+            // Timed out, or nothing in flight
+            Err(_) | Ok(None) => {}
+        }
+    }
+}
+
+/// Resolve `host` and connect to it on `port`.
+pub async fn connect(host: &str, port: u16, delay: Duration) -> io::Result<TcpStream> {
+    let resolved = dns::resolve(host, Family::Any)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+    let ips: Vec<IpAddr> = resolved
+        .iter()
+        .filter_map(|a| a.address.parse().ok())
+        .collect();
+    let addrs: Vec<SocketAddr> = interleave(&ips)
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+    connect_addrs(&addrs, delay).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_interleave() {
+        let ips: Vec<IpAddr> = ["1.1.1.1", "2.2.2.2", "3.3.3.3", "::1", "::2"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = interleave(&ips).iter().map(ToString::to_string).collect();
+        assert_eq!(ordered, ["::1", "1.1.1.1", "::2", "2.2.2.2", "3.3.3.3"]);
+        assert_eq!(attempt_delay(None), DEFAULT_ATTEMPT_DELAY);
+        assert_eq!(attempt_delay(Some(0)), MIN_ATTEMPT_DELAY);
+        assert_eq!(attempt_delay(Some(60_000)), MAX_ATTEMPT_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_connect_addrs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let good = listener.local_addr().unwrap();
+        // TEST-NET-1 never answers, so the good address wins after the delay
+        let blackhole: SocketAddr = "192.0.2.1:9".parse().unwrap();
+        // Nothing listens here, so it fails at once
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = closed.local_addr().unwrap();
+        drop(closed);
+
+        let stream = connect_addrs(&[blackhole, refused, good], Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), good);
+        assert!(connect_addrs(&[refused], Duration::from_millis(20))
+            .await
+            .is_err());
+        assert!(connect_addrs(&[], DEFAULT_ATTEMPT_DELAY).await.is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Export what `inspector` captured as a HAR 1.2 file, which browsers'
+//! developer tools and most HTTP debugging tools can open. Bodies are as
+//! captured, so they may be cut short; such entries say so in a comment.
+
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tauri::State;
+
+use ok200_common::http::{percent_decode, reason};
+
+use super::http::HttpState;
+use super::inspector::{CapturedBody, Exchange};
+use super::logging;
+
+/// Which captures to export.
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarFilter {
+    /// Every capturing server when absent.
+    pub server_id: Option<u32>,
+    /// Unix milliseconds; earlier captures are left out.
+    pub since: Option<u64>,
+}
+
+/// `2009-07-24T19:20:30.045Z`
+fn iso_time(ms: u64) -> String {
+    let secs = logging::utc_timestamp(ms / 1000);
+    format!("{}.{:03}Z", secs.trim_end_matches('Z'), ms % 1000)
+}
+
+fn name_values(pairs: &[(String, String)]) -> Vec<Value> {
+    pairs
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn query_string(path: &str) -> Vec<Value> {
+    let Some((_, query)) = path.split_once('?') else {
+        return Vec::new();
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| percent_decode(&s.replace('+', " ")).unwrap_or_default();
+            json!({ "name": decode(name), "value": decode(value) })
+        })
+        .collect()
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn truncated_comment(body: Option<&CapturedBody>) -> &'static str {
+    if body.is_some_and(|b| b.truncated) {
+        "truncated by the inspector"
+    } else {
+        ""
+    }
+}
+
+/// One HAR entry for `exchange`, captured on the server at `origin`.
+fn entry(server_id: u32, origin: &str, exchange: &Exchange) -> Value {
+    let url = match header(&exchange.request_headers, "Host") {
+        Some(host) => {
+            let scheme = origin.split_once("://").map_or("http", |(s, _)| s);
+            format!("{scheme}://{host}{}", exchange.path)
+        }
+        None => format!("{origin}{}", exchange.path),
+    };
+    let mut request = json!({
+        "method": exchange.method,
+        "url": url,
+        "httpVersion": "HTTP/1.1",
+        "cookies": [],
+        "headers": name_values(&exchange.request_headers),
+        "queryString": query_string(&exchange.path),
+        "headersSize": -1,
+        "bodySize": exchange.request_body.as_ref().map_or(0, |b| b.text.len()),
+    });
+    if let Some(body) = &exchange.request_body {
+        let mime = header(&exchange.request_headers, "Content-Type").unwrap_or_default();
+        request["postData"] = json!({
+            "mimeType": mime,
+            "text": body.text,
+            "comment": truncated_comment(Some(body)),
+        });
+    }
+    let mut content = json!({
+        "size": exchange.bytes,
+        "mimeType": header(&exchange.response_headers, "Content-Type").unwrap_or_default(),
+        "comment": truncated_comment(exchange.response_body.as_ref()),
+    });
+    if let Some(body) = &exchange.response_body {
+        content["text"] = body.text.clone().into();
+        if let Some(encoding) = body.encoding {
+            content["encoding"] = encoding.into();
+        }
+    }
+    json!({
+        "startedDateTime": iso_time(exchange.time_ms),
+        "time": exchange.duration_ms,
+        "request": request,
+        "response": {
+            "status": exchange.status,
+            "statusText": reason(exchange.status),
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": name_values(&exchange.response_headers),
+            "content": content,
+            "redirectURL": header(&exchange.response_headers, "Location").unwrap_or_default(),
+            "headersSize": -1,
+            "bodySize": exchange.bytes,
+        },
+        "cache": {},
+        "timings": { "send": 0, "wait": exchange.duration_ms, "receive": 0 },
+        "comment": format!("server {server_id}"),
+        "_requestId": exchange.request_id,
+    })
+}
+
+/// A HAR log of `captures`, given as `(server id, origin, exchange)`,
+/// in the order they arrived.
+fn to_har(mut captures: Vec<(u32, String, Exchange)>) -> Value {
+    captures.sort_by_key(|(_, _, e)| e.time_ms);
+    let entries: Vec<Value> = captures
+        .iter()
+        .map(|(id, origin, exchange)| entry(*id, origin, exchange))
+        .collect();
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": { "name": "200 OK", "version": env!("CARGO_PKG_VERSION") },
+            "pages": [],
+            "entries": entries,
+        }
+    })
+}
+
+/// Write captured exchanges to `path` as HAR. Returns how many were
+/// written.
+#[tauri::command]
+pub async fn inspector_export_har(
+    path: String,
+    filter: Option<HarFilter>,
+    state: State<'_, HttpState>,
+) -> Result<usize, String> {
+    let filter = filter.unwrap_or_default();
+    let mut inspectors = state.inspectors();
+    if let Some(id) = filter.server_id {
+        // For its error when the server is gone or not capturing
+        state.inspector(id)?;
+        inspectors.retain(|(server_id, _, _)| *server_id == id);
+    }
+    let since = filter.since.unwrap_or(0);
+    let captures: Vec<_> = inspectors
+        .into_iter()
+        .flat_map(|(id, origin, inspector)| {
+            inspector
+                .exchanges()
+                .into_iter()
+                .filter(|e| e.time_ms >= since)
+                .map(move |e| (id, origin.clone(), e))
+        })
+        .collect();
+    let count = captures.len();
+    let json = serde_json::to_string_pretty(&to_har(captures)).map_err(|e| e.to_string())?;
+    let path = Path::new(&path);
+    std::fs::write(path, json).map_err(|e| format!("write {}: {e}", path.display()))?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(time_ms: u64, path: &str, host: Option<&str>) -> Exchange {
+        let mut request_headers = vec![("Accept".to_string(), "*/*".to_string())];
+        if let Some(host) = host {
+            request_headers.push(("Host".to_string(), host.to_string()));
+        }
+        Exchange {
+            id: 1,
+            request_id: "4.2".to_string(),
+            time_ms,
+            remote_address: "127.0.0.1:5000".to_string(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            request_headers,
+            request_body: None,
+            status: 200,
+            response_headers: vec![("Content-Type".to_string(), "text/css".to_string())],
+            response_body: Some(CapturedBody::new(b"body{}", 4, true)),
+            bytes: 6,
+            duration_ms: 3,
+        }
+    }
+
+    #[test]
+    fn test_to_har() {
+        let har = to_har(vec![
+            (
+                2,
+                "https://127.0.0.1:8443".to_string(),
+                exchange(
+                    1_700_000_000_500,
+                    "/b.css?v=1&q=a+b",
+                    Some("site.local:8443"),
+                ),
+            ),
+            (
+                1,
+                "http://127.0.0.1:8080".to_string(),
+                exchange(1_700_000_000_045, "/a.css", None),
+            ),
+        ]);
+        assert_eq!(har["log"]["version"], "1.2");
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let first = &entries[0];
+        assert_eq!(first["startedDateTime"], "2023-11-14T22:13:20.045Z");
+        assert_eq!(first["request"]["url"], "http://127.0.0.1:8080/a.css");
+        assert_eq!(first["response"]["statusText"], "OK");
+        let content = &first["response"]["content"];
+        assert_eq!(content["mimeType"], "text/css");
+        assert_eq!(content["text"], "body");
+        assert_eq!(content["comment"], "truncated by the inspector");
+
+        let second = &entries[1];
+        assert_eq!(
+            second["request"]["url"],
+            "https://site.local:8443/b.css?v=1&q=a+b"
+        );
+        assert_eq!(
+            second["request"]["queryString"],
+            json!([{ "name": "v", "value": "1" }, { "name": "q", "value": "a b" }])
+        );
+        assert_eq!(second["comment"], "server 2");
+        assert_eq!(second["_requestId"], "4.2");
+    }
+}
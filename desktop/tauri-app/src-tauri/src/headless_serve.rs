@@ -0,0 +1,155 @@
+//! `--serve <dir> [--port N] [--host H] [--spa]`: serve a folder from the
+//! terminal with no window or tray, logging requests to stdout until Ctrl-C.
+
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use super::http_server;
+
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_HOST: &str = "127.0.0.1";
+
+#[derive(Debug, PartialEq, Eq)]
+struct ServeArgs {
+    root: PathBuf,
+    port: u16,
+    host: String,
+    /// Answer client-side routes with `index.html`.
+    spa: bool,
+}
+
+/// Parse the arguments following `--serve`.
+fn parse_args(args: &[String]) -> Result<ServeArgs, String> {
+    let mut root = None;
+    let mut port = DEFAULT_PORT;
+    let mut host = DEFAULT_HOST.to_string();
+    let mut spa = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--port" => {
+                let value = iter.next().ok_or("--port needs a value")?;
+                port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port: {value}"))?;
+            }
+            "--host" => {
+                iter.next()
+                    .ok_or("--host needs a value")?
+                    .clone_into(&mut host);
+            }
+            "--spa" => spa = true,
+            // Handled by `ok200_common::portable_dir`
+            "--portable" => {}
+            flag if flag.starts_with('-') => return Err(format!("unknown option: {flag}")),
+            dir if root.is_none() => root = Some(PathBuf::from(dir)),
+            extra => return Err(format!("unexpected argument: {extra}")),
+        }
+    }
+    let root = root.ok_or("missing folder to serve")?;
+    if !root.is_dir() {
+        return Err(format!("not a folder: {}", root.display()));
+    }
+    Ok(ServeArgs {
+        root,
+        port,
+        host,
+        spa,
+    })
+}
+
+async fn serve(args: ServeArgs) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::bind((args.host.as_str(), args.port))
+        .await
+        .map_err(|e| format!("bind {}:{} failed: {e}", args.host, args.port))?;
+    let addr = listener
+        .local_addr()
+        .map_err(|e| format!("local_addr failed: {e}"))?;
+    println!("Serving {} at http://{addr}/", args.root.display());
+    println!("Press Ctrl-C to stop");
+
+    let server = http_server::serve(
+        listener,
+        args.root,
+        Arc::new(RwLock::new(http_server::ServeOptions {
+            spa: args.spa,
+            ..http_server::ServeOptions::default()
+        })),
+        |entry| println!("{entry}"),
+    );
+    tokio::select! {
+        result = server => result.map_err(|e| format!("server failed: {e}")),
+        _ = tokio::signal::ctrl_c() => {
+            println!("Stopped");
+            Ok(())
+        }
+    }
+}
+
+/// Entry point for `--serve`; never returns.
+pub fn run(args: &[String]) -> ! {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("serve: {e}");
+            eprintln!("serve: usage: --serve <dir> [--port N] [--host H] [--spa]");
+            std::process::exit(2);
+        }
+    };
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("serve: failed to start runtime: {e}");
+            std::process::exit(1);
+        }
+    };
+    match runtime.block_on(serve(args)) {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("serve: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_str().unwrap();
+
+        let parsed = parse_args(&args(&[
+            dir, "--port", "3000", "--host", "0.0.0.0", "--spa",
+        ]))
+        .unwrap();
+        assert_eq!(
+            parsed,
+            ServeArgs {
+                root: PathBuf::from(dir),
+                port: 3000,
+                host: "0.0.0.0".to_string(),
+                spa: true,
+            }
+        );
+        let defaults = parse_args(&args(&[dir])).unwrap();
+        assert_eq!((defaults.port, defaults.host.as_str()), (8080, "127.0.0.1"));
+        assert!(!defaults.spa);
+
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&[dir, "--port"])).is_err());
+        assert!(parse_args(&args(&[dir, "--port", "http"])).is_err());
+        assert!(parse_args(&args(&[dir, "--portable"])).is_ok());
+        assert!(parse_args(&args(&[dir, "--verbose"])).is_err());
+        assert!(parse_args(&args(&["/no/such/folder"])).is_err());
+    }
+}
@@ -1,9 +1,12 @@
+use std::future::Future;
+use std::time::Duration;
+
 use serde::Serialize;
 use tauri::Manager;
 use tauri_plugin_updater::UpdaterExt;
 
 /// Result written to `update-check-result.json` in the config directory.
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct UpdateCheckResult {
     available: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -14,20 +17,53 @@ struct UpdateCheckResult {
     body: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
+    /// Set when an update exists but the user skipped or snoozed it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deferred: Option<&'static str>,
+    installed: bool,
+    /// Retries spent on transient network failures across check and download.
+    retries_attempted: u32,
 }
 
 const RESULT_FILENAME: &str = "update-check-result.json";
 
+/// First retry delay; doubles on each further attempt up to `MAX_BACKOFF`.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_mins(1);
+
+/// Process exit codes, so callers can act without reading the result file.
+pub const EXIT_UP_TO_DATE: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_UPDATE_AVAILABLE: i32 = 10;
+pub const EXIT_INSTALLED: i32 = 20;
+
+impl UpdateCheckResult {
+    fn exit_code(&self) -> i32 {
+        if self.error.is_some() {
+            EXIT_ERROR
+        } else if self.installed {
+            EXIT_INSTALLED
+        } else if self.available {
+            EXIT_UPDATE_AVAILABLE
+        } else {
+            EXIT_UP_TO_DATE
+        }
+    }
+}
+
 /// Run a headless update check (and optionally auto-install).
 /// Builds a minimal Tauri app with only the updater plugin,
-/// performs the check, writes the result to a JSON file, then exits.
+/// performs the check, writes the result to a JSON file and stdout, then
+/// exits with one of the `EXIT_*` codes.
 pub fn run(auto_update: bool, context: tauri::Context) {
     let app = tauri::Builder::default()
         .setup(move |app| {
             #[cfg(desktop)]
             {
+                let channel = super::load_settings(app.handle()).channel;
                 let mut builder = tauri_plugin_updater::Builder::new()
-                    .header("X-Check-Reason", "host")?;
+                    .header("X-Check-Reason", "host")?
+                    .header("X-Update-Channel", channel.as_str())?;
                 if let Some(cfu_id) = ok200_common::get_or_create_cfu_id() {
                     builder = builder.header("X-CFU-Id", &cfu_id)?;
                 }
@@ -41,23 +77,22 @@ pub fn run(auto_update: bool, context: tauri::Context) {
 
             let handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                do_update_check(&handle, auto_update).await;
-                handle.exit(0);
+                let code = do_update_check(&handle, auto_update).await;
+                handle.exit(code);
             });
 
             Ok(())
         })
         .build(context)
         .unwrap_or_else(|e| {
-            eprintln!("headless-updater: failed to init: {e}");
-            write_result_to_shared_dir(&UpdateCheckResult {
-                available: false,
-                version: None,
-                current_version: None,
-                body: None,
+            tracing::error!("failed to init: {e}");
+            let result = UpdateCheckResult {
                 error: Some(format!("Failed to initialize: {e}")),
-            });
-            std::process::exit(1);
+                ..Default::default()
+            };
+            write_result_to_shared_dir(&result);
+            print_result(&result);
+            std::process::exit(EXIT_ERROR);
         });
 
     app.run(|_app_handle, event| {
@@ -69,69 +104,97 @@ pub fn run(auto_update: bool, context: tauri::Context) {
     });
 }
 
-async fn do_update_check(handle: &tauri::AppHandle, auto_update: bool) {
+async fn do_update_check(handle: &tauri::AppHandle, auto_update: bool) -> i32 {
     let result = check_and_maybe_install(handle, auto_update).await;
     write_result(handle, &result);
+    print_result(&result);
     if result.error.is_some() {
-        eprintln!(
-            "headless-updater: error: {}",
-            result.error.as_deref().unwrap_or("unknown")
+        tracing::error!("error: {}", result.error.as_deref().unwrap_or("unknown"));
+    } else if result.installed {
+        tracing::info!(
+            "installed {}",
+            result.version.as_deref().unwrap_or("unknown")
         );
     } else if result.available {
-        eprintln!(
-            "headless-updater: update available: {}",
+        tracing::info!(
+            "update available: {}",
             result.version.as_deref().unwrap_or("unknown")
         );
     } else {
-        eprintln!("headless-updater: up to date");
+        tracing::info!("up to date");
     }
+    result.exit_code()
 }
 
 async fn check_and_maybe_install(
     handle: &tauri::AppHandle,
     auto_update: bool,
 ) -> UpdateCheckResult {
-    let updater = match handle.updater() {
+    let settings = super::load_settings(handle);
+    super::logging::set_level(settings.log_level);
+    let mut builder = handle
+        .updater_builder()
+        .timeout(Duration::from_secs(settings.update_check_timeout_secs));
+    if let Some(proxy) = settings.updater_proxy() {
+        builder = builder.proxy(proxy);
+    }
+    let updater = match builder
+        .header("X-Update-Channel", settings.channel.as_str())
+        .and_then(tauri_plugin_updater::UpdaterBuilder::build)
+    {
         Ok(u) => u,
         Err(e) => {
             return UpdateCheckResult {
-                available: false,
-                version: None,
-                current_version: None,
-                body: None,
                 error: Some(format!("Failed to create updater: {e}")),
+                ..Default::default()
             };
         }
     };
 
-    let update = match updater.check().await {
+    let mut retries_attempted = 0;
+    let checked = with_retries(
+        "check",
+        settings.update_retries,
+        &mut retries_attempted,
+        || updater.check(),
+    )
+    .await;
+    let mut update = match checked {
         Ok(Some(update)) => update,
         Ok(None) => {
             return UpdateCheckResult {
-                available: false,
-                version: None,
-                current_version: None,
-                body: None,
-                error: None,
-            };
+                retries_attempted,
+                ..Default::default()
+            }
         }
         Err(e) => {
             return UpdateCheckResult {
-                available: false,
-                version: None,
-                current_version: None,
-                body: None,
                 error: Some(format!("Update check failed: {e}")),
+                retries_attempted,
+                ..Default::default()
             };
         }
     };
 
+    // A skipped or snoozed update is reported but neither offered nor installed
+    if let Some(reason) = super::updates::current_deferral(&settings, &update.version) {
+        tracing::info!("update {} {reason} by user", update.version);
+        return UpdateCheckResult {
+            version: Some(update.version.clone()),
+            current_version: Some(update.current_version.clone()),
+            deferred: Some(reason),
+            retries_attempted,
+            ..Default::default()
+        };
+    }
+
     let result = UpdateCheckResult {
         available: true,
         version: Some(update.version.clone()),
         current_version: Some(update.current_version.clone()),
         body: update.body.clone(),
-        error: None,
+        retries_attempted,
+        ..Default::default()
     };
 
     if !auto_update {
@@ -141,51 +204,170 @@ async fn check_and_maybe_install(
     // Write interim result before download (in case install kills the process on Windows)
     write_result(handle, &result);
 
-    eprintln!(
-        "headless-updater: downloading update {}...",
-        update.version
-    );
-    if let Err(e) = update
-        .download_and_install(
-            |chunk_len, content_len| {
-                eprintln!("headless-updater: download progress: +{chunk_len} / {content_len:?}");
-            },
-            || {
-                eprintln!("headless-updater: download complete, installing...");
-            },
-        )
-        .await
-    {
+    tracing::info!("downloading update {}...", update.version);
+    // The check timeout is too short for a full bundle download
+    update.timeout = Some(Duration::from_secs(settings.update_download_timeout_secs));
+    let installed = with_retries(
+        "download",
+        settings.update_retries,
+        &mut retries_attempted,
+        || {
+            update.download_and_install(
+                |chunk_len, content_len| {
+                    tracing::debug!("download progress: +{chunk_len} / {content_len:?}");
+                },
+                || {
+                    tracing::info!("download complete, installing...");
+                },
+            )
+        },
+    )
+    .await;
+    if let Err(e) = installed {
         return UpdateCheckResult {
-            available: true,
-            version: Some(result.version.unwrap_or_default()),
-            current_version: result.current_version,
-            body: result.body,
             error: Some(format!("Install failed: {e}")),
+            retries_attempted,
+            ..result
         };
     }
 
-    eprintln!("headless-updater: install complete, restarting...");
-    handle.restart();
+    tracing::info!("install complete");
+    if super::notifications::enabled(&settings, super::notifications::Kind::UpdateInstalled) {
+        super::notifications::show(
+            "200 OK updated",
+            &format!("Version {} was installed in the background", update.version),
+        );
+    }
+    UpdateCheckResult {
+        installed: true,
+        retries_attempted,
+        ..result
+    }
+}
+
+/// Network failures worth retrying; anything else (bad signature, missing
+/// platform, install errors) fails immediately.
+fn is_transient(e: &tauri_plugin_updater::Error) -> bool {
+    matches!(
+        e,
+        tauri_plugin_updater::Error::Reqwest(_)
+            | tauri_plugin_updater::Error::Network(_)
+            | tauri_plugin_updater::Error::ReleaseNotFound
+    )
+}
+
+fn backoff_delay(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(2u32.saturating_pow(attempt))
+        .map_or(MAX_BACKOFF, |d| d.min(MAX_BACKOFF))
+}
+
+/// Run `op`, retrying transient failures up to `retries` times with
+/// exponential backoff. Each retry is added to `attempted`.
+async fn with_retries<T, F, Fut>(
+    what: &str,
+    retries: u32,
+    attempted: &mut u32,
+    mut op: F,
+) -> Result<T, tauri_plugin_updater::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, tauri_plugin_updater::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                let delay = backoff_delay(attempt);
+                tracing::warn!("{what} failed ({e}), retrying in {}s", delay.as_secs());
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                *attempted += 1;
+            }
+            result => return result,
+        }
+    }
 }
 
 fn write_result(_handle: &tauri::AppHandle, result: &UpdateCheckResult) {
     write_result_to_shared_dir(result);
 }
 
+/// Print the result as a single JSON line on stdout for scripts.
+fn print_result(result: &UpdateCheckResult) {
+    if let Ok(json) = serde_json::to_string(result) {
+        println!("{json}");
+    }
+}
+
 /// Write result to the shared config directory that the native host can also read.
 fn write_result_to_shared_dir(result: &UpdateCheckResult) {
-    let dir = dirs::config_dir().map(|d| d.join("ok200-native"));
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    if let Err(e) = ok200_common::shared_state::update(|s| s.last_update_check = Some(now)) {
+        tracing::warn!("failed to update shared state: {e}");
+    }
+    let dir = ok200_common::native_dir();
     if let Some(dir) = dir {
         std::fs::create_dir_all(&dir).ok();
         let path = dir.join(RESULT_FILENAME);
         if let Ok(json) = serde_json::to_string_pretty(result) {
             if let Err(e) = std::fs::write(&path, json) {
-                eprintln!(
-                    "headless-updater: failed to write result to {}: {e}",
-                    path.display()
-                );
+                tracing::error!("failed to write result to {}: {e}", path.display());
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(UpdateCheckResult::default().exit_code(), EXIT_UP_TO_DATE);
+        let available = UpdateCheckResult {
+            available: true,
+            ..Default::default()
+        };
+        assert_eq!(available.exit_code(), EXIT_UPDATE_AVAILABLE);
+        let installed = UpdateCheckResult {
+            installed: true,
+            ..available
+        };
+        assert_eq!(installed.exit_code(), EXIT_INSTALLED);
+        let failed = UpdateCheckResult {
+            error: Some("boom".to_string()),
+            ..installed
+        };
+        assert_eq!(failed.exit_code(), EXIT_ERROR);
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(0), Duration::from_secs(2));
+        assert_eq!(backoff_delay(1), Duration::from_secs(4));
+        assert_eq!(backoff_delay(4), Duration::from_secs(32));
+        assert_eq!(backoff_delay(5), MAX_BACKOFF);
+        assert_eq!(backoff_delay(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        use tauri_plugin_updater::Error;
+        assert!(is_transient(&Error::Network("reset".to_string())));
+        assert!(is_transient(&Error::ReleaseNotFound));
+        assert!(!is_transient(&Error::UnsupportedOs));
+    }
+
+    #[test]
+    fn test_deferred_update_is_not_available() {
+        let deferred = UpdateCheckResult {
+            version: Some("2.0.0".to_string()),
+            deferred: Some("skipped"),
+            ..Default::default()
+        };
+        assert_eq!(deferred.exit_code(), EXIT_UP_TO_DATE);
+    }
+}
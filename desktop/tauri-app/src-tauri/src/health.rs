@@ -0,0 +1,156 @@
+//! Health checks for `http_server`, so monitors and scripts can tell a
+//! share is up without fetching real content. `<prefix>/health` answers
+//! `ok` (or 503 when the folder has gone away) and `<prefix>/status` the
+//! same as JSON, with uptime and request counts. Neither needs `auth`, and
+//! neither reveals the folder's path.
+
+use std::path::Path;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use super::metrics::ServerStats;
+
+pub const DEFAULT_PREFIX: &str = "/_ok200";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HealthOptions {
+    /// Where the endpoints live, e.g. `/_ok200` for `/_ok200/health`.
+    pub prefix: String,
+}
+
+impl Default for HealthOptions {
+    fn default() -> Self {
+        Self {
+            prefix: DEFAULT_PREFIX.to_string(),
+        }
+    }
+}
+
+impl HealthOptions {
+    /// Check that `prefix` is a plain absolute path like `/_ok200`.
+    pub fn check(&self) -> Result<(), String> {
+        let prefix = &self.prefix;
+        let valid = prefix.len() > 1
+            && prefix.starts_with('/')
+            && !prefix.ends_with('/')
+            && !prefix.contains(['?', '#', '%'])
+            && !prefix.chars().any(|c| c.is_control() || c.is_whitespace());
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("invalid health prefix: {prefix:?}"))
+        }
+    }
+}
+
+/// Health checks turned on for a server, and when it started.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Health {
+    pub options: HealthOptions,
+    started: Instant,
+}
+
+/// Body of `<prefix>/status`.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    /// `ok`, or `unavailable` when the served folder can't be read.
+    pub status: &'static str,
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    pub requests: u64,
+    pub active_connections: u64,
+}
+
+/// Which endpoint a request is for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Health,
+    Status,
+}
+
+impl Health {
+    pub fn new(options: HealthOptions) -> Self {
+        Self {
+            options,
+            started: Instant::now(),
+        }
+    }
+
+    /// The endpoint `target` asks for, if any. The query is ignored.
+    pub fn endpoint(&self, target: &str) -> Option<Endpoint> {
+        let path = target.split(['?', '#']).next().unwrap_or_default();
+        match path.strip_prefix(self.options.prefix.as_str())? {
+            "/health" => Some(Endpoint::Health),
+            "/status" => Some(Endpoint::Status),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self, root: &Path, stats: &ServerStats) -> StatusReport {
+        StatusReport {
+            status: if root.is_dir() { "ok" } else { "unavailable" },
+            version: env!("CARGO_PKG_VERSION"),
+            uptime_secs: self.started.elapsed().as_secs(),
+            requests: stats.requests(),
+            active_connections: stats.active(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        let health = Health::new(HealthOptions::default());
+        assert_eq!(health.endpoint("/_ok200/health"), Some(Endpoint::Health));
+        assert_eq!(
+            health.endpoint("/_ok200/status?x=1"),
+            Some(Endpoint::Status)
+        );
+        assert_eq!(health.endpoint("/_ok200/health/"), None);
+        assert_eq!(health.endpoint("/_ok200x/health"), None);
+        assert_eq!(health.endpoint("/health"), None);
+
+        let custom = Health::new(HealthOptions {
+            prefix: "/internal/up".to_string(),
+        });
+        assert_eq!(
+            custom.endpoint("/internal/up/health"),
+            Some(Endpoint::Health)
+        );
+        assert_eq!(custom.endpoint("/_ok200/health"), None);
+    }
+
+    #[test]
+    fn test_check_prefix() {
+        let check = |prefix: &str| {
+            HealthOptions {
+                prefix: prefix.to_string(),
+            }
+            .check()
+        };
+        assert!(check("/_ok200").is_ok());
+        assert!(check("/a/b").is_ok());
+        for bad in ["", "/", "_ok200", "/x/", "/x?y", "/a b", "/%2e"] {
+            assert!(check(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_status() {
+        let tmp = tempfile::tempdir().unwrap();
+        let health = Health::new(HealthOptions::default());
+        let stats = ServerStats::default();
+        let _connection = stats.connection();
+        let report = health.status(tmp.path(), &stats);
+        assert_eq!(report.status, "ok");
+        assert_eq!((report.requests, report.active_connections), (0, 1));
+        let gone = health.status(&tmp.path().join("missing"), &stats);
+        assert_eq!(gone.status, "unavailable");
+    }
+}
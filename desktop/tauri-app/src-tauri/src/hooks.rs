@@ -0,0 +1,297 @@
+//! Commands run when something happens on a native server: it started, a
+//! file was uploaded, or a request matched a pattern. Enough for simple
+//! automation, like rebuilding a site when files arrive, without a plugin
+//! system. Details of the event are passed in `OK200_*` environment
+//! variables; the command runs directly, not through a shell. What it
+//! prints goes to the app log, and it's killed if it runs too long.
+
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::http_server::RequestLog;
+use super::uploads::UploadedFile;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const MAX_TIMEOUT_SECS: u64 = 3600;
+/// Hook commands running at once, across servers. Events past this are
+/// dropped with a warning rather than queued.
+const MAX_RUNNING: usize = 8;
+
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HookEvent {
+    /// The server started listening.
+    Started,
+    /// A file was written by an upload.
+    Upload,
+    /// A request was handled, whatever its status.
+    Request,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Started => "started",
+            Self::Upload => "upload",
+            Self::Request => "request",
+        }
+    }
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Hook {
+    pub on: HookEvent,
+    /// Program to run, by path or looked up in `PATH`.
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Glob over the URL path of the request or uploaded file, like
+    /// `/incoming/*.csv`; every one when absent. `*` stays within one path
+    /// segment and `**` crosses them.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Hook {
+    fn matches(&self, path: &str) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        self.pattern.as_ref().is_none_or(|pattern| {
+            glob::Pattern::new(pattern).is_ok_and(|p| p.matches_with(path, options))
+        })
+    }
+}
+
+/// Check that every hook can run: a command, a pattern that parses and
+/// only where there's a path to match, and a sane timeout.
+pub fn check_hooks(hooks: &[Hook]) -> Result<(), String> {
+    for hook in hooks {
+        if hook.command.trim().is_empty() {
+            return Err("hook without a command".to_string());
+        }
+        if let Some(pattern) = &hook.pattern {
+            if hook.on == HookEvent::Started {
+                return Err(format!("{}: started hooks take no pattern", hook.command));
+            }
+            glob::Pattern::new(pattern)
+                .map_err(|e| format!("invalid hook pattern {pattern:?}: {e}"))?;
+        }
+        if !(1..=MAX_TIMEOUT_SECS).contains(&hook.timeout_secs) {
+            return Err(format!(
+                "{}: timeoutSecs must be from 1 to {MAX_TIMEOUT_SECS}",
+                hook.command
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The server an event happened on.
+#[derive(Clone, Debug)]
+pub struct ServerContext {
+    pub id: u32,
+    pub root: String,
+    /// e.g. `http://127.0.0.1:8080`
+    pub origin: String,
+}
+
+type Env = Vec<(&'static str, String)>;
+
+fn server_env(event: HookEvent, server: &ServerContext) -> Env {
+    vec![
+        ("OK200_EVENT", event.name().to_string()),
+        ("OK200_SERVER_ID", server.id.to_string()),
+        ("OK200_ROOT", server.root.clone()),
+        ("OK200_ORIGIN", server.origin.clone()),
+    ]
+}
+
+fn request_env(event: HookEvent, server: &ServerContext, entry: &RequestLog) -> Env {
+    let mut env = server_env(event, server);
+    env.extend([
+        ("OK200_REQUEST_ID", entry.request_id.clone()),
+        ("OK200_REMOTE_ADDRESS", entry.remote_address.clone()),
+        ("OK200_METHOD", entry.method.clone()),
+        ("OK200_PATH", entry.path.clone()),
+        ("OK200_STATUS", entry.status.to_string()),
+        ("OK200_BYTES", entry.bytes.to_string()),
+    ]);
+    env
+}
+
+fn upload_env(server: &ServerContext, entry: &RequestLog, upload: &UploadedFile) -> Env {
+    let mut env = request_env(HookEvent::Upload, server, entry);
+    env.extend([
+        ("OK200_UPLOAD_PATH", upload.path.clone()),
+        ("OK200_UPLOAD_BYTES", upload.bytes.to_string()),
+    ]);
+    env
+}
+
+/// Run `hook` in the background, logging what it prints.
+fn spawn(hook: &Hook, env: Env) {
+    if RUNNING.fetch_add(1, Ordering::Relaxed) >= MAX_RUNNING {
+        RUNNING.fetch_sub(1, Ordering::Relaxed);
+        tracing::warn!("hook {}: skipped, too many running", hook.command);
+        return;
+    }
+    let hook = hook.clone();
+    tauri::async_runtime::spawn(async move {
+        run(&hook, env).await;
+        RUNNING.fetch_sub(1, Ordering::Relaxed);
+    });
+}
+
+async fn run(hook: &Hook, env: Env) {
+    let mut cmd = tokio::process::Command::new(&hook.command);
+    cmd.args(&hook.args)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(target_os = "windows")]
+    {
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    let timeout = Duration::from_secs(hook.timeout_secs);
+    let output = match tokio::time::timeout(timeout, cmd.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            tracing::warn!("hook {}: run failed: {e}", hook.command);
+            return;
+        }
+        // Dropping the child kills it
+        Err(_) => {
+            tracing::warn!("hook {}: killed after {timeout:?}", hook.command);
+            return;
+        }
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        tracing::info!("hook {}: {line}", hook.command);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        tracing::warn!("hook {}: {line}", hook.command);
+    }
+    if !output.status.success() {
+        tracing::warn!("hook {}: exited with {}", hook.command, output.status);
+    }
+}
+
+/// Run the `started` hooks of `server`.
+pub fn started(hooks: &[Hook], server: &ServerContext) {
+    for hook in hooks.iter().filter(|h| h.on == HookEvent::Started) {
+        spawn(hook, server_env(HookEvent::Started, server));
+    }
+}
+
+/// Run the hooks matching a logged request and the files it uploaded.
+/// Connections that never sent a request don't count.
+pub fn request(hooks: &[Hook], server: &ServerContext, entry: &RequestLog) {
+    if entry.method.is_empty() {
+        return;
+    }
+    for hook in hooks {
+        match hook.on {
+            HookEvent::Request if hook.matches(&entry.path) => {
+                spawn(hook, request_env(HookEvent::Request, server, entry));
+            }
+            HookEvent::Upload => {
+                for upload in entry.uploads.iter().filter(|u| hook.matches(&u.path)) {
+                    spawn(hook, upload_env(server, entry, upload));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(json: &str) -> Hook {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let all = hook(r#"{"on": "request", "command": "true"}"#);
+        assert!(all.matches("/anything?x=1"));
+        assert_eq!(all.timeout_secs, DEFAULT_TIMEOUT_SECS);
+
+        let csv = hook(r#"{"on": "upload", "command": "true", "pattern": "/in/*.csv"}"#);
+        assert!(csv.matches("/in/a.csv"));
+        assert!(csv.matches("/in/a.csv?overwrite=1"));
+        assert!(!csv.matches("/in/sub/a.csv"));
+        assert!(!csv.matches("/in/a.txt"));
+    }
+
+    #[test]
+    fn test_check_hooks() {
+        let check = |json: &str| check_hooks(&[hook(json)]);
+        assert!(check(r#"{"on": "started", "command": "notify-send", "args": ["up"]}"#).is_ok());
+        assert!(check(r#"{"on": "request", "command": " "}"#).is_err());
+        assert!(check(r#"{"on": "started", "command": "x", "pattern": "/*"}"#).is_err());
+        assert!(check(r#"{"on": "upload", "command": "x", "pattern": "/[a"}"#).is_err());
+        assert!(check(r#"{"on": "upload", "command": "x", "timeoutSecs": 0}"#).is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        let server = ServerContext {
+            id: 2,
+            root: "/srv".to_string(),
+            origin: "http://127.0.0.1:8080".to_string(),
+        };
+        let mut entry = RequestLog::new("10.0.0.5:5000".parse().unwrap(), "9".to_string());
+        entry.method = "PUT".to_string();
+        entry.path = "/in/a.csv".to_string();
+        entry.status = 201;
+        let upload = UploadedFile {
+            path: "/in/a.csv".to_string(),
+            bytes: 12,
+        };
+        let env = upload_env(&server, &entry, &upload);
+        for expected in [
+            ("OK200_EVENT", "upload"),
+            ("OK200_SERVER_ID", "2"),
+            ("OK200_ORIGIN", "http://127.0.0.1:8080"),
+            ("OK200_REQUEST_ID", "9"),
+            ("OK200_REMOTE_ADDRESS", "10.0.0.5:5000"),
+            ("OK200_STATUS", "201"),
+            ("OK200_UPLOAD_BYTES", "12"),
+        ] {
+            assert!(
+                env.iter().any(|(k, v)| (*k, v.as_str()) == expected),
+                "{expected:?}"
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_times_out() {
+        let slow =
+            hook(r#"{"on": "started", "command": "sleep", "args": ["5"], "timeoutSecs": 1}"#);
+        let started = std::time::Instant::now();
+        run(&slow, Vec::new()).await;
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,860 @@
+//! `http_server_*` commands: static file servers that run entirely in Rust
+//! (see `http_server`), so the webview only starts, updates and stops them.
+//! Each handled request is streamed back over the channel passed at
+//! creation, and uploaded files are announced with an `http-upload` event.
+//! Most options can be replaced on a running server without dropping its
+//! connections.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use tauri::ipc::Channel;
+use tauri::{Emitter, Manager, State};
+use tokio::task::JoinHandle;
+
+use super::access_log::{AccessLog, AccessLogOptions};
+use super::auth::{self, Auth};
+use super::build_step::{self, BuildEvent, BuildStep};
+use super::compression::Compression;
+use super::cors::Cors;
+use super::dlna::{self, Dlna, DlnaOptions};
+use super::dotfiles::Dotfiles;
+use super::health::{Health, HealthOptions};
+use super::hooks::{self, Hook, ServerContext};
+use super::http_server::{self, CacheRule, RequestLog, ServeOptions, SharedOptions};
+use super::inspector::{CaptureEvent, Inspector, InspectorOptions};
+use super::ip_filter::{IpFilter, IpFilterOptions};
+use super::live_reload::{LiveReload, LiveReloadOptions};
+use super::metrics::{ServerMetrics, ServerStats};
+use super::mounts::{Mount, Mounted};
+use super::plugins::PluginHost;
+use super::profiles;
+use super::proxy::{self, ProxyRoute};
+use super::rate_limit::{RateLimitOptions, RateLimiter};
+use super::tls::{Tls, TlsOptions};
+use super::uploads::{UploadedFile, Uploads};
+use super::usage::Usage;
+use super::webdav::WebDav;
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_listing() -> bool {
+    true
+}
+
+/// `cors`, `compression` and the like may be `true` for the defaults, `false`, or a
+/// full settings object.
+fn deserialize_toggle<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting<T> {
+        Enabled(bool),
+        Custom(T),
+    }
+    Ok(match Setting::deserialize(deserializer)? {
+        Setting::Enabled(true) => Some(T::default()),
+        Setting::Enabled(false) => None,
+        Setting::Custom(custom) => Some(custom),
+    })
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpServerOptions {
+    pub root: String,
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default)]
+    pub spa: bool,
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub cors: Option<Cors>,
+    #[serde(default = "default_listing")]
+    pub listing: bool,
+    #[serde(default)]
+    pub dotfiles: Dotfiles,
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    /// Extension to MIME type. When absent, the folder's saved profile
+    /// applies.
+    #[serde(default)]
+    pub mime_types: Option<HashMap<String, String>>,
+    /// When absent, every request is streamed and nothing is written.
+    #[serde(default)]
+    pub access_log: Option<AccessLogOptions>,
+    #[serde(default)]
+    pub cache_control: Vec<CacheRule>,
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub compression: Option<Compression>,
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub uploads: Option<Uploads>,
+    /// Mountable as a network drive; read-only unless `auth` is set.
+    #[serde(default)]
+    pub webdav: bool,
+    #[serde(default)]
+    pub proxies: Vec<ProxyRoute>,
+    /// More folders on the same port, by path prefix or host name.
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    /// Reload open pages when files in the folder change.
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub live_reload: Option<LiveReloadOptions>,
+    /// Show Markdown files as HTML pages; `?raw=1` gets the file itself.
+    #[serde(default)]
+    pub markdown: bool,
+    /// Serve HTTPS with this certificate, and HTTP/2 where offered.
+    #[serde(default)]
+    pub tls: Option<TlsOptions>,
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub rate_limit: Option<RateLimitOptions>,
+    /// Clients refused at connect time, reported as errors in the log.
+    #[serde(default)]
+    pub ip_filter: Option<IpFilterOptions>,
+    /// Answer `<prefix>/health` and `<prefix>/status` for monitors.
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub health: Option<HealthOptions>,
+    /// Keep recent requests and responses for `inspector_*`.
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub inspector: Option<InspectorOptions>,
+    /// Commands run when the server starts, a file is uploaded or a
+    /// request matches.
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    /// Run before the server starts, which fails if it does.
+    #[serde(default)]
+    pub build: Option<BuildStep>,
+    /// Announce the folder to TVs on the network as a media server. Needs
+    /// a `host` they can reach, and no `auth`.
+    #[serde(default, deserialize_with = "deserialize_toggle")]
+    pub dlna: Option<DlnaOptions>,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpServerInfo {
+    pub id: u32,
+    pub root: String,
+    pub host: String,
+    /// The bound port, which differs from the requested one for port 0.
+    pub port: u16,
+    pub spa: bool,
+    pub cors: Option<Cors>,
+    pub listing: bool,
+    pub dotfiles: Dotfiles,
+    /// Includes the generated token, so the webview can share the URL.
+    pub auth: Option<Auth>,
+    pub mime_types: HashMap<String, String>,
+    pub access_log: Option<AccessLogOptions>,
+    pub cache_control: Vec<CacheRule>,
+    pub compression: Option<Compression>,
+    pub uploads: Option<Uploads>,
+    pub webdav: bool,
+    pub proxies: Vec<ProxyRoute>,
+    pub mounts: Vec<Mount>,
+    pub live_reload: Option<LiveReloadOptions>,
+    pub markdown: bool,
+    pub tls: Option<TlsOptions>,
+    pub rate_limit: Option<RateLimitOptions>,
+    pub ip_filter: Option<IpFilterOptions>,
+    pub health: Option<HealthOptions>,
+    pub inspector: Option<InspectorOptions>,
+    pub hooks: Vec<Hook>,
+    pub build: Option<BuildStep>,
+    pub dlna: Option<DlnaOptions>,
+}
+
+/// Payload of the `http-upload` event.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadEvent {
+    pub server_id: u32,
+    pub request_id: String,
+    pub remote_address: String,
+    #[serde(flatten)]
+    pub file: UploadedFile,
+}
+
+struct HttpServer {
+    task: JoinHandle<()>,
+    root: String,
+    host: String,
+    port: u16,
+    options: SharedOptions,
+    access_log: Option<AccessLogOptions>,
+    build: Option<BuildStep>,
+}
+
+impl HttpServer {
+    fn info(&self, id: u32) -> HttpServerInfo {
+        let options = self.options.read().unwrap();
+        HttpServerInfo {
+            id,
+            root: self.root.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            spa: options.spa,
+            cors: options.cors.clone(),
+            listing: options.listing,
+            dotfiles: options.dotfiles,
+            auth: options.auth.clone(),
+            mime_types: options.mime_types.clone(),
+            access_log: self.access_log,
+            cache_control: options.cache_control.clone(),
+            compression: options.compression.clone(),
+            uploads: options.uploads.clone(),
+            webdav: options.webdav.is_some(),
+            proxies: options.proxies.clone(),
+            mounts: options.mounts.iter().map(|m| m.mount.clone()).collect(),
+            live_reload: options.live_reload.as_ref().map(|l| l.options().clone()),
+            markdown: options.markdown,
+            tls: options.tls.as_ref().map(|t| t.options.clone()),
+            rate_limit: options.rate_limit.as_ref().map(|r| r.options.clone()),
+            ip_filter: options.ip_filter.as_ref().map(|f| f.options.clone()),
+            health: options.health.as_ref().map(|h| h.options.clone()),
+            inspector: options.inspector.as_ref().map(|i| i.options.clone()),
+            hooks: options.hooks.clone(),
+            build: self.build.clone(),
+            dlna: options.dlna.as_ref().map(|d| d.options.clone()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HttpState {
+    servers: Mutex<HashMap<u32, HttpServer>>,
+    next_id: AtomicU32,
+}
+
+impl HttpState {
+    /// Stop every server.
+    pub fn close_all(&self) {
+        for (_, server) in self.servers.lock().unwrap().drain() {
+            server.task.abort();
+        }
+    }
+
+    /// Connections open on every server, for confirming before quitting.
+    pub fn active_connections(&self) -> u64 {
+        let servers = self.servers.lock().unwrap();
+        servers
+            .values()
+            .map(|s| s.options.read().unwrap().stats.active())
+            .sum()
+    }
+
+    /// Counters of every running server, for `metrics`.
+    pub fn metrics(&self) -> Vec<ServerMetrics> {
+        let servers = self.servers.lock().unwrap();
+        let mut metrics: Vec<_> = servers
+            .iter()
+            .map(|(&id, server)| ServerMetrics {
+                id,
+                port: server.port,
+                root: server.root.clone(),
+                stats: server.options.read().unwrap().stats.clone(),
+            })
+            .collect();
+        metrics.sort_by_key(|m| m.id);
+        metrics
+    }
+
+    /// The inspector of server `id`, if it's capturing.
+    pub fn inspector(&self, id: u32) -> Result<Inspector, String> {
+        let servers = self.servers.lock().unwrap();
+        let server = servers
+            .get(&id)
+            .ok_or_else(|| format!("server {id} not found"))?;
+        let options = server.options.read().unwrap();
+        options
+            .inspector
+            .clone()
+            .ok_or_else(|| format!("server {id} isn't capturing"))
+    }
+
+    /// Every capturing server, as `(id, origin, inspector)` with an origin
+    /// like `https://127.0.0.1:8443`, for `har`.
+    pub fn inspectors(&self) -> Vec<(u32, String, Inspector)> {
+        let servers = self.servers.lock().unwrap();
+        let mut inspectors: Vec<_> = servers
+            .iter()
+            .filter_map(|(&id, server)| {
+                let options = server.options.read().unwrap();
+                let scheme = if options.tls.is_some() {
+                    "https"
+                } else {
+                    "http"
+                };
+                let origin = format!("{scheme}://{}:{}", server.host, server.port);
+                Some((id, origin, options.inspector.clone()?))
+            })
+            .collect();
+        inspectors.sort_by_key(|(id, _, _)| *id);
+        inspectors
+    }
+}
+
+/// Check `options` and build what `http_server::serve` reads from them,
+/// all but the certificate, which is loaded once the port is known.
+fn serve_options(
+    app: &tauri::AppHandle,
+    options: &HttpServerOptions,
+) -> Result<ServeOptions, String> {
+    let mime_types = match &options.mime_types {
+        Some(mime_types) => http_server::normalize_mime_types(mime_types.clone())?,
+        None => profiles::mime_types(app, &options.root),
+    };
+    http_server::check_cache_rules(&options.cache_control)?;
+    proxy::check_routes(&options.proxies)?;
+    if let Some(health) = &options.health {
+        health.check()?;
+    }
+    hooks::check_hooks(&options.hooks)?;
+    if options.dlna.is_some() {
+        if options.auth.is_some() {
+            return Err("dlna can't be used with auth: TVs can't log in".to_string());
+        }
+        let loopback = options.host == "localhost"
+            || options
+                .host
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback());
+        if loopback {
+            return Err("dlna needs a host other devices can reach, such as 0.0.0.0".to_string());
+        }
+    }
+    let ip_filter = options.ip_filter.clone().map(IpFilter::new).transpose()?;
+    let mounts = options
+        .mounts
+        .iter()
+        .cloned()
+        .map(Mounted::new)
+        .collect::<Result<_, _>>()?;
+    Ok(ServeOptions {
+        spa: options.spa,
+        cors: options.cors.clone(),
+        listing: options.listing,
+        dotfiles: options.dotfiles,
+        auth: options.auth.clone().map(Auth::with_token),
+        mime_types,
+        cache_control: options.cache_control.clone(),
+        compression: options.compression.clone(),
+        uploads: options.uploads.clone(),
+        webdav: options.webdav.then(WebDav::default),
+        proxies: options.proxies.clone(),
+        mounts,
+        live_reload: options.live_reload.clone().map(LiveReload::new),
+        markdown: options.markdown,
+        tls: None,
+        rate_limit: options.rate_limit.clone().map(RateLimiter::new),
+        ip_filter,
+        health: options.health.clone().map(Health::new),
+        dlna: options
+            .dlna
+            .clone()
+            .map(|d| Dlna::new(d, std::path::Path::new(&options.root))),
+        inspector: options.inspector.clone().map(Inspector::new),
+        hooks: options.hooks.clone(),
+        plugins: app.state::<PluginHost>().plugins.clone(),
+        stats: ServerStats::default(),
+    })
+}
+
+/// Start a server, after running its build if it has one. Build output
+/// goes to `on_build`.
+#[tauri::command]
+pub async fn http_server_create(
+    options: HttpServerOptions,
+    on_request: Channel<RequestLog>,
+    on_build: Channel<BuildEvent>,
+    app: tauri::AppHandle,
+    state: State<'_, HttpState>,
+) -> Result<HttpServerInfo, String> {
+    let send_build = move |event| {
+        let _ = on_build.send(event);
+    };
+    if let Some(step) = &options.build {
+        build_step::check_build(step)?;
+    }
+    let root = PathBuf::from(&options.root);
+    let not_folder = || format!("not a folder: {}", options.root);
+    // A build may create the folder it outputs to
+    if options.build.is_none() && !root.is_dir() {
+        return Err(not_folder());
+    }
+    let mut serve_options = serve_options(&app, &options)?;
+    let listener = tokio::net::TcpListener::bind((options.host.as_str(), options.port))
+        .await
+        .map_err(|e| format!("bind failed: {e}"))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("local_addr failed: {e}"))?
+        .port();
+    serve_options.tls = options
+        .tls
+        .clone()
+        .map(|t| Tls::load(t, port))
+        .transpose()?;
+    // Only once everything else checked out, so a bad option or a taken
+    // port fails right away rather than after a long build
+    if let Some(step) = &options.build {
+        build_step::run(step, false, &send_build).await?;
+        if !root.is_dir() {
+            return Err(not_folder());
+        }
+    }
+    let advertiser = serve_options
+        .dlna
+        .as_ref()
+        .map(|d| dlna::advertise(d, port))
+        .transpose()?;
+
+    let stream = options.access_log.is_none_or(|a| a.stream);
+    let file = match options.access_log {
+        Some(a) if a.file => Some(AccessLog::for_server(&app, port, a.format)?),
+        _ => None,
+    };
+
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let serve_options = Arc::new(RwLock::new(serve_options));
+    let task_options = serve_options.clone();
+    let hook_options = serve_options.clone();
+    let scheme = if options.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let context = ServerContext {
+        id,
+        root: options.root.clone(),
+        origin: format!("{scheme}://{}:{port}", options.host),
+    };
+    hooks::started(&serve_options.read().unwrap().hooks, &context);
+    let rebuild = options
+        .build
+        .clone()
+        .filter(|b| b.watch)
+        .map(|step| build_step::watch(step, send_build));
+    let task = tokio::spawn(async move {
+        // Stops with the server
+        let _rebuild = rebuild;
+        let _advertiser = advertiser;
+        let log = move |entry: &RequestLog| {
+            if let Some(file) = &file {
+                file.write(entry);
+            }
+            app.state::<Usage>().record(&context.root, entry);
+            hooks::request(&hook_options.read().unwrap().hooks, &context, entry);
+            if let Some(error) = &entry.error {
+                tracing::debug!("server {id} request {}: {error}", entry.request_id);
+            }
+            for upload in &entry.uploads {
+                tracing::info!(
+                    "server {id} request {} received {} ({} bytes)",
+                    entry.request_id,
+                    upload.path,
+                    upload.bytes
+                );
+                let event = UploadEvent {
+                    server_id: id,
+                    request_id: entry.request_id.clone(),
+                    remote_address: entry.remote_address.clone(),
+                    file: upload.clone(),
+                };
+                let _ = app.emit("http-upload", event);
+            }
+            if let Some(exchange) = &entry.capture {
+                let event = CaptureEvent {
+                    server_id: id,
+                    exchange: (**exchange).clone(),
+                };
+                let _ = app.emit("inspector-capture", event);
+            }
+            if stream {
+                let _ = on_request.send(entry.clone());
+            }
+        };
+        if let Err(e) = http_server::serve(listener, root, task_options, log).await {
+            tracing::error!("server {id} stopped: {e}");
+        }
+    });
+    tracing::info!(
+        "server {id} serving {} on {}:{port}",
+        options.root,
+        options.host
+    );
+    let server = HttpServer {
+        task,
+        root: options.root,
+        host: options.host,
+        port,
+        options: serve_options,
+        access_log: options.access_log,
+        build: options.build,
+    };
+    let info = server.info(id);
+    state.servers.lock().unwrap().insert(id, server);
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn http_server_close(id: u32, state: State<'_, HttpState>) -> Result<(), String> {
+    let server = state.servers.lock().unwrap().remove(&id);
+    match server {
+        Some(server) => {
+            server.task.abort();
+            Ok(())
+        }
+        None => Err(format!("server {id} not found")),
+    }
+}
+
+#[tauri::command]
+pub async fn http_server_info(
+    id: u32,
+    state: State<'_, HttpState>,
+) -> Result<HttpServerInfo, String> {
+    state
+        .servers
+        .lock()
+        .unwrap()
+        .get(&id)
+        .map(|s| s.info(id))
+        .ok_or_else(|| format!("server {id} not found"))
+}
+
+/// The first option in `options` that differs from the running server's
+/// but can only be set when a server is created.
+fn fixed_change(running: &HttpServerInfo, options: &HttpServerOptions) -> Option<&'static str> {
+    if options.root != running.root {
+        Some("root")
+    } else if options.host != running.host {
+        Some("host")
+    } else if options.port != 0 && options.port != running.port {
+        Some("port")
+    } else if options.access_log != running.access_log {
+        Some("accessLog")
+    } else if options.tls != running.tls {
+        Some("tls")
+    } else if options.live_reload != running.live_reload {
+        Some("liveReload")
+    } else if options.build != running.build {
+        Some("build")
+    } else if options.dlna != running.dlna {
+        Some("dlna")
+    } else {
+        None
+    }
+}
+
+/// Replace a running server's options without dropping its connections.
+/// Connections accepted from now on get the new options; ones already
+/// open, such as downloads in progress, finish with the old. A token auth
+/// without a token keeps the current token.
+#[tauri::command]
+pub async fn http_server_update(
+    id: u32,
+    options: HttpServerOptions,
+    app: tauri::AppHandle,
+    state: State<'_, HttpState>,
+) -> Result<HttpServerInfo, String> {
+    let mut next = serve_options(&app, &options)?;
+    let servers = state.servers.lock().unwrap();
+    let server = servers
+        .get(&id)
+        .ok_or_else(|| format!("server {id} not found"))?;
+    if let Some(name) = fixed_change(&server.info(id), &options) {
+        return Err(format!("{name} can't change while server {id} runs"));
+    }
+    {
+        let mut current = server.options.write().unwrap();
+        // Loaded or started with the server, and counted since
+        next.tls = current.tls.take();
+        next.live_reload = current.live_reload.take();
+        next.stats = current.stats.clone();
+        // Carry over state when the setting itself is unchanged
+        let keep_token = matches!(&options.auth, Some(Auth::Token { token }) if token.is_empty())
+            && matches!(current.auth, Some(Auth::Token { .. }));
+        if keep_token {
+            next.auth = current.auth.take();
+        }
+        if next.webdav.is_some() {
+            next.webdav = current.webdav.take().or(next.webdav);
+        }
+        if next.rate_limit.as_ref().map(|r| &r.options)
+            == current.rate_limit.as_ref().map(|r| &r.options)
+        {
+            next.rate_limit = current.rate_limit.take();
+        }
+        if next.health.as_ref().map(|h| &h.options) == current.health.as_ref().map(|h| &h.options) {
+            next.health = current.health.take();
+        }
+        if next.inspector.as_ref().map(|i| &i.options)
+            == current.inspector.as_ref().map(|i| &i.options)
+        {
+            next.inspector = current.inspector.take();
+        }
+        *current = next;
+    }
+    tracing::info!("server {id} options updated");
+    Ok(server.info(id))
+}
+
+/// Give a token-protected server a new token. The old one, and cookies
+/// set from it, stop working immediately. Returns the new token.
+#[tauri::command]
+pub async fn http_server_rotate_token(
+    id: u32,
+    state: State<'_, HttpState>,
+) -> Result<String, String> {
+    let servers = state.servers.lock().unwrap();
+    let server = servers
+        .get(&id)
+        .ok_or_else(|| format!("server {id} not found"))?;
+    let mut options = server.options.write().unwrap();
+    match &mut options.auth {
+        Some(Auth::Token { token }) => {
+            *token = auth::generate_token();
+            tracing::info!("server {id} token rotated");
+            Ok(token.clone())
+        }
+        _ => Err(format!("server {id} doesn't use token auth")),
+    }
+}
+
+/// Serve another folder from a running server, replacing whatever was
+/// mounted at the same host and prefix.
+#[tauri::command]
+pub async fn http_server_mount(
+    id: u32,
+    mount: Mount,
+    state: State<'_, HttpState>,
+) -> Result<HttpServerInfo, String> {
+    let mounted = Mounted::new(mount)?;
+    let servers = state.servers.lock().unwrap();
+    let server = servers
+        .get(&id)
+        .ok_or_else(|| format!("server {id} not found"))?;
+    {
+        let mut options = server.options.write().unwrap();
+        let (host, prefix) = (mounted.mount.host.as_deref(), &mounted.mount.prefix);
+        options.mounts.retain(|m| !m.same_place(host, prefix));
+        tracing::info!("server {id} mounted {} at {prefix}", mounted.mount.root);
+        options.mounts.push(mounted);
+    }
+    Ok(server.info(id))
+}
+
+#[tauri::command]
+pub async fn http_server_unmount(
+    id: u32,
+    host: Option<String>,
+    prefix: String,
+    state: State<'_, HttpState>,
+) -> Result<HttpServerInfo, String> {
+    let servers = state.servers.lock().unwrap();
+    let server = servers
+        .get(&id)
+        .ok_or_else(|| format!("server {id} not found"))?;
+    {
+        let mut options = server.options.write().unwrap();
+        let before = options.mounts.len();
+        options
+            .mounts
+            .retain(|m| !m.same_place(host.as_deref(), &prefix));
+        if options.mounts.len() == before {
+            return Err(format!("nothing mounted at {prefix}"));
+        }
+    }
+    Ok(server.info(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access_log::AccessLogFormat;
+
+    #[test]
+    fn test_options_defaults() {
+        let options: HttpServerOptions = serde_json::from_str(r#"{"root": "/srv"}"#).unwrap();
+        assert_eq!(options.port, 0);
+        assert_eq!(options.host, "127.0.0.1");
+        assert!(!options.spa && options.cors.is_none() && options.listing);
+        assert_eq!(options.dotfiles, Dotfiles::Serve);
+        assert_eq!(options.auth, None);
+        assert_eq!(options.mime_types, None);
+        assert_eq!(options.access_log, None);
+        assert!(options.cache_control.is_empty());
+        assert_eq!(options.compression, None);
+        assert_eq!(options.uploads, None);
+        assert!(!options.webdav);
+        assert!(options.proxies.is_empty());
+        assert!(options.mounts.is_empty());
+        assert_eq!(options.live_reload, None);
+        assert!(!options.markdown);
+        assert!(options.tls.is_none());
+        assert!(options.rate_limit.is_none());
+        assert!(options.ip_filter.is_none());
+        assert!(options.health.is_none());
+        assert!(options.inspector.is_none());
+        assert!(options.hooks.is_empty());
+        assert!(options.build.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_active_connections() {
+        let state = HttpState::default();
+        let options = SharedOptions::default();
+        let counters = options.read().unwrap().stats.clone();
+        let server = HttpServer {
+            task: tokio::spawn(async {}),
+            root: "/srv".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            options,
+            access_log: None,
+            build: None,
+        };
+        state.servers.lock().unwrap().insert(1, server);
+        assert_eq!(state.active_connections(), 0);
+        let connection = counters.connection();
+        assert_eq!(state.active_connections(), 1);
+        drop(connection);
+        assert_eq!(state.active_connections(), 0);
+    }
+
+    #[test]
+    fn test_options_cors() {
+        let parse = |json: &str| {
+            serde_json::from_str::<HttpServerOptions>(json)
+                .unwrap()
+                .cors
+        };
+        assert_eq!(
+            parse(r#"{"root": "/", "cors": true}"#),
+            Some(Cors::default())
+        );
+        assert_eq!(parse(r#"{"root": "/", "cors": false}"#), None);
+        let custom = parse(r#"{"root": "/", "cors": {"allowedOrigins": ["https://a.test"]}}"#);
+        let custom = custom.unwrap();
+        assert_eq!(custom.allowed_origins, ["https://a.test"]);
+        assert_eq!(custom.allowed_methods, Cors::default().allowed_methods);
+    }
+
+    #[test]
+    fn test_options_compression() {
+        let parse = |json: &str| {
+            serde_json::from_str::<HttpServerOptions>(json)
+                .unwrap()
+                .compression
+        };
+        assert_eq!(
+            parse(r#"{"root": "/", "compression": true}"#),
+            Some(Compression::default())
+        );
+        let custom = parse(r#"{"root": "/", "compression": {"minBytes": 0}}"#).unwrap();
+        assert_eq!(custom.min_bytes, 0);
+        assert!(custom.precompressed);
+    }
+
+    #[test]
+    fn test_options_live_reload() {
+        let parse = |json: &str| {
+            serde_json::from_str::<HttpServerOptions>(json)
+                .unwrap()
+                .live_reload
+        };
+        assert_eq!(
+            parse(r#"{"root": "/", "liveReload": true}"#),
+            Some(LiveReloadOptions::default())
+        );
+        assert_eq!(parse(r#"{"root": "/", "liveReload": false}"#), None);
+        let custom = parse(r#"{"root": "/", "liveReload": {"debounceMs": 50}}"#).unwrap();
+        assert_eq!(custom.batch.debounce_ms, 50);
+        assert_eq!(custom.batch.max_wait_ms, 2000);
+    }
+
+    #[test]
+    fn test_fixed_change() {
+        let running = HttpServerInfo {
+            id: 1,
+            root: "/srv".to_string(),
+            host: default_host(),
+            port: 8080,
+            spa: false,
+            cors: None,
+            listing: true,
+            dotfiles: Dotfiles::Serve,
+            auth: None,
+            mime_types: HashMap::new(),
+            access_log: None,
+            cache_control: Vec::new(),
+            compression: None,
+            uploads: None,
+            webdav: false,
+            proxies: Vec::new(),
+            mounts: Vec::new(),
+            live_reload: None,
+            markdown: false,
+            tls: None,
+            rate_limit: None,
+            ip_filter: None,
+            health: None,
+            inspector: None,
+            hooks: Vec::new(),
+            build: None,
+            dlna: None,
+        };
+        let change = |json: &str| {
+            let options: HttpServerOptions = serde_json::from_str(json).unwrap();
+            fixed_change(&running, &options)
+        };
+        assert_eq!(
+            change(r#"{"root": "/srv", "spa": true, "cors": true, "auth": {"type": "token"}}"#),
+            None
+        );
+        assert_eq!(change(r#"{"root": "/srv", "port": 8080}"#), None);
+        assert_eq!(change(r#"{"root": "/srv", "port": 9090}"#), Some("port"));
+        assert_eq!(change(r#"{"root": "/www"}"#), Some("root"));
+        assert_eq!(
+            change(r#"{"root": "/srv", "host": "0.0.0.0"}"#),
+            Some("host")
+        );
+        assert_eq!(
+            change(r#"{"root": "/srv", "accessLog": {}}"#),
+            Some("accessLog")
+        );
+        assert_eq!(
+            change(r#"{"root": "/srv", "liveReload": true}"#),
+            Some("liveReload")
+        );
+        assert_eq!(
+            change(r#"{"root": "/srv", "build": {"command": "hugo", "cwd": "/src"}}"#),
+            Some("build")
+        );
+        assert_eq!(change(r#"{"root": "/srv", "dlna": true}"#), Some("dlna"));
+    }
+
+    #[test]
+    fn test_options_access_log() {
+        let options: HttpServerOptions = serde_json::from_str(
+            r#"{"root": "/", "accessLog": {"format": "json", "stream": false}}"#,
+        )
+        .unwrap();
+        let access_log = options.access_log.unwrap();
+        assert_eq!(access_log.format, AccessLogFormat::Json);
+        assert!(access_log.file && !access_log.stream);
+    }
+}
@@ -0,0 +1,346 @@
+//! HTTP/2 for TLS connections that negotiate it. Each stream is replayed
+//! as an HTTP/1.1 request over an in-memory pipe to `http_server`'s usual
+//! handling, so auth, uploads, DAV and proxying behave the same on
+//! either version. The helpers here are shared with `http3`.
+
+use std::fmt::Write as _;
+use std::future::Future;
+use std::io;
+
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+};
+
+/// Buffer between a stream and its handler.
+pub const PIPE_BYTES: usize = 64 * 1024;
+const MAX_RESPONSE_HEAD_BYTES: u64 = 64 * 1024;
+const READ_CHUNK_BYTES: usize = 16 * 1024;
+
+/// Request headers that only mean something on one HTTP/1.1 connection.
+/// `Expect` is dropped too, so the handler doesn't send `100 Continue`.
+const REQUEST_SKIPPED: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+    "expect",
+];
+/// Response headers that HTTP/2 and HTTP/3 forbid.
+const RESPONSE_SKIPPED: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// HTTP/1.1 head replaying `parts`, and whether its body is sent chunked,
+/// which it is when there is one and the client didn't give its length.
+pub fn request_head(parts: &http::request::Parts, has_body: bool) -> (String, bool) {
+    let target = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+    let mut head = format!("{} {target} HTTP/1.1\r\n", parts.method);
+    if let Some(authority) = parts.uri.authority() {
+        if !parts.headers.contains_key(http::header::HOST) {
+            let _ = write!(head, "Host: {authority}\r\n");
+        }
+    }
+    // HTTP/2 and HTTP/3 may split cookies over several fields
+    let cookies: Vec<_> = parts
+        .headers
+        .get_all(http::header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    if !cookies.is_empty() {
+        let _ = write!(head, "Cookie: {}\r\n", cookies.join("; "));
+    }
+    for (name, value) in &parts.headers {
+        if name == http::header::COOKIE || REQUEST_SKIPPED.contains(&name.as_str()) {
+            continue;
+        }
+        if let Ok(value) = value.to_str() {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+    }
+    let chunked = has_body && !parts.headers.contains_key(http::header::CONTENT_LENGTH);
+    if chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    head.push_str("\r\n");
+    (head, chunked)
+}
+
+/// Write one piece of a request body to the handler.
+pub async fn write_body(
+    pipe: &mut (impl AsyncWrite + Unpin),
+    data: &[u8],
+    chunked: bool,
+) -> io::Result<()> {
+    // An empty chunk would end the body
+    if data.is_empty() {
+        return Ok(());
+    }
+    if chunked {
+        pipe.write_all(format!("{:x}\r\n", data.len()).as_bytes())
+            .await?;
+        pipe.write_all(data).await?;
+        pipe.write_all(b"\r\n").await
+    } else {
+        pipe.write_all(data).await
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
+
+/// Reads the handler's HTTP/1.1 response back out of the pipe.
+pub struct ResponseReader<R> {
+    reader: BufReader<R>,
+    chunked: bool,
+    /// Bytes left in the current chunk.
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> ResponseReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            chunked: false,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    /// The next line, or an empty string at the end of the pipe.
+    async fn line(&mut self, limit: u64) -> io::Result<String> {
+        let mut line = String::new();
+        (&mut self.reader).take(limit).read_line(&mut line).await?;
+        if !line.is_empty() && !line.ends_with('\n') {
+            return Err(invalid("response head too long"));
+        }
+        Ok(line)
+    }
+
+    /// Status and headers, without the ones HTTP/2 forbids; `None` if the
+    /// handler closed the pipe without answering.
+    pub async fn head(&mut self) -> io::Result<Option<http::Response<()>>> {
+        loop {
+            let mut limit = MAX_RESPONSE_HEAD_BYTES;
+            let line = self.line(limit).await?;
+            if line.is_empty() {
+                return Ok(None);
+            }
+            let status: u16 = line
+                .split(' ')
+                .nth(1)
+                .and_then(|s| s.trim().parse().ok())
+                .ok_or_else(|| invalid("bad status line"))?;
+            let mut response = http::Response::builder().status(status);
+            limit -= line.len() as u64;
+            loop {
+                let line = self.line(limit).await?;
+                if line.is_empty() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                limit -= line.len() as u64;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let Some((name, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let (name, value) = (name.trim(), value.trim());
+                if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                    self.chunked = value.eq_ignore_ascii_case("chunked");
+                }
+                if !RESPONSE_SKIPPED
+                    .iter()
+                    .any(|h| name.eq_ignore_ascii_case(h))
+                {
+                    response = response.header(name, value);
+                }
+            }
+            // Interim responses aren't passed on
+            if (100..200).contains(&status) {
+                continue;
+            }
+            return response.body(()).map(Some).map_err(io::Error::other);
+        }
+    }
+
+    /// The next piece of the body, decoded; `None` at its end.
+    pub async fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        if self.done {
+            return Ok(None);
+        }
+        if self.chunked && self.remaining == 0 {
+            let line = self.line(MAX_RESPONSE_HEAD_BYTES).await?;
+            let size = line.trim_end().split(';').next().unwrap_or_default();
+            self.remaining =
+                u64::from_str_radix(size.trim(), 16).map_err(|_| invalid("bad chunk size"))?;
+            if self.remaining == 0 {
+                // Trailers, if any, are dropped
+                self.done = true;
+                return Ok(None);
+            }
+        }
+        let want = if self.chunked {
+            usize::try_from(self.remaining).map_or(READ_CHUNK_BYTES, |r| r.min(READ_CHUNK_BYTES))
+        } else {
+            READ_CHUNK_BYTES
+        };
+        let mut buf = vec![0; want];
+        let n = self.reader.read(&mut buf).await?;
+        if n == 0 {
+            self.done = true;
+            if self.chunked {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            return Ok(None);
+        }
+        buf.truncate(n);
+        if self.chunked {
+            self.remaining -= n as u64;
+            if self.remaining == 0 {
+                let mut crlf = [0; 2];
+                self.reader.read_exact(&mut crlf).await?;
+            }
+        }
+        Ok(Some(buf.into()))
+    }
+}
+
+/// Serve HTTP/2 on `io` until the client goes away, handing each request
+/// to `handle` as if it were its own HTTP/1.1 connection.
+pub async fn serve_connection<T, H, F>(io: T, handle: H) -> Result<(), h2::Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    H: Fn(DuplexStream) -> F,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut connection = h2::server::handshake(io).await?;
+    while let Some(accepted) = connection.accept().await {
+        let (request, respond) = accepted?;
+        let (ours, theirs) = tokio::io::duplex(PIPE_BYTES);
+        tokio::spawn(handle(theirs));
+        // The handler logs the request, including whatever went wrong
+        tokio::spawn(async move { bridge(request, respond, ours).await.ok() });
+    }
+    Ok(())
+}
+
+async fn bridge(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    pipe: DuplexStream,
+) -> io::Result<()> {
+    let (parts, mut body) = request.into_parts();
+    let (head, chunked) = request_head(&parts, !body.is_end_stream());
+    let (reader, mut writer) = tokio::io::split(pipe);
+    let upload = async move {
+        writer.write_all(head.as_bytes()).await?;
+        while let Some(data) = body.data().await {
+            let data = data.map_err(io::Error::other)?;
+            body.flow_control().release_capacity(data.len()).ok();
+            write_body(&mut writer, &data, chunked).await?;
+        }
+        if chunked {
+            writer.write_all(b"0\r\n\r\n").await?;
+        }
+        // Keep the pipe open: closing it would look like the client leaving
+        std::future::pending::<io::Result<()>>().await
+    };
+    let download = async move {
+        let mut response = ResponseReader::new(reader);
+        let Some(head) = response.head().await? else {
+            respond.send_reset(h2::Reason::INTERNAL_ERROR);
+            return Ok(());
+        };
+        let mut send = respond
+            .send_response(head, false)
+            .map_err(io::Error::other)?;
+        while let Some(mut chunk) = response.next_chunk().await? {
+            // Wait for the client's window so slow clients don't buffer files
+            while !chunk.is_empty() {
+                send.reserve_capacity(chunk.len());
+                let capacity = std::future::poll_fn(|cx| send.poll_capacity(cx))
+                    .await
+                    .ok_or(io::ErrorKind::BrokenPipe)?
+                    .map_err(io::Error::other)?;
+                let part = chunk.split_to(capacity.min(chunk.len()));
+                send.send_data(part, false).map_err(io::Error::other)?;
+            }
+        }
+        send.send_data(Bytes::new(), true).map_err(io::Error::other)
+    };
+    // The response can be complete before the body is read, e.g. a 401
+    tokio::select! {
+        result = download => result,
+        Err(e) = upload => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_head() {
+        let request = http::Request::post("https://localhost:8443/up?x=1")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .header("te", "trailers")
+            .header("content-type", "text/plain")
+            .body(())
+            .unwrap();
+        let (parts, ()) = request.into_parts();
+        let (head, chunked) = request_head(&parts, true);
+        assert!(chunked);
+        assert_eq!(
+            head,
+            "POST /up?x=1 HTTP/1.1\r\nHost: localhost:8443\r\nCookie: a=1; b=2\r\n\
+             content-type: text/plain\r\nTransfer-Encoding: chunked\r\n\r\n"
+        );
+        let (head, chunked) = request_head(&parts, false);
+        assert!(!chunked);
+        assert!(!head.contains("chunked"));
+    }
+
+    #[tokio::test]
+    async fn test_response_reader() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\n\
+                    HTTP/1.1 201 Created\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\
+                    X-Test: yes\r\n\r\n5\r\nhello\r\n1;ext=1\r\n!\r\n0\r\nTrailer: x\r\n\r\n";
+        let mut response = ResponseReader::new(&raw[..]);
+        let head = response.head().await.unwrap().unwrap();
+        assert_eq!(head.status(), 201);
+        assert_eq!(head.headers()["x-test"], "yes");
+        assert!(!head.headers().contains_key("connection"));
+        assert!(!head.headers().contains_key("transfer-encoding"));
+        let mut body = Vec::new();
+        while let Some(chunk) = response.next_chunk().await.unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+        assert_eq!(body, b"hello!");
+
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi";
+        let mut response = ResponseReader::new(&raw[..]);
+        let head = response.head().await.unwrap().unwrap();
+        assert_eq!(head.headers()["content-length"], "2");
+        assert_eq!(&response.next_chunk().await.unwrap().unwrap()[..], b"hi");
+        assert_eq!(response.next_chunk().await.unwrap(), None);
+        assert!(ResponseReader::new(&b""[..])
+            .head()
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,98 @@
+//! HTTP/3 over QUIC, answered on the same port as a TLS server's TCP
+//! listener. Requests are bridged to HTTP/1.1 handling as in `http2`.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes};
+use h3::server::RequestResolver;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio_rustls::rustls::ServerConfig;
+
+use super::http2::{self, ResponseReader};
+
+/// A QUIC endpoint on `addr`; `config` must offer `h3`.
+pub fn endpoint(addr: SocketAddr, config: ServerConfig) -> Result<quinn::Endpoint, String> {
+    let crypto = quinn::crypto::rustls::QuicServerConfig::try_from(config)
+        .map_err(|e| format!("QUIC config failed: {e}"))?;
+    let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    quinn::Endpoint::server(config, addr).map_err(|e| format!("bind failed: {e}"))
+}
+
+/// Accept connections on `endpoint` until it's closed, handing each
+/// request and its client's address to `handle` as if it were its own
+/// HTTP/1.1 connection.
+pub async fn serve<H, F>(endpoint: quinn::Endpoint, handle: H)
+where
+    H: Fn(DuplexStream, SocketAddr) -> F + Clone + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    while let Some(incoming) = endpoint.accept().await {
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let Ok(connection) = incoming.await else {
+                return;
+            };
+            let peer = connection.remote_address();
+            let connection = h3_quinn::Connection::new(connection);
+            let Ok(mut connection) = h3::server::Connection::<_, Bytes>::new(connection).await
+            else {
+                return;
+            };
+            while let Ok(Some(resolver)) = connection.accept().await {
+                let (ours, theirs) = tokio::io::duplex(http2::PIPE_BYTES);
+                tokio::spawn(handle(theirs, peer));
+                tokio::spawn(async move { bridge(resolver, ours).await.ok() });
+            }
+        });
+    }
+}
+
+async fn bridge(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    pipe: DuplexStream,
+) -> io::Result<()> {
+    let (request, stream) = resolver.resolve_request().await.map_err(io::Error::other)?;
+    let (mut send, mut recv) = stream.split();
+    let (parts, ()) = request.into_parts();
+    // Requests without a body end along with their headers
+    let first = recv.recv_data().await.map_err(io::Error::other)?;
+    let mut data = first.map(|mut buf| buf.copy_to_bytes(buf.remaining()));
+    let (head, chunked) = http2::request_head(&parts, data.is_some());
+    let (reader, mut writer) = tokio::io::split(pipe);
+    let upload = async move {
+        writer.write_all(head.as_bytes()).await?;
+        while let Some(bytes) = data {
+            http2::write_body(&mut writer, &bytes, chunked).await?;
+            data = recv
+                .recv_data()
+                .await
+                .map_err(io::Error::other)?
+                .map(|mut buf| buf.copy_to_bytes(buf.remaining()));
+        }
+        if chunked {
+            writer.write_all(b"0\r\n\r\n").await?;
+        }
+        // Keep the pipe open: closing it would look like the client leaving
+        std::future::pending::<io::Result<()>>().await
+    };
+    let download = async move {
+        let mut response = ResponseReader::new(reader);
+        let Some(head) = response.head().await? else {
+            send.stop_stream(h3::error::Code::H3_INTERNAL_ERROR);
+            return Ok(());
+        };
+        send.send_response(head).await.map_err(io::Error::other)?;
+        while let Some(chunk) = response.next_chunk().await? {
+            send.send_data(chunk).await.map_err(io::Error::other)?;
+        }
+        send.finish().await.map_err(io::Error::other)
+    };
+    // The response can be complete before the body is read, e.g. a 401
+    tokio::select! {
+        result = download => result,
+        Err(e) = upload => Err(e),
+    }
+}
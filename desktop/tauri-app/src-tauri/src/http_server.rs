@@ -0,0 +1,3040 @@
+//! Minimal static file server, used by `--serve` and the `http_server_*`
+//! commands. Answers GET and HEAD for files under a root folder, serving
+//! `index.html` or a listing for folders. One request per connection.
+//! Files carry an `ETag` and `Last-Modified`, and conditional requests for
+//! unchanged files get a 304. Bodies can be compressed (see `compression`),
+//! and uploads accepted (see `uploads`).
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_rustls::TlsAcceptor;
+
+use ok200_common::http::{html_escape, percent_decode, percent_encode, reason, resolve};
+
+use super::auth::{self, Auth, Credentials};
+use super::compression::{self, Compression, Encoding};
+use super::cors::Cors;
+use super::dlna::{self, Dlna};
+use super::dotfiles::Dotfiles;
+use super::fingerprint;
+use super::health::{Endpoint, Health};
+use super::hooks::Hook;
+use super::http2;
+#[cfg(feature = "http3")]
+use super::http3;
+use super::inspector::{self, CapturedBody, Exchange, Inspector};
+use super::ip_filter::IpFilter;
+use super::live_reload::{self, LiveReload};
+use super::logging::UtcTime;
+use super::markdown;
+use super::metrics::ServerStats;
+use super::mounts::{self, Mounted};
+use super::plugins::{Outcome, PluginRequest, Plugins};
+use super::proxy::{self, ProxyRoute, Upstream};
+use super::rate_limit::RateLimiter;
+use super::sniff::{self, Protocol};
+use super::tcp::ACCEPT_RETRY_DELAY;
+use super::tls::{self, Tls};
+use super::uploads::{self, BodyLength, BodyReader, UploadedFile, Uploads};
+use super::watch_batch::Batch;
+use super::webdav::{self, WebDav};
+
+/// Requests with a larger head than this are rejected.
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+/// Largest body read for DAV methods other than `PUT`.
+const MAX_DAV_BODY_BYTES: u64 = 1024 * 1024;
+/// Largest DLNA control request; `Browse` arguments are short.
+const MAX_SOAP_BODY_BYTES: u64 = 64 * 1024;
+const PROXY_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+pub const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+/// Headers a 304 repeats from the response it stands in for.
+const NOT_MODIFIED_HEADERS: [&str; 4] = ["Cache-Control", "ETag", "Last-Modified", "Vary"];
+
+/// `Cache-Control` for URL paths matching a glob, e.g. `/assets/**` or
+/// `**/*.html`. `*` stays within one path segment.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheRule {
+    pub pattern: String,
+    pub value: String,
+}
+
+impl CacheRule {
+    fn matches(&self, url_path: &str) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        glob::Pattern::new(&self.pattern).is_ok_and(|p| p.matches_with(url_path, options))
+    }
+}
+
+/// Check that every rule's pattern parses and its value is a valid header.
+pub fn check_cache_rules(rules: &[CacheRule]) -> Result<(), String> {
+    for rule in rules {
+        glob::Pattern::new(&rule.pattern)
+            .map_err(|e| format!("invalid pattern {:?}: {e}", rule.pattern))?;
+        if rule.value.is_empty() || rule.value.chars().any(char::is_control) {
+            return Err(format!("invalid Cache-Control value: {:?}", rule.value));
+        }
+    }
+    Ok(())
+}
+
+/// Per-server behaviour switches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServeOptions {
+    /// Answer unknown paths with the root `index.html` (single-page apps).
+    pub spa: bool,
+    /// Let other origins fetch served files.
+    pub cors: Option<Cors>,
+    /// List folders that have no `index.html`.
+    pub listing: bool,
+    /// Whether dotfiles are listed and served.
+    pub dotfiles: Dotfiles,
+    /// Credentials required for every request except CORS preflights.
+    pub auth: Option<Auth>,
+    /// Extension (lowercase, no dot) to MIME type, overriding the built-in
+    /// table. See `normalize_mime_types`.
+    pub mime_types: HashMap<String, String>,
+    /// First matching rule sets `Cache-Control` on successful responses.
+    pub cache_control: Vec<CacheRule>,
+    pub compression: Option<Compression>,
+    /// Accept `PUT` and multipart `POST` into the served folder.
+    pub uploads: Option<Uploads>,
+    /// Answer DAV methods so the folder can be mounted as a drive. The
+    /// ones that change files also need `auth`.
+    pub webdav: Option<WebDav>,
+    /// Path prefixes forwarded to other origins, ahead of any files.
+    pub proxies: Vec<ProxyRoute>,
+    /// Other folders, served in place of the root where they match.
+    pub mounts: Vec<Mounted>,
+    /// Add a script to HTML pages that reloads them when files change.
+    pub live_reload: Option<LiveReload>,
+    /// Render Markdown files as HTML pages, unless asked for `?raw=1`.
+    pub markdown: bool,
+    /// Serve HTTPS, and HTTP/2 to clients that offer it.
+    pub tls: Option<Tls>,
+    /// Answer clients that send too much with 429.
+    pub rate_limit: Option<RateLimiter>,
+    /// Clients turned away as soon as they connect.
+    pub ip_filter: Option<IpFilter>,
+    /// Answer `/_ok200/health` and `/_ok200/status`, or under another
+    /// prefix, without auth.
+    pub health: Option<Health>,
+    /// Announce the folder to TVs over SSDP and answer DLNA browsing under
+    /// `dlna::PREFIX`, without auth.
+    pub dlna: Option<Dlna>,
+    /// Keep recent requests and responses for debugging.
+    pub inspector: Option<Inspector>,
+    /// Commands run on server events, see `hooks`. Only the app runs them.
+    pub hooks: Vec<Hook>,
+    /// WASM middleware run ahead of proxies and files, see `plugins`.
+    pub plugins: Plugins,
+    /// Request and connection counters, see `metrics`.
+    pub stats: ServerStats,
+}
+
+/// Options that can change while the server runs, e.g. a rotated token.
+pub type SharedOptions = Arc<RwLock<ServeOptions>>;
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            spa: false,
+            cors: None,
+            listing: true,
+            dotfiles: Dotfiles::default(),
+            auth: None,
+            mime_types: HashMap::new(),
+            cache_control: Vec::new(),
+            compression: None,
+            uploads: None,
+            webdav: None,
+            proxies: Vec::new(),
+            mounts: Vec::new(),
+            live_reload: None,
+            markdown: false,
+            tls: None,
+            rate_limit: None,
+            ip_filter: None,
+            health: None,
+            dlna: None,
+            inspector: None,
+            hooks: Vec::new(),
+            plugins: Plugins::default(),
+            stats: ServerStats::default(),
+        }
+    }
+}
+
+/// One handled request (or failed connection), as passed to the logger.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestLog {
+    /// Ties together everything logged about one request: the
+    /// connection's number on its server, then the stream's for HTTP/2,
+    /// e.g. `12` or `12.3`.
+    pub request_id: String,
+    pub remote_address: String,
+    /// Unix time the connection was accepted.
+    pub time: u64,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uploads: Vec<UploadedFile>,
+    /// The whole exchange, when the server's inspector is on.
+    #[serde(skip)]
+    pub capture: Option<Box<Exchange>>,
+}
+
+impl RequestLog {
+    /// An entry for a connection from `peer` accepted just now.
+    pub fn new(peer: SocketAddr, request_id: String) -> Self {
+        Self {
+            request_id,
+            remote_address: peer.to_string(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            method: String::new(),
+            path: String::new(),
+            status: 0,
+            bytes: 0,
+            duration_ms: 0,
+            user_agent: None,
+            referer: None,
+            error: None,
+            uploads: Vec::new(),
+            capture: None,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            Some(error) => write!(f, "{} {error}", self.remote_address),
+            None => write!(
+                f,
+                "{} {} {} {}",
+                self.remote_address, self.method, self.path, self.status
+            ),
+        }
+    }
+}
+
+/// Request line and headers of an incoming request.
+struct Request<'a> {
+    method: &'a str,
+    target: &'a str,
+    headers: Vec<(&'a str, &'a str)>,
+    /// Prefix of the mount `target` was found under, cut from it; empty
+    /// if none was.
+    base: &'a str,
+}
+
+impl<'a> Request<'a> {
+    /// `None` if the request line is incomplete.
+    fn parse(head: &'a str) -> Option<Self> {
+        let mut lines = head.lines();
+        let mut parts = lines.next()?.split(' ');
+        let method = parts.next().filter(|m| !m.is_empty())?;
+        let target = parts.next().filter(|t| !t.is_empty())?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        Some(Self {
+            method,
+            target,
+            headers,
+            base: "",
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    /// An open file, its length, and its path.
+    File(tokio::fs::File, u64, PathBuf),
+}
+
+impl Response {
+    fn text(status: u16) -> Self {
+        let body = format!("{status} {}\n", reason(status)).into_bytes();
+        Self {
+            status,
+            headers: vec![("Content-Type", "text/plain; charset=utf-8".to_string())],
+            body: Body::Bytes(body),
+        }
+    }
+
+    fn empty(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Body::Bytes(Vec::new()),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        match &self.body {
+            Body::Bytes(b) => b.len() as u64,
+            Body::File(_, len, _) => *len,
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// `Sun, 06 Nov 1994 08:49:37 GMT`
+fn http_date(secs: u64) -> String {
+    let t = UtcTime::from_unix(secs);
+    let weekday = WEEKDAYS[usize::try_from((secs / 86_400 + 4) % 7).unwrap_or(0)];
+    let month = MONTHS[usize::try_from(t.month - 1).unwrap_or(0) % 12];
+    format!(
+        "{weekday}, {:02} {month} {:04} {:02}:{:02}:{:02} GMT",
+        t.day, t.year, t.hour, t.minute, t.second
+    )
+}
+
+/// Parse an `http_date`. Browsers only send this format back.
+fn parse_http_date(value: &str) -> Option<u64> {
+    let (_, rest) = value.split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let day = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)?;
+    let year = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(str::parse::<u64>);
+    if parts.next() != Some("GMT") {
+        return None;
+    }
+    UtcTime {
+        year,
+        month: i64::try_from(month).ok()? + 1,
+        day,
+        hour: time.next()?.ok()?,
+        minute: time.next()?.ok()?,
+        second: time.next()?.ok()?,
+    }
+    .to_unix()
+}
+
+/// Check user-supplied MIME overrides and normalize their keys, so `.MD`
+/// and `md` both become `md`.
+pub fn normalize_mime_types(
+    map: impl IntoIterator<Item = (String, String)>,
+) -> Result<HashMap<String, String>, String> {
+    map.into_iter()
+        .map(|(ext, mime)| {
+            let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+            let mime = mime.trim().to_string();
+            if ext.is_empty() || ext.contains(['/', '\\']) {
+                return Err(format!("invalid extension: {ext:?}"));
+            }
+            // Ends up in a header, so no control characters
+            let valid = mime
+                .split_once('/')
+                .is_some_and(|(t, s)| !t.is_empty() && !s.is_empty())
+                && !mime.chars().any(char::is_control);
+            if !valid {
+                return Err(format!("invalid MIME type for .{ext}: {mime:?}"));
+            }
+            Ok((ext, mime))
+        })
+        .collect()
+}
+
+fn content_type(path: &Path, overrides: &HashMap<String, String>) -> String {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    if let Some(mime) = ext.as_ref().and_then(|e| overrides.get(e)) {
+        return mime.clone();
+    }
+    ok200_common::http::mime_type(ext.as_deref()).to_string()
+}
+
+/// Decoded value of query parameter `name` in a request target.
+fn query_param(target: &str, name: &str) -> Option<String> {
+    let (_, query) = target.split_once('?')?;
+    let query = query.split('#').next().unwrap_or_default();
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(n, _)| *n == name)
+        .and_then(|(_, value)| percent_decode(&value.replace('+', " ")))
+}
+
+/// `target` with any token in the query hidden, for logs.
+fn redact_token(target: &str) -> String {
+    let Some((path, query)) = target.split_once('?') else {
+        return target.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((auth::TOKEN_PARAM, _)) => format!("{}=***", auth::TOKEN_PARAM),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{path}?{}", query.join("&"))
+}
+
+async fn listing(
+    dir: &Path,
+    url_path: &str,
+    upload_form: bool,
+    dotfiles: Dotfiles,
+) -> std::io::Result<Vec<u8>> {
+    let mut names = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let mut name = entry.file_name().to_string_lossy().into_owned();
+        if !dotfiles.listed(&name) {
+            continue;
+        }
+        if entry.file_type().await?.is_dir() {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    names.sort();
+    let title = html_escape(url_path);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<meta charset=\"utf-8\">\n<title>Index of {title}</title>\n\
+         <h1>Index of {title}</h1>\n"
+    );
+    if upload_form {
+        html.push_str(
+            "<form method=\"post\" enctype=\"multipart/form-data\">\n\
+             <input type=\"file\" name=\"file\" multiple> <button>Upload</button>\n</form>\n",
+        );
+    }
+    html.push_str("<ul>\n");
+    if url_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in names {
+        let href = match name.strip_suffix('/') {
+            Some(dir) => format!("{}/", percent_encode(dir)),
+            None => percent_encode(&name),
+        };
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{href}\">{}</a></li>",
+            html_escape(&name)
+        );
+    }
+    html.push_str("</ul>\n");
+    Ok(html.into_bytes())
+}
+
+async fn file_response(path: &Path, options: &ServeOptions) -> Response {
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => return Response::text(403),
+        Err(_) => return Response::text(404),
+    };
+    let Ok(meta) = file.metadata().await else {
+        return Response::text(500);
+    };
+    let mut headers = vec![("Content-Type", content_type(path, &options.mime_types))];
+    if let Ok(etag) = fingerprint::etag(path, &meta).await {
+        headers.push(("ETag", etag));
+    }
+    if let Ok(modified) = meta.modified() {
+        let secs = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        headers.push(("Last-Modified", http_date(secs)));
+    }
+    Response {
+        status: 200,
+        headers,
+        body: Body::File(file, meta.len(), path.to_path_buf()),
+    }
+}
+
+/// Whether a missing path looks like an app route rather than a file, so a
+/// single-page app can handle it.
+fn is_app_route(url_path: &str) -> bool {
+    let last = url_path.rsplit('/').next().unwrap_or_default();
+    !last.contains('.')
+}
+
+async fn route(root: &Path, options: &ServeOptions, method: &str, target: &str) -> Response {
+    if method != "GET" && method != "HEAD" {
+        let mut res = Response::text(405);
+        res.headers.push(("Allow", "GET, HEAD".to_string()));
+        return res;
+    }
+    let raw_path = target.split(['?', '#']).next().unwrap_or("/");
+    let Some(url_path) = percent_decode(raw_path).filter(|p| p.starts_with('/')) else {
+        return Response::text(400);
+    };
+    let Some(mut path) = resolve(root, &url_path) else {
+        let index = root.join("index.html");
+        if options.spa && is_app_route(&url_path) && index.is_file() {
+            // The shell is shared by every route, so don't let it go stale
+            let mut res = file_response(&index, options).await;
+            res.headers.push(("Cache-Control", "no-cache".to_string()));
+            return res;
+        }
+        return Response::text(404);
+    };
+
+    if path.is_dir() {
+        if !url_path.ends_with('/') {
+            let mut res = Response::text(301);
+            res.headers.push(("Location", format!("{raw_path}/")));
+            return res;
+        }
+        let index = path.join("index.html");
+        if !index.is_file() {
+            if !options.listing {
+                return Response::text(404);
+            }
+            let upload_form = options.uploads.is_some();
+            return match listing(&path, &url_path, upload_form, options.dotfiles).await {
+                Ok(body) => Response {
+                    status: 200,
+                    headers: vec![("Content-Type", "text/html; charset=utf-8".to_string())],
+                    body: Body::Bytes(body),
+                },
+                Err(_) => Response::text(403),
+            };
+        }
+        path = index;
+    }
+    file_response(&path, options).await
+}
+
+/// Whether the client's cached copy, described by the request's
+/// conditional headers, is still current for `res`.
+fn not_modified(req: &Request<'_>, res: &Response) -> bool {
+    let header = |name: &str| res.header(name);
+    // If-None-Match wins over If-Modified-Since when both are sent
+    if let Some(tags) = req.header("If-None-Match") {
+        let Some(etag) = header("ETag") else {
+            return false;
+        };
+        return tags
+            .split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == "*" || t == etag);
+    }
+    let since = req.header("If-Modified-Since").and_then(parse_http_date);
+    let modified = header("Last-Modified").and_then(parse_http_date);
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+/// Mark the `ETag` as belonging to a variant of the file.
+fn suffix_etag(res: &mut Response, suffix: &str) {
+    for (name, value) in &mut res.headers {
+        if *name == "ETag" {
+            if let Some(tag) = value.strip_suffix('"') {
+                *value = format!("{tag}-{suffix}\"");
+            }
+        }
+    }
+}
+
+/// Mark `res` as encoded with `encoding`. Each encoding is a separate
+/// representation, so it gets its own `ETag`.
+fn set_encoding(res: &mut Response, encoding: Encoding) {
+    res.headers
+        .push(("Content-Encoding", encoding.token().to_string()));
+    suffix_etag(res, encoding.token());
+}
+
+/// Add the live reload script to an HTML response.
+async fn inject_live_reload(res: &mut Response) -> std::io::Result<()> {
+    let html = match &mut res.body {
+        Body::File(_, len, _) if *len > live_reload::MAX_INJECT_BYTES => return Ok(()),
+        Body::File(file, _, _) => {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes).await?;
+            bytes
+        }
+        Body::Bytes(bytes) => std::mem::take(bytes),
+    };
+    res.body = Body::Bytes(live_reload::inject(&html));
+    suffix_etag(res, "livereload");
+    Ok(())
+}
+
+/// Replace a Markdown file in `res` with its rendered page.
+async fn render_markdown(res: &mut Response) -> std::io::Result<()> {
+    let Body::File(file, len, path) = &mut res.body else {
+        return Ok(());
+    };
+    if *len > markdown::MAX_RENDER_BYTES || !markdown::is_markdown(path) {
+        return Ok(());
+    }
+    let mut source = String::new();
+    file.read_to_string(&mut source).await?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    res.body = Body::Bytes(markdown::render(&source, &name).into_bytes());
+    for (header, value) in &mut res.headers {
+        if *header == "Content-Type" {
+            *value = "text/html; charset=utf-8".to_string();
+        }
+    }
+    suffix_etag(res, "md");
+    Ok(())
+}
+
+/// Pick an encoding for a response. A pre-compressed sibling of a file
+/// replaces the body right away; an encoding that's returned still has to
+/// be applied with `compress_body`.
+async fn negotiate_encoding(
+    compression: &Compression,
+    req: &Request<'_>,
+    res: &mut Response,
+) -> Option<Encoding> {
+    let (len, path) = match &res.body {
+        Body::File(_, len, path) => (*len, Some(path.clone())),
+        Body::Bytes(bytes) => (bytes.len() as u64, None),
+    };
+    if !compression.compressible(res.header("Content-Type")?) {
+        return None;
+    }
+    res.headers.push(("Vary", "Accept-Encoding".to_string()));
+    let accepted = compression::accepted(req.header("Accept-Encoding"));
+    if let Some(path) = path.filter(|_| compression.precompressed) {
+        for &encoding in &accepted {
+            let mut sibling = path.clone().into_os_string();
+            sibling.push(format!(".{}", encoding.extension()));
+            let sibling = PathBuf::from(sibling);
+            let Ok(file) = tokio::fs::File::open(&sibling).await else {
+                continue;
+            };
+            match file.metadata().await {
+                Ok(meta) if meta.is_file() => {
+                    res.body = Body::File(file, meta.len(), sibling);
+                    set_encoding(res, encoding);
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+    if len < compression.min_bytes || len > compression::MAX_COMPRESS_BYTES {
+        return None;
+    }
+    let encoding = *accepted.first()?;
+    set_encoding(res, encoding);
+    Some(encoding)
+}
+
+/// Replace the body with its `encoding`-compressed bytes.
+async fn compress_body(res: &mut Response, encoding: Encoding) -> std::io::Result<()> {
+    let bytes = match &mut res.body {
+        Body::File(file, _, _) => {
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes).await?;
+            bytes
+        }
+        Body::Bytes(bytes) => std::mem::take(bytes),
+    };
+    let encoded = tokio::task::spawn_blocking(move || encoding.encode(&bytes))
+        .await
+        .map_err(std::io::Error::other)??;
+    res.body = Body::Bytes(encoded);
+    Ok(())
+}
+
+/// `route`, plus the matching `Cache-Control` rule, Markdown rendering, the
+/// live reload script, compression, and a 304 when the client's copy is
+/// current.
+async fn route_cached(root: &Path, options: &ServeOptions, req: &Request<'_>) -> Response {
+    let mut res = route(root, options, req.method, req.target).await;
+    if res.status != 200 {
+        return res;
+    }
+    let url_path = req.target.split(['?', '#']).next().unwrap_or("/");
+    let url_path = percent_decode(url_path).unwrap_or_default();
+    let has_cache_control = res.headers.iter().any(|(n, _)| *n == "Cache-Control");
+    if let Some(rule) = options.cache_control.iter().find(|r| r.matches(&url_path)) {
+        if !has_cache_control {
+            res.headers.push(("Cache-Control", rule.value.clone()));
+        }
+    }
+    let raw = query_param(req.target, markdown::RAW_PARAM).is_some_and(|v| v == "1");
+    if options.markdown && !raw && render_markdown(&mut res).await.is_err() {
+        return Response::text(500);
+    }
+    let is_html = res
+        .header("Content-Type")
+        .is_some_and(|t| t.starts_with("text/html"));
+    if options.live_reload.is_some() && is_html && inject_live_reload(&mut res).await.is_err() {
+        return Response::text(500);
+    }
+    let encoding = match &options.compression {
+        Some(compression) => negotiate_encoding(compression, req, &mut res).await,
+        None => None,
+    };
+    if not_modified(req, &res) {
+        let mut not_modified = Response::empty(304);
+        not_modified.headers = res
+            .headers
+            .into_iter()
+            .filter(|(n, _)| NOT_MODIFIED_HEADERS.contains(n))
+            .collect();
+        return not_modified;
+    }
+    if let Some(encoding) = encoding {
+        if compress_body(&mut res, encoding).await.is_err() {
+            return Response::text(500);
+        }
+    }
+    res
+}
+
+async fn respond(root: &Path, options: &ServeOptions, req: &Request<'_>) -> Response {
+    let handler = route_cached(root, options, req);
+    with_cors(options, req, guarded(options, req, handler)).await
+}
+
+/// Answer CORS preflights, and add CORS headers to `inner`'s response.
+async fn with_cors(
+    options: &ServeOptions,
+    req: &Request<'_>,
+    inner: impl Future<Output = Response>,
+) -> Response {
+    let origin = req.header("Origin");
+    let Some(cors) = &options.cors else {
+        return inner.await;
+    };
+    if req.method == "OPTIONS" && req.header("Access-Control-Request-Method").is_some() {
+        let mut res = Response::empty(204);
+        res.headers
+            .extend(cors.preflight_headers(origin, req.header("Access-Control-Request-Headers")));
+        return res;
+    }
+    let mut res = inner.await;
+    res.headers.extend(cors.response_headers(origin));
+    res
+}
+
+fn authorized(options: &ServeOptions, req: &Request<'_>) -> bool {
+    options
+        .auth
+        .as_ref()
+        .is_none_or(|auth| auth.check(&credentials(req)))
+}
+
+fn credentials<'a>(req: &Request<'a>) -> Credentials<'a> {
+    Credentials {
+        authorization: req.header("Authorization"),
+        query_token: query_param(req.target, auth::TOKEN_PARAM),
+        cookie: req.header("Cookie"),
+    }
+}
+
+/// Run `inner` if the request carries the credentials `options.auth` asks
+/// for.
+async fn guarded(
+    options: &ServeOptions,
+    req: &Request<'_>,
+    inner: impl Future<Output = Response>,
+) -> Response {
+    let Some(auth) = &options.auth else {
+        return inner.await;
+    };
+    let creds = credentials(req);
+    if !auth.check(&creds) {
+        let mut res = Response::text(401);
+        res.headers.push(("WWW-Authenticate", auth.challenge()));
+        return res;
+    }
+    let mut res = inner.await;
+    if let (Auth::Token { token }, Some(_)) = (auth, &creds.query_token) {
+        // Let the page's own requests in without the query parameter
+        res.headers.push((
+            "Set-Cookie",
+            format!(
+                "{}={token}; Path=/; HttpOnly; SameSite=Strict",
+                auth::TOKEN_COOKIE
+            ),
+        ));
+    }
+    res
+}
+
+/// How to read the request body, or the status (411, 413) refusing it.
+fn body_length(req: &Request<'_>, max: u64) -> Result<BodyLength, u16> {
+    if req
+        .header("Transfer-Encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        return Ok(BodyLength::Chunked { max });
+    }
+    match req
+        .header("Content-Length")
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        Some(len) if len > max => Err(413),
+        Some(len) => Ok(BodyLength::Fixed(len)),
+        None => Err(411),
+    }
+}
+
+fn expects_continue(req: &Request<'_>) -> bool {
+    req.header("Expect")
+        .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+}
+
+/// Save a `PUT` body as the file at the request path, or the files of a
+/// multipart `POST` into the folder at the request path. The target folder
+/// has to exist inside `root`.
+async fn upload<S: AsyncRead + AsyncWrite + Unpin>(
+    root: &Path,
+    options: &Uploads,
+    req: &Request<'_>,
+    stream: S,
+    leftover: Vec<u8>,
+    log: &mut RequestLog,
+) -> Response {
+    let length = match body_length(req, options.max_bytes) {
+        Ok(length) => length,
+        Err(status) => return Response::text(status),
+    };
+    let raw_path = req.target.split(['?', '#']).next().unwrap_or("/");
+    let Some(url_path) = percent_decode(raw_path).filter(|p| p.starts_with('/')) else {
+        return Response::text(400);
+    };
+    let mut body = BodyReader::new(stream, leftover, length, expects_continue(req));
+
+    let result = if req.method == "PUT" {
+        let (parent, name) = url_path.rsplit_once('/').unwrap_or_default();
+        let Some(name) = uploads::file_name(name) else {
+            return Response::text(400);
+        };
+        let Some(dir) = resolve(root, parent).filter(|d| d.is_dir()) else {
+            return Response::text(404);
+        };
+        uploads::save_put(&mut body, &dir.join(name), options)
+            .await
+            .map(|(bytes, replaced)| {
+                log.uploads.push(UploadedFile {
+                    path: url_path.clone(),
+                    bytes,
+                });
+                if replaced {
+                    Response::empty(204)
+                } else {
+                    Response::text(201)
+                }
+            })
+    } else {
+        let Some(dir) = resolve(root, &url_path).filter(|d| d.is_dir()) else {
+            return Response::text(404);
+        };
+        let Some(boundary) = req
+            .header("Content-Type")
+            .and_then(uploads::multipart_boundary)
+        else {
+            return Response::text(415);
+        };
+        uploads::save_multipart(&mut body, boundary, &dir, options)
+            .await
+            .map(|saved| {
+                let folder = url_path.trim_end_matches('/');
+                for (name, bytes) in saved {
+                    let path = format!("{folder}/{name}");
+                    log.uploads.push(UploadedFile { path, bytes });
+                }
+                // Back to the listing the form was posted from
+                let mut res = Response::text(303);
+                res.headers
+                    .push(("Location", format!("{}/", raw_path.trim_end_matches('/'))));
+                res
+            })
+    };
+    result.unwrap_or_else(|e| {
+        log.error = Some(e.to_string());
+        Response::text(e.status())
+    })
+}
+
+/// Decoded URL path of a request target, without query or fragment.
+fn target_path(target: &str) -> Option<String> {
+    let raw_path = target.split(['?', '#']).next().unwrap_or("/");
+    percent_decode(raw_path).filter(|p| p.starts_with('/'))
+}
+
+/// A decoded URL path with each segment percent-encoded again, for `href`s.
+fn encode_path(url_path: &str) -> String {
+    url_path
+        .split('/')
+        .map(percent_encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn xml_response(status: u16, xml: String) -> Response {
+    Response {
+        status,
+        headers: vec![("Content-Type", "application/xml; charset=utf-8".to_string())],
+        body: Body::Bytes(xml.into_bytes()),
+    }
+}
+
+fn io_status(e: &std::io::Error) -> u16 {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => 403,
+        std::io::ErrorKind::NotFound => 404,
+        _ => 500,
+    }
+}
+
+/// Where a new file or folder at `url_path` goes: a plain name in an
+/// existing folder inside `root`. Otherwise the status to refuse with.
+fn new_entry(root: &Path, url_path: &str) -> Result<PathBuf, u16> {
+    let (parent, name) = url_path
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .unwrap_or_default();
+    let Some(name) = uploads::file_name(name) else {
+        return Err(400);
+    };
+    let Some(dir) = resolve(root, parent).filter(|d| d.is_dir()) else {
+        return Err(409);
+    };
+    Ok(dir.join(name))
+}
+
+fn dav_resource(path: &Path, href: String, options: &ServeOptions) -> Option<webdav::Resource> {
+    let meta = std::fs::metadata(path).ok()?;
+    let modified = meta
+        .modified()
+        .ok()
+        .map(|m| http_date(m.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())));
+    Some(webdav::Resource {
+        href,
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        is_dir: meta.is_dir(),
+        len: meta.len(),
+        modified,
+        content_type: (!meta.is_dir()).then(|| content_type(path, &options.mime_types)),
+    })
+}
+
+/// `Depth: infinity` is answered like `1`, rather than walking a whole tree.
+fn dav_propfind(
+    root: &Path,
+    options: &ServeOptions,
+    req: &Request<'_>,
+    url_path: &str,
+) -> Response {
+    let Some(path) = resolve(root, url_path) else {
+        return Response::text(404);
+    };
+    let mut href = format!("{}{}", req.base, encode_path(url_path));
+    if path.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    let mut resources: Vec<_> = dav_resource(&path, href.clone(), options)
+        .into_iter()
+        .collect();
+    if path.is_dir() && req.header("Depth") != Some("0") {
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            return Response::text(403);
+        };
+        let mut children: Vec<_> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                options
+                    .dotfiles
+                    .listed(&entry.file_name().to_string_lossy())
+            })
+            .filter_map(|entry| {
+                let path = entry.path();
+                let mut child = format!(
+                    "{href}{}",
+                    percent_encode(&entry.file_name().to_string_lossy())
+                );
+                if path.is_dir() {
+                    child.push('/');
+                }
+                dav_resource(&path, child, options)
+            })
+            .collect();
+        children.sort_by(|a, b| a.href.cmp(&b.href));
+        resources.extend(children);
+    }
+    xml_response(207, webdav::multistatus(&resources))
+}
+
+fn dav_mkcol(root: &Path, url_path: &str, has_body: bool) -> Response {
+    if has_body {
+        return Response::text(415);
+    }
+    if resolve(root, url_path).is_some() {
+        let mut res = Response::text(405);
+        res.headers.push(("Allow", webdav::ALLOW.to_string()));
+        return res;
+    }
+    match new_entry(root, url_path).map(std::fs::create_dir) {
+        Ok(Ok(())) => Response::text(201),
+        Ok(Err(e)) => Response::text(io_status(&e)),
+        Err(status) => Response::text(status),
+    }
+}
+
+fn remove(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+fn dav_delete(root: &Path, dav: &WebDav, url_path: &str) -> Response {
+    let Some(path) = resolve(root, url_path) else {
+        return Response::text(404);
+    };
+    if path == root {
+        return Response::text(403);
+    }
+    match remove(&path) {
+        Ok(()) => {
+            dav.locks.forget(url_path);
+            Response::empty(204)
+        }
+        Err(e) => Response::text(io_status(&e)),
+    }
+}
+
+/// `COPY` or `MOVE` to the `Destination` header, which has to be on this
+/// server. `Overwrite: F` refuses to replace an existing target.
+fn dav_copy_move(root: &Path, dav: &WebDav, req: &Request<'_>, url_path: &str) -> Response {
+    let Some(from) = resolve(root, url_path) else {
+        return Response::text(404);
+    };
+    let Some(dest) = req
+        .header("Destination")
+        .and_then(webdav::destination_path)
+        .and_then(percent_decode)
+    else {
+        return Response::text(400);
+    };
+    // Only within the same mount
+    let Some(dest) = dest.strip_prefix(req.base).filter(|d| d.starts_with('/')) else {
+        return Response::text(403);
+    };
+    if !dav.locks.allows(dest, req.header("If")) {
+        return Response::text(423);
+    }
+    let to = match new_entry(root, dest) {
+        Ok(to) => to,
+        Err(status) => return Response::text(status),
+    };
+    // Also refuses copying a folder into itself
+    if from == root || to.starts_with(&from) {
+        return Response::text(403);
+    }
+    let existed = to.symlink_metadata().is_ok();
+    if existed {
+        if req
+            .header("Overwrite")
+            .is_some_and(|v| v.eq_ignore_ascii_case("F"))
+        {
+            return Response::text(412);
+        }
+        if let Err(e) = remove(&to) {
+            return Response::text(io_status(&e));
+        }
+        dav.locks.forget(dest);
+    }
+    let result = if req.method == "MOVE" {
+        std::fs::rename(&from, &to)
+    } else {
+        webdav::copy_recursive(&from, &to)
+    };
+    match result {
+        Ok(()) if req.method == "MOVE" => {
+            dav.locks.forget(url_path);
+            if existed {
+                Response::empty(204)
+            } else {
+                Response::text(201)
+            }
+        }
+        Ok(()) if existed => Response::empty(204),
+        Ok(()) => Response::text(201),
+        Err(e) => Response::text(io_status(&e)),
+    }
+}
+
+/// A `LOCK` with a body takes a new lock; without one it refreshes the
+/// lock named in `If`. Locking a path that doesn't exist yet creates an
+/// empty file there, as clients lock before they write.
+fn dav_lock(
+    root: &Path,
+    dav: &WebDav,
+    req: &Request<'_>,
+    url_path: &str,
+    has_body: bool,
+) -> Response {
+    let timeout = webdav::lock_timeout(req.header("Timeout"));
+    if !has_body {
+        return match req.header("If").and_then(|h| dav.locks.refresh(h, timeout)) {
+            Some((path, token)) => xml_response(
+                200,
+                webdav::lock_discovery(
+                    &format!("{}{}", req.base, encode_path(&path)),
+                    &token,
+                    timeout,
+                ),
+            ),
+            None => Response::text(412),
+        };
+    }
+    let Some(token) = dav.locks.lock(url_path, timeout) else {
+        return Response::text(423);
+    };
+    let mut status = 200;
+    if resolve(root, url_path).is_none() {
+        let created = new_entry(root, url_path).and_then(|path| {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .map_err(|e| io_status(&e))
+        });
+        if let Err(status) = created {
+            dav.locks.unlock(&token);
+            return Response::text(status);
+        }
+        status = 201;
+    }
+    let href = format!("{}{}", req.base, encode_path(url_path));
+    let discovery = webdav::lock_discovery(&href, &token, timeout);
+    let mut res = xml_response(status, discovery);
+    res.headers.push(("Lock-Token", format!("<{token}>")));
+    res
+}
+
+/// Handle a DAV method. Ones that change files need `options.auth`,
+/// except `PUT` when uploads are turned on by themselves, and can't touch
+/// a locked path without the lock's token in `If`.
+async fn webdav<S: AsyncRead + AsyncWrite + Unpin>(
+    root: &Path,
+    options: &ServeOptions,
+    dav: &WebDav,
+    req: &Request<'_>,
+    stream: S,
+    leftover: Vec<u8>,
+    log: &mut RequestLog,
+) -> Response {
+    let anonymous_put = req.method == "PUT" && options.uploads.is_some();
+    if webdav::WRITE_METHODS.contains(&req.method) && options.auth.is_none() && !anonymous_put {
+        return Response::text(403);
+    }
+    if req.method == "OPTIONS" {
+        let mut res = Response::empty(200);
+        res.headers.extend([
+            ("DAV", "1, 2".to_string()),
+            ("Allow", webdav::ALLOW.to_string()),
+            ("MS-Author-Via", "DAV".to_string()),
+        ]);
+        return res;
+    }
+    let Some(url_path) = target_path(req.target) else {
+        return Response::text(400);
+    };
+    if matches!(
+        req.method,
+        "PUT" | "DELETE" | "MOVE" | "MKCOL" | "PROPPATCH"
+    ) && !dav.locks.allows(&url_path, req.header("If"))
+    {
+        return Response::text(423);
+    }
+    if req.method == "PUT" {
+        // Clients save over files in place
+        let uploads = options.uploads.clone().unwrap_or(Uploads {
+            overwrite: true,
+            ..Uploads::default()
+        });
+        return upload(root, &uploads, req, stream, leftover, log).await;
+    }
+    // Property lists and lock owners aren't used, but are read so the
+    // client isn't cut off mid-send
+    let has_body = match body_length(req, MAX_DAV_BODY_BYTES) {
+        Ok(BodyLength::Fixed(0)) | Err(411) => false,
+        Ok(length) => {
+            let mut body = BodyReader::new(stream, leftover, length, expects_continue(req));
+            if body.copy_to(&mut tokio::io::sink()).await.is_err() {
+                return Response::text(400);
+            }
+            true
+        }
+        Err(status) => return Response::text(status),
+    };
+    match req.method {
+        "PROPFIND" => dav_propfind(root, options, req, &url_path),
+        "PROPPATCH" => match resolve(root, &url_path) {
+            Some(_) => {
+                let href = format!("{}{}", req.base, encode_path(&url_path));
+                xml_response(207, webdav::proppatch_ok(&href))
+            }
+            None => Response::text(404),
+        },
+        "MKCOL" => dav_mkcol(root, &url_path, has_body),
+        "DELETE" => dav_delete(root, dav, &url_path),
+        "COPY" | "MOVE" => dav_copy_move(root, dav, req, &url_path),
+        "LOCK" => dav_lock(root, dav, req, &url_path, has_body),
+        "UNLOCK" => match req.header("Lock-Token") {
+            Some(token) if dav.locks.unlock(token) => Response::empty(204),
+            _ => Response::text(409),
+        },
+        _ => Response::text(405),
+    }
+}
+
+/// Find the folder `req` is for among `options.mounts`, falling back to
+/// `root`, and `dispatch_in` it.
+async fn dispatch<S: AsyncRead + AsyncWrite + Unpin>(
+    root: &Path,
+    options: &ServeOptions,
+    req: &Request<'_>,
+    stream: S,
+    leftover: Vec<u8>,
+    log: &mut RequestLog,
+) -> Response {
+    let Some((mount, rest)) = mounts::matching(&options.mounts, req.header("Host"), req.target)
+    else {
+        return dispatch_in(root, options, req, stream, leftover, log).await;
+    };
+    let base = mount.base();
+    // Links in the mount's pages are relative to its folder
+    if !rest.starts_with('/') && matches!(req.method, "GET" | "HEAD") {
+        let mut res = Response::text(301);
+        res.headers.push(("Location", format!("{base}/{rest}")));
+        return res;
+    }
+    let target = if rest.starts_with('/') {
+        rest.to_string()
+    } else {
+        format!("/{rest}")
+    };
+    let req = Request {
+        method: req.method,
+        target: &target,
+        headers: req.headers.clone(),
+        base,
+    };
+    let mut res = dispatch_in(&mount.root, options, &req, stream, leftover, log).await;
+    for (name, value) in &mut res.headers {
+        if *name == "Location" && value.starts_with('/') {
+            *value = format!("{base}{value}");
+        }
+    }
+    res
+}
+
+/// Pick what handles `req` under `root`: DAV, uploads, or plain file
+/// serving.
+async fn dispatch_in<S: AsyncRead + AsyncWrite + Unpin>(
+    root: &Path,
+    options: &ServeOptions,
+    req: &Request<'_>,
+    stream: S,
+    leftover: Vec<u8>,
+    log: &mut RequestLog,
+) -> Response {
+    // Whatever the method, as if nothing were there
+    let denied = |path: &str| percent_decode(path).is_some_and(|p| !options.dotfiles.allows(&p));
+    if denied(req.target.split(['?', '#']).next().unwrap_or("/")) {
+        return Response::text(404);
+    }
+    let dav = options
+        .webdav
+        .as_ref()
+        .filter(|_| webdav::METHODS.contains(&req.method));
+    if let Some(dav) = dav {
+        // Nor can anything be copied or moved there
+        let destination = req.header("Destination").and_then(webdav::destination_path);
+        if destination.is_some_and(denied) {
+            return Response::text(403);
+        }
+        let handler = webdav(root, options, dav, req, stream, leftover, log);
+        return with_cors(options, req, guarded(options, req, handler)).await;
+    }
+    match &options.uploads {
+        Some(uploads) if matches!(req.method, "PUT" | "POST") => {
+            let handler = upload(root, uploads, req, stream, leftover, log);
+            with_cors(options, req, guarded(options, req, handler)).await
+        }
+        _ => respond(root, options, req).await,
+    }
+}
+
+/// Answer `req` if it's for one of the health endpoints.
+fn health_check(root: &Path, options: &ServeOptions, req: &Request<'_>) -> Option<Response> {
+    let health = options.health.as_ref()?;
+    let endpoint = health.endpoint(req.target)?;
+    if !matches!(req.method, "GET" | "HEAD") {
+        let mut res = Response::text(405);
+        res.headers.push(("Allow", "GET, HEAD".to_string()));
+        return Some(res);
+    }
+    let report = health.status(root, &options.stats);
+    let code = if report.status == "ok" { 200 } else { 503 };
+    let mut res = match endpoint {
+        Endpoint::Health => Response::text(code),
+        Endpoint::Status => Response {
+            status: code,
+            headers: vec![("Content-Type", "application/json".to_string())],
+            body: Body::Bytes(serde_json::to_vec(&report).unwrap_or_default()),
+        },
+    };
+    res.headers.push(("Cache-Control", "no-store".to_string()));
+    Some(res)
+}
+
+/// Answer a request under `dlna::PREFIX`: descriptions, SOAP actions, and
+/// event subscriptions, which are accepted but never sent anything.
+async fn dlna_request<S: AsyncRead + AsyncWrite + Unpin>(
+    root: &Path,
+    options: &ServeOptions,
+    dlna: &Dlna,
+    endpoint: dlna::Endpoint,
+    req: &Request<'_>,
+    stream: S,
+    leftover: Vec<u8>,
+) -> Response {
+    let xml = |status, xml: String| Response {
+        status,
+        headers: vec![("Content-Type", "text/xml; charset=\"utf-8\"".to_string())],
+        body: Body::Bytes(xml.into_bytes()),
+    };
+    match (endpoint, req.method) {
+        (dlna::Endpoint::Description, "GET" | "HEAD") => xml(200, dlna.description()),
+        (dlna::Endpoint::Service(service), "GET" | "HEAD") => xml(200, dlna::scpd(service)),
+        (dlna::Endpoint::Control(service), "POST") => {
+            let mut body = Vec::new();
+            match body_length(req, MAX_SOAP_BODY_BYTES) {
+                Ok(length) => {
+                    let mut reader =
+                        BodyReader::new(stream, leftover, length, expects_continue(req));
+                    if reader.copy_to(&mut body).await.is_err() {
+                        return Response::text(400);
+                    }
+                }
+                Err(status) => return Response::text(status),
+            }
+            let action = req
+                .header("SOAPAction")
+                .and_then(dlna::soap_action)
+                .unwrap_or_default();
+            // Media URLs use the address the player reached us at
+            let base_url = format!("http://{}", req.header("Host").unwrap_or_default());
+            let mime_type = |path: &Path| content_type(path, &options.mime_types);
+            let body = String::from_utf8_lossy(&body);
+            let library = dlna::Library {
+                root,
+                dotfiles: options.dotfiles,
+                mime_type: &mime_type,
+            };
+            let (status, answer) = library.control(service, action, &body, &base_url);
+            xml(status, answer)
+        }
+        (dlna::Endpoint::Event, "SUBSCRIBE") => {
+            let mut res = Response::empty(200);
+            res.headers.extend([
+                ("SID", format!("uuid:{}", uuid::Uuid::new_v4())),
+                ("TIMEOUT", "Second-1800".to_string()),
+            ]);
+            res
+        }
+        (dlna::Endpoint::Event, "UNSUBSCRIBE") => Response::empty(200),
+        _ => Response::text(405),
+    }
+}
+
+/// What of a request's body arrived with its head, if it has one.
+fn request_body(req: &Request<'_>, leftover: &[u8], max: usize) -> Option<CapturedBody> {
+    let length = req
+        .header("Content-Length")
+        .and_then(|l| l.parse::<usize>().ok());
+    if leftover.is_empty() && length.unwrap_or(0) == 0 {
+        return None;
+    }
+    let complete = length == Some(leftover.len());
+    Some(CapturedBody::new(leftover, max, complete))
+}
+
+/// Record `req` and the response about to be sent for it. A file body's
+/// start is read, then the file is rewound for sending.
+async fn capture(
+    req: &Request<'_>,
+    time: SystemTime,
+    request_body: Option<CapturedBody>,
+    res: &mut Response,
+    max: usize,
+    log: &RequestLog,
+) -> std::io::Result<Exchange> {
+    let mut response_headers = inspector::capture_headers(
+        res.headers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str())),
+    );
+    if !matches!(res.status, 204 | 304) {
+        response_headers.push(("Content-Length".to_string(), res.len().to_string()));
+    }
+    let response_body = match &mut res.body {
+        _ if req.method == "HEAD" => None,
+        Body::Bytes(bytes) if bytes.is_empty() => None,
+        Body::Bytes(bytes) => Some(CapturedBody::new(bytes, max, true)),
+        Body::File(file, len, _) => {
+            let mut start = Vec::new();
+            (&mut *file)
+                .take(max as u64)
+                .read_to_end(&mut start)
+                .await?;
+            file.seek(std::io::SeekFrom::Start(0)).await?;
+            Some(CapturedBody::new(&start, max, start.len() as u64 == *len))
+        }
+    };
+    Ok(Exchange {
+        id: 0,
+        request_id: log.request_id.clone(),
+        time_ms: time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+        remote_address: log.remote_address.clone(),
+        method: req.method.to_string(),
+        path: log.path.clone(),
+        request_headers: inspector::capture_headers(req.headers.iter().copied()),
+        request_body,
+        status: res.status,
+        response_headers,
+        response_body,
+        bytes: if req.method == "HEAD" { 0 } else { res.len() },
+        duration_ms: 0,
+    })
+}
+
+/// Address of the client behind `log`, if it came over the network.
+fn peer_ip(log: &RequestLog) -> Option<IpAddr> {
+    log.remote_address
+        .parse::<SocketAddr>()
+        .ok()
+        .map(|a| a.ip())
+}
+
+/// Forward `req` to `route`'s upstream and relay the answer straight to
+/// the client. `Err` is the response to send instead when the upstream
+/// can't be reached or sends nothing.
+async fn proxy<S: AsyncRead + AsyncWrite + Unpin>(
+    route: &ProxyRoute,
+    options: &ServeOptions,
+    req: &Request<'_>,
+    stream: &mut S,
+    leftover: Vec<u8>,
+    log: &mut RequestLog,
+) -> Result<(), Response> {
+    let upstream = Upstream::parse(&route.target).map_err(|_| Response::text(502))?;
+    let mut target = route.upstream_target(&upstream, req.target);
+    // This server's own credentials aren't passed on
+    let mut redact = proxy::Redact::default();
+    if let Some(auth) = &options.auth {
+        redact.authorization = auth.check(&Credentials {
+            authorization: req.header("Authorization"),
+            ..Credentials::default()
+        });
+        if matches!(auth, Auth::Token { .. }) {
+            target = proxy::strip_query_param(&target, auth::TOKEN_PARAM);
+            redact.cookie = Some(auth::TOKEN_COOKIE);
+        }
+    }
+    let client_ip = peer_ip(log).map_or_else(|| log.remote_address.clone(), |ip| ip.to_string());
+    let mut head = proxy::request_head(
+        req.method,
+        &target,
+        &req.headers,
+        &upstream,
+        &client_ip,
+        if options.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        },
+        &redact,
+    )
+    .into_bytes();
+    head.extend_from_slice(&leftover);
+
+    let failed = |log: &mut RequestLog, error: String, status: u16| {
+        log.error = Some(error);
+        Response::text(status)
+    };
+    let tcp = match tokio::time::timeout(PROXY_CONNECT_TIMEOUT, proxy::connect(&upstream)).await {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => return Err(failed(log, format!("proxy connect failed: {e}"), 502)),
+        Err(_) => return Err(failed(log, "proxy connect timed out".to_string(), 504)),
+    };
+    let relayed = if upstream.tls {
+        let tls = tokio::time::timeout(PROXY_CONNECT_TIMEOUT, proxy::connect_tls(&upstream, tcp));
+        match tls.await {
+            Ok(Ok(tls)) => proxy::relay(stream, tls, &head).await,
+            Ok(Err(e)) => return Err(failed(log, format!("proxy TLS failed: {e}"), 502)),
+            Err(_) => return Err(failed(log, "proxy TLS timed out".to_string(), 504)),
+        }
+    } else {
+        proxy::relay(stream, tcp, &head).await
+    };
+    match relayed {
+        Ok(Some((status, bytes))) => {
+            log.status = status;
+            log.bytes = bytes;
+            Ok(())
+        }
+        Ok(None) => Err(failed(log, "proxy upstream sent nothing".to_string(), 502)),
+        // Part of the answer may have gone out, so there's no clean response
+        Err(e) => {
+            log.status = 502;
+            log.error = Some(format!("proxy failed: {e}"));
+            Ok(())
+        }
+    }
+}
+
+/// Stream change events until the client goes away or the server stops.
+async fn live_reload_events<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    live_reload: &LiveReload,
+) -> std::io::Result<()> {
+    let (mut changes, mut stopped) = live_reload.subscribe();
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+              Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    loop {
+        let chunk = tokio::select! {
+            change = changes.recv() => match change {
+                Ok(batch) => live_reload::event(&batch),
+                // Missed some, which reloads the page
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    live_reload::event(&Batch::overflowed())
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = stopped.changed() => break,
+            () = tokio::time::sleep(live_reload::KEEPALIVE) => ": keepalive\n\n".to_string(),
+        };
+        stream.write_all(chunk.as_bytes()).await?;
+    }
+    stream.shutdown().await
+}
+
+/// Read the request head, and whatever part of the body came with it;
+/// `None` if the client sent something unusable.
+async fn read_head<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> std::io::Result<Option<(String, Vec<u8>)>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let end = loop {
+        if let Some(at) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break at + 4;
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Ok(None);
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let body = buf.split_off(end);
+    Ok(String::from_utf8(buf).ok().map(|head| (head, body)))
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    root: &Path,
+    options: &ServeOptions,
+    log: &mut RequestLog,
+) -> std::io::Result<()> {
+    let Some((head, leftover)) = read_head(&mut stream).await? else {
+        log.error = Some("bad request".to_string());
+        return Ok(());
+    };
+    let req = Request::parse(&head);
+    let method = req.as_ref().map_or("", |r| r.method);
+    // Taken before `leftover` is handed on
+    let arrival = options
+        .inspector
+        .as_ref()
+        .zip(req.as_ref())
+        .map(|(inspector, req)| {
+            let request_body = request_body(req, &leftover, inspector.options.max_body_bytes);
+            (SystemTime::now(), request_body)
+        });
+    let mut _in_flight = None;
+    let mut res = match &req {
+        Some(req) => {
+            req.method.clone_into(&mut log.method);
+            log.path = redact_token(req.target);
+            log.user_agent = req.header("User-Agent").map(str::to_string);
+            log.referer = req.header("Referer").map(str::to_string);
+            let admitted = match (&options.rate_limit, peer_ip(log)) {
+                (Some(limiter), Some(ip)) => limiter.admit(ip).map(Some),
+                _ => Ok(None),
+            };
+            match admitted {
+                Err(refusal) => {
+                    log.error = Some(format!("rate limited: {}", refusal.reason));
+                    let mut res = Response::text(429);
+                    res.headers
+                        .push(("Retry-After", refusal.retry_after.to_string()));
+                    res
+                }
+                Ok(permit) => {
+                    // Held until the response has been sent
+                    _in_flight = permit;
+                    let events = options.live_reload.as_ref().filter(|_| {
+                        req.target.split('?').next() == Some(live_reload::EVENTS_PATH)
+                            && authorized(options, req)
+                    });
+                    if let Some(live_reload) = events {
+                        log.status = 200;
+                        return live_reload_events(&mut stream, live_reload).await;
+                    }
+                    // Ahead of proxies and mounts, and open to monitors without auth
+                    let dlna = options.dlna.as_ref().zip(dlna::endpoint(req.target));
+                    if let Some(res) = health_check(root, options, req) {
+                        res
+                    } else if let Some((dlna, endpoint)) = dlna {
+                        dlna_request(root, options, dlna, endpoint, req, &mut stream, leftover)
+                            .await
+                    } else {
+                        match with_plugins(options, req, log) {
+                            Err(res) => *res,
+                            Ok(rewritten) => {
+                                let reparsed = rewritten.as_deref().and_then(Request::parse);
+                                let req = reparsed.as_ref().unwrap_or(req);
+                                let route = proxy::matching(&options.proxies, req.target)
+                                    .filter(|_| authorized(options, req));
+                                match route {
+                                    Some(route) => {
+                                        let proxied =
+                                            proxy(route, options, req, &mut stream, leftover, log);
+                                        match proxied.await {
+                                            Ok(()) => {
+                                                // Already closed by the upstream's end of a
+                                                // WebSocket
+                                                stream.shutdown().await.ok();
+                                                return Ok(());
+                                            }
+                                            Err(res) => res,
+                                        }
+                                    }
+                                    // Unauthorized proxy requests get their 401 here
+                                    None => {
+                                        dispatch(root, options, req, &mut stream, leftover, log)
+                                            .await
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        None => Response::text(400),
+    };
+    let streamed = req
+        .as_ref()
+        .is_some_and(|r| r.header(dlna::FEATURES_REQUEST).is_some());
+    if options.dlna.is_some() && streamed && matches!(res.status, 200 | 206) {
+        res.headers.extend([
+            ("transferMode.dlna.org", "Streaming".to_string()),
+            (
+                "contentFeatures.dlna.org",
+                dlna::CONTENT_FEATURES.to_string(),
+            ),
+        ]);
+    }
+    log.status = res.status;
+    if let (Some(inspector), Some(req), Some((time, request_body))) =
+        (&options.inspector, &req, arrival)
+    {
+        let max = inspector.options.max_body_bytes;
+        let exchange = capture(req, time, request_body, &mut res, max, log).await?;
+        log.capture = Some(Box::new(exchange));
+    }
+
+    let mut out = format!("HTTP/1.1 {} {}\r\n", res.status, reason(res.status));
+    for (name, value) in &res.headers {
+        let _ = write!(out, "{name}: {value}\r\n");
+    }
+    // No body length for 204 and 304, whose bodies are always empty
+    if !matches!(res.status, 204 | 304) {
+        let _ = write!(out, "Content-Length: {}\r\n", res.len());
+    }
+    if let Some(alt_svc) = options.tls.as_ref().and_then(|t| t.alt_svc.as_ref()) {
+        let _ = write!(out, "Alt-Svc: {alt_svc}\r\n");
+    }
+    out.push_str("Connection: close\r\n\r\n");
+    stream.write_all(out.as_bytes()).await?;
+    if method != "HEAD" {
+        log.bytes = res.len();
+        match &mut res.body {
+            Body::Bytes(bytes) => stream.write_all(bytes).await?,
+            Body::File(file, _, _) => {
+                tokio::io::copy(file, &mut stream).await?;
+            }
+        }
+    }
+    stream.shutdown().await
+}
+
+/// Run `options.plugins` on `req`: `Ok` with the head to parse in its
+/// place if they changed it, `Err` with their answer.
+fn with_plugins(
+    options: &ServeOptions,
+    req: &Request<'_>,
+    log: &mut RequestLog,
+) -> Result<Option<String>, Box<Response>> {
+    if options.plugins.is_empty() {
+        return Ok(None);
+    }
+    let request = PluginRequest {
+        method: req.method.to_string(),
+        path: req.target.to_string(),
+        headers: req
+            .headers
+            .iter()
+            .map(|(name, value)| ((*name).to_string(), (*value).to_string()))
+            .collect(),
+        remote_address: log.remote_address.clone(),
+    };
+    match options.plugins.run(request) {
+        Outcome::Continue(changed) => Ok(changed.map(|req| req.head())),
+        Outcome::Respond(res) => Err(Box::new(Response {
+            status: res.status,
+            headers: res.allowed_headers(),
+            body: Body::Bytes(res.body.into_bytes()),
+        })),
+        Outcome::Failed(e) => {
+            tracing::warn!("{e}");
+            log.error = Some(e);
+            Err(Box::new(Response::text(500)))
+        }
+    }
+}
+
+/// Log a client turned away by `options.ip_filter`; `true` if it was.
+fn rejected(
+    peer: SocketAddr,
+    request_id: String,
+    options: &ServeOptions,
+    log: &impl Fn(&RequestLog),
+) -> bool {
+    let Some(Err(reason)) = options.ip_filter.as_ref().map(|f| f.check(peer.ip())) else {
+        return false;
+    };
+    let mut entry = RequestLog::new(peer, request_id);
+    entry.error = Some(format!("rejected: {reason}"));
+    log(&entry);
+    true
+}
+
+/// Handle the request on `stream`, which came from `peer`, and log it.
+async fn serve_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    peer: SocketAddr,
+    request_id: String,
+    root: &Path,
+    options: &ServeOptions,
+    log: &impl Fn(&RequestLog),
+) {
+    let started = Instant::now();
+    let mut entry = RequestLog::new(peer, request_id);
+    if let Err(e) = handle_connection(stream, root, options, &mut entry).await {
+        entry.error = Some(format!("connection error: {e}"));
+    }
+    entry.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    if let (Some(inspector), Some(mut exchange)) = (&options.inspector, entry.capture.take()) {
+        exchange.duration_ms = entry.duration_ms;
+        entry.capture = Some(Box::new(inspector.push(*exchange)));
+    }
+    log(&entry);
+}
+
+/// Send a plain HTTP request that reached a TLS port to the same URL over
+/// HTTPS, and log it.
+async fn redirect_to_https(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    request_id: String,
+    log: &impl Fn(&RequestLog),
+) {
+    let started = Instant::now();
+    let mut entry = RequestLog::new(peer, request_id);
+    let result = async {
+        let Some((head, _)) = read_head(&mut stream).await? else {
+            entry.error = Some("bad request".to_string());
+            return Ok(());
+        };
+        let req = Request::parse(&head);
+        let location = req.as_ref().and_then(|req| {
+            req.method.clone_into(&mut entry.method);
+            entry.path = redact_token(req.target);
+            let host = req.header("Host").filter(|h| !h.is_empty())?;
+            req.target
+                .starts_with('/')
+                .then(|| format!("https://{host}{}", req.target))
+        });
+        let mut res = Response::text(if location.is_some() { 301 } else { 400 });
+        if let Some(location) = location {
+            res.headers.push(("Location", location));
+        }
+        entry.status = res.status;
+        let mut out = format!("HTTP/1.1 {} {}\r\n", res.status, reason(res.status));
+        for (name, value) in &res.headers {
+            let _ = write!(out, "{name}: {value}\r\n");
+        }
+        let _ = write!(
+            out,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            res.len()
+        );
+        stream.write_all(out.as_bytes()).await?;
+        if let Body::Bytes(body) = &res.body {
+            stream.write_all(body).await?;
+            entry.bytes = res.len();
+        }
+        stream.shutdown().await
+    };
+    if let Err(e) = result.await {
+        entry.error = Some(format!("connection error: {e}"));
+    }
+    entry.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    log(&entry);
+}
+
+/// Finish the TLS handshake, then serve HTTP/2 if the client chose it and
+/// a single HTTP/1.1 request otherwise. `connection` numbers the requests.
+async fn serve_tls<L: Fn(&RequestLog) + Send + Sync + 'static>(
+    stream: TcpStream,
+    peer: SocketAddr,
+    connection: u64,
+    tls: &Tls,
+    root: Arc<PathBuf>,
+    options: ServeOptions,
+    log: Arc<L>,
+) {
+    let handshake = TlsAcceptor::from(tls.config.clone()).accept(stream);
+    // Failed handshakes never became requests, so they aren't logged
+    let Ok(Ok(stream)) = tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, handshake).await else {
+        return;
+    };
+    if stream.get_ref().1.alpn_protocol() == Some(b"h2") {
+        let streams = AtomicU64::new(0);
+        let handle = |pipe| {
+            let (root, options, log) = (root.clone(), options.clone(), log.clone());
+            let request_id = format!(
+                "{connection}.{}",
+                streams.fetch_add(1, Ordering::Relaxed) + 1
+            );
+            async move { serve_request(pipe, peer, request_id, &root, &options, &*log).await }
+        };
+        http2::serve_connection(stream, handle).await.ok();
+    } else {
+        let request_id = connection.to_string();
+        serve_request(stream, peer, request_id, &root, &options, &*log).await;
+    }
+}
+
+/// Answer HTTP/3 on `endpoint` until the future is dropped.
+#[cfg(feature = "http3")]
+async fn serve_http3<L: Fn(&RequestLog) + Send + Sync + 'static>(
+    endpoint: quinn::Endpoint,
+    root: Arc<PathBuf>,
+    options: SharedOptions,
+    log: Arc<L>,
+) {
+    let handle = move |pipe, peer| {
+        let root = root.clone();
+        let options = options.read().unwrap().clone();
+        let log = log.clone();
+        let connection = options.stats.connection();
+        let request_id = connection.id().to_string();
+        async move {
+            let _connection = connection;
+            // Dropping the pipe resets the stream
+            if !rejected(peer, request_id.clone(), &options, &*log) {
+                serve_request(pipe, peer, request_id, &root, &options, &*log).await;
+            }
+        }
+    };
+    http3::serve(endpoint, handle).await;
+}
+
+/// Serve files under `root` until the task is dropped, logging each
+/// request with `log`.
+pub async fn serve<L: Fn(&RequestLog) + Send + Sync + 'static>(
+    listener: TcpListener,
+    root: PathBuf,
+    options: SharedOptions,
+    log: L,
+) -> std::io::Result<()> {
+    let root = Arc::new(std::fs::canonicalize(&root)?);
+    let stats = options.read().unwrap().stats.clone();
+    let log = Arc::new(move |entry: &RequestLog| {
+        stats.record(entry);
+        log(entry);
+    });
+    // Dropped, stopping the watcher, when this task is
+    let live_reload = options.read().unwrap().live_reload.clone();
+    let _watcher = live_reload.map(|l| l.watch(root.to_path_buf()));
+    #[cfg(feature = "http3")]
+    {
+        let tls = options.read().unwrap().tls.clone();
+        if let Some(tls) = tls.filter(|t| t.options.http3) {
+            let endpoint = http3::endpoint(listener.local_addr()?, tls.http3_config())
+                .map_err(std::io::Error::other)?;
+            let http3 = serve_http3(endpoint, root.clone(), options.clone(), log.clone());
+            return tokio::select! {
+                result = accept_loop(listener, root, options, log) => result,
+                () = http3 => Ok(()),
+            };
+        }
+    }
+    accept_loop(listener, root, options, log).await
+}
+
+/// Serve each TCP connection on its own task.
+async fn accept_loop<L: Fn(&RequestLog) + Send + Sync + 'static>(
+    listener: TcpListener,
+    root: Arc<PathBuf>,
+    options: SharedOptions,
+    log: Arc<L>,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("accept error: {e}");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let options = options.read().unwrap().clone();
+        let connection = options.stats.connection();
+        let id = connection.id();
+        // Dropping the stream closes it
+        if rejected(peer, id.to_string(), &options, &*log) {
+            continue;
+        }
+        let root = root.clone();
+        let log = log.clone();
+        tokio::spawn(async move {
+            let _connection = connection;
+            match options.tls.clone() {
+                Some(tls)
+                    if tls.options.redirect_http
+                        && sniff::peek(&stream, tls::HANDSHAKE_TIMEOUT).await == Protocol::Http =>
+                {
+                    redirect_to_https(stream, peer, id.to_string(), &*log).await;
+                }
+                Some(tls) => Box::pin(serve_tls(stream, peer, id, &tls, root, options, log)).await,
+                None => serve_request(stream, peer, id.to_string(), &root, &options, &*log).await,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::HealthOptions;
+    use crate::inspector::InspectorOptions;
+    use crate::ip_filter::IpFilterOptions;
+    use crate::rate_limit::RateLimitOptions;
+
+    async fn get(root: &Path, options: &ServeOptions, target: &str) -> Response {
+        let head = format!("GET {target} HTTP/1.1\r\nHost: x\r\n\r\n");
+        respond(root, options, &Request::parse(&head).unwrap()).await
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let head = "GET /a?b HTTP/1.1\r\nHost: x\r\norigin: http://a.test\r\n\r\n";
+        let req = Request::parse(head).unwrap();
+        assert_eq!((req.method, req.target), ("GET", "/a?b"));
+        assert_eq!(req.header("Origin"), Some("http://a.test"));
+        assert_eq!(req.header("Cookie"), None);
+        assert!(Request::parse("GET\r\n\r\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_respond() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/a.css"), "body{}").unwrap();
+        let options = ServeOptions::default();
+
+        let res = get(&root, &options, "/docs/a.css?v=1").await;
+        assert_eq!(res.status, 200);
+        assert_eq!(res.len(), 6);
+        assert_eq!(res.headers[0].1, "text/css; charset=utf-8");
+
+        assert_eq!(get(&root, &options, "/docs").await.status, 301);
+        assert_eq!(get(&root, &options, "/docs/").await.status, 200);
+        assert_eq!(get(&root, &options, "/nope").await.status, 404);
+        assert_eq!(get(&root, &options, "nope").await.status, 400);
+        let post = Request::parse("POST / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(respond(&root, &options, &post).await.status, 405);
+    }
+
+    #[tokio::test]
+    async fn test_respond_options() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir(root.join("empty")).unwrap();
+        std::fs::write(root.join("index.html"), "<div id=app>").unwrap();
+        let options = ServeOptions {
+            spa: true,
+            listing: false,
+            ..ServeOptions::default()
+        };
+
+        let res = get(&root, &options, "/settings/profile").await;
+        assert_eq!((res.status, res.len()), (200, 12));
+        assert!(res
+            .headers
+            .contains(&("Cache-Control", "no-cache".to_string())));
+        assert_eq!(get(&root, &options, "/missing.js").await.status, 404);
+        assert_eq!(get(&root, &options, "/empty/").await.status, 404);
+        assert_eq!(
+            get(&root, &ServeOptions::default(), "/settings")
+                .await
+                .status,
+            404
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dotfiles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir(root.join(".git")).unwrap();
+        std::fs::write(root.join(".git/config"), "[core]").unwrap();
+        std::fs::write(root.join("Thumbs.db"), "").unwrap();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        let listed = |res: &Response| {
+            let html = body_text(res);
+            (html.contains(".git/"), html.contains("Thumbs.db"))
+        };
+        let res = get(&root, &ServeOptions::default(), "/").await;
+        assert_eq!(listed(&res), (true, true));
+
+        let hide = ServeOptions {
+            dotfiles: Dotfiles::Hide,
+            ..ServeOptions::default()
+        };
+        let res = get(&root, &hide, "/").await;
+        assert_eq!(listed(&res), (false, false));
+        assert!(body_text(&res).contains("a.txt"));
+        assert_eq!(get(&root, &hide, "/.git/config").await.status, 200);
+
+        let deny = ServeOptions {
+            dotfiles: Dotfiles::Deny,
+            webdav: Some(WebDav::default()),
+            ..ServeOptions::default()
+        };
+        let head = "GET /.git/config HTTP/1.1\r\n\r\n";
+        assert_eq!(send(&root, &deny, head, b"").await.0.status, 404);
+        let head = "GET /%2Egit/ HTTP/1.1\r\n\r\n";
+        assert_eq!(send(&root, &deny, head, b"").await.0.status, 404);
+        let head = "PROPFIND / HTTP/1.1\r\nDepth: 1\r\n\r\n";
+        let (res, _) = send(&root, &deny, head, b"").await;
+        assert_eq!(res.status, 207);
+        assert!(!body_text(&res).contains(".git"));
+        assert!(body_text(&res).contains("a.txt"));
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(http_date(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_requests() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::write(root.join("a.js"), "let a;").unwrap();
+        let options = ServeOptions::default();
+        let conditional = |header: &str| format!("GET /a.js HTTP/1.1\r\n{header}\r\n\r\n");
+
+        let res = get(&root, &options, "/a.js").await;
+        let header = |name: &str| {
+            res.headers
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        let (etag, modified) = (header("ETag"), header("Last-Modified"));
+
+        for head in [
+            conditional(&format!("If-None-Match: \"other\", W/{etag}")),
+            conditional(&format!("If-Modified-Since: {modified}")),
+        ] {
+            let res = respond(&root, &options, &Request::parse(&head).unwrap()).await;
+            assert_eq!((res.status, res.len()), (304, 0));
+            assert!(res.headers.iter().all(|(n, _)| *n != "Content-Type"));
+        }
+        for head in [
+            conditional("If-None-Match: \"other\""),
+            conditional("If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT"),
+            // If-None-Match takes precedence
+            conditional(&format!(
+                "If-None-Match: \"other\"\r\nIf-Modified-Since: {modified}"
+            )),
+        ] {
+            let res = respond(&root, &options, &Request::parse(&head).unwrap()).await;
+            assert_eq!(res.status, 200);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_rules() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir_all(root.join("assets/img")).unwrap();
+        std::fs::write(root.join("assets/img/a.png"), "png").unwrap();
+        std::fs::write(root.join("index.html"), "hi").unwrap();
+        let rule = |pattern: &str, value: &str| CacheRule {
+            pattern: pattern.to_string(),
+            value: value.to_string(),
+        };
+        let options = ServeOptions {
+            cache_control: vec![
+                rule("/assets/**", "max-age=31536000, immutable"),
+                rule("**/*.html", "no-cache"),
+            ],
+            ..ServeOptions::default()
+        };
+        let cache_control = |res: &Response| {
+            res.headers
+                .iter()
+                .find(|(n, _)| *n == "Cache-Control")
+                .map(|(_, v)| v.clone())
+        };
+
+        let res = get(&root, &options, "/assets/img/a.png").await;
+        assert_eq!(
+            cache_control(&res).as_deref(),
+            Some("max-age=31536000, immutable")
+        );
+        let res = get(&root, &options, "/index.html").await;
+        assert_eq!(cache_control(&res).as_deref(), Some("no-cache"));
+        assert_eq!(cache_control(&get(&root, &options, "/").await), None);
+
+        assert!(check_cache_rules(&options.cache_control).is_ok());
+        assert!(check_cache_rules(&[rule("/a/***", "no-cache")]).is_err());
+        assert!(check_cache_rules(&[rule("/a", "no-cache\r\nX: y")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        use std::io::Read;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        let text = "console.log('compress me');\n".repeat(100);
+        std::fs::write(root.join("app.js"), &text).unwrap();
+        std::fs::write(root.join("small.js"), "let a;").unwrap();
+        std::fs::write(root.join("pre.css"), "body{}".repeat(300)).unwrap();
+        std::fs::write(root.join("pre.css.br"), "brotli bytes").unwrap();
+        let options = ServeOptions {
+            compression: Some(Compression::default()),
+            ..ServeOptions::default()
+        };
+        let get_encoded = |target: &str, accept: &str| {
+            format!("GET {target} HTTP/1.1\r\nAccept-Encoding: {accept}\r\n\r\n")
+        };
+
+        let head = get_encoded("/app.js", "gzip");
+        let res = respond(&root, &options, &Request::parse(&head).unwrap()).await;
+        assert_eq!(res.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.header("Vary"), Some("Accept-Encoding"));
+        assert!(res.header("ETag").unwrap().ends_with("-gzip\""));
+        let Body::Bytes(body) = &res.body else {
+            panic!("expected a compressed body");
+        };
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(body.as_slice())
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+
+        let head = get_encoded("/pre.css", "gzip, br");
+        let res = respond(&root, &options, &Request::parse(&head).unwrap()).await;
+        assert_eq!(res.header("Content-Encoding"), Some("br"));
+        assert_eq!(res.header("Content-Type"), Some("text/css; charset=utf-8"));
+        assert_eq!(res.len(), 12);
+
+        let head = get_encoded("/small.js", "gzip");
+        let res = respond(&root, &options, &Request::parse(&head).unwrap()).await;
+        assert_eq!(res.header("Content-Encoding"), None);
+        let res = get(&root, &options, "/app.js").await;
+        assert_eq!(res.header("Content-Encoding"), None);
+        assert_eq!(res.header("Vary"), Some("Accept-Encoding"));
+    }
+
+    /// Run `req` through `dispatch`, with `body` arriving over a stream.
+    async fn send(
+        root: &Path,
+        options: &ServeOptions,
+        head: &str,
+        body: &[u8],
+    ) -> (Response, RequestLog) {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client.write_all(body).await.unwrap();
+        let req = Request::parse(head).unwrap();
+        let mut log = RequestLog {
+            request_id: String::new(),
+            remote_address: String::new(),
+            time: 0,
+            method: String::new(),
+            path: String::new(),
+            status: 0,
+            bytes: 0,
+            duration_ms: 0,
+            user_agent: None,
+            referer: None,
+            error: None,
+            uploads: Vec::new(),
+            capture: None,
+        };
+        let res = dispatch(root, options, &req, server, Vec::new(), &mut log).await;
+        (res, log)
+    }
+
+    #[tokio::test]
+    async fn test_uploads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir(root.join("in")).unwrap();
+        let options = ServeOptions {
+            uploads: Some(Uploads {
+                max_bytes: 1000,
+                overwrite: false,
+            }),
+            ..ServeOptions::default()
+        };
+
+        let put = "PUT /in/a%20b.txt HTTP/1.1\r\nContent-Length: 5\r\n\r\n";
+        let (res, log) = send(&root, &options, put, b"hello").await;
+        assert_eq!(res.status, 201);
+        assert_eq!(
+            std::fs::read_to_string(root.join("in/a b.txt")).unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            log.uploads,
+            [UploadedFile {
+                path: "/in/a b.txt".to_string(),
+                bytes: 5
+            }]
+        );
+        let (res, log) = send(&root, &options, put, b"again").await;
+        assert_eq!(res.status, 409);
+        assert!(log.error.is_some());
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"file\"; \
+                    filename=\"c.txt\"\r\n\r\nfrom a phone\r\n--b--\r\n";
+        let post = format!(
+            "POST /in/ HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=b\r\n\
+             Content-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let (res, log) = send(&root, &options, &post, body.as_bytes()).await;
+        assert_eq!(res.status, 303);
+        assert_eq!(res.header("Location"), Some("/in/"));
+        assert_eq!(log.uploads[0].path, "/in/c.txt");
+        assert_eq!(
+            std::fs::read_to_string(root.join("in/c.txt")).unwrap(),
+            "from a phone"
+        );
+
+        for (head, status) in [
+            ("PUT /in/x HTTP/1.1\r\n\r\n", 411),
+            ("PUT /in/x HTTP/1.1\r\nContent-Length: 5000\r\n\r\n", 413),
+            ("PUT /missing/x HTTP/1.1\r\nContent-Length: 1\r\n\r\n", 404),
+            ("PUT /in/.. HTTP/1.1\r\nContent-Length: 1\r\n\r\n", 400),
+            ("POST /in/ HTTP/1.1\r\nContent-Length: 1\r\n\r\n", 415),
+        ] {
+            let (res, _) = send(&root, &options, head, b"x").await;
+            assert_eq!(res.status, status, "{head}");
+        }
+        assert!(!root.join("in/x").exists());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_need_auth() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        let options = ServeOptions {
+            uploads: Some(Uploads::default()),
+            auth: Some(Auth::Token {
+                token: "abc".to_string(),
+            }),
+            ..ServeOptions::default()
+        };
+        let put = "PUT /a.txt HTTP/1.1\r\nContent-Length: 2\r\n\r\n";
+        let (res, _) = send(&root, &options, put, b"hi").await;
+        assert_eq!(res.status, 401);
+        assert!(!root.join("a.txt").exists());
+
+        let listing = get(&root, &options, "/?token=abc").await;
+        let Body::Bytes(html) = &listing.body else {
+            panic!("expected a listing");
+        };
+        assert!(String::from_utf8_lossy(html).contains("multipart/form-data"));
+    }
+
+    fn body_text(res: &Response) -> String {
+        match &res.body {
+            Body::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            Body::File(..) => panic!("expected bytes"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mounts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        for dir in ["site", "docs", "media"] {
+            std::fs::create_dir(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("a.txt"), dir).unwrap();
+        }
+        let mount = |host: Option<&str>, prefix: &str, dir: &str| {
+            Mounted::new(mounts::Mount {
+                host: host.map(str::to_string),
+                prefix: prefix.to_string(),
+                root: root.join(dir).to_string_lossy().into_owned(),
+            })
+            .unwrap()
+        };
+        let options = ServeOptions {
+            mounts: vec![
+                mount(None, "/docs", "docs"),
+                mount(Some("media.local"), "/", "media"),
+            ],
+            webdav: Some(WebDav::default()),
+            ..ServeOptions::default()
+        };
+        let site = root.join("site");
+        let fetch = |head: String| {
+            let (site, options) = (site.clone(), options.clone());
+            async move { send(&site, &options, &head, b"").await.0 }
+        };
+        let res = fetch("GET /a.txt HTTP/1.1\r\n\r\n".to_string()).await;
+        assert!(matches!(res.body, Body::File(_, 4, _)));
+        let res = fetch("GET /docs/a.txt HTTP/1.1\r\n\r\n".to_string()).await;
+        assert!(matches!(res.body, Body::File(_, 4, ref p) if p.ends_with("docs/a.txt")));
+        let res = fetch("GET /a.txt HTTP/1.1\r\nHost: media.local:8080\r\n\r\n".to_string()).await;
+        assert!(matches!(res.body, Body::File(_, 5, _)));
+
+        let res = fetch("GET /docs?x=1 HTTP/1.1\r\n\r\n".to_string()).await;
+        assert_eq!(
+            (res.status, res.header("Location")),
+            (301, Some("/docs/?x=1"))
+        );
+        std::fs::create_dir(root.join("docs/sub")).unwrap();
+        let res = fetch("GET /docs/sub HTTP/1.1\r\n\r\n".to_string()).await;
+        assert_eq!(res.header("Location"), Some("/docs/sub/"));
+        let res = fetch("PROPFIND /docs/ HTTP/1.1\r\nDepth: 1\r\n\r\n".to_string()).await;
+        assert!(body_text(&res).contains("<D:href>/docs/a.txt</D:href>"));
+    }
+
+    #[tokio::test]
+    async fn test_webdav_read_only_without_auth() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::create_dir(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/a b.txt"), "hello").unwrap();
+        let options = ServeOptions {
+            webdav: Some(WebDav::default()),
+            ..ServeOptions::default()
+        };
+
+        let (res, _) = send(&root, &options, "OPTIONS / HTTP/1.1\r\n\r\n", b"").await;
+        assert_eq!(res.header("DAV"), Some("1, 2"));
+
+        let propfind = "PROPFIND /docs HTTP/1.1\r\nDepth: 1\r\n\r\n";
+        let (res, _) = send(&root, &options, propfind, b"").await;
+        assert_eq!(res.status, 207);
+        let xml = body_text(&res);
+        assert!(xml.contains("<D:href>/docs/</D:href>"));
+        assert!(xml.contains("<D:href>/docs/a%20b.txt</D:href>"));
+        assert!(xml.contains("<D:getcontentlength>5</D:getcontentlength>"));
+
+        let depth0 = "PROPFIND /docs/ HTTP/1.1\r\nDepth: 0\r\n\r\n";
+        let (res, _) = send(&root, &options, depth0, b"").await;
+        assert!(!body_text(&res).contains("a%20b.txt"));
+
+        for head in [
+            "DELETE /docs/a%20b.txt HTTP/1.1\r\n\r\n",
+            "MKCOL /new HTTP/1.1\r\n\r\n",
+            "PUT /docs/c.txt HTTP/1.1\r\nContent-Length: 1\r\n\r\n",
+        ] {
+            let (res, _) = send(&root, &options, head, b"x").await;
+            assert_eq!(res.status, 403, "{head}");
+        }
+        assert!(root.join("docs/a b.txt").exists());
+        assert!(!root.join("new").exists());
+    }
+
+    #[tokio::test]
+    async fn test_webdav_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        let options = ServeOptions {
+            webdav: Some(WebDav::default()),
+            auth: Some(Auth::Basic {
+                username: "me".to_string(),
+                password: "pw".to_string(),
+            }),
+            ..ServeOptions::default()
+        };
+        let auth = "Authorization: Basic bWU6cHc=\r\n";
+        let send_dav = |head: String, body: &'static [u8]| {
+            let root = root.clone();
+            let options = options.clone();
+            async move { send(&root, &options, &head, body).await.0 }
+        };
+
+        let res = send_dav("MKCOL /docs HTTP/1.1\r\n\r\n".to_string(), b"").await;
+        assert_eq!(res.status, 401);
+        let res = send_dav(format!("MKCOL /docs HTTP/1.1\r\n{auth}\r\n"), b"").await;
+        assert_eq!(res.status, 201);
+        let res = send_dav(format!("MKCOL /docs HTTP/1.1\r\n{auth}\r\n"), b"").await;
+        assert_eq!(res.status, 405);
+        let res = send_dav(format!("MKCOL /x/y HTTP/1.1\r\n{auth}\r\n"), b"").await;
+        assert_eq!(res.status, 409);
+
+        // Finder writes with a chunked body and overwrites in place
+        let put = format!("PUT /docs/a.txt HTTP/1.1\r\n{auth}Transfer-Encoding: chunked\r\n\r\n");
+        let res = send_dav(put.clone(), b"2\r\nhi\r\n0\r\n\r\n").await;
+        assert_eq!(res.status, 201);
+        let res = send_dav(put, b"3\r\nbye\r\n0\r\n\r\n").await;
+        assert_eq!(res.status, 204);
+        assert_eq!(
+            std::fs::read_to_string(root.join("docs/a.txt")).unwrap(),
+            "bye"
+        );
+
+        let copy =
+            format!("COPY /docs HTTP/1.1\r\n{auth}Destination: http://127.0.0.1:8080/copy\r\n\r\n");
+        assert_eq!(send_dav(copy.clone(), b"").await.status, 201);
+        assert!(root.join("copy/a.txt").exists());
+        let no_overwrite = format!("{}Overwrite: F\r\n\r\n", copy.strip_suffix("\r\n").unwrap());
+        assert_eq!(send_dav(no_overwrite, b"").await.status, 412);
+        let into_itself = format!("MOVE /docs HTTP/1.1\r\n{auth}Destination: /docs/inner\r\n\r\n");
+        assert_eq!(send_dav(into_itself, b"").await.status, 403);
+
+        let move_file =
+            format!("MOVE /copy/a.txt HTTP/1.1\r\n{auth}Destination: /copy/b%20c.txt\r\n\r\n");
+        assert_eq!(send_dav(move_file, b"").await.status, 201);
+        assert!(!root.join("copy/a.txt").exists());
+        assert!(root.join("copy/b c.txt").exists());
+
+        let delete = format!("DELETE /copy HTTP/1.1\r\n{auth}\r\n");
+        assert_eq!(send_dav(delete.clone(), b"").await.status, 204);
+        assert!(!root.join("copy").exists());
+        assert_eq!(send_dav(delete, b"").await.status, 404);
+        let delete_root = format!("DELETE / HTTP/1.1\r\n{auth}\r\n");
+        assert_eq!(send_dav(delete_root, b"").await.status, 403);
+    }
+
+    #[tokio::test]
+    async fn test_webdav_locks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        let options = ServeOptions {
+            webdav: Some(WebDav::default()),
+            auth: Some(Auth::Token {
+                token: "abc".to_string(),
+            }),
+            ..ServeOptions::default()
+        };
+        let lock_body = b"<?xml version=\"1.0\"?><D:lockinfo xmlns:D=\"DAV:\"/>";
+        let lock = format!(
+            "LOCK /new.txt?token=abc HTTP/1.1\r\nTimeout: Second-60\r\nContent-Length: {}\r\n\r\n",
+            lock_body.len()
+        );
+        let (res, _) = send(&root, &options, &lock, lock_body).await;
+        // Locking creates the file
+        assert_eq!(res.status, 201);
+        assert!(root.join("new.txt").exists());
+        assert!(body_text(&res).contains("Second-60"));
+        let token = res.header("Lock-Token").unwrap().to_string();
+        let (res, _) = send(&root, &options, &lock, lock_body).await;
+        assert_eq!(res.status, 423);
+
+        let put = "PUT /new.txt?token=abc HTTP/1.1\r\nContent-Length: 2\r\n";
+        let (res, _) = send(&root, &options, &format!("{put}\r\n"), b"hi").await;
+        assert_eq!(res.status, 423);
+        let with_token = format!("{put}If: ({token})\r\n\r\n");
+        let (res, _) = send(&root, &options, &with_token, b"hi").await;
+        assert_eq!(res.status, 204);
+
+        let refresh = format!("LOCK /new.txt?token=abc HTTP/1.1\r\nIf: ({token})\r\n\r\n");
+        let (res, _) = send(&root, &options, &refresh, b"").await;
+        assert_eq!(res.status, 200);
+
+        let unlock = format!("UNLOCK /new.txt?token=abc HTTP/1.1\r\nLock-Token: {token}\r\n\r\n");
+        let (res, _) = send(&root, &options, &unlock, b"").await;
+        assert_eq!(res.status, 204);
+        let (res, _) = send(&root, &options, &unlock, b"").await;
+        assert_eq!(res.status, 409);
+        let (res, _) = send(
+            &root,
+            &options,
+            "DELETE /new.txt?token=abc HTTP/1.1\r\n\r\n",
+            b"",
+        )
+        .await;
+        assert_eq!(res.status, 204);
+    }
+
+    async fn fetch(port: u16, request: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        // Answers with the request head it was sent
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = upstream.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {n}\r\n\r\n");
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(&buf[..n]).await.unwrap();
+            }
+        });
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("index.html"), "home").unwrap();
+        let options = ServeOptions {
+            auth: Some(Auth::Token {
+                token: "abc".to_string(),
+            }),
+            proxies: vec![
+                ProxyRoute {
+                    prefix: "/api".to_string(),
+                    target: format!("http://127.0.0.1:{upstream_port}/v1"),
+                    strip_prefix: true,
+                },
+                ProxyRoute {
+                    prefix: "/down".to_string(),
+                    target: format!("http://127.0.0.1:{closed_port}"),
+                    strip_prefix: false,
+                },
+            ],
+            ..ServeOptions::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(RwLock::new(options));
+        tokio::spawn(serve(listener, tmp.path().to_path_buf(), shared, |_| {}));
+
+        let res = fetch(
+            port,
+            "GET /api/users?token=abc&x=1 HTTP/1.1\r\nHost: lan:8080\r\n\r\n",
+        )
+        .await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+        assert!(res.contains(&format!(
+            "\r\n\r\nGET /v1/users?x=1 HTTP/1.1\r\nHost: 127.0.0.1:{upstream_port}\r\n"
+        )));
+        assert!(res.contains("X-Forwarded-Host: lan:8080\r\n"));
+
+        let res = fetch(port, "GET /api/users HTTP/1.1\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 401 "), "{res}");
+        let res = fetch(port, "GET /?token=abc HTTP/1.1\r\n\r\n").await;
+        assert!(res.ends_with("\r\n\r\nhome"), "{res}");
+        let res = fetch(port, "GET /down?token=abc HTTP/1.1\r\n\r\n").await;
+        assert!(res.starts_with("HTTP/1.1 502 "), "{res}");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("index.html"), "home").unwrap();
+        let options = ServeOptions {
+            rate_limit: Some(RateLimiter::new(RateLimitOptions {
+                requests_per_sec: 1,
+                burst: 1,
+                ..RateLimitOptions::default()
+            })),
+            ..ServeOptions::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(RwLock::new(options));
+        tokio::spawn(serve(listener, tmp.path().to_path_buf(), shared, |_| {}));
+
+        let request = "GET / HTTP/1.1\r\n\r\n";
+        assert!(fetch(port, request).await.starts_with("HTTP/1.1 200 OK"));
+        let limited = fetch(port, request).await;
+        assert!(limited.starts_with("HTTP/1.1 429 Too Many Requests"));
+        assert!(limited.contains("Retry-After: 1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_health() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("index.html"), "home").unwrap();
+        let options = ServeOptions {
+            auth: Some(Auth::Token {
+                token: "secret".to_string(),
+            }),
+            health: Some(Health::new(HealthOptions::default())),
+            ..ServeOptions::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(RwLock::new(options));
+        tokio::spawn(serve(listener, tmp.path().to_path_buf(), shared, |_| {}));
+
+        let health = fetch(port, "GET /_ok200/health HTTP/1.1\r\n\r\n").await;
+        assert!(health.starts_with("HTTP/1.1 200 OK"));
+        assert!(health.contains("Cache-Control: no-store\r\n"));
+        let status = fetch(port, "GET /_ok200/status HTTP/1.1\r\n\r\n").await;
+        let (_, body) = status.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert!(body["uptimeSecs"].is_u64() && body["requests"].is_u64());
+        let refused = fetch(port, "POST /_ok200/health HTTP/1.1\r\n\r\n").await;
+        assert!(refused.starts_with("HTTP/1.1 405"));
+        // Everything else still needs the token
+        let home = fetch(port, "GET / HTTP/1.1\r\n\r\n").await;
+        assert!(home.starts_with("HTTP/1.1 401"));
+    }
+
+    #[tokio::test]
+    async fn test_inspector() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("app.js"), "let x = 1;").unwrap();
+        let inspector = Inspector::new(InspectorOptions {
+            max_body_bytes: 4,
+            ..InspectorOptions::default()
+        });
+        let options = ServeOptions {
+            inspector: Some(inspector.clone()),
+            ..ServeOptions::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(RwLock::new(options));
+        tokio::spawn(serve(listener, tmp.path().to_path_buf(), shared, |_| {}));
+
+        let request = "GET /app.js HTTP/1.1\r\nCookie: session=1\r\n\r\n";
+        // The file is rewound after its start is captured
+        assert!(fetch(port, request).await.ends_with("\r\n\r\nlet x = 1;"));
+        fetch(port, "GET /missing HTTP/1.1\r\n\r\n").await;
+        // Captures are kept once the response has gone out
+        for _ in 0..100 {
+            if inspector.exchanges().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let exchanges = inspector.exchanges();
+        let js = exchanges.iter().find(|e| e.path == "/app.js").unwrap();
+        assert_eq!(js.status, 200);
+        assert!(js
+            .request_headers
+            .contains(&("Cookie".to_string(), "***".to_string())));
+        assert!(js.response_headers.contains(&(
+            "Content-Type".to_string(),
+            "text/javascript; charset=utf-8".to_string()
+        )));
+        let body = js.response_body.as_ref().unwrap();
+        assert_eq!((body.text.as_str(), body.truncated), ("let ", true));
+        let missing = exchanges.iter().find(|e| e.path == "/missing").unwrap();
+        assert_eq!(missing.status, 404);
+        // Numbered by connection, in the order they were accepted
+        assert_eq!(
+            (js.request_id.as_str(), missing.request_id.as_str()),
+            ("1", "2")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ip_filter() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("index.html"), "home").unwrap();
+        let filter = |deny: &str| {
+            IpFilter::new(IpFilterOptions {
+                deny: vec![deny.to_string()],
+                ..IpFilterOptions::default()
+            })
+            .unwrap()
+        };
+        let options = ServeOptions {
+            ip_filter: Some(filter("127.0.0.0/8")),
+            ..ServeOptions::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(RwLock::new(options));
+        let (sender, mut logged) = tokio::sync::mpsc::unbounded_channel();
+        let log = move |entry: &RequestLog| sender.send(entry.clone()).unwrap();
+        tokio::spawn(serve(
+            listener,
+            tmp.path().to_path_buf(),
+            shared.clone(),
+            log,
+        ));
+
+        // Closed before anything is read
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        assert_eq!(stream.read(&mut [0; 16]).await.unwrap(), 0);
+        let entry = logged.recv().await.unwrap();
+        assert_eq!(entry.error.as_deref(), Some("rejected: denied"));
+        assert_eq!(entry.status, 0);
+
+        shared.write().unwrap().ip_filter = Some(filter("10.0.0.0/8"));
+        let request = "GET / HTTP/1.1\r\n\r\n";
+        assert!(fetch(port, request).await.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn test_markdown() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::write(root.join("README.md"), "# Docs\n\n*hi*").unwrap();
+        let options = ServeOptions {
+            markdown: true,
+            ..ServeOptions::default()
+        };
+        let res = get(&root, &options, "/README.md").await;
+        assert_eq!(res.header("Content-Type"), Some("text/html; charset=utf-8"));
+        assert!(res.header("ETag").unwrap().ends_with("-md\""));
+        assert!(body_text(&res).contains("<em>hi</em>"));
+
+        let raw = get(&root, &options, "/README.md?raw=1").await;
+        assert!(raw
+            .header("Content-Type")
+            .unwrap()
+            .starts_with("text/plain"));
+        assert!(matches!(raw.body, Body::File(..)));
+        let off = get(&root, &ServeOptions::default(), "/README.md").await;
+        assert!(matches!(off.body, Body::File(..)));
+    }
+
+    #[tokio::test]
+    async fn test_live_reload() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::write(root.join("index.html"), "<body>hi</body>").unwrap();
+        let options = ServeOptions {
+            live_reload: Some(LiveReload::default()),
+            ..ServeOptions::default()
+        };
+        let res = get(&root, &options, "/").await;
+        assert!(body_text(&res).contains(live_reload::EVENTS_PATH));
+        assert!(res.header("ETag").unwrap().ends_with("-livereload\""));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let shared = Arc::new(RwLock::new(options));
+        let server = tokio::spawn(serve(listener, root.clone(), shared, |_| {}));
+        let mut events = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\n\r\n", live_reload::EVENTS_PATH);
+        events.write_all(request.as_bytes()).await.unwrap();
+        let mut buf = vec![0; 4096];
+        let n = events.read(&mut buf).await.unwrap();
+        assert!(String::from_utf8_lossy(&buf[..n]).contains("text/event-stream"));
+
+        // Let the watcher take its first snapshot
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        std::fs::write(root.join("app.css"), "a{}").unwrap();
+        let n = events.read(&mut buf).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf[..n]),
+            "data: {\"paths\":[\"/app.css\"],\"total\":1,\"dirs\":{\"/\":1},\"overflow\":false}\n\n"
+        );
+        // Stopping the server ends the stream
+        server.abort();
+        let mut rest = Vec::new();
+        events.read_to_end(&mut rest).await.unwrap();
+    }
+
+    /// Status and body of an HTTP/2 response.
+    async fn h2_response(response: h2::client::ResponseFuture) -> (http::StatusCode, Vec<u8>) {
+        let response = response.await.unwrap();
+        let status = response.status();
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(data) = body.data().await {
+            let data = data.unwrap();
+            body.flow_control().release_capacity(data.len()).unwrap();
+            bytes.extend_from_slice(&data);
+        }
+        (status, bytes)
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        use tokio_rustls::rustls::pki_types::ServerName;
+        use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::write(root.join("hello.txt"), "hello").unwrap();
+        let certs = tempfile::tempdir().unwrap();
+        let (mut tls_options, cert) = tls::tests::localhost_cert(certs.path());
+        tls_options.redirect_http = true;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let options = ServeOptions {
+            uploads: Some(Uploads::default()),
+            tls: Some(Tls::load(tls_options, port).unwrap()),
+            ..ServeOptions::default()
+        };
+        let shared = Arc::new(RwLock::new(options));
+        tokio::spawn(serve(listener, root.clone(), shared, |_| {}));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let connect = |alpn: &[u8]| {
+            let mut config = ClientConfig::builder()
+                .with_root_certificates(roots.clone())
+                .with_no_client_auth();
+            config.alpn_protocols = vec![alpn.to_vec()];
+            async move {
+                let tcp = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+                let name = ServerName::try_from("localhost").unwrap();
+                tokio_rustls::TlsConnector::from(Arc::new(config))
+                    .connect(name, tcp)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let (client, connection) = h2::client::handshake(connect(b"h2").await).await.unwrap();
+        tokio::spawn(connection);
+        let mut client = client.ready().await.unwrap();
+        let url = |path: &str| format!("https://localhost:{port}{path}");
+        let get = http::Request::get(url("/hello.txt")).body(()).unwrap();
+        let (get, _) = client.send_request(get, true).unwrap();
+        // A body without a length reaches the handler chunked
+        let put = http::Request::put(url("/new.txt")).body(()).unwrap();
+        let (put, mut body) = client.send_request(put, false).unwrap();
+        body.send_data("uploaded".into(), true).unwrap();
+        assert_eq!(
+            h2_response(get).await,
+            (http::StatusCode::OK, b"hello".to_vec())
+        );
+        assert_eq!(h2_response(put).await.0, http::StatusCode::CREATED);
+        assert_eq!(
+            std::fs::read_to_string(root.join("new.txt")).unwrap(),
+            "uploaded"
+        );
+
+        let mut http1 = connect(b"http/1.1").await;
+        http1
+            .write_all(b"GET /hello.txt HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        http1.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        // Plain HTTP on the same port is sent to HTTPS
+        let mut plain = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        plain
+            .write_all(
+                format!("GET /hello.txt?x=1 HTTP/1.1\r\nHost: localhost:{port}\r\n\r\n").as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        plain.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(response.contains(&format!(
+            "\r\nLocation: https://localhost:{port}/hello.txt?x=1\r\n"
+        )));
+    }
+
+    #[test]
+    fn test_mime_overrides() {
+        let overrides = normalize_mime_types([
+            (".MD".to_string(), "text/plain; charset=utf-8".to_string()),
+            ("dat".to_string(), " application/x-custom ".to_string()),
+        ])
+        .unwrap();
+        assert_eq!(
+            content_type(Path::new("README.md"), &overrides),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("a.DAT"), &overrides),
+            "application/x-custom"
+        );
+        assert_eq!(
+            content_type(Path::new("a.css"), &overrides),
+            "text/css; charset=utf-8"
+        );
+
+        assert!(normalize_mime_types([(String::new(), "text/plain".to_string())]).is_err());
+        assert!(normalize_mime_types([("md".to_string(), "plain".to_string())]).is_err());
+        let injected = "text/plain\r\nSet-Cookie: x=1".to_string();
+        assert!(normalize_mime_types([("md".to_string(), injected)]).is_err());
+    }
+
+    #[test]
+    fn test_query_token() {
+        assert_eq!(query_param("/a?x=1&token=ab%2Fc", "token").unwrap(), "ab/c");
+        assert_eq!(query_param("/a?x=1", "token"), None);
+        assert_eq!(query_param("/a", "token"), None);
+        assert_eq!(redact_token("/a?x=1&token=abc"), "/a?x=1&token=***");
+        assert_eq!(redact_token("/a"), "/a");
+    }
+
+    #[tokio::test]
+    async fn test_respond_token_auth() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::write(root.join("index.html"), "hi").unwrap();
+        let options = ServeOptions {
+            auth: Some(Auth::Token {
+                token: "abc".to_string(),
+            }),
+            ..ServeOptions::default()
+        };
+
+        let res = get(&root, &options, "/").await;
+        assert_eq!(res.status, 401);
+        assert!(res.headers.iter().any(|(n, _)| *n == "WWW-Authenticate"));
+        assert_eq!(get(&root, &options, "/?token=nope").await.status, 401);
+
+        let res = get(&root, &options, "/?token=abc").await;
+        assert_eq!(res.status, 200);
+        let cookie = res
+            .headers
+            .iter()
+            .find(|(n, _)| *n == "Set-Cookie")
+            .unwrap();
+        assert!(cookie.1.starts_with("ok200_token=abc;"));
+
+        let with_cookie = "GET / HTTP/1.1\r\nCookie: ok200_token=abc\r\n\r\n";
+        let req = Request::parse(with_cookie).unwrap();
+        assert_eq!(respond(&root, &options, &req).await.status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_respond_cors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(tmp.path()).unwrap();
+        std::fs::write(root.join("data.json"), "{}").unwrap();
+        let has_cors = |res: &Response| {
+            res.headers
+                .iter()
+                .any(|(name, _)| *name == "Access-Control-Allow-Origin")
+        };
+        let preflight = "OPTIONS /data.json HTTP/1.1\r\nOrigin: http://a.test\r\n\
+                         Access-Control-Request-Method: GET\r\n\r\n";
+        let preflight = Request::parse(preflight).unwrap();
+        let fetch = "GET /data.json HTTP/1.1\r\nOrigin: http://a.test\r\n\r\n";
+        let fetch = Request::parse(fetch).unwrap();
+
+        let plain = ServeOptions::default();
+        assert!(!has_cors(&respond(&root, &plain, &fetch).await));
+        assert_eq!(respond(&root, &plain, &preflight).await.status, 405);
+
+        let options = ServeOptions {
+            cors: Some(Cors::default()),
+            ..ServeOptions::default()
+        };
+        let res = respond(&root, &options, &preflight).await;
+        assert_eq!(res.status, 204);
+        assert!(has_cors(&res));
+        let res = respond(&root, &options, &fetch).await;
+        assert_eq!(res.status, 200);
+        assert!(has_cors(&res));
+    }
+}
@@ -0,0 +1,341 @@
+//! Opt-in capture of a server's recent requests and responses, so users
+//! can see why a client got a 404 or the wrong MIME type. The last
+//! `capacity` exchanges are kept in a ring buffer, and each one is also
+//! sent to the webview as an `inspector-capture` event. Credentials in
+//! headers are masked and bodies are cut to `maxBodyBytes`. Proxied
+//! responses and live reload streams aren't captured.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use super::http::HttpState;
+
+/// Header values replaced with `***` in captures.
+const MASKED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InspectorOptions {
+    /// Exchanges kept; the oldest is dropped to make room.
+    pub capacity: usize,
+    /// Bytes kept from each request and response body.
+    pub max_body_bytes: usize,
+}
+
+impl Default for InspectorOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            max_body_bytes: 64 * 1024,
+        }
+    }
+}
+
+/// The start of a body.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CapturedBody {
+    pub text: String,
+    /// `base64` when the bytes aren't UTF-8.
+    pub encoding: Option<&'static str>,
+    /// Whether the body went on past what was kept.
+    pub truncated: bool,
+}
+
+impl CapturedBody {
+    /// Keep up to `max` bytes of `bytes`, which are all of a body if
+    /// `complete`.
+    pub fn new(bytes: &[u8], max: usize, complete: bool) -> Self {
+        let kept = &bytes[..bytes.len().min(max)];
+        let truncated = kept.len() < bytes.len() || !complete;
+        match std::str::from_utf8(kept) {
+            Ok(text) => Self::text(text, truncated),
+            // Cut in the middle of a character
+            Err(e) if truncated && e.error_len().is_none() => {
+                let text = std::str::from_utf8(&kept[..e.valid_up_to()]).unwrap_or_default();
+                Self::text(text, truncated)
+            }
+            Err(_) => Self {
+                text: base64::engine::general_purpose::STANDARD.encode(kept),
+                encoding: Some("base64"),
+                truncated,
+            },
+        }
+    }
+
+    fn text(text: &str, truncated: bool) -> Self {
+        Self {
+            text: text.to_string(),
+            encoding: None,
+            truncated,
+        }
+    }
+}
+
+/// One captured request and its response.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Exchange {
+    /// Increases with each capture on a server.
+    pub id: u64,
+    /// The `requestId` of the request's log entry.
+    pub request_id: String,
+    /// Unix milliseconds the request arrived.
+    pub time_ms: u64,
+    pub remote_address: String,
+    pub method: String,
+    /// Request target, with any token in the query hidden.
+    pub path: String,
+    pub request_headers: Vec<(String, String)>,
+    /// What arrived with the request head; uploads stream past it.
+    pub request_body: Option<CapturedBody>,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: Option<CapturedBody>,
+    /// Response body length.
+    pub bytes: u64,
+    pub duration_ms: u64,
+}
+
+/// An `Exchange` without headers and bodies, for listing.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeSummary {
+    pub id: u64,
+    pub request_id: String,
+    pub time_ms: u64,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub bytes: u64,
+    pub duration_ms: u64,
+}
+
+impl Exchange {
+    fn summary(&self) -> ExchangeSummary {
+        let content_type = self
+            .response_headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, value)| value.clone());
+        ExchangeSummary {
+            id: self.id,
+            request_id: self.request_id.clone(),
+            time_ms: self.time_ms,
+            method: self.method.clone(),
+            path: self.path.clone(),
+            status: self.status,
+            content_type,
+            bytes: self.bytes,
+            duration_ms: self.duration_ms,
+        }
+    }
+}
+
+/// Copy headers for a capture, masking credentials.
+pub fn capture_headers<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Vec<(String, String)> {
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            let masked = MASKED_HEADERS.contains(&name.to_ascii_lowercase().as_str());
+            let value = if masked { "***" } else { value };
+            (name.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+struct Ring {
+    exchanges: VecDeque<Exchange>,
+    next_id: u64,
+}
+
+/// Capture turned on for a server. Clones share the same buffer.
+#[derive(Clone, Debug)]
+pub struct Inspector {
+    pub options: InspectorOptions,
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl PartialEq for Inspector {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.ring, &other.ring)
+    }
+}
+
+impl Eq for Inspector {}
+
+impl Inspector {
+    pub fn new(options: InspectorOptions) -> Self {
+        Self {
+            options,
+            ring: Arc::default(),
+        }
+    }
+
+    /// Number `exchange`, keep it, and return it as kept.
+    pub fn push(&self, mut exchange: Exchange) -> Exchange {
+        let mut ring = self.ring.lock().unwrap();
+        ring.next_id += 1;
+        exchange.id = ring.next_id;
+        while ring.exchanges.len() >= self.options.capacity.max(1) {
+            ring.exchanges.pop_front();
+        }
+        ring.exchanges.push_back(exchange.clone());
+        exchange
+    }
+
+    /// Every kept exchange, oldest first.
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.ring
+            .lock()
+            .unwrap()
+            .exchanges
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<Exchange> {
+        let ring = self.ring.lock().unwrap();
+        ring.exchanges.iter().find(|e| e.id == id).cloned()
+    }
+
+    pub fn clear(&self) {
+        self.ring.lock().unwrap().exchanges.clear();
+    }
+}
+
+/// Payload of the `inspector-capture` event.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CaptureEvent {
+    pub server_id: u32,
+    #[serde(flatten)]
+    pub exchange: Exchange,
+}
+
+/// Captured exchanges of a server, oldest first.
+#[tauri::command]
+pub async fn inspector_list(
+    server_id: u32,
+    state: State<'_, HttpState>,
+) -> Result<Vec<ExchangeSummary>, String> {
+    let inspector = state.inspector(server_id)?;
+    Ok(inspector
+        .exchanges()
+        .iter()
+        .map(Exchange::summary)
+        .collect())
+}
+
+#[tauri::command]
+pub async fn inspector_get(
+    server_id: u32,
+    id: u64,
+    state: State<'_, HttpState>,
+) -> Result<Exchange, String> {
+    state
+        .inspector(server_id)?
+        .get(id)
+        .ok_or_else(|| format!("capture {id} not found"))
+}
+
+#[tauri::command]
+pub async fn inspector_clear(server_id: u32, state: State<'_, HttpState>) -> Result<(), String> {
+    state.inspector(server_id)?.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(path: &str) -> Exchange {
+        Exchange {
+            id: 0,
+            request_id: "1".to_string(),
+            time_ms: 1_700_000_000_000,
+            remote_address: "127.0.0.1:5000".to_string(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            request_headers: Vec::new(),
+            request_body: None,
+            status: 404,
+            response_headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            response_body: None,
+            bytes: 14,
+            duration_ms: 1,
+        }
+    }
+
+    #[test]
+    fn test_ring() {
+        let inspector = Inspector::new(InspectorOptions {
+            capacity: 2,
+            ..InspectorOptions::default()
+        });
+        for path in ["/a", "/b", "/c"] {
+            inspector.push(exchange(path));
+        }
+        let kept: Vec<_> = inspector
+            .exchanges()
+            .iter()
+            .map(Exchange::summary)
+            .collect();
+        assert_eq!(
+            kept.iter()
+                .map(|s| (s.id, s.path.as_str()))
+                .collect::<Vec<_>>(),
+            [(2, "/b"), (3, "/c")]
+        );
+        assert_eq!(kept[0].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(inspector.get(3).unwrap().path, "/c");
+        assert!(inspector.get(1).is_none());
+        inspector.clear();
+        assert!(inspector.exchanges().is_empty());
+        assert_eq!(inspector.push(exchange("/d")).id, 4);
+    }
+
+    #[test]
+    fn test_captured_body() {
+        let body = CapturedBody::new(b"hello", 64, true);
+        assert_eq!((body.text.as_str(), body.truncated), ("hello", false));
+        let cut = CapturedBody::new("héllo".as_bytes(), 2, true);
+        assert_eq!((cut.text.as_str(), cut.truncated), ("h", true));
+        let partial = CapturedBody::new(b"abc", 64, false);
+        assert!(partial.truncated);
+        let binary = CapturedBody::new(&[0xff, 0x00, 0x10], 64, true);
+        assert_eq!(binary.encoding, Some("base64"));
+        assert_eq!(binary.text, "/wAQ");
+    }
+
+    #[test]
+    fn test_capture_headers() {
+        let headers = capture_headers([
+            ("Host", "localhost"),
+            ("authorization", "Basic YTpi"),
+            ("Cookie", "ok200_token=abc"),
+        ]);
+        assert_eq!(
+            headers,
+            [
+                ("Host".to_string(), "localhost".to_string()),
+                ("authorization".to_string(), "***".to_string()),
+                ("Cookie".to_string(), "***".to_string()),
+            ]
+        );
+    }
+}
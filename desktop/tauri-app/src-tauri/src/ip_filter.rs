@@ -0,0 +1,173 @@
+//! Per-server allow and deny rules on client addresses, checked when a
+//! connection is accepted by `tcp` or `http_server`.
+
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// A network written as `10.0.0.0/8`, or a single address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|e| format!("invalid address {s}: {e}"))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|&p| p <= bits)
+                .ok_or_else(|| format!("invalid prefix length in {s}"))?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Loopback, RFC 1918, and IPv6 unique local and link-local addresses.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_loopback() || ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct IpFilterOptions {
+    /// When not empty, only clients in one of these networks get in.
+    pub allow: Vec<String>,
+    /// Clients in these networks are refused, even if allowed.
+    pub deny: Vec<String>,
+    /// Refuse clients outside the local network (see `is_private`).
+    pub private_only: bool,
+}
+
+/// Parsed rules for one server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpFilter {
+    pub options: IpFilterOptions,
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+fn parse_all(networks: &[String]) -> Result<Vec<Cidr>, String> {
+    networks.iter().map(|n| Cidr::parse(n)).collect()
+}
+
+impl IpFilter {
+    pub fn new(options: IpFilterOptions) -> Result<Self, String> {
+        Ok(Self {
+            allow: parse_all(&options.allow)?,
+            deny: parse_all(&options.deny)?,
+            options,
+        })
+    }
+
+    /// Let a client at `ip` in, or say why not.
+    pub fn check(&self, ip: IpAddr) -> Result<(), &'static str> {
+        // Dual-stack listeners see IPv4 clients as `::ffff:a.b.c.d`
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return Err("denied");
+        }
+        if self.options.private_only && !is_private(ip) {
+            return Err("not a local address");
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|c| c.contains(ip)) {
+            return Err("not allowed");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let net = Cidr::parse("10.1.0.0/16").unwrap();
+        assert!(net.contains(ip("10.1.200.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(!net.contains(ip("::1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+        assert!(Cidr::parse("fd00::/8").unwrap().contains(ip("fd12::1")));
+        let single = Cidr::parse("192.168.1.5").unwrap();
+        assert!(single.contains(ip("192.168.1.5")));
+        assert!(!single.contains(ip("192.168.1.6")));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let filter = IpFilter::new(IpFilterOptions {
+            allow: vec!["192.168.0.0/16".to_string()],
+            deny: vec!["192.168.1.66".to_string()],
+            private_only: false,
+        })
+        .unwrap();
+        assert_eq!(filter.check(ip("192.168.1.20")), Ok(()));
+        assert_eq!(filter.check(ip("::ffff:192.168.1.20")), Ok(()));
+        assert_eq!(filter.check(ip("192.168.1.66")), Err("denied"));
+        assert_eq!(filter.check(ip("10.0.0.1")), Err("not allowed"));
+
+        let local = IpFilter::new(IpFilterOptions {
+            private_only: true,
+            ..IpFilterOptions::default()
+        })
+        .unwrap();
+        for allowed in [
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.5.4",
+            "192.168.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert_eq!(local.check(ip(allowed)), Ok(()), "{allowed}");
+        }
+        assert_eq!(local.check(ip("8.8.8.8")), Err("not a local address"));
+        assert_eq!(local.check(ip("2001:db8::1")), Err("not a local address"));
+        assert!(IpFilter::new(IpFilterOptions {
+            deny: vec!["nope".to_string()],
+            ..IpFilterOptions::default()
+        })
+        .is_err());
+    }
+}
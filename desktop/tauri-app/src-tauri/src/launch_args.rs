@@ -0,0 +1,78 @@
+//! Folder passed on the command line, e.g. `200-ok /path/to/folder` or a
+//! folder opened with the app from the file manager. The first launch
+//! stashes it until the webview asks; later launches are forwarded by the
+//! single-instance plugin and emitted straight to the webview.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use tauri::{Emitter, State};
+
+use super::deep_link;
+
+/// Folder from the first launch, waiting for the webview to pick it up.
+pub struct LaunchFolder(pub Mutex<Option<String>>);
+
+/// First positional argument, resolved against `cwd`, if it is a folder.
+/// Flags are skipped; the one flag that takes a value (`--install-update`)
+/// never reaches a GUI launch.
+pub fn folder_arg(args: &[String], cwd: &Path) -> Option<String> {
+    let arg = args
+        .iter()
+        .skip(1)
+        .find(|a| !a.starts_with('-') && !deep_link::is_deep_link(a))?;
+    let path = cwd.join(arg);
+    if !path.is_dir() {
+        tracing::warn!("ignoring {arg}: not a folder");
+        return None;
+    }
+    let path = std::fs::canonicalize(&path).unwrap_or(path);
+    Some(display_path(&path))
+}
+
+/// Strip the `\\?\` prefix `canonicalize` adds on Windows.
+pub fn display_path(path: &Path) -> String {
+    let path = path.to_string_lossy().into_owned();
+    match path.strip_prefix(r"\\?\") {
+        Some(stripped) => stripped.to_string(),
+        None => path,
+    }
+}
+
+/// Handle arguments from a second launch of the app.
+pub fn forward(app: &tauri::AppHandle, args: &[String], cwd: &str) {
+    if let Some(link) = args.iter().skip(1).find(|a| deep_link::is_deep_link(a)) {
+        deep_link::handle(app, link);
+    } else if let Some(folder) = folder_arg(args, Path::new(cwd)) {
+        let _ = app.emit("open-folder", folder);
+    }
+}
+
+/// Folder the app was launched with, if any. Returns it only once.
+#[tauri::command]
+pub async fn launch_folder_take(state: State<'_, LaunchFolder>) -> Result<Option<String>, String> {
+    Ok(state.0.lock().unwrap().take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_folder_arg() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("site")).unwrap();
+        std::fs::write(tmp.path().join("file.txt"), "").unwrap();
+        let site = std::fs::canonicalize(tmp.path().join("site")).unwrap();
+
+        let found = folder_arg(&args(&["200-ok", "--hidden", "site"]), tmp.path());
+        assert_eq!(found, Some(display_path(&site)));
+        assert_eq!(folder_arg(&args(&["200-ok", "file.txt"]), tmp.path()), None);
+        assert_eq!(folder_arg(&args(&["200-ok", "--hidden"]), tmp.path()), None);
+        assert_eq!(folder_arg(&args(&["200-ok"]), tmp.path()), None);
+    }
+}
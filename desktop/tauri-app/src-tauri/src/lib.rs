@@ -4,14 +4,81 @@ use std::sync::Mutex;
 use tauri::{
     menu::{CheckMenuItem, Menu, MenuItem, MenuItemKind, PredefinedMenuItem, SubmenuBuilder},
     tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
-    Emitter, Manager,
+    Emitter, Manager, State,
 };
 use tauri_plugin_autostart::ManagerExt as AutostartManagerExt;
+use tauri_plugin_opener::OpenerExt;
 
+mod access_log;
+mod auth;
+mod build_step;
+mod capability_tokens;
+mod compression;
+mod cors;
+mod deep_link;
+mod diagnostics;
+mod discovery;
+mod dlna;
+mod dns;
+mod dotfiles;
+mod fingerprint;
+mod folder_grants;
+mod fs_audit;
 mod fs_commands;
+mod ftp;
+mod happy_eyeballs;
+mod har;
+mod headless_serve;
 mod headless_updater;
+mod health;
+mod hooks;
+mod http;
+mod http2;
+#[cfg(feature = "http3")]
+mod http3;
+mod http_server;
+mod inspector;
+mod ip_filter;
+mod launch_args;
+mod legacy_import;
+mod live_reload;
+mod logging;
+mod markdown;
+mod metrics;
+mod mounts;
 mod native_host;
+mod network_monitor;
+mod notifications;
+mod offline_update;
+mod paths;
+#[cfg(feature = "plugins")]
+mod plugin_runtime;
+mod plugins;
+mod profiles;
+mod proxy;
+mod quit;
+mod rate_limit;
+mod recents;
+mod rollback;
+mod secrets;
+mod servers;
+mod settings_transfer;
+#[cfg(feature = "sftp")]
+mod sftp;
+mod sniff;
 mod tcp;
+mod tls;
+mod tray_icon;
+mod tray_status;
+mod tree_snapshots;
+mod tree_sync;
+mod updates;
+mod uploads;
+mod usage;
+mod watch_batch;
+mod webdav;
+#[cfg(feature = "webrtc")]
+mod webrtc;
 
 /// Strip the `\\?\` extended-length path prefix that Windows APIs produce.
 /// Chrome's native messaging launcher doesn't understand this prefix.
@@ -31,6 +98,73 @@ fn default_true() -> bool {
     true
 }
 
+fn default_update_check_timeout_secs() -> u64 {
+    30
+}
+
+fn default_update_download_timeout_secs() -> u64 {
+    600
+}
+
+fn default_update_retries() -> u32 {
+    3
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+/// Which interfaces the web server listens on.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum BindAddress {
+    /// Loopback only (`127.0.0.1`).
+    #[default]
+    Localhost,
+    /// Every interface (`0.0.0.0`), reachable from the LAN.
+    AllInterfaces,
+}
+
+/// Release channel the updater follows.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+    Nightly,
+}
+
+impl UpdateChannel {
+    const ALL: [UpdateChannel; 3] = [Self::Stable, Self::Beta, Self::Nightly];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Beta => "beta",
+            Self::Nightly => "nightly",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Stable => "Stable",
+            Self::Beta => "Beta",
+            Self::Nightly => "Nightly",
+        }
+    }
+
+    fn menu_id(self) -> String {
+        format!("update-channel-{}", self.as_str())
+    }
+
+    fn from_menu_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.menu_id() == id)
+    }
+}
+
+// User-facing toggles are naturally independent bools
+#[allow(clippy::struct_excessive_bools)]
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 struct Settings {
     #[serde(default)]
@@ -40,6 +174,101 @@ struct Settings {
     /// Show tray icon in macOS menu bar. Ignored on other platforms.
     #[serde(default = "default_true")]
     show_in_menu_bar: bool,
+    /// Left-clicking the tray icon hides a focused window instead of only
+    /// showing it. Ignored on macOS, where a click opens the menu.
+    #[serde(default = "default_true")]
+    tray_click_toggles: bool,
+    /// Show the server count beside the macOS menu bar icon.
+    #[serde(default = "default_true")]
+    menu_bar_count: bool,
+    #[serde(default)]
+    channel: UpdateChannel,
+    /// Explicit proxy for updater requests. When unset, `HTTPS_PROXY` and
+    /// the OS proxy configuration are used.
+    #[serde(default)]
+    proxy_url: Option<String>,
+    /// Update version the user chose to skip.
+    #[serde(default)]
+    skipped_version: Option<String>,
+    /// Unix time (seconds) until which update prompts are suppressed.
+    #[serde(default)]
+    snooze_until: Option<u64>,
+    /// Stay in the tray instead of showing the window at launch.
+    #[serde(default)]
+    start_hidden: bool,
+    /// Notify when a server starts or stops.
+    #[serde(default = "default_true")]
+    notify_server_lifecycle: bool,
+    /// Notify the first time a remote device connects.
+    #[serde(default = "default_true")]
+    notify_new_clients: bool,
+    /// Notify when an update is installed in the background.
+    #[serde(default = "default_true")]
+    notify_updates: bool,
+    /// Apply a downloaded update when the app quits instead of restarting.
+    #[serde(default)]
+    install_on_quit: bool,
+    /// Per-request timeout for update checks, in seconds.
+    #[serde(default = "default_update_check_timeout_secs")]
+    update_check_timeout_secs: u64,
+    /// Timeout for downloading an update bundle, in seconds.
+    #[serde(default = "default_update_download_timeout_secs")]
+    update_download_timeout_secs: u64,
+    /// How many times the headless updater retries a transient failure.
+    #[serde(default = "default_update_retries")]
+    update_retries: u32,
+    /// Port the server UI starts with.
+    #[serde(default = "default_port")]
+    default_port: u16,
+    /// Folder the server UI starts with.
+    #[serde(default)]
+    default_serve_folder: Option<String>,
+    #[serde(default)]
+    bind_address: BindAddress,
+    /// Most verbose level written to the log files.
+    #[serde(default)]
+    log_level: logging::LogLevel,
+    /// Serve Prometheus metrics on this localhost port; off when unset.
+    #[serde(default)]
+    metrics_port: Option<u16>,
+}
+
+impl Settings {
+    fn updater_proxy(&self) -> Option<tauri::Url> {
+        let url = self.proxy_url.as_deref()?.trim();
+        if url.is_empty() {
+            return None;
+        }
+        match tauri::Url::parse(url) {
+            Ok(url) => Some(url),
+            Err(e) => {
+                tracing::warn!("settings: ignoring invalid proxy_url {url:?}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Apply a partial settings object on top of these settings.
+    fn merged(&self, patch: serde_json::Value) -> Result<Settings, String> {
+        let serde_json::Value::Object(patch) = patch else {
+            return Err("settings must be an object".to_string());
+        };
+        let mut value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        if let serde_json::Value::Object(map) = &mut value {
+            map.extend(patch);
+        }
+        let merged: Settings =
+            serde_json::from_value(value).map_err(|e| format!("invalid settings: {e}"))?;
+        if let Some(folder) = merged.default_serve_folder.as_deref() {
+            if !std::path::Path::new(folder).is_dir() {
+                return Err(format!("default_serve_folder is not a directory: {folder}"));
+            }
+        }
+        if merged.metrics_port == Some(0) {
+            return Err("metrics_port must not be 0".to_string());
+        }
+        Ok(merged)
+    }
 }
 
 impl Default for Settings {
@@ -48,12 +277,31 @@ impl Default for Settings {
             autostart: false,
             run_in_background: true,
             show_in_menu_bar: true,
+            tray_click_toggles: true,
+            menu_bar_count: true,
+            channel: UpdateChannel::Stable,
+            proxy_url: None,
+            skipped_version: None,
+            snooze_until: None,
+            start_hidden: false,
+            notify_server_lifecycle: true,
+            notify_new_clients: true,
+            notify_updates: true,
+            install_on_quit: false,
+            update_check_timeout_secs: default_update_check_timeout_secs(),
+            update_download_timeout_secs: default_update_download_timeout_secs(),
+            update_retries: default_update_retries(),
+            default_port: default_port(),
+            default_serve_folder: None,
+            bind_address: BindAddress::Localhost,
+            log_level: logging::LogLevel::Info,
+            metrics_port: None,
         }
     }
 }
 
 fn load_settings(app: &tauri::AppHandle) -> Settings {
-    let data_dir = app.path().app_data_dir().expect("no app data directory");
+    let data_dir = paths::app_data_dir(app).expect("no app data directory");
     let path = data_dir.join("settings.json");
     std::fs::read_to_string(&path)
         .ok()
@@ -62,16 +310,128 @@ fn load_settings(app: &tauri::AppHandle) -> Settings {
 }
 
 fn save_settings(app: &tauri::AppHandle, settings: &Settings) {
-    let data_dir = app.path().app_data_dir().expect("no app data directory");
+    let data_dir = paths::app_data_dir(app).expect("no app data directory");
     std::fs::create_dir_all(&data_dir).ok();
     let path = data_dir.join("settings.json");
     if let Ok(json) = serde_json::to_string_pretty(settings) {
         std::fs::write(&path, json).ok();
     }
+    let _ = app.emit("settings-changed", settings);
+}
+
+#[tauri::command]
+async fn settings_get(state: State<'_, Mutex<Settings>>) -> Result<Settings, String> {
+    Ok(state.lock().unwrap().clone())
+}
+
+/// Merge a partial settings object into the current settings, apply it to
+/// autostart and the menus, and save. Returns the updated settings.
+#[tauri::command]
+async fn settings_set(
+    settings: serde_json::Value,
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<Settings>>,
+) -> Result<Settings, String> {
+    let next = state.lock().unwrap().merged(settings)?;
+    apply_settings(&app, next.clone())?;
+    Ok(next)
+}
+
+/// Replace the current settings, updating autostart, the tray, and the
+/// menu check items to match, then save.
+fn apply_settings(app: &tauri::AppHandle, next: Settings) -> Result<(), String> {
+    let state = app.state::<Mutex<Settings>>();
+    let mut s = state.lock().unwrap();
+    if next.autostart != s.autostart {
+        let result = if next.autostart {
+            app.autolaunch().enable()
+        } else {
+            app.autolaunch().disable()
+        };
+        result.map_err(|e| format!("autostart failed: {e}"))?;
+    }
+    #[cfg(target_os = "macos")]
+    if next.show_in_menu_bar != s.show_in_menu_bar {
+        if let Some(tray) = app.tray_by_id("tray") {
+            let _ = tray.set_visible(next.show_in_menu_bar);
+        }
+    }
+    logging::set_level(next.log_level);
+    metrics::configure(app, next.metrics_port);
+    *s = next;
+    save_settings(app, &s);
+    let next = s.clone();
+    drop(s);
+
+    sync_check_items(app, "autostart", next.autostart);
+    sync_check_items(app, "run-in-background", next.run_in_background);
+    sync_check_items(app, "show-in-menu-bar", next.show_in_menu_bar);
+    sync_check_items(app, "tray-click-toggles", next.tray_click_toggles);
+    sync_check_items(app, "menu-bar-count", next.menu_bar_count);
+    tray_status::refresh(app);
+    sync_check_items(app, "install-on-quit", next.install_on_quit);
+    sync_check_items(app, "start-hidden", next.start_hidden);
+    sync_check_items(app, "notify-servers", next.notify_server_lifecycle);
+    sync_check_items(app, "notify-clients", next.notify_new_clients);
+    sync_check_items(app, "notify-updates", next.notify_updates);
+    for c in UpdateChannel::ALL {
+        sync_check_items(app, &c.menu_id(), c == next.channel);
+    }
+    Ok(())
 }
 
 // -- Sidecar resolution --
 
+/// `(file name, SHA-256)` of each sidecar bundled at build time.
+const SIDECAR_HASHES: &[(&str, &str)] =
+    include!(concat!(env!("OUT_DIR"), "/sidecar_hashes.rs"));
+
+/// Check `path` against its hash in `hashes`. Sidecars the build didn't
+/// bundle, such as the untagged ones of dev builds, aren't checked.
+fn verify_sidecar(path: &std::path::Path, hashes: &[(&str, &str)]) -> Result<(), String> {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write as _;
+
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let Some((_, expected)) = hashes.iter().find(|(n, _)| *n == name) else {
+        return Ok(());
+    };
+    let mut file = std::fs::File::open(path).map_err(|e| format!("verify failed: {e}"))?;
+    let mut digest = Sha256::new();
+    std::io::copy(&mut file, &mut digest).map_err(|e| format!("verify failed: {e}"))?;
+    let actual = digest
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut out, b| {
+            let _ = write!(out, "{b:02x}");
+            out
+        });
+    if actual != *expected {
+        return Err(format!(
+            "{} does not match the build (sha256 {actual})",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Other triples whose binaries run on `triple`: x64 under emulation on
+/// ARM64 Windows and macOS, and the other libc on Linux.
+fn fallback_triples(triple: &str) -> Vec<String> {
+    let mut fallbacks = Vec::new();
+    if let Some(rest) = triple.strip_prefix("aarch64-") {
+        if rest.ends_with("-windows-msvc") || rest == "apple-darwin" {
+            fallbacks.push(format!("x86_64-{rest}"));
+        }
+    }
+    if let Some(base) = triple.strip_suffix("-linux-gnu") {
+        fallbacks.push(format!("{base}-linux-musl"));
+    } else if let Some(base) = triple.strip_suffix("-linux-musl") {
+        fallbacks.push(format!("{base}-linux-gnu"));
+    }
+    fallbacks
+}
+
 /// Resolve the path to a sidecar binary, trying multiple candidate paths.
 pub(crate) fn resolve_sidecar(
     app: &tauri::AppHandle,
@@ -92,15 +452,21 @@ pub(crate) fn resolve_sidecar(
 
     let mut candidates: Vec<PathBuf> = Vec::new();
 
+    let triples: Vec<String> = std::iter::once(target_triple.to_string())
+        .chain(fallback_triples(target_triple))
+        .collect();
+
     for dir in [Some(&resource_dir), exe_dir.as_ref()]
         .into_iter()
         .flatten()
     {
-        // With triple suffix (standard Tauri sidecar naming)
-        candidates.push(dir.join(name).with_file_name(format!(
-            "{base_name}-{target_triple}{ext}",
-            ext = std::env::consts::EXE_SUFFIX,
-        )));
+        // With triple suffix (standard Tauri sidecar naming), ours first
+        for triple in &triples {
+            candidates.push(dir.join(name).with_file_name(format!(
+                "{base_name}-{triple}{ext}",
+                ext = std::env::consts::EXE_SUFFIX,
+            )));
+        }
         // Without triple suffix (dev builds)
         candidates.push(dir.join(name).with_file_name(format!(
             "{base_name}{ext}",
@@ -110,6 +476,12 @@ pub(crate) fn resolve_sidecar(
 
     for candidate in &candidates {
         if candidate.exists() {
+            // Only logged: the bundler code-signs sidecars on macOS and
+            // Windows after they were hashed, which changes their bytes
+            if let Err(e) = verify_sidecar(candidate, SIDECAR_HASHES) {
+                tracing::warn!("sidecar: {e}");
+            }
+            tracing::info!("sidecar: using {}", candidate.display());
             let path = candidate.clone();
             #[cfg(windows)]
             let path = strip_win_prefix(path);
@@ -137,16 +509,279 @@ fn show_main_window(app: &tauri::AppHandle) {
     }
 }
 
-// -- macOS menu/tray check item sync --
+/// Tray left click: hide the window if it's in front, otherwise bring it
+/// up. Only shows it when `toggle` is off.
+fn toggle_main_window(app: &tauri::AppHandle, toggle: bool) {
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    let in_front = window.is_visible().unwrap_or(false)
+        && !window.is_minimized().unwrap_or(false)
+        && window.is_focused().unwrap_or(false);
+    if toggle && in_front {
+        let _ = window.hide();
+    } else {
+        show_main_window(app);
+    }
+}
 
-#[cfg(target_os = "macos")]
-struct CheckItemSync(HashMap<String, Vec<CheckMenuItem<tauri::Wry>>>);
+// -- Menus --
 
-/// Keep `CheckMenuItems` in sync across app menu and tray menu on macOS.
-#[cfg(target_os = "macos")]
+/// Build the Settings submenu. Each menu needs its own item instances
+/// (macOS `NSMenuItem` can only have one parent).
+fn build_settings_menu<M: Manager<tauri::Wry>>(
+    app: &M,
+    settings: &Settings,
+) -> tauri::Result<tauri::menu::Submenu<tauri::Wry>> {
+    let autostart_i = CheckMenuItem::with_id(
+        app,
+        "autostart",
+        "Start at Login",
+        true,
+        settings.autostart,
+        None::<&str>,
+    )?;
+    let background_i = CheckMenuItem::with_id(
+        app,
+        "run-in-background",
+        "Run in Background",
+        true,
+        settings.run_in_background,
+        None::<&str>,
+    )?;
+    let start_hidden_i = CheckMenuItem::with_id(
+        app,
+        "start-hidden",
+        "Start Hidden",
+        true,
+        settings.start_hidden,
+        None::<&str>,
+    )?;
+    let install_on_quit_i = CheckMenuItem::with_id(
+        app,
+        "install-on-quit",
+        "Install Updates on Quit",
+        true,
+        settings.install_on_quit,
+        None::<&str>,
+    )?;
+    let notifications_menu = SubmenuBuilder::new(app, "Notifications")
+        .item(&CheckMenuItem::with_id(
+            app,
+            "notify-servers",
+            "Server Started or Stopped",
+            true,
+            settings.notify_server_lifecycle,
+            None::<&str>,
+        )?)
+        .item(&CheckMenuItem::with_id(
+            app,
+            "notify-clients",
+            "New Device Connected",
+            true,
+            settings.notify_new_clients,
+            None::<&str>,
+        )?)
+        .item(&CheckMenuItem::with_id(
+            app,
+            "notify-updates",
+            "Update Installed",
+            true,
+            settings.notify_updates,
+            None::<&str>,
+        )?)
+        .build()?;
+    let mut channel_menu = SubmenuBuilder::new(app, "Update Channel");
+    for channel in UpdateChannel::ALL {
+        channel_menu = channel_menu.item(&CheckMenuItem::with_id(
+            app,
+            channel.menu_id(),
+            channel.label(),
+            true,
+            settings.channel == channel,
+            None::<&str>,
+        )?);
+    }
+    let channel_menu = channel_menu.build()?;
+    let export_i = MenuItem::with_id(
+        app,
+        "settings-export",
+        "Export Settings to Downloads",
+        true,
+        None::<&str>,
+    )?;
+    let import_i = MenuItem::with_id(
+        app,
+        "settings-import",
+        "Import Settings from Downloads",
+        true,
+        None::<&str>,
+    )?;
+    let mut builder = SubmenuBuilder::new(app, "Settings")
+        .item(&autostart_i)
+        .item(&background_i)
+        .item(&start_hidden_i)
+        .item(&install_on_quit_i)
+        .item(&notifications_menu)
+        .item(&channel_menu)
+        .separator()
+        .item(&export_i)
+        .item(&import_i);
+    #[cfg(target_os = "macos")]
+    {
+        let show_in_menu_bar_i = CheckMenuItem::with_id(
+            app,
+            "show-in-menu-bar",
+            "Show Icon in Menu Bar",
+            true,
+            settings.show_in_menu_bar,
+            None::<&str>,
+        )?;
+        let menu_bar_count_i = CheckMenuItem::with_id(
+            app,
+            "menu-bar-count",
+            "Show Server Count in Menu Bar",
+            true,
+            settings.menu_bar_count,
+            None::<&str>,
+        )?;
+        builder = builder
+            .item(&show_in_menu_bar_i)
+            .item(&menu_bar_count_i);
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let tray_click_toggles_i = CheckMenuItem::with_id(
+            app,
+            "tray-click-toggles",
+            "Tray Click Hides Window",
+            true,
+            settings.tray_click_toggles,
+            None::<&str>,
+        )?;
+        builder = builder.item(&tray_click_toggles_i);
+    }
+    builder.build()
+}
+
+/// Build the tray menu, with an entry per running server at the top.
+fn build_tray_menu<M: Manager<tauri::Wry>>(
+    app: &M,
+    settings: &Settings,
+    servers: &[servers::ServerInfo],
+    recent_folders: &[String],
+    profile_names: &[String],
+) -> tauri::Result<Menu<tauri::Wry>> {
+    let menu = Menu::new(app)?;
+    if !servers.is_empty() {
+        for server in servers {
+            menu.append(&servers::server_submenu(app, server)?)?;
+        }
+        menu.append(&PredefinedMenuItem::separator(app)?)?;
+    }
+    let show_i = MenuItem::with_id(app, "show", "Show App", true, None::<&str>)?;
+    let update_i = MenuItem::with_id(
+        app,
+        "check-updates",
+        "Check for Updates",
+        true,
+        None::<&str>,
+    )?;
+    let rollback_i = MenuItem::with_id(
+        app,
+        "rollback-update",
+        "Revert Last Update",
+        true,
+        None::<&str>,
+    )?;
+    let repair_i = MenuItem::with_id(
+        app,
+        "repair-native-host",
+        "Repair Browser Integration",
+        true,
+        None::<&str>,
+    )?;
+    let diagnostics_i = MenuItem::with_id(
+        app,
+        "generate-diagnostics",
+        "Save Diagnostics for Bug Report",
+        true,
+        None::<&str>,
+    )?;
+    let quit_i = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    menu.append_items(&[
+        &show_i,
+        &recents::recents_submenu(app, recent_folders)?,
+        &profiles::profiles_submenu(app, profile_names)?,
+        &update_i,
+        &rollback_i,
+        &repair_i,
+        &diagnostics_i,
+        &PredefinedMenuItem::separator(app)?,
+        &build_settings_menu(app, settings)?,
+        &PredefinedMenuItem::separator(app)?,
+        &quit_i,
+    ])?;
+    Ok(menu)
+}
+
+/// Rebuild the tray menu after the server list changes.
+pub(crate) fn rebuild_tray_menu(app: &tauri::AppHandle) {
+    let settings = app.state::<Mutex<Settings>>().lock().unwrap().clone();
+    let servers = app.state::<servers::ServerRegistry>().list();
+    let recent_folders = app.state::<recents::RecentFolders>().list();
+    let profile_names = profiles::profile_names(app);
+    let menu = match build_tray_menu(app, &settings, &servers, &recent_folders, &profile_names) {
+        Ok(menu) => menu,
+        Err(e) => {
+            tracing::error!("tray: failed to rebuild menu: {e}");
+            return;
+        }
+    };
+    if let Some(tray) = app.tray_by_id("tray") {
+        let _ = tray.set_menu(Some(menu.clone()));
+    }
+    if let Some(sync) = app.try_state::<CheckItemSync>() {
+        *sync.0.lock().unwrap() = collect_check_items(app.menu(), &menu);
+    }
+}
+
+// -- Menu/tray check item sync --
+
+type CheckItemMap = HashMap<String, Vec<CheckMenuItem<tauri::Wry>>>;
+
+struct CheckItemSync(Mutex<CheckItemMap>);
+
+fn collect_check_items(
+    app_menu: Option<Menu<tauri::Wry>>,
+    tray_menu: &Menu<tauri::Wry>,
+) -> CheckItemMap {
+    fn collect(items: Vec<MenuItemKind<tauri::Wry>>, map: &mut CheckItemMap) {
+        for item in items {
+            match item {
+                MenuItemKind::Check(c) => {
+                    map.entry(c.id().as_ref().to_string()).or_default().push(c);
+                }
+                MenuItemKind::Submenu(sub) => {
+                    collect(sub.items().unwrap_or_default(), map);
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut map = CheckItemMap::new();
+    if let Some(app_menu) = app_menu {
+        collect(app_menu.items().unwrap_or_default(), &mut map);
+    }
+    collect(tray_menu.items().unwrap_or_default(), &mut map);
+    map
+}
+
+/// Keep `CheckMenuItems` in sync across app menu and tray menu, and restore
+/// the intended state after the platform toggles an item on click.
 fn sync_check_items(app: &tauri::AppHandle, id: &str, checked: bool) {
     if let Some(sync) = app.try_state::<CheckItemSync>() {
-        if let Some(items) = sync.0.get(id) {
+        if let Some(items) = sync.0.lock().unwrap().get(id) {
             for item in items {
                 let _ = item.set_checked(checked);
             }
@@ -156,14 +791,127 @@ fn sync_check_items(app: &tauri::AppHandle, id: &str, checked: bool) {
 
 // -- Menu event handler --
 
+/// Ask the webview to run an update check, passing the proxy it should use.
+fn emit_check_for_updates(app: &tauri::AppHandle) {
+    let proxy = app
+        .state::<Mutex<Settings>>()
+        .lock()
+        .unwrap()
+        .updater_proxy()
+        .map(|url| url.to_string());
+    let _ = app.emit("check-for-updates", serde_json::json!({ "proxy": proxy }));
+}
+
+/// Flip a boolean setting from a menu check item and save it.
+fn toggle_setting(app: &tauri::AppHandle, id: &str, field: fn(&mut Settings) -> &mut bool) {
+    let state = app.state::<Mutex<Settings>>();
+    let mut s = state.lock().unwrap();
+    let value = field(&mut s);
+    *value = !*value;
+    let checked = *value;
+    save_settings(app, &s);
+    drop(s);
+    sync_check_items(app, id, checked);
+}
+
+fn set_update_channel(app: &tauri::AppHandle, channel: UpdateChannel) {
+    let state = app.state::<Mutex<Settings>>();
+    let mut s = state.lock().unwrap();
+    s.channel = channel;
+    save_settings(app, &s);
+    drop(s);
+    for c in UpdateChannel::ALL {
+        sync_check_items(app, &c.menu_id(), c == channel);
+    }
+}
+
 fn handle_menu_event(app: &tauri::AppHandle, event_id: &str) {
+    if let Some(channel) = UpdateChannel::from_menu_id(event_id) {
+        set_update_channel(app, channel);
+        return;
+    }
+    if let Some((action, id)) = servers::ServerAction::from_menu_id(event_id) {
+        servers::handle_server_action(app, action, id);
+        return;
+    }
+    if let Some(index) = recents::folder_from_menu_id(event_id) {
+        recents::handle_recent_folder(app, index);
+        return;
+    }
+    if let Some(index) = profiles::profile_from_menu_id(event_id) {
+        profiles::handle_profile(app, index);
+        return;
+    }
+
     match event_id {
         "show" => {
             show_main_window(app);
         }
+        recents::CLEAR_MENU_ID => {
+            app.state::<recents::RecentFolders>().clear(app);
+            rebuild_tray_menu(app);
+        }
         "check-updates" => {
             show_main_window(app);
-            let _ = app.emit("check-for-updates", ());
+            emit_check_for_updates(app);
+        }
+        "rollback-update" => {
+            let app = app.clone();
+            std::thread::spawn(move || match rollback::rollback(&app) {
+                Ok(reverted) => {
+                    let state = app.state::<Mutex<Settings>>();
+                    let mut s = state.lock().unwrap();
+                    s.skipped_version = Some(reverted);
+                    save_settings(&app, &s);
+                    drop(s);
+                    quit::restart_blocking(&app);
+                }
+                Err(e) => {
+                    tracing::error!("rollback: failed: {e}");
+                    let _ = app.emit("update-rollback-failed", e);
+                }
+            });
+        }
+        "settings-export" => {
+            let result = settings_transfer::default_export_path()
+                .ok_or_else(|| "no downloads directory".to_string())
+                .and_then(|path| {
+                    settings_transfer::export_to(app, &path)?;
+                    Ok(path)
+                });
+            match result {
+                Ok(path) => {
+                    let _ = app.opener().reveal_item_in_dir(&path);
+                }
+                Err(e) => tracing::error!("settings-export: {e}"),
+            }
+        }
+        "settings-import" => {
+            let result = settings_transfer::default_export_path()
+                .ok_or_else(|| "no downloads directory".to_string())
+                .and_then(|path| settings_transfer::import_from(app, &path));
+            if let Err(e) = result {
+                tracing::error!("settings-import: {e}");
+            }
+        }
+        "generate-diagnostics" => {
+            let app = app.clone();
+            std::thread::spawn(move || match diagnostics::generate(&app) {
+                Ok(path) => {
+                    let _ = app.opener().reveal_item_in_dir(&path);
+                }
+                Err(e) => tracing::error!("diagnostics: {e}"),
+            });
+        }
+        "repair-native-host" => {
+            match native_host::check_native_messaging_hosts(app, true) {
+                Ok(status) => {
+                    let _ = app.emit("native-host-status", &status);
+                }
+                Err(e) => {
+                    tracing::error!("native-host: repair failed: {e}");
+                }
+            }
         }
         "autostart" => {
             let state = app.state::<Mutex<Settings>>();
@@ -177,7 +925,6 @@ fn handle_menu_event(app: &tauri::AppHandle, event_id: &str) {
             }
             save_settings(app, &s);
             drop(s);
-            #[cfg(target_os = "macos")]
             sync_check_items(app, "autostart", checked);
         }
         "run-in-background" => {
@@ -187,9 +934,42 @@ fn handle_menu_event(app: &tauri::AppHandle, event_id: &str) {
             let checked = s.run_in_background;
             save_settings(app, &s);
             drop(s);
-            #[cfg(target_os = "macos")]
             sync_check_items(app, "run-in-background", checked);
         }
+        "start-hidden" => {
+            let state = app.state::<Mutex<Settings>>();
+            let mut s = state.lock().unwrap();
+            s.start_hidden = !s.start_hidden;
+            let checked = s.start_hidden;
+            save_settings(app, &s);
+            drop(s);
+            sync_check_items(app, "start-hidden", checked);
+        }
+        "menu-bar-count" => {
+            toggle_setting(app, event_id, |s| &mut s.menu_bar_count);
+            tray_status::refresh(app);
+        }
+        "tray-click-toggles" => {
+            toggle_setting(app, event_id, |s| &mut s.tray_click_toggles);
+        }
+        "notify-servers" => {
+            toggle_setting(app, event_id, |s| &mut s.notify_server_lifecycle);
+        }
+        "notify-clients" => {
+            toggle_setting(app, event_id, |s| &mut s.notify_new_clients);
+        }
+        "notify-updates" => {
+            toggle_setting(app, event_id, |s| &mut s.notify_updates);
+        }
+        "install-on-quit" => {
+            let state = app.state::<Mutex<Settings>>();
+            let mut s = state.lock().unwrap();
+            s.install_on_quit = !s.install_on_quit;
+            let checked = s.install_on_quit;
+            save_settings(app, &s);
+            drop(s);
+            sync_check_items(app, "install-on-quit", checked);
+        }
         "show-in-menu-bar" => {
             let state = app.state::<Mutex<Settings>>();
             let mut s = state.lock().unwrap();
@@ -200,20 +980,33 @@ fn handle_menu_event(app: &tauri::AppHandle, event_id: &str) {
             if let Some(tray) = app.tray_by_id("tray") {
                 let _ = tray.set_visible(visible);
             }
-            #[cfg(target_os = "macos")]
             sync_check_items(app, "show-in-menu-bar", visible);
         }
         "quit" => {
             app.exit(0);
         }
         _ => {
-            eprintln!("handle_menu_event: unhandled event: {event_id}");
+            tracing::warn!("handle_menu_event: unhandled event: {event_id}");
         }
     }
 }
 
 // -- Entry point --
 
+/// Record in the shared state file that this app started or stopped.
+fn publish_running(app: &tauri::AppHandle, running: bool) {
+    let version = app.package_info().version.to_string();
+    let result = ok200_common::shared_state::update(|s| {
+        s.app_version = Some(version);
+        s.running = running;
+        s.pid = running.then(std::process::id);
+        s.servers.clear();
+    });
+    if let Err(e) = result {
+        tracing::warn!("failed to update shared state: {e}");
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let context = tauri::generate_context!();
@@ -222,20 +1015,52 @@ pub fn run() {
     let args: Vec<String> = std::env::args().collect();
     let check_update = args.iter().any(|a| a == "--check-update");
     let auto_update = args.iter().any(|a| a == "--auto-update");
+    let identifier = context.config().identifier.clone();
     if check_update || auto_update {
+        logging::init(&identifier, "updater");
         headless_updater::run(auto_update, context);
         return;
     }
+    if let Some(i) = args.iter().position(|a| a == "--serve") {
+        headless_serve::run(&args[i + 1..]);
+    }
+    if let Some(i) = args.iter().position(|a| a == "--install-update") {
+        offline_update::run(args.get(i + 1).map(String::as_str), &context);
+        return;
+    }
+    logging::init(&identifier, "app");
+    let launched_hidden = args.iter().any(|a| a == "--hidden");
+    let launch_folder = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| launch_args::folder_arg(&args, &cwd));
+    let launch_link = deep_link::from_args(&args);
 
     let app = tauri::Builder::default()
         .manage(tcp::TcpState::new())
         .manage(fs_commands::FsState::new())
-        .invoke_handler(tauri::generate_handler![
+        .manage(fs_audit::FsAudit::default())
+        .manage(capability_tokens::CapabilityTokens::default())
+        .manage(folder_grants::FolderGrants::default())
+        .manage(servers::ServerRegistry::default())
+        .manage(http::HttpState::default())
+        .manage(ftp::FtpState::default())
+        .manage(discovery::Discovery::default())
+        .manage(metrics::MetricsServer::default())
+        .manage(quit::QuitConfirmed::default())
+        .manage(launch_args::LaunchFolder(Mutex::new(launch_folder)))
+        .manage(deep_link::PendingDeepLink(Mutex::new(launch_link)))
+        .invoke_handler(capability_tokens::guard(tauri::generate_handler![
             tcp::tcp_server_create,
+            tcp::tcp_server_reattach,
+            tcp::tcp_connect,
             tcp::tcp_send,
+            tcp::tcp_send_json,
+            tcp::tcp_send_file,
             tcp::tcp_close,
             tcp::tcp_server_close,
             tcp::tcp_server_address,
+            tcp::tcp_server_info,
+            tcp::tcp_gc_stats,
             fs_commands::fs_open,
             fs_commands::fs_read,
             fs_commands::fs_write,
@@ -243,93 +1068,212 @@ pub fn run() {
             fs_commands::fs_stat,
             fs_commands::fs_exists,
             fs_commands::fs_readdir,
+            fs_commands::fs_readdir_detailed,
+            fs_commands::fs_readlink,
+            fs_commands::fs_hardlink,
+            fs_commands::fs_get_xattr,
+            fs_commands::fs_set_xattr,
+            fs_commands::fs_list_xattrs,
+            fs_commands::fs_resolve_within,
+            folder_grants::pick_folder_and_grant,
+            folder_grants::folder_grant_path,
             fs_commands::fs_mkdir,
             fs_commands::fs_delete,
             fs_commands::fs_realpath,
             fs_commands::fs_list_tree,
+            tree_snapshots::fs_tree_snapshot,
+            tree_snapshots::fs_tree_diff,
+            tree_sync::fs_tree_sync,
+            fs_commands::fs_operation_start,
+            fs_commands::fs_cancel,
             fs_commands::fs_truncate,
             fs_commands::fs_sync,
-        ])
-        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
+            fs_commands::os_reveal,
+            fs_audit::fs_audit_tail,
+            capability_tokens::capability_issue,
+            capability_tokens::capability_revoke,
+            native_host::native_host_status,
+            native_host::native_host_browsers,
+            updates::update_skip_version,
+            updates::update_snooze,
+            updates::update_install_from_file,
+            updates::update_stage,
+            updates::update_staged,
+            updates::update_rollback,
+            updates::update_rollback_version,
+            settings_get,
+            settings_set,
+            settings_transfer::settings_export,
+            settings_transfer::settings_import,
+            legacy_import::legacy_import,
+            servers::server_register,
+            servers::server_unregister,
+            servers::server_list,
+            profiles::server_profile_get,
+            profiles::server_profile_set,
+            profiles::profile_save,
+            profiles::profile_load,
+            profiles::profile_list,
+            profiles::profile_delete,
+            profiles::profile_start,
+            recents::recent_folders_list,
+            recents::recent_folders_clear,
+            launch_args::launch_folder_take,
+            http::http_server_create,
+            http::http_server_close,
+            http::http_server_info,
+            http::http_server_update,
+            http::http_server_rotate_token,
+            http::http_server_mount,
+            http::http_server_unmount,
+            ftp::ftp_server_create,
+            ftp::ftp_server_close,
+            ftp::ftp_server_list,
+            #[cfg(feature = "sftp")]
+            sftp::sftp_server_create,
+            #[cfg(feature = "sftp")]
+            sftp::sftp_server_close,
+            #[cfg(feature = "sftp")]
+            sftp::sftp_server_list,
+            discovery::discovery_start,
+            discovery::discovery_stop,
+            discovery::discovery_list,
+            #[cfg(feature = "webrtc")]
+            webrtc::webrtc_peer_create,
+            #[cfg(feature = "webrtc")]
+            webrtc::webrtc_peer_connect,
+            #[cfg(feature = "webrtc")]
+            webrtc::webrtc_send_file,
+            #[cfg(feature = "webrtc")]
+            webrtc::webrtc_peer_close,
+            #[cfg(feature = "webrtc")]
+            webrtc::webrtc_peer_list,
+            inspector::inspector_list,
+            inspector::inspector_get,
+            inspector::inspector_clear,
+            har::inspector_export_har,
+            usage::stats_get,
+            plugins::plugins_list,
+            plugins::plugins_set_enabled,
+            plugins::plugins_reload,
+            quit::quit_confirm,
+            quit::quit_cancel,
+            quit::app_restart,
+            secrets::secret_set,
+            secrets::secret_get,
+            secrets::secret_delete,
+            deep_link::deep_link_take,
+            logging::logs_tail,
+            logging::logs_open_folder,
+            diagnostics::generate_diagnostics,
+            dns::network_resolve,
+        ]))
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
             show_main_window(app);
+            launch_args::forward(app, &args, &cwd);
         }))
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_process::init())
-        .plugin(tauri_plugin_autostart::init(
-            tauri_plugin_autostart::MacosLauncher::LaunchAgent,
-            None,
-        ))
         .plugin(tauri_plugin_window_state::Builder::default().build())
+        .on_page_load(|webview, payload| {
+            // Keep tcp events for the page that replaces this one
+            if payload.event() == tauri::webview::PageLoadEvent::Started {
+                let state = webview.state::<tcp::TcpState>();
+                tauri::async_runtime::spawn(state.detach_webview(webview.label()));
+            }
+        })
         .on_window_event(|window, event| {
-            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
-                let state = window.app_handle().state::<Mutex<Settings>>();
-                if state.lock().unwrap().run_in_background {
-                    let _ = window.hide();
-                    api.prevent_close();
-                } else if let Some(tray) = window.app_handle().tray_by_id("tray") {
-                    let _ = tray.set_tooltip(Some("200 OK"));
-                    #[cfg(target_os = "macos")]
-                    let _ = tray.set_title(Some(""));
+            match event {
+                tauri::WindowEvent::CloseRequested { api, .. } => {
+                    let state = window.app_handle().state::<Mutex<Settings>>();
+                    if state.lock().unwrap().run_in_background {
+                        let _ = window.hide();
+                        api.prevent_close();
+                    } else if let Some(tray) = window.app_handle().tray_by_id("tray") {
+                        let _ = tray.set_tooltip(Some("200 OK"));
+                        #[cfg(target_os = "macos")]
+                        let _ = tray.set_title(Some(""));
+                    }
+                }
+                tauri::WindowEvent::ThemeChanged(_) => {
+                    tray_icon::refresh(window.app_handle());
+                }
+                tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) => {
+                    folder_grants::handle_drop(window.app_handle(), paths);
                 }
+                tauri::WindowEvent::Destroyed => {
+                    window
+                        .state::<capability_tokens::CapabilityTokens>()
+                        .revoke_webview(window.label());
+                }
+                _ => {}
             }
         })
         .setup(move |app| {
-            // Auto-updater with check-for-update ID header
+            // Auto-updater with check-for-update ID header. The channel
+            // header here is only the startup default; `update_stage` sets
+            // the current one on each check.
             #[cfg(desktop)]
             {
-                let mut builder = tauri_plugin_updater::Builder::new();
+                let channel = load_settings(app.handle()).channel;
+                let mut builder = tauri_plugin_updater::Builder::new()
+                    .header("X-Update-Channel", channel.as_str())?;
                 if let Some(cfu_id) = ok200_common::get_or_create_cfu_id() {
                     builder = builder.header("X-CFU-Id", &cfu_id)?;
                 }
                 app.handle().plugin(builder.build())?;
             }
 
+            // Hold the app lock while running so the host daemon stands down
+            if let Some(lock_path) = ok200_common::app_lock_path() {
+                match ok200_common::lock::try_lock_exclusive(&lock_path) {
+                    Ok(Some(lock)) => {
+                        app.manage(lock);
+                        publish_running(app.handle(), true);
+                    }
+                    Ok(None) => tracing::info!("app-lock: already held by another process"),
+                    Err(e) => {
+                        tracing::warn!("app-lock: failed to lock {}: {e}", lock_path.display());
+                    }
+                }
+            }
+
             // Settings
             let settings = load_settings(app.handle());
+            logging::set_level(settings.log_level);
             app.manage(Mutex::new(settings.clone()));
+            metrics::configure(app.handle(), settings.metrics_port);
+            app.manage(usage::Usage::load(app.handle()));
+            app.manage(plugins::PluginHost::load(app.handle()));
+            #[cfg(feature = "webrtc")]
+            app.manage(webrtc::WebRtcState::default());
+            #[cfg(feature = "sftp")]
+            app.manage(sftp::SftpState::default());
+            usage::spawn_flush(app.handle());
+
+            // Login launches carry --hidden only while start_hidden is on.
+            // The launcher entry stores the args, so refresh it on every start
+            // in case the setting changed since it was written.
+            app.handle().plugin(tauri_plugin_autostart::init(
+                tauri_plugin_autostart::MacosLauncher::LaunchAgent,
+                settings.start_hidden.then(|| vec!["--hidden"]),
+            ))?;
+            if settings.autostart {
+                let _ = app.autolaunch().enable();
+            }
+            app.manage(updates::StagedUpdate::default());
+            if let Some(version) = app.config().version.as_deref() {
+                updates::discard_installed_staged(version);
+            }
 
-            // Build settings submenu items. Each menu needs its own item
-            // instances (macOS NSMenuItem can only have one parent).
-            let build_settings_menu = |app: &tauri::App,
-                                       settings: &Settings|
-             -> Result<
-                tauri::menu::Submenu<tauri::Wry>,
-                Box<dyn std::error::Error>,
-            > {
-                let autostart_i = CheckMenuItem::with_id(
-                    app,
-                    "autostart",
-                    "Start at Login",
-                    true,
-                    settings.autostart,
-                    None::<&str>,
-                )?;
-                let background_i = CheckMenuItem::with_id(
-                    app,
-                    "run-in-background",
-                    "Run in Background",
-                    true,
-                    settings.run_in_background,
-                    None::<&str>,
-                )?;
-                let mut builder = SubmenuBuilder::new(app, "Settings")
-                    .item(&autostart_i)
-                    .item(&background_i);
-                #[cfg(target_os = "macos")]
-                {
-                    let show_in_menu_bar_i = CheckMenuItem::with_id(
-                        app,
-                        "show-in-menu-bar",
-                        "Show Icon in Menu Bar",
-                        true,
-                        settings.show_in_menu_bar,
-                        None::<&str>,
-                    )?;
-                    builder = builder.item(&show_in_menu_bar_i);
+            // Keep a copy of this version around for "Revert Last Update"
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                if let Err(e) = rollback::snapshot_current_install(&handle) {
+                    tracing::info!("rollback: snapshot skipped: {e}");
                 }
-                Ok(builder.build()?)
-            };
+            });
 
             // macOS native app menu bar
             #[cfg(target_os = "macos")]
@@ -367,64 +1311,21 @@ pub fn run() {
             }
 
             // System tray (separate item instances)
-            let tray_settings_menu = build_settings_menu(app, &settings)?;
-            let tray_menu = {
-                let show_i =
-                    MenuItem::with_id(app, "show", "Show App", true, None::<&str>)?;
-                let update_i = MenuItem::with_id(
-                    app,
-                    "check-updates",
-                    "Check for Updates",
-                    true,
-                    None::<&str>,
-                )?;
-                let quit_i =
-                    MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
-                let sep1 = PredefinedMenuItem::separator(app)?;
-                let sep2 = PredefinedMenuItem::separator(app)?;
-
-                Menu::with_items(
-                    app,
-                    &[
-                        &show_i,
-                        &update_i,
-                        &sep1,
-                        &tray_settings_menu,
-                        &sep2,
-                        &quit_i,
-                    ],
-                )?
-            };
-
-            // Collect CheckMenuItems for macOS sync
-            #[cfg(target_os = "macos")]
-            {
-                let mut sync_map: HashMap<String, Vec<CheckMenuItem<tauri::Wry>>> =
-                    HashMap::new();
-                fn collect_checks(
-                    items: Vec<MenuItemKind<tauri::Wry>>,
-                    map: &mut HashMap<String, Vec<CheckMenuItem<tauri::Wry>>>,
-                ) {
-                    for item in items {
-                        match item {
-                            MenuItemKind::Check(c) => {
-                                map.entry(c.id().as_ref().to_string())
-                                    .or_default()
-                                    .push(c);
-                            }
-                            MenuItemKind::Submenu(sub) => {
-                                collect_checks(sub.items().unwrap_or_default(), map);
-                            }
-                            _ => {}
-                        }
-                    }
-                }
-                if let Some(app_menu) = app.menu() {
-                    collect_checks(app_menu.items().unwrap_or_default(), &mut sync_map);
-                }
-                collect_checks(tray_menu.items().unwrap_or_default(), &mut sync_map);
-                app.manage(CheckItemSync(sync_map));
-            }
+            let recent_folders = recents::RecentFolders::load(app.handle());
+            let tray_menu = build_tray_menu(
+                app,
+                &settings,
+                &[],
+                &recent_folders.list(),
+                &profiles::profile_names(app.handle()),
+            )?;
+            app.manage(recent_folders);
+
+            // Collect CheckMenuItems so toggles stay in sync across menus
+            app.manage(CheckItemSync(Mutex::new(collect_check_items(
+                app.menu(),
+                &tray_menu,
+            ))));
 
             // Global menu handler for both app-menu and tray-menu events
             app.on_menu_event(move |app, event| {
@@ -433,7 +1334,8 @@ pub fn run() {
 
             TrayIconBuilder::with_id("tray")
                 .tooltip("200 OK")
-                .icon(app.default_window_icon().unwrap().clone())
+                .icon(tray_icon::current(app.handle())?)
+                .icon_as_template(cfg!(target_os = "macos"))
                 .menu(&tray_menu)
                 .show_menu_on_left_click(cfg!(target_os = "macos"))
                 .on_tray_icon_event(|tray, event| {
@@ -444,12 +1346,24 @@ pub fn run() {
                             ..
                         } = event
                         {
-                            show_main_window(tray.app_handle());
+                            let app = tray.app_handle();
+                            let toggle = app
+                                .state::<Mutex<Settings>>()
+                                .lock()
+                                .unwrap()
+                                .tray_click_toggles;
+                            toggle_main_window(app, toggle);
                         }
                     }
                 })
                 .build(app)?;
 
+            // Live server and connection counts in the tray tooltip
+            tray_status::spawn(app.handle().clone());
+            notifications::watch_new_clients(app.handle().clone());
+            network_monitor::spawn(app.handle().clone());
+            tcp::spawn_sweeper(app.handle().clone());
+
             // Hide tray icon if user disabled it (macOS only)
             #[cfg(target_os = "macos")]
             if !settings.show_in_menu_bar {
@@ -458,30 +1372,51 @@ pub fn run() {
                 }
             }
 
-            // Register native messaging host manifests
-            match native_host::register_native_messaging_hosts(app.handle()) {
-                Ok(count) => {
-                    eprintln!("native-host: registered with {count} browser(s)");
-                }
-                Err(e) => {
-                    eprintln!("native-host: registration failed: {e}");
+            // Register native messaging host manifests, and again for
+            // browsers installed later
+            native_host::watch_for_new_browsers(app.handle().clone());
+
+            // Make ok200:// links open this install
+            std::thread::spawn(|| {
+                if let Err(e) = deep_link::register_scheme() {
+                    tracing::warn!(
+                        "deep-link: failed to register {}://: {e}",
+                        deep_link::SCHEME
+                    );
                 }
-            }
+            });
 
-            // Show window on first launch
-            show_main_window(app.handle());
+            // Show window on launch unless asked to stay in the tray
+            if !(settings.start_hidden || launched_hidden) {
+                show_main_window(app.handle());
+            }
 
             Ok(())
         })
         .build(context)
         .expect("error building Tauri application");
 
-    app.run(|_app_handle, event| {
-        if let tauri::RunEvent::ExitRequested { api, code, .. } = event {
-            if code.is_none() {
-                api.prevent_exit();
+    app.run(|app_handle, event| match event {
+        tauri::RunEvent::ExitRequested { api, code, .. } => {
+            match code {
+                Some(code) if quit::allow_exit(app_handle, code) => {
+                    updates::install_staged_on_quit(app_handle);
+                }
+                _ => api.prevent_exit(),
+            }
+        }
+        tauri::RunEvent::Exit => {
+            app_handle.state::<usage::Usage>().flush();
+            app_handle.state::<discovery::Discovery>().stop();
+            publish_running(app_handle, false);
+        }
+        #[cfg(target_os = "macos")]
+        tauri::RunEvent::Opened { urls } => {
+            for url in urls {
+                deep_link::handle(app_handle, url.as_str());
             }
         }
+        _ => {}
     });
 }
 
@@ -489,6 +1424,47 @@ pub fn run() {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_fallback_triples() {
+        assert_eq!(
+            fallback_triples("aarch64-pc-windows-msvc"),
+            ["x86_64-pc-windows-msvc"]
+        );
+        assert_eq!(
+            fallback_triples("aarch64-apple-darwin"),
+            ["x86_64-apple-darwin"]
+        );
+        assert_eq!(
+            fallback_triples("aarch64-unknown-linux-gnu"),
+            ["aarch64-unknown-linux-musl"]
+        );
+        assert_eq!(
+            fallback_triples("x86_64-unknown-linux-musl"),
+            ["x86_64-unknown-linux-gnu"]
+        );
+        assert!(fallback_triples("x86_64-pc-windows-msvc").is_empty());
+        assert!(fallback_triples("x86_64-pc-windows-gnu").is_empty());
+    }
+
+    #[test]
+    fn test_verify_sidecar() {
+        let tmp = tempfile::tempdir().unwrap();
+        let host = tmp.path().join("ok200-host-x86_64-unknown-linux-gnu");
+        std::fs::write(&host, "abc").unwrap();
+        // sha256("abc")
+        let hashes = [(
+            "ok200-host-x86_64-unknown-linux-gnu",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        )];
+        assert!(verify_sidecar(&host, &hashes).is_ok());
+        std::fs::write(&host, "abd").unwrap();
+        assert!(verify_sidecar(&host, &hashes)
+            .unwrap_err()
+            .contains("does not match the build"));
+        // Not bundled, so not checked
+        assert!(verify_sidecar(&tmp.path().join("ok200-host"), &hashes).is_ok());
+    }
+
     #[test]
     fn test_settings_defaults() {
         let s = Settings::default();
@@ -506,6 +1482,8 @@ mod tests {
         assert!(!s.run_in_background);
         // show_in_menu_bar should get its default (true)
         assert!(s.show_in_menu_bar);
+        assert!(s.tray_click_toggles);
+        assert_eq!(s.update_retries, default_update_retries());
     }
 
     #[test]
@@ -514,18 +1492,110 @@ mod tests {
             autostart: true,
             run_in_background: false,
             show_in_menu_bar: false,
+            tray_click_toggles: false,
+            menu_bar_count: false,
+            channel: UpdateChannel::Beta,
+            proxy_url: Some("http://proxy.example:3128".to_string()),
+            skipped_version: Some("0.2.0".to_string()),
+            snooze_until: Some(1_700_000_000),
+            start_hidden: true,
+            notify_server_lifecycle: false,
+            notify_new_clients: false,
+            notify_updates: false,
+            install_on_quit: true,
+            update_check_timeout_secs: 10,
+            update_download_timeout_secs: 120,
+            update_retries: 5,
+            default_port: 3000,
+            default_serve_folder: Some("/srv/www".to_string()),
+            bind_address: BindAddress::AllInterfaces,
+            log_level: logging::LogLevel::Debug,
+            metrics_port: Some(9464),
         };
         let json = serde_json::to_string(&s).unwrap();
         let parsed: Settings = serde_json::from_str(&json).unwrap();
         assert_eq!(parsed.autostart, s.autostart);
         assert_eq!(parsed.run_in_background, s.run_in_background);
         assert_eq!(parsed.show_in_menu_bar, s.show_in_menu_bar);
+        assert!(!parsed.tray_click_toggles);
+        assert!(!parsed.menu_bar_count);
+        assert_eq!(parsed.channel, s.channel);
+        assert_eq!(parsed.proxy_url, s.proxy_url);
+        assert_eq!(parsed.skipped_version, s.skipped_version);
+        assert_eq!(parsed.snooze_until, s.snooze_until);
+        assert_eq!(parsed.install_on_quit, s.install_on_quit);
+        assert_eq!(parsed.start_hidden, s.start_hidden);
+        assert!(!parsed.notify_server_lifecycle);
+        assert!(!parsed.notify_new_clients);
+        assert!(!parsed.notify_updates);
+        assert_eq!(parsed.update_check_timeout_secs, 10);
+        assert_eq!(parsed.update_download_timeout_secs, 120);
+        assert_eq!(parsed.update_retries, 5);
+        assert_eq!(parsed.default_port, 3000);
+        assert_eq!(parsed.default_serve_folder, s.default_serve_folder);
+        assert_eq!(parsed.bind_address, s.bind_address);
+        assert_eq!(parsed.log_level, s.log_level);
+        assert_eq!(parsed.metrics_port, Some(9464));
+    }
+
+    #[test]
+    fn test_settings_merged() {
+        let s = Settings::default();
+        assert_eq!(s.default_port, 8080);
+        assert_eq!(s.bind_address, BindAddress::Localhost);
+
+        let merged = s
+            .merged(serde_json::json!({ "default_port": 3000, "bind_address": "all_interfaces" }))
+            .unwrap();
+        assert_eq!(merged.default_port, 3000);
+        assert_eq!(merged.bind_address, BindAddress::AllInterfaces);
+        // Fields not in the patch are kept
+        assert!(merged.run_in_background);
+
+        assert!(s.merged(serde_json::json!({ "default_port": 70000 })).is_err());
+        assert!(s.merged(serde_json::json!({ "bind_address": "lan" })).is_err());
+        assert!(s.merged(serde_json::json!([1, 2])).is_err());
+        assert!(s
+            .merged(serde_json::json!({ "default_serve_folder": "/no/such/folder" }))
+            .is_err());
+        let tmp = std::env::temp_dir();
+        let merged = s
+            .merged(serde_json::json!({ "default_serve_folder": tmp }))
+            .unwrap();
+        assert_eq!(merged.default_serve_folder.as_deref(), tmp.to_str());
+    }
+
+    #[test]
+    fn test_settings_updater_proxy() {
+        let mut s = Settings::default();
+        assert!(s.updater_proxy().is_none());
+        s.proxy_url = Some("  ".to_string());
+        assert!(s.updater_proxy().is_none());
+        s.proxy_url = Some("not a url".to_string());
+        assert!(s.updater_proxy().is_none());
+        s.proxy_url = Some("http://proxy.example:3128".to_string());
+        assert_eq!(
+            s.updater_proxy().unwrap().as_str(),
+            "http://proxy.example:3128/"
+        );
+    }
+
+    #[test]
+    fn test_update_channel_serde_and_menu_ids() {
+        let s: Settings = serde_json::from_str(r#"{"channel": "nightly"}"#).unwrap();
+        assert_eq!(s.channel, UpdateChannel::Nightly);
+        assert_eq!(Settings::default().channel, UpdateChannel::Stable);
+        for channel in UpdateChannel::ALL {
+            assert_eq!(UpdateChannel::from_menu_id(&channel.menu_id()), Some(channel));
+        }
+        assert_eq!(UpdateChannel::from_menu_id("autostart"), None);
     }
 
     #[test]
     fn test_settings_missing_fields_get_defaults() {
         let s: Settings = serde_json::from_str("{}").unwrap();
         assert!(!s.autostart);
+        assert!(!s.start_hidden);
         assert!(s.run_in_background);
         assert!(s.show_in_menu_bar);
     }
@@ -0,0 +1,288 @@
+//! Live reload for `http_server`: the served folder is polled for changes,
+//! which are pushed to browsers as server-sent events. HTML responses get a
+//! small script that listens for them, swapping stylesheets when only CSS
+//! changed and reloading the page otherwise. Bursts of changes are sent
+//! as one batch once the folder settles.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+
+use super::watch_batch::{Batch, BatchOptions, Coalescer};
+
+/// Reserved URL path of the event stream.
+pub const EVENTS_PATH: &str = "/__ok200/livereload";
+/// HTML bigger than this is served without the script.
+pub const MAX_INJECT_BYTES: u64 = 16 * 1024 * 1024;
+/// Comment sent on an idle event stream so proxies don't close it.
+pub const KEEPALIVE: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Folders with more entries than this are only watched in part.
+const MAX_WATCHED: usize = 20_000;
+
+const CLIENT: &str = r#"<script>
+(() => {
+  const events = new EventSource("/__ok200/livereload");
+  events.onmessage = (e) => {
+    const { paths, total, overflow } = JSON.parse(e.data);
+    const listed = paths.length && paths.length === total && !overflow;
+    if (listed && paths.every((p) => p.endsWith(".css"))) {
+      for (const link of document.querySelectorAll('link[rel="stylesheet"]')) {
+        const url = new URL(link.href);
+        url.searchParams.set("livereload", Date.now());
+        link.href = url.href;
+      }
+    } else {
+      location.reload();
+    }
+  };
+})();
+</script>
+"#;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LiveReloadOptions {
+    /// How changes are merged before pages hear about them.
+    #[serde(flatten)]
+    pub batch: BatchOptions,
+}
+
+#[derive(Debug)]
+struct Channels {
+    options: LiveReloadOptions,
+    /// Changed URL paths, a batch per burst.
+    changes: broadcast::Sender<Batch>,
+    stopped: watch::Sender<bool>,
+}
+
+/// Turns live reload on for a server. Clones share the same channels, so
+/// every event stream hears about every change.
+#[derive(Clone, Debug)]
+pub struct LiveReload(Arc<Channels>);
+
+impl Default for LiveReload {
+    fn default() -> Self {
+        Self::new(LiveReloadOptions::default())
+    }
+}
+
+impl PartialEq for LiveReload {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for LiveReload {}
+
+/// Stops the watcher, and ends open event streams, when dropped.
+pub struct WatchGuard {
+    task: JoinHandle<()>,
+    live_reload: LiveReload,
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.live_reload.0.stopped.send_replace(true);
+    }
+}
+
+impl LiveReload {
+    pub fn new(options: LiveReloadOptions) -> Self {
+        Self(Arc::new(Channels {
+            options,
+            changes: broadcast::channel(16).0,
+            stopped: watch::channel(false).0,
+        }))
+    }
+
+    pub fn options(&self) -> &LiveReloadOptions {
+        &self.0.options
+    }
+
+    /// Poll `root` for changes until the guard is dropped.
+    pub fn watch(&self, root: PathBuf) -> WatchGuard {
+        let live_reload = self.clone();
+        let task = tokio::spawn(async move {
+            let mut coalescer = Coalescer::new(&live_reload.0.options.batch);
+            let mut previous = None;
+            loop {
+                let scan_root = root.clone();
+                let Ok(current) = tokio::task::spawn_blocking(move || snapshot(&scan_root)).await
+                else {
+                    return;
+                };
+                if let Some(previous) = &previous {
+                    let changed = changed_paths(previous, &current);
+                    if !changed.is_empty() {
+                        // Changes past the limit go unseen
+                        let overflow = current.len() >= MAX_WATCHED;
+                        coalescer.add(changed, overflow, Instant::now());
+                    }
+                }
+                if let Some(batch) = coalescer.take(Instant::now()) {
+                    // Nobody listening is fine
+                    live_reload.0.changes.send(batch).ok();
+                }
+                previous = Some(current);
+                // Look again sooner when a batch is due before the next poll
+                let pause = coalescer
+                    .due(Instant::now())
+                    .map_or(POLL_INTERVAL, |due| due.min(POLL_INTERVAL));
+                tokio::time::sleep(pause).await;
+            }
+        });
+        WatchGuard {
+            task,
+            live_reload: self.clone(),
+        }
+    }
+
+    /// Changes from now on, and whether the server has stopped.
+    pub fn subscribe(&self) -> (broadcast::Receiver<Batch>, watch::Receiver<bool>) {
+        (self.0.changes.subscribe(), self.0.stopped.subscribe())
+    }
+}
+
+pub type Snapshot = HashMap<PathBuf, (u64, Option<SystemTime>)>;
+
+/// Size and modification time of everything under `root`, skipping hidden
+/// entries (including upload temp files) and `node_modules`.
+pub fn snapshot(root: &Path) -> Snapshot {
+    let mut files = HashMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') || name == "node_modules" {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let path = entry.path();
+            // Symlinked folders aren't followed, so loops can't happen
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                dirs.push(path.clone());
+            }
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            files.insert(relative, (meta.len(), meta.modified().ok()));
+            if files.len() >= MAX_WATCHED {
+                return files;
+            }
+        }
+    }
+    files
+}
+
+/// URL paths added, removed or modified between two snapshots.
+fn changed_paths(before: &Snapshot, after: &Snapshot) -> Vec<String> {
+    let modified = after
+        .iter()
+        .filter(|(path, state)| before.get(*path) != Some(state))
+        .map(|(path, _)| path);
+    let removed = before.keys().filter(|path| !after.contains_key(*path));
+    let mut paths: Vec<String> = modified
+        .chain(removed)
+        .map(|path| {
+            let segments: Vec<_> = path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            format!("/{}", segments.join("/"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// One server-sent event announcing `batch`.
+pub fn event(batch: &Batch) -> String {
+    let data = serde_json::to_string(batch).unwrap_or_default();
+    format!("data: {data}\n\n")
+}
+
+/// `html` with the client script added before `</body>`, or at the end.
+pub fn inject(html: &[u8]) -> Vec<u8> {
+    let lower = html.to_ascii_lowercase();
+    let at = lower
+        .windows(b"</body>".len())
+        .rposition(|w| w == b"</body>")
+        .unwrap_or(html.len());
+    let mut out = Vec::with_capacity(html.len() + CLIENT.len());
+    out.extend_from_slice(&html[..at]);
+    out.extend_from_slice(CLIENT.as_bytes());
+    out.extend_from_slice(&html[at..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject() {
+        let html = inject(b"<html><BODY>hi</BODY></html>");
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.starts_with("<html><BODY>hi<script>"));
+        assert!(html.ends_with("</script>\n</BODY></html>"));
+        assert!(String::from_utf8(inject(b"bare"))
+            .unwrap()
+            .starts_with("bare<script>"));
+    }
+
+    #[test]
+    fn test_snapshot_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("css")).unwrap();
+        std::fs::create_dir_all(tmp.path().join("node_modules/x")).unwrap();
+        std::fs::write(tmp.path().join("css/app.css"), "a{}").unwrap();
+        std::fs::write(tmp.path().join("index.html"), "hi").unwrap();
+        let before = snapshot(tmp.path());
+        assert_eq!(before.len(), 3);
+
+        std::fs::write(tmp.path().join("css/app.css"), "a{color:red}").unwrap();
+        std::fs::remove_file(tmp.path().join("index.html")).unwrap();
+        std::fs::write(tmp.path().join(".hidden"), "x").unwrap();
+        std::fs::write(tmp.path().join("node_modules/x/y.js"), "x").unwrap();
+        let after = snapshot(tmp.path());
+        assert_eq!(
+            changed_paths(&before, &after),
+            ["/css/app.css", "/index.html"]
+        );
+        assert!(changed_paths(&after, &after).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_watch_broadcasts_until_stopped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let live_reload = LiveReload::default();
+        let (mut changes, mut stopped) = live_reload.subscribe();
+        let guard = live_reload.watch(tmp.path().to_path_buf());
+        tokio::time::sleep(POLL_INTERVAL / 2).await;
+        std::fs::write(tmp.path().join("new.js"), "x").unwrap();
+        let batch = tokio::time::timeout(POLL_INTERVAL * 4, changes.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(batch.paths, ["/new.js"]);
+        assert_eq!(
+            event(&batch),
+            "data: {\"paths\":[\"/new.js\"],\"total\":1,\"dirs\":{\"/\":1},\"overflow\":false}\n\n"
+        );
+
+        drop(guard);
+        stopped.changed().await.unwrap();
+        assert!(*stopped.borrow());
+    }
+}
@@ -0,0 +1,409 @@
+//! `tracing` subscriber that writes to a size-rotated file in the app data
+//! dir (and echoes to stderr). Each process writes its own channel: the app
+//! writes `app.log`, the headless updater `updater.log`.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// Log files the viewer can show.
+pub const CHANNELS: [&str; 2] = ["app", "updater"];
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+/// Rotated files kept besides the live one (`app.1.log` .. `app.3.log`).
+const KEEP_ROTATED: u32 = 3;
+const DEFAULT_TAIL_LINES: usize = 200;
+const CRATE_TARGET: &str = "ok200_desktop_lib";
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn as_tracing(self) -> Level {
+        match self {
+            Self::Error => Level::ERROR,
+            Self::Warn => Level::WARN,
+            Self::Info => Level::INFO,
+            Self::Debug => Level::DEBUG,
+            Self::Trace => Level::TRACE,
+        }
+    }
+
+    fn from_u8(n: u8) -> Self {
+        match n {
+            0 => Self::Error,
+            1 => Self::Warn,
+            3 => Self::Debug,
+            4 => Self::Trace,
+            _ => Self::Info,
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Change the level at runtime, e.g. after the setting changes.
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn level() -> LogLevel {
+    LogLevel::from_u8(LEVEL.load(Ordering::Relaxed))
+}
+
+/// `<data dir>/<identifier>/logs`, the same root as `app_data_dir`, or
+/// `data/logs` in portable mode.
+pub fn log_dir(identifier: &str) -> Option<PathBuf> {
+    if let Some(dir) = ok200_common::portable_dir() {
+        return Some(dir.join("logs"));
+    }
+    Some(dirs::data_dir()?.join(identifier).join("logs"))
+}
+
+pub fn channel_path(dir: &Path, channel: &str) -> PathBuf {
+    dir.join(format!("{channel}.log"))
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    path.with_extension(format!("{n}.log"))
+}
+
+// -- Rotating file --
+
+/// Append-only file that rotates at 5 MB, keeping three old copies. Also
+/// used for per-server access logs.
+pub struct LogFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+}
+
+impl LogFile {
+    pub fn open(path: PathBuf) -> Self {
+        let mut log = Self {
+            path,
+            file: None,
+            size: 0,
+        };
+        log.reopen();
+        if log.size > MAX_LOG_BYTES {
+            log.rotate();
+        }
+        log
+    }
+
+    fn reopen(&mut self) {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .ok();
+        self.size = std::fs::metadata(&self.path).map_or(0, |m| m.len());
+    }
+
+    /// Shift `app.log` to `app.1.log`, `app.1.log` to `app.2.log`, and so on.
+    fn rotate(&mut self) {
+        self.file = None;
+        for n in (1..KEEP_ROTATED).rev() {
+            std::fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1)).ok();
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1)).ok();
+        self.reopen();
+    }
+
+    pub fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 > MAX_LOG_BYTES {
+            self.rotate();
+        }
+        if let Some(file) = &mut self.file {
+            if file.write_all(line.as_bytes()).is_ok() {
+                self.size += line.len() as u64;
+            }
+        }
+    }
+}
+
+// -- Subscriber --
+
+/// Collects an event's message and fields into one line.
+#[derive(Default)]
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+/// UTC calendar date and time of day for a Unix time.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UtcTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+}
+
+impl UtcTime {
+    pub fn from_unix(secs: u64) -> Self {
+        let days = i64::try_from(secs / 86_400).unwrap_or(0);
+        let rem = secs % 86_400;
+        // Civil-from-days, proleptic Gregorian calendar
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        Self {
+            year: yoe + era * 400 + i64::from(month <= 2),
+            month,
+            day,
+            hour: rem / 3_600,
+            minute: rem % 3_600 / 60,
+            second: rem % 60,
+        }
+    }
+
+    /// Inverse of `from_unix`; `None` before 1970 or for invalid fields.
+    pub fn to_unix(&self) -> Option<u64> {
+        if !(1..=12).contains(&self.month) || !(1..=31).contains(&self.day) {
+            return None;
+        }
+        if self.hour > 23 || self.minute > 59 || self.second > 60 {
+            return None;
+        }
+        // Days-from-civil, the inverse of the above
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let mp = (self.month + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + self.day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+        Some(days * 86_400 + self.hour * 3_600 + self.minute * 60 + self.second)
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` for a Unix time.
+pub fn utc_timestamp(secs: u64) -> String {
+    let t = UtcTime::from_unix(secs);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
+
+fn format_line(secs: u64, meta: &Metadata<'_>, visitor: &LineVisitor) -> String {
+    let target = meta.target();
+    let target = target
+        .strip_prefix(CRATE_TARGET)
+        .map_or(target, |t| t.trim_start_matches("::"));
+    let target = if target.is_empty() { "app" } else { target };
+    format!(
+        "{} {:5} {target}: {}{}\n",
+        utc_timestamp(secs),
+        meta.level(),
+        visitor.message,
+        visitor.fields
+    )
+}
+
+struct FileSubscriber {
+    file: Mutex<LogFile>,
+    next_span: AtomicU64,
+}
+
+impl Subscriber for FileSubscriber {
+    fn register_callsite(&self, _meta: &'static Metadata<'static>) -> Interest {
+        // The level can change at runtime, so never cache the decision
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, meta: &Metadata<'_>) -> bool {
+        let max = level().as_tracing();
+        // Dependencies only get through at info and above so debug output
+        // stays about this app
+        let max = if meta.target().starts_with(CRATE_TARGET) {
+            max
+        } else {
+            max.min(Level::INFO)
+        };
+        *meta.level() <= max
+    }
+
+    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+        Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut visitor = LineVisitor::default();
+        event.record(&mut visitor);
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let line = format_line(secs, event.metadata(), &visitor);
+        eprint!("{line}");
+        self.file.lock().unwrap().write_line(&line);
+    }
+
+    fn enter(&self, _span: &Id) {}
+
+    fn exit(&self, _span: &Id) {}
+}
+
+/// Install the global subscriber writing to `channel`'s log file.
+pub fn init(identifier: &str, channel: &str) {
+    let Some(dir) = log_dir(identifier) else {
+        eprintln!("logging: no data directory, logging to stderr only");
+        return;
+    };
+    let subscriber = FileSubscriber {
+        file: Mutex::new(LogFile::open(channel_path(&dir, channel))),
+        next_span: AtomicU64::new(1),
+    };
+    if tracing::subscriber::set_global_default(subscriber).is_err() {
+        eprintln!("logging: a subscriber is already installed");
+    }
+}
+
+pub fn tail(path: &Path, lines: usize) -> Result<Vec<String>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("read {}: {e}", path.display())),
+    };
+    let all: Vec<&str> = text.lines().collect();
+    let start = all.len().saturating_sub(lines);
+    Ok(all[start..].iter().map(ToString::to_string).collect())
+}
+
+fn app_log_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    log_dir(&app.config().identifier).ok_or_else(|| "no data directory".to_string())
+}
+
+/// Last `lines` lines (default 200) of a log channel, oldest first.
+#[tauri::command]
+pub async fn logs_tail(
+    channel: String,
+    lines: Option<usize>,
+    app: tauri::AppHandle,
+) -> Result<Vec<String>, String> {
+    if !CHANNELS.contains(&channel.as_str()) {
+        return Err(format!("unknown log channel: {channel}"));
+    }
+    let path = channel_path(&app_log_dir(&app)?, &channel);
+    tail(&path, lines.unwrap_or(DEFAULT_TAIL_LINES))
+}
+
+/// Open the log folder in the system file manager.
+#[tauri::command]
+pub async fn logs_open_folder(app: tauri::AppHandle) -> Result<(), String> {
+    use tauri_plugin_opener::OpenerExt;
+
+    let dir = app_log_dir(&app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("mkdir {}: {e}", dir.display()))?;
+    app.opener()
+        .open_path(dir.to_string_lossy(), None::<&str>)
+        .map_err(|e| format!("open log folder failed: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_utc_time_roundtrip() {
+        for secs in [0, 951_782_400, 1_700_000_000] {
+            assert_eq!(UtcTime::from_unix(secs).to_unix(), Some(secs));
+        }
+        let mut t = UtcTime::from_unix(0);
+        t.month = 13;
+        assert_eq!(t.to_unix(), None);
+    }
+
+    #[test]
+    fn test_log_file_rotates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = channel_path(tmp.path(), "app");
+        let mut log = LogFile::open(path.clone());
+        log.write_line("first\n");
+        log.size = MAX_LOG_BYTES;
+        log.write_line("second\n");
+
+        assert_eq!(tail(&path, 10).unwrap(), ["second"]);
+        assert_eq!(tail(&rotated_path(&path, 1), 10).unwrap(), ["first"]);
+    }
+
+    #[test]
+    fn test_tail() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("app.log");
+        assert!(tail(&path, 5).unwrap().is_empty());
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        assert_eq!(tail(&path, 2).unwrap(), ["b", "c"]);
+        assert_eq!(tail(&path, 10).unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_log_level_roundtrip() {
+        for level in [
+            LogLevel::Error,
+            LogLevel::Warn,
+            LogLevel::Info,
+            LogLevel::Debug,
+            LogLevel::Trace,
+        ] {
+            assert_eq!(LogLevel::from_u8(level as u8), level);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Markdown files rendered as HTML pages by `http_server`, so a folder of
+//! docs reads like a site. `?raw=1` gets the file as it is.
+
+use std::path::Path;
+
+use ok200_common::http::html_escape;
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+/// Query parameter asking for the file itself.
+pub const RAW_PARAM: &str = "raw";
+/// Files bigger than this are served as they are.
+pub const MAX_RENDER_BYTES: u64 = 4 * 1024 * 1024;
+
+const STYLE: &str = "<style>
+body { max-width: 46rem; margin: 2rem auto; padding: 0 1rem; line-height: 1.6;
+  font-family: -apple-system, BlinkMacSystemFont, \"Segoe UI\", sans-serif; color: #24292f; }
+pre, code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 0.9em; }
+pre { padding: 1rem; overflow: auto; background: #f6f8fa; border-radius: 6px; }
+code { background: #f6f8fa; padding: 0.1em 0.3em; border-radius: 4px; }
+pre code { padding: 0; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 0.3rem 0.7rem; }
+blockquote { margin: 0; padding-left: 1rem; color: #57606a; border-left: 4px solid #d0d7de; }
+img { max-width: 100%; }
+@media (prefers-color-scheme: dark) {
+  body { background: #0d1117; color: #e6edf3; }
+  a { color: #58a6ff; }
+  pre, code { background: #161b22; }
+  th, td { border-color: #30363d; }
+  blockquote { color: #8d96a0; border-color: #30363d; }
+}
+</style>
+";
+
+/// Whether `path` is a Markdown file, going by its extension.
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("md") || e.eq_ignore_ascii_case("markdown"))
+}
+
+/// Text of the first top-level heading, for the page title.
+fn first_heading(source: &str) -> Option<String> {
+    let mut title: Option<String> = None;
+    for event in Parser::new(source) {
+        match event {
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            }) if title.is_none() => title = Some(String::new()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(title) = &mut title {
+                    title.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) if title.is_some() => break,
+            _ => {}
+        }
+    }
+    title.filter(|t| !t.trim().is_empty())
+}
+
+/// A styled HTML page for `source`, titled after its first heading or,
+/// failing that, `name`. Raw HTML in the file is passed through.
+pub fn render(source: &str, name: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES;
+    let title = first_heading(source).unwrap_or_else(|| name.to_string());
+    let mut page = format!(
+        "<!DOCTYPE html>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n{STYLE}<body>\n",
+        html_escape(&title)
+    );
+    html::push_html(&mut page, Parser::new_ext(source, options));
+    page.push_str("</body>\n");
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_markdown() {
+        assert!(is_markdown(Path::new("docs/README.md")));
+        assert!(is_markdown(Path::new("notes.Markdown")));
+        assert!(!is_markdown(Path::new("index.html")));
+        assert!(!is_markdown(Path::new("md")));
+    }
+
+    #[test]
+    fn test_render() {
+        let page = render(
+            "# Hello `world` & co\n\n| a | b |\n|---|---|\n| 1 | 2 |\n",
+            "x.md",
+        );
+        assert!(page.contains("<title>Hello world &amp; co</title>"));
+        assert!(page.contains("<h1>Hello <code>world</code> &amp; co</h1>"));
+        assert!(page.contains("<td>1</td>"));
+        assert!(render("just text", "notes.md").contains("<title>notes.md</title>"));
+    }
+}
@@ -0,0 +1,325 @@
+//! Prometheus text metrics: requests, bytes served and open connections
+//! for each native server (see `http`), and the TCP server and socket
+//! counts (see `tcp`). Served at `http://127.0.0.1:<metrics_port>/metrics`
+//! while the `metrics_port` setting is on; never on other interfaces.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tauri::Manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::http::HttpState;
+use super::http_server::RequestLog;
+use super::tcp::{GcStats, TcpState};
+
+pub const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Labels for responses by the first digit of their status; the last is
+/// for connections that never got one.
+const STATUS_CLASSES: [&str; 6] = ["1xx", "2xx", "3xx", "4xx", "5xx", "none"];
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+#[derive(Default, Debug)]
+struct Counters {
+    requests: [AtomicU64; STATUS_CLASSES.len()],
+    bytes: AtomicU64,
+    connections: AtomicU64,
+    active: AtomicU64,
+}
+
+/// Counters for one server. Clones share them.
+#[derive(Clone, Default, Debug)]
+pub struct ServerStats(Arc<Counters>);
+
+impl PartialEq for ServerStats {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ServerStats {}
+
+/// Counts a connection as open until dropped.
+pub struct Connection {
+    counters: Arc<Counters>,
+    id: u64,
+}
+
+impl Connection {
+    /// The connection's number on its server, from 1.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.counters.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ServerStats {
+    pub fn connection(&self) -> Connection {
+        let id = self.0.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        self.0.active.fetch_add(1, Ordering::Relaxed);
+        Connection {
+            counters: self.0.clone(),
+            id,
+        }
+    }
+
+    /// Requests logged so far, whatever their status.
+    pub fn requests(&self) -> u64 {
+        self.0.requests.iter().map(load).sum()
+    }
+
+    pub fn active(&self) -> u64 {
+        load(&self.0.active)
+    }
+
+    /// Count a logged request, including ones refused or failed.
+    pub fn record(&self, entry: &RequestLog) {
+        let class = match entry.status / 100 {
+            class @ 1..=5 => usize::from(class) - 1,
+            _ => STATUS_CLASSES.len() - 1,
+        };
+        self.0.requests[class].fetch_add(1, Ordering::Relaxed);
+        self.0.bytes.fetch_add(entry.bytes, Ordering::Relaxed);
+    }
+}
+
+/// One native server as it appears in the metrics.
+pub struct ServerMetrics {
+    pub id: u32,
+    pub port: u16,
+    pub root: String,
+    pub stats: ServerStats,
+}
+
+/// Escape a label value as the text format requires.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n");
+}
+
+fn load(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// The metrics in Prometheus text format.
+pub fn render(servers: &[ServerMetrics], tcp: (usize, usize), gc: &GcStats) -> String {
+    let mut out = String::new();
+
+    family(
+        &mut out,
+        "ok200_http_server_info",
+        "gauge",
+        "Running native servers.",
+    );
+    for s in servers {
+        let _ = writeln!(
+            out,
+            "ok200_http_server_info{{server=\"{}\",port=\"{}\",root=\"{}\"}} 1",
+            s.id,
+            s.port,
+            label(&s.root)
+        );
+    }
+    family(
+        &mut out,
+        "ok200_http_requests_total",
+        "counter",
+        "Requests handled, by status class.",
+    );
+    for s in servers {
+        for (class, count) in STATUS_CLASSES.iter().zip(&s.stats.0.requests) {
+            let _ = writeln!(
+                out,
+                "ok200_http_requests_total{{server=\"{}\",code=\"{class}\"}} {}",
+                s.id,
+                load(count)
+            );
+        }
+    }
+    let mut per_server = |name: &str, kind: &str, help: &str, counter: fn(&Counters) -> u64| {
+        family(&mut out, name, kind, help);
+        for s in servers {
+            let _ = writeln!(out, "{name}{{server=\"{}\"}} {}", s.id, counter(&s.stats.0));
+        }
+    };
+    per_server(
+        "ok200_http_response_bytes_total",
+        "counter",
+        "Body bytes sent.",
+        |c| load(&c.bytes),
+    );
+    per_server(
+        "ok200_http_connections_total",
+        "counter",
+        "Connections accepted.",
+        |c| load(&c.connections),
+    );
+    per_server(
+        "ok200_http_active_connections",
+        "gauge",
+        "Connections open now.",
+        |c| load(&c.active),
+    );
+
+    let (tcp_servers, tcp_sockets) = tcp;
+    family(
+        &mut out,
+        "ok200_tcp_servers",
+        "gauge",
+        "Listening TCP servers.",
+    );
+    let _ = writeln!(out, "ok200_tcp_servers {tcp_servers}");
+    family(&mut out, "ok200_tcp_sockets", "gauge", "Open TCP sockets.");
+    let _ = writeln!(out, "ok200_tcp_sockets {tcp_sockets}");
+    family(
+        &mut out,
+        "ok200_tcp_sockets_reaped_total",
+        "counter",
+        "Dead sockets freed.",
+    );
+    let _ = writeln!(out, "ok200_tcp_sockets_reaped_total {}", gc.reaped);
+    out
+}
+
+async fn snapshot(app: &tauri::AppHandle) -> String {
+    let servers = app.state::<HttpState>().metrics();
+    let tcp = app.state::<TcpState>();
+    let gc = tcp.gc_stats();
+    render(&servers, tcp.counts().await, &gc)
+}
+
+/// Answer one scrape on `stream`: the metrics for `GET /metrics`, 404 for
+/// anything else.
+async fn answer(app: &tauri::AppHandle, mut stream: tokio::net::TcpStream) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST_BYTES {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        head.extend_from_slice(&chunk[..n]);
+    }
+    let line = head.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = line.split(|b| *b == b' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split(|b| *b == b'?').next().unwrap_or_default();
+    let (status, body) = if matches!(method, b"GET" | b"HEAD") && path == METRICS_PATH.as_bytes() {
+        ("200 OK", snapshot(app).await)
+    } else {
+        ("404 Not Found", "404 Not Found\n".to_string())
+    };
+    let mut out = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    );
+    if method != b"HEAD" {
+        out.push_str(&body);
+    }
+    stream.write_all(out.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// The running metrics listener and its port, if any.
+#[derive(Default)]
+pub struct MetricsServer(Mutex<Option<(u16, tauri::async_runtime::JoinHandle<()>)>>);
+
+/// Start, move or stop the metrics listener to match the `metrics_port`
+/// setting. A port that can't be bound is logged and left off.
+pub fn configure(app: &tauri::AppHandle, port: Option<u16>) {
+    let state = app.state::<MetricsServer>();
+    let mut current = state.0.lock().unwrap();
+    if current.as_ref().map(|(p, _)| *p) == port {
+        return;
+    }
+    if let Some((_, task)) = current.take() {
+        task.abort();
+    }
+    let Some(port) = port else {
+        return;
+    };
+    let app = app.clone();
+    let task = tauri::async_runtime::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::warn!("metrics: bind 127.0.0.1:{port} failed: {e}");
+                return;
+            }
+        };
+        tracing::info!("metrics: serving http://127.0.0.1:{port}{METRICS_PATH}");
+        while let Ok((stream, _)) = listener.accept().await {
+            let app = app.clone();
+            tokio::spawn(async move {
+                if let Err(e) = answer(&app, stream).await {
+                    tracing::debug!("metrics: {e}");
+                }
+            });
+        }
+    });
+    *current = Some((port, task));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = ServerStats::default();
+        let entry = |status: u16, bytes: u64| {
+            let mut entry = RequestLog::new("127.0.0.1:5000".parse().unwrap(), "1".to_string());
+            entry.status = status;
+            entry.bytes = bytes;
+            entry
+        };
+        stats.record(&entry(200, 100));
+        stats.record(&entry(304, 0));
+        stats.record(&entry(404, 14));
+        stats.record(&entry(0, 0));
+        let open = stats.connection();
+        drop(stats.connection());
+        assert_eq!((open.id(), stats.connection().id()), (1, 3));
+
+        let servers = [ServerMetrics {
+            id: 3,
+            port: 8080,
+            root: "C:\\Users\\me\\\"site\"".to_string(),
+            stats: stats.clone(),
+        }];
+        let text = render(&servers, (1, 2), &GcStats::default());
+        for line in [
+            "# TYPE ok200_http_requests_total counter",
+            r#"ok200_http_server_info{server="3",port="8080",root="C:\\Users\\me\\\"site\""} 1"#,
+            r#"ok200_http_requests_total{server="3",code="2xx"} 1"#,
+            r#"ok200_http_requests_total{server="3",code="3xx"} 1"#,
+            r#"ok200_http_requests_total{server="3",code="5xx"} 0"#,
+            r#"ok200_http_requests_total{server="3",code="none"} 1"#,
+            r#"ok200_http_response_bytes_total{server="3"} 114"#,
+            r#"ok200_http_connections_total{server="3"} 3"#,
+            r#"ok200_http_active_connections{server="3"} 1"#,
+            "ok200_tcp_servers 1",
+            "ok200_tcp_sockets 2",
+            "ok200_tcp_sockets_reaped_total 0",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line}:\n{text}");
+        }
+        drop(open);
+        assert_eq!(stats.0.active.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,143 @@
+//! More folders on one `http_server` port: each mounted under a path
+//! prefix, for one host name, or both. Requests that match no mount get
+//! the server's own root.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+fn default_prefix() -> String {
+    "/".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Mount {
+    /// Host name without the port, e.g. `docs.local`; any host when absent.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// URL path prefix such as `/docs`; matches `/docs` and `/docs/...`.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Folder served there.
+    pub root: String,
+}
+
+/// A `Mount` ready to serve from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mounted {
+    pub mount: Mount,
+    /// `mount.root`, canonicalized.
+    pub root: PathBuf,
+}
+
+impl Mounted {
+    /// Check `mount` and tidy its host and prefix for matching.
+    pub fn new(mut mount: Mount) -> Result<Self, String> {
+        if !mount.prefix.starts_with('/') {
+            return Err(format!(
+                "mount prefix must start with /: {:?}",
+                mount.prefix
+            ));
+        }
+        let trimmed = mount.prefix.trim_end_matches('/');
+        mount.prefix = if trimmed.is_empty() {
+            "/".to_string()
+        } else {
+            trimmed.to_string()
+        };
+        mount.host = mount
+            .host
+            .map(|h| h.trim_end_matches('.').to_ascii_lowercase());
+        let root = std::fs::canonicalize(&mount.root)
+            .ok()
+            .filter(|r| r.is_dir())
+            .ok_or_else(|| format!("not a folder: {}", mount.root))?;
+        Ok(Self { mount, root })
+    }
+
+    /// Whether this is the mount at `prefix` for `host`.
+    pub fn same_place(&self, host: Option<&str>, prefix: &str) -> bool {
+        let host = host.map(|h| h.trim_end_matches('.').to_ascii_lowercase());
+        self.mount.host == host && self.base() == prefix.trim_end_matches('/')
+    }
+
+    /// The path prefix to put back in front of URLs that were matched,
+    /// empty for `/`.
+    pub fn base(&self) -> &str {
+        self.mount.prefix.trim_end_matches('/')
+    }
+}
+
+/// Host name from a `Host` header, without the port.
+fn host_name(header: &str) -> String {
+    let name = match header.strip_prefix('[') {
+        // IPv6 literal
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => header.split(':').next().unwrap_or_default(),
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The mount for a request to `host` (a `Host` header) and `target`, and
+/// what's left of the target after its prefix: empty, or starting with
+/// `/`, `?` or `#`. Mounts for the host win over ones for any host, then
+/// the longest prefix does.
+pub fn matching<'a, 't>(
+    mounts: &'a [Mounted],
+    host: Option<&str>,
+    target: &'t str,
+) -> Option<(&'a Mounted, &'t str)> {
+    let host = host.map(host_name);
+    mounts
+        .iter()
+        .filter(|m| m.mount.host.is_none() || m.mount.host == host)
+        .filter_map(|m| {
+            let rest = target.strip_prefix(m.base())?;
+            (rest.is_empty() || rest.starts_with(['/', '?', '#'])).then_some((m, rest))
+        })
+        .max_by_key(|(m, _)| (m.mount.host.is_some(), m.base().len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mounted(host: Option<&str>, prefix: &str) -> Mounted {
+        Mounted::new(Mount {
+            host: host.map(str::to_string),
+            prefix: prefix.to_string(),
+            root: std::env::temp_dir().to_string_lossy().into_owned(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_matching() {
+        let mounts = [
+            mounted(None, "/docs/"),
+            mounted(None, "/docs/api"),
+            mounted(Some("Media.Local"), "/"),
+        ];
+        let find = |host, target| {
+            matching(&mounts, host, target).map(|(m, rest)| (m.mount.prefix.as_str(), rest))
+        };
+        assert_eq!(find(None, "/docs/a.md"), Some(("/docs", "/a.md")));
+        assert_eq!(find(None, "/docs?x=1"), Some(("/docs", "?x=1")));
+        assert_eq!(find(None, "/docs/api/v1"), Some(("/docs/api", "/v1")));
+        assert_eq!(find(None, "/docsx"), None);
+        assert_eq!(
+            find(Some("media.local:8080"), "/docs/a"),
+            Some(("/", "/docs/a"))
+        );
+        assert_eq!(find(Some("other:8080"), "/a"), None);
+        assert_eq!(host_name("[::1]:8080"), "::1");
+        assert!(mounts[2].same_place(Some("media.local"), "/"));
+        assert!(Mounted::new(Mount {
+            host: None,
+            prefix: "docs".to_string(),
+            root: "/".to_string(),
+        })
+        .is_err());
+    }
+}
@@ -1,7 +1,149 @@
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const MANIFEST_NAME: &str = "app.ok200.native";
-const MANIFEST_FILENAME: &str = "app.ok200.native.json";
+use ok200_common::browsers::{self, BrowserProbe, MANIFEST_FILENAME, MANIFEST_NAME};
+use serde::Serialize;
+
+use super::notifications;
+
+const REGISTERED_BROWSERS_FILENAME: &str = "registered-browsers.json";
+/// How often to look for browsers installed after the app was.
+const BROWSER_CHECK_INTERVAL: Duration = Duration::from_hours(24);
+
+/// Registration state of the native messaging host for a single browser.
+#[derive(Serialize)]
+pub struct BrowserHostStatus {
+    #[serde(flatten)]
+    probe: BrowserProbe,
+    /// The registered path exists and is executable.
+    path_valid: bool,
+    /// The registered path matches the sidecar this app would register.
+    path_current: bool,
+}
+
+impl BrowserHostStatus {
+    fn is_stale(&self) -> bool {
+        self.probe.config_dir_exists && !(self.path_valid && self.path_current)
+    }
+}
+
+#[derive(Serialize)]
+pub struct NativeHostStatus {
+    expected_path: String,
+    browsers: Vec<BrowserHostStatus>,
+    repaired: bool,
+}
+
+/// Check the native messaging host registration for every known browser,
+/// optionally re-registering when any installed browser has a stale entry.
+#[tauri::command]
+pub async fn native_host_status(
+    app: tauri::AppHandle,
+    repair: Option<bool>,
+) -> Result<NativeHostStatus, String> {
+    check_native_messaging_hosts(&app, repair.unwrap_or(false))
+}
+
+/// List every probed browser config path and what manifest (if any) it holds.
+#[tauri::command]
+pub async fn native_host_browsers() -> Vec<BrowserProbe> {
+    browsers::probe_browsers()
+}
+
+pub fn check_native_messaging_hosts(
+    app: &tauri::AppHandle,
+    repair: bool,
+) -> Result<NativeHostStatus, String> {
+    let expected = expected_host_path(app)?;
+    let mut statuses = probe_host_statuses(&expected);
+
+    #[cfg(target_os = "linux")]
+    let outdated = appimage_copy_outdated(app);
+    #[cfg(not(target_os = "linux"))]
+    let outdated = false;
+
+    let mut repaired = false;
+    if repair && (outdated || statuses.iter().any(BrowserHostStatus::is_stale)) {
+        let count = register_native_messaging_hosts(app)?;
+        tracing::info!("repaired registration for {count} browser(s)");
+        statuses = probe_host_statuses(&expected);
+        repaired = true;
+    }
+
+    Ok(NativeHostStatus {
+        expected_path: expected.to_string_lossy().to_string(),
+        browsers: statuses,
+        repaired,
+    })
+}
+
+/// The host path that `register_native_messaging_hosts` writes into manifests.
+fn expected_host_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    #[cfg(target_os = "linux")]
+    if std::env::var_os("APPDIR").is_some() {
+        return appimage_stable_host_path();
+    }
+    super::resolve_sidecar(app, "binaries/ok200-host")
+}
+
+fn is_executable(path: &Path) -> bool {
+    let Ok(meta) = std::fs::metadata(path) else {
+        return false;
+    };
+    if !meta.is_file() {
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        true
+    }
+}
+
+/// Whether `copy` is missing or differs from `original`. Compares contents,
+/// since copying doesn't keep the original's timestamps.
+fn is_outdated_copy(copy: &Path, original: &Path) -> bool {
+    match (std::fs::read(copy), std::fs::read(original)) {
+        (Ok(copy), Ok(original)) => copy != original,
+        (Err(_), Ok(_)) => true,
+        (_, Err(_)) => false,
+    }
+}
+
+/// Whether this `AppImage` carries a different host than the stable copy
+/// the manifests point to, e.g. after an update.
+#[cfg(target_os = "linux")]
+fn appimage_copy_outdated(app: &tauri::AppHandle) -> bool {
+    if std::env::var_os("APPDIR").is_none() {
+        return false;
+    }
+    match (
+        appimage_stable_host_path(),
+        super::resolve_sidecar(app, "binaries/ok200-host"),
+    ) {
+        (Ok(stable), Ok(bundled)) => is_outdated_copy(&stable, &bundled),
+        _ => false,
+    }
+}
+
+fn probe_host_statuses(expected: &Path) -> Vec<BrowserHostStatus> {
+    browsers::probe_browsers()
+        .into_iter()
+        .map(|probe| {
+            let registered = probe.host_path.as_deref().map(Path::new);
+            BrowserHostStatus {
+                path_valid: registered.is_some_and(is_executable),
+                path_current: registered.is_some_and(|p| p == expected),
+                probe,
+            }
+        })
+        .collect()
+}
 
 /// Register native messaging host manifest for all detected Chromium browsers.
 /// Returns the number of browsers successfully registered.
@@ -14,14 +156,11 @@ pub fn register_native_messaging_hosts(app: &tauri::AppHandle) -> Result<usize,
     let host_path = if std::env::var_os("APPDIR").is_some() {
         match copy_sidecar_for_appimage(&host_path) {
             Ok(stable_path) => {
-                eprintln!(
-                    "native-host: copied sidecar to stable path: {}",
-                    stable_path.display()
-                );
+                tracing::info!("copied sidecar to stable path: {}", stable_path.display());
                 stable_path
             }
             Err(e) => {
-                eprintln!("native-host: failed to copy sidecar for AppImage: {e}");
+                tracing::error!("failed to copy sidecar for AppImage: {e}");
                 host_path
             }
         }
@@ -61,6 +200,83 @@ pub fn register_native_messaging_hosts(app: &tauri::AppHandle) -> Result<usize,
     Ok(count)
 }
 
+/// Installed browsers in `probes` that aren't in `known`.
+fn newly_installed(probes: &[BrowserProbe], known: &BTreeSet<String>) -> Vec<String> {
+    probes
+        .iter()
+        .filter(|p| p.config_dir_exists && !known.contains(&p.browser))
+        .map(|p| p.browser.clone())
+        .collect()
+}
+
+fn registered_browsers_path(app: &tauri::AppHandle) -> Option<PathBuf> {
+    Some(
+        super::paths::app_data_dir(app)
+            .ok()?
+            .join(REGISTERED_BROWSERS_FILENAME),
+    )
+}
+
+/// Register with browsers that have appeared since the last check, and
+/// announce them. The first check of a run also repairs stale entries.
+fn check_for_new_browsers(app: &tauri::AppHandle, first_check: bool) {
+    let path = registered_browsers_path(app);
+    let known: Option<BTreeSet<String>> = path
+        .as_ref()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok());
+    let probes = browsers::probe_browsers();
+    let new = newly_installed(&probes, known.as_ref().unwrap_or(&BTreeSet::new()));
+    if !new.is_empty() {
+        match register_native_messaging_hosts(app) {
+            Ok(count) => tracing::info!("native-host: registered with {count} browser(s)"),
+            Err(e) => {
+                tracing::error!("native-host: registration failed: {e}");
+                return;
+            }
+        }
+        // Nothing to announce on the very first launch
+        if known.is_some() {
+            for browser in &new {
+                notifications::show("Browser integration", &format!("Registered with {browser}"));
+            }
+        }
+    } else if first_check {
+        // Picks up a moved app or an outdated AppImage copy of the host
+        if let Err(e) = check_native_messaging_hosts(app, true) {
+            tracing::error!("native-host: repair failed: {e}");
+        }
+    } else {
+        return;
+    }
+    let installed: BTreeSet<&str> = probes
+        .iter()
+        .filter(|p| p.config_dir_exists)
+        .map(|p| p.browser.as_str())
+        .collect();
+    if let (Some(path), Ok(json)) = (path, serde_json::to_string_pretty(&installed)) {
+        std::fs::write(path, json).ok();
+    }
+}
+
+/// Register now, then check daily for browsers installed since.
+pub fn watch_for_new_browsers(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut first_check = true;
+        loop {
+            let handle = app.clone();
+            let checked = tauri::async_runtime::spawn_blocking(move || {
+                check_for_new_browsers(&handle, first_check);
+            });
+            if let Err(e) = checked.await {
+                tracing::error!("native-host: browser check failed: {e}");
+            }
+            first_check = false;
+            tokio::time::sleep(BROWSER_CHECK_INTERVAL).await;
+        }
+    });
+}
+
 /// Write manifest to a browser's `NativeMessagingHosts` directory.
 /// Only writes if the browser's parent config directory already exists
 /// (i.e., the browser is installed).
@@ -70,20 +286,17 @@ fn write_manifest_for_browser(browser_config_dir: &Path, manifest_bytes: &[u8])
     }
     let hosts_dir = browser_config_dir.join("NativeMessagingHosts");
     if std::fs::create_dir_all(&hosts_dir).is_err() {
-        eprintln!("native-host: failed to create {}", hosts_dir.display());
+        tracing::error!("failed to create {}", hosts_dir.display());
         return false;
     }
     let manifest_path = hosts_dir.join(MANIFEST_FILENAME);
     match std::fs::write(&manifest_path, manifest_bytes) {
         Ok(()) => {
-            eprintln!("native-host: registered {}", manifest_path.display());
+            tracing::info!("registered {}", manifest_path.display());
             true
         }
         Err(e) => {
-            eprintln!(
-                "native-host: failed to write {}: {e}",
-                manifest_path.display()
-            );
+            tracing::error!("failed to write {}: {e}", manifest_path.display());
             false
         }
     }
@@ -91,41 +304,19 @@ fn write_manifest_for_browser(browser_config_dir: &Path, manifest_bytes: &[u8])
 
 #[cfg(target_os = "macos")]
 fn register_macos_browsers(manifest_bytes: &[u8]) -> usize {
-    let Some(home) = dirs::home_dir() else {
-        eprintln!("native-host: could not determine home directory");
-        return 0;
-    };
-    let app_support = home.join("Library/Application Support");
-    let browsers = [
-        "Google/Chrome",
-        "Google/Chrome Canary",
-        "Chromium",
-        "BraveSoftware/Brave-Browser",
-        "Microsoft Edge",
-        "Vivaldi",
-        "Arc/User Data",
-    ];
-    browsers
+    browsers::known_browsers()
         .iter()
-        .filter(|b| write_manifest_for_browser(&app_support.join(b), manifest_bytes))
+        .filter_map(browsers::Browser::config_path)
+        .filter(|dir| write_manifest_for_browser(dir, manifest_bytes))
         .count()
 }
 
 #[cfg(target_os = "linux")]
 fn register_linux_browsers(manifest_bytes: &[u8]) -> usize {
-    let Some(home) = dirs::home_dir() else {
-        eprintln!("native-host: could not determine home directory");
-        return 0;
-    };
-    let browsers = [
-        ".config/google-chrome",
-        ".config/chromium",
-        ".config/BraveSoftware/Brave-Browser",
-        ".config/microsoft-edge",
-    ];
-    browsers
+    browsers::known_browsers()
         .iter()
-        .filter(|b| write_manifest_for_browser(&home.join(b), manifest_bytes))
+        .filter_map(browsers::Browser::config_path)
+        .filter(|dir| write_manifest_for_browser(dir, manifest_bytes))
         .count()
 }
 
@@ -134,36 +325,32 @@ fn register_windows_browsers(
     app: &tauri::AppHandle,
     manifest_bytes: &[u8],
 ) -> Result<usize, String> {
-    use tauri::Manager;
     use winreg::enums::*;
     use winreg::RegKey;
 
-    let app_data =
-        super::strip_win_prefix(app.path().app_local_data_dir().map_err(|e| e.to_string())?);
+    let app_data = super::strip_win_prefix(super::paths::app_local_data_dir(app)?);
     std::fs::create_dir_all(&app_data).map_err(|e| e.to_string())?;
     let manifest_path = app_data.join(MANIFEST_FILENAME);
     std::fs::write(&manifest_path, manifest_bytes).map_err(|e| e.to_string())?;
     let manifest_path_str = manifest_path.to_string_lossy().to_string();
 
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let registry_keys = [
-        format!("Software\\Google\\Chrome\\NativeMessagingHosts\\{MANIFEST_NAME}"),
-        format!("Software\\Chromium\\NativeMessagingHosts\\{MANIFEST_NAME}"),
-        format!("Software\\BraveSoftware\\Brave-Browser\\NativeMessagingHosts\\{MANIFEST_NAME}"),
-        format!("Software\\Microsoft\\Edge\\NativeMessagingHosts\\{MANIFEST_NAME}"),
-    ];
+    let registry_keys: Vec<String> = browsers::known_browsers()
+        .iter()
+        .filter_map(browsers::Browser::registry_host_key)
+        .collect();
 
     let mut count = 0;
     for subkey in &registry_keys {
         match hkcu.create_subkey(subkey) {
             Ok((key, _)) => match key.set_value("", &manifest_path_str) {
                 Ok(()) => {
-                    eprintln!("native-host: registered HKCU\\{subkey}");
+                    tracing::info!("registered HKCU\\{subkey}");
                     count += 1;
                 }
-                Err(e) => eprintln!("native-host: failed to set HKCU\\{subkey}: {e}"),
+                Err(e) => tracing::error!("failed to set HKCU\\{subkey}: {e}"),
             },
-            Err(e) => eprintln!("native-host: failed to create HKCU\\{subkey}: {e}"),
+            Err(e) => tracing::error!("failed to create HKCU\\{subkey}: {e}"),
         }
     }
 
@@ -173,11 +360,14 @@ fn register_windows_browsers(
 /// Copy the sidecar binary from the AppImage FUSE mount to `~/.local/lib/ok200/`.
 #[cfg(target_os = "linux")]
 fn copy_sidecar_for_appimage(fuse_path: &std::path::Path) -> Result<std::path::PathBuf, String> {
-    let home = dirs::home_dir().ok_or("could not determine home directory")?;
-    let lib_dir = home.join(".local/lib/ok200");
-    std::fs::create_dir_all(&lib_dir).map_err(|e| format!("mkdir {}: {e}", lib_dir.display()))?;
+    let dest = appimage_stable_host_path()?;
+    let lib_dir = dest.parent().ok_or("invalid stable host path")?;
+    std::fs::create_dir_all(lib_dir).map_err(|e| format!("mkdir {}: {e}", lib_dir.display()))?;
+    // Overwriting a host a browser is running fails, so leave a current one
+    if !is_outdated_copy(&dest, fuse_path) {
+        return Ok(dest);
+    }
 
-    let dest = lib_dir.join("ok200-host");
     std::fs::copy(fuse_path, &dest)
         .map_err(|e| format!("copy {} -> {}: {e}", fuse_path.display(), dest.display()))?;
 
@@ -188,3 +378,52 @@ fn copy_sidecar_for_appimage(fuse_path: &std::path::Path) -> Result<std::path::P
 
     Ok(dest)
 }
+
+/// Stable location of the host binary for `AppImage` installs.
+#[cfg(target_os = "linux")]
+fn appimage_stable_host_path() -> Result<PathBuf, String> {
+    let home = dirs::home_dir().ok_or("could not determine home directory")?;
+    Ok(home.join(".local/lib/ok200/ok200-host"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(browser: &str, installed: bool) -> BrowserProbe {
+        BrowserProbe {
+            browser: browser.to_string(),
+            config_dir: None,
+            config_dir_exists: installed,
+            manifest_path: None,
+            manifest_present: false,
+            host_path: None,
+        }
+    }
+
+    #[test]
+    fn test_newly_installed() {
+        let probes = [
+            probe("Chrome", true),
+            probe("Brave", true),
+            probe("Edge", false),
+        ];
+        let known = BTreeSet::from(["Chrome".to_string()]);
+        assert_eq!(newly_installed(&probes, &known), ["Brave"]);
+        assert!(newly_installed(&probes[..1], &known).is_empty());
+    }
+
+    #[test]
+    fn test_is_outdated_copy() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (copy, original) = (tmp.path().join("copy"), tmp.path().join("original"));
+        std::fs::write(&original, "v2").unwrap();
+        assert!(is_outdated_copy(&copy, &original));
+        std::fs::write(&copy, "v1").unwrap();
+        assert!(is_outdated_copy(&copy, &original));
+        std::fs::copy(&original, &copy).unwrap();
+        assert!(!is_outdated_copy(&copy, &original));
+        // Nothing to copy from
+        assert!(!is_outdated_copy(&copy, &tmp.path().join("missing")));
+    }
+}
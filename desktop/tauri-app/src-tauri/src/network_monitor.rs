@@ -0,0 +1,101 @@
+//! Notice wake-from-sleep and network changes, re-bind servers that didn't
+//! survive them, and tell the webview the new LAN address so displayed URLs
+//! stay correct. Both are detected by polling: a wall-clock jump much larger
+//! than the poll interval means the machine was asleep, and a different
+//! route-probe address (see `ok200_common::net::lan_ip`) means the network
+//! changed.
+
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+
+use super::tcp::TcpState;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Extra wall-clock time between polls that counts as having slept.
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Reason {
+    Wake,
+    Network,
+}
+
+#[derive(Serialize, Clone)]
+struct NetworkChanged {
+    reason: Reason,
+    lan_ip: Option<String>,
+    rebound: usize,
+}
+
+/// What changed between two polls, if anything. Waking up wins, since the
+/// network usually changes along with it.
+fn detect(elapsed: Duration, last_ip: Option<IpAddr>, ip: Option<IpAddr>) -> Option<Reason> {
+    if elapsed > POLL_INTERVAL + SLEEP_THRESHOLD {
+        Some(Reason::Wake)
+    } else if ip != last_ip {
+        Some(Reason::Network)
+    } else {
+        None
+    }
+}
+
+/// Poll in the background for the lifetime of the app.
+pub fn spawn(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last_ip = ok200_common::net::lan_ip();
+        let mut last_tick = SystemTime::now();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let now = SystemTime::now();
+            // The wall clock keeps running while asleep; the timer doesn't
+            let elapsed = now.duration_since(last_tick).unwrap_or_default();
+            last_tick = now;
+            let ip = ok200_common::net::lan_ip();
+            let Some(reason) = detect(elapsed, last_ip, ip) else {
+                continue;
+            };
+            last_ip = ip;
+
+            let rebound = app.state::<TcpState>().revalidate().await;
+            tracing::info!(
+                "{reason:?}: LAN address {}, re-bound {rebound} server(s)",
+                ip.map_or_else(|| "none".to_string(), |ip| ip.to_string())
+            );
+            super::rebuild_tray_menu(&app);
+            let _ = app.emit(
+                "network-changed",
+                NetworkChanged {
+                    reason,
+                    lan_ip: ip.map(|ip| ip.to_string()),
+                    rebound,
+                },
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let home: IpAddr = "192.168.1.20".parse().unwrap();
+        let office: IpAddr = "10.0.0.7".parse().unwrap();
+        let tick = POLL_INTERVAL;
+        let nap = POLL_INTERVAL + SLEEP_THRESHOLD + Duration::from_secs(1);
+
+        assert_eq!(detect(tick, Some(home), Some(home)), None);
+        assert_eq!(
+            detect(tick, Some(home), Some(office)),
+            Some(Reason::Network)
+        );
+        assert_eq!(detect(tick, Some(home), None), Some(Reason::Network));
+        assert_eq!(detect(nap, Some(home), Some(home)), Some(Reason::Wake));
+        assert_eq!(detect(nap, Some(home), Some(office)), Some(Reason::Wake));
+    }
+}
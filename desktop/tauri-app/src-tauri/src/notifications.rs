@@ -0,0 +1,187 @@
+//! OS notifications for server lifecycle, new remote clients, and
+//! background updates. Uses each platform's stock notifier so no extra
+//! plugin is needed.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use tauri::Manager;
+
+use super::tcp::TcpState;
+use super::Settings;
+
+const KNOWN_CLIENTS_FILENAME: &str = "known-clients.json";
+
+/// What a notification is about, so each kind can be turned off separately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    ServerLifecycle,
+    NewClient,
+    UpdateInstalled,
+}
+
+pub fn enabled(settings: &Settings, kind: Kind) -> bool {
+    match kind {
+        Kind::ServerLifecycle => settings.notify_server_lifecycle,
+        Kind::NewClient => settings.notify_new_clients,
+        Kind::UpdateInstalled => settings.notify_updates,
+    }
+}
+
+/// Show a notification if the user hasn't turned this kind off.
+pub fn notify(app: &tauri::AppHandle, kind: Kind, title: &str, body: &str) {
+    let settings = app.state::<Mutex<Settings>>().lock().unwrap().clone();
+    if enabled(&settings, kind) {
+        show(title, body);
+    }
+}
+
+/// Show a notification without consulting settings.
+pub fn show(title: &str, body: &str) {
+    if let Err(e) = notifier_command(title, body).and_then(|mut cmd| {
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }) {
+        tracing::warn!("failed to show {title:?}: {e}");
+    }
+}
+
+// Fallible only on platforms without a notifier
+#[cfg(target_os = "macos")]
+#[allow(clippy::unnecessary_wraps)]
+fn notifier_command(title: &str, body: &str) -> Result<Command, String> {
+    // Pass text through argv so it never needs AppleScript escaping
+    let mut cmd = Command::new("osascript");
+    cmd.args([
+        "-e",
+        "on run argv",
+        "-e",
+        "display notification (item 2 of argv) with title (item 1 of argv)",
+        "-e",
+        "end run",
+        title,
+        body,
+    ]);
+    Ok(cmd)
+}
+
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_wraps)]
+fn notifier_command(title: &str, body: &str) -> Result<Command, String> {
+    let mut cmd = Command::new("notify-send");
+    cmd.args(["--app-name=200 OK", title, body]);
+    Ok(cmd)
+}
+
+#[cfg(target_os = "windows")]
+#[allow(clippy::unnecessary_wraps)]
+fn notifier_command(title: &str, body: &str) -> Result<Command, String> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    // Text goes through the environment so it never needs PowerShell escaping
+    const SCRIPT: &str = "\
+        $m = [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime];\
+        $t = $m::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02);\
+        $x = $t.GetElementsByTagName('text');\
+        [void]$x.Item(0).AppendChild($t.CreateTextNode($env:OK200_TITLE));\
+        [void]$x.Item(1).AppendChild($t.CreateTextNode($env:OK200_BODY));\
+        $m::CreateToastNotifier('200 OK').Show([Windows.UI.Notifications.ToastNotification]::new($t))";
+    let mut cmd = Command::new("powershell.exe");
+    cmd.args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+        .env("OK200_TITLE", title)
+        .env("OK200_BODY", body)
+        .creation_flags(CREATE_NO_WINDOW);
+    Ok(cmd)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+fn notifier_command(_title: &str, _body: &str) -> Result<Command, String> {
+    Err("notifications are not supported on this platform".to_string())
+}
+
+// -- New remote clients --
+
+fn known_clients_path(app: &tauri::AppHandle) -> Option<PathBuf> {
+    Some(super::paths::app_data_dir(app).ok()?.join(KNOWN_CLIENTS_FILENAME))
+}
+
+fn load_known_clients(app: &tauri::AppHandle) -> HashSet<IpAddr> {
+    known_clients_path(app)
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_known_clients(app: &tauri::AppHandle, clients: &HashSet<IpAddr>) {
+    if let Some(path) = known_clients_path(app) {
+        if let Ok(json) = serde_json::to_string_pretty(clients) {
+            std::fs::write(path, json).ok();
+        }
+    }
+}
+
+/// Whether a connection from `ip` comes from another device.
+fn is_remote(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 @ IpAddr::V4(_) => v4,
+    };
+    !ip.is_loopback() && !ip.is_unspecified()
+}
+
+/// Notify the first time each remote device connects to any server.
+pub fn watch_new_clients(app: tauri::AppHandle) {
+    let mut accepts = app.state::<TcpState>().subscribe_accepts();
+    tauri::async_runtime::spawn(async move {
+        let mut known = load_known_clients(&app);
+        loop {
+            let ip = match accepts.recv().await {
+                Ok(ip) => ip,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            if !is_remote(ip) || !known.insert(ip) {
+                continue;
+            }
+            save_known_clients(&app, &known);
+            notify(
+                &app,
+                Kind::NewClient,
+                "New device connected",
+                &format!("{ip} connected to 200 OK for the first time"),
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_remote() {
+        assert!(!is_remote("127.0.0.1".parse().unwrap()));
+        assert!(!is_remote("::1".parse().unwrap()));
+        assert!(!is_remote("::ffff:127.0.0.1".parse().unwrap()));
+        assert!(is_remote("192.168.1.20".parse().unwrap()));
+        assert!(is_remote("::ffff:192.168.1.20".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_enabled_respects_settings() {
+        let mut s = Settings::default();
+        assert!(enabled(&s, Kind::ServerLifecycle));
+        assert!(enabled(&s, Kind::NewClient));
+        assert!(enabled(&s, Kind::UpdateInstalled));
+        s.notify_new_clients = false;
+        assert!(!enabled(&s, Kind::NewClient));
+        assert!(enabled(&s, Kind::ServerLifecycle));
+    }
+}
@@ -0,0 +1,213 @@
+//! Install an updater bundle from a local file, for machines that can't
+//! reach the update endpoint. The bundle must carry the same minisign
+//! signature the online updater checks (`<bundle>.sig` next to it).
+
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use minisign_verify::{PublicKey, Signature};
+
+/// The updater public key from `plugins.updater.pubkey` in `tauri.conf.json`.
+pub fn updater_pubkey(config: &tauri::Config) -> Result<String, String> {
+    config
+        .plugins
+        .0
+        .get("updater")
+        .and_then(|u| u.get("pubkey"))
+        .and_then(|k| k.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| "no updater pubkey configured".to_string())
+}
+
+fn base64_to_string(encoded: &str) -> Result<String, String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|e| format!("invalid base64: {e}"))?;
+    String::from_utf8(bytes).map_err(|e| format!("invalid utf-8: {e}"))
+}
+
+/// Verify `data` against a base64-encoded minisign signature, the same
+/// encoding the updater uses for `pubkey` and `.sig` files.
+pub fn verify_signature(data: &[u8], signature_b64: &str, pubkey_b64: &str) -> Result<(), String> {
+    let public_key = PublicKey::decode(&base64_to_string(pubkey_b64)?)
+        .map_err(|e| format!("invalid public key: {e}"))?;
+    let signature = Signature::decode(&base64_to_string(signature_b64)?)
+        .map_err(|e| format!("invalid signature: {e}"))?;
+    public_key
+        .verify(data, &signature, true)
+        .map_err(|e| format!("signature verification failed: {e}"))
+}
+
+pub(crate) fn signature_path_for(bundle: &Path) -> PathBuf {
+    let mut name = bundle.as_os_str().to_os_string();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+/// Verify and install `bundle`. On Windows this hands off to the installer
+/// and exits the process; elsewhere it returns once the app has been replaced.
+pub fn install_from_file(bundle: &Path, pubkey: &str) -> Result<(), String> {
+    let bytes = std::fs::read(bundle).map_err(|e| format!("read {}: {e}", bundle.display()))?;
+    let sig_path = signature_path_for(bundle);
+    let signature = std::fs::read_to_string(&sig_path)
+        .map_err(|e| format!("read {}: {e}", sig_path.display()))?;
+    verify_signature(&bytes, &signature, pubkey)?;
+    tracing::info!("signature verified for {}", bundle.display());
+    install_bundle(bundle, &bytes)
+}
+
+/// Install a bundle we produced ourselves, such as a rollback snapshot,
+/// without a signature check.
+pub(crate) fn install_local(bundle: &Path) -> Result<(), String> {
+    let bytes = std::fs::read(bundle).map_err(|e| format!("read {}: {e}", bundle.display()))?;
+    install_bundle(bundle, &bytes)
+}
+
+/// Replace the running `AppImage` with the bundle, restoring it on failure.
+#[cfg(target_os = "linux")]
+fn install_bundle(bundle: &Path, bytes: &[u8]) -> Result<(), String> {
+    if bundle.extension().and_then(|e| e.to_str()) != Some("AppImage") {
+        return Err("only AppImage bundles can be installed offline on Linux".to_string());
+    }
+    let current = std::env::var_os("APPIMAGE")
+        .map(PathBuf::from)
+        .ok_or("not running from an AppImage")?;
+    let permissions = std::fs::metadata(&current)
+        .map_err(|e| format!("stat {}: {e}", current.display()))?
+        .permissions();
+
+    let backup = current.with_extension("AppImage.bak");
+    std::fs::rename(&current, &backup).map_err(|e| format!("backup failed: {e}"))?;
+    let written = std::fs::write(&current, bytes)
+        .and_then(|()| std::fs::set_permissions(&current, permissions));
+    if let Err(e) = written {
+        std::fs::rename(&backup, &current).ok();
+        return Err(format!("write {}: {e}", current.display()));
+    }
+    std::fs::remove_file(&backup).ok();
+    Ok(())
+}
+
+/// The `.app` directory the running binary lives in.
+#[cfg(target_os = "macos")]
+pub(crate) fn running_app_bundle() -> Result<PathBuf, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("cannot find own exe: {e}"))?;
+    // <Name>.app/Contents/MacOS/<binary>
+    exe.ancestors()
+        .nth(3)
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("app"))
+        .map(Path::to_path_buf)
+        .ok_or_else(|| "not running from an .app bundle".to_string())
+}
+
+/// Unpack the `.app.tar.gz` next to the running bundle and swap it in.
+#[cfg(target_os = "macos")]
+fn install_bundle(bundle: &Path, _bytes: &[u8]) -> Result<(), String> {
+    let app_dir = running_app_bundle()?;
+    let parent = app_dir.parent().ok_or("app bundle has no parent")?;
+
+    let extract_dir = parent.join(".ok200-update");
+    std::fs::remove_dir_all(&extract_dir).ok();
+    std::fs::create_dir_all(&extract_dir).map_err(|e| format!("mkdir: {e}"))?;
+    let status = std::process::Command::new("tar")
+        .arg("-xzf")
+        .arg(bundle)
+        .arg("-C")
+        .arg(&extract_dir)
+        .status()
+        .map_err(|e| format!("tar failed: {e}"))?;
+    let new_app = std::fs::read_dir(&extract_dir).ok().and_then(|entries| {
+        entries
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .find(|p| p.extension().and_then(|e| e.to_str()) == Some("app"))
+    });
+    let Some(new_app) = new_app.filter(|_| status.success()) else {
+        std::fs::remove_dir_all(&extract_dir).ok();
+        return Err("bundle does not contain an .app".to_string());
+    };
+
+    let backup = extract_dir.join("previous.app");
+    std::fs::rename(&app_dir, &backup).map_err(|e| format!("backup failed: {e}"))?;
+    if let Err(e) = std::fs::rename(&new_app, &app_dir) {
+        std::fs::rename(&backup, &app_dir).ok();
+        std::fs::remove_dir_all(&extract_dir).ok();
+        return Err(format!("install failed: {e}"));
+    }
+    std::fs::remove_dir_all(&extract_dir).ok();
+    Ok(())
+}
+
+/// Launch the NSIS or MSI installer in passive mode and exit so it can
+/// replace our files.
+#[cfg(target_os = "windows")]
+fn install_bundle(bundle: &Path, _bytes: &[u8]) -> Result<(), String> {
+    let ext = bundle
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let mut cmd = match ext.as_deref() {
+        Some("exe") => {
+            let mut cmd = std::process::Command::new(bundle);
+            cmd.args(["/P", "/R", "/UPDATE"]);
+            cmd
+        }
+        Some("msi") => {
+            let mut cmd = std::process::Command::new("msiexec.exe");
+            cmd.arg("/i")
+                .arg(bundle)
+                .args(["/passive", "/promptrestart", "AUTOLAUNCHAPP=True"]);
+            cmd
+        }
+        _ => return Err("expected an .exe or .msi installer".to_string()),
+    };
+    cmd.spawn()
+        .map_err(|e| format!("failed to launch installer: {e}"))?;
+    std::process::exit(0);
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+fn install_bundle(_bundle: &Path, _bytes: &[u8]) -> Result<(), String> {
+    Err("offline updates are not supported on this platform".to_string())
+}
+
+/// Entry point for `--install-update <file>`: verify, install, and exit
+/// without building the full app.
+pub fn run(bundle: Option<&str>, context: &tauri::Context) {
+    let Some(bundle) = bundle else {
+        eprintln!("offline-update: usage: --install-update <bundle>");
+        std::process::exit(2);
+    };
+    let result =
+        updater_pubkey(context.config()).and_then(|key| install_from_file(Path::new(bundle), &key));
+    match result {
+        Ok(()) => {
+            eprintln!("offline-update: installed {bundle}");
+            std::process::exit(0);
+        }
+        Err(e) => {
+            eprintln!("offline-update: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_path_for() {
+        assert_eq!(
+            signature_path_for(Path::new("/tmp/200 OK.AppImage")),
+            PathBuf::from("/tmp/200 OK.AppImage.sig")
+        );
+    }
+
+    #[test]
+    fn test_verify_signature_rejects_garbage() {
+        assert!(verify_signature(b"data", "not base64!", "also not").is_err());
+        let b64 = base64::engine::general_purpose::STANDARD.encode("not a key");
+        assert!(verify_signature(b"data", &b64, &b64).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+//! App data locations. In portable mode (see `ok200_common::portable_dir`)
+//! everything lives in `data/` beside the executable instead.
+
+use std::path::PathBuf;
+
+use tauri::Manager;
+
+/// Where settings, recents, rollback snapshots and the like are stored.
+pub fn app_data_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    if let Some(dir) = ok200_common::portable_dir() {
+        return Ok(dir);
+    }
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("no app data directory: {e}"))
+}
+
+/// Where the Windows native-host manifest is written.
+#[cfg(target_os = "windows")]
+pub fn app_local_data_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    if let Some(dir) = ok200_common::portable_dir() {
+        return Ok(dir);
+    }
+    app.path()
+        .app_local_data_dir()
+        .map_err(|e| format!("no local app data directory: {e}"))
+}
@@ -0,0 +1,142 @@
+//! Runs `plugins` in the wasmi interpreter. Modules that import anything
+//! are refused, so plugins only compute. Every call gets its own instance,
+//! so nothing carries over between requests, and is cut off when it runs
+//! out of fuel or memory.
+
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Instructions, roughly, one call may run.
+const FUEL: u64 = 20_000_000;
+const MAX_MEMORY_BYTES: usize = 16 * 1024 * 1024;
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// A plugin, checked and compiled.
+pub struct Compiled {
+    engine: Engine,
+    module: Module,
+}
+
+/// Check `wasm` is a plugin and compile it.
+pub fn compile(wasm: &[u8]) -> Result<Compiled, String> {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, wasm).map_err(|e| format!("invalid module: {e}"))?;
+    if let Some(import) = module.imports().next() {
+        return Err(format!(
+            "imports {}::{}, but plugins can't import anything",
+            import.module(),
+            import.name()
+        ));
+    }
+    for export in ["memory", "alloc", "on_request"] {
+        if module.get_export(export).is_none() {
+            return Err(format!("doesn't export {export}"));
+        }
+    }
+    Ok(Compiled { engine, module })
+}
+
+/// Call `on_request` with `input`. `None` if the plugin passed.
+pub fn call(plugin: &Compiled, input: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(MAX_MEMORY_BYTES)
+        .instances(1)
+        .build();
+    let mut store: Store<StoreLimits> = Store::new(&plugin.engine, limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+    let instance = Linker::new(&plugin.engine)
+        .instantiate(&mut store, &plugin.module)
+        .and_then(|pre| pre.start(&mut store))
+        .map_err(|e| format!("instantiate failed: {e}"))?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or("memory isn't a memory")?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "alloc")
+        .map_err(|e| format!("alloc: {e}"))?;
+    let on_request = instance
+        .get_typed_func::<(i32, i32), i64>(&store, "on_request")
+        .map_err(|e| format!("on_request: {e}"))?;
+
+    let len = i32::try_from(input.len()).map_err(|_| "request too large")?;
+    let ptr = alloc
+        .call(&mut store, len)
+        .map_err(|e| format!("alloc failed: {e}"))?;
+    let offset = usize::try_from(ptr).map_err(|_| "alloc returned a negative pointer")?;
+    memory
+        .write(&mut store, offset, input)
+        .map_err(|e| format!("alloc returned a bad pointer: {e}"))?;
+    let packed = on_request
+        .call(&mut store, (ptr, len))
+        .map_err(|e| format!("on_request failed: {e}"))?;
+    if packed == 0 {
+        return Ok(None);
+    }
+    // `ptr << 32 | len`, both unsigned
+    let packed = u64::from_ne_bytes(packed.to_ne_bytes());
+    let out_ptr = usize::try_from(packed >> 32).map_err(|_| "bad output pointer")?;
+    let out_len = usize::try_from(packed & 0xffff_ffff).map_err(|_| "bad output length")?;
+    if out_len > MAX_OUTPUT_BYTES {
+        return Err(format!("output over {MAX_OUTPUT_BYTES} bytes"));
+    }
+    let mut output = vec![0; out_len];
+    memory
+        .read(&store, out_ptr, &mut output)
+        .map_err(|e| format!("output out of bounds: {e}"))?;
+    Ok(Some(output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plugin answering every request with `output`, written at 1024.
+    fn plugin(output: &str) -> Vec<u8> {
+        let len = output.len();
+        let escaped = output.replace('"', "\\\"");
+        wat(&format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 1024) "{escaped}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "on_request") (param i32 i32) (result i64)
+                    (i64.or (i64.shl (i64.const 1024) (i64.const 32)) (i64.const {len}))))"#
+        ))
+    }
+
+    fn wat(text: &str) -> Vec<u8> {
+        wat::parse_str(text).unwrap()
+    }
+
+    #[test]
+    fn test_call() {
+        let compiled = compile(&plugin(r#"{"path":"/b"}"#)).unwrap();
+        let output = call(&compiled, br#"{"method":"GET"}"#).unwrap();
+        assert_eq!(output.as_deref(), Some(&br#"{"path":"/b"}"#[..]));
+    }
+
+    #[test]
+    fn test_compile_refuses() {
+        let imports = wat(
+            r#"(module (import "env" "open" (func)) (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "on_request") (param i32 i32) (result i64) (i64.const 0)))"#,
+        );
+        assert!(compile(&imports).err().unwrap().contains("env::open"));
+        let no_alloc = wat(r#"(module (memory (export "memory") 1))"#);
+        assert!(compile(&no_alloc).err().unwrap().contains("alloc"));
+        assert!(compile(b"not wasm").is_err());
+    }
+
+    #[test]
+    fn test_fuel() {
+        let spin = wat(r#"(module (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func (export "on_request") (param i32 i32) (result i64)
+                    (loop (br 0)) (i64.const 0)))"#);
+        let compiled = compile(&spin).unwrap();
+        assert!(call(&compiled, b"{}").is_err());
+    }
+}
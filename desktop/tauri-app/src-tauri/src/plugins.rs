@@ -0,0 +1,455 @@
+//! Request middleware for native servers, written as WASM modules and
+//! dropped into the `plugins` folder in the app data dir. Each is off until
+//! enabled with `plugins_set_enabled`; enabled ones see every request on
+//! every native server, in name order, and can rewrite it or answer it.
+//!
+//! Modules run sandboxed (see `plugin_runtime`): they can't import anything,
+//! so no files, network or clock, and each call gets a fresh instance with
+//! bounded memory and fuel. A module exports:
+//!
+//! - `memory`
+//! - `alloc(len: i32) -> i32`, returning where to write `len` bytes
+//! - `on_request(ptr: i32, len: i32) -> i64`, given the request as JSON
+//!   (`{"method", "path", "headers": [[name, value]], "remoteAddress"}`)
+//!   and returning `ptr << 32 | len` of its answer, or 0 to pass the
+//!   request on unchanged
+//!
+//! The answer is JSON too: `{"path", "setHeaders", "removeHeaders"}` to
+//! rewrite the request, or `{"respond": {"status", "headers", "body"}}` to
+//! answer it. A plugin that fails gets the request a 500, so one guarding
+//! a folder can't be bypassed by making it trap.
+//!
+//! Loading needs the `plugins` build feature; without it every plugin is
+//! listed with an error.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, State};
+
+#[cfg(feature = "plugins")]
+use super::plugin_runtime::{self as runtime, Compiled};
+
+#[cfg(not(feature = "plugins"))]
+mod runtime {
+    pub struct Compiled;
+
+    pub fn compile(_wasm: &[u8]) -> Result<Compiled, String> {
+        Err("this build has no plugin support".to_string())
+    }
+
+    pub fn call(_plugin: &Compiled, _input: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        Err("this build has no plugin support".to_string())
+    }
+}
+#[cfg(not(feature = "plugins"))]
+use runtime::Compiled;
+
+const PLUGINS_DIR: &str = "plugins";
+const ENABLED_FILENAME: &str = "plugins.json";
+const EXTENSION: &str = "wasm";
+
+/// Headers a plugin's own response can carry, besides `Content-Length`.
+const RESPONSE_HEADERS: &[&str] = &[
+    "Cache-Control",
+    "Content-Type",
+    "Location",
+    "Retry-After",
+    "Set-Cookie",
+    "Vary",
+    "WWW-Authenticate",
+];
+
+/// What a plugin is shown.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub remote_address: String,
+}
+
+impl PluginRequest {
+    /// The request head to parse in place of the one received.
+    pub fn head(&self) -> String {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.method, self.path);
+        for (name, value) in &self.headers {
+            let _ = write!(head, "{name}: {value}\r\n");
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// A response made by a plugin.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: String,
+}
+
+impl PluginResponse {
+    /// `headers` that may be sent, by their canonical names.
+    pub fn allowed_headers(&self) -> Vec<(&'static str, String)> {
+        self.headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let allowed = RESPONSE_HEADERS
+                    .iter()
+                    .find(|h| h.eq_ignore_ascii_case(name));
+                if allowed.is_none() {
+                    tracing::debug!("plugin response header {name} dropped");
+                }
+                Some((*allowed?, value.clone()))
+            })
+            .collect()
+    }
+}
+
+/// A plugin's answer.
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+struct PluginOutput {
+    path: Option<String>,
+    set_headers: Vec<(String, String)>,
+    remove_headers: Vec<String>,
+    respond: Option<PluginResponse>,
+}
+
+/// What to do with a request once the plugins have seen it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Carry on, with the request as changed if it was.
+    Continue(Option<PluginRequest>),
+    Respond(PluginResponse),
+    /// A plugin failed; the request gets a 500.
+    Failed(String),
+}
+
+fn valid_header(name: &str, value: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+        && !value.contains(['\r', '\n'])
+}
+
+/// Apply `output` to `req`, or turn it into a response.
+fn apply(req: &mut PluginRequest, output: PluginOutput) -> Result<Option<PluginResponse>, String> {
+    if let Some(res) = output.respond {
+        if !(100..=599).contains(&res.status) {
+            return Err(format!("invalid status {}", res.status));
+        }
+        if !res.headers.iter().all(|(n, v)| valid_header(n, v)) {
+            return Err("invalid response header".to_string());
+        }
+        return Ok(Some(res));
+    }
+    if let Some(path) = output.path {
+        if !path.starts_with('/') || path.contains(|c: char| c.is_control() || c == ' ') {
+            return Err(format!("invalid path {path:?}"));
+        }
+        req.path = path;
+    }
+    for name in &output.remove_headers {
+        req.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+    for (name, value) in output.set_headers {
+        if !valid_header(&name, &value) {
+            return Err(format!("invalid header {name:?}"));
+        }
+        req.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+        req.headers.push((name, value));
+    }
+    Ok(None)
+}
+
+struct Loaded {
+    name: String,
+    compiled: Compiled,
+}
+
+/// The enabled plugins, shared by every native server. Clones share them.
+#[derive(Clone, Default)]
+pub struct Plugins(Arc<RwLock<Vec<Loaded>>>);
+
+impl std::fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<_> = self
+            .0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| p.name.clone())
+            .collect();
+        f.debug_tuple("Plugins").field(&names).finish()
+    }
+}
+
+impl PartialEq for Plugins {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Plugins {}
+
+impl Plugins {
+    pub fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_empty()
+    }
+
+    /// Pass `req` through each plugin in turn.
+    pub fn run(&self, mut req: PluginRequest) -> Outcome {
+        let plugins = self.0.read().unwrap();
+        let mut changed = false;
+        for plugin in plugins.iter() {
+            let input = serde_json::to_vec(&req).unwrap_or_default();
+            let output = runtime::call(&plugin.compiled, &input).and_then(|output| {
+                output
+                    .map(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+                    .transpose()
+            });
+            let step = output.and_then(|output: Option<PluginOutput>| match output {
+                Some(output) => apply(&mut req, output).map(|res| (res, true)),
+                None => Ok((None, false)),
+            });
+            match step {
+                Ok((Some(res), _)) => return Outcome::Respond(res),
+                Ok((None, touched)) => changed |= touched,
+                Err(e) => return Outcome::Failed(format!("plugin {}: {e}", plugin.name)),
+            }
+        }
+        Outcome::Continue(changed.then_some(req))
+    }
+}
+
+/// A plugin found in the folder.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginInfo {
+    /// File name without `.wasm`.
+    pub name: String,
+    pub path: String,
+    pub enabled: bool,
+    /// Why an enabled plugin couldn't be loaded.
+    pub error: Option<String>,
+}
+
+/// The plugins folder, what's in it, and the ones running.
+pub struct PluginHost {
+    dir: Option<PathBuf>,
+    listed: RwLock<Vec<PluginInfo>>,
+    pub plugins: Plugins,
+}
+
+/// `*.wasm` files in `dir`, by name.
+fn discover(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut found: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == EXTENSION))
+        .filter_map(|p| Some((p.file_stem()?.to_str()?.to_string(), p)))
+        .collect();
+    found.sort();
+    found
+}
+
+fn read_enabled(dir: &Path) -> BTreeSet<String> {
+    std::fs::read_to_string(dir.join(ENABLED_FILENAME))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+impl PluginHost {
+    pub fn load(app: &tauri::AppHandle) -> Self {
+        let dir = super::paths::app_data_dir(app)
+            .ok()
+            .map(|d| d.join(PLUGINS_DIR));
+        let host = Self {
+            dir,
+            listed: RwLock::default(),
+            plugins: Plugins::default(),
+        };
+        host.reload();
+        host
+    }
+
+    /// Scan the folder again and load the enabled plugins. Servers use
+    /// the new set from their next request.
+    pub fn reload(&self) {
+        let Some(dir) = &self.dir else {
+            return;
+        };
+        let enabled = read_enabled(dir);
+        let mut listed = Vec::new();
+        let mut loaded = Vec::new();
+        for (name, path) in discover(dir) {
+            let on = enabled.contains(&name);
+            let compiled = on.then(|| {
+                std::fs::read(&path)
+                    .map_err(|e| format!("read failed: {e}"))
+                    .and_then(|wasm| runtime::compile(&wasm))
+            });
+            let error = match compiled {
+                Some(Ok(compiled)) => {
+                    loaded.push(Loaded {
+                        name: name.clone(),
+                        compiled,
+                    });
+                    None
+                }
+                Some(Err(e)) => {
+                    tracing::warn!("plugin {name}: {e}");
+                    Some(e)
+                }
+                None => None,
+            };
+            listed.push(PluginInfo {
+                name,
+                path: path.display().to_string(),
+                enabled: on,
+                error,
+            });
+        }
+        tracing::info!("plugins: {} of {} loaded", loaded.len(), listed.len());
+        *self.plugins.0.write().unwrap() = loaded;
+        *self.listed.write().unwrap() = listed;
+    }
+
+    fn set_enabled(&self, name: &str, enabled: bool) -> Result<(), String> {
+        let dir = self.dir.as_ref().ok_or("no app data directory")?;
+        if !discover(dir).iter().any(|(n, _)| n == name) {
+            return Err(format!("plugin {name} not found"));
+        }
+        let mut names = read_enabled(dir);
+        if enabled {
+            names.insert(name.to_string());
+        } else {
+            names.remove(name);
+        }
+        let json = serde_json::to_string_pretty(&names).map_err(|e| e.to_string())?;
+        let path = dir.join(ENABLED_FILENAME);
+        std::fs::write(&path, json).map_err(|e| format!("write {}: {e}", path.display()))?;
+        self.reload();
+        Ok(())
+    }
+}
+
+/// Plugins in the folder, and whether each is on.
+#[tauri::command]
+pub async fn plugins_list(host: State<'_, PluginHost>) -> Result<Vec<PluginInfo>, String> {
+    Ok(host.listed.read().unwrap().clone())
+}
+
+#[tauri::command]
+pub async fn plugins_set_enabled(
+    name: String,
+    enabled: bool,
+    host: State<'_, PluginHost>,
+) -> Result<Vec<PluginInfo>, String> {
+    host.set_enabled(&name, enabled)?;
+    Ok(host.listed.read().unwrap().clone())
+}
+
+/// Pick up plugins added to or changed in the folder.
+#[tauri::command]
+pub async fn plugins_reload(app: tauri::AppHandle) -> Result<Vec<PluginInfo>, String> {
+    let host = app.state::<PluginHost>();
+    if let Some(dir) = &host.dir {
+        std::fs::create_dir_all(dir).map_err(|e| format!("mkdir failed: {e}"))?;
+    }
+    host.reload();
+    let listed = host.listed.read().unwrap().clone();
+    Ok(listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> PluginRequest {
+        PluginRequest {
+            method: "GET".to_string(),
+            path: "/a".to_string(),
+            headers: vec![
+                ("Host".to_string(), "x".to_string()),
+                ("X-Debug".to_string(), "1".to_string()),
+            ],
+            remote_address: "127.0.0.1:5000".to_string(),
+        }
+    }
+
+    fn output(json: &str) -> PluginOutput {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_apply_rewrite() {
+        let mut req = request();
+        let changed = output(
+            r#"{"path": "/b?c=1", "setHeaders": [["host", "y"]], "removeHeaders": ["x-debug"]}"#,
+        );
+        assert_eq!(apply(&mut req, changed), Ok(None));
+        assert_eq!(req.path, "/b?c=1");
+        assert_eq!(req.headers, [("host".to_string(), "y".to_string())]);
+        assert_eq!(req.head(), "GET /b?c=1 HTTP/1.1\r\nhost: y\r\n\r\n");
+
+        for bad in [
+            r#"{"path": "b"}"#,
+            r#"{"path": "/a b"}"#,
+            r#"{"setHeaders": [["X-A", "1\r\nX-B: 2"]]}"#,
+            r#"{"setHeaders": [["X A", "1"]]}"#,
+        ] {
+            assert!(apply(&mut request(), output(bad)).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_apply_respond() {
+        let res = apply(
+            &mut request(),
+            output(
+                r#"{"respond": {"status": 403, "headers": [["content-type", "text/plain"], ["X-Other", "1"]], "body": "no"}}"#,
+            ),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!((res.status, res.body.as_str()), (403, "no"));
+        assert_eq!(
+            res.allowed_headers(),
+            [("Content-Type", "text/plain".to_string())]
+        );
+        let bad = output(r#"{"respond": {"status": 1000}}"#);
+        assert!(apply(&mut request(), bad).is_err());
+    }
+
+    #[test]
+    fn test_discover() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["b.wasm", "a.wasm", "notes.txt", ENABLED_FILENAME] {
+            std::fs::write(tmp.path().join(name), "").unwrap();
+        }
+        std::fs::create_dir(tmp.path().join("c.wasm")).unwrap();
+        let names: Vec<_> = discover(tmp.path()).into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert!(discover(&tmp.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_run_without_plugins() {
+        assert_eq!(Plugins::default().run(request()), Outcome::Continue(None));
+    }
+}
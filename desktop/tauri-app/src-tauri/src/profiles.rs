@@ -0,0 +1,373 @@
+//! Per-folder server settings that outlive a single server, keyed by the
+//! served root. Currently only MIME type overrides, which `http` applies
+//! when a server for that folder starts without its own.
+//!
+//! Also named profiles: whole server setups saved under a name, so they
+//! can be started again in one click from the app or the tray. Their
+//! `auth` is kept in the keychain (see `secrets`), not in the file.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use tauri::menu::{MenuItem, Submenu, SubmenuBuilder};
+use tauri::{Emitter, Manager, State};
+
+use super::build_step::BuildEvent;
+use super::http::{self, HttpServerInfo, HttpServerOptions, HttpState};
+use super::http_server::{normalize_mime_types, RequestLog};
+use super::secrets;
+
+const PROFILES_FILENAME: &str = "server-profiles.json";
+const NAMED_PROFILES_FILENAME: &str = "named-profiles.json";
+const MENU_PREFIX: &str = "profile-start-";
+/// Saved in place of a named profile's `auth`: whether the keychain holds
+/// one, so profiles without it never need the keychain.
+const AUTH_IN_KEYCHAIN: &str = "authInKeychain";
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerProfile {
+    /// Extension (no dot) to MIME type.
+    #[serde(default)]
+    pub mime_types: BTreeMap<String, String>,
+}
+
+type Profiles = BTreeMap<String, ServerProfile>;
+
+fn profiles_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    Ok(super::paths::app_data_dir(app)?.join(PROFILES_FILENAME))
+}
+
+fn read_profiles(path: &Path) -> Profiles {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn write_profiles(path: &Path, profiles: &Profiles) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("mkdir failed: {e}"))?;
+    }
+    let json =
+        serde_json::to_string_pretty(profiles).map_err(|e| format!("serialize failed: {e}"))?;
+    std::fs::write(path, json).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+/// Validate the profile and store it under `root`, or remove the entry
+/// when it's empty.
+fn set_profile(path: &Path, root: &str, profile: ServerProfile) -> Result<ServerProfile, String> {
+    let mime_types = normalize_mime_types(profile.mime_types)?;
+    let profile = ServerProfile {
+        mime_types: mime_types.into_iter().collect(),
+    };
+    let mut profiles = read_profiles(path);
+    if profile == ServerProfile::default() {
+        profiles.remove(root);
+    } else {
+        profiles.insert(root.to_string(), profile.clone());
+    }
+    write_profiles(path, &profiles)?;
+    Ok(profile)
+}
+
+/// Saved MIME overrides for `root`, empty if none.
+pub fn mime_types(app: &tauri::AppHandle, root: &str) -> HashMap<String, String> {
+    let Ok(path) = profiles_path(app) else {
+        return HashMap::new();
+    };
+    read_profiles(&path)
+        .remove(root)
+        .map(|p| p.mime_types.into_iter().collect())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+pub async fn server_profile_get(
+    root: String,
+    app: tauri::AppHandle,
+) -> Result<ServerProfile, String> {
+    let path = profiles_path(&app)?;
+    Ok(read_profiles(&path).remove(&root).unwrap_or_default())
+}
+
+/// Replace the saved profile for `root`. Returns it normalized.
+#[tauri::command]
+pub async fn server_profile_set(
+    root: String,
+    profile: ServerProfile,
+    app: tauri::AppHandle,
+) -> Result<ServerProfile, String> {
+    set_profile(&profiles_path(&app)?, &root, profile)
+}
+
+// -- Named profiles --
+
+/// `http_server_create` options by profile name.
+type NamedProfiles = BTreeMap<String, serde_json::Value>;
+
+fn named_profiles_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    Ok(super::paths::app_data_dir(app)?.join(NAMED_PROFILES_FILENAME))
+}
+
+fn read_named(path: &Path) -> NamedProfiles {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn write_named(path: &Path, profiles: &NamedProfiles) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("mkdir failed: {e}"))?;
+    }
+    let json =
+        serde_json::to_string_pretty(profiles).map_err(|e| format!("serialize failed: {e}"))?;
+    std::fs::write(path, json).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+fn auth_key(name: &str) -> String {
+    format!("server-password:profile:{name}")
+}
+
+/// Check `options` would start a server, and take its `auth` out to be
+/// stored separately.
+fn split_auth(
+    name: &str,
+    mut options: serde_json::Value,
+) -> Result<(serde_json::Value, Option<serde_json::Value>), String> {
+    if name.trim().is_empty() {
+        return Err("profile name is empty".to_string());
+    }
+    serde_json::from_value::<HttpServerOptions>(options.clone())
+        .map_err(|e| format!("invalid profile {name}: {e}"))?;
+    let auth = options.as_object_mut().and_then(|o| {
+        let auth = o.remove("auth").filter(|auth| !auth.is_null());
+        o.insert(AUTH_IN_KEYCHAIN.to_string(), auth.is_some().into());
+        auth
+    });
+    Ok((options, auth))
+}
+
+/// Whether saved `options` have `auth` in the keychain: `None` for
+/// profiles saved before that was recorded, which might.
+fn auth_in_keychain(options: &serde_json::Value) -> Option<bool> {
+    options[AUTH_IN_KEYCHAIN].as_bool()
+}
+
+/// Remove a profile's saved `auth`. Failing only leaves a stale secret
+/// behind, so it's logged rather than stopping the change.
+async fn forget_auth(app: &tauri::AppHandle, name: &str) {
+    if let Err(e) = secrets::delete(app, &auth_key(name)).await {
+        tracing::warn!("profile {name}: {e}");
+    }
+}
+
+/// Saved profile names, sorted.
+pub fn profile_names(app: &tauri::AppHandle) -> Vec<String> {
+    named_profiles_path(app)
+        .map(|path| read_named(&path).into_keys().collect())
+        .unwrap_or_default()
+}
+
+/// Save `options` as profile `name`, replacing any with that name.
+#[tauri::command]
+pub async fn profile_save(
+    name: String,
+    options: serde_json::Value,
+    app: tauri::AppHandle,
+) -> Result<(), String> {
+    let (options, auth) = split_auth(&name, options)?;
+    let path = named_profiles_path(&app)?;
+    let mut profiles = read_named(&path);
+    let had_auth = profiles
+        .get(&name)
+        .is_some_and(|saved| auth_in_keychain(saved) != Some(false));
+    match auth {
+        Some(auth) => secrets::set(&app, &auth_key(&name), &auth.to_string()).await?,
+        None if had_auth => forget_auth(&app, &name).await,
+        None => {}
+    }
+    profiles.insert(name, options);
+    write_named(&path, &profiles)?;
+    super::rebuild_tray_menu(&app);
+    Ok(())
+}
+
+/// Options saved as profile `name`, `auth` included.
+#[tauri::command]
+pub async fn profile_load(
+    name: String,
+    app: tauri::AppHandle,
+) -> Result<serde_json::Value, String> {
+    let mut options = read_named(&named_profiles_path(&app)?)
+        .remove(&name)
+        .ok_or_else(|| format!("no profile named {name}"))?;
+    let in_keychain = auth_in_keychain(&options);
+    if let Some(o) = options.as_object_mut() {
+        o.remove(AUTH_IN_KEYCHAIN);
+    }
+    if in_keychain == Some(false) {
+        return Ok(options);
+    }
+    match secrets::get(&app, &auth_key(&name)).await? {
+        Some(auth) => {
+            let auth =
+                serde_json::from_str(&auth).map_err(|e| format!("invalid saved auth: {e}"))?;
+            options["auth"] = auth;
+        }
+        // Starting it without would leave the folder open
+        None if in_keychain == Some(true) => {
+            return Err(format!(
+                "profile {name}: saved auth is missing from the keychain"
+            ));
+        }
+        None => {}
+    }
+    Ok(options)
+}
+
+#[tauri::command]
+pub async fn profile_list(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    Ok(profile_names(&app))
+}
+
+#[tauri::command]
+pub async fn profile_delete(name: String, app: tauri::AppHandle) -> Result<(), String> {
+    let path = named_profiles_path(&app)?;
+    let mut profiles = read_named(&path);
+    let Some(saved) = profiles.remove(&name) else {
+        return Err(format!("no profile named {name}"));
+    };
+    write_named(&path, &profiles)?;
+    if auth_in_keychain(&saved) != Some(false) {
+        forget_auth(&app, &name).await;
+    }
+    super::rebuild_tray_menu(&app);
+    Ok(())
+}
+
+/// Start a server with profile `name`, as `http_server_create` would.
+#[tauri::command]
+pub async fn profile_start(
+    name: String,
+    on_request: Channel<RequestLog>,
+    on_build: Channel<BuildEvent>,
+    app: tauri::AppHandle,
+    state: State<'_, HttpState>,
+) -> Result<HttpServerInfo, String> {
+    let options = profile_load(name.clone(), app.clone()).await?;
+    let options =
+        serde_json::from_value(options).map_err(|e| format!("invalid profile {name}: {e}"))?;
+    http::http_server_create(options, on_request, on_build, app, state).await
+}
+
+pub fn profile_from_menu_id(id: &str) -> Option<usize> {
+    id.strip_prefix(MENU_PREFIX)?.parse().ok()
+}
+
+/// "Saved Servers" tray submenu. Item ids index into `profile_names`.
+pub fn profiles_submenu<M: Manager<tauri::Wry>>(
+    app: &M,
+    names: &[String],
+) -> tauri::Result<Submenu<tauri::Wry>> {
+    let mut builder = SubmenuBuilder::new(app, "Saved Servers");
+    if names.is_empty() {
+        builder = builder.item(&MenuItem::new(
+            app,
+            "No Saved Servers",
+            false,
+            None::<&str>,
+        )?);
+    }
+    for (i, name) in names.iter().enumerate() {
+        builder = builder.item(&MenuItem::with_id(
+            app,
+            format!("{MENU_PREFIX}{i}"),
+            name,
+            true,
+            None::<&str>,
+        )?);
+    }
+    builder.build()
+}
+
+/// Start a profile picked from the tray. The webview starts it with
+/// `profile_start`, so it can follow the server's log like any other.
+pub fn handle_profile(app: &tauri::AppHandle, index: usize) {
+    let Some(name) = profile_names(app).into_iter().nth(index) else {
+        tracing::warn!("no profile {index}");
+        return;
+    };
+    let _ = app.emit("profile-start-requested", name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_profile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(PROFILES_FILENAME);
+        let profile = ServerProfile {
+            mime_types: BTreeMap::from([(".WASM".to_string(), "application/wasm".to_string())]),
+        };
+
+        let saved = set_profile(&path, "/srv/site", profile).unwrap();
+        assert_eq!(saved.mime_types["wasm"], "application/wasm");
+        assert_eq!(read_profiles(&path)["/srv/site"], saved);
+
+        let bad = ServerProfile {
+            mime_types: BTreeMap::from([("md".to_string(), "nope".to_string())]),
+        };
+        assert!(set_profile(&path, "/srv/site", bad).is_err());
+        assert_eq!(read_profiles(&path)["/srv/site"], saved);
+
+        set_profile(&path, "/srv/site", ServerProfile::default()).unwrap();
+        assert!(read_profiles(&path).is_empty());
+    }
+
+    #[test]
+    fn test_split_auth() {
+        let options = serde_json::json!({
+            "root": "/srv/site",
+            "port": 8080,
+            "cors": true,
+            "auth": {"type": "basic", "username": "me", "password": "s3cret"},
+        });
+        let (saved, auth) = split_auth("site", options).unwrap();
+        assert_eq!(
+            saved,
+            serde_json::json!({"root": "/srv/site", "port": 8080, "cors": true, "authInKeychain": true})
+        );
+        assert_eq!(auth.unwrap()["password"], "s3cret");
+        assert_eq!(auth_in_keychain(&saved), Some(true));
+
+        let (saved, auth) = split_auth("site", serde_json::json!({"root": "/srv/site"})).unwrap();
+        assert_eq!(auth, None);
+        // Never needs the keychain
+        assert_eq!(auth_in_keychain(&saved), Some(false));
+        assert_eq!(auth_in_keychain(&serde_json::json!({"root": "/srv"})), None);
+        assert!(split_auth(" ", serde_json::json!({"root": "/srv/site"})).is_err());
+        assert!(split_auth("site", serde_json::json!({"port": 8080})).is_err());
+    }
+
+    #[test]
+    fn test_named_profiles_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join(NAMED_PROFILES_FILENAME);
+        assert!(read_named(&path).is_empty());
+
+        let profiles = NamedProfiles::from([
+            ("docs".to_string(), serde_json::json!({"root": "/srv/docs"})),
+            ("blog".to_string(), serde_json::json!({"root": "/srv/blog"})),
+        ]);
+        write_named(&path, &profiles).unwrap();
+        let read = read_named(&path);
+        assert_eq!(read, profiles);
+        assert_eq!(read.into_keys().collect::<Vec<_>>(), ["blog", "docs"]);
+    }
+}
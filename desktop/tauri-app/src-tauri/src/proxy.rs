@@ -0,0 +1,488 @@
+//! Reverse proxy routes for `http_server`: requests under a path prefix are
+//! forwarded to another origin, so static files and an API can share one
+//! port. Bodies are streamed both ways, and `Upgrade` requests (such as
+//! websockets) become a two-way pipe once the upstream switches protocols.
+
+use std::fmt::Write as _;
+use std::sync::{Arc, LazyLock};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use super::happy_eyeballs;
+
+/// Request headers that only apply to one connection, and so aren't
+/// forwarded. `Transfer-Encoding` is kept: bodies are relayed as sent.
+const HOP_BY_HOP: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Upgrade",
+    "Host",
+];
+/// Upstream response heads longer than this are relayed without being
+/// parsed for the log.
+const MAX_RESPONSE_HEAD_BYTES: usize = 16 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyRoute {
+    /// URL path prefix such as `/api`; matches `/api` and `/api/...`.
+    pub prefix: String,
+    /// `http://` or `https://` origin, optionally with a base path, e.g.
+    /// `http://localhost:3000` or `https://example.com/v1`.
+    pub target: String,
+    /// Forward `/api/users` as `/users` rather than `/api/users`.
+    #[serde(default)]
+    pub strip_prefix: bool,
+}
+
+/// A parsed `ProxyRoute::target`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upstream {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Prepended to forwarded paths; empty or starting with `/`, never
+    /// ending with one.
+    pub base_path: String,
+}
+
+impl Upstream {
+    pub fn parse(target: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid proxy target: {target:?}");
+        let (tls, rest) = if let Some(rest) = target.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = target.strip_prefix("https://") {
+            (true, rest)
+        } else {
+            return Err(invalid());
+        };
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if path.contains(['?', '#']) {
+            return Err(invalid());
+        }
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.rsplit_once(':') {
+            // Bracketed IPv6 without a port, e.g. `[::1]`
+            Some((_, port)) if port.ends_with(']') => (authority, default_port),
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, default_port),
+        };
+        if host.is_empty() || host.contains(['@', ' ']) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            base_path: path.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Value for the forwarded `Host` header.
+    fn authority(&self) -> String {
+        if self.port == if self.tls { 443 } else { 80 } {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Reject routes that could never match or forward.
+pub fn check_routes(routes: &[ProxyRoute]) -> Result<(), String> {
+    for route in routes {
+        if !route.prefix.starts_with('/') {
+            return Err(format!(
+                "proxy prefix must start with /: {:?}",
+                route.prefix
+            ));
+        }
+        Upstream::parse(&route.target)?;
+    }
+    Ok(())
+}
+
+/// The route whose prefix is the longest match for a request target.
+pub fn matching<'a>(routes: &'a [ProxyRoute], target: &str) -> Option<&'a ProxyRoute> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    routes
+        .iter()
+        .filter(|route| {
+            let prefix = route.prefix.trim_end_matches('/');
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .max_by_key(|route| route.prefix.trim_end_matches('/').len())
+}
+
+impl ProxyRoute {
+    /// The request target to send upstream.
+    pub fn upstream_target(&self, upstream: &Upstream, target: &str) -> String {
+        let rest = if self.strip_prefix {
+            target
+                .strip_prefix(self.prefix.trim_end_matches('/'))
+                .unwrap_or(target)
+        } else {
+            target
+        };
+        match rest.chars().next() {
+            Some('/') => format!("{}{rest}", upstream.base_path),
+            // Just the prefix, or the prefix and a query
+            _ => format!("{}/{rest}", upstream.base_path),
+        }
+    }
+}
+
+/// `target` without query parameter `name`.
+pub fn strip_query_param(target: &str, name: &str) -> String {
+    let Some((path, query)) = target.split_once('?') else {
+        return target.to_string();
+    };
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| pair.split('=').next() != Some(name))
+        .collect();
+    if kept.is_empty() {
+        path.to_string()
+    } else {
+        format!("{path}?{}", kept.join("&"))
+    }
+}
+
+/// A `Cookie` header without cookie `name`; `None` if nothing is left.
+fn strip_cookie(cookie: &str, name: &str) -> Option<String> {
+    let kept: Vec<&str> = cookie
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(name))
+        .collect();
+    (!kept.is_empty()).then(|| kept.join("; "))
+}
+
+fn header<'a>(headers: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| *value)
+}
+
+/// Whether the client asked to switch protocols, e.g. to a WebSocket.
+pub fn is_upgrade(headers: &[(&str, &str)]) -> bool {
+    header(headers, "Upgrade").is_some()
+        && header(headers, "Connection").is_some_and(|c| {
+            c.split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        })
+}
+
+/// What is left out of a forwarded request.
+#[derive(Default)]
+pub struct Redact<'a> {
+    /// The `Authorization` header was for this server.
+    pub authorization: bool,
+    /// Cookie set by this server's token auth.
+    pub cookie: Option<&'a str>,
+}
+
+/// The request head to send upstream; `proto` is the scheme the client
+/// used to reach this server.
+pub fn request_head(
+    method: &str,
+    target: &str,
+    headers: &[(&str, &str)],
+    upstream: &Upstream,
+    client_ip: &str,
+    proto: &str,
+    redact: &Redact<'_>,
+) -> String {
+    let mut head = format!(
+        "{method} {target} HTTP/1.1\r\nHost: {}\r\n",
+        upstream.authority()
+    );
+    let mut forwarded_for = None;
+    for (name, value) in headers {
+        let skip = HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h))
+            || (redact.authorization && name.eq_ignore_ascii_case("Authorization"));
+        if skip {
+            continue;
+        }
+        if name.eq_ignore_ascii_case("X-Forwarded-For") {
+            forwarded_for = Some(format!("{value}, {client_ip}"));
+            continue;
+        }
+        if name.eq_ignore_ascii_case("Cookie") {
+            if let Some(cookie) = redact.cookie {
+                if let Some(kept) = strip_cookie(value, cookie) {
+                    let _ = write!(head, "Cookie: {kept}\r\n");
+                }
+                continue;
+            }
+        }
+        let _ = write!(head, "{name}: {value}\r\n");
+    }
+    let forwarded_for = forwarded_for.unwrap_or_else(|| client_ip.to_string());
+    let _ = write!(
+        head,
+        "X-Forwarded-For: {forwarded_for}\r\nX-Forwarded-Proto: {proto}\r\n"
+    );
+    if let Some(host) = header(headers, "Host") {
+        let _ = write!(head, "X-Forwarded-Host: {host}\r\n");
+    }
+    match header(headers, "Upgrade").filter(|_| is_upgrade(headers)) {
+        Some(protocol) => {
+            let _ = write!(head, "Connection: Upgrade\r\nUpgrade: {protocol}\r\n\r\n");
+        }
+        None => head.push_str("Connection: close\r\n\r\n"),
+    }
+    head
+}
+
+static TLS_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(webpki_root_certs::TLS_SERVER_ROOT_CERTS.iter().cloned());
+    Arc::new(
+        ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    )
+});
+
+pub async fn connect(upstream: &Upstream) -> std::io::Result<TcpStream> {
+    let host = upstream.host.trim_start_matches('[').trim_end_matches(']');
+    happy_eyeballs::connect(host, upstream.port, happy_eyeballs::DEFAULT_ATTEMPT_DELAY).await
+}
+
+pub async fn connect_tls(
+    upstream: &Upstream,
+    tcp: TcpStream,
+) -> std::io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let host = upstream.host.trim_start_matches('[').trim_end_matches(']');
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    TlsConnector::from(TLS_CONFIG.clone())
+        .connect(name, tcp)
+        .await
+}
+
+/// Status code from an HTTP response head.
+fn response_status(head: &[u8]) -> Option<u16> {
+    let line = head.split(|&b| b == b'\r').next()?;
+    std::str::from_utf8(line)
+        .ok()?
+        .split(' ')
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// Send `head` (and whatever of the body the client already sent) to
+/// `upstream`, then pipe both ways until the upstream has answered.
+/// Returns the upstream's status and the response body bytes relayed, or
+/// `None` if it closed without answering.
+pub async fn relay<C, U>(client: C, upstream: U, head: &[u8]) -> std::io::Result<Option<(u16, u64)>>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let (mut upstream_read, mut upstream_write) = tokio::io::split(upstream);
+    upstream_write.write_all(head).await?;
+
+    let request = async {
+        tokio::io::copy(&mut client_read, &mut upstream_write).await?;
+        upstream_write.shutdown().await
+    };
+    let response = async {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 8192];
+        let head_end = loop {
+            if let Some(at) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break Some(at + 4);
+            }
+            if buf.len() > MAX_RESPONSE_HEAD_BYTES {
+                break None;
+            }
+            let n = upstream_read.read(&mut chunk).await?;
+            if n == 0 && buf.is_empty() {
+                return Ok(None);
+            }
+            if n == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        };
+        client_write.write_all(&buf).await?;
+        let body_read = head_end.map_or(0, |end| buf.len() - end);
+        let copied = tokio::io::copy(&mut upstream_read, &mut client_write).await?;
+        let status = response_status(&buf).unwrap_or(0);
+        Ok::<_, std::io::Error>(Some((status, body_read as u64 + copied)))
+    };
+
+    tokio::pin!(request, response);
+    let mut request_done = false;
+    loop {
+        tokio::select! {
+            // The client may hold its side open, or drop it early; either
+            // way the upstream's answer is what counts
+            _ = &mut request, if !request_done => request_done = true,
+            result = &mut response => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(prefix: &str, target: &str, strip_prefix: bool) -> ProxyRoute {
+        ProxyRoute {
+            prefix: prefix.to_string(),
+            target: target.to_string(),
+            strip_prefix,
+        }
+    }
+
+    #[test]
+    fn test_parse_upstream() {
+        let upstream = Upstream::parse("https://example.com/v1/").unwrap();
+        assert_eq!(
+            upstream,
+            Upstream {
+                tls: true,
+                host: "example.com".to_string(),
+                port: 443,
+                base_path: "/v1".to_string(),
+            }
+        );
+        assert_eq!(upstream.authority(), "example.com");
+        let local = Upstream::parse("http://localhost:3000").unwrap();
+        assert_eq!(
+            (local.port, local.authority().as_str()),
+            (3000, "localhost:3000")
+        );
+        assert_eq!(Upstream::parse("http://[::1]").unwrap().port, 80);
+        for bad in [
+            "localhost:3000",
+            "ftp://x",
+            "http://",
+            "http://a:b",
+            "http://x/?q",
+        ] {
+            assert!(Upstream::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_matching_and_rewrite() {
+        let routes = [
+            route("/api", "http://localhost:3000", false),
+            route("/api/v2/", "http://localhost:4000/next", true),
+        ];
+        assert_eq!(
+            matching(&routes, "/api?x=1").unwrap().target,
+            routes[0].target
+        );
+        assert_eq!(
+            matching(&routes, "/api/v2/users").unwrap().target,
+            routes[1].target
+        );
+        assert!(matching(&routes, "/apis").is_none());
+        assert!(check_routes(&routes).is_ok());
+        assert!(check_routes(&[route("api", "http://x", false)]).is_err());
+
+        let upstream = Upstream::parse(&routes[1].target).unwrap();
+        assert_eq!(
+            routes[1].upstream_target(&upstream, "/api/v2/users?id=1"),
+            "/next/users?id=1"
+        );
+        assert_eq!(
+            routes[1].upstream_target(&upstream, "/api/v2?id=1"),
+            "/next/?id=1"
+        );
+        let upstream = Upstream::parse(&routes[0].target).unwrap();
+        assert_eq!(routes[0].upstream_target(&upstream, "/api/x"), "/api/x");
+    }
+
+    #[test]
+    fn test_request_head() {
+        let upstream = Upstream::parse("http://localhost:3000").unwrap();
+        let headers = [
+            ("Host", "192.168.1.5:8080"),
+            ("Authorization", "Basic bWU6cHc="),
+            ("Cookie", "theme=dark; ok200_token=abc"),
+            ("Connection", "keep-alive"),
+            ("X-Forwarded-For", "10.0.0.1"),
+        ];
+        let redact = Redact {
+            authorization: true,
+            cookie: Some("ok200_token"),
+        };
+        let head = request_head(
+            "GET", "/api", &headers, &upstream, "10.0.0.2", "http", &redact,
+        );
+        assert_eq!(
+            head,
+            "GET /api HTTP/1.1\r\nHost: localhost:3000\r\nCookie: theme=dark\r\n\
+             X-Forwarded-For: 10.0.0.1, 10.0.0.2\r\nX-Forwarded-Proto: http\r\n\
+             X-Forwarded-Host: 192.168.1.5:8080\r\nConnection: close\r\n\r\n"
+        );
+
+        let ws = [
+            ("Connection", "keep-alive, Upgrade"),
+            ("Upgrade", "websocket"),
+        ];
+        assert!(is_upgrade(&ws));
+        let head = request_head(
+            "GET",
+            "/ws",
+            &ws,
+            &upstream,
+            "::1",
+            "https",
+            &Redact::default(),
+        );
+        assert!(head.contains("X-Forwarded-Proto: https\r\n"));
+        assert!(head.ends_with("Connection: Upgrade\r\nUpgrade: websocket\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_strip_query_param() {
+        assert_eq!(strip_query_param("/a?token=x&b=1", "token"), "/a?b=1");
+        assert_eq!(strip_query_param("/a?token=x", "token"), "/a");
+        assert_eq!(strip_query_param("/a", "token"), "/a");
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let (client, mut client_peer) = tokio::io::duplex(1024);
+        let (upstream, mut upstream_peer) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut request = vec![0; 64];
+            let n = upstream_peer.read(&mut request).await.unwrap();
+            upstream_peer
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            drop(upstream_peer);
+            request.truncate(n);
+            request
+        });
+        let relayed = relay(client, upstream, b"POST / HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(relayed, Some((201, 2)));
+        assert_eq!(server.await.unwrap(), b"POST / HTTP/1.1\r\n\r\n");
+        let mut response = String::new();
+        client_peer.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with("\r\n\r\nok"));
+    }
+}
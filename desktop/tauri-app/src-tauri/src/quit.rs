@@ -0,0 +1,106 @@
+//! Ask before quitting while servers still have clients connected. Every
+//! explicit exit (the tray's Quit, `app.exit` from the webview) arrives as
+//! `RunEvent::ExitRequested` with a code, so that's the one place we check.
+//! Closing the window never quits (the tray stays), and restarts for
+//! updates are never held up. Restarts go through `restart`, which winds
+//! things down first instead of leaving clients mid-response.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{Emitter, Manager, State};
+
+use super::ftp::FtpState;
+use super::http::HttpState;
+#[cfg(feature = "sftp")]
+use super::sftp::SftpState;
+use super::tcp::TcpState;
+use super::usage::Usage;
+use super::Settings;
+
+/// Set once the user chose "Quit anyway", so the next exit goes through.
+#[derive(Default)]
+pub struct QuitConfirmed(AtomicBool);
+
+#[derive(Serialize, Clone)]
+struct ConfirmQuit {
+    connections: usize,
+}
+
+/// Open sockets on the webview's TCP servers, plus connections to the
+/// native HTTP, FTP and SFTP servers.
+fn active_connections(app: &tauri::AppHandle) -> usize {
+    let tcp = app.state::<TcpState>();
+    let sockets = tauri::async_runtime::block_on(tcp.counts()).1;
+    let native = app.state::<HttpState>().active_connections()
+        + app.state::<FtpState>().active_connections();
+    #[cfg(feature = "sftp")]
+    let native = native + app.state::<SftpState>().active_connections();
+    sockets + usize::try_from(native).unwrap_or(usize::MAX)
+}
+
+/// Whether an exit with `code` may go ahead. If not, the webview is shown
+/// and asked to confirm with a `confirm-quit` event.
+pub fn allow_exit(app: &tauri::AppHandle, code: i32) -> bool {
+    if code == tauri::RESTART_EXIT_CODE || app.state::<QuitConfirmed>().0.load(Ordering::Relaxed) {
+        return true;
+    }
+    let connections = active_connections(app);
+    if connections == 0 {
+        return true;
+    }
+    super::show_main_window(app);
+    let _ = app.emit("confirm-quit", ConfirmQuit { connections });
+    false
+}
+
+/// "Quit anyway".
+#[tauri::command]
+pub async fn quit_confirm(
+    app: tauri::AppHandle,
+    state: State<'_, QuitConfirmed>,
+) -> Result<(), String> {
+    state.0.store(true, Ordering::Relaxed);
+    app.exit(0);
+    Ok(())
+}
+
+/// "Keep running in background".
+#[tauri::command]
+pub async fn quit_cancel(app: tauri::AppHandle) -> Result<(), String> {
+    if let Some(window) = app.get_webview_window("main") {
+        window
+            .hide()
+            .map_err(|e| format!("hide window failed: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Stop all servers, write settings out, and relaunch with the same
+/// arguments.
+pub async fn restart(app: &tauri::AppHandle) -> ! {
+    tracing::info!("restarting");
+    app.state::<HttpState>().close_all();
+    app.state::<FtpState>().close_all();
+    #[cfg(feature = "sftp")]
+    app.state::<SftpState>().close_all();
+    app.state::<TcpState>().close_all().await;
+    if let Some(settings) = app.try_state::<Mutex<Settings>>() {
+        super::save_settings(app, &settings.lock().unwrap());
+    }
+    app.state::<Usage>().flush();
+    app.restart()
+}
+
+/// `restart` from a thread outside the async runtime, such as the tray's.
+/// Blocking on the runtime from one of its own tasks panics.
+pub fn restart_blocking(app: &tauri::AppHandle) -> ! {
+    tauri::async_runtime::block_on(restart(app))
+}
+
+/// Restart after changing settings that only take effect at startup.
+#[tauri::command]
+pub async fn app_restart(app: tauri::AppHandle) -> Result<(), String> {
+    restart(&app).await
+}
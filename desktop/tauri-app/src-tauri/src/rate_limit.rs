@@ -0,0 +1,255 @@
+//! Per-client limits for `http_server`: requests per second (a token
+//! bucket, so short bursts are fine) and requests being handled at once.
+//! Clients that keep going over are banned for a while.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Refusals further apart than this don't add up to a ban.
+const STRIKE_WINDOW: Duration = Duration::from_mins(1);
+/// Idle clients are forgotten once this many are tracked.
+const MAX_TRACKED: usize = 4096;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RateLimitOptions {
+    /// Sustained requests per second from one client; 0 for no limit.
+    pub requests_per_sec: u32,
+    /// Requests a client can make in a row after being idle.
+    pub burst: u32,
+    /// Requests from one client handled at the same time (HTTP/1.1
+    /// connections, or HTTP/2 streams); 0 for no limit.
+    pub max_connections: u32,
+    /// Refused requests, each within a minute of the last, that get a
+    /// client banned; 0 never bans.
+    pub ban_after: u32,
+    pub ban_secs: u64,
+}
+
+impl Default for RateLimitOptions {
+    fn default() -> Self {
+        Self {
+            requests_per_sec: 20,
+            burst: 40,
+            max_connections: 16,
+            ban_after: 50,
+            ban_secs: 5 * 60,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    tokens: f64,
+    refilled: Instant,
+    connections: u32,
+    strikes: u32,
+    last_strike: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+/// Why a request was turned away, and how many seconds until it's worth
+/// trying again.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Refusal {
+    pub reason: &'static str,
+    pub retry_after: u64,
+}
+
+/// Limits and per-client state for one server. Clones share the state.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    pub options: RateLimitOptions,
+    clients: Arc<Mutex<HashMap<IpAddr, Client>>>,
+}
+
+impl PartialEq for RateLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.clients, &other.clients)
+    }
+}
+
+impl Eq for RateLimiter {}
+
+/// Counts a request as being handled until dropped.
+pub struct Permit {
+    clients: Arc<Mutex<HashMap<IpAddr, Client>>>,
+    ip: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&self.ip) {
+            client.connections -= 1;
+        }
+    }
+}
+
+/// Whole seconds until `until`, at least one.
+fn secs_until(until: Instant, now: Instant) -> u64 {
+    let wait = until.saturating_duration_since(now);
+    (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).max(1)
+}
+
+impl RateLimiter {
+    pub fn new(options: RateLimitOptions) -> Self {
+        Self {
+            options,
+            clients: Arc::default(),
+        }
+    }
+
+    /// Let a request from `ip` through, or say why not.
+    pub fn admit(&self, ip: IpAddr) -> Result<Permit, Refusal> {
+        self.admit_at(ip, Instant::now())
+    }
+
+    fn admit_at(&self, ip: IpAddr, now: Instant) -> Result<Permit, Refusal> {
+        let options = &self.options;
+        let burst = f64::from(options.burst.max(1));
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED {
+            clients.retain(|_, c| {
+                c.connections > 0
+                    || c.banned_until.is_some_and(|until| until > now)
+                    || now.duration_since(c.refilled) < STRIKE_WINDOW
+            });
+        }
+        let client = clients.entry(ip).or_insert_with(|| Client {
+            tokens: burst,
+            refilled: now,
+            connections: 0,
+            strikes: 0,
+            last_strike: None,
+            banned_until: None,
+        });
+        if let Some(until) = client.banned_until {
+            if until > now {
+                return Err(Refusal {
+                    reason: "banned",
+                    retry_after: secs_until(until, now),
+                });
+            }
+            client.banned_until = None;
+            client.strikes = 0;
+        }
+
+        let rate = f64::from(options.requests_per_sec);
+        if options.requests_per_sec > 0 {
+            let elapsed = now.duration_since(client.refilled).as_secs_f64();
+            client.tokens = (client.tokens + elapsed * rate).min(burst);
+        }
+        client.refilled = now;
+        let refusal =
+            if options.max_connections > 0 && client.connections >= options.max_connections {
+                Refusal {
+                    reason: "too many connections",
+                    retry_after: 1,
+                }
+            } else if options.requests_per_sec > 0 && client.tokens < 1.0 {
+                let wait = Duration::from_secs_f64((1.0 - client.tokens) / rate);
+                Refusal {
+                    reason: "too many requests",
+                    retry_after: secs_until(now + wait, now),
+                }
+            } else {
+                client.tokens -= 1.0;
+                client.connections += 1;
+                return Ok(Permit {
+                    clients: self.clients.clone(),
+                    ip,
+                });
+            };
+
+        if client
+            .last_strike
+            .is_none_or(|last| now.duration_since(last) > STRIKE_WINDOW)
+        {
+            client.strikes = 0;
+        }
+        client.strikes += 1;
+        client.last_strike = Some(now);
+        if options.ban_after > 0 && client.strikes >= options.ban_after {
+            let ban = Duration::from_secs(options.ban_secs);
+            client.banned_until = Some(now + ban);
+            return Err(Refusal {
+                reason: "banned",
+                retry_after: options.ban_secs.max(1),
+            });
+        }
+        Err(refusal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 20));
+
+    #[test]
+    fn test_requests_per_sec() {
+        let limiter = RateLimiter::new(RateLimitOptions {
+            requests_per_sec: 2,
+            burst: 2,
+            ban_after: 0,
+            ..RateLimitOptions::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.admit_at(IP, now).is_ok());
+        assert!(limiter.admit_at(IP, now).is_ok());
+        let refusal = limiter.admit_at(IP, now).err().unwrap();
+        assert_eq!(refusal.reason, "too many requests");
+        assert_eq!(refusal.retry_after, 1);
+        // Other clients have their own budget
+        let other = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+        assert!(limiter.admit_at(other, now).is_ok());
+        // Half a second refills one token
+        assert!(limiter
+            .admit_at(IP, now + Duration::from_millis(500))
+            .is_ok());
+        assert!(limiter
+            .admit_at(IP, now + Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let limiter = RateLimiter::new(RateLimitOptions {
+            max_connections: 1,
+            ..RateLimitOptions::default()
+        });
+        let permit = limiter.admit(IP).unwrap();
+        assert_eq!(
+            limiter.admit(IP).err().unwrap().reason,
+            "too many connections"
+        );
+        drop(permit);
+        assert!(limiter.admit(IP).is_ok());
+    }
+
+    #[test]
+    fn test_ban() {
+        let limiter = RateLimiter::new(RateLimitOptions {
+            requests_per_sec: 1,
+            burst: 1,
+            ban_after: 3,
+            ban_secs: 60,
+            ..RateLimitOptions::default()
+        });
+        let now = Instant::now();
+        assert!(limiter.admit_at(IP, now).is_ok());
+        assert_eq!(limiter.admit_at(IP, now).err().unwrap().retry_after, 1);
+        assert!(limiter.admit_at(IP, now).is_err());
+        let banned = limiter.admit_at(IP, now).err().unwrap();
+        assert_eq!((banned.reason, banned.retry_after), ("banned", 60));
+        // Still banned once tokens have refilled
+        let later = now + Duration::from_secs(30);
+        assert_eq!(limiter.admit_at(IP, later).err().unwrap().retry_after, 30);
+        assert!(limiter.admit_at(IP, now + Duration::from_secs(61)).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+//! Most recently served folders, persisted across launches and listed in
+//! the tray so a folder can be served again in one click.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tauri::menu::{MenuItem, PredefinedMenuItem, Submenu, SubmenuBuilder};
+use tauri::{Emitter, Manager, State};
+
+const RECENTS_FILENAME: &str = "recent-folders.json";
+const MAX_RECENTS: usize = 10;
+const MENU_PREFIX: &str = "recent-folder-";
+pub const CLEAR_MENU_ID: &str = "recent-clear";
+
+pub struct RecentFolders(Mutex<Vec<String>>);
+
+fn recents_path(app: &tauri::AppHandle) -> Option<PathBuf> {
+    Some(super::paths::app_data_dir(app).ok()?.join(RECENTS_FILENAME))
+}
+
+fn save(app: &tauri::AppHandle, folders: &[String]) {
+    if let Some(path) = recents_path(app) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        if let Ok(json) = serde_json::to_string_pretty(folders) {
+            std::fs::write(path, json).ok();
+        }
+    }
+}
+
+/// Move `folder` to the front, dropping duplicates and the oldest entries.
+fn push_recent(folders: &mut Vec<String>, folder: String) {
+    folders.retain(|f| *f != folder);
+    folders.insert(0, folder);
+    folders.truncate(MAX_RECENTS);
+}
+
+impl RecentFolders {
+    pub fn load(app: &tauri::AppHandle) -> Self {
+        let folders = recents_path(app)
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self(Mutex::new(folders))
+    }
+
+    /// Folders that still exist, most recent first.
+    pub fn list(&self) -> Vec<String> {
+        let folders = self.0.lock().unwrap();
+        folders
+            .iter()
+            .filter(|f| Path::new(f).is_dir())
+            .cloned()
+            .collect()
+    }
+
+    /// Record a folder that was just served.
+    pub fn add(&self, app: &tauri::AppHandle, folder: String) {
+        let mut folders = self.0.lock().unwrap();
+        push_recent(&mut folders, folder);
+        save(app, &folders);
+    }
+
+    pub fn clear(&self, app: &tauri::AppHandle) {
+        let mut folders = self.0.lock().unwrap();
+        folders.clear();
+        save(app, &folders);
+    }
+}
+
+pub fn folder_from_menu_id(id: &str) -> Option<usize> {
+    id.strip_prefix(MENU_PREFIX)?.parse().ok()
+}
+
+/// "Recent Folders" tray submenu. Item ids index into `RecentFolders::list`.
+pub fn recents_submenu<M: Manager<tauri::Wry>>(
+    app: &M,
+    folders: &[String],
+) -> tauri::Result<Submenu<tauri::Wry>> {
+    let mut builder = SubmenuBuilder::new(app, "Recent Folders");
+    if folders.is_empty() {
+        builder = builder.item(&MenuItem::new(
+            app,
+            "No Recent Folders",
+            false,
+            None::<&str>,
+        )?);
+    }
+    for (i, folder) in folders.iter().enumerate() {
+        builder = builder.item(&MenuItem::with_id(
+            app,
+            format!("{MENU_PREFIX}{i}"),
+            folder,
+            true,
+            None::<&str>,
+        )?);
+    }
+    builder
+        .item(&PredefinedMenuItem::separator(app)?)
+        .item(&MenuItem::with_id(
+            app,
+            CLEAR_MENU_ID,
+            "Clear Recent Folders",
+            !folders.is_empty(),
+            None::<&str>,
+        )?)
+        .build()
+}
+
+/// Start serving a folder picked from the tray. The engine runs in the
+/// webview, so it's asked to select the folder and start.
+pub fn handle_recent_folder(app: &tauri::AppHandle, index: usize) {
+    let Some(folder) = app.state::<RecentFolders>().list().into_iter().nth(index) else {
+        tracing::warn!("no recent folder {index}");
+        return;
+    };
+    let _ = app.emit("serve-folder", folder);
+}
+
+#[tauri::command]
+pub async fn recent_folders_list(recents: State<'_, RecentFolders>) -> Result<Vec<String>, String> {
+    Ok(recents.list())
+}
+
+#[tauri::command]
+pub async fn recent_folders_clear(
+    app: tauri::AppHandle,
+    recents: State<'_, RecentFolders>,
+) -> Result<(), String> {
+    recents.clear(&app);
+    super::rebuild_tray_menu(&app);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_recent() {
+        let mut folders = vec!["/a".to_string(), "/b".to_string()];
+        push_recent(&mut folders, "/b".to_string());
+        assert_eq!(folders, ["/b", "/a"]);
+
+        for i in 0..MAX_RECENTS {
+            push_recent(&mut folders, format!("/{i}"));
+        }
+        assert_eq!(folders.len(), MAX_RECENTS);
+        assert_eq!(folders[0], format!("/{}", MAX_RECENTS - 1));
+    }
+
+    #[test]
+    fn test_folder_from_menu_id() {
+        assert_eq!(folder_from_menu_id("recent-folder-3"), Some(3));
+        assert_eq!(folder_from_menu_id("recent-folder-x"), None);
+        assert_eq!(folder_from_menu_id(CLEAR_MENU_ID), None);
+    }
+}
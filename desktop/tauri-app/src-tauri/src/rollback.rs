@@ -0,0 +1,170 @@
+//! Keep a copy of the previously installed version so a bad release can be
+//! reverted. Updates can be installed from several places (the webview, the
+//! headless updater, install-on-quit), so instead of hooking each one we
+//! snapshot the running install at startup whenever its version changes;
+//! the snapshot taken before the change is then the previous version.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::offline_update;
+
+const ROLLBACK_DIR: &str = "rollback";
+const RECORD_FILENAME: &str = "rollback.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Snapshot {
+    version: String,
+    bundle: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RollbackRecord {
+    current: Option<Snapshot>,
+    previous: Option<Snapshot>,
+}
+
+fn rollback_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    super::paths::app_data_dir(app).map(|d| d.join(ROLLBACK_DIR))
+}
+
+fn load_record(dir: &Path) -> RollbackRecord {
+    std::fs::read_to_string(dir.join(RECORD_FILENAME))
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn save_record(dir: &Path, record: &RollbackRecord) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(record).map_err(|e| e.to_string())?;
+    std::fs::write(dir.join(RECORD_FILENAME), json)
+        .map_err(|e| format!("write rollback record: {e}"))
+}
+
+fn fresh_dir(dir: &Path) -> Result<(), String> {
+    std::fs::remove_dir_all(dir).ok();
+    std::fs::create_dir_all(dir).map_err(|e| format!("mkdir {}: {e}", dir.display()))
+}
+
+/// Snapshot the running install if its version differs from the last
+/// snapshot, demoting that snapshot to `previous`.
+pub fn snapshot_current_install(app: &tauri::AppHandle) -> Result<(), String> {
+    let version = app.package_info().version.to_string();
+    let dir = rollback_dir(app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("mkdir {}: {e}", dir.display()))?;
+    let mut record = load_record(&dir);
+    if record
+        .current
+        .as_ref()
+        .is_some_and(|s| s.version == version)
+    {
+        return Ok(());
+    }
+
+    if let Some(current) = record.current.take() {
+        let previous_dir = dir.join("previous");
+        fresh_dir(&previous_dir)?;
+        let file_name = current
+            .bundle
+            .file_name()
+            .ok_or("snapshot has no file name")?;
+        let bundle = previous_dir.join(file_name);
+        std::fs::rename(&current.bundle, &bundle)
+            .map_err(|e| format!("move snapshot {}: {e}", current.bundle.display()))?;
+        record.previous = Some(Snapshot {
+            version: current.version,
+            bundle,
+        });
+    }
+
+    let current_dir = dir.join("current");
+    fresh_dir(&current_dir)?;
+    let bundle = snapshot_install(&current_dir)?;
+    tracing::info!("saved {version} to {}", bundle.display());
+    record.current = Some(Snapshot { version, bundle });
+    save_record(&dir, &record)
+}
+
+/// Copy the running `AppImage`.
+#[cfg(target_os = "linux")]
+fn snapshot_install(dest: &Path) -> Result<PathBuf, String> {
+    let appimage = std::env::var_os("APPIMAGE")
+        .map(PathBuf::from)
+        .ok_or("not running from an AppImage")?;
+    let bundle = dest.join(appimage.file_name().ok_or("AppImage has no file name")?);
+    std::fs::copy(&appimage, &bundle).map_err(|e| format!("copy {}: {e}", appimage.display()))?;
+    Ok(bundle)
+}
+
+/// Archive the running `.app` in the same `.app.tar.gz` format the updater ships.
+#[cfg(target_os = "macos")]
+fn snapshot_install(dest: &Path) -> Result<PathBuf, String> {
+    let app_dir = offline_update::running_app_bundle()?;
+    let parent = app_dir.parent().ok_or("app bundle has no parent")?;
+    let name = app_dir.file_name().ok_or("app bundle has no name")?;
+    let mut archive_name = name.to_os_string();
+    archive_name.push(".tar.gz");
+    let bundle = dest.join(archive_name);
+    let status = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&bundle)
+        .arg("-C")
+        .arg(parent)
+        .arg(name)
+        .status()
+        .map_err(|e| format!("tar failed: {e}"))?;
+    if !status.success() {
+        return Err(format!("tar exited with {status}"));
+    }
+    Ok(bundle)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn snapshot_install(_dest: &Path) -> Result<PathBuf, String> {
+    Err("rollback is not supported on this platform".to_string())
+}
+
+/// Version a rollback would return to, if a snapshot exists.
+pub fn previous_version(app: &tauri::AppHandle) -> Option<String> {
+    let dir = rollback_dir(app).ok()?;
+    load_record(&dir).previous.map(|s| s.version)
+}
+
+/// Reinstall the previous version's snapshot. Returns the version that was
+/// rolled back from. Both snapshots are discarded so the next launch starts
+/// a fresh history instead of offering to "revert" to the bad release.
+pub fn rollback(app: &tauri::AppHandle) -> Result<String, String> {
+    let dir = rollback_dir(app)?;
+    let record = load_record(&dir);
+    let previous = record
+        .previous
+        .ok_or("no previous version to roll back to")?;
+    offline_update::install_local(&previous.bundle)?;
+    tracing::info!("reinstalled {}", previous.version);
+    std::fs::remove_dir_all(&dir).ok();
+    Ok(app.package_info().version.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollback_record_roundtrip() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(load_record(tmp.path()).current.is_none());
+
+        let record = RollbackRecord {
+            current: Some(Snapshot {
+                version: "1.1.0".to_string(),
+                bundle: tmp.path().join("current/200 OK.AppImage"),
+            }),
+            previous: None,
+        };
+        save_record(tmp.path(), &record).unwrap();
+        let loaded = load_record(tmp.path());
+        assert_eq!(loaded.current.unwrap().version, "1.1.0");
+        assert!(loaded.previous.is_none());
+    }
+}
@@ -0,0 +1,102 @@
+//! Server passwords, bearer tokens and tunnel credentials, kept in the OS
+//! keychain (Keychain, Credential Manager, Secret Service) rather than in
+//! settings.json. The webview can only touch the kinds listed in
+//! `KNOWN_KINDS`, optionally narrowed with a scope such as a served root:
+//! `server-password:/home/me/site`. Keychain calls block (on Linux they
+//! start a runtime of their own), so they run on the blocking pool.
+
+const KNOWN_KINDS: &[&str] = &["server-password", "bearer-token", "tunnel-credentials"];
+const MAX_KEY_LEN: usize = 1024;
+
+/// Check that `key` is one of the known kinds, alone or with a scope.
+fn check_key(key: &str) -> Result<(), String> {
+    let (kind, scope) = key.split_once(':').unwrap_or((key, ""));
+    if !KNOWN_KINDS.contains(&kind) {
+        return Err(format!("unknown secret: {kind}"));
+    }
+    if key.len() > MAX_KEY_LEN || scope.chars().any(char::is_control) {
+        return Err("invalid secret key".to_string());
+    }
+    Ok(())
+}
+
+/// Run `f` on the keychain entry for `key`, off the async runtime.
+async fn with_entry<T: Send + 'static>(
+    app: &tauri::AppHandle,
+    key: &str,
+    f: impl FnOnce(keyring::Entry) -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    check_key(key)?;
+    let service = app.config().identifier.clone();
+    let key = key.to_string();
+    tokio::task::spawn_blocking(move || {
+        let entry =
+            keyring::Entry::new(&service, &key).map_err(|e| format!("keychain failed: {e}"))?;
+        f(entry)
+    })
+    .await
+    .map_err(|e| format!("keychain failed: {e}"))?
+}
+
+pub async fn set(app: &tauri::AppHandle, key: &str, value: &str) -> Result<(), String> {
+    let value = value.to_string();
+    with_entry(app, key, move |entry| {
+        entry
+            .set_password(&value)
+            .map_err(|e| format!("keychain failed: {e}"))
+    })
+    .await
+}
+
+/// The stored secret, or `None` if there isn't one.
+pub async fn get(app: &tauri::AppHandle, key: &str) -> Result<Option<String>, String> {
+    with_entry(app, key, |entry| match entry.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("keychain failed: {e}")),
+    })
+    .await
+}
+
+/// Remove a secret. Removing one that isn't there is not an error.
+pub async fn delete(app: &tauri::AppHandle, key: &str) -> Result<(), String> {
+    with_entry(app, key, |entry| match entry.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("keychain failed: {e}")),
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn secret_set(key: String, value: String, app: tauri::AppHandle) -> Result<(), String> {
+    set(&app, &key, &value).await
+}
+
+#[tauri::command]
+pub async fn secret_get(key: String, app: tauri::AppHandle) -> Result<Option<String>, String> {
+    get(&app, &key).await
+}
+
+#[tauri::command]
+pub async fn secret_delete(key: String, app: tauri::AppHandle) -> Result<(), String> {
+    delete(&app, &key).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("bearer-token").is_ok());
+        assert!(check_key("server-password:/home/me/site").is_ok());
+        assert!(check_key("tunnel-credentials:a:b").is_ok());
+        assert_eq!(
+            check_key("settings"),
+            Err("unknown secret: settings".to_string())
+        );
+        assert!(check_key("bearer-token-x").is_err());
+        assert!(check_key("bearer-token:a\nb").is_err());
+        assert!(check_key(&format!("bearer-token:{}", "x".repeat(MAX_KEY_LEN))).is_err());
+    }
+}
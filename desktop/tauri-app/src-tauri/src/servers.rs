@@ -0,0 +1,313 @@
+//! Registry of web servers the webview has started, so the tray can list
+//! them. The servers themselves run in the webview; actions that need the
+//! engine (stop, clipboard) are forwarded back to it as events.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use ok200_common::shared_state::{self, SharedServer};
+use serde::Serialize;
+use tauri::menu::{MenuItem, Submenu, SubmenuBuilder};
+use tauri::{Manager, State};
+
+use super::notifications;
+use super::recents::RecentFolders;
+
+/// A running server as shown in the tray.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub id: u32,
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl ServerInfo {
+    /// Address to open locally; wildcard binds are reachable on loopback.
+    pub fn url(&self) -> String {
+        let host = match self.host.as_str() {
+            "0.0.0.0" | "::" | "" => "127.0.0.1",
+            host => host,
+        };
+        format!("http://{host}:{}", self.port)
+    }
+
+    /// Address other devices on the network can use, if the server is
+    /// bound somewhere they can reach.
+    pub fn lan_url(&self) -> Option<String> {
+        let ip = match self.host.as_str() {
+            "0.0.0.0" | "::" | "" => ok200_common::net::lan_ip()?,
+            host => host.parse().ok().filter(|ip: &IpAddr| !ip.is_loopback())?,
+        };
+        Some(ok200_common::net::http_url(ip, self.port))
+    }
+
+    fn reachable_from_lan(&self) -> bool {
+        match self.host.as_str() {
+            "0.0.0.0" | "::" | "" => true,
+            host => host.parse::<IpAddr>().is_ok_and(|ip| !ip.is_loopback()),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{} on :{}", self.name, self.port)
+    }
+}
+
+#[derive(Default)]
+pub struct ServerRegistry {
+    servers: Mutex<Vec<ServerInfo>>,
+    next_id: AtomicU32,
+}
+
+impl ServerRegistry {
+    pub fn list(&self) -> Vec<ServerInfo> {
+        self.servers.lock().unwrap().clone()
+    }
+
+    fn add(&self, name: String, host: String, port: u16) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.servers.lock().unwrap().push(ServerInfo {
+            id,
+            name,
+            host,
+            port,
+        });
+        id
+    }
+
+    fn remove(&self, id: u32) -> bool {
+        let mut servers = self.servers.lock().unwrap();
+        let before = servers.len();
+        servers.retain(|s| s.id != id);
+        servers.len() != before
+    }
+
+    /// Mirror the list into the shared state file so the host can see it.
+    fn publish(&self) {
+        let servers = self
+            .list()
+            .into_iter()
+            .map(|s| SharedServer {
+                name: s.name,
+                host: s.host,
+                port: s.port,
+            })
+            .collect();
+        if let Err(e) = shared_state::update(|state| state.servers = servers) {
+            tracing::warn!("failed to update shared state: {e}");
+        }
+    }
+
+    fn get(&self, id: u32) -> Option<ServerInfo> {
+        self.servers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|s| s.id == id)
+            .cloned()
+    }
+}
+
+/// Tray actions available for each running server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerAction {
+    Open,
+    CopyUrl,
+    CopyLanUrl,
+    Stop,
+}
+
+impl ServerAction {
+    const ALL: [ServerAction; 4] = [Self::Open, Self::CopyUrl, Self::CopyLanUrl, Self::Stop];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::CopyUrl => "copy",
+            Self::CopyLanUrl => "lan",
+            Self::Stop => "stop",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Open => "Open in Browser",
+            Self::CopyUrl => "Copy URL",
+            Self::CopyLanUrl => "Copy LAN URL",
+            Self::Stop => "Stop",
+        }
+    }
+
+    fn menu_id(self, server_id: u32) -> String {
+        format!("server-{}-{server_id}", self.as_str())
+    }
+
+    pub fn from_menu_id(id: &str) -> Option<(Self, u32)> {
+        let rest = id.strip_prefix("server-")?;
+        let (action, server_id) = rest.split_once('-')?;
+        let action = Self::ALL.into_iter().find(|a| a.as_str() == action)?;
+        Some((action, server_id.parse().ok()?))
+    }
+}
+
+/// Tray submenu for one server.
+pub fn server_submenu<M: Manager<tauri::Wry>>(
+    app: &M,
+    server: &ServerInfo,
+) -> tauri::Result<Submenu<tauri::Wry>> {
+    let mut builder = SubmenuBuilder::new(app, server.label());
+    for action in ServerAction::ALL {
+        if action == ServerAction::CopyLanUrl && !server.reachable_from_lan() {
+            continue;
+        }
+        builder = builder.item(&MenuItem::with_id(
+            app,
+            action.menu_id(server.id),
+            action.label(),
+            true,
+            None::<&str>,
+        )?);
+    }
+    builder.build()
+}
+
+/// Run a tray action for a registered server.
+pub fn handle_server_action(app: &tauri::AppHandle, action: ServerAction, id: u32) {
+    use tauri::Emitter;
+    use tauri_plugin_opener::OpenerExt;
+
+    let Some(server) = app.state::<ServerRegistry>().get(id) else {
+        tracing::warn!("no server {id}");
+        return;
+    };
+    match action {
+        ServerAction::Open => {
+            if let Err(e) = app.opener().open_url(server.url(), None::<&str>) {
+                tracing::error!("failed to open {}: {e}", server.url());
+            }
+        }
+        ServerAction::CopyUrl => {
+            let _ = app.emit("server-copy-url", server.url());
+        }
+        ServerAction::CopyLanUrl => {
+            if let Some(url) = server.lan_url() {
+                let _ = app.emit("server-copy-url", url);
+            } else {
+                tracing::warn!("no LAN address for {}", server.label());
+            }
+        }
+        ServerAction::Stop => {
+            let _ = app.emit("server-stop-requested", id);
+        }
+    }
+}
+
+/// Record a server the webview started, add it to the tray, and remember
+/// its folder in the recents list.
+/// Returns the registry id to pass to `server_unregister`.
+#[tauri::command]
+pub async fn server_register(
+    name: String,
+    root: String,
+    host: String,
+    port: u16,
+    app: tauri::AppHandle,
+    registry: State<'_, ServerRegistry>,
+    recents: State<'_, RecentFolders>,
+) -> Result<u32, String> {
+    let id = registry.add(name, host, port);
+    registry.publish();
+    recents.add(&app, root);
+    super::rebuild_tray_menu(&app);
+    if let Some(server) = registry.get(id) {
+        notifications::notify(
+            &app,
+            notifications::Kind::ServerLifecycle,
+            "Server started",
+            &format!("Serving {} at {}", server.name, server.url()),
+        );
+    }
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn server_unregister(
+    id: u32,
+    app: tauri::AppHandle,
+    registry: State<'_, ServerRegistry>,
+) -> Result<(), String> {
+    let server = registry.get(id);
+    if registry.remove(id) {
+        registry.publish();
+        super::rebuild_tray_menu(&app);
+    }
+    if let Some(server) = server {
+        notifications::notify(
+            &app,
+            notifications::Kind::ServerLifecycle,
+            "Server stopped",
+            &format!("Stopped serving {}", server.name),
+        );
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn server_list(registry: State<'_, ServerRegistry>) -> Result<Vec<ServerInfo>, String> {
+    Ok(registry.list())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_info_url_and_label() {
+        let server = ServerInfo {
+            id: 1,
+            name: "docs".to_string(),
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+        };
+        assert_eq!(server.url(), "http://127.0.0.1:8080");
+        assert_eq!(server.label(), "docs on :8080");
+        assert!(server.reachable_from_lan());
+
+        let local = ServerInfo {
+            host: "127.0.0.1".to_string(),
+            ..server.clone()
+        };
+        assert!(!local.reachable_from_lan());
+        assert_eq!(local.lan_url(), None);
+
+        let bound = ServerInfo {
+            host: "192.168.1.20".to_string(),
+            ..server
+        };
+        assert_eq!(bound.lan_url().unwrap(), "http://192.168.1.20:8080/");
+    }
+
+    #[test]
+    fn test_registry_add_remove() {
+        let registry = ServerRegistry::default();
+        let a = registry.add("a".to_string(), "127.0.0.1".to_string(), 8080);
+        let b = registry.add("b".to_string(), "127.0.0.1".to_string(), 8081);
+        assert_ne!(a, b);
+        assert_eq!(registry.list().len(), 2);
+        assert!(registry.remove(a));
+        assert!(!registry.remove(a));
+        assert_eq!(registry.get(b).unwrap().name, "b");
+    }
+
+    #[test]
+    fn test_server_action_menu_ids() {
+        for action in ServerAction::ALL {
+            let id = action.menu_id(42);
+            assert_eq!(ServerAction::from_menu_id(&id), Some((action, 42)));
+        }
+        assert_eq!(ServerAction::from_menu_id("server-open-x"), None);
+        assert_eq!(ServerAction::from_menu_id("show"), None);
+    }
+}
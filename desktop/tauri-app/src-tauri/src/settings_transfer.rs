@@ -0,0 +1,101 @@
+//! Export and import the app's configuration as a single JSON file so it can
+//! be moved to another machine.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use ok200_common::daemon::{load_daemon_config, save_daemon_config, DaemonConfig};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use super::{apply_settings, Settings};
+
+const FORMAT_VERSION: u32 = 1;
+const DEFAULT_EXPORT_FILENAME: &str = "200-ok-settings.json";
+
+#[derive(Serialize, Deserialize)]
+struct SettingsBundle {
+    format_version: u32,
+    settings: Settings,
+    /// Host daemon config; absent in bundles that predate it.
+    #[serde(default)]
+    daemon: Option<DaemonConfig>,
+}
+
+/// Where the tray menu exports to and imports from.
+pub fn default_export_path() -> Option<PathBuf> {
+    let dir = dirs::download_dir().or_else(dirs::home_dir)?;
+    Some(dir.join(DEFAULT_EXPORT_FILENAME))
+}
+
+fn parse_bundle(text: &str) -> Result<SettingsBundle, String> {
+    let mut bundle: SettingsBundle =
+        serde_json::from_str(text).map_err(|e| format!("invalid settings file: {e}"))?;
+    if bundle.format_version > FORMAT_VERSION {
+        return Err(format!(
+            "settings file format {} is newer than this app supports",
+            bundle.format_version
+        ));
+    }
+    // Folders from another machine may not exist here
+    if let Some(folder) = bundle.settings.default_serve_folder.as_deref() {
+        if !Path::new(folder).is_dir() {
+            tracing::warn!("dropping missing default_serve_folder {folder}");
+            bundle.settings.default_serve_folder = None;
+        }
+    }
+    Ok(bundle)
+}
+
+pub fn export_to(app: &tauri::AppHandle, path: &Path) -> Result<(), String> {
+    let bundle = SettingsBundle {
+        format_version: FORMAT_VERSION,
+        settings: app.state::<Mutex<Settings>>().lock().unwrap().clone(),
+        daemon: Some(load_daemon_config()),
+    };
+    let json = serde_json::to_string_pretty(&bundle).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+pub fn import_from(app: &tauri::AppHandle, path: &Path) -> Result<(), String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let bundle = parse_bundle(&text)?;
+    apply_settings(app, bundle.settings)?;
+    if let Some(daemon) = bundle.daemon {
+        save_daemon_config(&daemon).map_err(|e| format!("save daemon config: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Write settings and daemon config to `path`.
+#[tauri::command]
+pub async fn settings_export(path: String, app: tauri::AppHandle) -> Result<(), String> {
+    export_to(&app, Path::new(&path))
+}
+
+/// Replace settings and daemon config with the contents of `path`.
+#[tauri::command]
+pub async fn settings_import(path: String, app: tauri::AppHandle) -> Result<(), String> {
+    import_from(&app, Path::new(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bundle() {
+        let json = r#"{
+            "format_version": 1,
+            "settings": { "autostart": true, "default_serve_folder": "/no/such/folder" }
+        }"#;
+        let bundle = parse_bundle(json).unwrap();
+        assert!(bundle.settings.autostart);
+        assert!(bundle.settings.default_serve_folder.is_none());
+        assert!(bundle.daemon.is_none());
+
+        assert!(parse_bundle(r#"{"format_version": 99, "settings": {}}"#).is_err());
+        assert!(parse_bundle("not json").is_err());
+    }
+}
@@ -0,0 +1,945 @@
+//! Folders served over SFTP, for the tools people already point at SSH
+//! servers: `sftp`, `scp`, Cyberduck, most desktop file managers. Only the
+//! `sftp` subsystem is offered, with no shell, so tools that run commands
+//! on the server (rsync, legacy `scp -O`) can't connect. Read-only unless
+//! `write` is set; paths are confined to the root the way `ftp` confines
+//! them, and `dotfiles` means what it does there. Clients log in with the
+//! `auth` password (a token is the password, with any user name) or one of
+//! `authorized_keys`; with neither, anyone can. The host key is made the
+//! first time a server starts and kept with the app's data, so clients
+//! only have to trust it once.
+
+use std::collections::HashMap;
+use std::io::{self, SeekFrom};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ok200_common::http::resolve;
+use russh::keys::ssh_key::rand_core::OsRng;
+use russh::keys::ssh_key::{AuthorizedKeys, LineEnding};
+use russh::keys::{Algorithm, HashAlg, PrivateKey, PublicKey};
+use russh::server::{Auth as SshAuth, Config, Msg, Session};
+use russh::{Channel, ChannelId, MethodKind, MethodSet};
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
+};
+use russh_sftp::server::StatusReply;
+use serde::{Deserialize, Serialize};
+use tauri::State;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use super::auth::Auth;
+use super::dotfiles::Dotfiles;
+use super::ftp::join_path;
+use super::metrics::{Connection, ServerStats};
+use super::tcp::ACCEPT_RETRY_DELAY;
+use super::uploads::file_name;
+
+/// In the app's data folder.
+const HOST_KEY_FILENAME: &str = "sftp_host_key";
+const IDLE_TIMEOUT: Duration = Duration::from_mins(5);
+/// The connection is closed after this many failed logins. As in
+/// OpenSSH, since clients try each of their keys before a password.
+const MAX_LOGIN_FAILURES: usize = 6;
+/// Largest read answered at once; clients ask again for the rest.
+const MAX_READ: u32 = 256 * 1024;
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpServerOptions {
+    pub root: String,
+    /// 0 picks a free port; 22 usually needs admin rights.
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// Allow uploads, deletes, renames and new folders.
+    #[serde(default)]
+    pub write: bool,
+    #[serde(default)]
+    pub dotfiles: Dotfiles,
+    /// Password logins.
+    #[serde(default)]
+    pub auth: Option<Auth>,
+    /// Keys that may log in, as lines of an `authorized_keys` file.
+    #[serde(default)]
+    pub authorized_keys: Vec<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SftpServerInfo {
+    pub id: u32,
+    pub root: String,
+    pub host: String,
+    /// The bound port, which differs from the requested one for port 0.
+    pub port: u16,
+    pub write: bool,
+    pub dotfiles: Dotfiles,
+    /// Includes the generated token, which is the password.
+    pub auth: Option<Auth>,
+    pub authorized_keys: Vec<String>,
+    /// `SHA256:…`, what clients show when first asked to trust the server.
+    pub host_key_fingerprint: String,
+}
+
+struct SftpServer {
+    info: SftpServerInfo,
+    task: JoinHandle<()>,
+    stats: ServerStats,
+}
+
+#[derive(Default)]
+pub struct SftpState {
+    servers: Mutex<HashMap<u32, SftpServer>>,
+    next_id: AtomicU32,
+}
+
+impl SftpState {
+    /// Stop every server.
+    pub fn close_all(&self) {
+        for (_, server) in self.servers.lock().unwrap().drain() {
+            server.task.abort();
+        }
+    }
+
+    /// Clients connected to every server, for confirming before quitting.
+    pub fn active_connections(&self) -> u64 {
+        let servers = self.servers.lock().unwrap();
+        servers.values().map(|s| s.stats.active()).sum()
+    }
+}
+
+/// A served folder, shared by its connections.
+struct Served {
+    /// Canonical, for `resolve`.
+    root: PathBuf,
+    write: bool,
+    dotfiles: Dotfiles,
+    auth: Option<Auth>,
+    authorized_keys: Vec<PublicKey>,
+    /// Counts connected clients.
+    stats: ServerStats,
+}
+
+impl Served {
+    fn open_to_anyone(&self) -> bool {
+        self.auth.is_none() && self.authorized_keys.is_empty()
+    }
+
+    fn authorized(&self, key: &PublicKey) -> bool {
+        self.authorized_keys
+            .iter()
+            .any(|k| k.key_data() == key.key_data())
+    }
+
+    /// The file or folder at `path`, if it exists and may be reached.
+    fn existing(&self, path: &str) -> Option<PathBuf> {
+        if !self.dotfiles.allows(path) {
+            return None;
+        }
+        resolve(&self.root, path)
+    }
+
+    /// The entry `path` names itself, rather than what a link there points
+    /// at: a plain name in an existing folder. For creating, deleting and
+    /// renaming.
+    fn entry(&self, path: &str) -> Option<PathBuf> {
+        if !self.dotfiles.allows(path) {
+            return None;
+        }
+        let (parent, name) = path.rsplit_once('/')?;
+        let dir = resolve(&self.root, parent).filter(|d| d.is_dir())?;
+        Some(dir.join(file_name(name)?))
+    }
+}
+
+/// Authentication methods to offer.
+fn methods(served: &Served) -> MethodSet {
+    let mut methods = Vec::new();
+    if served.open_to_anyone() {
+        methods.extend([MethodKind::None, MethodKind::Password]);
+    }
+    if served.auth.is_some() {
+        methods.push(MethodKind::Password);
+    }
+    if !served.authorized_keys.is_empty() {
+        methods.push(MethodKind::PublicKey);
+    }
+    MethodSet::from(&methods[..])
+}
+
+/// The keys in `lines`, which are in `authorized_keys` format.
+fn parse_keys(lines: &[String]) -> Result<Vec<PublicKey>, String> {
+    AuthorizedKeys::new(&lines.join("\n"))
+        .map(|entry| {
+            entry
+                .map(|e| e.public_key().clone())
+                .map_err(|e| format!("invalid authorized key: {e}"))
+        })
+        .collect()
+}
+
+/// The key at `path`, made and saved there if there isn't one yet.
+fn host_key(path: &Path) -> Result<PrivateKey, String> {
+    if path.exists() {
+        return PrivateKey::read_openssh_file(path)
+            .map_err(|e| format!("reading {} failed: {e}", path.display()));
+    }
+    let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)
+        .map_err(|e| format!("making a host key failed: {e}"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("create_dir_all failed: {e}"))?;
+    }
+    key.write_openssh_file(path, LineEnding::LF)
+        .map_err(|e| format!("writing {} failed: {e}", path.display()))?;
+    Ok(key)
+}
+
+fn config(host_key: PrivateKey, served: &Served) -> Config {
+    Config {
+        methods: methods(served),
+        // OpenSSH tries "none" first to learn the methods; don't make it wait
+        auth_rejection_time_initial: Some(Duration::ZERO),
+        keys: vec![host_key],
+        inactivity_timeout: Some(IDLE_TIMEOUT),
+        ..Config::default()
+    }
+}
+
+fn status(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_string(),
+        language_tag: "en-US".to_string(),
+    }
+}
+
+fn failed(e: &io::Error) -> StatusReply {
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        _ => StatusCode::Failure,
+    };
+    code.with_message(e.to_string())
+}
+
+fn not_found() -> StatusReply {
+    StatusCode::NoSuchFile.with_message("Not found")
+}
+
+fn not_allowed() -> StatusReply {
+    StatusCode::PermissionDenied.with_message("Not allowed")
+}
+
+/// Attributes as clients expect them, with the write bits cleared on a
+/// read-only server so they don't offer to change anything.
+fn attributes(meta: &std::fs::Metadata, write: bool) -> FileAttributes {
+    let mut attrs = FileAttributes::from(meta);
+    if !write {
+        attrs.permissions = attrs.permissions.map(|mode| mode & !0o222);
+    }
+    attrs
+}
+
+/// A folder's entries that may be listed, links followed.
+fn list_dir(dir: &Path, dotfiles: Dotfiles, write: bool) -> io::Result<Vec<File>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)?.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let (true, Ok(meta)) = (dotfiles.listed(&name), std::fs::metadata(entry.path())) {
+            files.push(File::new(name, attributes(&meta, write)));
+        }
+    }
+    files.sort_by(|a, b| a.filename.cmp(&b.filename));
+    Ok(files)
+}
+
+enum Open {
+    File(tokio::fs::File),
+    /// Sent in one reply, on the first read.
+    Dir {
+        path: PathBuf,
+        listed: bool,
+    },
+}
+
+/// One client's SFTP subsystem.
+struct Sftp {
+    served: Arc<Served>,
+    peer: SocketAddr,
+    open: HashMap<String, Open>,
+    next_handle: u32,
+}
+
+impl Sftp {
+    fn new(served: Arc<Served>, peer: SocketAddr) -> Self {
+        Self {
+            served,
+            peer,
+            open: HashMap::new(),
+            next_handle: 1,
+        }
+    }
+
+    fn add(&mut self, open: Open) -> String {
+        let handle = self.next_handle.to_string();
+        self.next_handle += 1;
+        self.open.insert(handle.clone(), open);
+        handle
+    }
+
+    fn file(&mut self, handle: &str) -> Result<&mut tokio::fs::File, StatusReply> {
+        match self.open.get_mut(handle) {
+            Some(Open::File(file)) => Ok(file),
+            _ => Err(StatusCode::BadMessage.with_message("Not an open file")),
+        }
+    }
+
+    /// The path to change, after checking the server allows changes.
+    fn writable(&self, path: &str) -> Result<(String, PathBuf), StatusReply> {
+        if !self.served.write {
+            return Err(StatusCode::PermissionDenied.with_message("This server is read-only"));
+        }
+        let path = join_path("/", path);
+        let target = self.served.entry(&path).ok_or_else(not_allowed)?;
+        Ok((path, target))
+    }
+
+    fn changed(&self, what: &str, path: &str) {
+        tracing::info!("sftp {}: {what} {path}", self.peer);
+    }
+
+    async fn metadata(&self, path: &str) -> Result<std::fs::Metadata, StatusReply> {
+        let target = self
+            .served
+            .existing(&join_path("/", path))
+            .ok_or_else(not_found)?;
+        tokio::fs::metadata(target).await.map_err(|e| failed(&e))
+    }
+}
+
+impl russh_sftp::server::Handler for Sftp {
+    type Error = StatusReply;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported.into()
+    }
+
+    async fn init(
+        &mut self,
+        _version: u32,
+        _extensions: HashMap<String, String>,
+    ) -> Result<Version, Self::Error> {
+        Ok(Version::new())
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> Result<Name, Self::Error> {
+        Ok(Name {
+            id,
+            files: vec![File::dummy(join_path("/", &path))],
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        let meta = self.metadata(&path).await?;
+        Ok(Attrs {
+            id,
+            attrs: attributes(&meta, self.served.write),
+        })
+    }
+
+    /// Links are always followed, as in listings.
+    async fn lstat(&mut self, id: u32, path: String) -> Result<Attrs, Self::Error> {
+        self.stat(id, path).await
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> Result<Attrs, Self::Error> {
+        let write = self.served.write;
+        let meta = self
+            .file(&handle)?
+            .metadata()
+            .await
+            .map_err(|e| failed(&e))?;
+        Ok(Attrs {
+            id,
+            attrs: attributes(&meta, write),
+        })
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> Result<Handle, Self::Error> {
+        let path = self
+            .served
+            .existing(&join_path("/", &path))
+            .filter(|p| p.is_dir())
+            .ok_or_else(|| StatusCode::NoSuchFile.with_message("No such folder"))?;
+        let handle = self.add(Open::Dir {
+            path,
+            listed: false,
+        });
+        Ok(Handle { id, handle })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> Result<Name, Self::Error> {
+        let Some(Open::Dir { path, listed }) = self.open.get_mut(&handle) else {
+            return Err(StatusCode::BadMessage.with_message("Not an open folder"));
+        };
+        if std::mem::replace(listed, true) {
+            return Err(StatusCode::Eof.into());
+        }
+        let (dir, dotfiles, write) = (path.clone(), self.served.dotfiles, self.served.write);
+        let files = tokio::task::spawn_blocking(move || list_dir(&dir, dotfiles, write))
+            .await
+            .map_err(|e| StatusCode::Failure.with_message(format!("List failed: {e}")))?
+            .map_err(|e| failed(&e))?;
+        Ok(Name { id, files })
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let file = if pflags.intersects(
+            OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+        ) {
+            let (path, target) = self.writable(&filename)?;
+            // Writing through a link could reach outside the root
+            if target.is_dir() || target.is_symlink() {
+                return Err(not_allowed());
+            }
+            let file = tokio::fs::OpenOptions::new()
+                .read(pflags.contains(OpenFlags::READ))
+                .write(true)
+                .append(pflags.contains(OpenFlags::APPEND))
+                .truncate(pflags.contains(OpenFlags::TRUNCATE))
+                .create(pflags.contains(OpenFlags::CREATE))
+                .create_new(pflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUDE))
+                .open(&target)
+                .await
+                .map_err(|e| failed(&e))?;
+            self.changed("write", &path);
+            file
+        } else {
+            let target = self
+                .served
+                .existing(&join_path("/", &filename))
+                .filter(|p| p.is_file())
+                .ok_or_else(|| StatusCode::NoSuchFile.with_message("Not a file"))?;
+            tokio::fs::File::open(&target)
+                .await
+                .map_err(|e| failed(&e))?
+        };
+        let handle = self.add(Open::File(file));
+        Ok(Handle { id, handle })
+    }
+
+    async fn read(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        len: u32,
+    ) -> Result<Data, Self::Error> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| failed(&e))?;
+        let mut data = Vec::new();
+        file.take(u64::from(len.min(MAX_READ)))
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| failed(&e))?;
+        if data.is_empty() && len > 0 {
+            return Err(StatusCode::Eof.into());
+        }
+        Ok(Data { id, data })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let file = self.file(&handle)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(|e| failed(&e))?;
+        file.write_all(&data).await.map_err(|e| failed(&e))?;
+        Ok(status(id))
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        match self.open.remove(&handle) {
+            Some(Open::File(mut file)) => file.flush().await.map_err(|e| failed(&e))?,
+            Some(Open::Dir { .. }) => {}
+            None => return Err(StatusCode::BadMessage.with_message("Not open")),
+        }
+        Ok(status(id))
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> Result<Status, Self::Error> {
+        let (path, target) = self.writable(&filename)?;
+        tokio::fs::remove_file(&target)
+            .await
+            .map_err(|e| failed(&e))?;
+        self.changed("remove", &path);
+        Ok(status(id))
+    }
+
+    async fn mkdir(
+        &mut self,
+        id: u32,
+        path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        let (path, target) = self.writable(&path)?;
+        tokio::fs::create_dir(&target)
+            .await
+            .map_err(|e| failed(&e))?;
+        self.changed("mkdir", &path);
+        Ok(status(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> Result<Status, Self::Error> {
+        let (path, target) = self.writable(&path)?;
+        tokio::fs::remove_dir(&target)
+            .await
+            .map_err(|e| failed(&e))?;
+        self.changed("rmdir", &path);
+        Ok(status(id))
+    }
+
+    async fn rename(
+        &mut self,
+        id: u32,
+        oldpath: String,
+        newpath: String,
+    ) -> Result<Status, Self::Error> {
+        let (from_path, from) = self.writable(&oldpath)?;
+        let (to_path, to) = self.writable(&newpath)?;
+        tokio::fs::rename(&from, &to)
+            .await
+            .map_err(|e| failed(&e))?;
+        self.changed("rename", &format!("{from_path} to {to_path}"));
+        Ok(status(id))
+    }
+
+    /// Times and permissions aren't kept, but clients that copy them after
+    /// an upload would report the upload failed if this did.
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.writable(&path)?;
+        Ok(status(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        _attrs: FileAttributes,
+    ) -> Result<Status, Self::Error> {
+        self.file(&handle)?;
+        Ok(status(id))
+    }
+}
+
+/// One SSH connection.
+struct Client {
+    served: Arc<Served>,
+    peer: SocketAddr,
+    failures: usize,
+    /// Opened sessions, until they ask for the `sftp` subsystem.
+    channels: HashMap<ChannelId, Channel<Msg>>,
+    _connection: Connection,
+}
+
+impl Client {
+    /// Turn a login away, keeping every method on offer so a mistyped
+    /// password can be tried again.
+    fn rejection(&self) -> SshAuth {
+        SshAuth::Reject {
+            proceed_with_methods: Some(methods(&self.served)),
+            partial_success: false,
+        }
+    }
+
+    /// A rejection, or the end of the connection after too many.
+    fn login_failed(&mut self) -> Result<SshAuth, russh::Error> {
+        self.failures += 1;
+        if self.failures >= MAX_LOGIN_FAILURES {
+            return Err(russh::Error::Disconnect);
+        }
+        Ok(self.rejection())
+    }
+}
+
+impl russh::server::Handler for Client {
+    type Error = russh::Error;
+
+    async fn auth_none(&mut self, _user: &str) -> Result<SshAuth, Self::Error> {
+        Ok(if self.served.open_to_anyone() {
+            SshAuth::Accept
+        } else {
+            self.rejection()
+        })
+    }
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<SshAuth, Self::Error> {
+        let accepted = match &self.served.auth {
+            Some(auth) => auth.check_login(user, password),
+            None => self.served.open_to_anyone(),
+        };
+        if accepted {
+            return Ok(SshAuth::Accept);
+        }
+        tracing::warn!("sftp {}: wrong password for {user}", self.peer);
+        self.login_failed()
+    }
+
+    async fn auth_publickey_offered(
+        &mut self,
+        _user: &str,
+        key: &PublicKey,
+    ) -> Result<SshAuth, Self::Error> {
+        if self.served.authorized(key) {
+            return Ok(SshAuth::Accept);
+        }
+        self.login_failed()
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        key: &PublicKey,
+    ) -> Result<SshAuth, Self::Error> {
+        if self.served.authorized(key) {
+            return Ok(SshAuth::Accept);
+        }
+        tracing::warn!("sftp {}: key not authorized for {user}", self.peer);
+        self.login_failed()
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        Ok(true)
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        match self.channels.remove(&channel) {
+            Some(opened) if name == "sftp" => {
+                session.channel_success(channel)?;
+                let sftp = Sftp::new(self.served.clone(), self.peer);
+                russh_sftp::server::run(opened.into_stream(), sftp).await;
+            }
+            _ => session.channel_failure(channel)?,
+        }
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // The client won't send more, and replies are all sent by then
+        session.close(channel)
+    }
+}
+
+/// Serve each connection on its own task.
+async fn serve(listener: TcpListener, config: Arc<Config>, served: Arc<Served>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("sftp accept error: {e}");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let client = Client {
+            served: served.clone(),
+            peer,
+            failures: 0,
+            channels: HashMap::new(),
+            _connection: served.stats.connection(),
+        };
+        let config = config.clone();
+        tokio::spawn(async move {
+            let ended = match russh::server::run_stream(config, stream, client).await {
+                Ok(session) => session.await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = ended {
+                tracing::debug!("sftp {peer}: {e}");
+            }
+        });
+    }
+}
+
+#[tauri::command]
+pub async fn sftp_server_create(
+    options: SftpServerOptions,
+    app: tauri::AppHandle,
+    state: State<'_, SftpState>,
+) -> Result<SftpServerInfo, String> {
+    let root = std::fs::canonicalize(&options.root)
+        .ok()
+        .filter(|r| r.is_dir())
+        .ok_or_else(|| format!("not a folder: {}", options.root))?;
+    let authorized_keys = parse_keys(&options.authorized_keys)?;
+    let key_path = super::paths::app_data_dir(&app)?.join(HOST_KEY_FILENAME);
+    let key = tokio::task::spawn_blocking(move || host_key(&key_path))
+        .await
+        .map_err(|e| format!("host key failed: {e}"))??;
+    let listener = TcpListener::bind((options.host.as_str(), options.port))
+        .await
+        .map_err(|e| format!("bind failed: {e}"))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("local_addr failed: {e}"))?
+        .port();
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let info = SftpServerInfo {
+        id,
+        root: options.root,
+        host: options.host,
+        port,
+        write: options.write,
+        dotfiles: options.dotfiles,
+        auth: options.auth.map(Auth::with_token),
+        authorized_keys: options.authorized_keys,
+        host_key_fingerprint: key.fingerprint(HashAlg::Sha256).to_string(),
+    };
+    let served = Arc::new(Served {
+        root,
+        write: info.write,
+        dotfiles: info.dotfiles,
+        auth: info.auth.clone(),
+        authorized_keys,
+        stats: ServerStats::default(),
+    });
+    let config = Arc::new(config(key, &served));
+    let clients = served.stats.clone();
+    tracing::info!("sftp server {id} on {}:{port} for {}", info.host, info.root);
+    let task = tokio::spawn(serve(listener, config, served));
+    state.servers.lock().unwrap().insert(
+        id,
+        SftpServer {
+            info: info.clone(),
+            task,
+            stats: clients,
+        },
+    );
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn sftp_server_close(id: u32, state: State<'_, SftpState>) -> Result<(), String> {
+    let server = state.servers.lock().unwrap().remove(&id);
+    match server {
+        Some(server) => {
+            server.task.abort();
+            Ok(())
+        }
+        None => Err(format!("SFTP server {id} not found")),
+    }
+}
+
+#[tauri::command]
+pub async fn sftp_server_list(state: State<'_, SftpState>) -> Result<Vec<SftpServerInfo>, String> {
+    let servers = state.servers.lock().unwrap();
+    let mut infos: Vec<_> = servers.values().map(|s| s.info.clone()).collect();
+    infos.sort_by_key(|i| i.id);
+    Ok(infos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::PrivateKeyWithHashAlg;
+    use russh_sftp::client::SftpSession;
+
+    struct TrustAll;
+
+    impl russh::client::Handler for TrustAll {
+        type Error = russh::Error;
+
+        async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    fn served(root: &Path, write: bool, auth: Option<Auth>, keys: Vec<PublicKey>) -> Arc<Served> {
+        Arc::new(Served {
+            root: std::fs::canonicalize(root).unwrap(),
+            write,
+            dotfiles: Dotfiles::Deny,
+            auth,
+            authorized_keys: keys,
+            stats: ServerStats::default(),
+        })
+    }
+
+    /// A logged-out client of `served`.
+    async fn connect(served: Arc<Served>) -> russh::client::Handle<TrustAll> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let config = Arc::new(config(key, &served));
+        tokio::spawn(serve(listener, config, served));
+        let config = Arc::new(russh::client::Config::default());
+        russh::client::connect(config, addr, TrustAll)
+            .await
+            .unwrap()
+    }
+
+    async fn sftp(client: &russh::client::Handle<TrustAll>) -> SftpSession {
+        let channel = client.channel_open_session().await.unwrap();
+        channel.request_subsystem(true, "sftp").await.unwrap();
+        SftpSession::new(channel.into_stream()).await.unwrap()
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let line = key.public_key().to_openssh().unwrap();
+        let lines = vec![
+            "# laptop".to_string(),
+            format!("{line} me@laptop"),
+            String::new(),
+        ];
+        let keys = parse_keys(&lines).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key_data(), key.public_key().key_data());
+        assert!(parse_keys(&["ssh-ed25519 nope".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_host_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("keys").join(HOST_KEY_FILENAME);
+        let made = host_key(&path).unwrap();
+        let loaded = host_key(&path).unwrap();
+        assert_eq!(
+            made.fingerprint(HashAlg::Sha256),
+            loaded.fingerprint(HashAlg::Sha256)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_only_session() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("photos")).unwrap();
+        std::fs::write(tmp.path().join("photos/a.jpg"), "jpeg bytes").unwrap();
+        std::fs::write(tmp.path().join(".env"), "SECRET=1").unwrap();
+        let auth = Auth::Basic {
+            username: "tv".to_string(),
+            password: "pw".to_string(),
+        };
+        let mut client = connect(served(tmp.path(), false, Some(auth), Vec::new())).await;
+        assert!(!client.authenticate_none("tv").await.unwrap().success());
+        assert!(!client
+            .authenticate_password("tv", "nope")
+            .await
+            .unwrap()
+            .success());
+        assert!(client
+            .authenticate_password("tv", "pw")
+            .await
+            .unwrap()
+            .success());
+        let sftp = sftp(&client).await;
+
+        let names: Vec<_> = sftp
+            .read_dir("/")
+            .await
+            .unwrap()
+            .map(|e| e.file_name())
+            .collect();
+        assert_eq!(names, ["photos"]);
+        assert_eq!(sftp.canonicalize("photos/../..").await.unwrap(), "/");
+        assert_eq!(sftp.read("photos/a.jpg").await.unwrap(), b"jpeg bytes");
+        assert_eq!(sftp.metadata("/photos/a.jpg").await.unwrap().len(), 10);
+
+        // Can't climb out, see denied dotfiles or change anything
+        assert!(sftp.read("../../etc/passwd").await.is_err());
+        assert!(sftp.read(".env").await.is_err());
+        assert!(sftp.write("photos/a.jpg", b"overwritten").await.is_err());
+        assert!(sftp.remove_file("photos/a.jpg").await.is_err());
+        assert!(sftp.create_dir("new").await.is_err());
+        assert_eq!(
+            std::fs::read(tmp.path().join("photos/a.jpg")).unwrap(),
+            b"jpeg bytes"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_writable_session() {
+        let tmp = tempfile::tempdir().unwrap();
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let authorized = vec![key.public_key().clone()];
+        let served = served(tmp.path(), true, None, authorized);
+        let stats = served.stats.clone();
+        let mut client = connect(served).await;
+        let other = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let other = PrivateKeyWithHashAlg::new(Arc::new(other), None);
+        assert!(!client
+            .authenticate_publickey("me", other)
+            .await
+            .unwrap()
+            .success());
+        let key = PrivateKeyWithHashAlg::new(Arc::new(key), None);
+        assert!(client
+            .authenticate_publickey("me", key)
+            .await
+            .unwrap()
+            .success());
+        assert_eq!(stats.active(), 1);
+        let sftp = sftp(&client).await;
+
+        sftp.create_dir("scans").await.unwrap();
+        let mut upload = sftp.create("scans/page.pdf").await.unwrap();
+        upload.write_all(b"%PDF").await.unwrap();
+        upload.shutdown().await.unwrap();
+        assert_eq!(
+            std::fs::read(tmp.path().join("scans/page.pdf")).unwrap(),
+            b"%PDF"
+        );
+        sftp.rename("scans/page.pdf", "scans/1.pdf").await.unwrap();
+        assert!(tmp.path().join("scans/1.pdf").exists());
+        assert!(sftp.create_dir(".git").await.is_err());
+        assert!(sftp.remove_dir("scans").await.is_err());
+        sftp.remove_file("scans/1.pdf").await.unwrap();
+        sftp.remove_dir("scans").await.unwrap();
+        assert!(!tmp.path().join("scans").exists());
+    }
+}
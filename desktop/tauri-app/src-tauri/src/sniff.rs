@@ -0,0 +1,117 @@
+//! Telling TLS from plain HTTP by the first bytes a client sends, without
+//! consuming them, so one port can answer both.
+
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::net::TcpStream;
+
+/// Long enough for any HTTP method and the space after it.
+const PEEK_BYTES: usize = 16;
+/// Pause between peeks while the first bytes trickle in.
+const PEEK_RETRY_DELAY: Duration = Duration::from_millis(10);
+const TLS_HANDSHAKE: u8 = 0x16;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tls,
+    Http,
+    /// Anything else, including clients that wait for the server to speak.
+    Other,
+}
+
+/// What `bytes` from the start of a connection look like, or `None` if
+/// more are needed to tell.
+fn detect(bytes: &[u8]) -> Option<Protocol> {
+    match bytes {
+        [] | [TLS_HANDSHAKE] => None,
+        // A handshake record with a TLS 1.x / SSL 3 version
+        [TLS_HANDSHAKE, 3, ..] => Some(Protocol::Tls),
+        _ => {
+            // An HTTP/1 request line, or the HTTP/2 preface `PRI * ...`
+            let method = bytes.iter().take_while(|b| b.is_ascii_uppercase()).count();
+            match bytes.get(method) {
+                Some(b' ') if method > 0 => Some(Protocol::Http),
+                None if method < PEEK_BYTES => None,
+                _ => Some(Protocol::Other),
+            }
+        }
+    }
+}
+
+/// Look at what the client sent first, leaving it for whoever reads the
+/// stream next. `Other` if it sends nothing within `timeout`.
+pub async fn peek(stream: &TcpStream, timeout: Duration) -> Protocol {
+    let look = async {
+        let mut buf = [0; PEEK_BYTES];
+        let mut seen = 0;
+        loop {
+            let n = stream.peek(&mut buf).await.ok()?;
+            if n == 0 {
+                return None;
+            }
+            if let Some(protocol) = detect(&buf[..n]) {
+                return Some(protocol);
+            }
+            // Peeking again returns at once with the same bytes
+            if n == seen {
+                tokio::time::sleep(PEEK_RETRY_DELAY).await;
+            }
+            seen = n;
+        }
+    };
+    tokio::time::timeout(timeout, look)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or(Protocol::Other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b"GET / HTTP/1.1\r\n"), Some(Protocol::Http));
+        assert_eq!(detect(b"PROPFIND /x"), Some(Protocol::Http));
+        assert_eq!(detect(b"PRI * HTTP/2.0"), Some(Protocol::Http));
+        assert_eq!(detect(&[0x16, 3, 1, 2, 0]), Some(Protocol::Tls));
+        assert_eq!(detect(b"SSH-2.0-OpenSSH"), Some(Protocol::Other));
+        assert_eq!(detect(b"get / HTTP/1.1"), Some(Protocol::Other));
+        assert_eq!(detect(&[0x16, 0xfe]), Some(Protocol::Other));
+        assert_eq!(detect(b"OPTI"), None);
+        assert_eq!(detect(&[0x16]), None);
+        assert_eq!(detect(b"AAAAAAAAAAAAAAAA"), Some(Protocol::Other));
+    }
+
+    #[tokio::test]
+    async fn test_peek() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+        // The method arrives in two pieces
+        client.write_all(b"HE").await.unwrap();
+        let rest = async {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            client.write_all(b"AD / HTTP/1.1\r\n").await.unwrap();
+        };
+        let (protocol, ()) = tokio::join!(peek(&server, Duration::from_secs(5)), rest);
+        assert_eq!(protocol, Protocol::Http);
+        // Nothing was consumed
+        let mut start = [0; 4];
+        server.read_exact(&mut start).await.unwrap();
+        assert_eq!(&start, b"HEAD");
+
+        let _quiet = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            peek(&server, Duration::from_millis(20)).await,
+            Protocol::Other
+        );
+    }
+}
@@ -1,32 +1,246 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde::Serialize;
-use tauri::ipc::{Channel, InvokeBody, InvokeResponseBody, Request, Response};
-use tauri::State;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, WriteHalf};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tauri::ipc::{Channel, InvokeBody, InvokeResponseBody, Request};
+use tauri::{Manager, State, Webview};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex, Notify};
 use tokio::task::JoinHandle;
 
+use super::happy_eyeballs;
+use super::ip_filter::{IpFilter, IpFilterOptions};
+use super::sniff::{self, Protocol};
+
+/// How often `spawn_sweeper` looks for stale sockets.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// Consecutive accept errors before a listener is considered dead.
+const MAX_ACCEPT_FAILURES: u32 = 32;
+/// Pause after a failed accept, e.g. when out of file descriptors
+/// (EMFILE), which fails every accept until some connection closes.
+pub const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(50);
+/// Events kept per server while its webview reloads; older ones are
+/// dropped past either limit.
+const MAX_BUFFERED_EVENTS: usize = 4096;
+const MAX_BUFFERED_BYTES: usize = 16 * 1024 * 1024;
+/// Default for both `readBufferBytes` and `maxFrameBytes`.
+const DEFAULT_CHUNK_BYTES: usize = 64 * 1024;
+/// How long `sniff` waits for a client's first bytes.
+const SNIFF_TIMEOUT: Duration = Duration::from_secs(1);
+const MIN_CHUNK_BYTES: usize = 512;
+const MAX_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+
 // -- State --
 
 pub struct TcpState {
     servers: Arc<Mutex<HashMap<u32, ServerHandle>>>,
     sockets: Arc<Mutex<HashMap<u32, SocketHandle>>>,
     next_id: Arc<AtomicU32>,
+    /// Signalled whenever a server or socket is added or removed.
+    changed: Arc<Notify>,
+    /// Peer address of every accepted connection.
+    accepts: broadcast::Sender<IpAddr>,
+    gc: std::sync::Mutex<GcStats>,
+}
+
+/// What `TcpState::sweep` has freed so far.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GcStats {
+    pub sweeps: u64,
+    /// Sockets freed in all sweeps.
+    pub reaped: u64,
+    /// Sockets freed by the latest sweep that found any.
+    pub last_reaped: Vec<u32>,
+    /// Unix seconds of the latest sweep.
+    pub last_sweep: Option<u64>,
 }
 
 struct ServerHandle {
     accept_task: JoinHandle<()>,
     local_addr: SocketAddr,
+    /// Kept so the server can be re-bound after a network change.
+    events: Arc<EventSink>,
+    ip_filter: Option<IpFilter>,
+    frames: FrameSizes,
+    /// Peek at each connection and report its protocol on `Accept`.
+    sniff: bool,
+    /// Label of the webview that created the server.
+    webview: String,
+}
+
+/// How much each socket reads at once, and the most data put in one
+/// frame on the channel.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameSizes {
+    pub read_buffer_bytes: usize,
+    /// Never more than `read_buffer_bytes`.
+    pub max_frame_bytes: usize,
+}
+
+impl FrameSizes {
+    fn new(
+        read_buffer_bytes: Option<usize>,
+        max_frame_bytes: Option<usize>,
+    ) -> Result<Self, String> {
+        let check = |name, bytes: usize| {
+            if (MIN_CHUNK_BYTES..=MAX_CHUNK_BYTES).contains(&bytes) {
+                Ok(bytes)
+            } else {
+                Err(format!(
+                    "{name} must be between {MIN_CHUNK_BYTES} and {MAX_CHUNK_BYTES}, got {bytes}"
+                ))
+            }
+        };
+        let read_buffer_bytes = check(
+            "readBufferBytes",
+            read_buffer_bytes.unwrap_or(DEFAULT_CHUNK_BYTES),
+        )?;
+        let max_frame_bytes = check(
+            "maxFrameBytes",
+            max_frame_bytes.unwrap_or(DEFAULT_CHUNK_BYTES),
+        )?;
+        Ok(Self {
+            read_buffer_bytes,
+            max_frame_bytes: max_frame_bytes.min(read_buffer_bytes),
+        })
+    }
 }
 
 struct SocketHandle {
     writer: Arc<Mutex<WriteHalf<TcpStream>>>,
     recv_task: JoinHandle<()>,
+    server_id: u32,
+    peer_addr: SocketAddr,
+}
+
+/// A buffered event, and the socket it announced if it was an accept.
+struct Buffered {
+    body: InvokeResponseBody,
+    accepted: Option<u32>,
+}
+
+#[derive(Default)]
+struct Buffer {
+    events: VecDeque<Buffered>,
+    bytes: usize,
+    dropped: usize,
+}
+
+/// Where a server's events go: its channel, or while the webview that
+/// owns the channel is reloading, a buffer that `tcp_server_reattach`
+/// replays into the new page's channel.
+struct EventSink {
+    channel: std::sync::Mutex<Channel<InvokeResponseBody>>,
+    /// `Some` while detached.
+    buffer: std::sync::Mutex<Option<Buffer>>,
+}
+
+fn body_len(body: &InvokeResponseBody) -> usize {
+    match body {
+        InvokeResponseBody::Json(json) => json.len(),
+        InvokeResponseBody::Raw(raw) => raw.len(),
+    }
+}
+
+impl Buffer {
+    fn push(&mut self, body: InvokeResponseBody, accepted: Option<u32>) {
+        self.bytes += body_len(&body);
+        self.events.push_back(Buffered { body, accepted });
+        while self.events.len() > MAX_BUFFERED_EVENTS || self.bytes > MAX_BUFFERED_BYTES {
+            let Some(oldest) = self.events.pop_front() else {
+                break;
+            };
+            self.bytes -= body_len(&oldest.body);
+            self.dropped += 1;
+        }
+    }
+}
+
+impl EventSink {
+    fn new(channel: Channel<InvokeResponseBody>) -> Self {
+        Self {
+            channel: std::sync::Mutex::new(channel),
+            buffer: std::sync::Mutex::new(None),
+        }
+    }
+
+    fn send(&self, body: InvokeResponseBody, accepted: Option<u32>) {
+        match self.buffer.lock().unwrap().as_mut() {
+            Some(buffer) => buffer.push(body, accepted),
+            None => {
+                let _ = self.channel.lock().unwrap().send(body);
+            }
+        }
+    }
+
+    /// Buffer events until `reattach`.
+    fn detach(&self) {
+        self.buffer
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Buffer::default);
+    }
+
+    /// Send events to `channel` from now on. `open` sockets the buffer
+    /// doesn't announce are announced first, then the buffer is replayed.
+    fn reattach(
+        &self,
+        server_id: u32,
+        channel: Channel<InvokeResponseBody>,
+        open: &[(u32, SocketAddr)],
+    ) -> ReattachInfo {
+        let mut buffer = self.buffer.lock().unwrap();
+        let kept = buffer.take().unwrap_or_default();
+        let announced: HashSet<u32> = kept.events.iter().filter_map(|e| e.accepted).collect();
+        let mut sockets = 0;
+        for &(socket_id, peer_addr) in open {
+            if announced.contains(&socket_id) {
+                continue;
+            }
+            let event = ControlEvent::Accept {
+                server_id,
+                socket_id,
+                remote_address: peer_addr.ip().to_string(),
+                remote_port: peer_addr.port(),
+                protocol: None,
+            };
+            let json = serde_json::to_string(&event).unwrap_or_default();
+            let _ = channel.send(InvokeResponseBody::Json(json));
+            sockets += 1;
+        }
+        let info = ReattachInfo {
+            sockets,
+            replayed: kept.events.len(),
+            dropped: kept.dropped,
+        };
+        for event in kept.events {
+            let _ = channel.send(event.body);
+        }
+        // Still holding the buffer lock, so nothing is sent out of order
+        *self.channel.lock().unwrap() = channel;
+        info
+    }
+}
+
+/// What `tcp_server_reattach` sent to the new channel.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReattachInfo {
+    /// Sockets announced again, having been accepted before the reload.
+    pub sockets: usize,
+    /// Events kept during the reload.
+    pub replayed: usize,
+    /// Events lost because too many were kept.
+    pub dropped: usize,
 }
 
 impl TcpState {
@@ -35,12 +249,305 @@ impl TcpState {
             servers: Arc::new(Mutex::new(HashMap::new())),
             sockets: Arc::new(Mutex::new(HashMap::new())),
             next_id: Arc::new(AtomicU32::new(1)),
+            changed: Arc::new(Notify::new()),
+            accepts: broadcast::channel(64).0,
+            gc: std::sync::Mutex::new(GcStats::default()),
         }
     }
 
     fn next_id(&self) -> u32 {
         self.next_id.fetch_add(1, Ordering::Relaxed)
     }
+
+    /// Notifier for changes to the server and socket counts.
+    pub fn changes(&self) -> Arc<Notify> {
+        self.changed.clone()
+    }
+
+    /// Receive the peer address of each accepted connection.
+    pub fn subscribe_accepts(&self) -> broadcast::Receiver<IpAddr> {
+        self.accepts.subscribe()
+    }
+
+    fn spawn_accept_loop(
+        &self,
+        listener: TcpListener,
+        server_id: u32,
+        events: Arc<EventSink>,
+        ip_filter: Option<IpFilter>,
+        frames: FrameSizes,
+        sniff: bool,
+    ) -> JoinHandle<()> {
+        let sockets_for_task = Arc::new(Mutex::new(Vec::<u32>::new()));
+        let state_sockets = self.sockets.clone();
+        let next_id = self.next_id.clone();
+        let changed = self.changed.clone();
+        let accepts = self.accepts.clone();
+
+        tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let (stream, peer_addr) = match listener.accept().await {
+                    Ok(conn) => {
+                        failures = 0;
+                        conn
+                    }
+                    Err(e) => {
+                        tracing::warn!("accept error: {e}");
+                        failures += 1;
+                        if failures >= MAX_ACCEPT_FAILURES {
+                            // Leave it to `revalidate` to re-bind
+                            tracing::error!("server {server_id} stopped accepting");
+                            break;
+                        }
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                if let Some(Err(reason)) = ip_filter.as_ref().map(|f| f.check(peer_addr.ip())) {
+                    // Dropping the stream closes it
+                    send_control(
+                        &events,
+                        &ControlEvent::Rejected {
+                            server_id,
+                            remote_address: peer_addr.ip().to_string(),
+                            reason: reason.to_string(),
+                        },
+                    );
+                    continue;
+                }
+
+                let events = events.clone();
+                let state_sockets = state_sockets.clone();
+                let next_id = next_id.clone();
+                let changed = changed.clone();
+                let accepts = accepts.clone();
+                let sockets_for_task = sockets_for_task.clone();
+                // Its own task, so a client slow to send its first bytes
+                // doesn't hold up the others
+                tokio::spawn(async move {
+                    let protocol = if sniff {
+                        Some(sniff::peek(&stream, SNIFF_TIMEOUT).await)
+                    } else {
+                        None
+                    };
+                    let socket_id = next_id.fetch_add(1, Ordering::Relaxed);
+                    let _ = accepts.send(peer_addr.ip());
+                    let (reader, writer) = tokio::io::split(stream);
+                    let writer = Arc::new(Mutex::new(writer));
+
+                    // Send accept event
+                    send_control(
+                        &events,
+                        &ControlEvent::Accept {
+                            server_id,
+                            socket_id,
+                            remote_address: peer_addr.ip().to_string(),
+                            remote_port: peer_addr.port(),
+                            protocol,
+                        },
+                    );
+
+                    let recv_task = spawn_recv(
+                        reader,
+                        socket_id,
+                        events,
+                        state_sockets.clone(),
+                        changed.clone(),
+                        frames,
+                    );
+
+                    // Store socket handle
+                    let handle = SocketHandle {
+                        writer,
+                        recv_task,
+                        server_id,
+                        peer_addr,
+                    };
+                    state_sockets.lock().await.insert(socket_id, handle);
+                    changed.notify_one();
+
+                    // Track socket IDs for cleanup on server close
+                    sockets_for_task.lock().await.push(socket_id);
+                });
+            }
+        })
+    }
+
+    /// Re-bind servers whose listener died or whose address went away,
+    /// e.g. after sleep or a network change. Returns how many were re-bound.
+    pub async fn revalidate(&self) -> usize {
+        let mut servers = self.servers.lock().await;
+        let mut rebound = 0;
+        for (&server_id, server) in servers.iter_mut() {
+            if !server.accept_task.is_finished() && address_available(server.local_addr.ip()) {
+                continue;
+            }
+            server.accept_task.abort();
+            match TcpListener::bind(server.local_addr).await {
+                Ok(listener) => {
+                    tracing::info!("re-bound server {server_id} on {}", server.local_addr);
+                    server.accept_task = self.spawn_accept_loop(
+                        listener,
+                        server_id,
+                        server.events.clone(),
+                        server.ip_filter.clone(),
+                        server.frames,
+                        server.sniff,
+                    );
+                    rebound += 1;
+                }
+                Err(e) => {
+                    // Stays dead; the next network change retries
+                    tracing::warn!("re-bind {} failed: {e}", server.local_addr);
+                    send_control(
+                        &server.events,
+                        &ControlEvent::ListenError {
+                            server_id,
+                            error: format!("bind failed: {e}"),
+                        },
+                    );
+                }
+            }
+        }
+        rebound
+    }
+
+    /// Free sockets whose recv task has ended but that are still in the
+    /// table, as happens when the task finishes before its handle is
+    /// stored. Returns how many were freed.
+    pub async fn sweep(&self) -> usize {
+        let mut reaped = Vec::new();
+        self.sockets.lock().await.retain(|&socket_id, socket| {
+            let stale = socket.recv_task.is_finished();
+            if stale {
+                reaped.push(socket_id);
+            }
+            !stale
+        });
+        reaped.sort_unstable();
+        if !reaped.is_empty() {
+            tracing::warn!("freed {} stale sockets: {reaped:?}", reaped.len());
+            self.changed.notify_one();
+        }
+        let mut gc = self.gc.lock().unwrap();
+        gc.sweeps += 1;
+        gc.reaped += reaped.len() as u64;
+        gc.last_sweep = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs());
+        let count = reaped.len();
+        if count > 0 {
+            gc.last_reaped = reaped;
+        }
+        count
+    }
+
+    /// Keep events for servers created by `webview` until they're
+    /// reattached, as its page is being replaced. Spawn the returned future.
+    pub fn detach_webview(&self, webview: &str) -> impl Future<Output = ()> + Send + 'static {
+        let servers = self.servers.clone();
+        let webview = webview.to_string();
+        async move {
+            for server in servers.lock().await.values() {
+                if server.webview == webview {
+                    server.events.detach();
+                }
+            }
+        }
+    }
+
+    /// Write all of `data` to a socket, returning how many bytes that was.
+    async fn write(&self, socket_id: u32, data: &[u8]) -> Result<usize, String> {
+        // Get the writer Arc without holding the sockets lock during the write
+        let writer = {
+            let sockets = self.sockets.lock().await;
+            sockets
+                .get(&socket_id)
+                .ok_or_else(|| format!("socket {socket_id} not found"))?
+                .writer
+                .clone()
+        };
+
+        let mut w = writer.lock().await;
+        w.write_all(data)
+            .await
+            .map_err(|e| format!("write failed: {e}"))?;
+        w.flush().await.map_err(|e| format!("flush failed: {e}"))?;
+        Ok(data.len())
+    }
+
+    /// Copy `length` bytes of a file from `start` to a socket, defaulting to
+    /// the rest of the file. Returns how many bytes that was.
+    async fn send_file(&self, socket_id: u32, path: &str, range: FileRange) -> Result<u64, String> {
+        let writer = {
+            let sockets = self.sockets.lock().await;
+            sockets
+                .get(&socket_id)
+                .ok_or_else(|| format!("socket {socket_id} not found"))?
+                .writer
+                .clone()
+        };
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| format!("open failed: {e}"))?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| format!("stat failed: {e}"))?
+            .len();
+        let length = range.length.unwrap_or(size.saturating_sub(range.start));
+        if range.start.checked_add(length).is_none_or(|end| end > size) {
+            return Err(format!(
+                "range {}+{length} is past the end of {path} ({size} bytes)",
+                range.start
+            ));
+        }
+        file.seek(SeekFrom::Start(range.start))
+            .await
+            .map_err(|e| format!("seek failed: {e}"))?;
+
+        let mut w = writer.lock().await;
+        let sent = tokio::io::copy(&mut file.take(length), &mut *w)
+            .await
+            .map_err(|e| format!("write failed: {e}"))?;
+        w.flush().await.map_err(|e| format!("flush failed: {e}"))?;
+        if sent < length {
+            return Err(format!(
+                "{path} shrank while sending, {sent} of {length} bytes sent"
+            ));
+        }
+        Ok(sent)
+    }
+
+    /// Stop every server and close every socket.
+    pub async fn close_all(&self) {
+        for (_, server) in self.servers.lock().await.drain() {
+            server.accept_task.abort();
+        }
+        for (_, socket) in self.sockets.lock().await.drain() {
+            socket.recv_task.abort();
+        }
+        self.changed.notify_one();
+    }
+
+    /// Number of listening servers and open sockets.
+    pub async fn counts(&self) -> (usize, usize) {
+        let servers = self.servers.lock().await.len();
+        let sockets = self.sockets.lock().await.len();
+        (servers, sockets)
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc.lock().unwrap().clone()
+    }
+}
+
+/// Whether `ip` is still assigned to this machine. Wildcard and loopback
+/// binds always are.
+fn address_available(ip: IpAddr) -> bool {
+    ip.is_unspecified() || ip.is_loopback() || std::net::TcpListener::bind((ip, 0)).is_ok()
 }
 
 // -- Control events sent as JSON through the channel --
@@ -68,6 +575,29 @@ enum ControlEvent {
         remote_address: String,
         #[serde(rename = "remotePort")]
         remote_port: u16,
+        /// What the client's first bytes looked like, for servers created
+        /// with `sniff`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        protocol: Option<Protocol>,
+    },
+    /// A connection turned away by the server's `IpFilter`.
+    Rejected {
+        #[serde(rename = "serverId")]
+        server_id: u32,
+        #[serde(rename = "remoteAddress")]
+        remote_address: String,
+        reason: String,
+    },
+    /// An outbound connection from `tcp_connect` was established.
+    Connect {
+        #[serde(rename = "socketId")]
+        socket_id: u32,
+        #[serde(rename = "remoteAddress")]
+        remote_address: String,
+        #[serde(rename = "remotePort")]
+        remote_port: u16,
+        /// 4 or 6, whichever won the race.
+        family: u8,
     },
     Close {
         #[serde(rename = "socketId")]
@@ -82,27 +612,107 @@ enum ControlEvent {
     },
 }
 
-fn send_control(channel: &Channel<InvokeResponseBody>, event: &ControlEvent) {
+/// Forward what arrives on a socket until it closes, then forget it.
+fn spawn_recv(
+    mut reader: ReadHalf<TcpStream>,
+    socket_id: u32,
+    events: Arc<EventSink>,
+    sockets: Arc<Mutex<HashMap<u32, SocketHandle>>>,
+    changed: Arc<Notify>,
+    frames: FrameSizes,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; frames.read_buffer_bytes];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) => {
+                    // EOF — clean close
+                    send_control(
+                        &events,
+                        &ControlEvent::Close {
+                            socket_id,
+                            had_error: false,
+                        },
+                    );
+                    break;
+                }
+                Ok(n) => {
+                    for chunk in buf[..n].chunks(frames.max_frame_bytes) {
+                        send_data(&events, socket_id, chunk);
+                    }
+                }
+                Err(e) => {
+                    send_control(
+                        &events,
+                        &ControlEvent::Error {
+                            socket_id,
+                            message: e.to_string(),
+                        },
+                    );
+                    send_control(
+                        &events,
+                        &ControlEvent::Close {
+                            socket_id,
+                            had_error: true,
+                        },
+                    );
+                    break;
+                }
+            }
+        }
+        // Clean up socket from state
+        sockets.lock().await.remove(&socket_id);
+        changed.notify_one();
+    })
+}
+
+/// Run `TcpState::sweep` every `SWEEP_INTERVAL` for as long as the app runs.
+pub fn spawn_sweeper(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(SWEEP_INTERVAL).await;
+            app.state::<TcpState>().sweep().await;
+        }
+    });
+}
+
+fn send_control(events: &EventSink, event: &ControlEvent) {
     let json = serde_json::to_string(event).unwrap_or_default();
-    let _ = channel.send(InvokeResponseBody::Json(json));
+    let accepted = match event {
+        ControlEvent::Accept { socket_id, .. } => Some(*socket_id),
+        _ => None,
+    };
+    events.send(InvokeResponseBody::Json(json), accepted);
 }
 
-fn send_data(channel: &Channel<InvokeResponseBody>, socket_id: u32, data: &[u8]) {
+fn send_data(events: &EventSink, socket_id: u32, data: &[u8]) {
     let mut frame = Vec::with_capacity(4 + data.len());
     frame.extend_from_slice(&socket_id.to_be_bytes());
     frame.extend_from_slice(data);
-    let _ = channel.send(InvokeResponseBody::Raw(frame));
+    events.send(InvokeResponseBody::Raw(frame), None);
 }
 
 // -- Commands --
 
+/// `read_buffer_bytes` and `max_frame_bytes` default to 64 KiB. With
+/// `sniff`, each `accept` event says whether the client opened with TLS or
+/// HTTP, at the cost of waiting up to a second for it to send something.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn tcp_server_create(
     port: u16,
     host: String,
     channel: Channel<InvokeResponseBody>,
+    ip_filter: Option<IpFilterOptions>,
+    read_buffer_bytes: Option<usize>,
+    max_frame_bytes: Option<usize>,
+    sniff: Option<bool>,
+    webview: Webview,
     state: State<'_, TcpState>,
 ) -> Result<u32, String> {
+    let ip_filter = ip_filter.map(IpFilter::new).transpose()?;
+    let frames = FrameSizes::new(read_buffer_bytes, max_frame_bytes)?;
+    let sniff = sniff.unwrap_or(false);
     let addr: SocketAddr = format!("{host}:{port}")
         .parse()
         .map_err(|e| format!("invalid address: {e}"))?;
@@ -117,117 +727,129 @@ pub async fn tcp_server_create(
 
     let server_id = state.next_id();
 
+    let events = Arc::new(EventSink::new(channel));
+
     // Send listening event
     send_control(
-        &channel,
+        &events,
         &ControlEvent::Listening {
             server_id,
             port: local_addr.port(),
         },
     );
 
-    // Clone state references for the accept loop
-    let sockets = Arc::new(Mutex::new(Vec::<u32>::new()));
-    let sockets_for_task = sockets.clone();
-
-    let channel = Arc::new(channel);
-    let state_sockets = state.sockets.clone();
-    let next_id = state.next_id.clone();
-
-    let accept_task = tokio::spawn(async move {
-        loop {
-            let (stream, peer_addr) = match listener.accept().await {
-                Ok(conn) => conn,
-                Err(e) => {
-                    eprintln!("tcp accept error: {e}");
-                    continue;
-                }
-            };
-
-            let socket_id = next_id.fetch_add(1, Ordering::Relaxed);
-            let (reader, writer) = tokio::io::split(stream);
-            let writer = Arc::new(Mutex::new(writer));
-
-            // Send accept event
-            send_control(
-                &channel,
-                &ControlEvent::Accept {
-                    server_id,
-                    socket_id,
-                    remote_address: peer_addr.ip().to_string(),
-                    remote_port: peer_addr.port(),
-                },
-            );
-
-            // Spawn recv task
-            let channel_for_recv = channel.clone();
-            let state_sockets_for_recv = state_sockets.clone();
-            let recv_task = tokio::spawn(async move {
-                let mut reader = reader;
-                let mut buf = vec![0u8; 65536];
-                loop {
-                    match reader.read(&mut buf).await {
-                        Ok(0) => {
-                            // EOF — clean close
-                            send_control(
-                                &channel_for_recv,
-                                &ControlEvent::Close {
-                                    socket_id,
-                                    had_error: false,
-                                },
-                            );
-                            break;
-                        }
-                        Ok(n) => {
-                            send_data(&channel_for_recv, socket_id, &buf[..n]);
-                        }
-                        Err(e) => {
-                            send_control(
-                                &channel_for_recv,
-                                &ControlEvent::Error {
-                                    socket_id,
-                                    message: e.to_string(),
-                                },
-                            );
-                            send_control(
-                                &channel_for_recv,
-                                &ControlEvent::Close {
-                                    socket_id,
-                                    had_error: true,
-                                },
-                            );
-                            break;
-                        }
-                    }
-                }
-                // Clean up socket from state
-                state_sockets_for_recv.lock().await.remove(&socket_id);
-            });
-
-            // Store socket handle
-            let handle = SocketHandle { writer, recv_task };
-            state_sockets.lock().await.insert(socket_id, handle);
-
-            // Track socket IDs for cleanup on server close
-            sockets_for_task.lock().await.push(socket_id);
-        }
-    });
+    let accept_task = state.spawn_accept_loop(
+        listener,
+        server_id,
+        events.clone(),
+        ip_filter.clone(),
+        frames,
+        sniff,
+    );
 
     // Store server handle
     let handle = ServerHandle {
         accept_task,
         local_addr,
+        events,
+        ip_filter,
+        frames,
+        sniff,
+        webview: webview.label().to_string(),
     };
     state.servers.lock().await.insert(server_id, handle);
+    state.changed.notify_one();
 
     Ok(server_id)
 }
 
+/// Connect to `host`, racing its IPv6 and IPv4 addresses (see
+/// `happy_eyeballs`). The socket's events arrive on `channel`, starting
+/// with `connect`. `attempt_delay_ms` defaults to 250.
 #[tauri::command]
-pub async fn tcp_send(
-    request: Request<'_>,
+pub async fn tcp_connect(
+    host: String,
+    port: u16,
+    channel: Channel<InvokeResponseBody>,
+    attempt_delay_ms: Option<u64>,
+    state: State<'_, TcpState>,
+) -> Result<u32, String> {
+    let delay = happy_eyeballs::attempt_delay(attempt_delay_ms);
+    let stream = happy_eyeballs::connect(&host, port, delay)
+        .await
+        .map_err(|e| format!("connect to {host}:{port} failed: {e}"))?;
+    let peer_addr = stream
+        .peer_addr()
+        .map_err(|e| format!("peer_addr failed: {e}"))?;
+    let socket_id = state.next_id();
+    let events = Arc::new(EventSink::new(channel));
+    send_control(
+        &events,
+        &ControlEvent::Connect {
+            socket_id,
+            remote_address: peer_addr.ip().to_string(),
+            remote_port: peer_addr.port(),
+            family: if peer_addr.is_ipv4() { 4 } else { 6 },
+        },
+    );
+
+    let (reader, writer) = tokio::io::split(stream);
+    let recv_task = spawn_recv(
+        reader,
+        socket_id,
+        events,
+        state.sockets.clone(),
+        state.changed.clone(),
+        FrameSizes::new(None, None)?,
+    );
+    let handle = SocketHandle {
+        writer: Arc::new(Mutex::new(writer)),
+        recv_task,
+        // Outbound sockets belong to no server
+        server_id: 0,
+        peer_addr,
+    };
+    state.sockets.lock().await.insert(socket_id, handle);
+    state.changed.notify_one();
+    Ok(socket_id)
+}
+
+/// Send a server's events to `channel` from now on, after its webview
+/// reloaded. Sockets accepted before the reload are announced again, then
+/// the events kept since are replayed.
+#[tauri::command]
+pub async fn tcp_server_reattach(
+    server_id: u32,
+    channel: Channel<InvokeResponseBody>,
+    webview: Webview,
     state: State<'_, TcpState>,
-) -> Result<Response, String> {
+) -> Result<ReattachInfo, String> {
+    let mut servers = state.servers.lock().await;
+    let server = servers
+        .get_mut(&server_id)
+        .ok_or_else(|| format!("server {server_id} not found"))?;
+    let mut open: Vec<_> = state
+        .sockets
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, s)| s.server_id == server_id)
+        .map(|(&id, s)| (id, s.peer_addr))
+        .collect();
+    open.sort_unstable();
+    server.webview = webview.label().to_string();
+    let info = server.events.reattach(server_id, channel, &open);
+    tracing::info!(
+        "server {server_id} reattached: {} sockets, {} events replayed, {} dropped",
+        info.sockets,
+        info.replayed,
+        info.dropped
+    );
+    Ok(info)
+}
+
+#[tauri::command]
+pub async fn tcp_send(request: Request<'_>, state: State<'_, TcpState>) -> Result<usize, String> {
     let socket_id: u32 = request
         .headers()
         .get("x-socket-id")
@@ -241,26 +863,48 @@ pub async fn tcp_send(
         InvokeBody::Raw(bytes) => bytes,
         InvokeBody::Json(_) => return Err("expected raw binary body".into()),
     };
+    state.write(socket_id, data).await
+}
 
-    // Get the writer Arc without holding the sockets lock during the write
-    let writer = {
-        let sockets = state.sockets.lock().await;
-        sockets
-            .get(&socket_id)
-            .ok_or_else(|| format!("socket {socket_id} not found"))?
-            .writer
-            .clone()
-    };
+/// `tcp_send` for callers that can't set headers or send a raw body.
+#[tauri::command]
+pub async fn tcp_send_json(
+    socket_id: u32,
+    data_base64: String,
+    state: State<'_, TcpState>,
+) -> Result<usize, String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data_base64)
+        .map_err(|e| format!("invalid base64: {e}"))?;
+    state.write(socket_id, &data).await
+}
 
-    let mut w = writer.lock().await;
-    w.write_all(data)
-        .await
-        .map_err(|e| format!("write failed: {e}"))?;
-    w.flush()
+/// Part of a file for `tcp_send_file`.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FileRange {
+    pub start: u64,
+    /// The rest of the file when absent.
+    pub length: Option<u64>,
+}
+
+/// Stream part of a file to a socket without it passing through the
+/// webview. Returns the bytes sent.
+#[tauri::command]
+pub async fn tcp_send_file(
+    socket_id: u32,
+    path: String,
+    range: Option<FileRange>,
+    state: State<'_, TcpState>,
+) -> Result<u64, String> {
+    state
+        .send_file(socket_id, &path, range.unwrap_or_default())
         .await
-        .map_err(|e| format!("flush failed: {e}"))?;
+}
 
-    Ok(Response::new(vec![]))
+#[tauri::command]
+pub async fn tcp_gc_stats(state: State<'_, TcpState>) -> Result<GcStats, String> {
+    Ok(state.gc_stats())
 }
 
 #[tauri::command]
@@ -269,6 +913,7 @@ pub async fn tcp_close(socket_id: u32, state: State<'_, TcpState>) -> Result<(),
     if let Some(h) = handle {
         h.recv_task.abort();
         // Dropping the writer closes the write half
+        state.changed.notify_one();
     }
     Ok(())
 }
@@ -278,6 +923,7 @@ pub async fn tcp_server_close(server_id: u32, state: State<'_, TcpState>) -> Res
     let handle = state.servers.lock().await.remove(&server_id);
     if let Some(h) = handle {
         h.accept_task.abort();
+        state.changed.notify_one();
     }
     Ok(())
 }
@@ -297,6 +943,36 @@ pub async fn tcp_server_address(
     }))
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TcpServerInfo {
+    pub address: String,
+    pub port: u16,
+    #[serde(flatten)]
+    pub frames: FrameSizes,
+    pub ip_filter: Option<IpFilterOptions>,
+    pub sniff: bool,
+}
+
+/// A server's address and the settings in effect.
+#[tauri::command]
+pub async fn tcp_server_info(
+    server_id: u32,
+    state: State<'_, TcpState>,
+) -> Result<TcpServerInfo, String> {
+    let servers = state.servers.lock().await;
+    let server = servers
+        .get(&server_id)
+        .ok_or_else(|| format!("server {server_id} not found"))?;
+    Ok(TcpServerInfo {
+        address: server.local_addr.ip().to_string(),
+        port: server.local_addr.port(),
+        frames: server.frames,
+        ip_filter: server.ip_filter.as_ref().map(|f| f.options.clone()),
+        sniff: server.sniff,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -308,11 +984,183 @@ mod tests {
             socket_id: 42,
             remote_address: "127.0.0.1".to_string(),
             remote_port: 54321,
+            protocol: None,
         };
         let json = serde_json::to_string(&event).unwrap();
         assert!(json.contains("\"type\":\"accept\""));
         assert!(json.contains("\"serverId\":1"));
         assert!(json.contains("\"socketId\":42"));
+        assert!(!json.contains("protocol"));
+
+        let event = ControlEvent::Accept {
+            server_id: 1,
+            socket_id: 43,
+            remote_address: "127.0.0.1".to_string(),
+            remote_port: 54322,
+            protocol: Some(Protocol::Tls),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("\"protocol\":\"tls\""));
+
+        let event = ControlEvent::Rejected {
+            server_id: 1,
+            remote_address: "203.0.113.9".to_string(),
+            reason: "denied".to_string(),
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("\"type\":\"rejected\""));
+        assert!(json.contains("\"remoteAddress\":\"203.0.113.9\""));
+    }
+
+    #[test]
+    fn test_address_available() {
+        assert!(address_available("0.0.0.0".parse().unwrap()));
+        assert!(address_available("127.0.0.1".parse().unwrap()));
+        // TEST-NET-1, never assigned to a local interface
+        assert!(!address_available("192.0.2.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let state = TcpState::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (_, writer) = tokio::io::split(stream);
+        let handle = SocketHandle {
+            writer: Arc::new(Mutex::new(writer)),
+            recv_task: tokio::spawn(async {}),
+            server_id: 1,
+            peer_addr: client.local_addr().unwrap(),
+        };
+        state.sockets.lock().await.insert(7, handle);
+
+        assert_eq!(state.write(7, b"hello").await, Ok(5));
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(
+            state.write(8, b"x").await,
+            Err("socket 8 not found".to_string())
+        );
+    }
+
+    #[test]
+    fn test_buffer_limits() {
+        let mut buffer = Buffer::default();
+        for i in 0..=MAX_BUFFERED_EVENTS {
+            let socket = u32::try_from(i).unwrap();
+            buffer.push(InvokeResponseBody::Raw(vec![0; 4]), Some(socket));
+        }
+        assert_eq!(buffer.events.len(), MAX_BUFFERED_EVENTS);
+        assert_eq!(buffer.dropped, 1);
+        assert_eq!(buffer.bytes, MAX_BUFFERED_EVENTS * 4);
+        assert_eq!(buffer.events[0].accepted, Some(1));
+
+        buffer.push(InvokeResponseBody::Raw(vec![0; MAX_BUFFERED_BYTES]), None);
+        assert_eq!(buffer.events.len(), 1);
+        assert_eq!(buffer.bytes, MAX_BUFFERED_BYTES);
+        assert_eq!(buffer.dropped, MAX_BUFFERED_EVENTS + 1);
+    }
+
+    #[test]
+    fn test_frame_sizes() {
+        let defaults = FrameSizes::new(None, None).unwrap();
+        assert_eq!(defaults.read_buffer_bytes, 64 * 1024);
+        assert_eq!(defaults.max_frame_bytes, 64 * 1024);
+        let small = FrameSizes::new(Some(4096), None).unwrap();
+        assert_eq!(small.max_frame_bytes, 4096);
+        let split = FrameSizes::new(Some(1024 * 1024), Some(16 * 1024)).unwrap();
+        assert_eq!(split.read_buffer_bytes, 1024 * 1024);
+        assert_eq!(split.max_frame_bytes, 16 * 1024);
+        assert!(FrameSizes::new(Some(0), None).is_err());
+        assert!(FrameSizes::new(None, Some(64 * 1024 * 1024)).is_err());
+
+        let json = serde_json::to_string(&TcpServerInfo {
+            address: "127.0.0.1".to_string(),
+            port: 8080,
+            frames: split,
+            ip_filter: None,
+            sniff: false,
+        })
+        .unwrap();
+        assert!(json.contains("\"readBufferBytes\":1048576"));
+        assert!(json.contains("\"maxFrameBytes\":16384"));
+    }
+
+    #[tokio::test]
+    async fn test_send_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("data.bin");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let state = TcpState::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (_, writer) = tokio::io::split(stream);
+        let handle = SocketHandle {
+            writer: Arc::new(Mutex::new(writer)),
+            recv_task: tokio::spawn(async {}),
+            server_id: 1,
+            peer_addr: client.local_addr().unwrap(),
+        };
+        state.sockets.lock().await.insert(7, handle);
+
+        let range = |start, length| FileRange { start, length };
+        assert_eq!(state.send_file(7, &path, range(2, Some(3))).await, Ok(3));
+        assert_eq!(state.send_file(7, &path, range(8, None)).await, Ok(2));
+        let mut buf = [0; 5];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"23489");
+        assert!(state
+            .send_file(7, &path, range(8, Some(3)))
+            .await
+            .unwrap_err()
+            .contains("past the end"));
+        assert!(state
+            .send_file(8, &path, FileRange::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sweep() {
+        let state = TcpState::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (_, writer) = tokio::io::split(stream);
+        let writer = Arc::new(Mutex::new(writer));
+        let finished = tokio::spawn(async {});
+        let running = tokio::spawn(std::future::pending::<()>());
+        while !finished.is_finished() {
+            tokio::task::yield_now().await;
+        }
+        for (socket_id, recv_task) in [(3, finished), (4, running)] {
+            let handle = SocketHandle {
+                writer: writer.clone(),
+                recv_task,
+                server_id: 1,
+                peer_addr: client.local_addr().unwrap(),
+            };
+            state.sockets.lock().await.insert(socket_id, handle);
+        }
+
+        assert_eq!(state.sweep().await, 1);
+        assert_eq!(state.sweep().await, 0);
+        assert!(state.sockets.lock().await.contains_key(&4));
+        let gc = state.gc.lock().unwrap().clone();
+        assert_eq!((gc.sweeps, gc.reaped), (2, 1));
+        assert_eq!(gc.last_reaped, vec![3]);
+        assert!(gc.last_sweep.is_some());
     }
 
     #[test]
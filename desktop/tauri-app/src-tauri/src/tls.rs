@@ -0,0 +1,253 @@
+//! TLS for `http_server`. The certificate and key are read from PEM files,
+//! and ALPN offers HTTP/2 ahead of HTTP/1.1. More certificates can be
+//! added for other host names, picked by the name the client asks for
+//! (SNI). Builds with the `http3` feature can also answer HTTP/3 over QUIC
+//! on the same port.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::ServerConfig;
+
+/// Connections that haven't finished the handshake by then are dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long browsers may remember that HTTP/3 is on offer.
+const ALT_SVC_MAX_AGE_SECS: u32 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsOptions {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: String,
+    /// PEM private key for the leaf certificate.
+    pub key_path: String,
+    /// Also answer HTTP/3 on the same port over UDP.
+    #[serde(default)]
+    pub http3: bool,
+    /// Answer plain HTTP sent to this port with a redirect to HTTPS.
+    #[serde(default)]
+    pub redirect_http: bool,
+    /// Certificates for other host names. The one above is used for names
+    /// not listed here, and for clients that don't send a name.
+    #[serde(default)]
+    pub sni: Vec<SniCert>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SniCert {
+    /// Such as `mysite.local`, or `*.mysite.local` for any one label in
+    /// front of it.
+    pub server_name: String,
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+/// Read a certificate chain and its key.
+fn load_key(
+    provider: &CryptoProvider,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<CertifiedKey, String> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| format!("reading {cert_path} failed: {e}"))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates in {cert_path}"));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("reading {key_path} failed: {e}"))?;
+    let key = CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| format!("certificate rejected: {e}"))?;
+    Ok(key)
+}
+
+/// Picks a certificate by the client's server name.
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    /// Keyed by lowercased name, wildcards included as `*.example`.
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    fn load(provider: &CryptoProvider, options: &TlsOptions) -> Result<Self, String> {
+        let default = load_key(provider, &options.cert_path, &options.key_path)?;
+        let mut by_name = HashMap::new();
+        for sni in &options.sni {
+            let name = sni.server_name.trim().trim_end_matches('.');
+            if name.is_empty() {
+                return Err("empty server name".to_string());
+            }
+            let key = load_key(provider, &sni.cert_path, &sni.key_path)?;
+            by_name.insert(name.to_ascii_lowercase(), Arc::new(key));
+        }
+        Ok(Self {
+            default: Arc::new(default),
+            by_name,
+        })
+    }
+
+    fn select(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let Some(name) = server_name.map(str::to_ascii_lowercase) else {
+            return self.default.clone();
+        };
+        let wildcard = name
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{parent}"));
+        self.by_name
+            .get(&name)
+            .or_else(|| wildcard.and_then(|w| self.by_name.get(&w)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.select(client_hello.server_name()))
+    }
+}
+
+/// Loaded TLS settings for a server.
+#[derive(Clone, Debug)]
+pub struct Tls {
+    pub options: TlsOptions,
+    /// Offers `h2` and `http/1.1`.
+    pub config: Arc<ServerConfig>,
+    /// `Alt-Svc` header pointing browsers at HTTP/3, when it's on.
+    pub alt_svc: Option<String>,
+}
+
+impl PartialEq for Tls {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.config, &other.config)
+    }
+}
+
+impl Eq for Tls {}
+
+impl Tls {
+    /// Read the certificates and keys for a server listening on `port`.
+    pub fn load(options: TlsOptions, port: u16) -> Result<Self, String> {
+        if options.http3 && !cfg!(feature = "http3") {
+            return Err("HTTP/3 isn't supported by this build".to_string());
+        }
+        let builder = ServerConfig::builder().with_no_client_auth();
+        let resolver = SniResolver::load(builder.crypto_provider(), &options)?;
+        let mut config = builder.with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let alt_svc = options
+            .http3
+            .then(|| format!("h3=\":{port}\"; ma={ALT_SVC_MAX_AGE_SECS}"));
+        Ok(Self {
+            options,
+            config: Arc::new(config),
+            alt_svc,
+        })
+    }
+
+    /// The same certificates without ALPN, for FTPS.
+    pub fn ftp_config(&self) -> ServerConfig {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols.clear();
+        config
+    }
+
+    /// The same certificates offering only HTTP/3, for QUIC.
+    #[cfg(feature = "http3")]
+    pub fn http3_config(&self) -> ServerConfig {
+        let mut config = (*self.config).clone();
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        config
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A self-signed certificate for `name`, written to `dir`.
+    fn self_signed(dir: &std::path::Path, name: &str) -> (SniCert, CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed([name.to_string()]).unwrap();
+        let cert_path = dir.join(format!("{name}.pem"));
+        let key_path = dir.join(format!("{name}.key.pem"));
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.signing_key.serialize_pem()).unwrap();
+        let sni = SniCert {
+            server_name: name.to_string(),
+            cert_path: cert_path.to_string_lossy().into_owned(),
+            key_path: key_path.to_string_lossy().into_owned(),
+        };
+        (sni, cert.cert.der().clone())
+    }
+
+    /// A self-signed certificate for `localhost`, written to `dir`.
+    pub fn localhost_cert(dir: &std::path::Path) -> (TlsOptions, CertificateDer<'static>) {
+        let (cert, der) = self_signed(dir, "localhost");
+        let options = TlsOptions {
+            cert_path: cert.cert_path,
+            key_path: cert.key_path,
+            http3: false,
+            redirect_http: false,
+            sni: Vec::new(),
+        };
+        (options, der)
+    }
+
+    #[test]
+    fn test_load() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (options, _) = localhost_cert(tmp.path());
+        let tls = Tls::load(options.clone(), 8443).unwrap();
+        assert_eq!(tls.config.alpn_protocols[0], b"h2");
+        assert_eq!(tls.alt_svc, None);
+
+        let missing = TlsOptions {
+            cert_path: tmp.path().join("nope.pem").to_string_lossy().into_owned(),
+            ..options.clone()
+        };
+        assert!(Tls::load(missing, 8443)
+            .unwrap_err()
+            .starts_with("reading "));
+        let swapped = TlsOptions {
+            cert_path: options.key_path.clone(),
+            ..options
+        };
+        assert!(Tls::load(swapped, 8443)
+            .unwrap_err()
+            .starts_with("no certificates"));
+    }
+
+    #[test]
+    fn test_sni() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (mut options, localhost) = localhost_cert(tmp.path());
+        let (mysite, mysite_der) = self_signed(tmp.path(), "mysite.local");
+        let (mut dev, dev_der) = self_signed(tmp.path(), "dev.local");
+        dev.server_name = "*.Dev.Local".to_string();
+        options.sni = vec![mysite, dev];
+        assert!(Tls::load(options.clone(), 8443).is_ok());
+
+        let provider = ServerConfig::builder().crypto_provider().clone();
+        let resolver = SniResolver::load(&provider, &options).unwrap();
+        let selected = |name| resolver.select(name).cert[0].clone();
+        assert_eq!(selected(Some("MySite.local")), mysite_der);
+        assert_eq!(selected(Some("api.dev.local")), dev_der);
+        assert_eq!(selected(Some("a.b.dev.local")), localhost);
+        assert_eq!(selected(Some("other.local")), localhost);
+        assert_eq!(selected(None), localhost);
+
+        options.sni[0].key_path = options.sni[1].key_path.clone();
+        assert!(Tls::load(options, 8443)
+            .unwrap_err()
+            .starts_with("certificate rejected"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Monochrome tray icons. macOS gets a template image that the menu bar
+//! tints itself; elsewhere we pick a light or dark glyph to match the
+//! taskbar/panel and swap it when the system theme changes.
+
+use tauri::image::Image;
+use tauri::{Manager, Theme};
+
+const TEMPLATE_ICON: &[u8] = include_bytes!("../icons/tray/tray-template.png");
+/// Dark glyph for light taskbars and panels.
+const LIGHT_THEME_ICON: &[u8] = include_bytes!("../icons/tray/tray-light.png");
+/// Light glyph for dark taskbars and panels.
+const DARK_THEME_ICON: &[u8] = include_bytes!("../icons/tray/tray-dark.png");
+
+fn icon_bytes(theme: Theme) -> &'static [u8] {
+    if cfg!(target_os = "macos") {
+        TEMPLATE_ICON
+    } else if theme == Theme::Light {
+        LIGHT_THEME_ICON
+    } else {
+        DARK_THEME_ICON
+    }
+}
+
+/// Theme of the surface the tray icon sits on.
+#[cfg(windows)]
+fn tray_theme(_app: &tauri::AppHandle) -> Theme {
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    // The taskbar follows the system theme, which can differ from the app theme
+    let light: u32 = RegKey::predef(HKEY_CURRENT_USER)
+        .open_subkey(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize")
+        .and_then(|key| key.get_value("SystemUsesLightTheme"))
+        .unwrap_or(0);
+    if light == 0 {
+        Theme::Dark
+    } else {
+        Theme::Light
+    }
+}
+
+#[cfg(not(windows))]
+fn tray_theme(app: &tauri::AppHandle) -> Theme {
+    // Most Linux panels are dark, so that's the fallback
+    app.get_webview_window("main")
+        .and_then(|w| w.theme().ok())
+        .unwrap_or(Theme::Dark)
+}
+
+/// Icon to show in the tray right now.
+pub fn current(app: &tauri::AppHandle) -> tauri::Result<Image<'static>> {
+    Image::from_bytes(icon_bytes(tray_theme(app)))
+}
+
+/// Re-pick the tray icon after a theme change.
+pub fn refresh(app: &tauri::AppHandle) {
+    if cfg!(target_os = "macos") {
+        // Template images follow the menu bar on their own
+        return;
+    }
+    let Some(tray) = app.tray_by_id("tray") else {
+        return;
+    };
+    match current(app) {
+        Ok(icon) => {
+            let _ = tray.set_icon(Some(icon));
+        }
+        Err(e) => tracing::error!("failed to load icon: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tray_icons_decode() {
+        for bytes in [TEMPLATE_ICON, LIGHT_THEME_ICON, DARK_THEME_ICON] {
+            let icon = Image::from_bytes(bytes).unwrap();
+            assert_eq!((icon.width(), icon.height()), (64, 64));
+        }
+    }
+}
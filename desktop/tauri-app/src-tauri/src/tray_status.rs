@@ -0,0 +1,112 @@
+//! Keep the tray tooltip (and on macOS the menu bar title and dock badge)
+//! in step with the number of listening servers and open connections.
+
+use std::time::Duration;
+
+use tauri::Manager;
+
+use super::tcp::TcpState;
+
+/// Coalesce bursts of connection churn into a single tray update.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("1 {word}")
+    } else {
+        format!("{n} {word}s")
+    }
+}
+
+pub fn status_text(servers: usize, connections: usize) -> String {
+    if servers == 0 {
+        return "200 OK".to_string();
+    }
+    format!(
+        "200 OK — {}, {}",
+        plural(servers, "server"),
+        plural(connections, "connection")
+    )
+}
+
+/// Dock badge: open connections, or servers while none are connected.
+/// `None` clears it when nothing is serving.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub fn badge_count(servers: usize, connections: usize) -> Option<i64> {
+    match (servers, connections) {
+        (0, _) => None,
+        (servers, 0) => i64::try_from(servers).ok(),
+        (_, connections) => i64::try_from(connections).ok(),
+    }
+}
+
+fn apply(app: &tauri::AppHandle, servers: usize, connections: usize) {
+    let Some(tray) = app.tray_by_id("tray") else {
+        return;
+    };
+    let _ = tray.set_tooltip(Some(status_text(servers, connections)));
+    #[cfg(target_os = "macos")]
+    {
+        let show_count = app
+            .state::<std::sync::Mutex<super::Settings>>()
+            .lock()
+            .unwrap()
+            .menu_bar_count;
+        let title = if servers == 0 || !show_count {
+            String::new()
+        } else {
+            servers.to_string()
+        };
+        let _ = tray.set_title(Some(title));
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.set_badge_count(badge_count(servers, connections));
+        }
+    }
+}
+
+/// Apply the current counts now, e.g. after a display setting changed.
+pub fn refresh(app: &tauri::AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let (servers, connections) = app.state::<TcpState>().counts().await;
+        apply(&app, servers, connections);
+    });
+}
+
+/// Refresh the tray whenever the TCP state changes.
+pub fn spawn(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<TcpState>();
+        let changes = state.changes();
+        let mut last = None;
+        loop {
+            let counts = state.counts().await;
+            if last != Some(counts) {
+                last = Some(counts);
+                apply(&app, counts.0, counts.1);
+            }
+            changes.notified().await;
+            tokio::time::sleep(DEBOUNCE).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_text() {
+        assert_eq!(status_text(0, 0), "200 OK");
+        assert_eq!(status_text(1, 1), "200 OK — 1 server, 1 connection");
+        assert_eq!(status_text(2, 5), "200 OK — 2 servers, 5 connections");
+        assert_eq!(status_text(1, 0), "200 OK — 1 server, 0 connections");
+    }
+
+    #[test]
+    fn test_badge_count() {
+        assert_eq!(badge_count(0, 0), None);
+        assert_eq!(badge_count(2, 0), Some(2));
+        assert_eq!(badge_count(2, 7), Some(7));
+    }
+}
@@ -0,0 +1,325 @@
+//! Manifests of a folder tree (path, size, modification time and SHA-256
+//! of every file) saved under `snapshots` in the app data dir, and what
+//! changed since one was taken. A diff only hashes files whose size is the
+//! same but whose modification time isn't; for the rest that's enough to
+//! tell.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::State;
+
+use super::fingerprint::hex;
+use super::fs_commands::{CancelToken, FsState};
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+/// The oldest snapshots are deleted past this many.
+const MAX_SNAPSHOTS: usize = 50;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct FileRecord {
+    size: u64,
+    /// Unix milliseconds; 0 when the platform doesn't say.
+    mtime_ms: u64,
+    sha256: String,
+}
+
+/// What a snapshot file holds. Paths are relative to `base`, with `/`
+/// separators.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    base: String,
+    created_ms: u64,
+    files: BTreeMap<String, FileRecord>,
+}
+
+/// Returned by `fs_tree_snapshot`.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotInfo {
+    /// For `fs_tree_diff`.
+    pub id: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Files that differ from a snapshot, each list sorted.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+pub fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+pub fn sha256(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file = std::fs::File::open(path)?;
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+pub struct Found {
+    /// From `base`, with `/` separators.
+    pub relative: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub mtime_ms: u64,
+}
+
+/// Every file under `base` for which `keep` holds, given its relative
+/// path. Folders `keep` refuses aren't entered. Symlinked files are
+/// followed; symlinked folders aren't, so loops can't happen.
+pub fn walk(
+    base: &Path,
+    cancel: &CancelToken,
+    keep: &dyn Fn(&str) -> bool,
+) -> Result<Vec<Found>, String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![base.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries =
+            std::fs::read_dir(&dir).map_err(|e| format!("read {} failed: {e}", dir.display()))?;
+        for entry in entries.filter_map(Result::ok) {
+            cancel.check()?;
+            let path = entry.path();
+            let segments: Vec<_> = path
+                .strip_prefix(base)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            let relative = segments.join("/");
+            if !keep(&relative) {
+                continue;
+            }
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                dirs.push(path);
+                continue;
+            }
+            // Broken links, sockets and the like
+            let Ok(meta) = std::fs::metadata(&path) else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            files.push(Found {
+                relative,
+                size: meta.len(),
+                mtime_ms: meta.modified().map_or(0, unix_ms),
+                path,
+            });
+        }
+    }
+    Ok(files)
+}
+
+fn capture(base: &Path, cancel: &CancelToken) -> Result<Manifest, String> {
+    let mut files = BTreeMap::new();
+    for found in walk(base, cancel, &|_| true)? {
+        cancel.check()?;
+        let sha256 =
+            sha256(&found.path).map_err(|e| format!("read {} failed: {e}", found.relative))?;
+        files.insert(
+            found.relative,
+            FileRecord {
+                size: found.size,
+                mtime_ms: found.mtime_ms,
+                sha256,
+            },
+        );
+    }
+    Ok(Manifest {
+        base: base.to_string_lossy().to_string(),
+        created_ms: unix_ms(SystemTime::now()),
+        files,
+    })
+}
+
+/// Compare the tree under `base` with `manifest`.
+fn diff(base: &Path, manifest: &Manifest, cancel: &CancelToken) -> Result<TreeDiff, String> {
+    let mut result = TreeDiff::default();
+    let mut present = BTreeSet::new();
+    for found in walk(base, cancel, &|_| true)? {
+        let Some(before) = manifest.files.get(&found.relative) else {
+            result.added.push(found.relative);
+            continue;
+        };
+        present.insert(found.relative.clone());
+        let same = if before.size != found.size {
+            false
+        } else if before.mtime_ms == found.mtime_ms {
+            true
+        } else {
+            cancel.check()?;
+            sha256(&found.path).map_err(|e| format!("read {} failed: {e}", found.relative))?
+                == before.sha256
+        };
+        if !same {
+            result.changed.push(found.relative);
+        }
+    }
+    result.removed = manifest
+        .files
+        .keys()
+        .filter(|p| !present.contains(*p))
+        .cloned()
+        .collect();
+    result.added.sort();
+    result.changed.sort();
+    Ok(result)
+}
+
+fn snapshots_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    Ok(super::paths::app_data_dir(app)?.join(SNAPSHOTS_DIR))
+}
+
+/// Where snapshot `id` is kept. Ids are ours, so anything else is refused
+/// rather than joined onto the folder.
+fn snapshot_path(dir: &Path, id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid snapshot id {id:?}"));
+    }
+    Ok(dir.join(format!("{id}.json")))
+}
+
+/// Delete all but the newest `keep` snapshots in `dir`.
+fn prune(dir: &Path, keep: usize) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut snapshots: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter(|e| e.path().extension().is_some_and(|x| x == "json"))
+        .map(|e| {
+            let modified = e.metadata().and_then(|m| m.modified()).ok();
+            (modified, e.path())
+        })
+        .collect();
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(keep);
+    for (_, path) in snapshots.into_iter().take(excess) {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+/// Record every file under `base`, to compare against later with
+/// `fs_tree_diff`. Reads every file, so pass `op_id` to be able to cancel.
+#[tauri::command]
+pub async fn fs_tree_snapshot(
+    base: String,
+    op_id: Option<u32>,
+    state: State<'_, FsState>,
+    app: tauri::AppHandle,
+) -> Result<SnapshotInfo, String> {
+    let dir = snapshots_dir(&app)?;
+    let cancel = state.cancel_token(op_id).await?;
+    let captured = tokio::task::spawn_blocking(move || -> Result<SnapshotInfo, String> {
+        let manifest = capture(Path::new(&base), &cancel)?;
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        std::fs::create_dir_all(&dir).map_err(|e| format!("mkdir failed: {e}"))?;
+        let json = serde_json::to_string(&manifest).map_err(|e| e.to_string())?;
+        std::fs::write(snapshot_path(&dir, &id)?, json)
+            .map_err(|e| format!("snapshot write failed: {e}"))?;
+        prune(&dir, MAX_SNAPSHOTS);
+        Ok(SnapshotInfo {
+            id,
+            files: manifest.files.len(),
+            bytes: manifest.files.values().map(|f| f.size).sum(),
+        })
+    })
+    .await
+    .map_err(|e| e.to_string());
+    state.finish(op_id).await;
+    captured?
+}
+
+/// Files added, removed or changed under `base` since snapshot
+/// `snapshot_id` was taken.
+#[tauri::command]
+pub async fn fs_tree_diff(
+    base: String,
+    snapshot_id: String,
+    op_id: Option<u32>,
+    state: State<'_, FsState>,
+    app: tauri::AppHandle,
+) -> Result<TreeDiff, String> {
+    let path = snapshot_path(&snapshots_dir(&app)?, &snapshot_id)?;
+    let cancel = state.cancel_token(op_id).await?;
+    let compared = tokio::task::spawn_blocking(move || -> Result<TreeDiff, String> {
+        let json = std::fs::read_to_string(&path)
+            .map_err(|e| format!("snapshot {snapshot_id} not found: {e}"))?;
+        let manifest: Manifest =
+            serde_json::from_str(&json).map_err(|e| format!("snapshot {snapshot_id}: {e}"))?;
+        diff(Path::new(&base), &manifest, &cancel)
+    })
+    .await
+    .map_err(|e| e.to_string());
+    state.finish(op_id).await;
+    compared?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path();
+        std::fs::create_dir(base.join("sub")).unwrap();
+        for (name, text) in [
+            ("same.txt", "same"),
+            ("edited.txt", "before"),
+            ("touched.txt", "touched"),
+            ("gone.txt", "gone"),
+            ("sub/nested.txt", "nested"),
+        ] {
+            std::fs::write(base.join(name), text).unwrap();
+        }
+        let cancel = CancelToken::default();
+        let mut manifest = capture(base, &cancel).unwrap();
+        assert_eq!(manifest.files.len(), 5);
+        assert!(manifest.files.contains_key("sub/nested.txt"));
+        assert_eq!(
+            manifest.files["same.txt"].sha256,
+            "0967115f2813a3541eaef77de9d9d5773f1c0c04314b0bbfe4ff3b3b1c55b5d5"
+        );
+        assert_eq!(diff(base, &manifest, &cancel).unwrap(), TreeDiff::default());
+
+        // Same size, different content, and a touch that changes nothing
+        std::fs::write(base.join("edited.txt"), "after!").unwrap();
+        manifest.files.get_mut("edited.txt").unwrap().mtime_ms = 1;
+        manifest.files.get_mut("touched.txt").unwrap().mtime_ms = 1;
+        std::fs::remove_file(base.join("gone.txt")).unwrap();
+        std::fs::write(base.join("sub/new.txt"), "new").unwrap();
+        std::fs::write(base.join("sub/nested.txt"), "longer now").unwrap();
+        assert_eq!(
+            diff(base, &manifest, &cancel).unwrap(),
+            TreeDiff {
+                added: vec!["sub/new.txt".to_string()],
+                removed: vec!["gone.txt".to_string()],
+                changed: vec!["edited.txt".to_string(), "sub/nested.txt".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_snapshot_path() {
+        let dir = Path::new("/data/snapshots");
+        assert_eq!(snapshot_path(dir, "0a1b").unwrap(), dir.join("0a1b.json"));
+        assert!(snapshot_path(dir, "../settings").is_err());
+        assert!(snapshot_path(dir, "").is_err());
+    }
+}
@@ -0,0 +1,460 @@
+//! One-way sync of a folder into another, for publishing a folder to a
+//! drive or share. Files are compared by size and modification time, or by
+//! content with `checksum`; the ones that differ are copied a few at a
+//! time and given the source's modification time, so the next run skips
+//! them. With `delete`, files only in the destination are removed too.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use tauri::{State, Webview};
+use tokio::task::JoinSet;
+
+use super::folder_grants::{self, FolderGrants};
+use super::fs_audit::FsAudit;
+use super::fs_commands::{CancelToken, FsState};
+use super::tree_snapshots::{sha256, walk, Found};
+
+const PARALLEL_COPIES: usize = 4;
+/// Modification times this close count as the same. FAT drives store them
+/// to 2 seconds.
+const MTIME_TOLERANCE_MS: u64 = 2000;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SyncOptions {
+    /// Remove files in the destination that aren't in the source.
+    pub delete: bool,
+    /// Work out what would change without changing anything.
+    pub dry_run: bool,
+    /// Globs of paths to leave alone on both sides. Without a `/`, a
+    /// pattern matches a name at any depth, like `node_modules` or `*.tmp`;
+    /// with one, it matches the whole path from the folder.
+    pub exclude: Vec<String>,
+    /// Compare the content of files the same size, rather than their
+    /// modification times.
+    pub checksum: bool,
+}
+
+/// Sent after each file is copied.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncProgress {
+    pub done: usize,
+    pub total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub path: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// What `fs_tree_sync` did, or with `dryRun` would do. Paths are relative,
+/// with `/` separators.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncReport {
+    pub copied: Vec<String>,
+    pub deleted: Vec<String>,
+    pub unchanged: usize,
+    pub bytes_copied: u64,
+    /// Files that couldn't be copied or deleted; the rest still were.
+    pub failed: Vec<SyncFailure>,
+    pub dry_run: bool,
+    pub duration_ms: u64,
+}
+
+fn exclude_patterns(exclude: &[String]) -> Result<Vec<glob::Pattern>, String> {
+    exclude
+        .iter()
+        .map(|p| glob::Pattern::new(p).map_err(|e| format!("invalid exclude {p:?}: {e}")))
+        .collect()
+}
+
+fn excluded(patterns: &[glob::Pattern], relative: &str) -> bool {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..glob::MatchOptions::new()
+    };
+    patterns.iter().any(|p| {
+        if p.as_str().contains('/') {
+            p.matches_with(relative, options)
+        } else {
+            relative.split('/').any(|name| p.matches(name))
+        }
+    })
+}
+
+/// `path` made absolute with symlinks resolved, as far as it exists.
+fn resolve(path: &Path) -> Result<PathBuf, String> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| format!("sync failed: {} not found", path.display()))?;
+    let real = std::fs::canonicalize(existing).map_err(|e| format!("sync failed: {e}"))?;
+    Ok(real.join(path.strip_prefix(existing).unwrap_or(path)))
+}
+
+/// Refuse to sync a folder that isn't one, or into itself or a parent.
+fn check_folders(src: &Path, dst: &Path) -> Result<(), String> {
+    if !src.is_dir() {
+        return Err(format!("sync failed: {} is not a folder", src.display()));
+    }
+    let (src, dst) = (resolve(src)?, resolve(dst)?);
+    if dst.starts_with(&src) || src.starts_with(&dst) {
+        return Err("sync failed: the folders overlap".to_string());
+    }
+    Ok(())
+}
+
+struct Plan {
+    copy: Vec<Found>,
+    delete: Vec<String>,
+    unchanged: usize,
+}
+
+fn plan(
+    src: &Path,
+    dst: &Path,
+    options: &SyncOptions,
+    cancel: &CancelToken,
+) -> Result<Plan, String> {
+    let patterns = exclude_patterns(&options.exclude)?;
+    let keep = |relative: &str| !excluded(&patterns, relative);
+    let source = walk(src, cancel, &keep)?;
+    let mut existing: BTreeMap<String, Found> = if dst.is_dir() {
+        walk(dst, cancel, &keep)?
+            .into_iter()
+            .map(|f| (f.relative.clone(), f))
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+    let mut plan = Plan {
+        copy: Vec::new(),
+        delete: Vec::new(),
+        unchanged: 0,
+    };
+    for found in source {
+        let differs = match existing.remove(&found.relative) {
+            None => true,
+            Some(current) if current.size != found.size => true,
+            Some(current) if options.checksum => {
+                cancel.check()?;
+                let hash = |f: &Found| {
+                    sha256(&f.path).map_err(|e| format!("read {} failed: {e}", f.relative))
+                };
+                hash(&found)? != hash(&current)?
+            }
+            Some(current) => current.mtime_ms.abs_diff(found.mtime_ms) > MTIME_TOLERANCE_MS,
+        };
+        if differs {
+            plan.copy.push(found);
+        } else {
+            plan.unchanged += 1;
+        }
+    }
+    if options.delete {
+        plan.delete = existing.into_keys().collect();
+    }
+    plan.copy.sort_by(|a, b| a.relative.cmp(&b.relative));
+    Ok(plan)
+}
+
+/// Copy `from` over `to` by way of a temporary file beside it, so `to` is
+/// never left half written, keeping the modification time of `from`.
+fn copy_file(from: &Path, to: &Path) -> std::io::Result<u64> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let name = to.file_name().unwrap_or_default().to_string_lossy();
+    let temp = to.with_file_name(format!(".{name}.{}.part", uuid::Uuid::new_v4().simple()));
+    let copied = std::fs::copy(from, &temp).and_then(|bytes| {
+        let modified = std::fs::metadata(from)?.modified()?;
+        std::fs::File::options()
+            .write(true)
+            .open(&temp)?
+            .set_modified(modified)?;
+        std::fs::rename(&temp, to)?;
+        Ok(bytes)
+    });
+    if copied.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    copied
+}
+
+/// Remove `relative` from `dst`, then any folders it leaves empty that the
+/// source doesn't have.
+fn delete_file(src: &Path, dst: &Path, relative: &str) -> std::io::Result<()> {
+    let path = dst.join(relative);
+    std::fs::remove_file(&path)?;
+    for dir in path.ancestors().skip(1) {
+        let Ok(inner) = dir.strip_prefix(dst) else {
+            break;
+        };
+        if inner.as_os_str().is_empty() || src.join(inner).is_dir() {
+            break;
+        }
+        if std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Carry out `plan`, calling `on_progress` after each copy.
+async fn apply(
+    src: &Path,
+    dst: &Path,
+    plan: Plan,
+    cancel: &CancelToken,
+    on_progress: &(dyn Fn(SyncProgress) + Sync),
+) -> Result<SyncReport, String> {
+    let mut report = SyncReport {
+        unchanged: plan.unchanged,
+        ..SyncReport::default()
+    };
+    let total = plan.copy.len();
+    let bytes_total = plan.copy.iter().map(|f| f.size).sum();
+    let mut pending = plan.copy.into_iter();
+    let mut running = JoinSet::new();
+    let mut done = 0;
+    loop {
+        while running.len() < PARALLEL_COPIES && cancel.check().is_ok() {
+            let Some(found) = pending.next() else {
+                break;
+            };
+            let to = dst.join(&found.relative);
+            running.spawn_blocking(move || {
+                let copied = copy_file(&found.path, &to);
+                (found.relative, copied)
+            });
+        }
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (relative, copied) = joined.map_err(|e| format!("sync failed: {e}"))?;
+        match copied {
+            Ok(bytes) => {
+                report.bytes_copied += bytes;
+                report.copied.push(relative.clone());
+            }
+            Err(e) => report.failed.push(SyncFailure {
+                path: relative.clone(),
+                error: format!("copy failed: {e}"),
+            }),
+        }
+        done += 1;
+        on_progress(SyncProgress {
+            done,
+            total,
+            bytes_done: report.bytes_copied,
+            bytes_total,
+            path: relative,
+        });
+    }
+    cancel.check().map_err(|e| format!("sync failed: {e}"))?;
+
+    let (src, dst) = (src.to_path_buf(), dst.to_path_buf());
+    let deleted = tokio::task::spawn_blocking(move || {
+        plan.delete
+            .into_iter()
+            .map(|relative| {
+                let deleted = delete_file(&src, &dst, &relative);
+                (relative, deleted)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| format!("sync failed: {e}"))?;
+    for (relative, deleted) in deleted {
+        match deleted {
+            Ok(()) => report.deleted.push(relative),
+            Err(e) => report.failed.push(SyncFailure {
+                path: relative,
+                error: format!("delete failed: {e}"),
+            }),
+        }
+    }
+    report.copied.sort();
+    Ok(report)
+}
+
+/// Make `dst` a copy of `src`: files missing or different there are
+/// copied, and with `delete`, files only there are removed. Pass `op_id`
+/// to be able to cancel; copies already made are kept.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn fs_tree_sync(
+    src: String,
+    dst: String,
+    options: Option<SyncOptions>,
+    on_progress: Channel<SyncProgress>,
+    op_id: Option<u32>,
+    state: State<'_, FsState>,
+    audit: State<'_, FsAudit>,
+    grants: State<'_, FolderGrants>,
+    webview: Webview,
+) -> Result<SyncReport, String> {
+    let cancel = state.cancel_token(op_id).await?;
+    let synced = sync(
+        &src,
+        &dst,
+        options.unwrap_or_default(),
+        &cancel,
+        &grants,
+        &on_progress,
+    )
+    .await;
+    state.finish(op_id).await;
+    if synced.as_ref().is_ok_and(|r| !r.dry_run) {
+        audit.record(&webview, "sync", &dst);
+    }
+    synced
+}
+
+async fn sync(
+    src: &str,
+    dst: &str,
+    options: SyncOptions,
+    cancel: &CancelToken,
+    grants: &FolderGrants,
+    on_progress: &Channel<SyncProgress>,
+) -> Result<SyncReport, String> {
+    let started = Instant::now();
+    let (src, dst) = (PathBuf::from(src), PathBuf::from(dst));
+    check_folders(&src, &dst)?;
+    let planned = {
+        let (src, dst, cancel) = (src.clone(), dst.clone(), cancel.clone());
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || plan(&src, &dst, &options, &cancel))
+            .await
+            .map_err(|e| format!("sync failed: {e}"))?
+    };
+    let plan = planned.map_err(|e| format!("sync failed: {e}"))?;
+    let mut report = if options.dry_run {
+        SyncReport {
+            bytes_copied: plan.copy.iter().map(|f| f.size).sum(),
+            copied: plan.copy.into_iter().map(|f| f.relative).collect(),
+            deleted: plan.delete,
+            unchanged: plan.unchanged,
+            dry_run: true,
+            ..SyncReport::default()
+        }
+    } else {
+        if !plan.delete.is_empty() {
+            folder_grants::confirm_delete(grants, &dst).await?;
+        }
+        apply(&src, &dst, plan, cancel, &|progress| {
+            on_progress.send(progress).ok();
+        })
+        .await?
+    };
+    report.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(base: &Path, relative: &str, text: &str) {
+        let path = base.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn test_excluded() {
+        let patterns =
+            exclude_patterns(&["node_modules".into(), "*.tmp".into(), "docs/*.md".into()]).unwrap();
+        assert!(excluded(&patterns, "node_modules"));
+        assert!(excluded(&patterns, "app/node_modules"));
+        assert!(excluded(&patterns, "a/b.tmp"));
+        assert!(excluded(&patterns, "docs/readme.md"));
+        assert!(!excluded(&patterns, "docs/sub/readme.md"));
+        assert!(!excluded(&patterns, "index.html"));
+        assert!(exclude_patterns(&["[a".into()]).is_err());
+    }
+
+    #[test]
+    fn test_check_folders() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("site");
+        std::fs::create_dir(&src).unwrap();
+        assert!(check_folders(&src, &tmp.path().join("copy")).is_ok());
+        assert!(check_folders(&src, &src.join("new/out")).is_err());
+        assert!(check_folders(&src, tmp.path()).is_err());
+        assert!(check_folders(&tmp.path().join("missing"), &src).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (src, dst) = (tmp.path().join("src"), tmp.path().join("dst"));
+        write(&src, "index.html", "home");
+        write(&src, "css/site.css", "body{}");
+        write(&src, "cache/x.tmp", "skip");
+        write(&dst, "old/gone.txt", "gone");
+        write(&dst, "keep.tmp", "mine");
+
+        let options = SyncOptions {
+            delete: true,
+            exclude: vec!["*.tmp".to_string()],
+            ..SyncOptions::default()
+        };
+        let cancel = CancelToken::default();
+        let planned = plan(&src, &dst, &options, &cancel).unwrap();
+        assert_eq!(planned.delete, ["old/gone.txt"]);
+        let progress = std::sync::Mutex::new(Vec::new());
+        let report = apply(&src, &dst, planned, &cancel, &|p| {
+            progress.lock().unwrap().push(p.done);
+        })
+        .await
+        .unwrap();
+        assert_eq!(report.copied, ["css/site.css", "index.html"]);
+        assert_eq!(report.deleted, ["old/gone.txt"]);
+        assert_eq!(report.bytes_copied, 10);
+        assert_eq!(progress.into_inner().unwrap(), [1, 2]);
+        assert_eq!(
+            std::fs::read_to_string(dst.join("css/site.css")).unwrap(),
+            "body{}"
+        );
+        assert!(!dst.join("old").exists());
+        assert!(dst.join("keep.tmp").exists());
+        assert!(!dst.join("cache").exists());
+
+        // Copies keep their modification time, so a second run has nothing to do
+        let again = plan(&src, &dst, &options, &cancel).unwrap();
+        assert!(again.copy.is_empty() && again.delete.is_empty());
+        assert_eq!(again.unchanged, 2);
+
+        // Same size and time, different content: only a checksum tells
+        let copy = dst.join("index.html");
+        let modified = std::fs::metadata(&copy).unwrap().modified().unwrap();
+        std::fs::write(&copy, "HOME").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&copy)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(plan(&src, &dst, &options, &cancel).unwrap().copy.is_empty());
+        let checksum = SyncOptions {
+            checksum: true,
+            ..options
+        };
+        let planned = plan(&src, &dst, &checksum, &cancel).unwrap();
+        let names: Vec<_> = planned.copy.iter().map(|f| f.relative.as_str()).collect();
+        assert_eq!(names, ["index.html"]);
+    }
+}
@@ -0,0 +1,277 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{Emitter, Manager, State};
+use tauri_plugin_updater::{Update, UpdaterExt};
+
+use super::{offline_update, quit, rollback, save_settings, Settings};
+
+const STAGED_DIR: &str = "staged-update";
+const STAGED_RECORD_FILENAME: &str = "staged-update.json";
+
+/// An update downloaded during this session, applied when the app quits.
+#[derive(Default)]
+pub(crate) struct StagedUpdate(Mutex<Option<(Update, Vec<u8>)>>);
+
+/// On-disk record of the staged bundle, so it survives a restart and can be
+/// installed through the offline path if the in-memory copy is gone.
+#[derive(Serialize, Deserialize)]
+struct StagedRecord {
+    version: String,
+    bundle: PathBuf,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Why an available update should not be offered right now, if at all.
+pub(crate) fn deferral_reason(
+    settings: &Settings,
+    version: &str,
+    now: u64,
+) -> Option<&'static str> {
+    if settings.skipped_version.as_deref() == Some(version) {
+        return Some("skipped");
+    }
+    if settings.snooze_until.is_some_and(|until| now < until) {
+        return Some("snoozed");
+    }
+    None
+}
+
+pub(crate) fn current_deferral(settings: &Settings, version: &str) -> Option<&'static str> {
+    deferral_reason(settings, version, unix_now())
+}
+
+/// Stop offering `version`. Pass `null` to clear a previously skipped version.
+#[tauri::command]
+pub async fn update_skip_version(
+    version: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<Settings>>,
+) -> Result<(), String> {
+    let mut s = state.lock().unwrap();
+    s.skipped_version = version;
+    save_settings(&app, &s);
+    Ok(())
+}
+
+/// Suppress update prompts for `hours`. Pass 0 to clear the snooze.
+/// Returns the unix time (seconds) the snooze ends, if any.
+#[tauri::command]
+pub async fn update_snooze(
+    hours: u64,
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<Settings>>,
+) -> Result<Option<u64>, String> {
+    let mut s = state.lock().unwrap();
+    s.snooze_until = (hours > 0).then(|| unix_now() + Duration::from_hours(hours).as_secs());
+    save_settings(&app, &s);
+    Ok(s.snooze_until)
+}
+
+fn staged_dir() -> Option<PathBuf> {
+    Some(ok200_common::native_dir()?.join(STAGED_DIR))
+}
+
+fn staged_record_path() -> Option<PathBuf> {
+    Some(ok200_common::native_dir()?.join(STAGED_RECORD_FILENAME))
+}
+
+fn load_staged_record() -> Option<StagedRecord> {
+    let text = std::fs::read_to_string(staged_record_path()?).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+fn clear_staged() {
+    if let Some(dir) = staged_dir() {
+        std::fs::remove_dir_all(dir).ok();
+    }
+    if let Some(path) = staged_record_path() {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+/// File name for the staged bundle, taken from the download URL.
+fn bundle_file_name(url: &tauri::Url) -> String {
+    url.path_segments()
+        .and_then(Iterator::last)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("update.bin")
+        .to_string()
+}
+
+/// Save the downloaded bundle and its signature to the config dir.
+fn write_staged(update: &Update, bytes: &[u8]) -> Result<(), String> {
+    let dir = staged_dir().ok_or("no config directory")?;
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("mkdir {}: {e}", dir.display()))?;
+    let bundle = dir.join(bundle_file_name(&update.download_url));
+    std::fs::write(&bundle, bytes).map_err(|e| format!("write {}: {e}", bundle.display()))?;
+    let sig_path = offline_update::signature_path_for(&bundle);
+    std::fs::write(&sig_path, &update.signature)
+        .map_err(|e| format!("write {}: {e}", sig_path.display()))?;
+
+    let record = StagedRecord {
+        version: update.version.clone(),
+        bundle,
+    };
+    let path = staged_record_path().ok_or("no config directory")?;
+    let json = serde_json::to_string_pretty(&record).map_err(|e| e.to_string())?;
+    std::fs::write(&path, json).map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+/// Drop a staged record left over from a session that already installed it.
+pub(crate) fn discard_installed_staged(current_version: &str) {
+    if load_staged_record().is_some_and(|r| r.version == current_version) {
+        clear_staged();
+    }
+}
+
+/// Download the available update and hold it until the app quits.
+/// Returns the staged version, or `None` when there is nothing to stage.
+#[tauri::command]
+pub async fn update_stage(
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<Settings>>,
+    staged: State<'_, StagedUpdate>,
+) -> Result<Option<String>, String> {
+    let settings = state.lock().unwrap().clone();
+    // Per check, since the channel can change while the app runs
+    let mut builder = app
+        .updater_builder()
+        .header("X-Update-Channel", settings.channel.as_str())
+        .map_err(|e| format!("updater init failed: {e}"))?;
+    if let Some(proxy) = settings.updater_proxy() {
+        builder = builder.proxy(proxy);
+    }
+    let updater = builder
+        .build()
+        .map_err(|e| format!("updater init failed: {e}"))?;
+    let Some(update) = updater
+        .check()
+        .await
+        .map_err(|e| format!("update check failed: {e}"))?
+    else {
+        return Ok(None);
+    };
+    if current_deferral(&settings, &update.version).is_some() {
+        return Ok(None);
+    }
+
+    // `download` verifies the signature before returning the bytes
+    let bytes = update
+        .download(|_, _| {}, || {})
+        .await
+        .map_err(|e| format!("download failed: {e}"))?;
+    write_staged(&update, &bytes)?;
+    let version = update.version.clone();
+    *staged.0.lock().unwrap() = Some((update, bytes));
+    let _ = app.emit("update-staged", &version);
+    Ok(Some(version))
+}
+
+/// Version of the update waiting to be installed on quit, if any.
+#[tauri::command]
+pub async fn update_staged() -> Result<Option<String>, String> {
+    Ok(load_staged_record().map(|r| r.version))
+}
+
+/// Apply the staged update while the app exits, if the user opted in.
+pub(crate) fn install_staged_on_quit(app: &tauri::AppHandle) {
+    if !app
+        .state::<Mutex<Settings>>()
+        .lock()
+        .unwrap()
+        .install_on_quit
+    {
+        return;
+    }
+    let in_memory = app.state::<StagedUpdate>().0.lock().unwrap().take();
+    let result = if let Some((update, bytes)) = in_memory {
+        update.install(&bytes).map_err(|e| e.to_string())
+    } else if let Some(record) = load_staged_record() {
+        offline_update::updater_pubkey(app.config())
+            .and_then(|key| offline_update::install_from_file(&record.bundle, &key))
+    } else {
+        return;
+    };
+    match result {
+        Ok(()) => {
+            tracing::info!("installed staged update on quit");
+            clear_staged();
+        }
+        Err(e) => tracing::error!("failed to install staged update: {e}"),
+    }
+}
+
+/// Version `update_rollback` would return to, if one is available.
+#[tauri::command]
+pub async fn update_rollback_version(app: tauri::AppHandle) -> Result<Option<String>, String> {
+    Ok(rollback::previous_version(&app))
+}
+
+/// Reinstall the previous version, skip the one being reverted, and restart.
+#[tauri::command]
+pub async fn update_rollback(
+    app: tauri::AppHandle,
+    state: State<'_, Mutex<Settings>>,
+) -> Result<(), String> {
+    let handle = app.clone();
+    let reverted = tauri::async_runtime::spawn_blocking(move || rollback::rollback(&handle))
+        .await
+        .map_err(|e| format!("rollback task failed: {e}"))??;
+    {
+        let mut s = state.lock().unwrap();
+        s.skipped_version = Some(reverted);
+        save_settings(&app, &s);
+    }
+    quit::restart(&app).await
+}
+
+/// Verify and install a locally downloaded update bundle, then restart.
+#[tauri::command]
+pub async fn update_install_from_file(path: String, app: tauri::AppHandle) -> Result<(), String> {
+    let pubkey = offline_update::updater_pubkey(app.config())?;
+    let bundle = PathBuf::from(path);
+    tauri::async_runtime::spawn_blocking(move || {
+        offline_update::install_from_file(&bundle, &pubkey)
+    })
+    .await
+    .map_err(|e| format!("install task failed: {e}"))??;
+    quit::restart(&app).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deferral_reason() {
+        let mut s = Settings::default();
+        assert_eq!(deferral_reason(&s, "1.2.0", 1000), None);
+
+        s.skipped_version = Some("1.2.0".to_string());
+        assert_eq!(deferral_reason(&s, "1.2.0", 1000), Some("skipped"));
+        assert_eq!(deferral_reason(&s, "1.3.0", 1000), None);
+
+        s.snooze_until = Some(2000);
+        assert_eq!(deferral_reason(&s, "1.3.0", 1000), Some("snoozed"));
+        assert_eq!(deferral_reason(&s, "1.3.0", 2000), None);
+    }
+
+    #[test]
+    fn test_bundle_file_name() {
+        let url = |s: &str| tauri::Url::parse(s).unwrap();
+        assert_eq!(
+            bundle_file_name(&url("https://example.com/v1/200.OK_1.2.0_amd64.AppImage")),
+            "200.OK_1.2.0_amd64.AppImage"
+        );
+        assert_eq!(bundle_file_name(&url("https://example.com/")), "update.bin");
+    }
+}
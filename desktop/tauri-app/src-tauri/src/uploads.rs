@@ -0,0 +1,638 @@
+//! Opt-in uploads for `http_server`: `PUT` writes one file, a multipart
+//! `POST` to a folder writes every file part into it. Bodies stream to a
+//! hidden temp file next to the target, which is renamed into place once
+//! complete, so a half-finished upload never shows up under its name.
+
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const READ_CHUNK: usize = 64 * 1024;
+/// Multipart preambles and part headers longer than this are rejected.
+const MAX_PART_HEAD_BYTES: usize = 16 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Uploads {
+    /// Largest request body accepted.
+    pub max_bytes: u64,
+    /// Replace existing files. Otherwise `PUT` fails with 409 and form
+    /// uploads get a numbered name like `photo (1).jpg`.
+    pub overwrite: bool,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 1024 * 1024,
+            overwrite: false,
+        }
+    }
+}
+
+/// A file written by an upload.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedFile {
+    /// URL path of the new file.
+    pub path: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    BadRequest(String),
+    Conflict(String),
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl UploadError {
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::BadRequest(_) => 400,
+            Self::Conflict(_) => 409,
+            Self::TooLarge => 413,
+            Self::Io(_) => 500,
+        }
+    }
+}
+
+impl std::fmt::Display for UploadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadRequest(msg) => write!(f, "bad upload: {msg}"),
+            Self::Conflict(name) => write!(f, "{name} already exists"),
+            Self::TooLarge => write!(f, "upload is over the size limit"),
+            Self::Io(e) => write!(f, "upload failed: {e}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::FileTooLarge => Self::TooLarge,
+            std::io::ErrorKind::InvalidData => Self::BadRequest(e.to_string()),
+            _ => Self::Io(e),
+        }
+    }
+}
+
+/// How the end of a request body is found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyLength {
+    /// `Content-Length`.
+    Fixed(u64),
+    /// `Transfer-Encoding: chunked`, as Finder sends, with the most
+    /// decoded bytes accepted.
+    Chunked { max: u64 },
+}
+
+/// Decoding state of a chunked body.
+struct Chunked {
+    /// Bytes read from the stream but not decoded yet.
+    raw: Vec<u8>,
+    /// Data left in the current chunk.
+    left: u64,
+    /// A CRLF ends the chunk data just read.
+    after_data: bool,
+    total: u64,
+    max: u64,
+    done: bool,
+}
+
+/// A request body: whatever followed the head in the first read, then the
+/// rest of the stream. Sends `100 Continue` before the first read if the
+/// client asked for it.
+pub struct BodyReader<S> {
+    stream: S,
+    buf: Vec<u8>,
+    remaining: u64,
+    chunked: Option<Chunked>,
+    send_continue: bool,
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// Append up to `want` bytes from `stream` to `into`.
+async fn read_more<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    send_continue: &mut bool,
+    into: &mut Vec<u8>,
+    want: usize,
+) -> std::io::Result<()> {
+    if std::mem::take(send_continue) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+    }
+    let start = into.len();
+    into.resize(start + want, 0);
+    let n = stream.read(&mut into[start..]).await?;
+    into.truncate(start + n);
+    if n == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> BodyReader<S> {
+    pub fn new(stream: S, mut leftover: Vec<u8>, length: BodyLength, send_continue: bool) -> Self {
+        let (buf, remaining, chunked) = match length {
+            BodyLength::Fixed(len) => {
+                leftover.truncate(usize::try_from(len).unwrap_or(usize::MAX));
+                let remaining = len - leftover.len() as u64;
+                (leftover, remaining, None)
+            }
+            BodyLength::Chunked { max } => {
+                let chunked = Chunked {
+                    raw: leftover,
+                    left: 0,
+                    after_data: false,
+                    total: 0,
+                    max,
+                    done: false,
+                };
+                (Vec::new(), 0, Some(chunked))
+            }
+        };
+        Self {
+            stream,
+            buf,
+            remaining,
+            chunked,
+            send_continue,
+        }
+    }
+
+    /// Read more of the body into `buf`; false once it's all been read.
+    async fn fill(&mut self) -> std::io::Result<bool> {
+        if self.chunked.is_some() {
+            return self.fill_chunked().await;
+        }
+        if self.remaining == 0 {
+            return Ok(false);
+        }
+        let want = usize::try_from(self.remaining).map_or(READ_CHUNK, |r| r.min(READ_CHUNK));
+        let start = self.buf.len();
+        read_more(
+            &mut self.stream,
+            &mut self.send_continue,
+            &mut self.buf,
+            want,
+        )
+        .await?;
+        self.remaining -= (self.buf.len() - start) as u64;
+        Ok(true)
+    }
+
+    async fn fill_chunked(&mut self) -> std::io::Result<bool> {
+        let Some(chunked) = &mut self.chunked else {
+            return Ok(false);
+        };
+        loop {
+            if chunked.done {
+                return Ok(false);
+            }
+            if chunked.left > 0 {
+                if !chunked.raw.is_empty() {
+                    let n = usize::try_from(chunked.left)
+                        .map_or(chunked.raw.len(), |left| left.min(chunked.raw.len()));
+                    self.buf.extend(chunked.raw.drain(..n));
+                    chunked.left -= n as u64;
+                    return Ok(true);
+                }
+            } else if let Some(at) = find(&chunked.raw, b"\r\n") {
+                let line: Vec<u8> = chunked.raw.drain(..at + 2).take(at).collect();
+                if std::mem::take(&mut chunked.after_data) {
+                    if !line.is_empty() {
+                        return Err(invalid("chunk longer than its size"));
+                    }
+                    continue;
+                }
+                // Chunk extensions after `;` are ignored
+                let size = std::str::from_utf8(&line)
+                    .ok()
+                    .and_then(|l| l.split(';').next())
+                    .and_then(|l| u64::from_str_radix(l.trim(), 16).ok())
+                    .ok_or_else(|| invalid("bad chunk size"))?;
+                if size == 0 {
+                    // Trailers, if any, are left unread
+                    chunked.done = true;
+                    return Ok(false);
+                }
+                chunked.total = chunked.total.saturating_add(size);
+                if chunked.total > chunked.max {
+                    return Err(std::io::ErrorKind::FileTooLarge.into());
+                }
+                chunked.left = size;
+                chunked.after_data = true;
+                continue;
+            } else if chunked.raw.len() > MAX_PART_HEAD_BYTES {
+                return Err(invalid("bad chunk size"));
+            }
+            read_more(
+                &mut self.stream,
+                &mut self.send_continue,
+                &mut chunked.raw,
+                READ_CHUNK,
+            )
+            .await?;
+        }
+    }
+
+    /// Write the rest of the body to `out`.
+    pub async fn copy_to(&mut self, out: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<u64> {
+        let mut written = 0;
+        loop {
+            out.write_all(&self.buf).await?;
+            written += self.buf.len() as u64;
+            self.buf.clear();
+            if !self.fill().await? {
+                return Ok(written);
+            }
+        }
+    }
+
+    /// The next `n` bytes, fewer only if the body ends first.
+    async fn peek(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        while self.buf.len() < n && self.fill().await? {}
+        Ok(&self.buf[..n.min(self.buf.len())])
+    }
+
+    /// Consume up to and including `delimiter`, returning what came before
+    /// it; `None` if the body ends first or it's further than `max` bytes.
+    async fn read_until(
+        &mut self,
+        delimiter: &[u8],
+        max: usize,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(at) = find(&self.buf, delimiter) {
+                let rest = self.buf.split_off(at + delimiter.len());
+                let mut before = std::mem::replace(&mut self.buf, rest);
+                before.truncate(at);
+                return Ok(Some(before));
+            }
+            if self.buf.len() > max || !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Like `read_until`, but streams what comes before `delimiter` to
+    /// `out` instead of buffering it.
+    async fn copy_until(
+        &mut self,
+        delimiter: &[u8],
+        out: &mut (impl AsyncWrite + Unpin),
+    ) -> std::io::Result<Option<u64>> {
+        let mut written = 0;
+        loop {
+            if let Some(at) = find(&self.buf, delimiter) {
+                out.write_all(&self.buf[..at]).await?;
+                self.buf.drain(..at + delimiter.len());
+                return Ok(Some(written + at as u64));
+            }
+            // Keep a tail that could be the start of the delimiter
+            let keep = delimiter.len().saturating_sub(1).min(self.buf.len());
+            let flush = self.buf.len() - keep;
+            out.write_all(&self.buf[..flush]).await?;
+            self.buf.drain(..flush);
+            written += flush as u64;
+            if !self.fill().await? {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// A single plain file name, or `None` if it could name anything else.
+pub fn file_name(name: &str) -> Option<&str> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(['/', '\\']) => Some(name),
+        _ => None,
+    }
+}
+
+pub fn temp_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!(".{name}.{}.part", uuid::Uuid::new_v4().simple()))
+}
+
+/// `photo.jpg`, `photo (1).jpg`, ... whichever doesn't exist yet.
+pub fn unused_name(dir: &Path, name: &str) -> String {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while dir.join(&candidate).exists() {
+        candidate = format!("{stem} ({n}){ext}");
+        n += 1;
+    }
+    candidate
+}
+
+/// Move a finished temp file into place.
+fn commit(temp: &Path, target: &Path, overwrite: bool) -> Result<(), UploadError> {
+    if !overwrite && target.exists() {
+        std::fs::remove_file(temp).ok();
+        let name = target.file_name().unwrap_or_default().to_string_lossy();
+        return Err(UploadError::Conflict(name.into_owned()));
+    }
+    std::fs::rename(temp, target).map_err(|e| {
+        std::fs::remove_file(temp).ok();
+        UploadError::Io(e)
+    })
+}
+
+/// Stream the body into a temp file in `dir`; `write` returns the size,
+/// or `None` if the body ended early.
+async fn write_temp<F>(dir: &Path, name: &str, write: F) -> Result<(PathBuf, u64), UploadError>
+where
+    F: AsyncFnOnce(&mut tokio::fs::File) -> std::io::Result<Option<u64>>,
+{
+    let temp = temp_path(dir, name);
+    let mut file = tokio::fs::File::create(&temp).await?;
+    let result = match write(&mut file).await {
+        Ok(Some(bytes)) => file.sync_all().await.map(|()| Some(bytes)),
+        other => other,
+    };
+    drop(file);
+    match result {
+        Ok(Some(bytes)) => Ok((temp, bytes)),
+        Ok(None) => {
+            std::fs::remove_file(&temp).ok();
+            Err(UploadError::BadRequest("body ended early".to_string()))
+        }
+        Err(e) => {
+            std::fs::remove_file(&temp).ok();
+            Err(e.into())
+        }
+    }
+}
+
+/// Write the whole body to `target`, whose folder has already been checked
+/// to be inside the served root. Returns the size and whether a file was
+/// replaced.
+pub async fn save_put<S: AsyncRead + AsyncWrite + Unpin>(
+    body: &mut BodyReader<S>,
+    target: &Path,
+    options: &Uploads,
+) -> Result<(u64, bool), UploadError> {
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return Err(UploadError::BadRequest("no file name".to_string()));
+    };
+    let name = name.to_string_lossy();
+    if target.is_dir() {
+        return Err(UploadError::Conflict(name.into_owned()));
+    }
+    if !options.overwrite && target.exists() {
+        return Err(UploadError::Conflict(name.into_owned()));
+    }
+    let (temp, bytes) =
+        write_temp(dir, &name, async |file| body.copy_to(file).await.map(Some)).await?;
+    let replaced = target.exists();
+    commit(&temp, target, options.overwrite)?;
+    Ok((bytes, replaced))
+}
+
+/// `filename` from a part's `Content-Disposition`, if it's a file part.
+fn part_filename(head: &str) -> Option<String> {
+    let disposition = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Disposition"))?
+        .1;
+    let start = disposition.find("filename=\"")? + "filename=\"".len();
+    let len = disposition[start..].find('"')?;
+    Some(disposition[start..start + len].to_string())
+}
+
+/// `boundary` from a `multipart/form-data` content type.
+pub fn multipart_boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';').map(str::trim);
+    if !params.next()?.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .find_map(|p| p.strip_prefix("boundary="))
+        .map(|b| b.trim_matches('"'))
+        .filter(|b| !b.is_empty())
+}
+
+/// Write each file part of a multipart body into `dir`. Returns the names
+/// written and their sizes.
+pub async fn save_multipart<S: AsyncRead + AsyncWrite + Unpin>(
+    body: &mut BodyReader<S>,
+    boundary: &str,
+    dir: &Path,
+    options: &Uploads,
+) -> Result<Vec<(String, u64)>, UploadError> {
+    let bad = |msg: &str| UploadError::BadRequest(msg.to_string());
+    let first = format!("--{boundary}\r\n");
+    let delimiter = format!("\r\n--{boundary}");
+    body.read_until(first.as_bytes(), MAX_PART_HEAD_BYTES)
+        .await?
+        .ok_or_else(|| bad("no opening boundary"))?;
+
+    let mut saved = Vec::new();
+    loop {
+        let head = body
+            .read_until(b"\r\n\r\n", MAX_PART_HEAD_BYTES)
+            .await?
+            .ok_or_else(|| bad("truncated part headers"))?;
+        let filename = part_filename(&String::from_utf8_lossy(&head));
+        match filename.as_deref().filter(|f| !f.is_empty()) {
+            Some(filename) => {
+                // Some browsers send the full client path
+                let base = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+                let name = file_name(base).ok_or_else(|| bad("invalid file name"))?;
+                let name = if options.overwrite {
+                    name.to_string()
+                } else {
+                    unused_name(dir, name)
+                };
+                let (temp, bytes) = write_temp(dir, &name, async |file| {
+                    body.copy_until(delimiter.as_bytes(), file).await
+                })
+                .await?;
+                commit(&temp, &dir.join(&name), options.overwrite)?;
+                saved.push((name, bytes));
+            }
+            // Plain form fields aren't needed
+            None => {
+                body.copy_until(delimiter.as_bytes(), &mut tokio::io::sink())
+                    .await?
+                    .ok_or_else(|| bad("truncated part"))?;
+            }
+        }
+        // `--` closes the body, CRLF starts another part
+        match body.peek(2).await? {
+            b"--" => return Ok(saved),
+            b"\r\n" => {
+                body.buf.drain(..2);
+            }
+            _ => return Err(bad("malformed boundary")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(body: &[u8], leftover: usize) -> BodyReader<tokio::io::DuplexStream> {
+        reader_with(body, leftover, BodyLength::Fixed(body.len() as u64))
+    }
+
+    fn reader_with(
+        body: &[u8],
+        leftover: usize,
+        length: BodyLength,
+    ) -> BodyReader<tokio::io::DuplexStream> {
+        let (client, server) = tokio::io::duplex(READ_CHUNK);
+        let rest = body[leftover..].to_vec();
+        tokio::spawn(async move {
+            let mut client = client;
+            client.write_all(&rest).await.unwrap();
+            // Keep the write side open until the reader is done
+            let mut sink = Vec::new();
+            client.read_to_end(&mut sink).await.ok();
+        });
+        BodyReader::new(server, body[..leftover].to_vec(), length, false)
+    }
+
+    #[tokio::test]
+    async fn test_save_put() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("notes.txt");
+        let options = Uploads::default();
+
+        let mut body = reader(b"hello upload", 5);
+        assert_eq!(
+            save_put(&mut body, &target, &options).await.unwrap(),
+            (12, false)
+        );
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "hello upload");
+
+        let mut body = reader(b"again", 0);
+        let err = save_put(&mut body, &target, &options).await.unwrap_err();
+        assert_eq!(err.status(), 409);
+        let overwrite = Uploads {
+            overwrite: true,
+            ..Uploads::default()
+        };
+        let mut body = reader(b"again", 0);
+        assert_eq!(
+            save_put(&mut body, &target, &overwrite).await.unwrap(),
+            (5, true)
+        );
+        // Only the target is left, no temp files
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("finder.txt");
+        let body = b"5;name=x\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n";
+        let options = Uploads::default();
+
+        let mut chunked = reader_with(body, 4, BodyLength::Chunked { max: 100 });
+        assert_eq!(
+            save_put(&mut chunked, &target, &options).await.unwrap(),
+            (12, false)
+        );
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "hello, world");
+
+        let other = tmp.path().join("other.txt");
+        let mut too_big = reader_with(body, 0, BodyLength::Chunked { max: 8 });
+        let err = save_put(&mut too_big, &other, &options).await.unwrap_err();
+        assert_eq!(err.status(), 413);
+        let mut malformed = reader_with(
+            b"zz\r\nhello\r\n0\r\n\r\n",
+            0,
+            BodyLength::Chunked { max: 100 },
+        );
+        let err = save_put(&mut malformed, &other, &options)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), 400);
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_save_multipart() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "old").unwrap();
+        let big = "x".repeat(3 * READ_CHUNK);
+        let body = format!(
+            "preamble\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+             just a field\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"C:\\\\Users\\\\me\\\\a.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             new\r\n--XyZ\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"big.bin\"\r\n\r\n\
+             {big}\r\n--XyZ--"
+        );
+
+        let mut body = reader(body.as_bytes(), 40);
+        let saved = save_multipart(&mut body, "XyZ", tmp.path(), &Uploads::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            saved,
+            [
+                ("a (1).txt".to_string(), 3),
+                ("big.bin".to_string(), big.len() as u64)
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("a.txt")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("big.bin")).unwrap(),
+            big
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_multipart_rejects_bad_bodies() {
+        let tmp = tempfile::tempdir().unwrap();
+        let options = Uploads::default();
+        for body in [
+            "no boundary here".to_string(),
+            "--b\r\nContent-Disposition: form-data; filename=\"..\"\r\n\r\nx\r\n--b--".to_string(),
+            "--b\r\nContent-Disposition: form-data; filename=\"cut.txt\"\r\n\r\nnever ends"
+                .to_string(),
+        ] {
+            let mut body = reader(body.as_bytes(), 0);
+            let err = save_multipart(&mut body, "b", tmp.path(), &options)
+                .await
+                .unwrap_err();
+            assert_eq!(err.status(), 400, "{err}");
+        }
+        assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_multipart_boundary() {
+        assert_eq!(
+            multipart_boundary("multipart/form-data; boundary=\"abc\""),
+            Some("abc")
+        );
+        assert_eq!(multipart_boundary("multipart/form-data"), None);
+        assert_eq!(multipart_boundary("text/plain; boundary=abc"), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! Request and bandwidth totals for native servers that outlive restarts,
+//! for the usage dashboard. Servers get new ids each launch, so totals are
+//! kept per served folder, rolled up by UTC day in `usage.json` in the app
+//! data dir. Recording only touches memory; the file is written once a
+//! minute and on quit.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, State};
+
+use super::http_server::RequestLog;
+use super::logging::UtcTime;
+
+const USAGE_FILENAME: &str = "usage.json";
+const FLUSH_INTERVAL: Duration = Duration::from_mins(1);
+/// Daily rollups kept; older days are dropped.
+const MAX_DAYS: usize = 400;
+/// Client addresses remembered per folder per day. Past this, requests
+/// are still counted but new addresses aren't.
+const MAX_IPS_PER_DAY: usize = 10_000;
+
+/// One folder's traffic on one day.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+struct DayCounters {
+    requests: u64,
+    bytes: u64,
+    ips: BTreeSet<String>,
+    /// Unix time of the last request.
+    last_active: u64,
+}
+
+/// Everything in `usage.json`: day (`YYYY-MM-DD`) to folder to counters.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+struct UsageLog {
+    days: BTreeMap<String, BTreeMap<String, DayCounters>>,
+}
+
+/// How far back `stats_get` looks, counting today.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StatsRange {
+    Today,
+    Week,
+    Month,
+    Year,
+    #[default]
+    All,
+}
+
+impl StatsRange {
+    fn days(self) -> Option<u64> {
+        match self {
+            Self::Today => Some(1),
+            Self::Week => Some(7),
+            Self::Month => Some(30),
+            Self::Year => Some(365),
+            Self::All => None,
+        }
+    }
+}
+
+/// Totals over a range, for all folders or one.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub bytes: u64,
+    pub unique_ips: usize,
+    /// Unix time of the last request, if there was one.
+    pub last_active: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderUsage {
+    pub root: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DayUsage {
+    /// `YYYY-MM-DD`, UTC.
+    pub date: String,
+    pub requests: u64,
+    pub bytes: u64,
+    pub unique_ips: usize,
+}
+
+/// What `stats_get` returns.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageReport {
+    pub totals: UsageTotals,
+    /// Busiest first.
+    pub folders: Vec<FolderUsage>,
+    /// Oldest first; days without traffic are left out.
+    pub days: Vec<DayUsage>,
+}
+
+fn date(secs: u64) -> String {
+    let t = UtcTime::from_unix(secs);
+    format!("{:04}-{:02}-{:02}", t.year, t.month, t.day)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Adds up counters, merging their addresses.
+#[derive(Default)]
+struct Sum<'a> {
+    requests: u64,
+    bytes: u64,
+    ips: BTreeSet<&'a str>,
+    last_active: u64,
+}
+
+impl<'a> Sum<'a> {
+    fn add(&mut self, counters: &'a DayCounters) {
+        self.requests += counters.requests;
+        self.bytes += counters.bytes;
+        self.ips.extend(counters.ips.iter().map(String::as_str));
+        self.last_active = self.last_active.max(counters.last_active);
+    }
+
+    fn totals(&self) -> UsageTotals {
+        UsageTotals {
+            requests: self.requests,
+            bytes: self.bytes,
+            unique_ips: self.ips.len(),
+            last_active: (self.last_active > 0).then_some(self.last_active),
+        }
+    }
+}
+
+impl UsageLog {
+    /// Count `entry`, served from `root`. Connections that never sent a
+    /// request aren't counted.
+    fn record(&mut self, root: &str, entry: &RequestLog) {
+        if entry.method.is_empty() {
+            return;
+        }
+        let day = self.days.entry(date(entry.time)).or_default();
+        let counters = day.entry(root.to_string()).or_default();
+        counters.requests += 1;
+        counters.bytes += entry.bytes;
+        counters.last_active = counters.last_active.max(entry.time);
+        let ip = entry
+            .remote_address
+            .parse::<SocketAddr>()
+            .map_or(entry.remote_address.clone(), |a| a.ip().to_string());
+        if counters.ips.len() < MAX_IPS_PER_DAY {
+            counters.ips.insert(ip);
+        }
+        while self.days.len() > MAX_DAYS {
+            self.days.pop_first();
+        }
+    }
+
+    /// Totals for `range`, which ends on the day of `now`.
+    fn report(&self, range: StatsRange, now: u64) -> UsageReport {
+        let first = range
+            .days()
+            .map(|days| date(now.saturating_sub((days - 1) * 86_400)));
+        let mut total = Sum::default();
+        let mut folders: BTreeMap<&str, Sum> = BTreeMap::new();
+        let mut days = Vec::new();
+        for (day, counters) in &self.days {
+            if first.as_ref().is_some_and(|first| day < first) {
+                continue;
+            }
+            let mut sum = Sum::default();
+            for (root, counters) in counters {
+                sum.add(counters);
+                total.add(counters);
+                folders.entry(root).or_default().add(counters);
+            }
+            days.push(DayUsage {
+                date: day.clone(),
+                requests: sum.requests,
+                bytes: sum.bytes,
+                unique_ips: sum.ips.len(),
+            });
+        }
+        let mut folders: Vec<_> = folders
+            .into_iter()
+            .map(|(root, sum)| FolderUsage {
+                root: root.to_string(),
+                totals: sum.totals(),
+            })
+            .collect();
+        folders.sort_by_key(|f| std::cmp::Reverse(f.totals.bytes));
+        UsageReport {
+            totals: total.totals(),
+            folders,
+            days,
+        }
+    }
+}
+
+/// The usage log, and where it's saved.
+pub struct Usage {
+    log: Mutex<UsageLog>,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl Usage {
+    pub fn load(app: &tauri::AppHandle) -> Self {
+        let path = super::paths::app_data_dir(app)
+            .ok()
+            .map(|dir| dir.join(USAGE_FILENAME));
+        let log = path
+            .as_ref()
+            .and_then(|p| std::fs::read_to_string(p).ok())
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        Self {
+            log: Mutex::new(log),
+            path,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, root: &str, entry: &RequestLog) {
+        self.log.lock().unwrap().record(root, entry);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the log out if anything was recorded since the last write.
+    pub fn flush(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let log = self.log.lock().unwrap();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        let written = serde_json::to_string(&*log)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            tracing::warn!("usage: write {} failed: {e}", path.display());
+        }
+    }
+}
+
+/// Write the usage log out every minute.
+pub fn spawn_flush(app: &tauri::AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            app.state::<Usage>().flush();
+        }
+    });
+}
+
+/// Usage of native servers over `range`, all time if absent.
+#[tauri::command]
+pub async fn stats_get(
+    range: Option<StatsRange>,
+    usage: State<'_, Usage>,
+) -> Result<UsageReport, String> {
+    let log = usage.log.lock().unwrap();
+    Ok(log.report(range.unwrap_or_default(), now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: u64, peer: &str, bytes: u64) -> RequestLog {
+        let mut entry = RequestLog::new(peer.parse().unwrap(), "1".to_string());
+        entry.time = time;
+        entry.method = "GET".to_string();
+        entry.bytes = bytes;
+        entry
+    }
+
+    #[test]
+    fn test_report() {
+        // 2023-11-14 and the days after
+        let day = |n: u64| 1_699_920_000 + n * 86_400;
+        let mut log = UsageLog::default();
+        log.record("/site", &entry(day(0), "10.0.0.1:5000", 100));
+        log.record("/site", &entry(day(0) + 60, "10.0.0.1:5001", 50));
+        log.record("/docs", &entry(day(0), "10.0.0.2:5000", 10));
+        log.record("/site", &entry(day(2), "[::1]:5000", 1000));
+        let mut refused = RequestLog::new("10.0.0.9:1".parse().unwrap(), "2".to_string());
+        refused.time = day(2);
+        log.record("/site", &refused);
+
+        let all = log.report(StatsRange::All, day(2));
+        assert_eq!(
+            all.totals,
+            UsageTotals {
+                requests: 4,
+                bytes: 1160,
+                unique_ips: 3,
+                last_active: Some(day(2)),
+            }
+        );
+        assert_eq!(
+            all.folders
+                .iter()
+                .map(|f| (f.root.as_str(), f.totals.requests))
+                .collect::<Vec<_>>(),
+            [("/site", 3), ("/docs", 1)]
+        );
+        assert_eq!(
+            all.days,
+            [
+                DayUsage {
+                    date: "2023-11-14".to_string(),
+                    requests: 3,
+                    bytes: 160,
+                    unique_ips: 2,
+                },
+                DayUsage {
+                    date: "2023-11-16".to_string(),
+                    requests: 1,
+                    bytes: 1000,
+                    unique_ips: 1,
+                },
+            ]
+        );
+
+        let today = log.report(StatsRange::Today, day(2) + 3_600);
+        assert_eq!(today.totals.requests, 1);
+        assert_eq!(today.folders.len(), 1);
+        let week = log.report(StatsRange::Week, day(2));
+        assert_eq!(week.totals.requests, 4);
+        assert_eq!(
+            log.report(StatsRange::Week, day(30)),
+            UsageReport::default()
+        );
+
+        let json = serde_json::to_string(&log).unwrap();
+        assert_eq!(serde_json::from_str::<UsageLog>(&json).unwrap(), log);
+    }
+
+    #[test]
+    fn test_max_days() {
+        let mut log = UsageLog::default();
+        for n in 0..=MAX_DAYS as u64 {
+            log.record("/site", &entry(n * 86_400, "10.0.0.1:5000", 1));
+        }
+        assert_eq!(log.days.len(), MAX_DAYS);
+        assert!(!log.days.contains_key("1970-01-01"));
+    }
+}
@@ -0,0 +1,180 @@
+//! Coalescing for the folder watcher: bursts of changes, like an
+//! `npm install` or a `git checkout`, are merged into one batch that is
+//! sent once the folder has been quiet for a while.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Changed paths listed in one batch; `total` counts the rest.
+const MAX_BATCH_PATHS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BatchOptions {
+    /// Quiet time after the last change before a batch is sent.
+    pub debounce_ms: u64,
+    /// Longest a batch is held back while changes keep coming.
+    pub max_wait_ms: u64,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 200,
+            max_wait_ms: 2000,
+        }
+    }
+}
+
+/// Changes merged over one burst.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Batch {
+    /// The first changed URL paths, sorted.
+    pub paths: Vec<String>,
+    /// Changed paths, including any not listed.
+    pub total: usize,
+    /// Changed paths by parent folder.
+    pub dirs: BTreeMap<String, usize>,
+    /// Some changes may have been missed, so treat everything as changed.
+    pub overflow: bool,
+}
+
+impl Batch {
+    /// A batch for when changes were missed and nothing more is known.
+    pub fn overflowed() -> Self {
+        Self {
+            overflow: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// Collects changes until a batch is due.
+#[derive(Debug)]
+pub struct Coalescer {
+    debounce: Duration,
+    max_wait: Duration,
+    paths: BTreeSet<String>,
+    overflow: bool,
+    /// When the pending batch started, and when it last grew.
+    pending: Option<(Instant, Instant)>,
+}
+
+impl Coalescer {
+    pub fn new(options: &BatchOptions) -> Self {
+        Self {
+            debounce: Duration::from_millis(options.debounce_ms),
+            max_wait: Duration::from_millis(options.max_wait_ms),
+            paths: BTreeSet::new(),
+            overflow: false,
+            pending: None,
+        }
+    }
+
+    /// Add changes seen at `now`; `overflow` if some may have been missed.
+    pub fn add(&mut self, paths: Vec<String>, overflow: bool, now: Instant) {
+        if paths.is_empty() && !overflow {
+            return;
+        }
+        self.paths.extend(paths);
+        self.overflow |= overflow;
+        let started = self.pending.map_or(now, |(started, _)| started);
+        self.pending = Some((started, now));
+    }
+
+    /// How long until the pending batch is sent if nothing else changes.
+    pub fn due(&self, now: Instant) -> Option<Duration> {
+        let (started, last) = self.pending?;
+        let due = (last + self.debounce).min(started + self.max_wait);
+        Some(due.saturating_duration_since(now))
+    }
+
+    /// The pending batch, if the folder has been quiet long enough or it
+    /// has waited as long as it may.
+    pub fn take(&mut self, now: Instant) -> Option<Batch> {
+        let (started, last) = self.pending?;
+        let quiet = now.saturating_duration_since(last) >= self.debounce;
+        if !quiet && now.saturating_duration_since(started) < self.max_wait {
+            return None;
+        }
+        self.pending = None;
+        let paths = std::mem::take(&mut self.paths);
+        let mut dirs = BTreeMap::new();
+        for path in &paths {
+            let dir = match path.rsplit_once('/') {
+                Some(("", _)) | None => "/",
+                Some((dir, _)) => dir,
+            };
+            *dirs.entry(dir.to_string()).or_insert(0) += 1;
+        }
+        Some(Batch {
+            total: paths.len(),
+            paths: paths.into_iter().take(MAX_BATCH_PATHS).collect(),
+            dirs,
+            overflow: std::mem::take(&mut self.overflow),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesce() {
+        let mut coalescer = Coalescer::new(&BatchOptions {
+            debounce_ms: 100,
+            max_wait_ms: 1000,
+        });
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(coalescer.take(at(0)), None);
+
+        coalescer.add(
+            vec!["/index.html".into(), "/css/a.css".into()],
+            false,
+            at(0),
+        );
+        coalescer.add(
+            vec!["/css/a.css".into(), "/css/b.css".into()],
+            false,
+            at(50),
+        );
+        assert_eq!(coalescer.due(at(60)), Some(Duration::from_millis(90)));
+        // Still settling
+        assert_eq!(coalescer.take(at(120)), None);
+        let batch = coalescer.take(at(150)).unwrap();
+        assert_eq!(batch.paths, ["/css/a.css", "/css/b.css", "/index.html"]);
+        assert_eq!(batch.total, 3);
+        assert_eq!(
+            batch.dirs,
+            BTreeMap::from([("/".into(), 1), ("/css".into(), 2)])
+        );
+        assert!(!batch.overflow);
+        assert_eq!(coalescer.take(at(400)), None);
+
+        // Changes that never stop are sent after the longest wait
+        for ms in (1000..=2000).step_by(50) {
+            let paths = (0..10)
+                .map(|i| format!("/node_modules/{ms}/{i}.js"))
+                .collect();
+            coalescer.add(paths, false, at(ms));
+            if let Some(batch) = coalescer.take(at(ms)) {
+                assert_eq!(ms, 2000);
+                assert_eq!(batch.total, 210);
+                assert_eq!(batch.paths.len(), MAX_BATCH_PATHS);
+                assert_eq!(batch.dirs.len(), 21);
+            }
+        }
+
+        coalescer.add(Vec::new(), false, at(3000));
+        assert_eq!(coalescer.take(at(4000)), None);
+        coalescer.add(Vec::new(), true, at(3000));
+        let batch = coalescer.take(at(4000)).unwrap();
+        assert!(batch.overflow);
+        assert_eq!(batch, Batch::overflowed());
+    }
+}
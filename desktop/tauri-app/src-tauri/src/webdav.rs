@@ -0,0 +1,346 @@
+//! DAV (RFC 4918) support for `http_server`, so a served folder can be mounted as
+//! a network drive. This module has the protocol pieces: the lock table,
+//! multistatus XML, and header parsing. Verbs that change files are only
+//! accepted when the server has auth configured.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Methods handled by the DAV layer rather than plain file serving.
+pub const METHODS: [&str; 10] = [
+    "OPTIONS",
+    "PROPFIND",
+    "PROPPATCH",
+    "MKCOL",
+    "PUT",
+    "DELETE",
+    "COPY",
+    "MOVE",
+    "LOCK",
+    "UNLOCK",
+];
+/// Methods that change the served folder.
+pub const WRITE_METHODS: [&str; 8] = [
+    "PROPPATCH",
+    "MKCOL",
+    "PUT",
+    "DELETE",
+    "COPY",
+    "MOVE",
+    "LOCK",
+    "UNLOCK",
+];
+pub const ALLOW: &str =
+    "OPTIONS, GET, HEAD, PROPFIND, PROPPATCH, MKCOL, PUT, DELETE, COPY, MOVE, LOCK, UNLOCK";
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_mins(10);
+const MAX_LOCK_TIMEOUT: Duration = Duration::from_hours(1);
+
+#[derive(Debug)]
+struct Lock {
+    token: String,
+    expires: Instant,
+}
+
+/// Exclusive write locks by URL path. Locks on a folder cover everything
+/// inside it.
+#[derive(Debug, Default)]
+pub struct LockTable(Mutex<HashMap<String, Lock>>);
+
+/// Turns DAV on for a server. Clones share one lock table, so every
+/// connection sees the same locks.
+#[derive(Clone, Debug, Default)]
+pub struct WebDav {
+    pub locks: Arc<LockTable>,
+}
+
+impl PartialEq for WebDav {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.locks, &other.locks)
+    }
+}
+
+impl Eq for WebDav {}
+
+/// Whether `path` is `ancestor` or inside it.
+fn covers(ancestor: &str, path: &str) -> bool {
+    let ancestor = ancestor.trim_end_matches('/');
+    path.trim_end_matches('/') == ancestor
+        || path.starts_with(&format!("{ancestor}/"))
+        || ancestor.is_empty()
+}
+
+impl LockTable {
+    /// The token locking `path` or a folder above it, if any.
+    fn holder(&self, path: &str) -> Option<String> {
+        let mut locks = self.0.lock().unwrap();
+        let now = Instant::now();
+        locks.retain(|_, lock| lock.expires > now);
+        locks
+            .iter()
+            .find(|(locked, _)| covers(locked, path))
+            .map(|(_, lock)| lock.token.clone())
+    }
+
+    /// Whether a write to `path` may go ahead: it isn't locked, or the
+    /// request's `If` header names the lock.
+    pub fn allows(&self, path: &str, if_header: Option<&str>) -> bool {
+        match self.holder(path) {
+            Some(token) => if_header.is_some_and(|h| h.contains(&token)),
+            None => true,
+        }
+    }
+
+    /// Lock `path` and return the new token, or `None` if something else
+    /// holds a lock on it.
+    pub fn lock(&self, path: &str, timeout: Duration) -> Option<String> {
+        let mut locks = self.0.lock().unwrap();
+        let now = Instant::now();
+        locks.retain(|_, lock| lock.expires > now);
+        let conflict = locks
+            .keys()
+            .any(|locked| covers(locked, path) || covers(path, locked));
+        if conflict {
+            return None;
+        }
+        let token = format!("opaquelocktoken:{}", uuid::Uuid::new_v4());
+        locks.insert(
+            path.to_string(),
+            Lock {
+                token: token.clone(),
+                expires: now + timeout,
+            },
+        );
+        Some(token)
+    }
+
+    /// Extend the lock whose token appears in `if_header`. Returns the
+    /// locked path and token.
+    pub fn refresh(&self, if_header: &str, timeout: Duration) -> Option<(String, String)> {
+        let mut locks = self.0.lock().unwrap();
+        let (path, lock) = locks
+            .iter_mut()
+            .find(|(_, lock)| if_header.contains(&lock.token))?;
+        lock.expires = Instant::now() + timeout;
+        Some((path.clone(), lock.token.clone()))
+    }
+
+    /// Release a lock by its token, as sent in `Lock-Token: <token>`.
+    pub fn unlock(&self, token: &str) -> bool {
+        let token = token.trim().trim_start_matches('<').trim_end_matches('>');
+        let mut locks = self.0.lock().unwrap();
+        let before = locks.len();
+        locks.retain(|_, lock| lock.token != token);
+        locks.len() != before
+    }
+
+    /// Drop locks on `path` and below, after it's deleted or moved away.
+    pub fn forget(&self, path: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|locked, _| !covers(path, locked));
+    }
+}
+
+/// `Timeout: Second-600` as a duration, capped at an hour.
+pub fn lock_timeout(header: Option<&str>) -> Duration {
+    header
+        .and_then(|h| h.split(',').next())
+        .and_then(|t| t.trim().strip_prefix("Second-"))
+        .and_then(|secs| secs.parse().ok())
+        .map_or(DEFAULT_LOCK_TIMEOUT, |secs| {
+            Duration::from_secs(secs).min(MAX_LOCK_TIMEOUT)
+        })
+}
+
+/// The still-encoded path of a `Destination` header, which may be an
+/// absolute URL.
+pub fn destination_path(header: &str) -> Option<&str> {
+    let path = match header.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => header,
+    };
+    path.split(['?', '#']).next().filter(|p| p.starts_with('/'))
+}
+
+/// Copy a file, or a folder and everything in it.
+pub fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Properties of one resource in a `PROPFIND` response.
+pub struct Resource {
+    /// Percent-encoded URL path; folders end with `/`.
+    pub href: String,
+    pub name: String,
+    pub is_dir: bool,
+    pub len: u64,
+    /// `http_date` of the modification time.
+    pub modified: Option<String>,
+    pub content_type: Option<String>,
+}
+
+const SUPPORTED_LOCK: &str =
+    "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+    <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>";
+
+/// `207 Multi-Status` body for `PROPFIND`.
+pub fn multistatus(resources: &[Resource]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    for r in resources {
+        let _ = write!(
+            xml,
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+             <D:displayname>{}</D:displayname>",
+            xml_escape(&r.href),
+            xml_escape(&r.name)
+        );
+        if r.is_dir {
+            xml.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+        } else {
+            let _ = write!(
+                xml,
+                "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>",
+                r.len
+            );
+        }
+        let optional = [
+            ("getlastmodified", &r.modified),
+            ("getcontenttype", &r.content_type),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                let _ = write!(xml, "<D:{name}>{}</D:{name}>", xml_escape(value));
+            }
+        }
+        xml.push_str(SUPPORTED_LOCK);
+        xml.push_str("</D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n");
+    }
+    xml.push_str("</D:multistatus>\n");
+    xml
+}
+
+/// `207 Multi-Status` body for `PROPPATCH`. Dead properties aren't stored,
+/// but clients (Finder, Office) give up on a resource if this fails.
+pub fn proppatch_ok(href: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n\
+         <D:response><D:href>{}</D:href><D:propstat><D:prop/>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n</D:multistatus>\n",
+        xml_escape(href)
+    )
+}
+
+/// Body for a successful `LOCK`.
+pub fn lock_discovery(href: &str, token: &str, timeout: Duration) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>\
+         <D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:depth>infinity</D:depth><D:timeout>Second-{}</D:timeout>\
+         <D:locktoken><D:href>{token}</D:href></D:locktoken>\
+         <D:lockroot><D:href>{}</D:href></D:lockroot>\
+         </D:activelock></D:lockdiscovery></D:prop>\n",
+        timeout.as_secs(),
+        xml_escape(href)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locks() {
+        let locks = LockTable::default();
+        let token = locks.lock("/docs", Duration::from_mins(1)).unwrap();
+        assert!(locks.lock("/docs/a.txt", Duration::from_mins(1)).is_none());
+        assert!(locks.lock("/", Duration::from_mins(1)).is_none());
+        assert!(locks.lock("/docs2", Duration::from_mins(1)).is_some());
+
+        assert!(!locks.allows("/docs/a.txt", None));
+        assert!(locks.allows("/docs/a.txt", Some(&format!("(<{token}>)"))));
+        assert!(locks.allows("/other", None));
+
+        let (path, refreshed) = locks
+            .refresh(&format!("(<{token}>)"), Duration::from_mins(1))
+            .unwrap();
+        assert_eq!(
+            (path.as_str(), refreshed.as_str()),
+            ("/docs", token.as_str())
+        );
+
+        assert!(locks.unlock(&format!("<{token}>")));
+        assert!(!locks.unlock(&token));
+        assert!(locks.allows("/docs/a.txt", None));
+
+        let expired = locks.lock("/tmp", Duration::ZERO).unwrap();
+        assert!(locks.allows("/tmp", None));
+        assert!(!locks.unlock(&expired));
+    }
+
+    #[test]
+    fn test_headers() {
+        assert_eq!(lock_timeout(None), DEFAULT_LOCK_TIMEOUT);
+        assert_eq!(
+            lock_timeout(Some("Second-30, Infinite")),
+            Duration::from_secs(30)
+        );
+        assert_eq!(lock_timeout(Some("Infinite")), DEFAULT_LOCK_TIMEOUT);
+        assert_eq!(lock_timeout(Some("Second-999999")), MAX_LOCK_TIMEOUT);
+
+        assert_eq!(
+            destination_path("http://192.168.1.2:8080/a%20b/c.txt"),
+            Some("/a%20b/c.txt")
+        );
+        assert_eq!(destination_path("/x?y"), Some("/x"));
+        assert_eq!(destination_path("http://host"), None);
+    }
+
+    #[test]
+    fn test_multistatus() {
+        let xml = multistatus(&[Resource {
+            href: "/a%26b.txt".to_string(),
+            name: "a&b.txt".to_string(),
+            is_dir: false,
+            len: 3,
+            modified: None,
+            content_type: Some("text/plain".to_string()),
+        }]);
+        assert!(xml.contains("<D:href>/a%26b.txt</D:href>"));
+        assert!(xml.contains("<D:displayname>a&amp;b.txt</D:displayname>"));
+        assert!(xml.contains("<D:getcontentlength>3</D:getcontentlength>"));
+        assert!(!xml.contains("getlastmodified"));
+    }
+
+    #[test]
+    fn test_copy_recursive() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("a/b")).unwrap();
+        std::fs::write(tmp.path().join("a/b/c.txt"), "c").unwrap();
+        copy_recursive(&tmp.path().join("a"), &tmp.path().join("copy")).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("copy/b/c.txt")).unwrap(),
+            "c"
+        );
+    }
+}
@@ -0,0 +1,970 @@
+//! Device-to-device file transfers over WebRTC data channels, so two
+//! machines can send each other files through NATs without either one
+//! opening a port. There's no signalling server: the UI carries the offer
+//! from one side to the other and the answer back (pasted, or as a QR
+//! code), and each is a complete SDP with all its ICE candidates.
+//!
+//! Only the data-channel half of WebRTC is here: ICE, DTLS with the peer's
+//! certificate pinned by the fingerprint in its SDP, and SCTP. Each file
+//! goes over its own data channel labelled with JSON `{"name", "size"}`.
+//! The receiver answers with the text message `done` once the file is
+//! saved, or with an error message, and the sender closes the channel.
+//! Browsers can send and receive the same way.
+//!
+//! Needs the `webrtc` build feature.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::ipc::Channel;
+use tauri::State;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, Notify, OnceCell};
+use tokio::task::JoinHandle;
+use webrtc_data::data_channel::{self, DataChannel};
+use webrtc_dtls::config::{ClientAuthType, Config as DtlsConfig, ExtendedMasterSecretType};
+use webrtc_dtls::conn::DTLSConn;
+use webrtc_dtls::crypto::Certificate;
+use webrtc_ice::agent::agent_config::AgentConfig;
+use webrtc_ice::agent::Agent;
+use webrtc_ice::candidate::candidate_base::unmarshal_candidate;
+use webrtc_ice::candidate::Candidate;
+use webrtc_ice::network_type::NetworkType;
+use webrtc_ice::state::ConnectionState;
+use webrtc_ice::url::Url;
+use webrtc_sctp::association::{self, Association};
+use webrtc_util::Conn;
+
+use super::uploads::{file_name, temp_path, unused_name};
+
+const DEFAULT_STUN: &str = "stun:stun.l.google.com:19302";
+/// Messages sent; every browser takes this much in one.
+const CHUNK_BYTES: usize = 16 * 1024;
+/// Largest message received, and the `max-message-size` we offer.
+const MAX_MESSAGE_BYTES: usize = 256 * 1024;
+/// Sending waits while more than this is queued, until it's down to
+/// `LOW_WATER_BYTES`.
+const HIGH_WATER_BYTES: usize = 1024 * 1024;
+const LOW_WATER_BYTES: usize = 256 * 1024;
+/// A `progress` event goes out each time this much more has moved.
+const PROGRESS_BYTES: u64 = 1024 * 1024;
+/// How long gathering ICE candidates can take, mostly waiting on STUN.
+const GATHER_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_mins(1);
+/// How long the receiver has to confirm a file once it's all sent.
+const REPLY_TIMEOUT: Duration = Duration::from_mins(1);
+/// The receiver's reply once a file is saved.
+const DONE: &str = "done";
+
+fn default_ice_servers() -> Vec<IceServer> {
+    vec![IceServer {
+        urls: vec![DEFAULT_STUN.to_string()],
+        username: String::new(),
+        credential: String::new(),
+    }]
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IceServer {
+    /// `stun:` and `turn:` URLs.
+    pub urls: Vec<String>,
+    /// For TURN.
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub credential: String,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerOptions {
+    /// The other device's offer, to answer instead of making one.
+    #[serde(default)]
+    pub offer: Option<String>,
+    /// Where received files go; without it they're refused.
+    #[serde(default)]
+    pub save_dir: Option<String>,
+    /// Defaults to a public STUN server. Two devices that both sit behind
+    /// strict NATs need a TURN server to relay for them.
+    #[serde(default = "default_ice_servers")]
+    pub ice_servers: Vec<IceServer>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerState {
+    /// Offered, waiting for `webrtc_peer_connect` to bring the answer.
+    Offered,
+    Connecting,
+    Connected,
+    Disconnected,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerInfo {
+    pub id: u32,
+    /// Whether this side made the offer.
+    pub offerer: bool,
+    pub save_dir: Option<String>,
+    pub state: PeerState,
+}
+
+/// What `webrtc_peer_create` gives the UI to carry to the other device.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalDescription {
+    pub id: u32,
+    /// An offer, or the answer to `PeerOptions::offer`.
+    pub sdp: String,
+}
+
+/// Streamed over the `onEvent` channel. `transfer` tells apart files
+/// moving at the same time.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum PeerEvent {
+    State {
+        state: PeerState,
+        error: Option<String>,
+    },
+    /// The other device started sending a file.
+    Incoming {
+        transfer: u16,
+        name: String,
+        size: u64,
+    },
+    Progress {
+        transfer: u16,
+        sending: bool,
+        bytes: u64,
+        size: u64,
+    },
+    /// `path` is where a received file was saved.
+    Finished {
+        transfer: u16,
+        sending: bool,
+        path: Option<String>,
+    },
+    Failed {
+        transfer: u16,
+        sending: bool,
+        error: String,
+    },
+}
+
+type Events = Arc<dyn Fn(PeerEvent) + Send + Sync>;
+
+/// The label of a file's data channel.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Header {
+    name: String,
+    size: u64,
+}
+
+/// What's read from the other device's SDP.
+#[derive(Debug, PartialEq, Eq)]
+struct Remote {
+    ufrag: String,
+    pwd: String,
+    /// SHA-256, uppercase hex pairs joined with `:`.
+    fingerprint: String,
+    setup: String,
+    candidates: Vec<String>,
+}
+
+impl Remote {
+    fn parse(sdp: &str) -> Result<Self, String> {
+        let mut ufrag = None;
+        let mut pwd = None;
+        let mut fingerprint = None;
+        let mut setup = None;
+        let mut candidates = Vec::new();
+        for line in sdp.lines() {
+            let Some((key, value)) = line
+                .trim()
+                .strip_prefix("a=")
+                .and_then(|a| a.split_once(':'))
+            else {
+                continue;
+            };
+            match key {
+                "ice-ufrag" => ufrag = Some(value.to_string()),
+                "ice-pwd" => pwd = Some(value.to_string()),
+                "fingerprint" => {
+                    if let Some(hash) = value.strip_prefix("sha-256 ") {
+                        fingerprint = Some(hash.trim().to_ascii_uppercase());
+                    }
+                }
+                "setup" => setup = Some(value.to_string()),
+                "candidate" => candidates.push(value.to_string()),
+                _ => {}
+            }
+        }
+        let missing = |what| format!("not a WebRTC description: no {what}");
+        Ok(Self {
+            ufrag: ufrag.ok_or_else(|| missing("ice-ufrag"))?,
+            pwd: pwd.ok_or_else(|| missing("ice-pwd"))?,
+            fingerprint: fingerprint.ok_or_else(|| missing("sha-256 fingerprint"))?,
+            setup: setup.unwrap_or_else(|| "actpass".to_string()),
+            candidates,
+        })
+    }
+}
+
+/// Whether this side starts the DTLS handshake, going by the other side's
+/// `a=setup`. Offers say `actpass`, so the answer decides.
+fn is_dtls_client(offerer: bool, remote_setup: &str) -> bool {
+    if offerer {
+        remote_setup == "passive"
+    } else {
+        remote_setup != "active"
+    }
+}
+
+/// `AB:CD:...`, as `a=fingerprint` has it.
+fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// A description with one data-channel section and every candidate.
+fn description(
+    session: u64,
+    (ufrag, pwd): (&str, &str),
+    fingerprint: &str,
+    setup: &str,
+    candidates: &[String],
+) -> String {
+    let mut sdp = format!(
+        "v=0\r\n\
+         o=- {session} 2 IN IP4 127.0.0.1\r\n\
+         s=-\r\n\
+         t=0 0\r\n\
+         a=group:BUNDLE 0\r\n\
+         m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+         c=IN IP4 0.0.0.0\r\n\
+         a=ice-ufrag:{ufrag}\r\n\
+         a=ice-pwd:{pwd}\r\n\
+         a=fingerprint:sha-256 {fingerprint}\r\n\
+         a=setup:{setup}\r\n\
+         a=mid:0\r\n\
+         a=sctp-port:5000\r\n\
+         a=max-message-size:{MAX_MESSAGE_BYTES}\r\n"
+    );
+    for candidate in candidates {
+        let _ = write!(sdp, "a=candidate:{candidate}\r\n");
+    }
+    sdp.push_str("a=end-of-candidates\r\n");
+    sdp
+}
+
+async fn new_agent(ice_servers: &[IceServer]) -> Result<Agent, String> {
+    let mut urls = Vec::new();
+    for server in ice_servers {
+        for raw in &server.urls {
+            let mut url = Url::parse_url(raw).map_err(|e| format!("bad ICE server {raw}: {e}"))?;
+            url.username.clone_from(&server.username);
+            url.password.clone_from(&server.credential);
+            urls.push(url);
+        }
+    }
+    Agent::new(AgentConfig {
+        urls,
+        network_types: vec![NetworkType::Udp4, NetworkType::Udp6],
+        ..Default::default()
+    })
+    .await
+    .map_err(|e| format!("ICE agent failed: {e}"))
+}
+
+/// Every local candidate, marshalled for `a=candidate`, or as many as
+/// turned up in `GATHER_TIMEOUT`.
+async fn gather(agent: &Agent) -> Result<Vec<String>, String> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    agent.on_candidate(Box::new(move |candidate| {
+        // `None` once gathering is done
+        let _ = tx.send(candidate.map(|c| c.marshal()));
+        Box::pin(async {})
+    }));
+    agent
+        .gather_candidates()
+        .map_err(|e| format!("gathering ICE candidates failed: {e}"))?;
+    let mut candidates = Vec::new();
+    let _ = tokio::time::timeout(GATHER_TIMEOUT, async {
+        while let Some(Some(candidate)) = rx.recv().await {
+            candidates.push(candidate);
+        }
+    })
+    .await;
+    Ok(candidates)
+}
+
+/// ICE, then DTLS, then SCTP.
+async fn establish(
+    agent: &Agent,
+    certificate: Certificate,
+    remote: &Remote,
+    offerer: bool,
+) -> Result<Link, String> {
+    // Dropping the sender would cancel
+    let (_cancel, cancel_rx) = mpsc::channel(1);
+    let (ufrag, pwd) = (remote.ufrag.clone(), remote.pwd.clone());
+    let conn: Arc<dyn Conn + Send + Sync> = if offerer {
+        agent.dial(cancel_rx, ufrag, pwd).await.map(|c| c as _)
+    } else {
+        agent.accept(cancel_rx, ufrag, pwd).await.map(|c| c as _)
+    }
+    .map_err(|e| format!("ICE failed: {e}"))?;
+
+    let dtls_client = is_dtls_client(offerer, &remote.setup);
+    let config = DtlsConfig {
+        certificates: vec![certificate],
+        // The certificate is self-signed; its fingerprint is checked below
+        insecure_skip_verify: true,
+        client_auth: ClientAuthType::RequireAnyClientCert,
+        extended_master_secret: ExtendedMasterSecretType::Require,
+        ..Default::default()
+    };
+    let dtls = DTLSConn::new(conn, config, dtls_client, None)
+        .await
+        .map_err(|e| format!("DTLS failed: {e}"))?;
+    let state = dtls.connection_state().await;
+    let theirs = state.peer_certificates.first().map(|c| fingerprint(c));
+    if theirs.as_deref() != Some(remote.fingerprint.as_str()) {
+        let _ = dtls.close().await;
+        return Err("the other device's certificate doesn't match its description".to_string());
+    }
+
+    let association = Association::client(association::Config {
+        net_conn: Arc::new(dtls),
+        max_receive_buffer_size: 0,
+        max_message_size: 0,
+        name: "ok200".to_string(),
+    })
+    .await
+    .map_err(|e| format!("SCTP failed: {e}"))?;
+    Ok(Link {
+        association: Arc::new(association),
+        // The DTLS client opens even streams, the server odd ones
+        next_stream: AtomicU16::new(u16::from(!dtls_client)),
+    })
+}
+
+/// A connected peer's SCTP association, carrying the data channels.
+struct Link {
+    association: Arc<Association>,
+    next_stream: AtomicU16,
+}
+
+impl Link {
+    /// Send the file at `path`, resolving once the other device has it.
+    async fn send(&self, path: &Path, events: &Events) -> Result<u64, String> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| format!("not a file: {}", path.display()))?;
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| format!("open failed: {e}"))?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| format!("stat failed: {e}"))?
+            .len();
+        let transfer = self.next_stream.fetch_add(2, Ordering::Relaxed);
+        let label = serde_json::to_string(&Header {
+            name: name.to_string(),
+            size,
+        })
+        .map_err(|e| e.to_string())?;
+        let config = data_channel::Config {
+            label,
+            ..Default::default()
+        };
+        let channel = DataChannel::dial(&self.association, transfer, config)
+            .await
+            .map_err(|e| format!("opening a data channel failed: {e}"))?;
+        let result = upload(&channel, file, size, events).await;
+        let _ = channel.close().await;
+        events(match &result {
+            Ok(()) => PeerEvent::Finished {
+                transfer,
+                sending: true,
+                path: None,
+            },
+            Err(error) => PeerEvent::Failed {
+                transfer,
+                sending: true,
+                error: error.clone(),
+            },
+        });
+        result.map(|()| size)
+    }
+
+    /// Save the files the other device sends, until it goes away.
+    async fn receive_all(&self, save_dir: Option<PathBuf>, events: &Events) {
+        loop {
+            let accepted = DataChannel::accept(
+                &self.association,
+                data_channel::Config::default(),
+                &[] as &[DataChannel],
+            )
+            .await;
+            match accepted {
+                Ok(channel) => {
+                    let (save_dir, events) = (save_dir.clone(), events.clone());
+                    tokio::spawn(async move { receive(channel, save_dir, &events).await });
+                }
+                Err(webrtc_data::Error::ErrStreamClosed) => break,
+                Err(e) => tracing::warn!("bad incoming data channel: {e}"),
+            }
+        }
+    }
+}
+
+async fn upload(
+    channel: &DataChannel,
+    mut file: tokio::fs::File,
+    size: u64,
+    events: &Events,
+) -> Result<(), String> {
+    let transfer = channel.stream_identifier();
+    let drained = Arc::new(Notify::new());
+    channel.set_buffered_amount_low_threshold(LOW_WATER_BYTES);
+    let notify = drained.clone();
+    channel.on_buffered_amount_low(Box::new(move || {
+        notify.notify_one();
+        Box::pin(async {})
+    }));
+
+    // A refusal or a failed save can come before the file is all sent
+    let reader = channel.clone();
+    let mut reply = tokio::spawn(async move { read_reply(&reader).await });
+
+    let mut buf = vec![0; CHUNK_BYTES];
+    let (mut sent, mut reported) = (0, 0);
+    loop {
+        let n = file
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("read failed: {e}"))?;
+        if n == 0 {
+            break;
+        }
+        while channel.buffered_amount() > HIGH_WATER_BYTES {
+            tokio::select! {
+                () = drained.notified() => {}
+                early = &mut reply => return early.map_err(|e| e.to_string())?,
+            }
+        }
+        channel
+            .write(&Bytes::copy_from_slice(&buf[..n]))
+            .await
+            .map_err(|e| format!("send failed: {e}"))?;
+        sent += n as u64;
+        if sent - reported >= PROGRESS_BYTES {
+            reported = sent;
+            events(PeerEvent::Progress {
+                transfer,
+                sending: true,
+                bytes: sent,
+                size,
+            });
+        }
+    }
+    if sent != size {
+        return Err("the file changed while it was being sent".to_string());
+    }
+
+    tokio::time::timeout(REPLY_TIMEOUT, reply)
+        .await
+        .map_err(|_| "the other device didn't confirm the file".to_string())?
+        .map_err(|e| e.to_string())?
+}
+
+/// `Ok` once the receiver says `done`.
+async fn read_reply(channel: &DataChannel) -> Result<(), String> {
+    let mut reply = vec![0; MAX_MESSAGE_BYTES];
+    let (n, _) = channel
+        .read_data_channel(&mut reply)
+        .await
+        .map_err(|e| format!("receive failed: {e}"))?;
+    match String::from_utf8_lossy(&reply[..n]) {
+        reply if reply == DONE => Ok(()),
+        reply if reply.is_empty() => Err("the other device closed the transfer".to_string()),
+        reply => Err(format!("the other device failed: {reply}")),
+    }
+}
+
+/// Save one incoming file, then reply on its channel.
+async fn receive(channel: DataChannel, save_dir: Option<PathBuf>, events: &Events) {
+    let transfer = channel.stream_identifier();
+    let result = match (serde_json::from_str(&channel.config.label), save_dir) {
+        (Ok(header), Some(dir)) => save(&channel, &dir, &header, events).await,
+        (Ok(_), None) => Err("not accepting files".to_string()),
+        (Err(_), _) => Err(format!("unknown data channel: {}", channel.config.label)),
+    };
+    let reply = match &result {
+        Ok(_) => DONE.to_string(),
+        Err(error) => error.clone(),
+    };
+    let _ = channel.write_data_channel(&Bytes::from(reply), true).await;
+    if result.is_err() {
+        // Drops whatever else the sender has queued
+        let _ = channel.close().await;
+    }
+    events(match result {
+        Ok(path) => PeerEvent::Finished {
+            transfer,
+            sending: false,
+            path: Some(path.to_string_lossy().into_owned()),
+        },
+        Err(error) => PeerEvent::Failed {
+            transfer,
+            sending: false,
+            error,
+        },
+    });
+}
+
+async fn save(
+    channel: &DataChannel,
+    dir: &Path,
+    header: &Header,
+    events: &Events,
+) -> Result<PathBuf, String> {
+    let transfer = channel.stream_identifier();
+    let name = file_name(&header.name).ok_or_else(|| format!("bad file name: {}", header.name))?;
+    events(PeerEvent::Incoming {
+        transfer,
+        name: name.to_string(),
+        size: header.size,
+    });
+    let temp = temp_path(dir, name);
+    let written = write_temp(channel, &temp, header.size, events).await;
+    let target = dir.join(unused_name(dir, name));
+    let result = written
+        .and_then(|()| std::fs::rename(&temp, &target).map_err(|e| format!("rename failed: {e}")));
+    if result.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    result.map(|()| target)
+}
+
+async fn write_temp(
+    channel: &DataChannel,
+    temp: &Path,
+    size: u64,
+    events: &Events,
+) -> Result<(), String> {
+    let transfer = channel.stream_identifier();
+    let mut file = tokio::fs::File::create(temp)
+        .await
+        .map_err(|e| format!("create failed: {e}"))?;
+    let mut buf = vec![0; MAX_MESSAGE_BYTES];
+    let (mut received, mut reported) = (0, 0);
+    while received < size {
+        let (n, _) = channel
+            .read_data_channel(&mut buf)
+            .await
+            .map_err(|e| format!("receive failed: {e}"))?;
+        if n == 0 {
+            return Err(format!(
+                "the other device stopped after {received} of {size} bytes"
+            ));
+        }
+        received += n as u64;
+        if received > size {
+            return Err(format!("the other device sent more than {size} bytes"));
+        }
+        file.write_all(&buf[..n])
+            .await
+            .map_err(|e| format!("write failed: {e}"))?;
+        if received - reported >= PROGRESS_BYTES {
+            reported = received;
+            events(PeerEvent::Progress {
+                transfer,
+                sending: false,
+                bytes: received,
+                size,
+            });
+        }
+    }
+    file.flush().await.map_err(|e| format!("write failed: {e}"))
+}
+
+struct Peer {
+    info: Arc<Mutex<PeerInfo>>,
+    agent: Arc<Agent>,
+    /// An offerer's certificate, until the answer comes back.
+    offered: Option<Certificate>,
+    link: Arc<OnceCell<Arc<Link>>>,
+    /// Connects, then receives files.
+    task: Option<JoinHandle<()>>,
+    save_dir: Option<PathBuf>,
+    events: Events,
+}
+
+fn set_state(info: &Mutex<PeerInfo>, events: &Events, state: PeerState, error: Option<String>) {
+    info.lock().unwrap().state = state;
+    events(PeerEvent::State { state, error });
+}
+
+impl Peer {
+    /// A peer, and the offer or answer for the other device.
+    async fn create(
+        id: u32,
+        options: PeerOptions,
+        events: Events,
+    ) -> Result<(Self, String), String> {
+        let save_dir = options
+            .save_dir
+            .as_deref()
+            .map(|dir| {
+                std::fs::canonicalize(dir)
+                    .ok()
+                    .filter(|d| d.is_dir())
+                    .ok_or_else(|| format!("not a folder: {dir}"))
+            })
+            .transpose()?;
+        let remote = options.offer.as_deref().map(Remote::parse).transpose()?;
+        let agent = Arc::new(new_agent(&options.ice_servers).await?);
+        let certificate = Certificate::generate_self_signed(vec!["ok200".to_string()])
+            .map_err(|e| format!("certificate failed: {e}"))?;
+        let local_fingerprint = fingerprint(&certificate.certificate[0].0);
+        let candidates = gather(&agent).await?;
+        let credentials = agent.get_local_user_credentials().await;
+
+        let info = PeerInfo {
+            id,
+            offerer: remote.is_none(),
+            save_dir: options.save_dir,
+            state: PeerState::Offered,
+        };
+        let mut peer = Self {
+            info: Arc::new(Mutex::new(info)),
+            agent: agent.clone(),
+            offered: None,
+            link: Arc::default(),
+            task: None,
+            save_dir,
+            events,
+        };
+        peer.watch_ice();
+        let setup = if let Some(remote) = remote {
+            let setup = if is_dtls_client(false, &remote.setup) {
+                "active"
+            } else {
+                "passive"
+            };
+            peer.start(certificate, remote, false)?;
+            setup
+        } else {
+            peer.offered = Some(certificate);
+            "actpass"
+        };
+        let session = uuid::Uuid::new_v4().as_u64_pair().0 >> 1;
+        let credentials = (credentials.0.as_str(), credentials.1.as_str());
+        let sdp = description(session, credentials, &local_fingerprint, setup, &candidates);
+        Ok((peer, sdp))
+    }
+
+    /// Report the connection dropping once it was up.
+    fn watch_ice(&self) {
+        let (info, events) = (self.info.clone(), self.events.clone());
+        self.agent
+            .on_connection_state_change(Box::new(move |state| {
+                let lost = match state {
+                    ConnectionState::Disconnected => Some(PeerState::Disconnected),
+                    ConnectionState::Failed => Some(PeerState::Failed),
+                    _ => None,
+                };
+                if let Some(lost) = lost {
+                    set_state(&info, &events, lost, None);
+                }
+                Box::pin(async {})
+            }));
+    }
+
+    /// Take the other device's answer to our offer.
+    fn connect(&mut self, answer: &str) -> Result<(), String> {
+        let remote = Remote::parse(answer)?;
+        let certificate = self
+            .offered
+            .take()
+            .ok_or_else(|| "not waiting for an answer".to_string())?;
+        self.start(certificate, remote, true)
+    }
+
+    fn start(
+        &mut self,
+        certificate: Certificate,
+        remote: Remote,
+        offerer: bool,
+    ) -> Result<(), String> {
+        let mut added = 0;
+        for raw in &remote.candidates {
+            // Candidates this agent can't use, like TCP ones, are skipped
+            let Ok(candidate) = unmarshal_candidate(raw) else {
+                continue;
+            };
+            let candidate: Arc<dyn Candidate + Send + Sync> = Arc::new(candidate);
+            if self.agent.add_remote_candidate(&candidate).is_ok() {
+                added += 1;
+            }
+        }
+        if added == 0 {
+            return Err("the other device's description has no usable ICE candidates".to_string());
+        }
+        set_state(&self.info, &self.events, PeerState::Connecting, None);
+
+        let (agent, info, link) = (self.agent.clone(), self.info.clone(), self.link.clone());
+        let (save_dir, events) = (self.save_dir.clone(), self.events.clone());
+        self.task = Some(tokio::spawn(async move {
+            let established = tokio::time::timeout(
+                CONNECT_TIMEOUT,
+                establish(&agent, certificate, &remote, offerer),
+            )
+            .await
+            .unwrap_or_else(|_| Err("timed out connecting".to_string()));
+            match established {
+                Ok(established) => {
+                    let established = link.get_or_init(|| async { Arc::new(established) }).await;
+                    set_state(&info, &events, PeerState::Connected, None);
+                    established.receive_all(save_dir, &events).await;
+                    set_state(&info, &events, PeerState::Disconnected, None);
+                }
+                Err(error) => set_state(&info, &events, PeerState::Failed, Some(error)),
+            }
+        }));
+        Ok(())
+    }
+
+    fn link(&self) -> Result<Arc<Link>, String> {
+        let id = self.info.lock().unwrap().id;
+        self.link
+            .get()
+            .cloned()
+            .ok_or_else(|| format!("peer {id} isn't connected"))
+    }
+
+    async fn close(mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(link) = self.link.get() {
+            let _ = link.association.close().await;
+        }
+        let _ = self.agent.close().await;
+    }
+}
+
+#[derive(Default)]
+pub struct WebRtcState {
+    peers: Mutex<HashMap<u32, Peer>>,
+    next_id: AtomicU32,
+}
+
+impl WebRtcState {
+    fn link(&self, id: u32) -> Result<(Arc<Link>, Events), String> {
+        let peers = self.peers.lock().unwrap();
+        let peer = peers
+            .get(&id)
+            .ok_or_else(|| format!("peer {id} not found"))?;
+        Ok((peer.link()?, peer.events.clone()))
+    }
+}
+
+/// Start a peer: an offer, or with `options.offer` the answer to one.
+#[tauri::command]
+pub async fn webrtc_peer_create(
+    options: PeerOptions,
+    on_event: Channel<PeerEvent>,
+    state: State<'_, WebRtcState>,
+) -> Result<LocalDescription, String> {
+    let events: Events = Arc::new(move |event| {
+        let _ = on_event.send(event);
+    });
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+    let (peer, sdp) = Peer::create(id, options, events).await?;
+    state.peers.lock().unwrap().insert(id, peer);
+    Ok(LocalDescription { id, sdp })
+}
+
+/// Give an offering peer the other device's answer.
+#[tauri::command]
+pub async fn webrtc_peer_connect(
+    id: u32,
+    answer: String,
+    state: State<'_, WebRtcState>,
+) -> Result<(), String> {
+    let mut peers = state.peers.lock().unwrap();
+    let peer = peers
+        .get_mut(&id)
+        .ok_or_else(|| format!("peer {id} not found"))?;
+    peer.connect(&answer)
+}
+
+/// Send a file, resolving with its size once the other device has it.
+#[tauri::command]
+pub async fn webrtc_send_file(
+    id: u32,
+    path: String,
+    state: State<'_, WebRtcState>,
+) -> Result<u64, String> {
+    let (link, events) = state.link(id)?;
+    link.send(Path::new(&path), &events).await
+}
+
+#[tauri::command]
+pub async fn webrtc_peer_close(id: u32, state: State<'_, WebRtcState>) -> Result<(), String> {
+    let peer = state.peers.lock().unwrap().remove(&id);
+    match peer {
+        Some(peer) => {
+            peer.close().await;
+            Ok(())
+        }
+        None => Err(format!("peer {id} not found")),
+    }
+}
+
+#[tauri::command]
+pub async fn webrtc_peer_list(state: State<'_, WebRtcState>) -> Result<Vec<PeerInfo>, String> {
+    let peers = state.peers.lock().unwrap();
+    let mut infos: Vec<_> = peers
+        .values()
+        .map(|p| p.info.lock().unwrap().clone())
+        .collect();
+    infos.sort_by_key(|i| i.id);
+    Ok(infos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_description() {
+        let candidates = vec!["1 1 udp 2130706431 192.168.1.5 50000 typ host".to_string()];
+        let sdp = description(7, ("uf", "pw"), "AB:CD", "actpass", &candidates);
+        assert!(sdp.starts_with("v=0\r\no=- 7 2 IN IP4 127.0.0.1\r\n"));
+        assert!(sdp.contains("m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n"));
+        assert!(sdp.ends_with("a=end-of-candidates\r\n"));
+
+        let remote = Remote::parse(&sdp).unwrap();
+        assert_eq!(
+            remote,
+            Remote {
+                ufrag: "uf".to_string(),
+                pwd: "pw".to_string(),
+                fingerprint: "AB:CD".to_string(),
+                setup: "actpass".to_string(),
+                candidates,
+            }
+        );
+        // As browsers write it
+        let browser = "v=0\na=ice-ufrag:x\na=ice-pwd:y\n\
+                       a=fingerprint:sha-1 00:11\na=fingerprint:sha-256 ab:cd\n";
+        assert_eq!(Remote::parse(browser).unwrap().fingerprint, "AB:CD");
+        assert!(Remote::parse("v=0\na=ice-ufrag:x\n").is_err());
+    }
+
+    #[test]
+    fn test_dtls_roles() {
+        // An offer says actpass, and an answer usually takes the client role
+        assert!(is_dtls_client(false, "actpass"));
+        assert!(!is_dtls_client(true, "active"));
+        assert!(!is_dtls_client(false, "active"));
+        assert!(is_dtls_client(true, "passive"));
+        assert_eq!(fingerprint(b"").len(), 32 * 3 - 1);
+        assert!(fingerprint(b"").starts_with("E3:B0:C4"));
+    }
+
+    #[tokio::test]
+    async fn test_transfer() {
+        let tmp = tempfile::tempdir().unwrap();
+        let inbox = tmp.path().join("inbox");
+        std::fs::create_dir(&inbox).unwrap();
+        let source = tmp.path().join("photo.jpg");
+        let data: Vec<u8> = (0..3 * CHUNK_BYTES + 5).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+
+        let connected = Arc::new(Notify::new());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let notify = connected.clone();
+        let offer_events: Events = Arc::new(move |event| {
+            if event
+                == (PeerEvent::State {
+                    state: PeerState::Connected,
+                    error: None,
+                })
+            {
+                notify.notify_one();
+            }
+        });
+        let events = received.clone();
+        let answer_events: Events = Arc::new(move |event| events.lock().unwrap().push(event));
+
+        let options = PeerOptions {
+            offer: None,
+            save_dir: None,
+            ice_servers: Vec::new(),
+        };
+        let (mut offerer, offer) = Peer::create(1, options.clone(), offer_events.clone())
+            .await
+            .unwrap();
+        let options = PeerOptions {
+            offer: Some(offer),
+            save_dir: Some(inbox.to_string_lossy().into_owned()),
+            ..options
+        };
+        let (answerer, answer) = Peer::create(2, options, answer_events.clone())
+            .await
+            .unwrap();
+        assert!(answer.contains("a=setup:active"));
+        offerer.connect(&answer).unwrap();
+        assert!(offerer.connect(&answer).is_err());
+        tokio::time::timeout(CONNECT_TIMEOUT, connected.notified())
+            .await
+            .unwrap();
+
+        let link = offerer.link().unwrap();
+        let sent = link.send(&source, &offer_events).await.unwrap();
+        assert_eq!(sent, data.len() as u64);
+        assert_eq!(std::fs::read(inbox.join("photo.jpg")).unwrap(), data);
+        // A name that's taken gets a number
+        link.send(&source, &offer_events).await.unwrap();
+        assert!(inbox.join("photo (1).jpg").exists());
+        let finished = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| matches!(e, PeerEvent::Finished { sending: false, .. }))
+            .count();
+        assert_eq!(finished, 2);
+
+        // The offerer has no save_dir, so it refuses files, even ones too
+        // big to queue up while waiting for the answer
+        let big = tmp.path().join("big.bin");
+        std::fs::write(&big, vec![0; 2 * HIGH_WATER_BYTES]).unwrap();
+        let back = answerer.link().unwrap();
+        let error = back.send(&big, &answer_events).await.unwrap_err();
+        assert!(error.contains("not accepting files"), "{error}");
+
+        offerer.close().await;
+        answerer.close().await;
+    }
+}
@@ -1,34 +1,83 @@
 import { getVersion } from "@tauri-apps/api/app";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { useCallback, useEffect, useState } from "react";
+import { LogViewer } from "./LogViewer";
 import { startServer, stopServer } from "./server";
 
+interface Settings {
+  default_port: number;
+  default_serve_folder: string | null;
+  bind_address: "localhost" | "all_interfaces";
+}
+
+/** Payload of an `ok200://` link, see `deep_link.rs`. */
+type DeepLink =
+  | { action: "serve"; path: string; port: number | null }
+  | { action: "open" };
+
+type ServeRequest = { path: string; port: number | null };
+
+/** A folder the user dropped or picked, see `folder_grants.rs`. */
+type FolderGrant = { token: string; path: string };
+
+function bindHost(settings: Settings): string {
+  return settings.bind_address === "all_interfaces" ? "0.0.0.0" : "127.0.0.1";
+}
+
 function App() {
   const [version, setVersion] = useState("");
   const [root, setRoot] = useState("");
   const [port, setPort] = useState(8080);
+  const [host, setHost] = useState("127.0.0.1");
   const [running, setRunning] = useState(false);
   const [actualPort, setActualPort] = useState<number | null>(null);
   const [error, setError] = useState<string | null>(null);
+  const [serveRequest, setServeRequest] = useState<ServeRequest | null>(null);
+  const [quitConnections, setQuitConnections] = useState<number | null>(null);
 
   useEffect(() => {
     getVersion().then(setVersion);
   }, []);
 
-  const handleStart = useCallback(async () => {
-    if (!root) {
-      setError("Select a directory to serve");
-      return;
-    }
-    setError(null);
-    try {
-      const p = await startServer({ root, port });
-      setActualPort(p);
-      setRunning(true);
-    } catch (e) {
-      setError(e instanceof Error ? e.message : String(e));
-    }
-  }, [root, port]);
+  useEffect(() => {
+    const apply = (settings: Settings) => {
+      setPort(settings.default_port);
+      setRoot((current) => current || settings.default_serve_folder || "");
+      setHost(bindHost(settings));
+    };
+    invoke<Settings>("settings_get").then(apply);
+    const unlisten = listen<Settings>("settings-changed", (e) =>
+      apply(e.payload),
+    );
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const serve = useCallback(
+    async (folder: string, portOverride?: number) => {
+      if (!folder) {
+        setError("Select a directory to serve");
+        return;
+      }
+      setError(null);
+      try {
+        const p = await startServer({
+          root: folder,
+          port: portOverride ?? port,
+          host,
+        });
+        setActualPort(p);
+        setRunning(true);
+      } catch (e) {
+        setError(e instanceof Error ? e.message : String(e));
+      }
+    },
+    [port, host],
+  );
+
+  const handleStart = useCallback(() => serve(root), [serve, root]);
 
   const handleStop = useCallback(async () => {
     try {
@@ -40,6 +89,85 @@ function App() {
     }
   }, []);
 
+  // Tray actions for running servers
+  useEffect(() => {
+    const unlistenStop = listen("server-stop-requested", () => handleStop());
+    const unlistenCopy = listen<string>("server-copy-url", (e) =>
+      navigator.clipboard.writeText(e.payload),
+    );
+    return () => {
+      unlistenStop.then((fn) => fn());
+      unlistenCopy.then((fn) => fn());
+    };
+  }, [handleStop]);
+
+  // Tray "Recent Folders" entries, folders passed on the command line and
+  // folders dropped on the window are served right away
+  useEffect(() => {
+    const serveFolder = (folder: string) => {
+      setRoot(folder);
+      serve(folder);
+    };
+    invoke<string | null>("launch_folder_take").then((folder) => {
+      if (folder) serveFolder(folder);
+    });
+    const unlistenRecent = listen<string>("serve-folder", (e) =>
+      serveFolder(e.payload),
+    );
+    const unlistenOpen = listen<string>("open-folder", (e) =>
+      serveFolder(e.payload),
+    );
+    const unlistenDrop = listen<FolderGrant>("folder-dropped", (e) =>
+      serveFolder(e.payload.path),
+    );
+    return () => {
+      unlistenRecent.then((fn) => fn());
+      unlistenOpen.then((fn) => fn());
+      unlistenDrop.then((fn) => fn());
+    };
+  }, [serve]);
+
+  // ok200:// links only ever ask; serving waits for the user to confirm
+  useEffect(() => {
+    const handleLink = (link: DeepLink) => {
+      if (link.action === "serve") {
+        setServeRequest({ path: link.path, port: link.port });
+      }
+    };
+    invoke<DeepLink | null>("deep_link_take").then((link) => {
+      if (link) handleLink(link);
+    });
+    const unlisten = listen<DeepLink>("deep-link", (e) =>
+      handleLink(e.payload),
+    );
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  // Quitting with clients still connected waits for the user, see `quit.rs`
+  useEffect(() => {
+    const unlisten = listen<{ connections: number }>("confirm-quit", (e) =>
+      setQuitConnections(e.payload.connections),
+    );
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  const keepRunning = useCallback(() => {
+    setQuitConnections(null);
+    invoke("quit_cancel");
+  }, []);
+
+  const confirmServeRequest = useCallback(() => {
+    if (!serveRequest) return;
+    setServeRequest(null);
+    setRoot(serveRequest.path);
+    if (serveRequest.port !== null) setPort(serveRequest.port);
+    serve(serveRequest.path, serveRequest.port ?? undefined);
+  }, [serveRequest, serve]);
+
   const serverUrl = actualPort ? `http://127.0.0.1:${actualPort}` : null;
 
   return (
@@ -47,6 +175,38 @@ function App() {
       <h1>200 OK</h1>
       <p className="version">v{version}</p>
 
+      {serveRequest && (
+        <div data-testid="deep-link-confirm" className="confirm">
+          <p>
+            A link asked to serve <code>{serveRequest.path}</code>
+            {serveRequest.port !== null && ` on port ${serveRequest.port}`}.
+          </p>
+          <button type="button" onClick={confirmServeRequest}>
+            Serve
+          </button>
+          <button type="button" onClick={() => setServeRequest(null)}>
+            Cancel
+          </button>
+        </div>
+      )}
+
+      {quitConnections !== null && (
+        <div data-testid="quit-confirm" className="confirm">
+          <p>
+            {quitConnections === 1
+              ? "1 connection is"
+              : `${quitConnections} connections are`}{" "}
+            still open. Quitting will stop all servers.
+          </p>
+          <button type="button" onClick={() => invoke("quit_confirm")}>
+            Quit Anyway
+          </button>
+          <button type="button" onClick={keepRunning}>
+            Keep Running in Background
+          </button>
+        </div>
+      )}
+
       <div className="controls">
         <label>
           Directory
@@ -60,6 +220,19 @@ function App() {
           />
         </label>
 
+        <button
+          data-testid="pick-btn"
+          type="button"
+          onClick={() =>
+            invoke<FolderGrant | null>("pick_folder_and_grant")
+              .then((grant) => grant && setRoot(grant.path))
+              .catch((e) => setError(String(e)))
+          }
+          disabled={running}
+        >
+          Choose…
+        </button>
+
         <label>
           Port
           <input
@@ -105,6 +278,8 @@ function App() {
           </a>
         </p>
       )}
+
+      <LogViewer />
     </main>
   );
 }
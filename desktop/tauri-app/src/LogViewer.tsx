@@ -0,0 +1,56 @@
+import { invoke } from "@tauri-apps/api/core";
+import { useCallback, useEffect, useState } from "react";
+
+/** Log files written by the app, see `logging.rs`. */
+const CHANNELS = ["app", "updater"] as const;
+type Channel = (typeof CHANNELS)[number];
+
+export function LogViewer() {
+  const [open, setOpen] = useState(false);
+  const [channel, setChannel] = useState<Channel>("app");
+  const [lines, setLines] = useState<string[]>([]);
+  const [error, setError] = useState<string | null>(null);
+
+  const refresh = useCallback(async () => {
+    try {
+      setLines(await invoke<string[]>("logs_tail", { channel }));
+      setError(null);
+    } catch (e) {
+      setError(String(e));
+    }
+  }, [channel]);
+
+  useEffect(() => {
+    if (open) refresh();
+  }, [open, refresh]);
+
+  return (
+    <details
+      className="logs"
+      onToggle={(e) => setOpen((e.target as HTMLDetailsElement).open)}
+    >
+      <summary>Logs</summary>
+      <div className="controls">
+        <select
+          data-testid="log-channel"
+          value={channel}
+          onChange={(e) => setChannel(e.target.value as Channel)}
+        >
+          {CHANNELS.map((c) => (
+            <option key={c} value={c}>
+              {c}
+            </option>
+          ))}
+        </select>
+        <button type="button" onClick={refresh}>
+          Refresh
+        </button>
+        <button type="button" onClick={() => invoke("logs_open_folder")}>
+          Open Folder
+        </button>
+      </div>
+      {error && <p className="error">{error}</p>}
+      <pre data-testid="log-lines">{lines.join("\n")}</pre>
+    </details>
+  );
+}
@@ -0,0 +1,772 @@
+/**
+ * Static file servers that run entirely in Rust (`http.rs`). The webview
+ * only starts and stops them and receives a log entry per request.
+ */
+
+import { Channel, invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+
+/** Per-server CORS settings, see `cors.rs`. */
+export interface CorsOptions {
+  /** Origins allowed to read responses; `*` allows any. */
+  allowedOrigins?: string[];
+  allowedMethods?: string[];
+  /** Empty allows whatever the browser asks for. */
+  allowedHeaders?: string[];
+  allowCredentials?: boolean;
+  maxAgeSecs?: number | null;
+}
+
+/** Per-server access control, see `auth.rs`. A missing token is generated. */
+export type AuthOptions =
+  | { type: "basic"; username: string; password: string }
+  | { type: "token"; token?: string };
+
+/** Access logging, see `access_log.rs`; files are `logs/access-<port>.log`. */
+export interface AccessLogOptions {
+  /** Defaults to `combined`. */
+  format?: "common" | "combined" | "json";
+  /** Write to a rotating file; defaults to `true`. */
+  file?: boolean;
+  /** Send entries to `onRequest`; defaults to `true`. */
+  stream?: boolean;
+}
+
+/** `Cache-Control` for paths matching a glob like `/assets/**`. */
+export interface CacheRule {
+  pattern: string;
+  value: string;
+}
+
+/** gzip/brotli response compression, see `compression.rs`. */
+export interface CompressionOptions {
+  /** MIME type prefixes to compress, e.g. `text/`. */
+  contentTypes?: string[];
+  minBytes?: number;
+  /** Serve `app.js.br` / `app.js.gz` instead when present. */
+  precompressed?: boolean;
+}
+
+/** `PUT` and multipart `POST` into the served folder, see `uploads.rs`. */
+export interface UploadOptions {
+  /** Largest accepted body; defaults to 1 GiB. */
+  maxBytes?: number;
+  /** Replace existing files instead of refusing or renaming. */
+  overwrite?: boolean;
+}
+
+/** See `proxy.rs`. */
+export interface ProxyRoute {
+  /** Such as `/api`; matches `/api` and `/api/...`. */
+  prefix: string;
+  /** `http://localhost:3000`, or an https origin with a base path. */
+  target: string;
+  /** Forward `/api/users` as `/users`. */
+  stripPrefix?: boolean;
+}
+
+/** Another folder on the same port, see `mounts.rs`. */
+export interface Mount {
+  /** Host name without the port; any host when absent. */
+  host?: string | null;
+  /** Such as `/docs`; matches `/docs` and `/docs/...`. Defaults to `/`. */
+  prefix?: string;
+  root: string;
+}
+
+/** A certificate for one host name, picked by the name clients ask for. */
+export interface SniCert {
+  /** Such as `mysite.local`, or `*.mysite.local`. */
+  serverName: string;
+  certPath: string;
+  keyPath: string;
+}
+
+/** PEM files for HTTPS, see `tls.rs`. HTTP/2 is offered alongside. */
+export interface TlsOptions {
+  certPath: string;
+  keyPath: string;
+  /** Also answer HTTP/3 over UDP; needs a build with the `http3` feature. */
+  http3?: boolean;
+  /** Answer plain HTTP on the same port with a redirect to HTTPS. */
+  redirectHttp?: boolean;
+  /** Certificates for other host names; `certPath` covers the rest. */
+  sni?: SniCert[];
+}
+
+/** Per-client limits, see `rate_limit.rs`; `0` turns a limit off. */
+export interface RateLimitOptions {
+  requestsPerSec?: number;
+  /** Requests allowed in a row after being idle. */
+  burst?: number;
+  /** Requests handled at once for one client. */
+  maxConnections?: number;
+  /** Refusals in quick succession that get a client banned. */
+  banAfter?: number;
+  banSecs?: number;
+}
+
+/** How bursts of changes are merged, see `watch_batch.rs`. */
+export interface LiveReloadOptions {
+  /** Quiet time after the last change before pages are told. */
+  debounceMs?: number;
+  /** Longest a burst is held back while changes keep coming. */
+  maxWaitMs?: number;
+}
+
+/** Client address rules checked at connect time, see `ip_filter.rs`. */
+export interface IpFilterOptions {
+  /** CIDR networks or addresses; when not empty, only these get in. */
+  allow?: string[];
+  /** CIDR networks or addresses refused even if allowed. */
+  deny?: string[];
+  /** Only allow loopback and local network addresses. */
+  privateOnly?: boolean;
+}
+
+/** Endpoints for monitors, see `health.rs`; neither needs `auth`. */
+export interface HealthOptions {
+  /** Defaults to `/_ok200`, giving `/_ok200/health` and `/_ok200/status`. */
+  prefix?: string;
+}
+
+/** Keep recent requests and responses, see `inspector.rs`. */
+export interface InspectorOptions {
+  /** Exchanges kept; defaults to 100. */
+  capacity?: number;
+  /** Bytes kept from each body; defaults to 64 KiB. */
+  maxBodyBytes?: number;
+}
+
+/**
+ * A command run on a server event, see `hooks.rs`. It gets the details in
+ * `OK200_*` environment variables and runs without a shell.
+ */
+export interface Hook {
+  on: "started" | "upload" | "request";
+  command: string;
+  args?: string[];
+  /** Glob over the request or upload URL path, e.g. `/in/*.csv`. */
+  pattern?: string;
+  /** Defaults to 30. */
+  timeoutSecs?: number;
+}
+
+/** A command run before the server starts, see `build_step.rs`. */
+export interface BuildStep {
+  command: string;
+  args?: string[];
+  /** Folder to run in, usually the project `root` is built from. */
+  cwd: string;
+  env?: Record<string, string>;
+  /** Build again when files in `cwd` change. */
+  watch?: boolean;
+  /** Defaults to 600. */
+  timeoutSecs?: number;
+}
+
+export type BuildEvent =
+  | { event: "started"; rebuild: boolean }
+  | { event: "output"; line: string; stderr: boolean }
+  | {
+      event: "finished";
+      success: boolean;
+      durationMs: number;
+      error: string | null;
+    };
+
+/** Announce the folder to TVs and media players, see `dlna.rs`. */
+export interface DlnaOptions {
+  /** Shown on TVs; defaults to `200 OK` and the folder's name. */
+  name?: string;
+}
+
+export type DotfilePolicy = "serve" | "hide" | "deny";
+
+export interface NativeServerOptions {
+  root: string;
+  port?: number;
+  host?: string;
+  spa?: boolean;
+  /** `true` allows any origin. */
+  cors?: boolean | CorsOptions;
+  listing?: boolean;
+  /**
+   * Dotfiles and OS junk like `Thumbs.db`: `"hide"` leaves them out of
+   * listings, `"deny"` also answers 404 for them. Defaults to `"serve"`.
+   */
+  dotfiles?: DotfilePolicy;
+  auth?: AuthOptions;
+  /** Extension to MIME type; defaults to the folder's saved profile. */
+  mimeTypes?: Record<string, string>;
+  /** Without it, every request is streamed and nothing is written. */
+  accessLog?: AccessLogOptions;
+  /** The first matching rule applies. */
+  cacheControl?: CacheRule[];
+  /** `true` uses the default settings. */
+  compression?: boolean | CompressionOptions;
+  /** `true` uses the default limits. */
+  uploads?: boolean | UploadOptions;
+  /** Mountable as a network drive; read-only unless `auth` is set. */
+  webdav?: boolean;
+  /** Forward path prefixes to other origins, e.g. `/api` to a dev API. */
+  proxies?: ProxyRoute[];
+  /** More folders by path prefix or `Host`, ahead of `root`. */
+  mounts?: Mount[];
+  /** Reload open pages when files in the folder change. */
+  liveReload?: boolean | LiveReloadOptions;
+  /** Show Markdown files as HTML pages; `?raw=1` gets the file itself. */
+  markdown?: boolean;
+  tls?: TlsOptions;
+  /** `true` uses the default limits; refused requests get a 429. */
+  rateLimit?: boolean | RateLimitOptions;
+  /** Refused clients are logged with an error starting "rejected:". */
+  ipFilter?: IpFilterOptions;
+  /** `true` uses the default prefix. */
+  health?: boolean | HealthOptions;
+  /** `true` uses the default limits. */
+  inspector?: boolean | InspectorOptions;
+  hooks?: Hook[];
+  /** The server isn't started if this fails. */
+  build?: BuildStep;
+  /** Needs a LAN-reachable `host` and no `auth`. */
+  dlna?: boolean | DlnaOptions;
+}
+
+export interface NativeServerInfo {
+  id: number;
+  root: string;
+  host: string;
+  port: number;
+  spa: boolean;
+  cors: Required<CorsOptions> | null;
+  listing: boolean;
+  dotfiles: DotfilePolicy;
+  auth: Required<AuthOptions> | null;
+  mimeTypes: Record<string, string>;
+  accessLog: Required<AccessLogOptions> | null;
+  cacheControl: CacheRule[];
+  compression: Required<CompressionOptions> | null;
+  uploads: Required<UploadOptions> | null;
+  webdav: boolean;
+  proxies: ProxyRoute[];
+  mounts: Required<Mount>[];
+  liveReload: Required<LiveReloadOptions> | null;
+  markdown: boolean;
+  tls: Required<TlsOptions> | null;
+  rateLimit: Required<RateLimitOptions> | null;
+  ipFilter: Required<IpFilterOptions> | null;
+  health: Required<HealthOptions> | null;
+  inspector: Required<InspectorOptions> | null;
+  hooks: Hook[];
+  build: Required<BuildStep> | null;
+  dlna: Required<DlnaOptions> | null;
+}
+
+export interface RequestLog {
+  /**
+   * Shared by the request's upload events and inspector capture: the
+   * connection's number, plus the stream's for HTTP/2, e.g. `12.3`.
+   */
+  requestId: string;
+  remoteAddress: string;
+  /** Unix seconds. */
+  time: number;
+  method: string;
+  path: string;
+  status: number;
+  bytes: number;
+  durationMs: number;
+  userAgent: string | null;
+  referer: string | null;
+  error: string | null;
+  /** Files written by this request, if it was an upload. */
+  uploads?: UploadedFile[];
+}
+
+export interface UploadedFile {
+  /** URL path of the new file. */
+  path: string;
+  bytes: number;
+}
+
+export interface UploadEvent extends UploadedFile {
+  serverId: number;
+  /** The `requestId` of the upload's log entry. */
+  requestId: string;
+  remoteAddress: string;
+}
+
+/**
+ * Start a server. With `build`, its output goes to `onBuild` first, and
+ * again on each rebuild.
+ */
+export async function createNativeServer(
+  options: NativeServerOptions,
+  onRequest: (entry: RequestLog) => void = () => {},
+  onBuild: (event: BuildEvent) => void = () => {},
+): Promise<NativeServerInfo> {
+  const channel = new Channel<RequestLog>();
+  channel.onmessage = onRequest;
+  const buildChannel = new Channel<BuildEvent>();
+  buildChannel.onmessage = onBuild;
+  return invoke<NativeServerInfo>("http_server_create", {
+    options,
+    onRequest: channel,
+    onBuild: buildChannel,
+  });
+}
+
+export function closeNativeServer(id: number): Promise<void> {
+  return invoke("http_server_close", { id });
+}
+
+export function nativeServerInfo(id: number): Promise<NativeServerInfo> {
+  return invoke<NativeServerInfo>("http_server_info", { id });
+}
+
+/**
+ * Replace a running server's options. Open connections, such as downloads,
+ * finish with the old ones. `root`, `host`, `port`, `tls`, `accessLog`,
+ * `liveReload` and `build` must stay the same; a token auth without a
+ * token keeps the current one.
+ */
+export function updateNativeServer(
+  id: number,
+  options: NativeServerOptions,
+): Promise<NativeServerInfo> {
+  return invoke<NativeServerInfo>("http_server_update", { id, options });
+}
+
+/** Replace a token-protected server's token and return the new one. */
+export function rotateNativeServerToken(id: number): Promise<string> {
+  return invoke<string>("http_server_rotate_token", { id });
+}
+
+/** Serve another folder, replacing any at the same host and prefix. */
+export function mountNativeServer(
+  id: number,
+  mount: Mount,
+): Promise<NativeServerInfo> {
+  return invoke<NativeServerInfo>("http_server_mount", { id, mount });
+}
+
+export function unmountNativeServer(
+  id: number,
+  prefix: string,
+  host?: string,
+): Promise<NativeServerInfo> {
+  return invoke<NativeServerInfo>("http_server_unmount", {
+    id,
+    host: host ?? null,
+    prefix,
+  });
+}
+
+/** Settings saved per served folder, see `profiles.rs`. */
+export interface ServerProfile {
+  mimeTypes: Record<string, string>;
+}
+
+export function getServerProfile(root: string): Promise<ServerProfile> {
+  return invoke<ServerProfile>("server_profile_get", { root });
+}
+
+/** Save a folder's profile; it's returned with extensions normalized. */
+export function setServerProfile(
+  root: string,
+  profile: ServerProfile,
+): Promise<ServerProfile> {
+  return invoke<ServerProfile>("server_profile_set", { root, profile });
+}
+
+/** Save a whole server setup under a name; its auth goes in the keychain. */
+export function saveNamedProfile(
+  name: string,
+  options: NativeServerOptions,
+): Promise<void> {
+  return invoke("profile_save", { name, options });
+}
+
+export function loadNamedProfile(name: string): Promise<NativeServerOptions> {
+  return invoke<NativeServerOptions>("profile_load", { name });
+}
+
+export function listNamedProfiles(): Promise<string[]> {
+  return invoke<string[]>("profile_list");
+}
+
+export function deleteNamedProfile(name: string): Promise<void> {
+  return invoke("profile_delete", { name });
+}
+
+export async function startNamedProfile(
+  name: string,
+  onRequest: (entry: RequestLog) => void = () => {},
+  onBuild: (event: BuildEvent) => void = () => {},
+): Promise<NativeServerInfo> {
+  const channel = new Channel<RequestLog>();
+  channel.onmessage = onRequest;
+  const buildChannel = new Channel<BuildEvent>();
+  buildChannel.onmessage = onBuild;
+  return invoke<NativeServerInfo>("profile_start", {
+    name,
+    onRequest: channel,
+    onBuild: buildChannel,
+  });
+}
+
+/** Called when a saved profile is picked from the tray. */
+export function onNamedProfileRequested(
+  handler: (name: string) => void,
+): Promise<UnlistenFn> {
+  return listen<string>("profile-start-requested", (e) => handler(e.payload));
+}
+
+export interface LegacyImport {
+  /** Name of the saved profile the old server setup went into. */
+  profile: string;
+  /** Old options with no equivalent here, described for the user. */
+  skipped: string[];
+}
+
+/**
+ * Import "Web Server for Chrome" settings exported from
+ * `chrome.storage.local`. `root` picks the folder when the export has none.
+ */
+export function importLegacySettings(
+  path: string,
+  root?: string,
+): Promise<LegacyImport> {
+  return invoke<LegacyImport>("legacy_import", { path, root: root ?? null });
+}
+
+export interface CapturedBody {
+  text: string;
+  /** `base64` when the bytes aren't UTF-8. */
+  encoding: "base64" | null;
+  truncated: boolean;
+}
+
+export interface ExchangeSummary {
+  id: number;
+  /** The `requestId` of the request's log entry. */
+  requestId: string;
+  /** Unix milliseconds. */
+  timeMs: number;
+  method: string;
+  path: string;
+  status: number;
+  contentType: string | null;
+  bytes: number;
+  durationMs: number;
+}
+
+/** A captured request and response; credentials in headers are masked. */
+export interface Exchange extends Omit<ExchangeSummary, "contentType"> {
+  remoteAddress: string;
+  requestHeaders: [string, string][];
+  requestBody: CapturedBody | null;
+  responseHeaders: [string, string][];
+  responseBody: CapturedBody | null;
+}
+
+export function listCaptures(serverId: number): Promise<ExchangeSummary[]> {
+  return invoke<ExchangeSummary[]>("inspector_list", { serverId });
+}
+
+export function getCapture(serverId: number, id: number): Promise<Exchange> {
+  return invoke<Exchange>("inspector_get", { serverId, id });
+}
+
+export function clearCaptures(serverId: number): Promise<void> {
+  return invoke("inspector_clear", { serverId });
+}
+
+/**
+ * Write captured exchanges to `path` as a HAR 1.2 file, from one server or
+ * all of them, optionally only those since `since` (Unix milliseconds).
+ * Resolves to the number of entries written.
+ */
+export function exportHar(
+  path: string,
+  filter?: { serverId?: number; since?: number },
+): Promise<number> {
+  return invoke("inspector_export_har", { path, filter });
+}
+
+export type StatsRange = "today" | "week" | "month" | "year" | "all";
+
+export interface UsageTotals {
+  requests: number;
+  bytes: number;
+  uniqueIps: number;
+  /** Unix seconds of the last request. */
+  lastActive: number | null;
+}
+
+export interface UsageReport {
+  totals: UsageTotals;
+  /** Per served folder, busiest first. */
+  folders: (UsageTotals & { root: string })[];
+  /** Daily rollups (UTC), oldest first; idle days are left out. */
+  days: { date: string; requests: number; bytes: number; uniqueIps: number }[];
+}
+
+/** Native server usage kept across restarts, over `range` (default all). */
+export function getUsageStats(range?: StatsRange): Promise<UsageReport> {
+  return invoke("stats_get", { range });
+}
+
+export interface PluginInfo {
+  name: string;
+  path: string;
+  enabled: boolean;
+  /** Why an enabled plugin couldn't be loaded. */
+  error: string | null;
+}
+
+/** WASM request plugins in the app's plugins folder. */
+export function listPlugins(): Promise<PluginInfo[]> {
+  return invoke("plugins_list");
+}
+
+export function setPluginEnabled(
+  name: string,
+  enabled: boolean,
+): Promise<PluginInfo[]> {
+  return invoke("plugins_set_enabled", { name, enabled });
+}
+
+/** Rescan the plugins folder, loading new or changed plugins. */
+export function reloadPlugins(): Promise<PluginInfo[]> {
+  return invoke("plugins_reload");
+}
+
+/**
+ * A folder served over FTP, see `ftp.rs`, for devices that can't speak
+ * HTTP well. Only passive mode is offered.
+ */
+export interface FtpServerOptions {
+  root: string;
+  /** 0 picks a free port; 21 usually needs admin rights. */
+  port?: number;
+  host?: string;
+  /** Allow uploads, deletes, renames and new folders. */
+  write?: boolean;
+  dotfiles?: DotfilePolicy;
+  /** Without it, any user name and password log in; a token is the password. */
+  auth?: AuthOptions;
+  /** Require explicit FTPS (`AUTH TLS`) with this certificate. */
+  tls?: TlsOptions;
+}
+
+export interface FtpServerInfo {
+  id: number;
+  root: string;
+  host: string;
+  port: number;
+  write: boolean;
+  dotfiles: DotfilePolicy;
+  auth: Required<AuthOptions> | null;
+  tls: Required<TlsOptions> | null;
+}
+
+export function createFtpServer(
+  options: FtpServerOptions,
+): Promise<FtpServerInfo> {
+  return invoke<FtpServerInfo>("ftp_server_create", { options });
+}
+
+export function closeFtpServer(id: number): Promise<void> {
+  return invoke("ftp_server_close", { id });
+}
+
+export function listFtpServers(): Promise<FtpServerInfo[]> {
+  return invoke<FtpServerInfo[]>("ftp_server_list");
+}
+
+/**
+ * A folder served over SFTP, see `sftp.rs`; needs the `sftp` build. Only
+ * the `sftp` subsystem is offered, with no shell.
+ */
+export interface SftpServerOptions {
+  root: string;
+  /** 0 picks a free port; 22 usually needs admin rights. */
+  port?: number;
+  host?: string;
+  /** Allow uploads, deletes, renames and new folders. */
+  write?: boolean;
+  dotfiles?: DotfilePolicy;
+  /** Password logins; a token is the password, with any user name. */
+  auth?: AuthOptions;
+  /**
+   * Keys that may log in, as `authorized_keys` lines. Without these or
+   * `auth`, anyone can.
+   */
+  authorizedKeys?: string[];
+}
+
+export interface SftpServerInfo {
+  id: number;
+  root: string;
+  host: string;
+  port: number;
+  write: boolean;
+  dotfiles: DotfilePolicy;
+  auth: Required<AuthOptions> | null;
+  authorizedKeys: string[];
+  /** `SHA256:…`, what clients ask the user to trust on first connect. */
+  hostKeyFingerprint: string;
+}
+
+export function createSftpServer(
+  options: SftpServerOptions,
+): Promise<SftpServerInfo> {
+  return invoke<SftpServerInfo>("sftp_server_create", { options });
+}
+
+export function closeSftpServer(id: number): Promise<void> {
+  return invoke("sftp_server_close", { id });
+}
+
+export function listSftpServers(): Promise<SftpServerInfo[]> {
+  return invoke<SftpServerInfo[]>("sftp_server_list");
+}
+
+/** STUN or TURN, as in the browser's `RTCIceServer`. */
+export interface IceServer {
+  urls: string[];
+  username?: string;
+  credential?: string;
+}
+
+/** A device-to-device link, see `webrtc.rs`; needs the `webrtc` build. */
+export interface PeerOptions {
+  /** The other device's offer, to answer instead of making one. */
+  offer?: string;
+  /** Where received files go; without it they're refused. */
+  saveDir?: string;
+  /** Defaults to a public STUN server. */
+  iceServers?: IceServer[];
+}
+
+export type PeerState =
+  | "offered"
+  | "connecting"
+  | "connected"
+  | "disconnected"
+  | "failed";
+
+export interface PeerInfo {
+  id: number;
+  offerer: boolean;
+  saveDir: string | null;
+  state: PeerState;
+}
+
+export type PeerEvent =
+  | { event: "state"; state: PeerState; error: string | null }
+  | { event: "incoming"; transfer: number; name: string; size: number }
+  | {
+      event: "progress";
+      transfer: number;
+      sending: boolean;
+      bytes: number;
+      size: number;
+    }
+  | {
+      event: "finished";
+      transfer: number;
+      sending: boolean;
+      path: string | null;
+    }
+  | { event: "failed"; transfer: number; sending: boolean; error: string };
+
+/**
+ * Start a peer. The `sdp` it returns goes to the other device: an offer
+ * for `options.offer` there, or an answer for `connectPeer` here.
+ */
+export async function createPeer(
+  options: PeerOptions,
+  onEvent: (event: PeerEvent) => void,
+): Promise<{ id: number; sdp: string }> {
+  const channel = new Channel<PeerEvent>();
+  channel.onmessage = onEvent;
+  return invoke("webrtc_peer_create", { options, onEvent: channel });
+}
+
+export function connectPeer(id: number, answer: string): Promise<void> {
+  return invoke("webrtc_peer_connect", { id, answer });
+}
+
+/** Resolves with the file's size once the other device has saved it. */
+export function sendFileToPeer(id: number, path: string): Promise<number> {
+  return invoke<number>("webrtc_send_file", { id, path });
+}
+
+export function closePeer(id: number): Promise<void> {
+  return invoke("webrtc_peer_close", { id });
+}
+
+export function listPeers(): Promise<PeerInfo[]> {
+  return invoke<PeerInfo[]>("webrtc_peer_list");
+}
+
+/** How this device shows up to other 200 OK instances on the LAN. */
+export interface DiscoveryOptions {
+  /** Defaults to the computer's name. */
+  name?: string;
+  /** A server here that accepts uploads; 0 or unset to only be listed. */
+  port?: number;
+  tls?: boolean;
+}
+
+export interface DiscoveredInstance {
+  id: string;
+  name: string;
+  version: string;
+  host: string;
+  addresses: string[];
+  port: number | null;
+  /** Where to send files, if it advertised a server. */
+  url: string | null;
+}
+
+/** Advertise this device and look for others; calling again re-advertises. */
+export function startDiscovery(options: DiscoveryOptions = {}): Promise<void> {
+  return invoke("discovery_start", { options });
+}
+
+export function stopDiscovery(): Promise<void> {
+  return invoke("discovery_stop");
+}
+
+export function listDiscovered(): Promise<DiscoveredInstance[]> {
+  return invoke<DiscoveredInstance[]>("discovery_list");
+}
+
+/** Called with the whole list whenever another instance appears or leaves. */
+export function onDiscoveryChanged(
+  handler: (instances: DiscoveredInstance[]) => void,
+): Promise<UnlistenFn> {
+  return listen<DiscoveredInstance[]>("discovery-changed", (e) =>
+    handler(e.payload),
+  );
+}
+
+/** Called for each exchange captured by a server with `inspector` on. */
+export function onCapture(
+  handler: (event: Exchange & { serverId: number }) => void,
+): Promise<UnlistenFn> {
+  return listen<Exchange & { serverId: number }>("inspector-capture", (e) =>
+    handler(e.payload),
+  );
+}
+
+/** Called for every file uploaded to any native server. */
+export function onNativeUpload(
+  handler: (event: UploadEvent) => void,
+): Promise<UnlistenFn> {
+  return listen<UploadEvent>("http-upload", (e) => handler(e.payload));
+}
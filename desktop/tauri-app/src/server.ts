@@ -14,6 +14,12 @@ import {
 import { Channel, invoke } from "@tauri-apps/api/core";
 
 let server: WebServer | null = null;
+/** Id of the running server in the Rust-side registry shown in the tray. */
+let registryId: number | null = null;
+
+function folderName(root: string): string {
+  return root.split(/[\\/]/).filter(Boolean).pop() ?? root;
+}
 
 export interface StartOptions {
   root: string;
@@ -26,9 +32,7 @@ export interface StartOptions {
 }
 
 export async function startServer(options: StartOptions): Promise<number> {
-  if (server) {
-    await server.stop();
-  }
+  await stopServer();
 
   const config = defaultConfig(options.root);
   config.port = options.port ?? 8080;
@@ -45,6 +49,12 @@ export async function startServer(options: StartOptions): Promise<number> {
   });
 
   const actualPort = await server.start();
+  registryId = await invoke<number>("server_register", {
+    name: folderName(options.root),
+    root: options.root,
+    host: config.host,
+    port: actualPort,
+  });
   return actualPort;
 }
 
@@ -53,6 +63,10 @@ export async function stopServer(): Promise<void> {
     await server.stop();
     server = null;
   }
+  if (registryId !== null) {
+    await invoke("server_unregister", { id: registryId });
+    registryId = null;
+  }
 }
 
 export function isRunning(): boolean {
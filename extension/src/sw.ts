@@ -15,25 +15,46 @@ import { getNativeConnection } from "./lib/native-connection";
 // Native Host Connection
 // ============================================================================
 
+/** Message protocol spoken with the host, see `PROTOCOL_VERSION` there. */
+const PROTOCOL_VERSION = "1.0";
+/** Host actions this extension uses. */
+const CAPABILITIES = ["launch"];
+
 const nativeConnection = getNativeConnection();
 let hostVersion: string | null = null;
+/** Actions the host answers; null for hosts older than the protocol version. */
+let hostCapabilities: string[] | null = null;
 
 async function connectToNativeHost() {
   try {
     await nativeConnection.connect();
     console.log("[SW] Connected to native host");
 
-    // Send handshake to get host version
+    // Send handshake to get host version and what it supports
     hostVersion = await new Promise<string | null>((resolve) => {
       const timeout = setTimeout(() => resolve(null), 2000);
       nativeConnection.onMessage((msg: unknown) => {
-        const m = msg as { action?: string; version?: string };
+        const m = msg as {
+          action?: string;
+          version?: string;
+          ok?: boolean;
+          capabilities?: string[];
+          error?: { code: string; message: string };
+        };
         if (m.action === "handshake" && m.version) {
           clearTimeout(timeout);
+          if (m.ok === false) {
+            console.error(`[SW] Native host refused: ${m.error?.message}`);
+          }
+          hostCapabilities = m.capabilities ?? null;
           resolve(m.version);
         }
       });
-      nativeConnection.send({ action: "handshake" });
+      nativeConnection.send({
+        action: "handshake",
+        protocolVersion: PROTOCOL_VERSION,
+        capabilities: CAPABILITIES,
+      });
     });
 
     if (hostVersion) {
@@ -43,6 +64,7 @@ async function connectToNativeHost() {
     nativeConnection.onDisconnect(() => {
       console.log("[SW] Native host disconnected");
       hostVersion = null;
+      hostCapabilities = null;
     });
   } catch (e) {
     console.error("[SW] Failed to connect to native host:", e);
@@ -83,6 +105,9 @@ chrome.runtime.onMessage.addListener((message, _sender, sendResponse) => {
       if (!nativeConnection.isConnected()) {
         return { ok: false, error: "Cannot connect to native host" };
       }
+      if (hostCapabilities && !hostCapabilities.includes("launch")) {
+        return { ok: false, error: "Native host can't launch the app" };
+      }
       return new Promise<{ ok: boolean; error?: string }>((resolve) => {
         const timeout = setTimeout(() => {
           resolve({ ok: false, error: "Launch timed out" });
@@ -1,9 +1,15 @@
 export { TauriFileHandle, TauriFileSystem } from "./tauri-filesystem.js";
+export type { SyncProgress, SyncReport } from "./tauri-filesystem.js";
 export { TauriSocketFactory } from "./tauri-socket-factory.js";
 export { TauriTcpServer } from "./tauri-tcp-server.js";
 export { TauriTcpSocket } from "./tauri-tcp-socket.js";
 export type {
   ControlEvent,
+  FrameSizeOptions,
+  GcStats,
+  IpFilterOptions,
+  ReattachInfo,
   TauriChannelCtor,
   TauriInvokeFn,
+  TcpServerInfo,
 } from "./types.js";
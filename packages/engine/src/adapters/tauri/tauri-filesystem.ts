@@ -11,7 +11,7 @@ import type {
   IFileStat,
   IFileSystem,
 } from "../../interfaces/filesystem.js";
-import type { TauriInvokeFn } from "./types.js";
+import type { TauriChannelCtor, TauriInvokeFn } from "./types.js";
 
 export class TauriFileHandle implements IFileHandle {
   private closed = false;
@@ -89,8 +89,30 @@ export class TauriFileHandle implements IFileHandle {
   }
 }
 
+export interface SyncProgress {
+  done: number;
+  total: number;
+  bytesDone: number;
+  bytesTotal: number;
+  path: string;
+}
+
+export interface SyncReport {
+  copied: string[];
+  deleted: string[];
+  unchanged: number;
+  bytesCopied: number;
+  failed: Array<{ path: string; error: string }>;
+  dryRun: boolean;
+  durationMs: number;
+}
+
 export class TauriFileSystem implements IFileSystem {
-  constructor(private readonly invoke: TauriInvokeFn) {}
+  constructor(
+    private readonly invoke: TauriInvokeFn,
+    /** Needed for `syncTree` progress. */
+    private readonly ChannelCtor?: TauriChannelCtor,
+  ) {}
 
   async open(path: string, mode: "r" | "w" | "r+"): Promise<IFileHandle> {
     const handleId = await this.invoke<number>("fs_open", { path, mode });
@@ -124,6 +146,79 @@ export class TauriFileSystem implements IFileSystem {
     return this.invoke<string[]>("fs_readdir", { path });
   }
 
+  /** Entries with their types; dangling symlinks have `brokenLink` set. */
+  async readdirDetailed(path: string): Promise<
+    Array<
+      IFileStat & { name: string; isSymlink: boolean; brokenLink: boolean }
+    >
+  > {
+    const raw = (await this.invoke("fs_readdir_detailed", { path })) as Array<{
+      name: string;
+      size: number;
+      mtime_ms: number;
+      is_directory: boolean;
+      is_file: boolean;
+      is_symlink: boolean;
+      broken_link: boolean;
+    }>;
+    return raw.map((e) => ({
+      name: e.name,
+      size: e.size,
+      mtime: new Date(e.mtime_ms),
+      isDirectory: e.is_directory,
+      isFile: e.is_file,
+      isSymlink: e.is_symlink,
+      brokenLink: e.broken_link,
+    }));
+  }
+
+  async readlink(path: string): Promise<string> {
+    return this.invoke<string>("fs_readlink", { path });
+  }
+
+  /** Fails rather than copying when they're on different devices. */
+  async hardlink(src: string, dst: string): Promise<void> {
+    await this.invoke("fs_hardlink", { src, dst });
+  }
+
+  /**
+   * `relative` joined onto `base`, rejecting `..` and absolute paths that
+   * would leave it. With `confineSymlinks`, links on the way must stay
+   * inside `base` too.
+   */
+  async resolveWithin(
+    base: string,
+    relative: string,
+    options: { confineSymlinks?: boolean } = {},
+  ): Promise<string> {
+    return this.invoke<string>("fs_resolve_within", {
+      base,
+      relative,
+      confineSymlinks: options.confineSymlinks,
+    });
+  }
+
+  /**
+   * Extended attribute `name`, or null if unset. On Windows `name` is an
+   * alternate data stream, e.g. `Zone.Identifier`.
+   */
+  async getXattr(path: string, name: string): Promise<Uint8Array | null> {
+    const value = await this.invoke<number[] | null>("fs_get_xattr", {
+      path,
+      name,
+    });
+    return value ? new Uint8Array(value) : null;
+  }
+
+  async setXattr(path: string, name: string, value: Uint8Array): Promise<void> {
+    await this.invoke("fs_set_xattr", { path, name, value: Array.from(value) });
+  }
+
+  /** Not supported on Windows. */
+  async listXattrs(path: string): Promise<string[]> {
+    return this.invoke<string[]>("fs_list_xattrs", { path });
+  }
+
   async delete(path: string): Promise<void> {
     await this.invoke("fs_delete", { path });
   }
@@ -132,9 +227,109 @@ export class TauriFileSystem implements IFileSystem {
     return this.invoke<string>("fs_realpath", { path });
   }
 
-  async listTree(path: string): Promise<Array<{ path: string; size: number }>> {
-    return this.invoke<Array<{ path: string; size: number }>>("fs_list_tree", {
-      path,
-    });
+  /** Show `path` selected in Finder, Explorer or the file manager. */
+  async reveal(path: string): Promise<void> {
+    await this.invoke("os_reveal", { path });
+  }
+
+  /**
+   * Files below `path`, including dangling symlinks with `broken_link`.
+   * Aborting `signal` stops the walk in Rust and rejects with "cancelled".
+   */
+  async listTree(
+    path: string,
+    options: { signal?: AbortSignal } = {},
+  ): Promise<Array<{ path: string; size: number; broken_link: boolean }>> {
+    return this.cancellable(options.signal, (opId) =>
+      this.invoke<Array<{ path: string; size: number; broken_link: boolean }>>(
+        "fs_list_tree",
+        { path, opId },
+      ),
+    );
+  }
+
+  /**
+   * Record the path, size and hash of every file below `path`. Resolves to
+   * an id for `diffTree`.
+   */
+  async snapshotTree(
+    path: string,
+    options: { signal?: AbortSignal } = {},
+  ): Promise<{ id: string; files: number; bytes: number }> {
+    return this.cancellable(options.signal, (opId) =>
+      this.invoke<{ id: string; files: number; bytes: number }>(
+        "fs_tree_snapshot",
+        { base: path, opId },
+      ),
+    );
+  }
+
+  /** Files added, removed or changed below `path` since `snapshotId`. */
+  async diffTree(
+    path: string,
+    snapshotId: string,
+    options: { signal?: AbortSignal } = {},
+  ): Promise<{ added: string[]; removed: string[]; changed: string[] }> {
+    return this.cancellable(options.signal, (opId) =>
+      this.invoke<{ added: string[]; removed: string[]; changed: string[] }>(
+        "fs_tree_diff",
+        { base: path, snapshotId, opId },
+      ),
+    );
+  }
+
+  /**
+   * Make `dst` a copy of `src`, copying files that are missing or differ
+   * by size and modification time (or content with `checksum`). `delete`
+   * also removes files only in `dst`; `exclude` globs are left alone on
+   * both sides.
+   */
+  async syncTree(
+    src: string,
+    dst: string,
+    options: {
+      delete?: boolean;
+      dryRun?: boolean;
+      exclude?: string[];
+      checksum?: boolean;
+      onProgress?: (progress: SyncProgress) => void;
+      signal?: AbortSignal;
+    } = {},
+  ): Promise<SyncReport> {
+    if (!this.ChannelCtor) {
+      throw new Error("syncTree needs the Tauri Channel constructor");
+    }
+    const { onProgress, signal, ...syncOptions } = options;
+    const onProgressChannel = new this.ChannelCtor((event: unknown) =>
+      onProgress?.(event as SyncProgress),
+    );
+    return this.cancellable(signal, (opId) =>
+      this.invoke<SyncReport>("fs_tree_sync", {
+        src,
+        dst,
+        options: syncOptions,
+        onProgress: onProgressChannel,
+        opId,
+      }),
+    );
+  }
+
+  /** Run a long command as an operation that `signal` can cancel. */
+  private async cancellable<T>(
+    signal: AbortSignal | undefined,
+    run: (opId: number | undefined) => Promise<T>,
+  ): Promise<T> {
+    if (!signal) return run(undefined);
+    const opId = await this.invoke<number>("fs_operation_start");
+    const cancel = () => {
+      this.invoke("fs_cancel", { opId }).catch(() => {});
+    };
+    if (signal.aborted) cancel();
+    signal.addEventListener("abort", cancel, { once: true });
+    try {
+      return await run(opId);
+    } finally {
+      signal.removeEventListener("abort", cancel);
+    }
   }
 }
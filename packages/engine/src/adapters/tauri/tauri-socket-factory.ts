@@ -6,8 +6,12 @@
  *
  * Each server gets its own Channel that multiplexes both binary data and
  * JSON control events. Binary frames have a 4-byte BE socket ID prefix.
- * JSON events are control messages (accept, close, error, listening).
+ * JSON events are control messages (accept, rejected, close, error,
+ * listening).
  * JS distinguishes them via `instanceof ArrayBuffer`.
+ *
+ * While the webview reloads, Rust keeps each server's events; the new page
+ * picks its servers back up with `reattachTcpServer`.
  */
 
 import type { TlsOptions } from "../../interfaces/certificate.js";
@@ -18,7 +22,14 @@ import type {
 } from "../../interfaces/socket.js";
 import { TauriTcpServer } from "./tauri-tcp-server.js";
 import { TauriTcpSocket } from "./tauri-tcp-socket.js";
-import type { ControlEvent, TauriChannelCtor, TauriInvokeFn } from "./types.js";
+import type {
+  ControlEvent,
+  FrameSizeOptions,
+  IpFilterOptions,
+  ReattachInfo,
+  TauriChannelCtor,
+  TauriInvokeFn,
+} from "./types.js";
 
 export class TauriSocketFactory implements ISocketFactory {
   private servers = new Map<number, TauriTcpServer>();
@@ -27,6 +38,9 @@ export class TauriSocketFactory implements ISocketFactory {
   constructor(
     private readonly invoke: TauriInvokeFn,
     private readonly ChannelCtor: TauriChannelCtor,
+    /** Applied to every server this factory creates. */
+    private readonly ipFilter?: IpFilterOptions,
+    private readonly frameSizes: FrameSizeOptions = {},
   ) {}
 
   async createTcpSocket(): Promise<ITcpSocket> {
@@ -72,6 +86,9 @@ export class TauriSocketFactory implements ISocketFactory {
         port,
         host: host || "0.0.0.0",
         channel,
+        ipFilter: this.ipFilter,
+        readBufferBytes: this.frameSizes.readBufferBytes,
+        maxFrameBytes: this.frameSizes.maxFrameBytes,
       })
         .then((serverId) => {
           server.serverId = serverId;
@@ -86,6 +103,33 @@ export class TauriSocketFactory implements ISocketFactory {
     return server;
   }
 
+  /**
+   * Take over a server created before this page was loaded. `setup` gets
+   * the server before its open sockets are announced and kept events are
+   * replayed, so it can register its "connection" handler.
+   */
+  async reattachTcpServer(
+    serverId: number,
+    setup: (server: ITcpServer) => void,
+  ): Promise<ReattachInfo> {
+    const server = new TauriTcpServer(this.invoke);
+    server.serverId = serverId;
+    this.servers.set(serverId, server);
+    setup(server);
+    const channel = new this.ChannelCtor((event: unknown) =>
+      this.handleChannelEvent(event),
+    );
+    try {
+      return await this.invoke<ReattachInfo>("tcp_server_reattach", {
+        serverId,
+        channel,
+      });
+    } catch (err) {
+      this.servers.delete(serverId);
+      throw err;
+    }
+  }
+
   wrapTcpSocket(socket: unknown): ITcpSocket {
     // The socket is already a TauriTcpSocket created in the accept handler
     return socket as ITcpSocket;
@@ -134,6 +178,11 @@ export class TauriSocketFactory implements ISocketFactory {
         }
         break;
       }
+      case "rejected": {
+        const server = this.servers.get(event.serverId);
+        server?._onRejected(event.remoteAddress, event.reason);
+        break;
+      }
       case "close": {
         const socket = this.sockets.get(event.socketId);
         socket?._onClose(event.hadError);
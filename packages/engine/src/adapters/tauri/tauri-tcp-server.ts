@@ -2,7 +2,7 @@
  * Tauri TCP server adapter.
  *
  * Mirrors the NativeTcpServer pattern: stores callbacks, has internal
- * _onListening/_onAccept/_onError methods called by the socket factory.
+ * _onListening/_onAccept/_onRejected/_onError methods called by the socket factory.
  * The listen() method is fire-and-forget (void return); the actual result
  * comes back asynchronously via the channel.
  */
@@ -14,6 +14,7 @@ export class TauriTcpServer implements ITcpServer {
   private connectionCallback?: (socket: unknown) => void;
   private errorCallback?: (err: Error) => void;
   private listenCallback?: () => void;
+  private rejectedCallback?: (remoteAddress: string, reason: string) => void;
   private closed = false;
 
   serverId?: number;
@@ -35,13 +36,25 @@ export class TauriTcpServer implements ITcpServer {
   on(event: "connection", cb: (socket: unknown) => void): void;
   on(event: "error", cb: (err: Error) => void): void;
   on(
-    event: "connection" | "error",
-    cb: ((socket: unknown) => void) | ((err: Error) => void),
+    event: "rejected",
+    cb: (remoteAddress: string, reason: string) => void,
+  ): void;
+  on(
+    event: "connection" | "error" | "rejected",
+    cb:
+      | ((socket: unknown) => void)
+      | ((err: Error) => void)
+      | ((remoteAddress: string, reason: string) => void),
   ): void {
     if (event === "connection") {
       this.connectionCallback = cb as (socket: unknown) => void;
     } else if (event === "error") {
       this.errorCallback = cb as (err: Error) => void;
+    } else if (event === "rejected") {
+      this.rejectedCallback = cb as (
+        remoteAddress: string,
+        reason: string,
+      ) => void;
     }
   }
 
@@ -71,4 +84,9 @@ export class TauriTcpServer implements ITcpServer {
   _onAccept(socket: unknown): void {
     this.connectionCallback?.(socket);
   }
+
+  /** Called by the socket factory when the IP filter turns a client away. */
+  _onRejected(remoteAddress: string, reason: string): void {
+    this.rejectedCallback?.(remoteAddress, reason);
+  }
 }
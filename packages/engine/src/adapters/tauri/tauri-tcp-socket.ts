@@ -41,6 +41,22 @@ export class TauriTcpSocket implements ITcpSocket {
     });
   }
 
+  /**
+   * Send `length` bytes of a file from `start` (default: all of it) straight
+   * from disk in Rust. Resolves with the bytes sent.
+   */
+  async sendFile(
+    path: string,
+    range: { start?: number; length?: number } = {},
+  ): Promise<number> {
+    if (this.closed) throw new Error("Socket closed");
+    return this.invoke<number>("tcp_send_file", {
+      socketId: this.socketId,
+      path,
+      range,
+    });
+  }
+
   onData(cb: (data: Uint8Array) => void): void {
     this.dataCallbacks.push(cb);
   }
@@ -20,6 +20,52 @@ export type TauriChannelCtor = new (
   callback: (event: unknown) => void,
 ) => unknown;
 
+/** Per-server client address rules, checked by Rust at accept time. */
+export interface IpFilterOptions {
+  /** CIDR networks or addresses; when not empty, only these get in. */
+  allow?: string[];
+  /** CIDR networks or addresses refused even if allowed. */
+  deny?: string[];
+  /** Only allow loopback and local network addresses. */
+  privateOnly?: boolean;
+}
+
+/** Socket read size and channel frame size; both default to 64 KiB. */
+export interface FrameSizeOptions {
+  /** Bytes read from a socket at once. */
+  readBufferBytes?: number;
+  /** Largest data frame sent to JS; capped at `readBufferBytes`. */
+  maxFrameBytes?: number;
+}
+
+/** Returned by `tcp_server_info`, with the sizes in effect. */
+export interface TcpServerInfo extends Required<FrameSizeOptions> {
+  address: string;
+  port: number;
+  ipFilter: Required<IpFilterOptions> | null;
+  sniff: boolean;
+}
+
+/** Returned by `tcp_gc_stats`: sockets freed after their reader ended. */
+export interface GcStats {
+  sweeps: number;
+  reaped: number;
+  /** Socket IDs freed by the latest sweep that found any. */
+  lastReaped: number[];
+  /** Unix seconds. */
+  lastSweep: number | null;
+}
+
+/** What `tcp_server_reattach` sent to the new channel. */
+export interface ReattachInfo {
+  /** Sockets announced again, having been accepted before the reload. */
+  sockets: number;
+  /** Events kept during the reload. */
+  replayed: number;
+  /** Events lost because too many were kept. */
+  dropped: number;
+}
+
 /** Control events sent as JSON from Rust through the channel. */
 export type ControlEvent =
   | {
@@ -38,6 +84,23 @@ export type ControlEvent =
       socketId: number;
       remoteAddress: string;
       remotePort: number;
+      /** Present when the server was created with `sniff`. */
+      protocol?: "tls" | "http" | "other";
+    }
+  | {
+      type: "rejected";
+      serverId: number;
+      remoteAddress: string;
+      reason: string;
+    }
+  | {
+      /** An outbound `tcp_connect` socket is open. */
+      type: "connect";
+      socketId: number;
+      remoteAddress: string;
+      remotePort: number;
+      /** Whichever address family won the connection race. */
+      family: 4 | 6;
     }
   | {
       type: "close";
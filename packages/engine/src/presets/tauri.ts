@@ -19,7 +19,7 @@ export interface TauriServerOptions {
 
 export function createTauriServer(options: TauriServerOptions): WebServer {
   const socketFactory = new TauriSocketFactory(options.invoke, options.Channel);
-  const fileSystem = new TauriFileSystem(options.invoke);
+  const fileSystem = new TauriFileSystem(options.invoke, options.Channel);
   return new WebServer({
     socketFactory,
     fileSystem,